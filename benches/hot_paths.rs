@@ -0,0 +1,307 @@
+//! Timings of the paths every request takes: header and body decode,
+//! response encode, metadata log parsing and the Fetch read path.
+//!
+//! Run them with
+//!
+//! ```text
+//! cargo bench --bench hot_paths [-- FILTER]
+//! ```
+//!
+//! criterion keeps the last run under `target/criterion` and reports the
+//! change against it, so run once before a change and once after.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, Criterion};
+use kafka_starter_rust::cluster_metadata::{RecordBatch, RecordBatches, TopicValue};
+use kafka_starter_rust::fetch::{FetchRequestV16, Partition, TopicRequest};
+use kafka_starter_rust::metadata::MetadataRequest;
+use kafka_starter_rust::*;
+
+const TOPIC: &str = "bench";
+const PARTITIONS: i32 = 8;
+/// Batches per partition segment, of `RECORDS_PER_BATCH` records each.
+const BATCHES: usize = 200;
+const RECORDS_PER_BATCH: usize = 10;
+const SEGMENT_FILE: &str = "00000000000000000000.log";
+
+/// A log directory holding a metadata log for `TOPIC` and a segment for
+/// each of its partitions, removed on drop.
+struct Fixture {
+    dir: PathBuf,
+    topic_id: Uuid,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("kafka-bench-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let topic_id = Uuid::random();
+        let mut records = vec![TopicValue {
+            topic_name: CompactNullableString(Some(TOPIC.to_string())),
+            topic_id: topic_id.clone(),
+        }
+        .record()];
+        records.extend(partition_records(
+            &topic_id,
+            0,
+            &vec![vec![1]; PARTITIONS as usize],
+            METADATA_VERSION_LATEST,
+        ));
+        let metadata_log_file = cluster_metadata_log_file(&dir);
+        std::fs::create_dir_all(metadata_log_file.parent().unwrap()).unwrap();
+        std::fs::write(metadata_log_file, encode_batch(0, 0, 0, &records, 0)).unwrap();
+
+        let segment = data_segment();
+        for partition in 0..PARTITIONS {
+            let partition_dir = dir.join(format!("{}-{}", TOPIC, partition));
+            std::fs::create_dir_all(&partition_dir).unwrap();
+            std::fs::write(partition_dir.join(SEGMENT_FILE), &segment).unwrap();
+        }
+        Self { dir, topic_id }
+    }
+
+    fn metadata_log_file(&self) -> PathBuf {
+        cluster_metadata_log_file(&self.dir)
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// `BATCHES` batches of 100-byte values, back to back.
+fn data_segment() -> Bytes {
+    let mut segment = BytesMut::new();
+    for batch in 0..BATCHES {
+        let records: Vec<BatchRecord> = (0..RECORDS_PER_BATCH)
+            .map(|i| BatchRecord {
+                key: Some(Bytes::from(format!("key-{}", i))),
+                value: Some(Bytes::from(vec![b'v'; 100])),
+            })
+            .collect();
+        let base_offset = (batch * RECORDS_PER_BATCH) as i64;
+        segment.put(encode_batch(base_offset, 0, 0, &records, 0));
+    }
+    segment.freeze()
+}
+
+/// A request as the broker reads it off the wire, without the size prefix.
+fn request(api_key: ApiKey, api_version: i16, body: Bytes) -> Bytes {
+    let mut b = BytesMut::new();
+    b.put_i16(api_key.into());
+    b.put_i16(api_version);
+    b.put_i32(1);
+    b.put(NullableString(Some("bench".to_string())).serialize());
+    if api_key.is_flexible(api_version) {
+        b.put(TagBuffer::serialize());
+    }
+    b.put(body);
+    b.freeze()
+}
+
+fn context(api_key: ApiKey, api_version: i16) -> RequestContext {
+    let address = SocketAddr::from(([127, 0, 0, 1], 9092));
+    RequestContext {
+        header: HeaderV2 {
+            api_key: api_key.into(),
+            api_version,
+            correlation_id: 1,
+            client_id: NullableString(Some("bench".to_string())),
+        },
+        listener_name: "PLAINTEXT".to_string(),
+        listener_type: ListenerType::Broker,
+        principal: KafkaPrincipal::anonymous(),
+        token_authenticated: false,
+        client_address: address,
+        local_address: address,
+        cancellation: Cancellation::default(),
+        commit: RequestCommit::default(),
+    }
+}
+
+/// A consumer fetch of every partition from `fetch_offset`.
+fn fetch_body(topic_id: &Uuid, fetch_offset: i64, partition_max_bytes: i32) -> Bytes {
+    let partitions = (0..PARTITIONS)
+        .map(|p| Partition::new(p, -1, fetch_offset, partition_max_bytes))
+        .collect();
+    FetchRequestV16::consumer(
+        Duration::ZERO,
+        0,
+        u32::MAX / 2,
+        vec![TopicRequest::new(topic_id.0.clone(), partitions)],
+    )
+    .serialize()
+}
+
+fn metadata_body() -> Bytes {
+    MetadataRequest {
+        topics: None,
+        allow_auto_topic_creation: false,
+        include_cluster_authorized_operations: false,
+        include_topic_authorized_operations: false,
+    }
+    .serialize()
+}
+
+fn isr_manager(metadata_log_file: &Path) -> IsrManager {
+    IsrManager::start(
+        IsrSettings::default(),
+        1,
+        metadata_log_file,
+        Arc::new(Metrics::default()),
+    )
+    .unwrap()
+}
+
+fn request_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    let topic_id = Uuid::random();
+    let fetch = request(ApiKey::Fetch, 16, fetch_body(&topic_id, 0, 1 << 20));
+    group.bench_function("fetch_v16", |b| {
+        b.iter(|| {
+            let mut src = fetch.clone();
+            let header = HeaderV2::deserialize(&mut src).unwrap();
+            let fetch: FetchRequestV16 = FetchRequestV16::deserialize(&mut src).unwrap();
+            (header, fetch)
+        })
+    });
+
+    let metadata = request(ApiKey::Metadata, 12, metadata_body());
+    group.bench_function("metadata_v12", |b| {
+        b.iter(|| {
+            let mut src = metadata.clone();
+            let header = HeaderV2::deserialize(&mut src).unwrap();
+            (header, MetadataRequest::deserialize(&mut src, 12).unwrap())
+        })
+    });
+    group.finish();
+}
+
+fn response_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    let fixture = Fixture::new();
+    let config = Config {
+        metadata_log_dir: fixture.dir.clone(),
+        ..Default::default()
+    };
+    let ctx = context(ApiKey::Metadata, 12);
+    let body = metadata_body();
+    let image = MetadataImage::load(&fixture.metadata_log_file());
+    let response = metadata::handle_request(
+        &config,
+        "bench",
+        &ctx,
+        &AllowAllAuthorizer,
+        None,
+        &image,
+        &mut body.clone(),
+    )
+    .unwrap();
+    group.bench_function("metadata_v12", |b| b.iter(|| response.as_bytes()));
+
+    let isr_manager = isr_manager(&fixture.metadata_log_file());
+    let ctx = context(ApiKey::Fetch, 16);
+    let response = fetch::handle_request(
+        &ctx,
+        1,
+        &AllowAllAuthorizer,
+        None,
+        &isr_manager,
+        None,
+        None,
+        None,
+        &image,
+        &fixture.dir,
+        &FetchSessionCache::new(0),
+        &mut fetch_body(&fixture.topic_id, 0, 1 << 20),
+    )
+    .unwrap();
+    group.bench_function("fetch_v16", |b| b.iter(|| response.as_bytes()));
+    group.finish();
+}
+
+fn record_batch_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    let fixture = Fixture::new();
+    let metadata = Bytes::from(std::fs::read(fixture.metadata_log_file()).unwrap());
+    group.bench_function("metadata_batch", |b| {
+        b.iter(|| RecordBatch::from_bytes(&mut metadata.clone()).unwrap())
+    });
+    group.bench_function("metadata_log_file", |b| {
+        b.iter(|| RecordBatches::from_file(fixture.metadata_log_file()).unwrap())
+    });
+
+    let segment = data_segment();
+    group.bench_function("segment_batch_headers", |b| {
+        b.iter(|| {
+            let mut at = 0;
+            let mut batches = 0;
+            while let Some(header) = BatchHeader::parse(&segment[at..]) {
+                at += header.size();
+                batches += 1;
+            }
+            batches
+        })
+    });
+    group.finish();
+}
+
+fn fetch_read_path(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fetch");
+    let fixture = Fixture::new();
+    let metadata_log_file = fixture.metadata_log_file();
+    let isr_manager = isr_manager(&metadata_log_file);
+    let image = MetadataImage::load(&metadata_log_file);
+    let ctx = context(ApiKey::Fetch, 16);
+    let sessions = FetchSessionCache::new(0);
+    let cases = [
+        ("from_start_1MiB", 0, 1 << 20),
+        ("from_start_32KiB", 0, 32 << 10),
+        (
+            "from_middle_1MiB",
+            (BATCHES * RECORDS_PER_BATCH / 2) as i64,
+            1 << 20,
+        ),
+        ("at_log_end", (BATCHES * RECORDS_PER_BATCH) as i64, 1 << 20),
+    ];
+    for (name, fetch_offset, partition_max_bytes) in cases {
+        let body = fetch_body(&fixture.topic_id, fetch_offset, partition_max_bytes);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                fetch::handle_request(
+                    &ctx,
+                    1,
+                    &AllowAllAuthorizer,
+                    None,
+                    &isr_manager,
+                    None,
+                    None,
+                    None,
+                    &image,
+                    &fixture.dir,
+                    &sessions,
+                    &mut body.clone(),
+                )
+                .unwrap()
+                .as_bytes()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    request_decode,
+    response_encode,
+    record_batch_parse,
+    fetch_read_path
+);
+criterion_main!(benches);
@@ -0,0 +1,34 @@
+//! Feeds arbitrary bytes to the metadata log parsers, which read whatever is
+//! on disk, truncated or corrupted. Run with
+//!
+//! ```text
+//! cargo +nightly fuzz run metadata_records
+//! ```
+//!
+//! from the repository root; the broker's own metadata log makes a good seed.
+//!
+//! Inputs that each trip one of the parsers' length checks are kept in
+//! `fuzz/regressions/metadata_records`, `value-*` as a single record value
+//! and the rest as a log. The unit tests replay them; pass the directory to
+//! `cargo fuzz run` to start from them.
+
+#![no_main]
+
+use bytes::{Buf, Bytes};
+use kafka_starter_rust::cluster_metadata::{RecordBatch, RecordValue};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // As a log file is read: batch after batch until the data runs out.
+    let mut src = Bytes::copy_from_slice(data);
+    while src.has_remaining() {
+        if RecordBatch::from_bytes(&mut src).is_err() {
+            break;
+        }
+    }
+
+    // As the value of a single record.
+    if !data.is_empty() {
+        let _ = RecordValue::from_bytes(&mut Bytes::copy_from_slice(data));
+    }
+});
@@ -0,0 +1,168 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    connection_registry::ConnectionRegistry, health::Health, isr_manager::IsrManager,
+    log_level::LogLevel, metrics::Metrics, replica_fetcher::ReplicaFetchers,
+};
+
+/// Largest request head accepted; the endpoints take no bodies.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A plain-text HTTP endpoint for operators, bound to `admin.listener`.
+///
+/// - `GET /metrics`: every metric in the Prometheus text format.
+/// - `GET /connections`: one line per open connection.
+/// - `GET /replicas`: one line per partition followed from another broker.
+/// - `GET /isr`: one line per partition this broker leads, with its ISR.
+/// - `GET /health/live`: 200 whenever the broker can answer.
+/// - `GET /health/ready`: 200 when every readiness check passes, else 503,
+///   with one line per check.
+/// - `GET /log-level`: the log filter in effect.
+/// - `PUT /log-level?filter=<directives>`: replaces the log filter, e.g.
+///   `filter=info,kafka_starter_rust=debug`, until `log.level` is next
+///   changed in the config file.
+pub struct AdminServer {
+    pub metrics: Arc<Metrics>,
+    pub connections: Arc<ConnectionRegistry>,
+    pub health: Arc<Health>,
+    pub log_level: Arc<LogLevel>,
+    pub replica_fetchers: Arc<ReplicaFetchers>,
+    pub isr_manager: Arc<IsrManager>,
+}
+
+impl AdminServer {
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(error = %e, "admin accept failed");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle(stream).await {
+                    debug!(peer = %peer, error = %e, "admin request failed");
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        let head = tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream))
+            .await
+            .map_err(|_| anyhow!("timed out reading request"))??;
+        let mut parts = head.split_whitespace();
+        let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let (status, body) = match (method, path) {
+            ("GET", "/metrics") => ("200 OK", self.metrics.render_prometheus()),
+            ("GET", "/connections") => ("200 OK", self.connections.render()),
+            ("GET", "/replicas") => ("200 OK", self.replica_fetchers.render()),
+            ("GET", "/isr") => ("200 OK", self.isr_manager.render()),
+            ("GET", "/health/live") => ("200 OK", "ok\n".to_string()),
+            ("GET", "/health/ready") => self.readiness(),
+            ("GET", "/log-level") => match self.log_level.current() {
+                Ok(filter) => ("200 OK", format!("{}\n", filter)),
+                Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+            },
+            ("PUT", "/log-level") => self.set_log_level(query),
+            ("GET", _) => ("404 Not Found", "not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    fn set_log_level(&self, query: &str) -> (&'static str, String) {
+        let filter = query
+            .split('&')
+            .find_map(|param| param.strip_prefix("filter="))
+            .map(percent_decode);
+        match filter {
+            Some(Ok(filter)) => match self.log_level.set(&filter) {
+                Ok(()) => ("200 OK", format!("{}\n", filter)),
+                Err(e) => ("400 Bad Request", format!("{}\n", e)),
+            },
+            Some(Err(e)) => ("400 Bad Request", format!("{}\n", e)),
+            None => (
+                "400 Bad Request",
+                "missing 'filter' parameter\n".to_string(),
+            ),
+        }
+    }
+
+    fn readiness(&self) -> (&'static str, String) {
+        let checks = self.health.readiness();
+        let mut body = String::new();
+        for check in &checks {
+            match &check.result {
+                Ok(()) => body += &format!("{}: ok\n", check.name),
+                Err(e) => body += &format!("{}: failed: {}\n", check.name, e),
+            }
+        }
+        let status = if checks.iter().all(|c| c.result.is_ok()) {
+            "200 OK"
+        } else {
+            "503 Service Unavailable"
+        };
+        (status, body)
+    }
+}
+
+/// Reads up to the blank line ending the request head and returns its first
+/// line.
+async fn read_head(stream: &mut TcpStream) -> Result<String> {
+    let mut buf = Vec::with_capacity(1024);
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&buf[..end]);
+            return Ok(head.lines().next().unwrap_or_default().to_string());
+        }
+        if buf.len() >= MAX_REQUEST_BYTES {
+            return Err(anyhow!("request head too large"));
+        }
+        if stream.read_buf(&mut buf).await? == 0 {
+            return Err(anyhow!("connection closed mid-request"));
+        }
+    }
+}
+
+/// Decodes a query string value, where `+` is a space and `%XX` a byte.
+fn percent_decode(s: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut input = s.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [input.next().unwrap_or(0), input.next().unwrap_or(0)];
+                let byte = std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| anyhow!("invalid escape in '{}'", s))?;
+                bytes.push(byte);
+            }
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| anyhow!("'{}' is not UTF-8", s))
+}
@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::coordinator::{TopicPartition, TransactionCoordinator};
+use crate::error::Error;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// AddPartitionsToTxn request, v3: a producer's own request, for one
+/// transaction.
+pub struct AddPartitionsToTxnRequest {
+    pub transactional_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub topics: Vec<AddPartitionsToTxnTopic>,
+}
+
+pub struct AddPartitionsToTxnTopic {
+    pub name: String,
+    pub partitions: Vec<i32>,
+}
+
+impl Deserialize<Self> for AddPartitionsToTxnRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let transactional_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let producer_id = src.try_get_i64()?;
+        let producer_epoch = src.try_get_i16()?;
+        let topics = CompactArray::<AddPartitionsToTxnTopic>::deserialize(src)?;
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            transactional_id,
+            producer_id,
+            producer_epoch,
+            topics,
+        })
+    }
+}
+
+impl Deserialize<Self> for AddPartitionsToTxnTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let name = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let partitions = CompactArray::<Self>::deserialize(src)?;
+        TagBuffer::deserialize(src)?;
+        Ok(Self { name, partitions })
+    }
+}
+
+impl Deserialize<i32> for AddPartitionsToTxnTopic {
+    fn deserialize(src: &mut Bytes) -> Result<i32, DecodeError> {
+        Ok(src.try_get_i32()?)
+    }
+}
+
+/// AddPartitionsToTxn response, v3.
+pub struct AddPartitionsToTxnResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    results: CompactArray<AddPartitionsToTxnTopicResult>,
+}
+
+pub struct AddPartitionsToTxnTopicResult {
+    pub name: String,
+    pub partitions: Vec<(i32, ErrorCode)>,
+}
+
+impl Serialize for AddPartitionsToTxnTopicResult {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(
+            CompactArray(
+                self.partitions
+                    .iter()
+                    .map(|&(p, e)| PartitionResult(p, e))
+                    .collect(),
+            )
+            .serialize(),
+        );
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+struct PartitionResult(i32, ErrorCode);
+
+impl Serialize for PartitionResult {
+    fn serialize(&self) -> Bytes {
+        let PartitionResult(partition_index, error_code) = *self;
+        let mut b = BytesMut::new();
+        b.put_i32(partition_index);
+        b.put_i16(error_code.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Response for AddPartitionsToTxnResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(self.results.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.results
+            .0
+            .iter()
+            .flat_map(|topic| &topic.partitions)
+            .map(|(_, error_code)| *error_code)
+            .find(|error_code| *error_code != ErrorCode::None)
+            .unwrap_or(ErrorCode::None)
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+}
+
+impl AddPartitionsToTxnResponse {
+    fn new(ctx: &RequestContext, results: Vec<AddPartitionsToTxnTopicResult>) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            results: CompactArray(results),
+        }
+    }
+
+    /// Every partition of `req` failing with `error_code`.
+    fn error(ctx: &RequestContext, req: &AddPartitionsToTxnRequest, error_code: ErrorCode) -> Self {
+        let results = req
+            .topics
+            .iter()
+            .map(|topic| AddPartitionsToTxnTopicResult {
+                name: topic.name.clone(),
+                partitions: topic.partitions.iter().map(|&p| (p, error_code)).collect(),
+            })
+            .collect();
+        Self::new(ctx, results)
+    }
+}
+
+pub struct AddPartitionsToTxnHandler {
+    transactions: Arc<TransactionCoordinator>,
+    authorizer: Arc<dyn Authorizer>,
+    image: Arc<MetadataImage>,
+}
+
+impl AddPartitionsToTxnHandler {
+    pub fn new(
+        transactions: Arc<TransactionCoordinator>,
+        authorizer: Arc<dyn Authorizer>,
+        image: Arc<MetadataImage>,
+    ) -> Self {
+        Self {
+            transactions,
+            authorizer,
+            image,
+        }
+    }
+}
+
+impl ApiHandler for AddPartitionsToTxnHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(
+            ctx,
+            &self.transactions,
+            &*self.authorizer,
+            &self.image,
+            body,
+        )?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = AddPartitionsToTxnRequest::deserialize(body)?;
+        Ok(Box::new(AddPartitionsToTxnResponse::error(
+            ctx, &req, error_code,
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = AddPartitionsToTxnRequest::deserialize(body)?;
+        dump.field("transactional_id", &req.transactional_id)
+            .field("producer_id", req.producer_id)
+            .field("producer_epoch", req.producer_epoch)
+            .list("topics", &req.topics, |dump, topic| {
+                dump.field("name", &topic.name)
+                    .field("partitions", format_args!("{:?}", topic.partitions));
+            });
+        Ok(())
+    }
+}
+
+/// Takes Write on the transactional id and on each topic. Partitions are
+/// added all together or not at all: if any is unknown or unauthorized,
+/// the rest fail with OPERATION_NOT_ATTEMPTED.
+pub fn handle_request(
+    ctx: &RequestContext,
+    transactions: &TransactionCoordinator,
+    authorizer: &dyn Authorizer,
+    image: &MetadataImage,
+    message: &mut Bytes,
+) -> Result<AddPartitionsToTxnResponse, Error> {
+    let req = AddPartitionsToTxnRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::AddPartitionsToTxn, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Write,
+        ResourceType::TransactionalId,
+        &req.transactional_id,
+    ) {
+        return Ok(AddPartitionsToTxnResponse::error(
+            ctx,
+            &req,
+            ErrorCode::TransactionalIdAuthorizationFailed,
+        ));
+    }
+    let record_batches = image.current();
+    let mut results = Vec::new();
+    let mut failed = false;
+    for topic in &req.topics {
+        let topic_error =
+            if !authorizer.authorize(ctx, AclOperation::Write, ResourceType::Topic, &topic.name) {
+                Some(ErrorCode::TopicAuthorizationFailed)
+            } else {
+                None
+            };
+        let topic_id = record_batches
+            .topic_by_name(&topic.name)
+            .map(|t| t.topic_id.clone());
+        let partitions = topic
+            .partitions
+            .iter()
+            .map(|&partition| {
+                let error_code = match (topic_error, &topic_id) {
+                    (Some(error_code), _) => error_code,
+                    (None, Some(topic_id))
+                        if record_batches.partition(topic_id, partition).is_some() =>
+                    {
+                        ErrorCode::None
+                    }
+                    (None, _) => ErrorCode::UnknownTopicOrPartition,
+                };
+                failed |= error_code != ErrorCode::None;
+                (partition, error_code)
+            })
+            .collect();
+        results.push(AddPartitionsToTxnTopicResult {
+            name: topic.name.clone(),
+            partitions,
+        });
+    }
+    if failed {
+        for (_, error_code) in results.iter_mut().flat_map(|topic| &mut topic.partitions) {
+            if *error_code == ErrorCode::None {
+                *error_code = ErrorCode::OperationNotAttempted;
+            }
+        }
+        return Ok(AddPartitionsToTxnResponse::new(ctx, results));
+    }
+    let partitions = req.topics.iter().flat_map(|topic| {
+        topic.partitions.iter().map(|&partition| TopicPartition {
+            topic: topic.name.clone(),
+            partition,
+        })
+    });
+    let error_code = transactions.add_partitions(
+        &req.transactional_id,
+        req.producer_id,
+        req.producer_epoch,
+        partitions,
+    );
+    Ok(AddPartitionsToTxnResponse::error(ctx, &req, error_code))
+}
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::raft::{MetadataQuorum, Voter};
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// AddRaftVoter request, v0: a controller to join the voter set, with the
+/// listeners the other voters reach it on.
+pub struct AddRaftVoterRequest {
+    pub cluster_id: Option<String>,
+    pub timeout_ms: i32,
+    pub voter_id: i32,
+    pub voter_directory_id: Uuid,
+    pub listeners: Vec<RaftVoterListener>,
+}
+
+pub struct RaftVoterListener {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Deserialize<Self> for AddRaftVoterRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let cluster_id = CompactNullableString::deserialize(src)?.0;
+        let timeout_ms = src.try_get_i32()?;
+        let voter_id = src.try_get_i32()?;
+        let voter_directory_id = Uuid::deserialize(src)?;
+        let listeners = CompactArray::deserialize_with(src, |src| {
+            let listener = RaftVoterListener {
+                name: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                host: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                port: src.try_get_u16()?,
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(listener)
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            cluster_id,
+            timeout_ms,
+            voter_id,
+            voter_directory_id,
+            listeners,
+        })
+    }
+}
+
+/// AddRaftVoter and RemoveRaftVoter response, v0.
+pub struct RaftVoterResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+    error_message: Option<String>,
+}
+
+impl RaftVoterResponse {
+    pub fn new(ctx: &RequestContext, error_code: ErrorCode) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            error_message: None,
+        }
+    }
+}
+
+impl Response for RaftVoterResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(CompactNullableString(self.error_message.clone()).serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        self.error_message = Some(error_message.to_string());
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("error_code", format_args!("{:?}", self.error_code))
+            .nullable("error_message", self.error_message.as_deref());
+    }
+}
+
+/// Served by the quorum's voters; only the leader can change the voter set.
+pub struct AddRaftVoterHandler {
+    quorum: MetadataQuorum,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl AddRaftVoterHandler {
+    pub fn new(quorum: MetadataQuorum, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self { quorum, authorizer }
+    }
+}
+
+impl ApiHandler for AddRaftVoterHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.quorum, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(RaftVoterResponse::new(ctx, error_code)))
+    }
+
+    fn describe_request(
+        &self,
+        _: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = AddRaftVoterRequest::deserialize(body)?;
+        dump.nullable("cluster_id", req.cluster_id.as_deref())
+            .field("timeout_ms", req.timeout_ms)
+            .field("voter_id", req.voter_id)
+            .field("voter_directory_id", &req.voter_directory_id)
+            .list("listeners", &req.listeners, |dump, listener| {
+                dump.field("name", &listener.name)
+                    .field("host", &listener.host)
+                    .field("port", listener.port);
+            });
+        Ok(())
+    }
+}
+
+/// Takes Alter on the cluster. The new voter is reached on the first
+/// listener it names, and starts counting towards the majority as soon as
+/// the leader has written it into the log.
+pub fn handle_request(
+    ctx: &RequestContext,
+    quorum: &MetadataQuorum,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<RaftVoterResponse, Error> {
+    let req = AddRaftVoterRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::AddRaftVoter, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Alter,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(RaftVoterResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+        ));
+    }
+    if !quorum.is_cluster(req.cluster_id.as_deref()) {
+        return Ok(RaftVoterResponse::new(
+            ctx,
+            ErrorCode::InconsistentClusterId,
+        ));
+    }
+    let Some(listener) = req.listeners.into_iter().next() else {
+        let mut res = RaftVoterResponse::new(ctx, ErrorCode::InvalidRequest);
+        res.set_error_message("The new voter names no listeners");
+        return Ok(res);
+    };
+    let voter = Voter {
+        id: req.voter_id,
+        host: listener.host,
+        port: listener.port,
+    };
+    let error_code = quorum.add_voter(voter).err().unwrap_or(ErrorCode::None);
+    Ok(RaftVoterResponse::new(ctx, error_code))
+}
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::cluster_metadata::{QuotaComponent, QuotaComponents};
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::controller::Controller;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// AlterClientQuotas request, v1.
+pub struct AlterClientQuotasRequest {
+    pub entries: Vec<AlterClientQuotasEntry>,
+    pub validate_only: bool,
+}
+
+pub struct AlterClientQuotasEntry {
+    pub entity: QuotaComponents,
+    pub ops: Vec<QuotaOp>,
+}
+
+pub struct QuotaOp {
+    pub key: String,
+    pub value: f64,
+    /// Whether the quota is removed rather than set to `value`.
+    pub remove: bool,
+}
+
+impl Deserialize<Self> for AlterClientQuotasRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let entries = CompactArray::deserialize_with(src, |src| {
+            let entity = CompactArray::deserialize_with(src, |src| {
+                let component = (
+                    CompactNullableString::deserialize(src)?
+                        .0
+                        .unwrap_or_default(),
+                    CompactNullableString::deserialize(src)?.0,
+                );
+                TagBuffer::deserialize_fields(src)?;
+                Ok(component)
+            })?;
+            let ops = CompactArray::deserialize_with(src, |src| {
+                let op = QuotaOp {
+                    key: CompactNullableString::deserialize(src)?
+                        .0
+                        .unwrap_or_default(),
+                    value: src.try_get_f64()?,
+                    remove: src.try_get_u8()? != 0,
+                };
+                TagBuffer::deserialize_fields(src)?;
+                Ok(op)
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(AlterClientQuotasEntry { entity, ops })
+        })?;
+        let validate_only = src.try_get_u8()? != 0;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            entries,
+            validate_only,
+        })
+    }
+}
+
+/// AlterClientQuotas response, v1.
+pub struct AlterClientQuotasResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    entries: CompactArray<AlterClientQuotasResult>,
+}
+
+pub struct AlterClientQuotasResult {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub entity: QuotaComponents,
+}
+
+impl Serialize for AlterClientQuotasResult {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i16(self.error_code.into());
+        b.put(CompactNullableString(self.error_message.clone()).serialize());
+        b.put(CompactArray(self.entity.iter().map(QuotaComponent).collect()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl AlterClientQuotasResponse {
+    fn new(ctx: &RequestContext, entries: Vec<AlterClientQuotasResult>) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            entries: CompactArray(entries),
+        }
+    }
+
+    fn error(ctx: &RequestContext, req: AlterClientQuotasRequest, error_code: ErrorCode) -> Self {
+        let entries = req
+            .entries
+            .into_iter()
+            .map(|entry| AlterClientQuotasResult {
+                error_code,
+                error_message: None,
+                entity: entry.entity,
+            })
+            .collect();
+        Self::new(ctx, entries)
+    }
+}
+
+impl Response for AlterClientQuotasResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(self.entries.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.entries
+            .0
+            .iter()
+            .map(|e| e.error_code)
+            .find(|&e| e != ErrorCode::None)
+            .unwrap_or(ErrorCode::None)
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        for entry in &mut self.entries.0 {
+            if entry.error_code != ErrorCode::None {
+                entry.error_message = Some(error_message.to_string());
+            }
+        }
+    }
+}
+
+/// Served by nodes that are also controllers, which record the new quotas
+/// in the metadata log.
+pub struct AlterClientQuotasHandler {
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl AlterClientQuotasHandler {
+    pub fn new(controller: Arc<Controller>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for AlterClientQuotasHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.controller, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = AlterClientQuotasRequest::deserialize(body)?;
+        Ok(Box::new(AlterClientQuotasResponse::error(
+            ctx, req, error_code,
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = AlterClientQuotasRequest::deserialize(body)?;
+        dump.list("entries", &req.entries, |dump, entry| {
+            dump.list("entity", &entry.entity, |dump, (entity_type, name)| {
+                dump.field("entity_type", entity_type)
+                    .nullable("entity_name", name.as_ref());
+            })
+            .list("ops", &entry.ops, |dump, op| {
+                dump.field("key", &op.key)
+                    .field("value", op.value)
+                    .field("remove", op.remove);
+            });
+        })
+        .field("validate_only", req.validate_only);
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes AlterConfigs on the cluster. Each entity is altered on its own.
+pub fn handle_request(
+    ctx: &RequestContext,
+    controller: &Controller,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<AlterClientQuotasResponse, Error> {
+    let req = AlterClientQuotasRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::AlterClientQuotas, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::AlterConfigs,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(AlterClientQuotasResponse::error(
+            ctx,
+            req,
+            ErrorCode::ClusterAuthorizationFailed,
+        ));
+    }
+    let entries = req
+        .entries
+        .into_iter()
+        .map(|mut entry| {
+            // Entities are recorded with their components in type order.
+            entry.entity.sort();
+            let error_code = alter(ctx, controller, &entry, req.validate_only)
+                .err()
+                .unwrap_or(ErrorCode::None);
+            AlterClientQuotasResult {
+                error_code,
+                error_message: None,
+                entity: entry.entity,
+            }
+        })
+        .collect();
+    Ok(AlterClientQuotasResponse::new(ctx, entries))
+}
+
+fn alter(
+    ctx: &RequestContext,
+    controller: &Controller,
+    entry: &AlterClientQuotasEntry,
+    validate_only: bool,
+) -> Result<(), ErrorCode> {
+    let mut changes = BTreeMap::new();
+    for op in &entry.ops {
+        let value = (!op.remove).then_some(op.value);
+        if changes.insert(op.key.clone(), value).is_some() {
+            return Err(ErrorCode::InvalidRequest);
+        }
+    }
+    if !validate_only && !ctx.commit.commit() {
+        return Err(ErrorCode::RequestTimedOut);
+    }
+    controller.alter_client_quotas(&entry.entity, &changes, validate_only)
+}
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::cluster_metadata::{BROKER_CONFIG_RESOURCE, TOPIC_CONFIG_RESOURCE};
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::controller::Controller;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+/// AlterConfigs request, v0-1: the full set of configs each resource should
+/// have afterwards.
+pub struct AlterConfigsRequest {
+    pub resources: Vec<AlterConfigsResource>,
+    pub validate_only: bool,
+}
+
+#[derive(Clone)]
+pub struct AlterConfigsResource {
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub configs: Vec<AlterableConfig>,
+}
+
+#[derive(Clone)]
+pub struct AlterableConfig {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Deserialize<Self> for AlterConfigsRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            resources: Array::<AlterConfigsResource>::deserialize(src)?,
+            validate_only: src.try_get_u8()? != 0,
+        })
+    }
+}
+
+/// Written as v1.
+impl Serialize for AlterConfigsRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(Array(self.resources.clone()).serialize());
+        b.put_u8(self.validate_only.into());
+        b.freeze()
+    }
+}
+
+impl Serialize for AlterConfigsResource {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i8(self.resource_type);
+        b.put(NullableString(Some(self.resource_name.clone())).serialize());
+        b.put(Array(self.configs.clone()).serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for AlterableConfig {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(NullableString(Some(self.name.clone())).serialize());
+        b.put(NullableString(self.value.clone()).serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for AlterConfigsResource {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            resource_type: src.try_get_i8()?,
+            resource_name: NullableString::deserialize(src)?.0.unwrap_or_default(),
+            configs: Array::<AlterableConfig>::deserialize(src)?,
+        })
+    }
+}
+
+impl Deserialize<Self> for AlterableConfig {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            name: NullableString::deserialize(src)?.0.unwrap_or_default(),
+            value: NullableString::deserialize(src)?.0,
+        })
+    }
+}
+
+/// AlterConfigs response, v0-1.
+pub struct AlterConfigsResponse {
+    header: HeaderV0,
+    throttle_time_ms: i32,
+    pub responses: Array<AlterConfigsResourceResponse>,
+}
+
+/// Reads a whole response, header included, as a client gets it.
+impl Deserialize<Self> for AlterConfigsResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            header: HeaderV0::deserialize(src)?,
+            throttle_time_ms: src.try_get_i32()?,
+            responses: Array(Array::<AlterConfigsResourceResponse>::deserialize(src)?),
+        })
+    }
+}
+
+pub struct AlterConfigsResourceResponse {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub resource_type: i8,
+    pub resource_name: String,
+}
+
+impl AlterConfigsResourceResponse {
+    fn new(resource: &AlterConfigsResource, error_code: ErrorCode) -> Self {
+        Self {
+            error_code,
+            error_message: None,
+            resource_type: resource.resource_type,
+            resource_name: resource.resource_name.clone(),
+        }
+    }
+}
+
+impl Deserialize<Self> for AlterConfigsResourceResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            error_code: ErrorCode::from(src.try_get_i16()?),
+            error_message: NullableString::deserialize(src)?.0,
+            resource_type: src.try_get_i8()?,
+            resource_name: NullableString::deserialize(src)?.0.unwrap_or_default(),
+        })
+    }
+}
+
+impl Serialize for AlterConfigsResourceResponse {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i16(self.error_code.into());
+        b.put(NullableString(self.error_message.clone()).serialize());
+        b.put_i8(self.resource_type);
+        b.put(NullableString(Some(self.resource_name.clone())).serialize());
+        b.freeze()
+    }
+}
+
+impl AlterConfigsResponse {
+    fn new(ctx: &RequestContext, responses: Vec<AlterConfigsResourceResponse>) -> Self {
+        Self {
+            header: HeaderV0::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            responses: Array(responses),
+        }
+    }
+}
+
+impl Response for AlterConfigsResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(self.responses.serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.responses
+            .0
+            .iter()
+            .map(|r| r.error_code)
+            .find(|&e| e != ErrorCode::None)
+            .unwrap_or(ErrorCode::None)
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        for response in &mut self.responses.0 {
+            if response.error_code != ErrorCode::None {
+                response.error_message = Some(error_message.to_string());
+            }
+        }
+    }
+}
+
+/// Served by nodes that are also controllers, which record the new configs
+/// in the metadata log.
+pub struct AlterConfigsHandler {
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl AlterConfigsHandler {
+    pub fn new(controller: Arc<Controller>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for AlterConfigsHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.controller, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = AlterConfigsRequest::deserialize(body)?;
+        let responses = req
+            .resources
+            .iter()
+            .map(|r| AlterConfigsResourceResponse::new(r, error_code))
+            .collect();
+        Ok(Box::new(AlterConfigsResponse::new(ctx, responses)))
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Topic configs take AlterConfigs on the topic, broker configs on the
+/// cluster. Each resource is altered on its own.
+pub fn handle_request(
+    ctx: &RequestContext,
+    controller: &Controller,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<AlterConfigsResponse, Error> {
+    let req = AlterConfigsRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::AlterConfigs, e))?;
+    let responses = req
+        .resources
+        .iter()
+        .map(|resource| {
+            let error_code = alter(ctx, controller, authorizer, resource, req.validate_only)
+                .err()
+                .unwrap_or(ErrorCode::None);
+            AlterConfigsResourceResponse::new(resource, error_code)
+        })
+        .collect();
+    Ok(AlterConfigsResponse::new(ctx, responses))
+}
+
+fn alter(
+    ctx: &RequestContext,
+    controller: &Controller,
+    authorizer: &dyn Authorizer,
+    resource: &AlterConfigsResource,
+    validate_only: bool,
+) -> Result<(), ErrorCode> {
+    let (acl_resource, acl_name, denied) = match resource.resource_type {
+        TOPIC_CONFIG_RESOURCE => (
+            ResourceType::Topic,
+            resource.resource_name.as_str(),
+            ErrorCode::TopicAuthorizationFailed,
+        ),
+        // A broker's id, or empty for the cluster-wide default.
+        BROKER_CONFIG_RESOURCE
+            if resource.resource_name.is_empty()
+                || resource.resource_name.parse::<i32>().is_ok() =>
+        {
+            (
+                ResourceType::Cluster,
+                CLUSTER_RESOURCE_NAME,
+                ErrorCode::ClusterAuthorizationFailed,
+            )
+        }
+        _ => return Err(ErrorCode::InvalidRequest),
+    };
+    if !authorizer.authorize(ctx, AclOperation::AlterConfigs, acl_resource, acl_name) {
+        return Err(denied);
+    }
+    let mut configs = BTreeMap::new();
+    for config in &resource.configs {
+        // A null value leaves the config out, which removes it.
+        let Some(value) = &config.value else {
+            continue;
+        };
+        if configs.insert(config.name.clone(), value.clone()).is_some() {
+            return Err(ErrorCode::InvalidRequest);
+        }
+    }
+    if !validate_only && !ctx.commit.commit() {
+        return Err(ErrorCode::RequestTimedOut);
+    }
+    controller.alter_configs(
+        resource.resource_type,
+        &resource.resource_name,
+        &configs,
+        validate_only,
+    )
+}
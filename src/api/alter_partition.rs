@@ -0,0 +1,464 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::cluster_metadata::{PartitionChangeValue, PartitionValue, RecordBatches};
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::controller::Controller;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::raft::MetadataQuorum;
+use crate::request_context::RequestContext;
+
+/// The only leader recovery state this broker knows: the leader was elected
+/// from the ISR.
+pub const RECOVERED: i8 = 0;
+
+/// AlterPartition request, v2: a partition leader asking the controller to
+/// change its ISR.
+#[derive(Clone)]
+pub struct AlterPartitionRequest {
+    pub broker_id: i32,
+    /// -1 when the leader doesn't know its own registration.
+    pub broker_epoch: i64,
+    pub topics: Vec<AlterPartitionTopic>,
+}
+
+#[derive(Clone)]
+pub struct AlterPartitionTopic {
+    pub topic_id: Uuid,
+    pub partitions: Vec<AlterPartitionPartition>,
+}
+
+#[derive(Clone)]
+pub struct AlterPartitionPartition {
+    pub partition_index: i32,
+    pub leader_epoch: i32,
+    pub new_isr: Vec<i32>,
+    pub leader_recovery_state: i8,
+    /// The partition epoch the change is based on.
+    pub partition_epoch: i32,
+}
+
+impl Deserialize<Self> for AlterPartitionRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let broker_id = src.try_get_i32()?;
+        let broker_epoch = src.try_get_i64()?;
+        let topics = CompactArray::<AlterPartitionTopic>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            broker_id,
+            broker_epoch,
+            topics,
+        })
+    }
+}
+
+impl Serialize for AlterPartitionRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.broker_id);
+        b.put_i64(self.broker_epoch);
+        b.put(CompactArray(self.topics.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for AlterPartitionTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topic_id = Uuid::deserialize(src)?;
+        let partitions = CompactArray::<AlterPartitionPartition>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            topic_id,
+            partitions,
+        })
+    }
+}
+
+impl Serialize for AlterPartitionTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(self.topic_id.serialize());
+        b.put(CompactArray(self.partitions.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for AlterPartitionPartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let partition = Self {
+            partition_index: src.try_get_i32()?,
+            leader_epoch: src.try_get_i32()?,
+            new_isr: CompactArray::deserialize_with(src, |src| Ok(src.try_get_i32()?))?,
+            leader_recovery_state: src.try_get_i8()?,
+            partition_epoch: src.try_get_i32()?,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(partition)
+    }
+}
+
+impl Serialize for AlterPartitionPartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i32(self.leader_epoch);
+        b.put(CompactArray(self.new_isr.clone()).serialize());
+        b.put_i8(self.leader_recovery_state);
+        b.put_i32(self.partition_epoch);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// AlterPartition response, v2.
+pub struct AlterPartitionResponse {
+    header: HeaderV1,
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub topics: CompactArray<AlterPartitionResponseTopic>,
+}
+
+pub struct AlterPartitionResponseTopic {
+    pub topic_id: Uuid,
+    pub partitions: CompactArray<AlterPartitionResponsePartition>,
+}
+
+/// The partition as it stands after the request, whether or not it was
+/// changed.
+pub struct AlterPartitionResponsePartition {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub isr: Vec<i32>,
+    pub leader_recovery_state: i8,
+    pub partition_epoch: i32,
+}
+
+impl AlterPartitionResponse {
+    fn new(
+        ctx: &RequestContext,
+        error_code: ErrorCode,
+        topics: Vec<AlterPartitionResponseTopic>,
+    ) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            topics: CompactArray(topics),
+        }
+    }
+}
+
+impl Response for AlterPartitionResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(self.topics.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+}
+
+/// Reads a whole response, header included, as the partition leader gets it.
+impl Deserialize<Self> for AlterPartitionResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let error_code = ErrorCode::from(src.try_get_i16()?);
+        let topics = CompactArray::<AlterPartitionResponseTopic>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            header,
+            throttle_time_ms,
+            error_code,
+            topics: CompactArray(topics),
+        })
+    }
+}
+
+impl Serialize for AlterPartitionResponseTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(self.topic_id.serialize());
+        b.put(self.partitions.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for AlterPartitionResponseTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topic_id = Uuid::deserialize(src)?;
+        let partitions = CompactArray::<AlterPartitionResponsePartition>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            topic_id,
+            partitions: CompactArray(partitions),
+        })
+    }
+}
+
+impl AlterPartitionResponsePartition {
+    fn error(partition_index: i32, error_code: ErrorCode) -> Self {
+        Self {
+            partition_index,
+            error_code,
+            leader_id: -1,
+            leader_epoch: -1,
+            isr: Vec::new(),
+            leader_recovery_state: RECOVERED,
+            partition_epoch: -1,
+        }
+    }
+
+    fn current(partition: &PartitionValue) -> Self {
+        Self {
+            partition_index: partition.partition_id as i32,
+            error_code: ErrorCode::None,
+            leader_id: partition.leader_id as i32,
+            leader_epoch: partition.leader_epoch as i32,
+            isr: partition
+                .in_sync_replicas
+                .iter()
+                .map(|&id| id as i32)
+                .collect(),
+            leader_recovery_state: RECOVERED,
+            partition_epoch: partition.partition_epoch as i32,
+        }
+    }
+}
+
+impl Serialize for AlterPartitionResponsePartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i16(self.error_code.into());
+        b.put_i32(self.leader_id);
+        b.put_i32(self.leader_epoch);
+        b.put(CompactArray(self.isr.clone()).serialize());
+        b.put_i8(self.leader_recovery_state);
+        b.put_i32(self.partition_epoch);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for AlterPartitionResponsePartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let partition = Self {
+            partition_index: src.try_get_i32()?,
+            error_code: ErrorCode::from(src.try_get_i16()?),
+            leader_id: src.try_get_i32()?,
+            leader_epoch: src.try_get_i32()?,
+            isr: CompactArray::deserialize_with(src, |src| Ok(src.try_get_i32()?))?,
+            leader_recovery_state: src.try_get_i8()?,
+            partition_epoch: src.try_get_i32()?,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(partition)
+    }
+}
+
+pub struct AlterPartitionHandler {
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl AlterPartitionHandler {
+    pub fn new(controller: Arc<Controller>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for AlterPartitionHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let _changes = self.controller.lock_changes();
+        let res = handle_request(ctx, self.controller.quorum(), &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(AlterPartitionResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+}
+
+/// Checks each change against the partition as the metadata log has it and
+/// appends the valid ones through the quorum as one batch. Only the quorum
+/// leader takes changes; the other voters answer NOT_CONTROLLER.
+pub fn handle_request(
+    ctx: &RequestContext,
+    quorum: &MetadataQuorum,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<AlterPartitionResponse, Error> {
+    let req = AlterPartitionRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::AlterPartition, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::ClusterAction,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(AlterPartitionResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    let metadata = RecordBatches::from_file(quorum.log_file()).map_err(Error::Storage)?;
+    let registered_epoch = metadata
+        .brokers()
+        .find(|b| b.broker_id == req.broker_id)
+        .map(|b| b.broker_epoch);
+    if req.broker_epoch != -1 && registered_epoch.is_some_and(|e| e != req.broker_epoch) {
+        return Ok(AlterPartitionResponse::new(
+            ctx,
+            ErrorCode::StaleBrokerEpoch,
+            Vec::new(),
+        ));
+    }
+
+    let mut changes = Vec::new();
+    let mut topics = Vec::new();
+    for topic in req.topics {
+        let known = metadata.topic(&topic.topic_id).is_some();
+        let partitions = topic
+            .partitions
+            .iter()
+            .map(|p| {
+                if !known {
+                    return AlterPartitionResponsePartition::error(
+                        p.partition_index,
+                        ErrorCode::UnknownTopicId,
+                    );
+                }
+                let Some(current) = metadata
+                    .partitions(&topic.topic_id)
+                    .find(|c| c.partition_id as i32 == p.partition_index)
+                else {
+                    return AlterPartitionResponsePartition::error(
+                        p.partition_index,
+                        ErrorCode::UnknownTopicOrPartition,
+                    );
+                };
+                if let Err(error_code) = validate(&metadata, req.broker_id, current, p) {
+                    return AlterPartitionResponsePartition::error(p.partition_index, error_code);
+                }
+                let mut answer = AlterPartitionResponsePartition::current(current);
+                if answer.isr != p.new_isr {
+                    changes.push(PartitionChangeValue {
+                        partition_id: current.partition_id,
+                        topic_id: topic.topic_id.clone(),
+                        isr: Some(p.new_isr.iter().map(|&id| id as u32).collect()),
+                        leader: None,
+                        replicas: None,
+                        removing_replicas: None,
+                        adding_replicas: None,
+                        directories: None,
+                    });
+                    answer.isr = p.new_isr.clone();
+                    answer.partition_epoch += 1;
+                }
+                answer
+            })
+            .collect();
+        topics.push(AlterPartitionResponseTopic {
+            topic_id: topic.topic_id,
+            partitions: CompactArray(partitions),
+        });
+    }
+
+    // Even with nothing to change, the answer only holds if this node is
+    // the controller.
+    let records = changes.iter().map(PartitionChangeValue::record).collect();
+    match quorum.append(records) {
+        Ok(_) => Ok(AlterPartitionResponse::new(ctx, ErrorCode::None, topics)),
+        Err(ErrorCode::NotController) => Ok(AlterPartitionResponse::new(
+            ctx,
+            ErrorCode::NotController,
+            Vec::new(),
+        )),
+        Err(error_code) => {
+            for topic in &mut topics {
+                for p in &mut topic.partitions.0 {
+                    let changed = changes.iter().any(|c| {
+                        c.topic_id == topic.topic_id && c.partition_id as i32 == p.partition_index
+                    });
+                    if changed {
+                        *p = AlterPartitionResponsePartition::error(p.partition_index, error_code);
+                    }
+                }
+            }
+            Ok(AlterPartitionResponse::new(ctx, ErrorCode::None, topics))
+        }
+    }
+}
+
+/// Whether `broker_id` may make the change to `current`: it has to be the
+/// leader in the same epoch, and the new ISR has to be made of live
+/// replicas that include it.
+fn validate(
+    metadata: &RecordBatches,
+    broker_id: i32,
+    current: &PartitionValue,
+    change: &AlterPartitionPartition,
+) -> Result<(), ErrorCode> {
+    if current.leader_id as i32 != broker_id {
+        return Err(ErrorCode::NotLeaderOrFollower);
+    }
+    let leader_epoch = current.leader_epoch as i32;
+    if change.leader_epoch < leader_epoch {
+        return Err(ErrorCode::FencedLeaderEpoch);
+    }
+    if change.leader_epoch > leader_epoch {
+        return Err(ErrorCode::NotLeaderOrFollower);
+    }
+    if change.partition_epoch != current.partition_epoch as i32 {
+        return Err(ErrorCode::InvalidUpdateVersion);
+    }
+    if change.leader_recovery_state != RECOVERED {
+        return Err(ErrorCode::InvalidRequest);
+    }
+    let mut seen = Vec::new();
+    for &id in &change.new_isr {
+        let fenced = metadata
+            .brokers()
+            .find(|b| b.broker_id == id)
+            .is_some_and(|b| b.fenced);
+        if seen.contains(&id) || fenced || !current.replicas.iter().any(|&r| r as i32 == id) {
+            return Err(ErrorCode::IneligibleReplica);
+        }
+        seen.push(id);
+    }
+    if !seen.contains(&broker_id) {
+        return Err(ErrorCode::IneligibleReplica);
+    }
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::controller::Controller;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+/// AlterPartitionReassignments request, v0: the replicas each partition
+/// should move to, or null to cancel its reassignment.
+pub struct AlterPartitionReassignmentsRequest {
+    pub timeout_ms: i32,
+    pub topics: Vec<ReassignableTopic>,
+}
+
+pub struct ReassignableTopic {
+    pub name: String,
+    pub partitions: Vec<ReassignablePartition>,
+}
+
+pub struct ReassignablePartition {
+    pub partition_index: i32,
+    pub replicas: Option<Vec<i32>>,
+}
+
+impl Deserialize<Self> for AlterPartitionReassignmentsRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let timeout_ms = src.try_get_i32()?;
+        let topics = CompactArray::deserialize_with(src, |src| {
+            let name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let partitions = CompactArray::deserialize_with(src, |src| {
+                let partition = ReassignablePartition {
+                    partition_index: src.try_get_i32()?,
+                    replicas: CompactArray::deserialize_nullable_with(src, |src| {
+                        Ok(src.try_get_i32()?)
+                    })?,
+                };
+                TagBuffer::deserialize_fields(src)?;
+                Ok(partition)
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(ReassignableTopic { name, partitions })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self { timeout_ms, topics })
+    }
+}
+
+/// AlterPartitionReassignments response, v0.
+pub struct AlterPartitionReassignmentsResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+    error_message: Option<String>,
+    responses: CompactArray<ReassignableTopicResponse>,
+}
+
+pub struct ReassignableTopicResponse {
+    pub name: String,
+    pub partitions: CompactArray<ReassignablePartitionResponse>,
+}
+
+pub struct ReassignablePartitionResponse {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+}
+
+impl Serialize for ReassignableTopicResponse {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(self.partitions.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for ReassignablePartitionResponse {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i16(self.error_code.into());
+        b.put(CompactNullableString(self.error_message.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl AlterPartitionReassignmentsResponse {
+    /// Answers every partition in `req` with the code `result` gives it.
+    fn new(
+        ctx: &RequestContext,
+        req: &AlterPartitionReassignmentsRequest,
+        error_code: ErrorCode,
+        mut result: impl FnMut(&ReassignableTopic, &ReassignablePartition) -> ErrorCode,
+    ) -> Self {
+        let responses = req
+            .topics
+            .iter()
+            .map(|topic| ReassignableTopicResponse {
+                name: topic.name.clone(),
+                partitions: CompactArray(
+                    topic
+                        .partitions
+                        .iter()
+                        .map(|p| ReassignablePartitionResponse {
+                            partition_index: p.partition_index,
+                            error_code: result(topic, p),
+                            error_message: None,
+                        })
+                        .collect(),
+                ),
+            })
+            .collect();
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            error_message: None,
+            responses: CompactArray(responses),
+        }
+    }
+}
+
+impl Response for AlterPartitionReassignmentsResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(CompactNullableString(self.error_message.clone()).serialize());
+        bytes.put(self.responses.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        if self.error_code != ErrorCode::None {
+            return self.error_code;
+        }
+        self.responses
+            .0
+            .iter()
+            .flat_map(|t| t.partitions.0.iter())
+            .map(|p| p.error_code)
+            .find(|&e| e != ErrorCode::None)
+            .unwrap_or(ErrorCode::None)
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        self.error_message = Some(error_message.to_string());
+    }
+}
+
+/// Served by nodes that are also controllers, which record the new
+/// replicas in the metadata log.
+pub struct AlterPartitionReassignmentsHandler {
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl AlterPartitionReassignmentsHandler {
+    pub fn new(controller: Arc<Controller>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for AlterPartitionReassignmentsHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.controller, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = AlterPartitionReassignmentsRequest::deserialize(body)?;
+        Ok(Box::new(AlterPartitionReassignmentsResponse::new(
+            ctx,
+            &req,
+            error_code,
+            |_, _| error_code,
+        )))
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes Alter on the cluster. Each partition is reassigned on its own;
+/// the response doesn't wait for any reassignment to complete.
+pub fn handle_request(
+    ctx: &RequestContext,
+    controller: &Controller,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<AlterPartitionReassignmentsResponse, Error> {
+    let req = AlterPartitionReassignmentsRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::AlterPartitionReassignments, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Alter,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        let denied = ErrorCode::ClusterAuthorizationFailed;
+        return Ok(AlterPartitionReassignmentsResponse::new(
+            ctx,
+            &req,
+            denied,
+            |_, _| denied,
+        ));
+    }
+    Ok(AlterPartitionReassignmentsResponse::new(
+        ctx,
+        &req,
+        ErrorCode::None,
+        |topic, p| {
+            if !ctx.commit.commit() {
+                return ErrorCode::RequestTimedOut;
+            }
+            controller
+                .alter_reassignment(&topic.name, p.partition_index, p.replicas.as_deref())
+                .err()
+                .unwrap_or(ErrorCode::None)
+        },
+    ))
+}
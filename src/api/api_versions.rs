@@ -1,62 +1,173 @@
+use std::sync::Arc;
+
 use bytes::{BufMut, Bytes, BytesMut};
 
+use crate::api::{ApiHandler, SupportedVersions};
+use crate::error::Error;
+use crate::features::{FeatureCache, FinalizedFeatures, SUPPORTED_FEATURES};
+use crate::listener::ListenerType;
 use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+/// Answers ApiVersions with the APIs registered alongside it that the
+/// finalized feature levels turn on.
+pub struct ApiVersionsHandler {
+    versions: SupportedVersions,
+    features: Arc<FeatureCache>,
+}
+
+impl ApiVersionsHandler {
+    pub fn new(versions: SupportedVersions, features: Arc<FeatureCache>) -> Self {
+        Self { versions, features }
+    }
+}
 
-const API_KEYS: [ApiVersionsApiKey; 3] = [
-    ApiVersionsApiKey {
-        key: ApiKey::ApiVersions,
-        min_version: 0,
-        max_version: 4,
-    },
-    ApiVersionsApiKey {
-        key: ApiKey::DescribeTopicPartitions,
-        min_version: 0,
-        max_version: 0,
-    },
-    ApiVersionsApiKey {
-        key: ApiKey::Fetch,
-        min_version: 0,
-        max_version: 16,
-    },
-];
+impl ApiHandler for ApiVersionsHandler {
+    fn handle(&self, ctx: &RequestContext, _: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        Ok(Box::new(ApiVersionsResponseV3::new(
+            &ctx.header,
+            ctx.listener_type,
+            &self.versions,
+            self.features.finalized(),
+        )))
+    }
+
+    /// ApiVersions is answered in full even then, so the client can still
+    /// find out what it may send.
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        _: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(ApiVersionsResponseV3::new(
+            &ctx.header,
+            ctx.listener_type,
+            &self.versions,
+            self.features.finalized(),
+        )))
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
 
 pub struct ApiVersionsResponseV3 {
+    api_version: i16,
     header: HeaderV0,
     error_code: ErrorCode,
     api_keys: CompactArray<ApiVersionsApiKey>,
     throttle_time_ms: i32,
+    finalized: FinalizedFeatures,
 }
 
 impl ApiVersionsResponseV3 {
-    pub fn new(req_header: HeaderV2) -> Self {
+    /// Lists those of the registered APIs served on listeners of
+    /// `listener_type` that `finalized` turns on.
+    pub fn new(
+        req_header: &HeaderV2,
+        listener_type: ListenerType,
+        versions: &SupportedVersions,
+        finalized: FinalizedFeatures,
+    ) -> Self {
         let header = HeaderV0::new(req_header.correlation_id);
 
         let mut error_code = ErrorCode::None;
-        if !matches!(req_header.api_version, 0..=4) {
+        if !versions
+            .get(ApiKey::ApiVersions)
+            .is_some_and(|v| v.contains(&req_header.api_version))
+        {
             error_code = ErrorCode::UnsupportedVersion
         }
 
         Self {
+            api_version: req_header.api_version,
             header,
             error_code,
-            api_keys: CompactArray(API_KEYS.to_vec()),
+            api_keys: CompactArray(
+                versions
+                    .all()
+                    .into_iter()
+                    .filter(|(key, _)| key.is_enabled_on(listener_type) && finalized.enables(*key))
+                    .map(|(key, versions)| ApiVersionsApiKey {
+                        key,
+                        min_version: *versions.start(),
+                        max_version: *versions.end(),
+                    })
+                    .collect(),
+            ),
             throttle_time_ms: 0,
+            finalized,
+        }
+    }
+
+    /// From v3, the features this broker supports and the levels finalized
+    /// for the cluster, as tagged fields.
+    fn feature_tags(&self) -> Bytes {
+        if self.api_version < 3 {
+            return TagBuffer::serialize();
         }
+        let supported = SUPPORTED_FEATURES
+            .iter()
+            .map(|f| FeatureRange {
+                name: f.name,
+                first: f.min_level,
+                second: f.max_level,
+            })
+            .collect();
+        // Finalized features have a max and a min level; since KIP-778 both
+        // are the one finalized level.
+        let finalized = self
+            .finalized
+            .levels
+            .iter()
+            .map(|(name, &level)| FeatureRange {
+                name,
+                first: level,
+                second: level,
+            })
+            .collect();
+        TagBuffer::serialize_fields(&[
+            (0, CompactArray::<FeatureRange>(supported).serialize()),
+            (
+                1,
+                Bytes::copy_from_slice(&self.finalized.epoch.to_be_bytes()),
+            ),
+            (2, CompactArray::<FeatureRange>(finalized).serialize()),
+        ])
     }
 }
 
 impl Response for ApiVersionsResponseV3 {
+    /// Up to v2 the response isn't flexible. A request at a version this
+    /// broker doesn't know is answered as v0, which any client can read.
     fn as_bytes(&self) -> Bytes {
         let mut bytes = BytesMut::from(self.header.serialize());
         bytes.put_i16(self.error_code.into());
-        bytes.put(self.api_keys.serialize());
-        bytes.put_i32(self.throttle_time_ms);
-        bytes.put(TagBuffer::serialize());
+        if self.api_version >= 3 && self.error_code != ErrorCode::UnsupportedVersion {
+            bytes.put(self.api_keys.serialize());
+            bytes.put_i32(self.throttle_time_ms);
+            bytes.put(self.feature_tags());
+            return bytes.freeze();
+        }
+        bytes.put_i32(self.api_keys.0.len() as i32);
+        for api_key in &self.api_keys.0 {
+            bytes.put_i16(api_key.key.into());
+            bytes.put_i16(api_key.min_version);
+            bytes.put_i16(api_key.max_version);
+        }
+        if (1..3).contains(&self.api_version) {
+            bytes.put_i32(self.throttle_time_ms);
+        }
         bytes.freeze()
     }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
 }
 
-#[derive(Clone)]
 struct ApiVersionsApiKey {
     key: ApiKey,
     min_version: i16,
@@ -73,3 +184,21 @@ impl Serialize for ApiVersionsApiKey {
         b.freeze()
     }
 }
+
+/// A feature and two levels of it, as both feature lists lay them out.
+struct FeatureRange<'a> {
+    name: &'a str,
+    first: i16,
+    second: i16,
+}
+
+impl Serialize for FeatureRange<'_> {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.to_string())).serialize());
+        b.put_i16(self.first);
+        b.put_i16(self.second);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
@@ -0,0 +1,365 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::cluster_metadata::{PartitionChangeValue, RecordBatches};
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::controller::{Controller, UNASSIGNED_DIRECTORY};
+use crate::error::Error;
+use crate::features::METADATA_VERSION_3_7_IV2;
+use crate::protocol::*;
+use crate::raft::MetadataQuorum;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// AssignReplicasToDirs request, v0: a broker telling the controller which
+/// of its log directories each of its replicas lives in.
+#[derive(Clone)]
+pub struct AssignReplicasToDirsRequest {
+    pub broker_id: i32,
+    pub broker_epoch: i64,
+    pub directories: Vec<AssignReplicasToDirsDirectory>,
+}
+
+#[derive(Clone)]
+pub struct AssignReplicasToDirsDirectory {
+    pub id: Uuid,
+    pub topics: Vec<AssignReplicasToDirsTopic>,
+}
+
+#[derive(Clone)]
+pub struct AssignReplicasToDirsTopic {
+    pub topic_id: Uuid,
+    pub partitions: Vec<i32>,
+}
+
+impl Deserialize<Self> for AssignReplicasToDirsRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let broker_id = src.try_get_i32()?;
+        let broker_epoch = src.try_get_i64()?;
+        let directories = CompactArray::<AssignReplicasToDirsDirectory>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            broker_id,
+            broker_epoch,
+            directories,
+        })
+    }
+}
+
+impl Serialize for AssignReplicasToDirsRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.broker_id);
+        b.put_i64(self.broker_epoch);
+        b.put(CompactArray(self.directories.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for AssignReplicasToDirsDirectory {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let id = Uuid::deserialize(src)?;
+        let topics = CompactArray::<AssignReplicasToDirsTopic>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self { id, topics })
+    }
+}
+
+impl Serialize for AssignReplicasToDirsDirectory {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(self.id.serialize());
+        b.put(CompactArray(self.topics.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for AssignReplicasToDirsTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topic_id = Uuid::deserialize(src)?;
+        let partitions = CompactArray::deserialize_with(src, |src| {
+            let partition_index = src.try_get_i32()?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(partition_index)
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            topic_id,
+            partitions,
+        })
+    }
+}
+
+impl Serialize for AssignReplicasToDirsTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(self.topic_id.serialize());
+        b.put_slice(&integer_encoding::VarInt::encode_var_vec(
+            self.partitions.len() as u32 + 1,
+        ));
+        for partition_index in &self.partitions {
+            b.put_i32(*partition_index);
+            b.put(TagBuffer::serialize());
+        }
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// AssignReplicasToDirs response, v0.
+pub struct AssignReplicasToDirsResponse {
+    header: HeaderV1,
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub directories: Vec<AssignReplicasToDirsResult>,
+}
+
+/// What became of each partition assigned to one directory.
+pub struct AssignReplicasToDirsResult {
+    pub id: Uuid,
+    /// By topic id, each partition with its error code.
+    pub topics: Vec<(Uuid, Vec<(i32, ErrorCode)>)>,
+}
+
+impl AssignReplicasToDirsResponse {
+    fn new(
+        ctx: &RequestContext,
+        error_code: ErrorCode,
+        directories: Vec<AssignReplicasToDirsResult>,
+    ) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            directories,
+        }
+    }
+}
+
+impl Response for AssignReplicasToDirsResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(CompactArray(self.directories.iter().collect()).serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+}
+
+/// Reads a whole response, header included, as the broker gets it.
+impl Deserialize<Self> for AssignReplicasToDirsResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let error_code = ErrorCode::from(src.try_get_i16()?);
+        let directories = CompactArray::deserialize_with(src, |src| {
+            let id = Uuid::deserialize(src)?;
+            let topics = CompactArray::deserialize_with(src, |src| {
+                let topic_id = Uuid::deserialize(src)?;
+                let partitions = CompactArray::deserialize_with(src, |src| {
+                    let partition = (src.try_get_i32()?, ErrorCode::from(src.try_get_i16()?));
+                    TagBuffer::deserialize_fields(src)?;
+                    Ok(partition)
+                })?;
+                TagBuffer::deserialize_fields(src)?;
+                Ok((topic_id, partitions))
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(AssignReplicasToDirsResult { id, topics })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            header,
+            throttle_time_ms,
+            error_code,
+            directories,
+        })
+    }
+}
+
+impl Serialize for &AssignReplicasToDirsResult {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(self.id.serialize());
+        b.put_slice(&integer_encoding::VarInt::encode_var_vec(
+            self.topics.len() as u32 + 1,
+        ));
+        for (topic_id, partitions) in &self.topics {
+            b.put(topic_id.serialize());
+            b.put_slice(&integer_encoding::VarInt::encode_var_vec(
+                partitions.len() as u32 + 1,
+            ));
+            for (partition_index, error_code) in partitions {
+                b.put_i32(*partition_index);
+                b.put_i16((*error_code).into());
+                b.put(TagBuffer::serialize());
+            }
+            b.put(TagBuffer::serialize());
+        }
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+pub struct AssignReplicasToDirsHandler {
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl AssignReplicasToDirsHandler {
+    pub fn new(controller: Arc<Controller>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for AssignReplicasToDirsHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let _changes = self.controller.lock_changes();
+        let res = handle_request(ctx, self.controller.quorum(), &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(AssignReplicasToDirsResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        _: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = AssignReplicasToDirsRequest::deserialize(body)?;
+        dump.field("broker_id", req.broker_id)
+            .field("broker_epoch", req.broker_epoch)
+            .list("directories", &req.directories, |dump, directory| {
+                dump.field("id", &directory.id)
+                    .list("topics", &directory.topics, |dump, topic| {
+                        dump.field("topic_id", &topic.topic_id)
+                            .field("partitions", format_args!("{:?}", topic.partitions));
+                    });
+            });
+        Ok(())
+    }
+}
+
+/// Moves each partition's replica on the broker to the directory it is
+/// listed under, as PartitionChangeRecords appended together. The
+/// metadata log only carries directories from metadata.version 3.7-IV2.
+pub fn handle_request(
+    ctx: &RequestContext,
+    quorum: &MetadataQuorum,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<AssignReplicasToDirsResponse, Error> {
+    let req = AssignReplicasToDirsRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::AssignReplicasToDirs, e))?;
+    let error = |error_code| AssignReplicasToDirsResponse::new(ctx, error_code, Vec::new());
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::ClusterAction,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(error(ErrorCode::ClusterAuthorizationFailed));
+    }
+    let metadata = RecordBatches::from_file(quorum.log_file()).map_err(Error::Storage)?;
+    if metadata.finalized_features().metadata_version() < METADATA_VERSION_3_7_IV2 {
+        return Ok(error(ErrorCode::UnsupportedVersion));
+    }
+    match metadata.brokers().find(|b| b.broker_id == req.broker_id) {
+        None => return Ok(error(ErrorCode::BrokerIdNotRegistered)),
+        Some(broker) if broker.broker_epoch != req.broker_epoch => {
+            return Ok(error(ErrorCode::StaleBrokerEpoch))
+        }
+        Some(_) => {}
+    }
+
+    let mut changes: Vec<PartitionChangeValue> = Vec::new();
+    let mut directories = Vec::new();
+    for directory in req.directories {
+        let mut topics = Vec::new();
+        for topic in directory.topics {
+            let known = metadata.topic(&topic.topic_id).is_some();
+            let partitions = topic
+                .partitions
+                .iter()
+                .map(|&partition_index| {
+                    if !known {
+                        return (partition_index, ErrorCode::UnknownTopicId);
+                    }
+                    let Some(p) = metadata.partition(&topic.topic_id, partition_index) else {
+                        return (partition_index, ErrorCode::UnknownTopicOrPartition);
+                    };
+                    let Some(i) = p.replicas.iter().position(|&r| r as i32 == req.broker_id) else {
+                        return (partition_index, ErrorCode::NotLeaderOrFollower);
+                    };
+                    // A partition named twice takes the last directory.
+                    let change = match changes
+                        .iter_mut()
+                        .find(|c| c.topic_id == p.topic_id && c.partition_id == p.partition_id)
+                    {
+                        Some(change) => change,
+                        None => {
+                            changes.push(PartitionChangeValue {
+                                partition_id: p.partition_id,
+                                topic_id: p.topic_id.clone(),
+                                isr: None,
+                                leader: None,
+                                replicas: None,
+                                removing_replicas: None,
+                                adding_replicas: None,
+                                directories: Some(p.directories.clone()),
+                            });
+                            changes.last_mut().unwrap()
+                        }
+                    };
+                    let assigned = change.directories.as_mut().unwrap();
+                    assigned.resize(p.replicas.len(), Uuid(UNASSIGNED_DIRECTORY.to_string()));
+                    assigned[i] = directory.id.clone();
+                    (partition_index, ErrorCode::None)
+                })
+                .collect();
+            topics.push((topic.topic_id, partitions));
+        }
+        directories.push(AssignReplicasToDirsResult {
+            id: directory.id,
+            topics,
+        });
+    }
+
+    let records = changes.iter().map(PartitionChangeValue::record).collect();
+    match quorum.append(records) {
+        Ok(_) => Ok(AssignReplicasToDirsResponse::new(
+            ctx,
+            ErrorCode::None,
+            directories,
+        )),
+        Err(error_code) => Ok(error(error_code)),
+    }
+}
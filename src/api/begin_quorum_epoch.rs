@@ -0,0 +1,317 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::raft::{MetadataQuorum, QuorumEpochResult, METADATA_TOPIC};
+use crate::request_context::RequestContext;
+
+/// BeginQuorumEpoch request, v0: a newly elected leader announcing itself.
+#[derive(Clone)]
+pub struct BeginQuorumEpochRequest {
+    pub cluster_id: Option<String>,
+    pub topics: Vec<BeginQuorumEpochTopic>,
+}
+
+#[derive(Clone)]
+pub struct BeginQuorumEpochTopic {
+    pub topic_name: String,
+    pub partitions: Vec<BeginQuorumEpochPartition>,
+}
+
+#[derive(Clone)]
+pub struct BeginQuorumEpochPartition {
+    pub partition_index: i32,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+}
+
+impl BeginQuorumEpochRequest {
+    /// The request a leader sends for the metadata partition.
+    pub fn metadata(cluster_id: &str, leader_id: i32, leader_epoch: i32) -> Self {
+        Self {
+            cluster_id: Some(cluster_id.to_string()),
+            topics: vec![BeginQuorumEpochTopic {
+                topic_name: METADATA_TOPIC.to_string(),
+                partitions: vec![BeginQuorumEpochPartition {
+                    partition_index: 0,
+                    leader_id,
+                    leader_epoch,
+                }],
+            }],
+        }
+    }
+}
+
+impl Deserialize<Self> for BeginQuorumEpochRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            cluster_id: NullableString::deserialize(src)?.0,
+            topics: Array::<BeginQuorumEpochTopic>::deserialize(src)?,
+        })
+    }
+}
+
+impl Serialize for BeginQuorumEpochRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(NullableString(self.cluster_id.clone()).serialize());
+        b.put(Array(self.topics.clone()).serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for BeginQuorumEpochTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            topic_name: NullableString::deserialize(src)?.0.unwrap_or_default(),
+            partitions: Array::<BeginQuorumEpochPartition>::deserialize(src)?,
+        })
+    }
+}
+
+impl Serialize for BeginQuorumEpochTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(NullableString(Some(self.topic_name.clone())).serialize());
+        b.put(Array(self.partitions.clone()).serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for BeginQuorumEpochPartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            partition_index: src.try_get_i32()?,
+            leader_id: src.try_get_i32()?,
+            leader_epoch: src.try_get_i32()?,
+        })
+    }
+}
+
+impl Serialize for BeginQuorumEpochPartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i32(self.leader_id);
+        b.put_i32(self.leader_epoch);
+        b.freeze()
+    }
+}
+
+/// BeginQuorumEpoch response, v0. EndQuorumEpoch v0 answers in the same
+/// shape.
+pub struct QuorumEpochResponse {
+    header: HeaderV0,
+    pub error_code: ErrorCode,
+    pub topics: Array<QuorumEpochResponseTopic>,
+}
+
+pub struct QuorumEpochResponseTopic {
+    pub topic_name: String,
+    pub partitions: Array<QuorumEpochResponsePartition>,
+}
+
+pub struct QuorumEpochResponsePartition {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+}
+
+impl QuorumEpochResponse {
+    pub(crate) fn new(
+        ctx: &RequestContext,
+        error_code: ErrorCode,
+        topics: Vec<QuorumEpochResponseTopic>,
+    ) -> Self {
+        Self {
+            header: HeaderV0::new(ctx.header.correlation_id),
+            error_code,
+            topics: Array(topics),
+        }
+    }
+
+    /// The answer for the metadata partition, if the response has one.
+    pub fn metadata_partition(&self) -> Option<&QuorumEpochResponsePartition> {
+        self.topics
+            .0
+            .iter()
+            .filter(|t| t.topic_name == METADATA_TOPIC)
+            .flat_map(|t| &t.partitions.0)
+            .find(|p| p.partition_index == 0)
+    }
+}
+
+impl Response for QuorumEpochResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i16(self.error_code.into());
+        bytes.put(self.topics.serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+}
+
+/// Reads a whole response, header included, as the leader gets it.
+impl Deserialize<Self> for QuorumEpochResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            header: HeaderV0::deserialize(src)?,
+            error_code: ErrorCode::from(src.try_get_i16()?),
+            topics: Array(Array::<QuorumEpochResponseTopic>::deserialize(src)?),
+        })
+    }
+}
+
+impl QuorumEpochResponseTopic {
+    /// Answers each of `topic`'s partitions: the metadata partition with
+    /// what `answer` says, any other with UNKNOWN_TOPIC_OR_PARTITION.
+    pub(crate) fn answer<P>(
+        topic_name: String,
+        partitions: impl IntoIterator<Item = (i32, P)>,
+        mut answer: impl FnMut(P) -> QuorumEpochResult,
+    ) -> Self {
+        let partitions = partitions
+            .into_iter()
+            .map(|(partition_index, partition)| {
+                if topic_name != METADATA_TOPIC || partition_index != 0 {
+                    return QuorumEpochResponsePartition {
+                        partition_index,
+                        error_code: ErrorCode::UnknownTopicOrPartition,
+                        leader_id: -1,
+                        leader_epoch: -1,
+                    };
+                }
+                let result = answer(partition);
+                QuorumEpochResponsePartition {
+                    partition_index,
+                    error_code: result.error_code,
+                    leader_id: result.leader_id,
+                    leader_epoch: result.leader_epoch,
+                }
+            })
+            .collect();
+        Self {
+            topic_name,
+            partitions: Array(partitions),
+        }
+    }
+}
+
+impl Serialize for QuorumEpochResponseTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(NullableString(Some(self.topic_name.clone())).serialize());
+        b.put(self.partitions.serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for QuorumEpochResponseTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            topic_name: NullableString::deserialize(src)?.0.unwrap_or_default(),
+            partitions: Array(Array::<QuorumEpochResponsePartition>::deserialize(src)?),
+        })
+    }
+}
+
+impl Serialize for QuorumEpochResponsePartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i16(self.error_code.into());
+        b.put_i32(self.leader_id);
+        b.put_i32(self.leader_epoch);
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for QuorumEpochResponsePartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            partition_index: src.try_get_i32()?,
+            error_code: ErrorCode::from(src.try_get_i16()?),
+            leader_id: src.try_get_i32()?,
+            leader_epoch: src.try_get_i32()?,
+        })
+    }
+}
+
+pub struct BeginQuorumEpochHandler {
+    quorum: MetadataQuorum,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl BeginQuorumEpochHandler {
+    pub fn new(quorum: MetadataQuorum, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self { quorum, authorizer }
+    }
+}
+
+impl ApiHandler for BeginQuorumEpochHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.quorum, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(QuorumEpochResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+}
+
+pub fn handle_request(
+    ctx: &RequestContext,
+    quorum: &MetadataQuorum,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<QuorumEpochResponse, Error> {
+    let req = BeginQuorumEpochRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::BeginQuorumEpoch, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::ClusterAction,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(QuorumEpochResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    if !quorum.is_cluster(req.cluster_id.as_deref()) {
+        return Ok(QuorumEpochResponse::new(
+            ctx,
+            ErrorCode::InconsistentClusterId,
+            Vec::new(),
+        ));
+    }
+    let topics = req
+        .topics
+        .into_iter()
+        .map(|topic| {
+            let partitions = topic.partitions.into_iter().map(|p| (p.partition_index, p));
+            QuorumEpochResponseTopic::answer(topic.topic_name, partitions, |p| {
+                quorum.begin_quorum_epoch(p.leader_id, p.leader_epoch)
+            })
+        })
+        .collect();
+    Ok(QuorumEpochResponse::new(ctx, ErrorCode::None, topics))
+}
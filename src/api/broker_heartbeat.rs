@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::controller::Controller;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+/// BrokerHeartbeat request, v0-1: a broker telling the controller it is
+/// alive, and whether it wants to be fenced or shut down.
+#[derive(Clone)]
+pub struct BrokerHeartbeatRequest {
+    pub broker_id: i32,
+    /// -1 when the broker doesn't know its own registration.
+    pub broker_epoch: i64,
+    pub current_metadata_offset: i64,
+    pub want_fence: bool,
+    pub want_shut_down: bool,
+}
+
+impl Deserialize<Self> for BrokerHeartbeatRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let req = Self {
+            broker_id: src.try_get_i32()?,
+            broker_epoch: src.try_get_i64()?,
+            current_metadata_offset: src.try_get_i64()?,
+            want_fence: src.try_get_u8()? != 0,
+            want_shut_down: src.try_get_u8()? != 0,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(req)
+    }
+}
+
+impl Serialize for BrokerHeartbeatRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.broker_id);
+        b.put_i64(self.broker_epoch);
+        b.put_i64(self.current_metadata_offset);
+        b.put_u8(self.want_fence.into());
+        b.put_u8(self.want_shut_down.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// BrokerHeartbeat response, v0-1.
+pub struct BrokerHeartbeatResponse {
+    header: HeaderV1,
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub is_caught_up: bool,
+    pub is_fenced: bool,
+    pub should_shut_down: bool,
+}
+
+impl BrokerHeartbeatResponse {
+    fn new(ctx: &RequestContext, error_code: ErrorCode) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            is_caught_up: false,
+            is_fenced: true,
+            should_shut_down: false,
+        }
+    }
+}
+
+impl Response for BrokerHeartbeatResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put_u8(self.is_caught_up.into());
+        bytes.put_u8(self.is_fenced.into());
+        bytes.put_u8(self.should_shut_down.into());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+}
+
+/// Reads a whole response, header included, as the broker gets it.
+impl Deserialize<Self> for BrokerHeartbeatResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let res = Self {
+            header: HeaderV1::deserialize(src)?,
+            throttle_time_ms: src.try_get_i32()?,
+            error_code: ErrorCode::from(src.try_get_i16()?),
+            is_caught_up: src.try_get_u8()? != 0,
+            is_fenced: src.try_get_u8()? != 0,
+            should_shut_down: src.try_get_u8()? != 0,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(res)
+    }
+}
+
+pub struct BrokerHeartbeatHandler {
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl BrokerHeartbeatHandler {
+    pub fn new(controller: Arc<Controller>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for BrokerHeartbeatHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.controller, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(BrokerHeartbeatResponse::new(ctx, error_code)))
+    }
+}
+
+/// Records the heartbeat with the controller. A broker reads the metadata
+/// log straight from disk, so it is always caught up.
+pub fn handle_request(
+    ctx: &RequestContext,
+    controller: &Controller,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<BrokerHeartbeatResponse, Error> {
+    let req = BrokerHeartbeatRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::BrokerHeartbeat, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::ClusterAction,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(BrokerHeartbeatResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+        ));
+    }
+    let result = controller.heartbeat(req.broker_id, req.broker_epoch, req.want_fence);
+    let mut res = BrokerHeartbeatResponse::new(ctx, result.error_code);
+    if result.error_code == ErrorCode::None {
+        res.is_caught_up = true;
+        res.is_fenced = result.is_fenced;
+        res.should_shut_down = req.want_shut_down;
+    }
+    Ok(res)
+}
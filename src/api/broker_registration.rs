@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::cluster_metadata::{BrokerEndpoint, BrokerFeature};
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::controller::{BrokerRegistration, Controller};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+/// BrokerRegistration request, v0-1: a starting broker asking the
+/// controller for a broker epoch.
+pub struct BrokerRegistrationRequest {
+    pub broker_id: i32,
+    pub cluster_id: String,
+    pub incarnation_id: Uuid,
+    pub listeners: Vec<BrokerEndpoint>,
+    pub features: Vec<BrokerFeature>,
+    pub rack: Option<String>,
+}
+
+impl BrokerRegistrationRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let broker_id = src.try_get_i32()?;
+        let cluster_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let incarnation_id = Uuid::deserialize(src)?;
+        let listeners = CompactArray::deserialize_with(src, |src| {
+            let endpoint = BrokerEndpoint {
+                name: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                host: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                port: src.try_get_u16()?,
+                security_protocol: src.try_get_i16()?,
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(endpoint)
+        })?;
+        let features = CompactArray::deserialize_with(src, |src| {
+            let feature = BrokerFeature {
+                name: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                min_supported_version: src.try_get_i16()?,
+                max_supported_version: src.try_get_i16()?,
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(feature)
+        })?;
+        let rack = CompactNullableString::deserialize(src)?.0;
+        if api_version >= 1 {
+            src.try_get_u8()?; // is_migrating_zk_broker
+        }
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            broker_id,
+            cluster_id,
+            incarnation_id,
+            listeners,
+            features,
+            rack,
+        })
+    }
+}
+
+/// Writes v1, the version brokers send.
+impl Serialize for BrokerRegistrationRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.broker_id);
+        b.put(CompactNullableString(Some(self.cluster_id.clone())).serialize());
+        b.put(self.incarnation_id.serialize());
+        b.put(CompactArray(self.listeners.clone()).serialize());
+        b.put(CompactArray(self.features.clone()).serialize());
+        b.put(CompactNullableString(self.rack.clone()).serialize());
+        b.put_u8(0); // is_migrating_zk_broker
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// BrokerRegistration response, v0-1.
+pub struct BrokerRegistrationResponse {
+    header: HeaderV1,
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub broker_epoch: i64,
+}
+
+impl BrokerRegistrationResponse {
+    fn new(ctx: &RequestContext, error_code: ErrorCode, broker_epoch: i64) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            broker_epoch,
+        }
+    }
+}
+
+impl Response for BrokerRegistrationResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put_i64(self.broker_epoch);
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+}
+
+/// Reads a whole response, header included, as the broker gets it.
+impl Deserialize<Self> for BrokerRegistrationResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let res = Self {
+            header: HeaderV1::deserialize(src)?,
+            throttle_time_ms: src.try_get_i32()?,
+            error_code: ErrorCode::from(src.try_get_i16()?),
+            broker_epoch: src.try_get_i64()?,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(res)
+    }
+}
+
+pub struct BrokerRegistrationHandler {
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl BrokerRegistrationHandler {
+    pub fn new(controller: Arc<Controller>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for BrokerRegistrationHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.controller, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(BrokerRegistrationResponse::new(
+            ctx, error_code, -1,
+        )))
+    }
+}
+
+/// The controller appends the registration; the broker stays fenced until
+/// it heartbeats with the epoch it is given.
+pub fn handle_request(
+    ctx: &RequestContext,
+    controller: &Controller,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<BrokerRegistrationResponse, Error> {
+    let req = BrokerRegistrationRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::BrokerRegistration, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::ClusterAction,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(BrokerRegistrationResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            -1,
+        ));
+    }
+    let registration = BrokerRegistration {
+        broker_id: req.broker_id,
+        cluster_id: req.cluster_id,
+        incarnation_id: req.incarnation_id,
+        endpoints: req.listeners,
+        rack: req.rack,
+        features: req.features,
+    };
+    match controller.register_broker(registration) {
+        Ok(broker_epoch) => Ok(BrokerRegistrationResponse::new(
+            ctx,
+            ErrorCode::None,
+            broker_epoch,
+        )),
+        Err(error_code) => Ok(BrokerRegistrationResponse::new(ctx, error_code, -1)),
+    }
+}
@@ -0,0 +1,407 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::cluster_metadata::RecordBatches;
+use crate::coordinator::{
+    Assignment, ConsumerGroupHeartbeat, ConsumerGroupHeartbeatResult, GroupCoordinator,
+};
+use crate::error::Error;
+use crate::features::FeatureCache;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+pub struct ConsumerGroupHeartbeatRequest {
+    pub group_id: String,
+    pub member_id: String,
+    pub member_epoch: i32,
+    pub instance_id: Option<String>,
+    pub rack_id: Option<String>,
+    /// -1 when unchanged since the last heartbeat.
+    pub rebalance_timeout_ms: i32,
+    /// `None` when unchanged since the last heartbeat.
+    pub subscribed_topic_names: Option<Vec<String>>,
+    pub server_assignor: Option<String>,
+    /// `None` when unchanged since the last heartbeat.
+    pub topic_partitions: Option<Vec<TopicPartitions>>,
+}
+
+impl ConsumerGroupHeartbeatRequest {
+    pub fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let group_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let member_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let member_epoch = src.try_get_i32()?;
+        let instance_id = CompactNullableString::deserialize(src)?.0;
+        let rack_id = CompactNullableString::deserialize(src)?.0;
+        let rebalance_timeout_ms = src.try_get_i32()?;
+        // A length of 0 is the null array.
+        let subscribed_topic_names = if src.first() == Some(&0) {
+            src.advance(1);
+            None
+        } else {
+            Some(CompactArray::<Self>::deserialize(src)?)
+        };
+        let server_assignor = CompactNullableString::deserialize(src)?.0;
+        let topic_partitions = if src.first() == Some(&0) {
+            src.advance(1);
+            None
+        } else {
+            Some(CompactArray::<TopicPartitions>::deserialize(src)?)
+        };
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            group_id,
+            member_id,
+            member_epoch,
+            instance_id,
+            rack_id,
+            rebalance_timeout_ms,
+            subscribed_topic_names,
+            server_assignor,
+            topic_partitions,
+        })
+    }
+}
+
+impl Serialize for ConsumerGroupHeartbeatRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.group_id.clone())).serialize());
+        b.put(CompactNullableString(Some(self.member_id.clone())).serialize());
+        b.put_i32(self.member_epoch);
+        b.put(CompactNullableString(self.instance_id.clone()).serialize());
+        b.put(CompactNullableString(self.rack_id.clone()).serialize());
+        b.put_i32(self.rebalance_timeout_ms);
+        match &self.subscribed_topic_names {
+            Some(names) => b.put(
+                CompactArray(
+                    names
+                        .iter()
+                        .map(|name| CompactNullableString(Some(name.clone())))
+                        .collect(),
+                )
+                .serialize(),
+            ),
+            None => b.put_u8(0),
+        }
+        b.put(CompactNullableString(self.server_assignor.clone()).serialize());
+        match &self.topic_partitions {
+            Some(topic_partitions) => b.put(CompactArray(topic_partitions.clone()).serialize()),
+            None => b.put_u8(0),
+        }
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<String> for ConsumerGroupHeartbeatRequest {
+    fn deserialize(src: &mut Bytes) -> Result<String, DecodeError> {
+        Ok(CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default())
+    }
+}
+
+/// A topic's partitions, as members report what they own and are told what
+/// they are assigned.
+#[derive(Clone)]
+pub struct TopicPartitions {
+    pub topic_id: Uuid,
+    pub partitions: Vec<i32>,
+}
+
+impl Deserialize<Self> for TopicPartitions {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topic_id = Uuid::deserialize(src)?;
+        let partitions = CompactArray::<Self>::deserialize(src)?;
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            topic_id,
+            partitions,
+        })
+    }
+}
+
+impl Deserialize<i32> for TopicPartitions {
+    fn deserialize(src: &mut Bytes) -> Result<i32, DecodeError> {
+        Ok(src.try_get_i32()?)
+    }
+}
+
+impl Serialize for TopicPartitions {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(self.topic_id.serialize());
+        b.put(CompactArray(self.partitions.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for i32 {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(*self);
+        b.freeze()
+    }
+}
+
+/// ConsumerGroupHeartbeat and ShareGroupHeartbeat response, v0.
+pub struct ConsumerGroupHeartbeatResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub error_message: CompactNullableString,
+    pub member_id: CompactNullableString,
+    pub member_epoch: i32,
+    pub heartbeat_interval_ms: i32,
+    /// Only sent when the member's assignment changed.
+    pub assignment: Option<CompactArray<TopicPartitions>>,
+}
+
+/// Reads a whole response, header included, as a group member gets it.
+impl Deserialize<Self> for ConsumerGroupHeartbeatResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let error_code = ErrorCode::from(src.try_get_i16()?);
+        let error_message = CompactNullableString::deserialize(src)?;
+        let member_id = CompactNullableString::deserialize(src)?;
+        let member_epoch = src.try_get_i32()?;
+        let heartbeat_interval_ms = src.try_get_i32()?;
+        // A nullable struct: -1 for null, 1 followed by the struct.
+        let assignment = if src.try_get_i8()? >= 0 {
+            let topic_partitions = CompactArray::<TopicPartitions>::deserialize(src)?;
+            TagBuffer::deserialize_fields(src)?;
+            Some(CompactArray(topic_partitions))
+        } else {
+            None
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            header,
+            throttle_time_ms,
+            error_code,
+            error_message,
+            member_id,
+            member_epoch,
+            heartbeat_interval_ms,
+            assignment,
+        })
+    }
+}
+
+impl Response for ConsumerGroupHeartbeatResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(self.error_message.serialize());
+        bytes.put(self.member_id.serialize());
+        bytes.put_i32(self.member_epoch);
+        bytes.put_i32(self.heartbeat_interval_ms);
+        match &self.assignment {
+            Some(topic_partitions) => {
+                bytes.put_i8(1);
+                bytes.put(topic_partitions.serialize());
+                bytes.put(TagBuffer::serialize());
+            }
+            None => bytes.put_i8(-1),
+        }
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        self.error_message = CompactNullableString(Some(error_message.to_string()));
+    }
+}
+
+impl ConsumerGroupHeartbeatResponse {
+    pub fn new(
+        ctx: &RequestContext,
+        result: ConsumerGroupHeartbeatResult,
+        topics: &[MetadataTopic],
+    ) -> Self {
+        let assignment = result.assignment.map(|assignment| {
+            CompactArray(
+                assignment
+                    .into_iter()
+                    .filter_map(|(name, partitions)| {
+                        let topic = topics.iter().find(|t| t.name == name)?;
+                        Some(TopicPartitions {
+                            topic_id: topic.id.clone(),
+                            partitions: partitions.into_iter().collect(),
+                        })
+                    })
+                    .collect(),
+            )
+        });
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code: result.error_code,
+            error_message: CompactNullableString(result.error_message),
+            member_id: CompactNullableString(Some(result.member_id)),
+            member_epoch: result.member_epoch,
+            heartbeat_interval_ms: result.heartbeat_interval.as_millis() as i32,
+            assignment,
+        }
+    }
+}
+
+/// A topic in the cluster metadata, as consumer group members address it.
+pub struct MetadataTopic {
+    pub name: String,
+    pub id: Uuid,
+    pub partition_count: i32,
+}
+
+pub fn metadata_topics(record_batches: &RecordBatches) -> Vec<MetadataTopic> {
+    record_batches
+        .topics()
+        .filter_map(|topic| {
+            let name = topic.topic_name.0.clone()?;
+            let partition_count = record_batches
+                .partitions(&topic.topic_id)
+                .map(|p| p.partition_id as i32 + 1)
+                .max()
+                .unwrap_or(0);
+            Some(MetadataTopic {
+                name,
+                id: topic.topic_id.clone(),
+                partition_count,
+            })
+        })
+        .collect()
+}
+
+/// Served once group.version 1 is finalized; before that the consumer
+/// group protocol is off and members are told the API is unsupported.
+pub struct ConsumerGroupHeartbeatHandler {
+    coordinator: GroupCoordinator,
+    authorizer: Arc<dyn Authorizer>,
+    image: Arc<MetadataImage>,
+    features: Arc<FeatureCache>,
+}
+
+impl ConsumerGroupHeartbeatHandler {
+    pub fn new(
+        coordinator: GroupCoordinator,
+        authorizer: Arc<dyn Authorizer>,
+        image: Arc<MetadataImage>,
+        features: Arc<FeatureCache>,
+    ) -> Self {
+        Self {
+            coordinator,
+            authorizer,
+            image,
+            features,
+        }
+    }
+}
+
+impl ApiHandler for ConsumerGroupHeartbeatHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        if !self
+            .features
+            .finalized()
+            .enables(ApiKey::ConsumerGroupHeartbeat)
+        {
+            return self
+                .error_response(ctx, body, ErrorCode::UnsupportedVersion)
+                .map_err(|e| Error::Decode(ApiKey::ConsumerGroupHeartbeat, e));
+        }
+        Ok(Box::new(handle_request(
+            ctx,
+            &self.coordinator,
+            &*self.authorizer,
+            &self.image,
+            body,
+        )?))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = ConsumerGroupHeartbeatRequest::deserialize(body)?;
+        let result = ConsumerGroupHeartbeatResult::error(req.member_id, error_code, None);
+        Ok(Box::new(ConsumerGroupHeartbeatResponse::new(
+            ctx,
+            result,
+            &[],
+        )))
+    }
+}
+
+/// Topics are named by id on the wire; the coordinator works with names, so
+/// ids are translated here against the cluster metadata.
+pub fn handle_request(
+    ctx: &RequestContext,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    image: &MetadataImage,
+    message: &mut Bytes,
+) -> Result<ConsumerGroupHeartbeatResponse, Error> {
+    let req = ConsumerGroupHeartbeatRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::ConsumerGroupHeartbeat, e))?;
+    if !authorizer.authorize(ctx, AclOperation::Read, ResourceType::Group, &req.group_id) {
+        let result = ConsumerGroupHeartbeatResult::error(
+            req.member_id,
+            ErrorCode::GroupAuthorizationFailed,
+            None,
+        );
+        return Ok(ConsumerGroupHeartbeatResponse::new(ctx, result, &[]));
+    }
+    let record_batches = image.current();
+    let topics = metadata_topics(&record_batches);
+
+    let owned_partitions = req.topic_partitions.map(|owned| {
+        let mut assignment = Assignment::new();
+        for owned in owned {
+            if let Some(topic) = topics.iter().find(|t| t.id == owned.topic_id) {
+                assignment
+                    .entry(topic.name.clone())
+                    .or_default()
+                    .extend(owned.partitions);
+            }
+        }
+        assignment
+    });
+    let result = coordinator.consumer_group_heartbeat(ConsumerGroupHeartbeat {
+        group_id: req.group_id,
+        member_id: req.member_id,
+        member_epoch: req.member_epoch,
+        instance_id: req.instance_id,
+        client_id: ctx.header.client_id.0.clone().unwrap_or_default(),
+        client_host: ctx.client_address.ip().to_string(),
+        rebalance_timeout: (req.rebalance_timeout_ms >= 0)
+            .then(|| Duration::from_millis(req.rebalance_timeout_ms as u64)),
+        subscribed_topic_names: req.subscribed_topic_names,
+        server_assignor: req.server_assignor,
+        owned_partitions,
+        topics: topics
+            .iter()
+            .map(|topic| (topic.name.clone(), topic.partition_count))
+            .collect::<BTreeMap<_, _>>(),
+    });
+    Ok(ConsumerGroupHeartbeatResponse::new(ctx, result, &topics))
+}
@@ -0,0 +1,218 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::config::SharedConfig;
+use crate::delegation_token::{token_requests_allowed, DelegationToken, DelegationTokenManager};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::security::KafkaPrincipal;
+use crate::wire_debug::WireDump;
+
+/// CreateDelegationToken request, v2 and v3.
+pub struct CreateDelegationTokenRequest {
+    /// Whom the token is for, since v3; the requester when `None`.
+    pub owner: Option<KafkaPrincipal>,
+    pub renewers: Vec<KafkaPrincipal>,
+    /// -1 for the configured maximum.
+    pub max_lifetime_ms: i64,
+}
+
+impl CreateDelegationTokenRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let owner = if api_version >= 3 {
+            let principal_type = CompactNullableString::deserialize(src)?.0;
+            let name = CompactNullableString::deserialize(src)?.0;
+            principal_type
+                .zip(name)
+                .map(|(principal_type, name)| KafkaPrincipal {
+                    principal_type,
+                    name,
+                })
+        } else {
+            None
+        };
+        let renewers = CompactArray::deserialize_with(src, |src| {
+            let renewer = KafkaPrincipal {
+                principal_type: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                name: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(renewer)
+        })?;
+        let max_lifetime_ms = src.try_get_i64()?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            owner,
+            renewers,
+            max_lifetime_ms,
+        })
+    }
+}
+
+/// CreateDelegationToken response, v2 and v3.
+pub struct CreateDelegationTokenResponse {
+    api_version: i16,
+    header: HeaderV1,
+    error_code: ErrorCode,
+    /// `None` when the request failed.
+    token: Option<DelegationToken>,
+    throttle_time_ms: i32,
+}
+
+impl CreateDelegationTokenResponse {
+    fn new(ctx: &RequestContext, result: Result<DelegationToken, ErrorCode>) -> Self {
+        let (error_code, token) = match result {
+            Ok(token) => (ErrorCode::None, Some(token)),
+            Err(error_code) => (error_code, None),
+        };
+        Self {
+            api_version: ctx.header.api_version,
+            header: HeaderV1::new(ctx.header.correlation_id),
+            error_code,
+            token,
+            throttle_time_ms: 0,
+        }
+    }
+}
+
+impl Response for CreateDelegationTokenResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i16(self.error_code.into());
+        let empty = KafkaPrincipal {
+            principal_type: String::new(),
+            name: String::new(),
+        };
+        let token = self.token.as_ref();
+        let owner = token.map_or(&empty, |t| &t.owner);
+        bytes.put(CompactNullableString(Some(owner.principal_type.clone())).serialize());
+        bytes.put(CompactNullableString(Some(owner.name.clone())).serialize());
+        if self.api_version >= 3 {
+            let requester = token.map_or(&empty, |t| &t.requester);
+            bytes.put(CompactNullableString(Some(requester.principal_type.clone())).serialize());
+            bytes.put(CompactNullableString(Some(requester.name.clone())).serialize());
+        }
+        bytes.put_i64(token.map_or(-1, |t| t.issue_timestamp_ms));
+        bytes.put_i64(token.map_or(-1, |t| t.expiry_timestamp_ms));
+        bytes.put_i64(token.map_or(-1, |t| t.max_timestamp_ms));
+        bytes.put(
+            CompactNullableString(Some(token.map_or(String::new(), |t| t.token_id.clone())))
+                .serialize(),
+        );
+        bytes.put(
+            CompactBytes(token.map_or(Bytes::new(), |t| Bytes::from(t.hmac.clone()))).serialize(),
+        );
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+}
+
+pub struct CreateDelegationTokenHandler {
+    config: Arc<SharedConfig>,
+    authorizer: Arc<dyn Authorizer>,
+    tokens: Arc<DelegationTokenManager>,
+}
+
+impl CreateDelegationTokenHandler {
+    pub fn new(
+        config: Arc<SharedConfig>,
+        authorizer: Arc<dyn Authorizer>,
+        tokens: Arc<DelegationTokenManager>,
+    ) -> Self {
+        Self {
+            config,
+            authorizer,
+            tokens,
+        }
+    }
+}
+
+impl ApiHandler for CreateDelegationTokenHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let req = CreateDelegationTokenRequest::deserialize(body, ctx.header.api_version)
+            .map_err(|e| Error::Decode(ApiKey::CreateDelegationToken, e))?;
+        let result = if !token_requests_allowed(&self.config.get(), ctx) {
+            Err(ErrorCode::DelegationTokenRequestNotAllowed)
+        } else {
+            create(ctx, &*self.authorizer, &self.tokens, req)
+        };
+        Ok(Box::new(CreateDelegationTokenResponse::new(ctx, result)))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(CreateDelegationTokenResponse::new(
+            ctx,
+            Err(error_code),
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = CreateDelegationTokenRequest::deserialize(body, ctx.header.api_version)?;
+        dump.nullable("owner", req.owner.as_ref())
+            .list("renewers", &req.renewers, |dump, renewer| {
+                dump.field("principal", renewer);
+            })
+            .field("max_lifetime_ms", req.max_lifetime_ms);
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Creating a token for someone else takes CreateTokens on that user.
+fn create(
+    ctx: &RequestContext,
+    authorizer: &dyn Authorizer,
+    tokens: &DelegationTokenManager,
+    req: CreateDelegationTokenRequest,
+) -> Result<DelegationToken, ErrorCode> {
+    let owner = req.owner.unwrap_or_else(|| ctx.principal.clone());
+    if owner != ctx.principal
+        && !authorizer.authorize(
+            ctx,
+            AclOperation::CreateTokens,
+            ResourceType::User,
+            &owner.name,
+        )
+    {
+        return Err(ErrorCode::DelegationTokenAuthorizationFailed);
+    }
+    if !ctx.commit.commit() {
+        return Err(ErrorCode::RequestTimedOut);
+    }
+    tokens.create(
+        owner,
+        ctx.principal.clone(),
+        req.renewers,
+        req.max_lifetime_ms,
+    )
+}
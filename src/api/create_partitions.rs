@@ -0,0 +1,283 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tracing::warn;
+
+use crate::api::create_topics::create_local_partitions;
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::config::{Config, SharedConfig};
+use crate::controller::Controller;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// CreatePartitions request, v2 and v3, which share a layout.
+pub struct CreatePartitionsRequest {
+    pub topics: Vec<CreatePartitionsTopic>,
+    pub timeout_ms: i32,
+    pub validate_only: bool,
+}
+
+pub struct CreatePartitionsTopic {
+    pub name: String,
+    /// The partition count to grow the topic to.
+    pub count: i32,
+    /// The replicas of each new partition, or `None` to spread them like
+    /// the topic's first.
+    pub assignments: Option<Vec<Vec<i32>>>,
+}
+
+impl Deserialize<Self> for CreatePartitionsRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topics = CompactArray::deserialize_with(src, |src| {
+            let name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let count = src.try_get_i32()?;
+            let assignments = CompactArray::deserialize_nullable_with(src, |src| {
+                let broker_ids = CompactArray::deserialize_with(src, |src| Ok(src.try_get_i32()?))?;
+                TagBuffer::deserialize_fields(src)?;
+                Ok(broker_ids)
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(CreatePartitionsTopic {
+                name,
+                count,
+                assignments,
+            })
+        })?;
+        let timeout_ms = src.try_get_i32()?;
+        let validate_only = src.try_get_u8()? != 0;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            topics,
+            timeout_ms,
+            validate_only,
+        })
+    }
+}
+
+/// CreatePartitions response, v2 and v3.
+pub struct CreatePartitionsResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    results: CompactArray<CreatePartitionsTopicResult>,
+}
+
+#[derive(Clone)]
+pub struct CreatePartitionsTopicResult {
+    pub name: String,
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+}
+
+impl Serialize for CreatePartitionsTopicResult {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put_i16(self.error_code.into());
+        b.put(CompactNullableString(self.error_message.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl CreatePartitionsResponse {
+    fn new(ctx: &RequestContext, results: Vec<CreatePartitionsTopicResult>) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            results: CompactArray(results),
+        }
+    }
+}
+
+impl Response for CreatePartitionsResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(self.results.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        for result in &mut self.results.0 {
+            if result.error_code != ErrorCode::None {
+                result.error_message = Some(error_message.to_string());
+            }
+        }
+    }
+
+    fn topic_partitions(&self) -> Vec<String> {
+        self.results.0.iter().map(|r| r.name.clone()).collect()
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms).list(
+            "results",
+            &self.results.0,
+            |dump, r| {
+                dump.field("name", &r.name)
+                    .field("error_code", format_args!("{:?}", r.error_code))
+                    .nullable("error_message", r.error_message.as_deref());
+            },
+        );
+    }
+}
+
+/// Served by nodes that are also controllers, which record the new
+/// partitions in the metadata log and create the directories of those they
+/// host.
+pub struct CreatePartitionsHandler {
+    config: Arc<SharedConfig>,
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl CreatePartitionsHandler {
+    pub fn new(
+        config: Arc<SharedConfig>,
+        controller: Arc<Controller>,
+        authorizer: Arc<dyn Authorizer>,
+    ) -> Self {
+        Self {
+            config,
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for CreatePartitionsHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.config, &self.controller, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = CreatePartitionsRequest::deserialize(body)?;
+        let results = req
+            .topics
+            .iter()
+            .map(|topic| CreatePartitionsTopicResult {
+                name: topic.name.clone(),
+                error_code,
+                error_message: None,
+            })
+            .collect();
+        Ok(Box::new(CreatePartitionsResponse::new(ctx, results)))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = CreatePartitionsRequest::deserialize(body)?;
+        dump.list("topics", &req.topics, |dump, topic| {
+            dump.field("name", &topic.name)
+                .field("count", topic.count)
+                .field(
+                    "assignments",
+                    format_args!("{:?}", topic.assignments.as_deref()),
+                );
+        })
+        .field("timeout_ms", req.timeout_ms)
+        .field("validate_only", req.validate_only);
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes Alter on each topic. A topic named more than once in the request
+/// isn't grown. Each is grown on its own, and before the response, so
+/// `timeout_ms` isn't waited on.
+pub fn handle_request(
+    ctx: &RequestContext,
+    config: &SharedConfig,
+    controller: &Controller,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<CreatePartitionsResponse, Error> {
+    let req = CreatePartitionsRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::CreatePartitions, e))?;
+    let config = config.get();
+    let results = req
+        .topics
+        .iter()
+        .map(|topic| {
+            let duplicated = req.topics.iter().filter(|t| t.name == topic.name).count() > 1;
+            let error_code = if duplicated {
+                ErrorCode::InvalidRequest
+            } else if !authorizer.authorize(
+                ctx,
+                AclOperation::Alter,
+                ResourceType::Topic,
+                &topic.name,
+            ) {
+                ErrorCode::TopicAuthorizationFailed
+            } else if !req.validate_only && !ctx.commit.commit() {
+                ErrorCode::RequestTimedOut
+            } else {
+                let assignments = topic.assignments.as_deref().unwrap_or_default();
+                match controller.create_partitions(
+                    &topic.name,
+                    topic.count,
+                    assignments,
+                    req.validate_only,
+                ) {
+                    Ok(()) => {
+                        if !req.validate_only {
+                            grow_local_partitions(controller, &config, &topic.name);
+                        }
+                        ErrorCode::None
+                    }
+                    Err(error_code) => error_code,
+                }
+            };
+            CreatePartitionsTopicResult {
+                name: topic.name.clone(),
+                error_code,
+                error_message: None,
+            }
+        })
+        .collect();
+    Ok(CreatePartitionsResponse::new(ctx, results))
+}
+
+/// Creates the directories of the new partitions this node hosts. They are
+/// already in the metadata, so one that can't be created is only logged.
+fn grow_local_partitions(controller: &Controller, config: &Config, name: &str) {
+    let topic_id = match controller.metadata() {
+        Ok(metadata) => metadata.topic_by_name(name).map(|t| t.topic_id.clone()),
+        Err(_) => None,
+    };
+    let Some(topic_id) = topic_id else {
+        return;
+    };
+    if let Err(e) = create_local_partitions(
+        controller,
+        &config.log_dirs,
+        config.node_id,
+        &topic_id,
+        name,
+    ) {
+        warn!(topic = name, error = %format!("{:#}", e), "failed to create partition directories");
+    }
+}
@@ -0,0 +1,587 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tracing::warn;
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::config::{Config, SharedConfig};
+use crate::controller::{Controller, NewTopic};
+use crate::error::Error;
+use crate::log_manager::{create_partition_dir, partition_log_dir};
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+const SEGMENT_FILE: &str = "00000000000000000000.log";
+const ZERO_UUID: &str = "00000000-0000-0000-0000-000000000000";
+/// The `config_source` of a config set on the topic itself.
+const TOPIC_CONFIG_SOURCE: i8 = 1;
+
+/// CreateTopics request, v5 to v7, which share a layout.
+pub struct CreateTopicsRequest {
+    pub topics: Vec<CreatableTopic>,
+    pub timeout_ms: i32,
+    pub validate_only: bool,
+}
+
+#[derive(Clone)]
+pub struct CreatableTopic {
+    pub name: String,
+    /// -1 to take the broker's default, or when `assignments` are given.
+    pub num_partitions: i32,
+    /// -1 to take the broker's default, or when `assignments` are given.
+    pub replication_factor: i16,
+    pub assignments: Vec<CreatableReplicaAssignment>,
+    pub configs: Vec<CreatableTopicConfig>,
+}
+
+#[derive(Clone)]
+pub struct CreatableReplicaAssignment {
+    pub partition_index: i32,
+    pub broker_ids: Vec<i32>,
+}
+
+#[derive(Clone)]
+pub struct CreatableTopicConfig {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Deserialize<Self> for CreateTopicsRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topics = CompactArray::deserialize_with(src, |src| {
+            let name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let num_partitions = src.try_get_i32()?;
+            let replication_factor = src.try_get_i16()?;
+            let assignments = CompactArray::deserialize_with(src, |src| {
+                let partition_index = src.try_get_i32()?;
+                let broker_ids = CompactArray::deserialize_with(src, |src| Ok(src.try_get_i32()?))?;
+                TagBuffer::deserialize_fields(src)?;
+                Ok(CreatableReplicaAssignment {
+                    partition_index,
+                    broker_ids,
+                })
+            })?;
+            let configs = CompactArray::deserialize_with(src, |src| {
+                let name = CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default();
+                let value = CompactNullableString::deserialize(src)?.0;
+                TagBuffer::deserialize_fields(src)?;
+                Ok(CreatableTopicConfig { name, value })
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(CreatableTopic {
+                name,
+                num_partitions,
+                replication_factor,
+                assignments,
+                configs,
+            })
+        })?;
+        let timeout_ms = src.try_get_i32()?;
+        let validate_only = src.try_get_u8()? != 0;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            topics,
+            timeout_ms,
+            validate_only,
+        })
+    }
+}
+
+impl Serialize for CreateTopicsRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactArray(self.topics.clone()).serialize());
+        b.put_i32(self.timeout_ms);
+        b.put_u8(self.validate_only.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for CreatableTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put_i32(self.num_partitions);
+        b.put_i16(self.replication_factor);
+        b.put(CompactArray(self.assignments.clone()).serialize());
+        b.put(CompactArray(self.configs.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for CreatableReplicaAssignment {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put(CompactArray(self.broker_ids.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for CreatableTopicConfig {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(CompactNullableString(self.value.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// CreateTopics response, v5 to v7. The topic id, added in v7, is left
+/// out of older versions.
+pub struct CreateTopicsResponse {
+    header: HeaderV1,
+    api_version: i16,
+    pub throttle_time_ms: i32,
+    pub topics: Vec<CreatableTopicResult>,
+}
+
+#[derive(Clone)]
+pub struct CreatableTopicResult {
+    pub name: String,
+    pub topic_id: Uuid,
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub num_partitions: i32,
+    pub replication_factor: i16,
+    /// The topic's configs as created, or `None` if they can't be shown.
+    pub configs: Option<Vec<CreatableTopicConfigs>>,
+}
+
+#[derive(Clone)]
+pub struct CreatableTopicConfigs {
+    pub name: String,
+    pub value: Option<String>,
+    pub read_only: bool,
+    pub config_source: i8,
+    pub is_sensitive: bool,
+}
+
+/// Reads a whole response, header included, as a client gets it.
+impl Deserialize<Self> for CreateTopicsResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let topics = CompactArray::deserialize_with(src, |src| {
+            let name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let topic_id = Uuid::deserialize(src)?;
+            let error_code = ErrorCode::from(src.try_get_i16()?);
+            let error_message = CompactNullableString::deserialize(src)?.0;
+            let num_partitions = src.try_get_i32()?;
+            let replication_factor = src.try_get_i16()?;
+            let configs = CompactArray::deserialize_nullable_with(src, |src| {
+                let config = CreatableTopicConfigs {
+                    name: CompactNullableString::deserialize(src)?
+                        .0
+                        .unwrap_or_default(),
+                    value: CompactNullableString::deserialize(src)?.0,
+                    read_only: src.try_get_u8()? != 0,
+                    config_source: src.try_get_i8()?,
+                    is_sensitive: src.try_get_u8()? != 0,
+                };
+                TagBuffer::deserialize_fields(src)?;
+                Ok(config)
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(CreatableTopicResult {
+                name,
+                topic_id,
+                error_code,
+                error_message,
+                num_partitions,
+                replication_factor,
+                configs,
+            })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            header,
+            api_version: 7,
+            throttle_time_ms,
+            topics,
+        })
+    }
+}
+
+impl CreateTopicsResponse {
+    pub fn new(ctx: &RequestContext, topics: Vec<CreatableTopicResult>) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            api_version: ctx.header.api_version,
+            throttle_time_ms: 0,
+            topics,
+        }
+    }
+}
+
+impl CreatableTopicResult {
+    fn new(name: &str, error_code: ErrorCode) -> Self {
+        Self {
+            name: name.to_string(),
+            topic_id: Uuid(ZERO_UUID.to_string()),
+            error_code,
+            error_message: None,
+            num_partitions: -1,
+            replication_factor: -1,
+            configs: None,
+        }
+    }
+
+    fn serialize(&self, api_version: i16) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        if api_version >= 7 {
+            b.put(self.topic_id.serialize());
+        }
+        b.put_i16(self.error_code.into());
+        b.put(CompactNullableString(self.error_message.clone()).serialize());
+        b.put_i32(self.num_partitions);
+        b.put_i16(self.replication_factor);
+        match &self.configs {
+            Some(configs) => b.put(CompactArray(configs.clone()).serialize()),
+            None => b.put_u8(0),
+        }
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for CreatableTopicConfigs {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(CompactNullableString(self.value.clone()).serialize());
+        b.put_u8(self.read_only.into());
+        b.put_i8(self.config_source);
+        b.put_u8(self.is_sensitive.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Response for CreateTopicsResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_u8(self.topics.len() as u8 + 1);
+        for topic in &self.topics {
+            bytes.put(topic.serialize(self.api_version));
+        }
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        for topic in &mut self.topics {
+            if topic.error_code != ErrorCode::None {
+                topic.error_message = Some(error_message.to_string());
+            }
+        }
+    }
+
+    fn topic_partitions(&self) -> Vec<String> {
+        self.topics.iter().map(|t| t.name.clone()).collect()
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms).list(
+            "topics",
+            &self.topics,
+            |dump, topic| {
+                dump.field("name", &topic.name)
+                    .field("topic_id", &topic.topic_id)
+                    .field("error_code", format_args!("{:?}", topic.error_code))
+                    .nullable("error_message", topic.error_message.as_deref())
+                    .field("num_partitions", topic.num_partitions)
+                    .field("replication_factor", topic.replication_factor);
+            },
+        );
+    }
+}
+
+/// Served by nodes that are also controllers, which record the new topics
+/// in the metadata log and create the directories of the partitions they
+/// host.
+pub struct CreateTopicsHandler {
+    config: Arc<SharedConfig>,
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl CreateTopicsHandler {
+    pub fn new(
+        config: Arc<SharedConfig>,
+        controller: Arc<Controller>,
+        authorizer: Arc<dyn Authorizer>,
+    ) -> Self {
+        Self {
+            config,
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for CreateTopicsHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.config, &self.controller, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = CreateTopicsRequest::deserialize(body)?;
+        let topics = req
+            .topics
+            .iter()
+            .map(|t| CreatableTopicResult::new(&t.name, error_code))
+            .collect();
+        Ok(Box::new(CreateTopicsResponse::new(ctx, topics)))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = CreateTopicsRequest::deserialize(body)?;
+        dump.list("topics", &req.topics, |dump, topic| {
+            dump.field("name", &topic.name)
+                .field("num_partitions", topic.num_partitions)
+                .field("replication_factor", topic.replication_factor)
+                .field("assignments", topic.assignments.len())
+                .field("configs", topic.configs.len());
+        })
+        .field("timeout_ms", req.timeout_ms)
+        .field("validate_only", req.validate_only);
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes Create on the cluster or on each topic. A topic named more than
+/// once in the request isn't created. Each is created on its own, and
+/// before the response, so `timeout_ms` isn't waited on.
+pub fn handle_request(
+    ctx: &RequestContext,
+    config: &SharedConfig,
+    controller: &Controller,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<CreateTopicsResponse, Error> {
+    let req = CreateTopicsRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::CreateTopics, e))?;
+    let config = config.get();
+    let may_create_any = authorizer.authorize(
+        ctx,
+        AclOperation::Create,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    );
+    let mut seen = HashSet::new();
+    let duplicates: HashSet<&str> = req
+        .topics
+        .iter()
+        .filter(|t| !seen.insert(t.name.as_str()))
+        .map(|t| t.name.as_str())
+        .collect();
+    let topics = req
+        .topics
+        .iter()
+        .map(|topic| {
+            if duplicates.contains(topic.name.as_str()) {
+                return CreatableTopicResult::new(&topic.name, ErrorCode::InvalidRequest);
+            }
+            if !may_create_any
+                && !authorizer.authorize(
+                    ctx,
+                    AclOperation::Create,
+                    ResourceType::Topic,
+                    &topic.name,
+                )
+            {
+                return CreatableTopicResult::new(&topic.name, ErrorCode::TopicAuthorizationFailed);
+            }
+            // Validating changes nothing, so it's fine past the timeout.
+            if !req.validate_only && !ctx.commit.commit() {
+                return CreatableTopicResult::new(&topic.name, ErrorCode::RequestTimedOut);
+            }
+            create_topic(&config, controller, topic, req.validate_only)
+                .unwrap_or_else(|error_code| CreatableTopicResult::new(&topic.name, error_code))
+        })
+        .collect();
+    Ok(CreateTopicsResponse::new(ctx, topics))
+}
+
+/// Creates one topic the principal may create, along with this node's
+/// replicas of it.
+fn create_topic(
+    config: &Config,
+    controller: &Controller,
+    topic: &CreatableTopic,
+    validate_only: bool,
+) -> Result<CreatableTopicResult, ErrorCode> {
+    let new_topic = new_topic(
+        topic,
+        config.num_partitions,
+        config.default_replication_factor,
+    )?;
+    let topic_id = controller.create_topic(&new_topic, validate_only)?;
+    if !validate_only {
+        let created = create_local_partitions(
+            controller,
+            &config.log_dirs,
+            config.node_id,
+            &topic_id,
+            &topic.name,
+        );
+        if let Err(e) = created {
+            warn!(
+                topic = %topic.name,
+                error = %format!("{:#}", e),
+                "failed to create partition directories"
+            );
+        }
+    }
+    Ok(created_topic(new_topic, topic_id, &topic.name))
+}
+
+/// The answer for a topic created as `new_topic`.
+fn created_topic(new_topic: NewTopic, topic_id: Uuid, name: &str) -> CreatableTopicResult {
+    let (num_partitions, replication_factor) = match new_topic.assignments.first() {
+        Some(replicas) => (new_topic.assignments.len() as i32, replicas.len() as i16),
+        None => (new_topic.num_partitions, new_topic.replication_factor),
+    };
+    let configs = new_topic
+        .configs
+        .into_iter()
+        .map(|(name, value)| CreatableTopicConfigs {
+            name,
+            value: Some(value),
+            read_only: false,
+            config_source: TOPIC_CONFIG_SOURCE,
+            is_sensitive: false,
+        })
+        .collect();
+    CreatableTopicResult {
+        topic_id,
+        num_partitions,
+        replication_factor,
+        configs: Some(configs),
+        ..CreatableTopicResult::new(name, ErrorCode::None)
+    }
+}
+
+/// The topic `topic` asks for, with -1 counts taken from the broker's
+/// defaults. Assignments replace both counts, and have to number the
+/// partitions from 0 with none skipped.
+fn new_topic(
+    topic: &CreatableTopic,
+    default_partitions: i32,
+    default_replication_factor: i16,
+) -> Result<NewTopic, ErrorCode> {
+    let mut assignments: Vec<&CreatableReplicaAssignment> = topic.assignments.iter().collect();
+    if !assignments.is_empty() && (topic.num_partitions != -1 || topic.replication_factor != -1) {
+        return Err(ErrorCode::InvalidRequest);
+    }
+    assignments.sort_by_key(|a| a.partition_index);
+    if assignments
+        .iter()
+        .enumerate()
+        .any(|(i, a)| a.partition_index != i as i32)
+    {
+        return Err(ErrorCode::InvalidReplicaAssignment);
+    }
+    let num_partitions = match topic.num_partitions {
+        -1 => default_partitions,
+        n if n > 0 || !assignments.is_empty() => n,
+        _ => return Err(ErrorCode::InvalidPartitions),
+    };
+    let replication_factor = match topic.replication_factor {
+        -1 => default_replication_factor,
+        n if n > 0 || !assignments.is_empty() => n,
+        _ => return Err(ErrorCode::InvalidReplicationFactor),
+    };
+    let mut configs = BTreeMap::new();
+    for config in &topic.configs {
+        let Some(value) = &config.value else {
+            return Err(ErrorCode::InvalidConfig);
+        };
+        if configs.insert(config.name.clone(), value.clone()).is_some() {
+            return Err(ErrorCode::InvalidRequest);
+        }
+    }
+    Ok(NewTopic {
+        name: topic.name.clone(),
+        num_partitions,
+        replication_factor,
+        assignments: assignments.iter().map(|a| a.broker_ids.clone()).collect(),
+        configs,
+    })
+}
+
+/// Creates the directory and empty first segment of each partition of the
+/// topic this node is a replica of that doesn't have them yet, so that Fetch
+/// finds a log to read. Replicas on other brokers are created when their
+/// replica fetchers start.
+pub(crate) fn create_local_partitions(
+    controller: &Controller,
+    log_dirs: &[PathBuf],
+    node_id: i32,
+    topic_id: &Uuid,
+    topic_name: &str,
+) -> Result<()> {
+    let metadata = controller
+        .metadata()
+        .map_err(|error_code| anyhow!("read metadata: {:?}", error_code))?;
+    for partition in metadata.partitions(topic_id) {
+        if !partition.replicas.contains(&(node_id as u32)) {
+            continue;
+        }
+        let name = format!("{}-{}", topic_name, partition.partition_id);
+        let dir = partition_log_dir(log_dirs, &name).join(&name);
+        create_segment(&dir, topic_id)?;
+    }
+    Ok(())
+}
+
+fn create_segment(dir: &Path, topic_id: &Uuid) -> Result<()> {
+    create_partition_dir(dir, &topic_id.0)?;
+    let path = dir.join(SEGMENT_FILE);
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("create '{}'", path.display()))?;
+    Ok(())
+}
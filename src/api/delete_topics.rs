@@ -0,0 +1,369 @@
+use std::{collections::HashSet, sync::Arc};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tracing::warn;
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::controller::Controller;
+use crate::coordinator::GroupCoordinator;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+const ZERO_UUID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// DeleteTopics request, v4 to v6. v6 names each topic by name or by id,
+/// earlier versions by name alone.
+pub struct DeleteTopicsRequest {
+    pub topics: Vec<DeleteTopicState>,
+    pub timeout_ms: i32,
+}
+
+#[derive(Clone)]
+pub struct DeleteTopicState {
+    /// `None` when the topic is named by id.
+    pub name: Option<String>,
+    /// All zeros when the topic is named by name.
+    pub topic_id: Uuid,
+}
+
+impl DeleteTopicState {
+    /// A topic named by name.
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            topic_id: Uuid(ZERO_UUID.to_string()),
+        }
+    }
+}
+
+impl DeleteTopicsRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let topics = if api_version >= 6 {
+            CompactArray::deserialize_with(src, |src| {
+                let name = CompactNullableString::deserialize(src)?.0;
+                let topic_id = Uuid::deserialize(src)?;
+                TagBuffer::deserialize_fields(src)?;
+                Ok(DeleteTopicState { name, topic_id })
+            })?
+        } else {
+            CompactArray::deserialize_with(src, |src| {
+                let name = CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default();
+                Ok(DeleteTopicState::named(&name))
+            })?
+        };
+        let timeout_ms = src.try_get_i32()?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self { topics, timeout_ms })
+    }
+}
+
+impl Serialize for DeleteTopicsRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactArray(self.topics.clone()).serialize());
+        b.put_i32(self.timeout_ms);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for DeleteTopicState {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(self.name.clone()).serialize());
+        b.put(self.topic_id.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// DeleteTopics response, v4 to v6.
+pub struct DeleteTopicsResponse {
+    header: HeaderV1,
+    api_version: i16,
+    pub throttle_time_ms: i32,
+    pub responses: Vec<DeletableTopicResult>,
+}
+
+pub struct DeletableTopicResult {
+    pub name: Option<String>,
+    pub topic_id: Uuid,
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+}
+
+/// Reads a whole response, header included, as a client gets it.
+impl Deserialize<Self> for DeleteTopicsResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let responses = CompactArray::deserialize_with(src, |src| {
+            let result = DeletableTopicResult {
+                name: CompactNullableString::deserialize(src)?.0,
+                topic_id: Uuid::deserialize(src)?,
+                error_code: ErrorCode::from(src.try_get_i16()?),
+                error_message: CompactNullableString::deserialize(src)?.0,
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(result)
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            header,
+            api_version: 6,
+            throttle_time_ms,
+            responses,
+        })
+    }
+}
+
+impl DeleteTopicsResponse {
+    pub fn new(ctx: &RequestContext, responses: Vec<DeletableTopicResult>) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            api_version: ctx.header.api_version,
+            throttle_time_ms: 0,
+            responses,
+        }
+    }
+}
+
+impl DeletableTopicResult {
+    fn new(topic: &DeleteTopicState, error_code: ErrorCode) -> Self {
+        Self {
+            name: topic.name.clone(),
+            topic_id: topic.topic_id.clone(),
+            error_code,
+            error_message: None,
+        }
+    }
+
+    fn serialize(&self, api_version: i16) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(self.name.clone()).serialize());
+        if api_version >= 6 {
+            b.put(self.topic_id.serialize());
+        }
+        b.put_i16(self.error_code.into());
+        if api_version >= 5 {
+            b.put(CompactNullableString(self.error_message.clone()).serialize());
+        }
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Response for DeleteTopicsResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_u8(self.responses.len() as u8 + 1);
+        for response in &self.responses {
+            bytes.put(response.serialize(self.api_version));
+        }
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        for response in &mut self.responses {
+            if response.error_code != ErrorCode::None {
+                response.error_message = Some(error_message.to_string());
+            }
+        }
+    }
+
+    fn topic_partitions(&self) -> Vec<String> {
+        self.responses
+            .iter()
+            .map(|r| r.name.clone().unwrap_or_else(|| r.topic_id.to_string()))
+            .collect()
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms).list(
+            "responses",
+            &self.responses,
+            |dump, r| {
+                dump.nullable("name", r.name.as_deref())
+                    .field("topic_id", &r.topic_id)
+                    .field("error_code", format_args!("{:?}", r.error_code))
+                    .nullable("error_message", r.error_message.as_deref());
+            },
+        );
+    }
+}
+
+/// Served by nodes that are also controllers, which remove the topics from
+/// the metadata log. Each broker deletes its own replicas once it sees the
+/// RemoveTopicRecord.
+pub struct DeleteTopicsHandler {
+    controller: Arc<Controller>,
+    coordinator: GroupCoordinator,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl DeleteTopicsHandler {
+    pub fn new(
+        controller: Arc<Controller>,
+        coordinator: GroupCoordinator,
+        authorizer: Arc<dyn Authorizer>,
+    ) -> Self {
+        Self {
+            controller,
+            coordinator,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for DeleteTopicsHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(
+            ctx,
+            &self.controller,
+            &self.coordinator,
+            &*self.authorizer,
+            body,
+        )?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = DeleteTopicsRequest::deserialize(body, ctx.header.api_version)?;
+        let responses = req
+            .topics
+            .iter()
+            .map(|t| DeletableTopicResult::new(t, error_code))
+            .collect();
+        Ok(Box::new(DeleteTopicsResponse::new(ctx, responses)))
+    }
+
+    fn describe_request(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = DeleteTopicsRequest::deserialize(body, ctx.header.api_version)?;
+        dump.list("topics", &req.topics, |dump, topic| {
+            dump.nullable("name", topic.name.as_deref())
+                .field("topic_id", &topic.topic_id);
+        })
+        .field("timeout_ms", req.timeout_ms);
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes Delete on each topic. A topic is named by name or by id, not both,
+/// and one named more than once isn't deleted. Each is deleted on its own,
+/// and before the response, so `timeout_ms` isn't waited on. Replicas are
+/// removed from disk by each broker as it reads the RemoveTopicRecord, which
+/// may be after the response. Groups' committed offsets for the
+/// deleted topics are removed as well: left behind, they would point a
+/// group past the end of a topic created again under the same name, and it
+/// would make no progress there.
+pub fn handle_request(
+    ctx: &RequestContext,
+    controller: &Controller,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<DeleteTopicsResponse, Error> {
+    let req = DeleteTopicsRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::DeleteTopics, e))?;
+    let metadata = match controller.metadata() {
+        Ok(metadata) => metadata,
+        Err(error_code) => {
+            let responses = req
+                .topics
+                .iter()
+                .map(|t| DeletableTopicResult::new(t, error_code))
+                .collect();
+            return Ok(DeleteTopicsResponse::new(ctx, responses));
+        }
+    };
+    let mut seen = HashSet::new();
+    let duplicates: HashSet<(Option<&str>, &str)> = req
+        .topics
+        .iter()
+        .map(|t| (t.name.as_deref(), t.topic_id.0.as_str()))
+        .filter(|key| !seen.insert(*key))
+        .collect();
+    let responses: Vec<_> = req
+        .topics
+        .iter()
+        .map(|topic| {
+            let by_id = topic.topic_id.0 != ZERO_UUID;
+            if duplicates.contains(&(topic.name.as_deref(), topic.topic_id.0.as_str())) {
+                return DeletableTopicResult::new(topic, ErrorCode::InvalidRequest);
+            }
+            let found = match (&topic.name, by_id) {
+                (Some(name), false) => metadata
+                    .topic_by_name(name)
+                    .ok_or(ErrorCode::UnknownTopicOrPartition),
+                (None, true) => metadata
+                    .topic(&topic.topic_id)
+                    .ok_or(ErrorCode::UnknownTopicId),
+                _ => Err(ErrorCode::InvalidRequest),
+            };
+            let found = match found {
+                Ok(found) => found,
+                Err(error_code) => return DeletableTopicResult::new(topic, error_code),
+            };
+            let name = found.topic_name.0.clone().unwrap_or_default();
+            let topic_id = found.topic_id.clone();
+            let answer = DeletableTopicResult {
+                name: Some(name.clone()),
+                topic_id: topic_id.clone(),
+                ..DeletableTopicResult::new(topic, ErrorCode::None)
+            };
+            if !authorizer.authorize(ctx, AclOperation::Delete, ResourceType::Topic, &name) {
+                // Named by id, the topic's name isn't given away.
+                return DeletableTopicResult::new(topic, ErrorCode::TopicAuthorizationFailed);
+            }
+            if !ctx.commit.commit() {
+                return DeletableTopicResult {
+                    error_code: ErrorCode::RequestTimedOut,
+                    ..answer
+                };
+            }
+            if let Err(error_code) = controller.delete_topic(&topic_id) {
+                return DeletableTopicResult {
+                    error_code,
+                    ..answer
+                };
+            }
+            answer
+        })
+        .collect();
+    let deleted: Vec<String> = responses
+        .iter()
+        .filter(|r| r.error_code == ErrorCode::None)
+        .filter_map(|r| r.name.clone())
+        .collect();
+    if !deleted.is_empty() {
+        if let Err(e) = coordinator.remove_topics(deleted) {
+            warn!(error = %e, "failed to remove committed offsets of deleted topics");
+        }
+    }
+    Ok(DeleteTopicsResponse::new(ctx, responses))
+}
@@ -0,0 +1,263 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::cluster_metadata::{QuotaComponent, QuotaComponents};
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::error::Error;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::quota::{CLIENT_ID_QUOTA_ENTITY, USER_QUOTA_ENTITY};
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// Matches an entity type's component by name.
+const MATCH_EXACT: i8 = 0;
+/// Matches an entity type's default.
+const MATCH_DEFAULT: i8 = 1;
+/// Matches any component of an entity type.
+const MATCH_ANY: i8 = 2;
+
+/// DescribeClientQuotas request, v1.
+pub struct DescribeClientQuotasRequest {
+    pub components: Vec<ComponentFilter>,
+    /// Whether entities with components of types not filtered on are left
+    /// out.
+    pub strict: bool,
+}
+
+pub struct ComponentFilter {
+    pub entity_type: String,
+    pub match_type: i8,
+    pub name: Option<String>,
+}
+
+impl Deserialize<Self> for DescribeClientQuotasRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let components = CompactArray::deserialize_with(src, |src| {
+            let component = ComponentFilter {
+                entity_type: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                match_type: src.try_get_i8()?,
+                name: CompactNullableString::deserialize(src)?.0,
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(component)
+        })?;
+        let strict = src.try_get_u8()? != 0;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self { components, strict })
+    }
+}
+
+impl ComponentFilter {
+    fn matches(&self, entity: &QuotaComponents) -> bool {
+        entity.iter().any(|(entity_type, name)| {
+            *entity_type == self.entity_type
+                && match self.match_type {
+                    MATCH_EXACT => *name == self.name,
+                    MATCH_DEFAULT => name.is_none(),
+                    _ => true,
+                }
+        })
+    }
+
+    fn is_valid(&self) -> bool {
+        [USER_QUOTA_ENTITY, CLIENT_ID_QUOTA_ENTITY].contains(&self.entity_type.as_str())
+            && match self.match_type {
+                MATCH_EXACT => self.name.is_some(),
+                MATCH_DEFAULT | MATCH_ANY => self.name.is_none(),
+                _ => false,
+            }
+    }
+}
+
+/// DescribeClientQuotas response, v1.
+pub struct DescribeClientQuotasResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+    error_message: Option<String>,
+    /// `None` when the request failed.
+    entries: Option<CompactArray<QuotaEntry>>,
+}
+
+pub struct QuotaEntry {
+    pub entity: QuotaComponents,
+    pub values: Vec<(String, f64)>,
+}
+
+impl Serialize for QuotaEntry {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactArray(self.entity.iter().map(QuotaComponent).collect()).serialize());
+        b.put(CompactArray(self.values.iter().map(QuotaValue).collect()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+struct QuotaValue<'a>(&'a (String, f64));
+
+impl Serialize for QuotaValue<'_> {
+    fn serialize(&self) -> Bytes {
+        let (key, value) = self.0;
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(key.clone())).serialize());
+        b.put_f64(*value);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl DescribeClientQuotasResponse {
+    fn new(ctx: &RequestContext, error_code: ErrorCode, entries: Option<Vec<QuotaEntry>>) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            error_message: None,
+            entries: entries.map(CompactArray),
+        }
+    }
+}
+
+impl Response for DescribeClientQuotasResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(CompactNullableString(self.error_message.clone()).serialize());
+        match &self.entries {
+            Some(entries) => bytes.put(entries.serialize()),
+            None => bytes.put_u8(0),
+        }
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        self.error_message = Some(error_message.to_string());
+    }
+}
+
+/// Served by every broker, from its copy of the metadata log.
+pub struct DescribeClientQuotasHandler {
+    authorizer: Arc<dyn Authorizer>,
+    image: Arc<MetadataImage>,
+}
+
+impl DescribeClientQuotasHandler {
+    pub fn new(authorizer: Arc<dyn Authorizer>, image: Arc<MetadataImage>) -> Self {
+        Self { authorizer, image }
+    }
+}
+
+impl ApiHandler for DescribeClientQuotasHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        Ok(Box::new(handle_request(
+            ctx,
+            &*self.authorizer,
+            &self.image,
+            body,
+        )?))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(DescribeClientQuotasResponse::new(
+            ctx, error_code, None,
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = DescribeClientQuotasRequest::deserialize(body)?;
+        dump.list("components", &req.components, |dump, component| {
+            dump.field("entity_type", &component.entity_type)
+                .field("match_type", component.match_type)
+                .nullable("match", component.name.as_ref());
+        })
+        .field("strict", req.strict);
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes DescribeConfigs on the cluster. An entity is described if every
+/// filter matches one of its components.
+pub fn handle_request(
+    ctx: &RequestContext,
+    authorizer: &dyn Authorizer,
+    image: &MetadataImage,
+    message: &mut Bytes,
+) -> Result<DescribeClientQuotasResponse, Error> {
+    let req = DescribeClientQuotasRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::DescribeClientQuotas, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::DescribeConfigs,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(DescribeClientQuotasResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            None,
+        ));
+    }
+    let mut entity_types: Vec<&str> = req
+        .components
+        .iter()
+        .map(|c| c.entity_type.as_str())
+        .collect();
+    entity_types.sort();
+    entity_types.dedup();
+    if entity_types.len() != req.components.len() || !req.components.iter().all(|c| c.is_valid()) {
+        return Ok(DescribeClientQuotasResponse::new(
+            ctx,
+            ErrorCode::InvalidRequest,
+            None,
+        ));
+    }
+
+    let entries = image
+        .current()
+        .client_quotas()
+        .into_iter()
+        .filter(|(entity, _)| {
+            req.components.iter().all(|c| c.matches(entity))
+                && (!req.strict || entity.len() == req.components.len())
+        })
+        .map(|(entity, values)| QuotaEntry {
+            entity,
+            values: values.into_iter().collect(),
+        })
+        .collect();
+    Ok(DescribeClientQuotasResponse::new(
+        ctx,
+        ErrorCode::None,
+        Some(entries),
+    ))
+}
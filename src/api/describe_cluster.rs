@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::metadata::MetadataBroker;
+use crate::api::ApiHandler;
+use crate::config::{Config, SharedConfig};
+use crate::error::Error;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+/// `endpoint_type` 1: the brokers' client listeners.
+pub const ENDPOINT_TYPE_BROKERS: i8 = 1;
+
+pub struct DescribeClusterRequest {
+    pub include_cluster_authorized_operations: bool,
+    pub endpoint_type: i8,
+}
+
+impl DescribeClusterRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let include_cluster_authorized_operations = src.try_get_u8()? != 0;
+        let endpoint_type = if api_version >= 1 {
+            src.try_get_i8()?
+        } else {
+            ENDPOINT_TYPE_BROKERS
+        };
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            include_cluster_authorized_operations,
+            endpoint_type,
+        })
+    }
+}
+
+/// Written as v1.
+impl Serialize for DescribeClusterRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_u8(self.include_cluster_authorized_operations.into());
+        b.put_i8(self.endpoint_type);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// DescribeCluster response, v0 and v1.
+pub struct DescribeClusterResponse {
+    api_version: i16,
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub error_message: CompactNullableString,
+    pub endpoint_type: i8,
+    pub cluster_id: CompactNullableString,
+    pub controller_id: i32,
+    pub brokers: CompactArray<MetadataBroker>,
+    pub cluster_authorized_operations: i32,
+}
+
+/// Reads a whole v1 response, header included, as a client gets it.
+impl Deserialize<Self> for DescribeClusterResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let error_code = ErrorCode::from(src.try_get_i16()?);
+        let error_message = CompactNullableString::deserialize(src)?;
+        let endpoint_type = src.try_get_i8()?;
+        let cluster_id = CompactNullableString::deserialize(src)?;
+        let controller_id = src.try_get_i32()?;
+        let brokers = CompactArray::deserialize_with(src, |src| {
+            let broker = MetadataBroker {
+                node_id: src.try_get_i32()?,
+                host: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                port: src.try_get_i32()?,
+                rack: CompactNullableString::deserialize(src)?,
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(broker)
+        })?;
+        let cluster_authorized_operations = src.try_get_i32()?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            api_version: 1,
+            header,
+            throttle_time_ms,
+            error_code,
+            error_message,
+            endpoint_type,
+            cluster_id,
+            controller_id,
+            brokers: CompactArray(brokers),
+            cluster_authorized_operations,
+        })
+    }
+}
+
+impl Response for DescribeClusterResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(self.error_message.serialize());
+        if self.api_version >= 1 {
+            bytes.put_i8(self.endpoint_type);
+        }
+        bytes.put(self.cluster_id.serialize());
+        bytes.put_i32(self.controller_id);
+        bytes.put(self.brokers.serialize());
+        bytes.put_i32(self.cluster_authorized_operations);
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        self.error_message = CompactNullableString(Some(error_message.to_string()));
+    }
+}
+
+impl DescribeClusterResponse {
+    pub fn error(config: &Config, ctx: &RequestContext, error_code: ErrorCode) -> Self {
+        Self {
+            api_version: ctx.header.api_version,
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            error_message: CompactNullableString(None),
+            endpoint_type: ENDPOINT_TYPE_BROKERS,
+            cluster_id: CompactNullableString(Some(String::new())),
+            controller_id: config.node_id,
+            brokers: CompactArray(Vec::new()),
+            cluster_authorized_operations: i32::MIN,
+        }
+    }
+}
+
+pub struct DescribeClusterHandler {
+    config: Arc<SharedConfig>,
+    cluster_id: String,
+    image: Arc<MetadataImage>,
+}
+
+impl DescribeClusterHandler {
+    pub fn new(config: Arc<SharedConfig>, cluster_id: String, image: Arc<MetadataImage>) -> Self {
+        Self {
+            config,
+            cluster_id,
+            image,
+        }
+    }
+}
+
+impl ApiHandler for DescribeClusterHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(&self.config.get(), &self.cluster_id, &self.image, ctx, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(DescribeClusterResponse::error(
+            &self.config.get(),
+            ctx,
+            error_code,
+        )))
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+pub fn handle_request(
+    config: &Config,
+    cluster_id: &str,
+    image: &MetadataImage,
+    ctx: &RequestContext,
+    message: &mut Bytes,
+) -> Result<DescribeClusterResponse, Error> {
+    let req = DescribeClusterRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::DescribeCluster, e))?;
+    if req.endpoint_type != ENDPOINT_TYPE_BROKERS {
+        let mut res =
+            DescribeClusterResponse::error(config, ctx, ErrorCode::UnsupportedEndpointType);
+        res.error_message =
+            CompactNullableString(Some("Only brokers can be described".to_string()));
+        res.endpoint_type = req.endpoint_type;
+        return Ok(res);
+    }
+
+    let metadata = image.current();
+    let mut res = DescribeClusterResponse::error(config, ctx, ErrorCode::None);
+    res.cluster_id = CompactNullableString(Some(cluster_id.to_string()));
+    res.brokers = CompactArray(MetadataBroker::all(config, ctx, &metadata));
+    if req.include_cluster_authorized_operations {
+        res.cluster_authorized_operations = 0;
+    }
+    Ok(res)
+}
@@ -0,0 +1,139 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::protocol::*;
+
+/// DescribeConfigs request, v4.
+pub struct DescribeConfigsRequest {
+    pub resources: Vec<DescribeConfigsResource>,
+    pub include_synonyms: bool,
+    pub include_documentation: bool,
+}
+
+#[derive(Clone)]
+pub struct DescribeConfigsResource {
+    pub resource_type: i8,
+    pub resource_name: String,
+    /// `None` asks for every config.
+    pub configuration_keys: Option<Vec<String>>,
+}
+
+impl Serialize for DescribeConfigsRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactArray(self.resources.clone()).serialize());
+        b.put_u8(self.include_synonyms.into());
+        b.put_u8(self.include_documentation.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for DescribeConfigsResource {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i8(self.resource_type);
+        b.put(CompactNullableString(Some(self.resource_name.clone())).serialize());
+        match &self.configuration_keys {
+            Some(keys) => b.put(
+                CompactArray(
+                    keys.iter()
+                        .map(|key| CompactNullableString(Some(key.clone())))
+                        .collect(),
+                )
+                .serialize(),
+            ),
+            None => b.put_u8(0),
+        }
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// DescribeConfigs response, v4.
+pub struct DescribeConfigsResponse {
+    pub throttle_time_ms: i32,
+    pub results: Vec<DescribeConfigsResult>,
+}
+
+pub struct DescribeConfigsResult {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub configs: Vec<DescribeConfigsResourceResult>,
+}
+
+pub struct DescribeConfigsResourceResult {
+    pub name: String,
+    pub value: Option<String>,
+    pub read_only: bool,
+    pub config_source: i8,
+    pub is_sensitive: bool,
+    /// Each other source of the config, highest precedence first, as
+    /// name, value and source.
+    pub synonyms: Vec<(String, Option<String>, i8)>,
+    pub config_type: i8,
+    pub documentation: Option<String>,
+}
+
+/// Reads a whole response, header included, as a client gets it.
+impl Deserialize<Self> for DescribeConfigsResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let results = CompactArray::deserialize_with(src, |src| {
+            let error_code = ErrorCode::from(src.try_get_i16()?);
+            let error_message = CompactNullableString::deserialize(src)?.0;
+            let resource_type = src.try_get_i8()?;
+            let resource_name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let configs = CompactArray::deserialize_with(src, |src| {
+                let name = CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default();
+                let value = CompactNullableString::deserialize(src)?.0;
+                let read_only = src.try_get_u8()? != 0;
+                let config_source = src.try_get_i8()?;
+                let is_sensitive = src.try_get_u8()? != 0;
+                let synonyms = CompactArray::deserialize_with(src, |src| {
+                    let synonym = (
+                        CompactNullableString::deserialize(src)?
+                            .0
+                            .unwrap_or_default(),
+                        CompactNullableString::deserialize(src)?.0,
+                        src.try_get_i8()?,
+                    );
+                    TagBuffer::deserialize_fields(src)?;
+                    Ok(synonym)
+                })?;
+                let config_type = src.try_get_i8()?;
+                let documentation = CompactNullableString::deserialize(src)?.0;
+                TagBuffer::deserialize_fields(src)?;
+                Ok(DescribeConfigsResourceResult {
+                    name,
+                    value,
+                    read_only,
+                    config_source,
+                    is_sensitive,
+                    synonyms,
+                    config_type,
+                    documentation,
+                })
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(DescribeConfigsResult {
+                error_code,
+                error_message,
+                resource_type,
+                resource_name,
+                configs,
+            })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            throttle_time_ms,
+            results,
+        })
+    }
+}
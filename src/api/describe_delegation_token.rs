@@ -0,0 +1,212 @@
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::config::SharedConfig;
+use crate::delegation_token::{token_requests_allowed, DelegationToken, DelegationTokenManager};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::security::KafkaPrincipal;
+use crate::wire_debug::WireDump;
+
+/// DescribeDelegationToken request, v2 and v3.
+pub struct DescribeDelegationTokenRequest {
+    /// `None` for every owner's tokens.
+    pub owners: Option<Vec<KafkaPrincipal>>,
+}
+
+impl Deserialize<Self> for DescribeDelegationTokenRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let owners = CompactArray::deserialize_nullable_with(src, |src| {
+            let owner = KafkaPrincipal {
+                principal_type: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                name: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(owner)
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self { owners })
+    }
+}
+
+/// DescribeDelegationToken response, v2 and v3.
+pub struct DescribeDelegationTokenResponse {
+    api_version: i16,
+    header: HeaderV1,
+    error_code: ErrorCode,
+    tokens: Vec<DelegationToken>,
+    throttle_time_ms: i32,
+}
+
+struct DescribedToken<'a> {
+    api_version: i16,
+    token: &'a DelegationToken,
+}
+
+impl Serialize for DescribedToken<'_> {
+    fn serialize(&self) -> Bytes {
+        let token = self.token;
+        let mut b = BytesMut::new();
+        b.put(principal(&token.owner));
+        if self.api_version >= 3 {
+            b.put(principal(&token.requester));
+        }
+        b.put_i64(token.issue_timestamp_ms);
+        b.put_i64(token.expiry_timestamp_ms);
+        b.put_i64(token.max_timestamp_ms);
+        b.put(CompactNullableString(Some(token.token_id.clone())).serialize());
+        b.put(CompactBytes(Bytes::from(token.hmac.clone())).serialize());
+        b.put(CompactArray(token.renewers.iter().map(Renewer).collect()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+struct Renewer<'a>(&'a KafkaPrincipal);
+
+impl Serialize for Renewer<'_> {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::from(principal(self.0));
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// A principal's type and name, as every token field naming one is laid out.
+fn principal(principal: &KafkaPrincipal) -> Bytes {
+    let mut b = BytesMut::new();
+    b.put(CompactNullableString(Some(principal.principal_type.clone())).serialize());
+    b.put(CompactNullableString(Some(principal.name.clone())).serialize());
+    b.freeze()
+}
+
+impl DescribeDelegationTokenResponse {
+    fn new(ctx: &RequestContext, result: Result<Vec<DelegationToken>, ErrorCode>) -> Self {
+        let (error_code, tokens) = match result {
+            Ok(tokens) => (ErrorCode::None, tokens),
+            Err(error_code) => (error_code, Vec::new()),
+        };
+        Self {
+            api_version: ctx.header.api_version,
+            header: HeaderV1::new(ctx.header.correlation_id),
+            error_code,
+            tokens,
+            throttle_time_ms: 0,
+        }
+    }
+}
+
+impl Response for DescribeDelegationTokenResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i16(self.error_code.into());
+        let tokens = self
+            .tokens
+            .iter()
+            .map(|token| DescribedToken {
+                api_version: self.api_version,
+                token,
+            })
+            .collect();
+        bytes.put(CompactArray(tokens).serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+}
+
+pub struct DescribeDelegationTokenHandler {
+    config: Arc<SharedConfig>,
+    authorizer: Arc<dyn Authorizer>,
+    tokens: Arc<DelegationTokenManager>,
+}
+
+impl DescribeDelegationTokenHandler {
+    pub fn new(
+        config: Arc<SharedConfig>,
+        authorizer: Arc<dyn Authorizer>,
+        tokens: Arc<DelegationTokenManager>,
+    ) -> Self {
+        Self {
+            config,
+            authorizer,
+            tokens,
+        }
+    }
+}
+
+impl ApiHandler for DescribeDelegationTokenHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let req = DescribeDelegationTokenRequest::deserialize(body)
+            .map_err(|e| Error::Decode(ApiKey::DescribeDelegationToken, e))?;
+        let result = if !token_requests_allowed(&self.config.get(), ctx) {
+            Err(ErrorCode::DelegationTokenRequestNotAllowed)
+        } else {
+            self.tokens.describe(req.owners.as_deref(), |token| {
+                visible(ctx, &*self.authorizer, token)
+            })
+        };
+        Ok(Box::new(DescribeDelegationTokenResponse::new(ctx, result)))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(DescribeDelegationTokenResponse::new(
+            ctx,
+            Err(error_code),
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = DescribeDelegationTokenRequest::deserialize(body)?;
+        match &req.owners {
+            Some(owners) => dump.list("owners", owners, |dump, owner| {
+                dump.field("principal", owner);
+            }),
+            None => dump.field("owners", "null"),
+        };
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// A token is described to its owner, requester and renewers, and to
+/// whoever has DescribeTokens on its owner.
+fn visible(ctx: &RequestContext, authorizer: &dyn Authorizer, token: &DelegationToken) -> bool {
+    token.is_renewer(&ctx.principal)
+        || token.requester == ctx.principal
+        || authorizer.authorize(
+            ctx,
+            AclOperation::DescribeTokens,
+            ResourceType::User,
+            &token.owner.name,
+        )
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::error::Error;
+use crate::log_manager::{partition_log_dir, partition_size};
+use crate::meta_properties::log_dir_ids;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// DescribeLogDirs request, v2-4.
+pub struct DescribeLogDirsRequest {
+    /// `None` describes every partition.
+    pub topics: Option<Vec<(String, Vec<i32>)>>,
+}
+
+impl Deserialize<Self> for DescribeLogDirsRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        // A length of 0 is the null array.
+        let topics = if src.first() == Some(&0) {
+            src.advance(1);
+            None
+        } else {
+            Some(CompactArray::deserialize_with(src, |src| {
+                let topic = CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default();
+                let partitions = CompactArray::deserialize_with(src, |src| Ok(src.try_get_i32()?))?;
+                TagBuffer::deserialize_fields(src)?;
+                Ok((topic, partitions))
+            })?)
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self { topics })
+    }
+}
+
+/// DescribeLogDirs response, v2-4.
+pub struct DescribeLogDirsResponse {
+    api_version: i16,
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    /// From v3; before that an unauthorized request just gets no results.
+    pub error_code: ErrorCode,
+    pub results: Vec<LogDirResult>,
+}
+
+/// One log dir and the partitions in it, by topic name.
+pub struct LogDirResult {
+    pub error_code: ErrorCode,
+    pub log_dir: String,
+    pub topics: BTreeMap<String, Vec<LogDirPartition>>,
+}
+
+pub struct LogDirPartition {
+    pub partition_index: i32,
+    pub partition_size: i64,
+}
+
+impl DescribeLogDirsResponse {
+    fn new(ctx: &RequestContext, error_code: ErrorCode, results: Vec<LogDirResult>) -> Self {
+        Self {
+            api_version: ctx.header.api_version,
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            results,
+        }
+    }
+}
+
+impl Response for DescribeLogDirsResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        if self.api_version >= 3 {
+            bytes.put_i16(self.error_code.into());
+        }
+        bytes.put_slice(&integer_encoding::VarInt::encode_var_vec(
+            self.results.len() as u32 + 1,
+        ));
+        for result in &self.results {
+            bytes.put_i16(result.error_code.into());
+            bytes.put(CompactNullableString(Some(result.log_dir.clone())).serialize());
+            bytes.put_slice(&integer_encoding::VarInt::encode_var_vec(
+                result.topics.len() as u32 + 1,
+            ));
+            for (name, partitions) in &result.topics {
+                bytes.put(CompactNullableString(Some(name.clone())).serialize());
+                bytes.put_slice(&integer_encoding::VarInt::encode_var_vec(
+                    partitions.len() as u32 + 1,
+                ));
+                for p in partitions {
+                    bytes.put_i32(p.partition_index);
+                    bytes.put_i64(p.partition_size);
+                    bytes.put_i64(0); // offset lag
+                    bytes.put_u8(0); // is future key
+                    bytes.put(TagBuffer::serialize());
+                }
+                bytes.put(TagBuffer::serialize());
+            }
+            if self.api_version >= 4 {
+                // Total and usable bytes, which this broker doesn't track.
+                bytes.put_i64(-1);
+                bytes.put_i64(-1);
+            }
+            bytes.put(TagBuffer::serialize());
+        }
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+}
+
+pub struct DescribeLogDirsHandler {
+    node_id: i32,
+    authorizer: Arc<dyn Authorizer>,
+    image: Arc<MetadataImage>,
+    log_dirs: Vec<PathBuf>,
+}
+
+impl DescribeLogDirsHandler {
+    pub fn new(
+        node_id: i32,
+        authorizer: Arc<dyn Authorizer>,
+        image: Arc<MetadataImage>,
+        log_dirs: Vec<PathBuf>,
+    ) -> Self {
+        Self {
+            node_id,
+            authorizer,
+            image,
+            log_dirs,
+        }
+    }
+}
+
+impl ApiHandler for DescribeLogDirsHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        Ok(Box::new(handle_request(
+            ctx,
+            self.node_id,
+            &*self.authorizer,
+            &self.image,
+            &self.log_dirs,
+            body,
+        )?))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(DescribeLogDirsResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        _: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = DescribeLogDirsRequest::deserialize(body)?;
+        dump.field("topics", format_args!("{:?}", req.topics));
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Lists each log dir with the replicas the metadata log places in it by
+/// directory id. A replica with no directory yet, or one this broker
+/// doesn't have, is listed under the log dir holding it on disk.
+pub fn handle_request(
+    ctx: &RequestContext,
+    node_id: i32,
+    authorizer: &dyn Authorizer,
+    image: &MetadataImage,
+    log_dirs: &[PathBuf],
+    message: &mut Bytes,
+) -> Result<DescribeLogDirsResponse, Error> {
+    let req = DescribeLogDirsRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::DescribeLogDirs, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Describe,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(DescribeLogDirsResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    let metadata = image.current();
+    let ids = log_dir_ids(log_dirs);
+    let mut results: Vec<LogDirResult> = log_dirs
+        .iter()
+        .map(|dir| LogDirResult {
+            error_code: ErrorCode::None,
+            log_dir: dir.display().to_string(),
+            topics: BTreeMap::new(),
+        })
+        .collect();
+    for topic in metadata.topics() {
+        let Some(name) = topic.topic_name.0.as_deref() else {
+            continue;
+        };
+        for p in metadata.partitions(&topic.topic_id) {
+            let index = p.partition_id as i32;
+            let requested = req.topics.as_ref().is_none_or(|topics| {
+                topics
+                    .iter()
+                    .any(|(topic, partitions)| topic == name && partitions.contains(&index))
+            });
+            let Some(i) = p.replicas.iter().position(|&r| r as i32 == node_id) else {
+                continue;
+            };
+            if !requested {
+                continue;
+            }
+            let partition_dir = format!("{}-{}", name, index);
+            let assigned = p
+                .directories
+                .get(i)
+                .and_then(|id| ids.iter().find(|(_, dir_id)| dir_id == id))
+                .map(|(dir, _)| dir.as_path());
+            let dir = match assigned {
+                Some(dir) => dir,
+                None => {
+                    let dir = partition_log_dir(log_dirs, &partition_dir);
+                    if !dir.join(&partition_dir).is_dir() {
+                        continue;
+                    }
+                    dir
+                }
+            };
+            let Some(result) = log_dirs.iter().position(|d| d == dir) else {
+                continue;
+            };
+            results[result]
+                .topics
+                .entry(name.to_string())
+                .or_default()
+                .push(LogDirPartition {
+                    partition_index: index,
+                    partition_size: partition_size(&dir.join(&partition_dir)) as i64,
+                });
+        }
+    }
+    Ok(DescribeLogDirsResponse::new(ctx, ErrorCode::None, results))
+}
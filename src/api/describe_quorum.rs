@@ -0,0 +1,263 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::raft::{MetadataQuorum, QuorumDescription, ReplicaState, METADATA_TOPIC};
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// DescribeQuorum request, v0 and v1.
+pub struct DescribeQuorumRequest {
+    pub topics: Vec<DescribeQuorumRequestTopic>,
+}
+
+pub struct DescribeQuorumRequestTopic {
+    pub topic_name: String,
+    pub partitions: Vec<i32>,
+}
+
+impl Deserialize<Self> for DescribeQuorumRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topics = CompactArray::deserialize_with(src, |src| {
+            let topic_name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let partitions = CompactArray::deserialize_with(src, |src| {
+                let partition_index = src.try_get_i32()?;
+                TagBuffer::deserialize_fields(src)?;
+                Ok(partition_index)
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(DescribeQuorumRequestTopic {
+                topic_name,
+                partitions,
+            })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self { topics })
+    }
+}
+
+/// DescribeQuorum response, v0 and v1; v1 adds when each replica last
+/// fetched and last caught up.
+pub struct DescribeQuorumResponse {
+    api_version: i16,
+    header: HeaderV1,
+    error_code: ErrorCode,
+    topics: Vec<DescribeQuorumResponseTopic>,
+}
+
+pub struct DescribeQuorumResponseTopic {
+    pub topic_name: String,
+    pub partitions: Vec<DescribeQuorumResponsePartition>,
+}
+
+pub struct DescribeQuorumResponsePartition {
+    pub partition_index: i32,
+    pub quorum: QuorumDescription,
+}
+
+impl DescribeQuorumResponse {
+    fn new(
+        ctx: &RequestContext,
+        error_code: ErrorCode,
+        topics: Vec<DescribeQuorumResponseTopic>,
+    ) -> Self {
+        Self {
+            api_version: ctx.header.api_version,
+            header: HeaderV1::new(ctx.header.correlation_id),
+            error_code,
+            topics,
+        }
+    }
+
+    fn serialize_replicas(&self, b: &mut BytesMut, replicas: &[ReplicaState]) {
+        b.put_slice(&integer_encoding::VarInt::encode_var_vec(
+            replicas.len() as u32 + 1,
+        ));
+        for replica in replicas {
+            b.put_i32(replica.replica_id);
+            b.put_i64(replica.log_end_offset);
+            if self.api_version >= 1 {
+                b.put_i64(replica.last_fetch_timestamp);
+                b.put_i64(replica.last_caught_up_timestamp);
+            }
+            b.put(TagBuffer::serialize());
+        }
+    }
+}
+
+impl Response for DescribeQuorumResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i16(self.error_code.into());
+        bytes.put_slice(&integer_encoding::VarInt::encode_var_vec(
+            self.topics.len() as u32 + 1,
+        ));
+        for topic in &self.topics {
+            bytes.put(CompactNullableString(Some(topic.topic_name.clone())).serialize());
+            bytes.put_slice(&integer_encoding::VarInt::encode_var_vec(
+                topic.partitions.len() as u32 + 1,
+            ));
+            for p in &topic.partitions {
+                bytes.put_i32(p.partition_index);
+                bytes.put_i16(p.quorum.error_code.into());
+                bytes.put_i32(p.quorum.leader_id);
+                bytes.put_i32(p.quorum.leader_epoch);
+                bytes.put_i64(p.quorum.high_watermark);
+                self.serialize_replicas(&mut bytes, &p.quorum.voters);
+                self.serialize_replicas(&mut bytes, &p.quorum.observers);
+                bytes.put(TagBuffer::serialize());
+            }
+            bytes.put(TagBuffer::serialize());
+        }
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        if self.error_code != ErrorCode::None {
+            return self.error_code;
+        }
+        self.topics
+            .iter()
+            .flat_map(|t| &t.partitions)
+            .map(|p| p.quorum.error_code)
+            .find(|&e| e != ErrorCode::None)
+            .unwrap_or(ErrorCode::None)
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        let ids =
+            |replicas: &[ReplicaState]| replicas.iter().map(|r| r.replica_id).collect::<Vec<_>>();
+        dump.field("error_code", format_args!("{:?}", self.error_code))
+            .list("topics", &self.topics, |dump, topic| {
+                dump.field("topic_name", &topic.topic_name).list(
+                    "partitions",
+                    &topic.partitions,
+                    |dump, p| {
+                        dump.field("partition_index", p.partition_index)
+                            .field("error_code", format_args!("{:?}", p.quorum.error_code))
+                            .field("leader_id", p.quorum.leader_id)
+                            .field("leader_epoch", p.quorum.leader_epoch)
+                            .field("high_watermark", p.quorum.high_watermark)
+                            .field("voters", format_args!("{:?}", ids(&p.quorum.voters)))
+                            .field("observers", format_args!("{:?}", ids(&p.quorum.observers)));
+                    },
+                );
+            });
+    }
+}
+
+/// Served by the quorum's voters. Only the leader knows how far each
+/// replica has got, so the others answer NOT_LEADER_OR_FOLLOWER with the
+/// leader they know of.
+pub struct DescribeQuorumHandler {
+    quorum: MetadataQuorum,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl DescribeQuorumHandler {
+    pub fn new(quorum: MetadataQuorum, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self { quorum, authorizer }
+    }
+}
+
+impl ApiHandler for DescribeQuorumHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.quorum, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(DescribeQuorumResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        _: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = DescribeQuorumRequest::deserialize(body)?;
+        dump.list("topics", &req.topics, |dump, topic| {
+            dump.field("topic_name", &topic.topic_name)
+                .field("partitions", format_args!("{:?}", topic.partitions));
+        });
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes Describe on the cluster. Only the metadata partition has a
+/// quorum; any other partition is UNKNOWN_TOPIC_OR_PARTITION.
+pub fn handle_request(
+    ctx: &RequestContext,
+    quorum: &MetadataQuorum,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<DescribeQuorumResponse, Error> {
+    let req = DescribeQuorumRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::DescribeQuorum, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Describe,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(DescribeQuorumResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    let topics = req
+        .topics
+        .into_iter()
+        .map(|topic| {
+            let partitions = topic
+                .partitions
+                .iter()
+                .map(|&partition_index| {
+                    let quorum = if topic.topic_name == METADATA_TOPIC && partition_index == 0 {
+                        quorum.describe()
+                    } else {
+                        QuorumDescription {
+                            error_code: ErrorCode::UnknownTopicOrPartition,
+                            leader_id: -1,
+                            leader_epoch: -1,
+                            high_watermark: -1,
+                            voters: Vec::new(),
+                            observers: Vec::new(),
+                        }
+                    };
+                    DescribeQuorumResponsePartition {
+                        partition_index,
+                        quorum,
+                    }
+                })
+                .collect();
+            DescribeQuorumResponseTopic {
+                topic_name: topic.topic_name,
+                partitions,
+            }
+        })
+        .collect();
+    Ok(DescribeQuorumResponse::new(ctx, ErrorCode::None, topics))
+}
@@ -1,29 +1,86 @@
-use anyhow::Result;
+use std::fmt;
+use std::sync::Arc;
+
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
-use crate::cluster_metadata::{RecordBatches, RecordValue};
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::error::Error;
+use crate::metadata_image::MetadataImage;
 use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
 
 const DEFAULT_UNKNOWN_TOPIC_UUID: &str = "00000000-0000-0000-0000-000000000000";
 
+/// The most partitions a response holds, whatever the request asks for, as
+/// Kafka's `max.request.partition.size.limit` defaults to.
+const MAX_RESPONSE_PARTITIONS: usize = 2000;
+
 pub struct DescribeTopicPartitionsRequestV0 {
     pub topic_names: Vec<CompactNullableString>,
     response_partition_limit: i32,
-    cursor: u8,
+    /// Where to start, from the previous response's `next_cursor`.
+    cursor: Option<Cursor>,
 }
 
 impl Deserialize<Self> for DescribeTopicPartitionsRequestV0 {
-    fn deserialize(src: &mut Bytes) -> Self {
-        let topic_names = CompactArray::<Topic>::deserialize(src);
-        let response_partition_limit = src.get_i32();
-        let cursor = src.get_u8();
-        TagBuffer::deserialize(src);
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topic_names = CompactArray::<Topic>::deserialize(src)?;
+        let response_partition_limit = src.try_get_i32()?;
+        let cursor = Cursor::deserialize(src)?;
+        TagBuffer::deserialize(src)?;
 
-        Self {
+        Ok(Self {
             topic_names,
             response_partition_limit,
             cursor,
+        })
+    }
+}
+
+/// The first partition of a page, as a topic name and partition index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub topic_name: String,
+    pub partition_index: i32,
+}
+
+impl Cursor {
+    /// Reads a nullable cursor, which leads with -1 when it is null.
+    fn deserialize(src: &mut Bytes) -> Result<Option<Self>, DecodeError> {
+        if src.try_get_i8()? < 0 {
+            return Ok(None);
         }
+        let topic_name = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let partition_index = src.try_get_i32()?;
+        TagBuffer::deserialize(src)?;
+        Ok(Some(Self {
+            topic_name,
+            partition_index,
+        }))
+    }
+
+    fn serialize(cursor: Option<&Self>) -> Bytes {
+        let mut b = BytesMut::new();
+        match cursor {
+            Some(cursor) => {
+                b.put_i8(1);
+                b.put(CompactNullableString(Some(cursor.topic_name.clone())).serialize());
+                b.put_i32(cursor.partition_index);
+                b.put(TagBuffer::serialize());
+            }
+            None => b.put_i8(-1),
+        }
+        b.freeze()
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.topic_name, self.partition_index)
     }
 }
 
@@ -32,7 +89,8 @@ pub struct DescribeTopicPartitionsResponseV0 {
     header: HeaderV1,
     throttle_time_ms: i32,
     topics: CompactArray<Topic>,
-    next_cursor: u8,
+    /// Where the next page starts, or `None` if this is the last.
+    next_cursor: Option<Cursor>,
 }
 
 impl DescribeTopicPartitionsResponseV0 {
@@ -41,7 +99,7 @@ impl DescribeTopicPartitionsResponseV0 {
             header: HeaderV1::new(correlation_id),
             throttle_time_ms: 0,
             topics: CompactArray(topics),
-            next_cursor: 0xFF,
+            next_cursor: None,
         }
     }
 }
@@ -51,79 +109,253 @@ impl Response for DescribeTopicPartitionsResponseV0 {
         let mut bytes = BytesMut::from(self.header.serialize());
         bytes.put_i32(self.throttle_time_ms);
         bytes.put(self.topics.serialize());
-        bytes.put_u8(self.next_cursor);
+        bytes.put(Cursor::serialize(self.next_cursor.as_ref()));
         bytes.put(TagBuffer::serialize());
         bytes.freeze()
     }
-}
 
-pub fn handle_request(
-    header: HeaderV2,
-    message: &mut Bytes,
-) -> Result<DescribeTopicPartitionsResponseV0> {
-    let record_batches = RecordBatches::from_file(CLUSTER_METADATA_LOG_FILE)?;
-    let topic_authorized_operations = 0x0DF;
-    let req = DescribeTopicPartitionsRequestV0::deserialize(message);
-    let mut topics = Vec::new();
-
-    for record_batch in record_batches.batches() {
-        for topic_name in &req.topic_names {
-            let mut partitions = Vec::new();
-            let mut topic_id = Uuid(DEFAULT_UNKNOWN_TOPIC_UUID.to_string());
-            let mut topic_error_code = ErrorCode::UnknownTopicOrPartition;
-
-            for rec in &record_batch.records {
-                if let RecordValue::Topic(ref topic) = rec.value {
-                    if topic.topic_name == *topic_name {
-                        topic_id = topic.topic_id.clone();
-                        topic_error_code = ErrorCode::None;
-                    }
-                }
-                if let RecordValue::Partition(p) = &rec.value {
-                    if p.topic_id == topic_id {
-                        partitions.push(Partition::new(
-                            ErrorCode::None,
-                            p.partition_id,
-                            p.leader_id,
-                            p.leader_epoch,
-                            p.replicas.clone(),
-                            p.in_sync_replicas.clone(),
-                            p.adding_replicas.clone(),
-                            Vec::new(),
-                            p.removing_replicas.clone(),
-                        ));
-                    }
-                }
+    fn topic_partitions(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for topic in &self.topics.0 {
+            let name = topic.name.0.as_deref().unwrap_or_default();
+            if topic.partitions.0.is_empty() {
+                names.push(name.to_string());
             }
-
-            if !partitions.is_empty() {
-                topics.push(Topic {
-                    error_code: topic_error_code,
-                    name: topic_name.clone(),
-                    topic_id: topic_id.clone(),
-                    is_internal: false,
-                    partitions: CompactArray(partitions),
-                    topic_authorized_operations,
-                });
+            for partition in &topic.partitions.0 {
+                names.push(format!("{}-{}", name, partition.partition_index));
             }
         }
+        names
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms)
+            .list("topics", &self.topics.0, |dump, topic| {
+                dump.field("error_code", format_args!("{:?}", topic.error_code))
+                    .string("name", &topic.name)
+                    .field("topic_id", &topic.topic_id)
+                    .field("is_internal", topic.is_internal)
+                    .list("partitions", &topic.partitions.0, |dump, p| {
+                        dump.field("error_code", format_args!("{:?}", p.error_code))
+                            .field("partition", p.partition_index)
+                            .field("leader_id", p.leader_id as i32)
+                            .field("leader_epoch", p.leader_epoch as i32)
+                            .field("replicas", format_args!("{:?}", p.replicas.0))
+                            .field("isr", format_args!("{:?}", p.in_sync_replicas.0))
+                            .field(
+                                "offline_replicas",
+                                format_args!("{:?}", p.offline_replicas.0),
+                            );
+                    });
+            })
+            .nullable("next_cursor", self.next_cursor.as_ref());
     }
+}
 
-    for requested_topic in req.topic_names {
-        if !topics.iter().any(|t| t.name == requested_topic) {
+pub struct DescribeTopicPartitionsHandler {
+    authorizer: Arc<dyn Authorizer>,
+    image: Arc<MetadataImage>,
+}
+
+impl DescribeTopicPartitionsHandler {
+    pub fn new(authorizer: Arc<dyn Authorizer>, image: Arc<MetadataImage>) -> Self {
+        Self { authorizer, image }
+    }
+}
+
+impl ApiHandler for DescribeTopicPartitionsHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        Ok(Box::new(handle_request(
+            ctx,
+            &*self.authorizer,
+            &self.image,
+            body,
+        )?))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(error_response(ctx, body, error_code)?))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = DescribeTopicPartitionsRequestV0::deserialize(body)?;
+        dump.list("topics", &req.topic_names, |dump, name| {
+            dump.string("name", name);
+        })
+        .field("response_partition_limit", req.response_partition_limit)
+        .nullable("cursor", req.cursor.as_ref());
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+pub fn handle_request(
+    ctx: &RequestContext,
+    authorizer: &dyn Authorizer,
+    image: &MetadataImage,
+    message: &mut Bytes,
+) -> Result<DescribeTopicPartitionsResponseV0, Error> {
+    let mut original = message.clone();
+    let record_batches = image.current();
+    let topic_authorized_operations = 0x0DF;
+    let req = DescribeTopicPartitionsRequestV0::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::DescribeTopicPartitions, e))?;
+    // A topic named twice is described once, and topics come in name order,
+    // which is also the order pages follow.
+    let mut topic_names = req.topic_names;
+    topic_names.sort_by(|a, b| a.0.cmp(&b.0));
+    topic_names.dedup();
+
+    let mut topics = Vec::with_capacity(topic_names.len());
+    for name in topic_names {
+        let authorized = authorizer.authorize(
+            ctx,
+            AclOperation::Describe,
+            ResourceType::Topic,
+            name.0.as_deref().unwrap_or_default(),
+        );
+        let topic = authorized
+            .then(|| record_batches.topic_by_name(name.0.as_deref()?))
+            .flatten();
+        let Some(topic) = topic else {
             topics.push(Topic {
-                error_code: ErrorCode::UnknownTopicOrPartition,
-                name: requested_topic,
+                error_code: if authorized {
+                    ErrorCode::UnknownTopicOrPartition
+                } else {
+                    ErrorCode::TopicAuthorizationFailed
+                },
+                name,
                 topic_id: Uuid(DEFAULT_UNKNOWN_TOPIC_UUID.to_string()),
                 is_internal: false,
                 partitions: CompactArray(Vec::new()),
                 topic_authorized_operations,
             });
+            continue;
+        };
+        let partitions = record_batches
+            .partitions(&topic.topic_id)
+            .map(|p| {
+                Partition::new(
+                    ErrorCode::None,
+                    p.partition_id,
+                    p.leader_id,
+                    p.leader_epoch,
+                    p.replicas.clone(),
+                    p.in_sync_replicas.clone(),
+                    p.adding_replicas.clone(),
+                    Vec::new(),
+                    p.removing_replicas.clone(),
+                )
+            })
+            .collect();
+        topics.push(Topic {
+            error_code: ErrorCode::None,
+            name,
+            topic_id: topic.topic_id.clone(),
+            is_internal: false,
+            partitions: CompactArray(partitions),
+            topic_authorized_operations,
+        });
+    }
+
+    // As in Kafka, a cursor must name one of the requested topics.
+    if let Some(cursor) = &req.cursor {
+        if !topics
+            .iter()
+            .any(|t| t.name.0.as_deref() == Some(&cursor.topic_name))
+        {
+            return error_response(ctx, &mut original, ErrorCode::InvalidRequest)
+                .map_err(|e| Error::Decode(ApiKey::DescribeTopicPartitions, e));
+        }
+    }
+    let limit = match usize::try_from(req.response_partition_limit) {
+        Ok(limit) if limit > 0 => limit.min(MAX_RESPONSE_PARTITIONS),
+        _ => MAX_RESPONSE_PARTITIONS,
+    };
+    let (topics, next_cursor) = paginate(topics, req.cursor.as_ref(), limit);
+    let mut response = DescribeTopicPartitionsResponseV0::new(ctx.header.correlation_id, topics);
+    response.next_cursor = next_cursor;
+    Ok(response)
+}
+
+/// The page of `topics` that starts at `cursor` and holds at most `limit`
+/// partitions, and the cursor for the page after it if there is one.
+fn paginate(
+    topics: Vec<Topic>,
+    cursor: Option<&Cursor>,
+    limit: usize,
+) -> (Vec<Topic>, Option<Cursor>) {
+    let start = cursor.map_or(0, |cursor| {
+        topics
+            .iter()
+            .position(|t| t.name.0.as_deref() == Some(&cursor.topic_name))
+            .unwrap_or(topics.len())
+    });
+    let mut page = Vec::new();
+    let mut left = limit;
+    for (i, mut topic) in topics.into_iter().skip(start).enumerate() {
+        let partitions = &mut topic.partitions.0;
+        partitions.sort_by_key(|p| p.partition_index);
+        if let (0, Some(cursor)) = (i, cursor) {
+            partitions.retain(|p| p.partition_index as i32 >= cursor.partition_index);
+        }
+        let name = || topic.name.0.clone().unwrap_or_default();
+        if left == 0 {
+            let next = Cursor {
+                topic_name: name(),
+                partition_index: partitions.first().map_or(0, |p| p.partition_index as i32),
+            };
+            return (page, Some(next));
         }
+        if partitions.len() > left {
+            let rest = partitions.split_off(left);
+            let next = Cursor {
+                topic_name: name(),
+                partition_index: rest[0].partition_index as i32,
+            };
+            page.push(topic);
+            return (page, Some(next));
+        }
+        left -= partitions.len();
+        page.push(topic);
     }
+    (page, None)
+}
 
+/// Answers every requested topic with `error_code`.
+pub fn error_response(
+    ctx: &RequestContext,
+    message: &mut Bytes,
+    error_code: ErrorCode,
+) -> Result<DescribeTopicPartitionsResponseV0, DecodeError> {
+    let req = DescribeTopicPartitionsRequestV0::deserialize(message)?;
+    let topics = req
+        .topic_names
+        .into_iter()
+        .map(|name| Topic {
+            error_code,
+            name,
+            topic_id: Uuid(DEFAULT_UNKNOWN_TOPIC_UUID.to_string()),
+            is_internal: false,
+            partitions: CompactArray(Vec::new()),
+            topic_authorized_operations: 0,
+        })
+        .collect();
     Ok(DescribeTopicPartitionsResponseV0::new(
-        header.correlation_id,
+        ctx.header.correlation_id,
         topics,
     ))
 }
@@ -153,10 +385,10 @@ impl Serialize for Topic {
 }
 
 impl Deserialize<CompactNullableString> for Topic {
-    fn deserialize(src: &mut Bytes) -> CompactNullableString {
-        let s = CompactNullableString::deserialize(src);
-        TagBuffer::deserialize(src);
-        s
+    fn deserialize(src: &mut Bytes) -> Result<CompactNullableString, DecodeError> {
+        let s = CompactNullableString::deserialize(src)?;
+        TagBuffer::deserialize(src)?;
+        Ok(s)
     }
 }
 
@@ -174,6 +406,7 @@ pub struct Partition {
 }
 
 impl Partition {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         error_code: ErrorCode,
         partition_index: u32,
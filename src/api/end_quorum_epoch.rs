@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::begin_quorum_epoch::{QuorumEpochResponse, QuorumEpochResponseTopic};
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::raft::{MetadataQuorum, METADATA_TOPIC};
+use crate::request_context::RequestContext;
+
+/// EndQuorumEpoch request, v0: a leader stepping down, naming the voters it
+/// would like to succeed it, most caught up first.
+#[derive(Clone)]
+pub struct EndQuorumEpochRequest {
+    pub cluster_id: Option<String>,
+    pub topics: Vec<EndQuorumEpochTopic>,
+}
+
+#[derive(Clone)]
+pub struct EndQuorumEpochTopic {
+    pub topic_name: String,
+    pub partitions: Vec<EndQuorumEpochPartition>,
+}
+
+#[derive(Clone)]
+pub struct EndQuorumEpochPartition {
+    pub partition_index: i32,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub preferred_successors: Vec<i32>,
+}
+
+impl EndQuorumEpochRequest {
+    /// The request a resigning leader sends for the metadata partition.
+    pub fn metadata(
+        cluster_id: &str,
+        leader_id: i32,
+        leader_epoch: i32,
+        preferred_successors: Vec<i32>,
+    ) -> Self {
+        Self {
+            cluster_id: Some(cluster_id.to_string()),
+            topics: vec![EndQuorumEpochTopic {
+                topic_name: METADATA_TOPIC.to_string(),
+                partitions: vec![EndQuorumEpochPartition {
+                    partition_index: 0,
+                    leader_id,
+                    leader_epoch,
+                    preferred_successors,
+                }],
+            }],
+        }
+    }
+}
+
+impl Deserialize<Self> for EndQuorumEpochRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            cluster_id: NullableString::deserialize(src)?.0,
+            topics: Array::<EndQuorumEpochTopic>::deserialize(src)?,
+        })
+    }
+}
+
+impl Serialize for EndQuorumEpochRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(NullableString(self.cluster_id.clone()).serialize());
+        b.put(Array(self.topics.clone()).serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for EndQuorumEpochTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            topic_name: NullableString::deserialize(src)?.0.unwrap_or_default(),
+            partitions: Array::<EndQuorumEpochPartition>::deserialize(src)?,
+        })
+    }
+}
+
+impl Serialize for EndQuorumEpochTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(NullableString(Some(self.topic_name.clone())).serialize());
+        b.put(Array(self.partitions.clone()).serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for EndQuorumEpochPartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            partition_index: src.try_get_i32()?,
+            leader_id: src.try_get_i32()?,
+            leader_epoch: src.try_get_i32()?,
+            preferred_successors: Array::<EndQuorumEpochPartition>::deserialize(src)?,
+        })
+    }
+}
+
+impl Deserialize<i32> for EndQuorumEpochPartition {
+    fn deserialize(src: &mut Bytes) -> Result<i32, DecodeError> {
+        Ok(src.try_get_i32()?)
+    }
+}
+
+impl Serialize for EndQuorumEpochPartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i32(self.leader_id);
+        b.put_i32(self.leader_epoch);
+        b.put_i32(self.preferred_successors.len() as i32);
+        for id in &self.preferred_successors {
+            b.put_i32(*id);
+        }
+        b.freeze()
+    }
+}
+
+pub struct EndQuorumEpochHandler {
+    quorum: MetadataQuorum,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl EndQuorumEpochHandler {
+    pub fn new(quorum: MetadataQuorum, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self { quorum, authorizer }
+    }
+}
+
+impl ApiHandler for EndQuorumEpochHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.quorum, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(QuorumEpochResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+}
+
+pub fn handle_request(
+    ctx: &RequestContext,
+    quorum: &MetadataQuorum,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<QuorumEpochResponse, Error> {
+    let req = EndQuorumEpochRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::EndQuorumEpoch, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::ClusterAction,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(QuorumEpochResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    if !quorum.is_cluster(req.cluster_id.as_deref()) {
+        return Ok(QuorumEpochResponse::new(
+            ctx,
+            ErrorCode::InconsistentClusterId,
+            Vec::new(),
+        ));
+    }
+    let topics = req
+        .topics
+        .into_iter()
+        .map(|topic| {
+            let partitions = topic.partitions.into_iter().map(|p| (p.partition_index, p));
+            QuorumEpochResponseTopic::answer(topic.topic_name, partitions, |p| {
+                quorum.end_quorum_epoch(p.leader_id, p.leader_epoch, p.preferred_successors)
+            })
+        })
+        .collect();
+    Ok(QuorumEpochResponse::new(ctx, ErrorCode::None, topics))
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::coordinator::TransactionCoordinator;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// EndTxn request, v3 and v4.
+pub struct EndTxnRequest {
+    pub transactional_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    /// Commit if true, abort if false.
+    pub committed: bool,
+}
+
+impl Deserialize<Self> for EndTxnRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let transactional_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let producer_id = src.try_get_i64()?;
+        let producer_epoch = src.try_get_i16()?;
+        let committed = src.try_get_i8()? != 0;
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            transactional_id,
+            producer_id,
+            producer_epoch,
+            committed,
+        })
+    }
+}
+
+/// EndTxn response, v3 and v4.
+pub struct EndTxnResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+}
+
+impl Response for EndTxnResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+}
+
+impl EndTxnResponse {
+    fn new(ctx: &RequestContext, error_code: ErrorCode) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+        }
+    }
+}
+
+pub struct EndTxnHandler {
+    transactions: Arc<TransactionCoordinator>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl EndTxnHandler {
+    pub fn new(transactions: Arc<TransactionCoordinator>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            transactions,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for EndTxnHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.transactions, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(EndTxnResponse::new(ctx, error_code)))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = EndTxnRequest::deserialize(body)?;
+        dump.field("transactional_id", &req.transactional_id)
+            .field("producer_id", req.producer_id)
+            .field("producer_epoch", req.producer_epoch)
+            .field("committed", req.committed);
+        Ok(())
+    }
+}
+
+/// Takes Write on the transactional id. The transaction's markers are
+/// written before it is answered.
+pub fn handle_request(
+    ctx: &RequestContext,
+    transactions: &TransactionCoordinator,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<EndTxnResponse, Error> {
+    let req = EndTxnRequest::deserialize(message).map_err(|e| Error::Decode(ApiKey::EndTxn, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Write,
+        ResourceType::TransactionalId,
+        &req.transactional_id,
+    ) {
+        return Ok(EndTxnResponse::new(
+            ctx,
+            ErrorCode::TransactionalIdAuthorizationFailed,
+        ));
+    }
+    let error_code = transactions.end_transaction(
+        &req.transactional_id,
+        req.producer_id,
+        req.producer_epoch,
+        req.committed,
+    );
+    Ok(EndTxnResponse::new(ctx, error_code))
+}
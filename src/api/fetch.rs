@@ -1,35 +1,197 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use anyhow::{Context, Result};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use integer_encoding::VarInt;
+use tracing::{debug, debug_span, warn};
 
-use crate::cluster_metadata::RecordBatches;
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::cluster_metadata::{PartitionValue, RecordBatches};
+use crate::error::Error;
+use crate::fetch_session::{FetchSessionCache, SessionFetch, FINAL_EPOCH};
+use crate::isr_manager::IsrManager;
+use crate::listener::ListenerType;
+use crate::metadata_image::MetadataImage;
+use crate::partition_rates::{ByteDirection, PartitionRates};
 use crate::protocol::*;
+use crate::purgatory::{DelayedOperation, Purgatory};
+use crate::raft::{MetadataFetch, MetadataQuorum, SnapshotId, METADATA_TOPIC_ID};
+use crate::record_batch::{BatchHeader, LeaderEpochs};
+use crate::replica_fetcher::ReplicaFetchers;
+use crate::request_context::{Cancellation, RequestContext};
+use crate::wire_debug::WireDump;
+
+/// How many partition logs one fetch reads at once.
+const MAX_CONCURRENT_READS: usize = 8;
+/// How much of a segment is read at a time.
+const READ_CHUNK_SIZE: usize = 1 << 20;
+
+/// Consumers reading `READ_COMMITTED` see data only up to the last stable
+/// offset and are told which transactions in it were aborted.
+const READ_COMMITTED: u8 = 1;
 
+/// Where a batch's records start, after its fixed header.
+const RECORDS_OFFSET: usize = 61;
+const TRANSACTIONAL_FLAG: i16 = 0x10;
+/// The control record type of an abort marker; a commit marker is 1.
+const ABORT_MARKER: i16 = 0;
+
+#[allow(dead_code)]
 pub struct FetchRequestV16 {
+    /// The cluster the fetching replica belongs to, tagged field 0.
+    cluster_id: Option<String>,
+    /// The fetching replica and its broker epoch, tagged field 1; -1 for
+    /// consumers.
+    replica_id: i32,
+    replica_epoch: i64,
     max_wait_ms: u32,
     min_bytes: u32,
     max_bytes: u32,
     isolation_level: u8,
     session_id: u32,
-    session_epoch: u32,
+    session_epoch: i32,
     topics: Vec<TopicRequest>,
     forgotten_topics_data: Vec<ForgottenTopicData>,
     rack_id: CompactNullableString,
 }
 
-impl Deserialize<Self> for FetchRequestV16 {
-    fn deserialize(src: &mut Bytes) -> Self {
-        let max_wait_ms = src.get_u32();
-        let min_bytes = src.get_u32();
-        let max_bytes = src.get_u32();
-        let isolation_level = src.get_u8();
-        let session_id = src.get_u32();
-        let session_epoch = src.get_u32();
-        let topics = CompactArray::<Self>::deserialize(src);
-        let forgotten_topics_data = CompactArray::<Self>::deserialize(src);
-        let rack_id = CompactNullableString::deserialize(src);
-        TagBuffer::deserialize(src);
+impl FetchRequestV16 {
+    /// A voter's fetch from the metadata partition of the quorum leader.
+    pub fn metadata(cluster_id: &str, replica_id: i32, fetch: &MetadataFetch) -> Self {
+        Self {
+            cluster_id: Some(cluster_id.to_string()),
+            replica_id,
+            replica_epoch: -1,
+            max_wait_ms: fetch.max_wait.as_millis() as u32,
+            min_bytes: 0,
+            max_bytes: fetch.max_bytes as u32,
+            isolation_level: 0,
+            session_id: 0,
+            session_epoch: FINAL_EPOCH,
+            topics: vec![TopicRequest {
+                topic_id: Uuid(METADATA_TOPIC_ID.to_string()),
+                partitions: vec![Partition {
+                    partition_index: 0,
+                    current_leader_epoch: fetch.current_leader_epoch,
+                    fetch_offset: fetch.fetch_offset,
+                    last_fetched_epoch: fetch.last_fetched_epoch,
+                    log_start_offset: -1,
+                    partition_max_bytes: fetch.max_bytes as i32,
+                }],
+            }],
+            forgotten_topics_data: Vec::new(),
+            rack_id: CompactNullableString(None),
+        }
+    }
+
+    /// A consumer's fetch from the leader of the partitions in `topics`.
+    pub fn consumer(
+        max_wait: Duration,
+        min_bytes: u32,
+        max_bytes: u32,
+        topics: Vec<TopicRequest>,
+    ) -> Self {
+        Self::replica(-1, max_wait, min_bytes, max_bytes, topics)
+    }
 
+    /// A follower's fetch from the leader of the partitions in `topics`.
+    pub fn replica(
+        replica_id: i32,
+        max_wait: Duration,
+        min_bytes: u32,
+        max_bytes: u32,
+        topics: Vec<TopicRequest>,
+    ) -> Self {
         Self {
+            cluster_id: None,
+            replica_id,
+            replica_epoch: -1,
+            max_wait_ms: max_wait.as_millis() as u32,
+            min_bytes,
+            max_bytes,
+            isolation_level: 0,
+            session_id: 0,
+            session_epoch: FINAL_EPOCH,
+            topics,
+            forgotten_topics_data: Vec::new(),
+            rack_id: CompactNullableString(None),
+        }
+    }
+}
+
+impl FetchRequestV16 {
+    fn describe(&self, dump: &mut WireDump) {
+        dump.nullable("cluster_id", self.cluster_id.as_deref())
+            .field("replica_id", self.replica_id)
+            .field("replica_epoch", self.replica_epoch)
+            .field("max_wait_ms", self.max_wait_ms)
+            .field("min_bytes", self.min_bytes)
+            .field("max_bytes", self.max_bytes)
+            .field("isolation_level", self.isolation_level)
+            .field("session_id", self.session_id)
+            .field("session_epoch", self.session_epoch)
+            .list("topics", &self.topics, |dump, topic| {
+                dump.field("topic_id", &topic.topic_id).list(
+                    "partitions",
+                    &topic.partitions,
+                    |dump, p| {
+                        dump.field("partition", p.partition_index)
+                            .field("current_leader_epoch", p.current_leader_epoch)
+                            .field("fetch_offset", p.fetch_offset)
+                            .field("last_fetched_epoch", p.last_fetched_epoch)
+                            .field("log_start_offset", p.log_start_offset)
+                            .field("partition_max_bytes", p.partition_max_bytes);
+                    },
+                );
+            })
+            .list(
+                "forgotten_topics_data",
+                &self.forgotten_topics_data,
+                |dump, topic| {
+                    dump.field("topic_id", &topic.topic_id)
+                        .field("partitions", format_args!("{:?}", topic.partitions));
+                },
+            )
+            .string("rack_id", &self.rack_id);
+    }
+}
+
+impl Deserialize<Self> for FetchRequestV16 {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let max_wait_ms = src.try_get_u32()?;
+        let min_bytes = src.try_get_u32()?;
+        let max_bytes = src.try_get_u32()?;
+        let isolation_level = src.try_get_u8()?;
+        let session_id = src.try_get_u32()?;
+        let session_epoch = src.try_get_i32()?;
+        let topics = CompactArray::<Self>::deserialize(src)?;
+        let forgotten_topics_data = CompactArray::<Self>::deserialize(src)?;
+        let rack_id = CompactNullableString::deserialize(src)?;
+        let mut cluster_id = None;
+        let mut replica_id = -1;
+        let mut replica_epoch = -1;
+        for (tag, mut value) in TagBuffer::deserialize_fields(src)? {
+            match tag {
+                0 => cluster_id = CompactNullableString::deserialize(&mut value)?.0,
+                1 => {
+                    replica_id = value.try_get_i32()?;
+                    replica_epoch = value.try_get_i64()?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            cluster_id,
+            replica_id,
+            replica_epoch,
             max_wait_ms,
             min_bytes,
             max_bytes,
@@ -39,16 +201,51 @@ impl Deserialize<Self> for FetchRequestV16 {
             topics,
             forgotten_topics_data,
             rack_id,
+        })
+    }
+}
+
+impl Serialize for FetchRequestV16 {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_u32(self.max_wait_ms);
+        b.put_u32(self.min_bytes);
+        b.put_u32(self.max_bytes);
+        b.put_u8(self.isolation_level);
+        b.put_u32(self.session_id);
+        b.put_i32(self.session_epoch);
+        b.put(CompactArray(self.topics.clone()).serialize());
+        // Nothing to forget without a session.
+        b.put(CompactArray::<TopicRequest>(Vec::new()).serialize());
+        b.put(self.rack_id.serialize());
+        let mut tags = Vec::new();
+        if self.cluster_id.is_some() {
+            tags.push((
+                0,
+                CompactNullableString(self.cluster_id.clone()).serialize(),
+            ));
+        }
+        if self.replica_id >= 0 {
+            let mut replica_state = BytesMut::new();
+            replica_state.put_i32(self.replica_id);
+            replica_state.put_i64(self.replica_epoch);
+            replica_state.put(TagBuffer::serialize());
+            tags.push((1, replica_state.freeze()));
         }
+        b.put(TagBuffer::serialize_fields(&tags));
+        b.freeze()
     }
 }
 
 pub struct FetchResponseV16 {
     header: HeaderV1,
     throttle_time_ms: i32,
-    error_code: ErrorCode,
+    pub error_code: ErrorCode,
     session_id: u32,
     responses: CompactArray<TopicResponse>,
+    /// Where to reach the leaders named in the partitions' current leader
+    /// hints, tagged field 0.
+    pub node_endpoints: Vec<NodeEndpoint>,
 }
 
 impl FetchResponseV16 {
@@ -59,7 +256,50 @@ impl FetchResponseV16 {
             error_code: ErrorCode::None,
             session_id,
             responses: CompactArray(responses),
+            node_endpoints: Vec::new(),
+        }
+    }
+
+    /// Every partition answered, with the id of its topic.
+    pub fn partitions(&self) -> impl Iterator<Item = (&str, &TopicPartition)> {
+        self.responses.0.iter().flat_map(|t| {
+            t.partitions
+                .0
+                .iter()
+                .map(move |p| (t.topic_id.0.as_str(), p))
+        })
+    }
+
+    /// The answer for the metadata partition, if the response has one.
+    pub fn metadata_partition(&self) -> Option<&TopicPartition> {
+        self.partitions()
+            .find(|(topic_id, p)| *topic_id == METADATA_TOPIC_ID && p.partition_index == 0)
+            .map(|(_, p)| p)
+    }
+}
+
+/// Reads a whole response, header included, as a fetching voter gets it.
+impl Deserialize<Self> for FetchResponseV16 {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let error_code = ErrorCode::from(src.try_get_i16()?);
+        let session_id = src.try_get_u32()?;
+        let responses = CompactArray::<TopicResponse>::deserialize(src)?;
+        let mut node_endpoints = Vec::new();
+        for (tag, mut value) in TagBuffer::deserialize_fields(src)? {
+            if tag == 0 {
+                node_endpoints = CompactArray::<NodeEndpoint>::deserialize(&mut value)?;
+            }
         }
+        Ok(Self {
+            header,
+            throttle_time_ms,
+            error_code,
+            session_id,
+            responses: CompactArray(responses),
+            node_endpoints,
+        })
     }
 }
 
@@ -70,76 +310,911 @@ impl Response for FetchResponseV16 {
         bytes.put_i16(self.error_code.into());
         bytes.put_u32(self.session_id);
         bytes.put(self.responses.serialize());
-        bytes.put(TagBuffer::serialize());
+        let mut tags = Vec::new();
+        if !self.node_endpoints.is_empty() {
+            tags.push((0, CompactArray(self.node_endpoints.clone()).serialize()));
+        }
+        bytes.put(TagBuffer::serialize_fields(&tags));
         bytes.freeze()
     }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn topic_partitions(&self) -> Vec<String> {
+        self.responses
+            .0
+            .iter()
+            .flat_map(|topic| {
+                topic
+                    .partitions
+                    .0
+                    .iter()
+                    .map(move |p| format!("{}-{}", topic.topic_id, p.partition_index))
+            })
+            .collect()
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms)
+            .field("error_code", format_args!("{:?}", self.error_code))
+            .field("session_id", self.session_id)
+            .list("responses", &self.responses.0, |dump, topic| {
+                dump.field("topic_id", &topic.topic_id).list(
+                    "partitions",
+                    &topic.partitions.0,
+                    |dump, p| {
+                        dump.field("partition", p.partition_index)
+                            .field("error_code", format_args!("{:?}", p.error_code))
+                            .field("high_watermark", p.high_watermark)
+                            .field("last_stable_offset", p.last_stable_offset)
+                            .field("log_start_offset", p.log_start_offset)
+                            .field("aborted_transactions", p.aborted_transactions.0.len())
+                            .field("preferred_read_replica", p.preferred_read_replica)
+                            .records("records", &p.records);
+                        if let Some((epoch, end_offset)) = p.diverging_epoch {
+                            dump.field(
+                                "diverging_epoch",
+                                format_args!("{} ending at {}", epoch, end_offset),
+                            );
+                        }
+                        if let Some((leader_id, leader_epoch)) = p.current_leader {
+                            dump.field(
+                                "current_leader",
+                                format_args!("{} at epoch {}", leader_id, leader_epoch),
+                            );
+                        }
+                        if let Some(id) = p.snapshot_id {
+                            dump.field(
+                                "snapshot_id",
+                                format_args!("{} at epoch {}", id.end_offset, id.epoch),
+                            );
+                        }
+                    },
+                );
+            })
+            .list("node_endpoints", &self.node_endpoints, |dump, node| {
+                dump.field("node_id", node.node_id)
+                    .field("address", format_args!("{}:{}", node.host, node.port))
+                    .nullable("rack", node.rack.as_deref());
+            });
+    }
+}
+
+pub struct FetchHandler {
+    /// Only partitions led by this broker are served.
+    node_id: i32,
+    authorizer: Arc<dyn Authorizer>,
+    /// Serves the metadata partition to the other voters, on nodes that are
+    /// part of the quorum.
+    quorum: Option<MetadataQuorum>,
+    /// Hears how far each follower has fetched.
+    isr_manager: Arc<IsrManager>,
+    /// Knows how far the partitions this broker follows are committed, for
+    /// consumers fetching from a follower.
+    replica_fetchers: Arc<ReplicaFetchers>,
+    /// Counts the bytes each partition sends.
+    rates: Arc<PartitionRates>,
+    /// Where fetches wait for `min_bytes`.
+    fetches: Arc<FetchPurgatory>,
+    image: Arc<MetadataImage>,
+    /// Where the partitions this broker leads are read from.
+    log_dir: PathBuf,
+    sessions: FetchSessionCache,
+}
+
+impl FetchHandler {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        node_id: i32,
+        authorizer: Arc<dyn Authorizer>,
+        quorum: Option<MetadataQuorum>,
+        isr_manager: Arc<IsrManager>,
+        replica_fetchers: Arc<ReplicaFetchers>,
+        rates: Arc<PartitionRates>,
+        fetches: Arc<FetchPurgatory>,
+        image: Arc<MetadataImage>,
+        log_dir: PathBuf,
+        fetch_session_cache_slots: usize,
+    ) -> Self {
+        Self {
+            node_id,
+            authorizer,
+            quorum,
+            isr_manager,
+            replica_fetchers,
+            rates,
+            fetches,
+            image,
+            log_dir,
+            sessions: FetchSessionCache::new(fetch_session_cache_slots),
+        }
+    }
+}
+
+impl ApiHandler for FetchHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(
+            ctx,
+            self.node_id,
+            &*self.authorizer,
+            self.quorum.as_ref(),
+            &self.isr_manager,
+            Some(&self.replica_fetchers),
+            Some(&self.rates),
+            Some(&self.fetches),
+            &self.image,
+            &self.log_dir,
+            &self.sessions,
+            body,
+        )?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(error_response(ctx, body, error_code)?))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req: FetchRequestV16 = FetchRequestV16::deserialize(body)?;
+        req.describe(dump);
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
 }
 
-pub fn handle_request(header: HeaderV2, message: &mut Bytes) -> Result<FetchResponseV16> {
-    let req: FetchRequestV16 = FetchRequestV16::deserialize(message);
-    let record_batches = RecordBatches::from_file(CLUSTER_METADATA_LOG_FILE)?;
+/// Fetches waiting for `min_bytes`, keyed by topic name and partition, and
+/// woken by appends to the partitions they read.
+pub type FetchPurgatory = Purgatory<(String, i32)>;
+
+/// The metadata partition is served by the quorum, and is all a controller
+/// listener serves. A fetch in an incremental session fetches every partition
+/// of the session, and is answered only for those with something new.
+///
+/// A fetch of other partitions with `max_wait_ms` and `min_bytes` set waits in
+/// `fetches`, if given, until they hold `min_bytes` between them, one of them
+/// fails, or `max_wait_ms` is up.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_request(
+    ctx: &RequestContext,
+    node_id: i32,
+    authorizer: &dyn Authorizer,
+    quorum: Option<&MetadataQuorum>,
+    isr_manager: &IsrManager,
+    replica_fetchers: Option<&ReplicaFetchers>,
+    rates: Option<&PartitionRates>,
+    fetches: Option<&FetchPurgatory>,
+    image: &MetadataImage,
+    log_dir: &Path,
+    sessions: &FetchSessionCache,
+    message: &mut Bytes,
+) -> Result<FetchResponseV16, Error> {
+    let mut req: FetchRequestV16 =
+        FetchRequestV16::deserialize(message).map_err(|e| Error::Decode(ApiKey::Fetch, e))?;
+    let forgotten = req.forgotten_topics_data.iter().flat_map(|topic| {
+        topic
+            .partitions
+            .iter()
+            .map(|p| (topic.topic_id.0.clone(), *p as i32))
+    });
+    // Beginning the session moves its epoch on, and a follower's fetch moves
+    // the ISR, so neither is done for a request already timed out.
+    if !ctx.commit.commit() {
+        let mut res = FetchResponseV16::new(ctx.header.correlation_id, 0, Vec::new());
+        res.error_code = ErrorCode::RequestTimedOut;
+        return Ok(res);
+    }
+    let session = match sessions.begin(req.session_id, req.session_epoch, &req.topics, forgotten) {
+        Ok(session) => session,
+        Err(error_code) => {
+            let mut res = FetchResponseV16::new(ctx.header.correlation_id, 0, Vec::new());
+            res.error_code = error_code;
+            return Ok(res);
+        }
+    };
+    if let SessionFetch::Incremental { topics, .. } = &session {
+        req.topics = topics.clone();
+    }
+    let (metadata_topics, topics): (Vec<_>, Vec<_>) = std::mem::take(&mut req.topics)
+        .into_iter()
+        .partition(|t| t.topic_id.0 == METADATA_TOPIC_ID);
     let mut responses = vec![];
+    let mut node_endpoints = vec![];
+    for topic_req in metadata_topics {
+        let response = match quorum {
+            Some(quorum) => fetch_metadata(ctx, authorizer, quorum, &req, topic_req),
+            None => TopicResponse::error(topic_req, ErrorCode::UnknownTopicId),
+        };
+        responses.push(response);
+    }
+    if ctx.listener_type == ListenerType::Controller {
+        responses.extend(
+            topics
+                .into_iter()
+                .map(|topic_req| TopicResponse::error(topic_req, ErrorCode::UnknownTopicId)),
+        );
+    } else if !topics.is_empty() {
+        let fetch = || {
+            fetch_topics(
+                ctx,
+                node_id,
+                authorizer,
+                isr_manager,
+                replica_fetchers,
+                image,
+                log_dir,
+                &req,
+                topics.clone(),
+            )
+        };
+        let (topic_responses, endpoints) = match fetches {
+            // A fetch that also asks for the metadata partition was held by
+            // the quorum already.
+            Some(fetches) if req.max_wait_ms > 0 && req.min_bytes > 0 && responses.is_empty() => {
+                let keys = watch_keys(image, &topics);
+                let delayed = DelayedFetch {
+                    fetch,
+                    min_bytes: req.min_bytes as usize,
+                };
+                let max_wait = Duration::from_millis(req.max_wait_ms as u64);
+                // Handlers run on the blocking pool, so waiting holds its
+                // thread, as a voter's fetch held by the quorum does.
+                tokio::runtime::Handle::current()
+                    .block_on(fetches.try_complete_else_watch(
+                        delayed,
+                        &keys,
+                        max_wait,
+                        &ctx.cancellation,
+                    ))
+                    .unwrap_or(Err(Error::Cancelled))?
+            }
+            _ => fetch()?,
+        };
+        if let Some(rates) = rates {
+            record_sent(rates, image, &topic_responses);
+        }
+        responses.extend(topic_responses);
+        node_endpoints = endpoints;
+    }
+
+    let responses = sessions.complete(&session, responses);
+    let mut res = FetchResponseV16::new(ctx.header.correlation_id, session.session_id(), responses);
+    res.node_endpoints = node_endpoints;
+    Ok(res)
+}
+
+/// A fetch that can't answer until its partitions have `min_bytes` between
+/// them, in the manner of Kafka's `DelayedFetch`. Each attempt reads the
+/// partitions again.
+struct DelayedFetch<F> {
+    fetch: F,
+    min_bytes: usize,
+}
+
+type FetchedTopics = (Vec<TopicResponse>, Vec<NodeEndpoint>);
+
+impl<F> DelayedOperation for DelayedFetch<F>
+where
+    F: FnMut() -> Result<FetchedTopics, Error> + Send,
+{
+    type Output = Result<FetchedTopics, Error>;
+
+    /// Done once there are `min_bytes` to send, or a partition has an error
+    /// or a diverging epoch to report, which more appends won't change.
+    fn try_complete(&mut self) -> Option<Self::Output> {
+        let fetched = (self.fetch)();
+        let Ok((responses, _)) = &fetched else {
+            return Some(fetched);
+        };
+        let partitions = || responses.iter().flat_map(|t| t.partitions.0.iter());
+        let bytes: usize = partitions().map(|p| p.records.len()).sum();
+        let final_answer =
+            partitions().any(|p| p.error_code != ErrorCode::None || p.diverging_epoch.is_some());
+        (bytes >= self.min_bytes || final_answer).then_some(fetched)
+    }
+
+    fn on_expiration(mut self) -> Self::Output {
+        (self.fetch)()
+    }
+}
+
+/// The partitions of `topics` a delayed fetch of them waits on.
+fn watch_keys(image: &MetadataImage, topics: &[TopicRequest]) -> Vec<(String, i32)> {
+    let record_batches = image.current();
+    topics
+        .iter()
+        .filter_map(|topic_req| {
+            let name = record_batches
+                .topic(&topic_req.topic_id)?
+                .topic_name
+                .0
+                .clone()?;
+            Some(
+                topic_req
+                    .partitions
+                    .iter()
+                    .map(move |p| (name.clone(), p.partition_index)),
+            )
+        })
+        .flatten()
+        .collect()
+}
+
+/// Counts the record bytes each partition of `responses` sends, once they
+/// are the answer.
+fn record_sent(rates: &PartitionRates, image: &MetadataImage, responses: &[TopicResponse]) {
+    let record_batches = image.current();
+    for topic in responses {
+        let topic_name = record_batches
+            .topic(&topic.topic_id)
+            .and_then(|t| t.topic_name.0.as_deref())
+            .unwrap_or_default();
+        for partition in topic.partitions.0.iter().filter(|p| !p.records.is_empty()) {
+            rates.record(
+                ByteDirection::Out,
+                topic_name,
+                partition.partition_index,
+                partition.records.len(),
+            );
+        }
+    }
+}
+
+/// Partitions this broker doesn't lead are answered NOT_LEADER_OR_FOLLOWER,
+/// with the leader as the metadata log has it and where to reach it on the
+/// listener the request came in on. Consumers may also fetch from an in-sync
+/// follower, up to the high watermark it last heard from its leader, and a
+/// consumer that gives its rack is pointed at a replica in it (KIP-392).
+#[allow(clippy::too_many_arguments)]
+fn fetch_topics(
+    ctx: &RequestContext,
+    node_id: i32,
+    authorizer: &dyn Authorizer,
+    isr_manager: &IsrManager,
+    replica_fetchers: Option<&ReplicaFetchers>,
+    image: &MetadataImage,
+    log_dir: &Path,
+    req: &FetchRequestV16,
+    topics: Vec<TopicRequest>,
+) -> Result<(Vec<TopicResponse>, Vec<NodeEndpoint>), Error> {
+    let record_batches = image.current();
+    let mut responses = vec![];
+    let mut leaders = BTreeSet::new();
+    // A follower copies whole partitions, which takes ClusterAction rather
+    // than Read on each topic.
+    let is_replica = req.replica_id >= 0;
+    let replica_authorized = is_replica
+        && authorizer.authorize(
+            ctx,
+            AclOperation::ClusterAction,
+            ResourceType::Cluster,
+            CLUSTER_RESOURCE_NAME,
+        );
+    let racks: HashMap<i32, &str> = record_batches
+        .live_brokers()
+        .filter_map(|b| Some((b.broker_id, b.rack.as_deref()?)))
+        .collect();
+    let client_rack = match req.rack_id.0.as_deref() {
+        Some(rack) if !rack.is_empty() && !is_replica => Some(rack),
+        _ => None,
+    };
 
-    for topic_req in req.topics {
+    // Partitions are checked here in order, their logs read together below,
+    // and the answers put back in request order.
+    let mut pending = Vec::new();
+    for topic_req in topics {
         let topic_id = topic_req.topic_id.clone();
-        let mut error_code = ErrorCode::UnknownTopicId;
+        let topic = record_batches.topic(&topic_id);
+        let topic_name = topic
+            .and_then(|t| t.topic_name.0.as_deref())
+            .unwrap_or_default();
+        let authorized = match topic {
+            Some(_) if is_replica => replica_authorized,
+            Some(_) => {
+                authorizer.authorize(ctx, AclOperation::Read, ResourceType::Topic, topic_name)
+            }
+            // Unknown topics are answered with UNKNOWN_TOPIC_ID below.
+            None => true,
+        };
+        if !authorized {
+            responses.push(TopicResponse::error(
+                topic_req,
+                ErrorCode::TopicAuthorizationFailed,
+            ));
+            continue;
+        }
         let mut partitions = vec![];
-
         for partition in topic_req.partitions {
             let partition_id = partition.partition_index;
-            let mut partition_record_batches = Vec::new();
-            if let Some(raw_batch) = record_batches
-                .raw_batch_for_topic(&topic_id, partition_id)
-                .context(format!(
-                    "read messages for topic '{}' in partition '{}'",
-                    topic_id, partition_id
-                ))?
-            {
+            let current = record_batches.partition(&topic_id, partition_id);
+            let mut follower_high_watermark = None;
+            let mut preferred_read_replica = -1;
+            if let Some(current) = current {
+                let leads = current.leader_id as i32 == node_id;
+                if !leads && !is_replica && current.in_sync_replicas.contains(&(node_id as u32)) {
+                    follower_high_watermark = replica_fetchers
+                        .and_then(|fetchers| fetchers.state(topic_name, partition_id))
+                        .map(|state| state.leader_high_watermark.max(0));
+                }
+                if let Err(error_code) = check_leader(
+                    node_id,
+                    current,
+                    &partition,
+                    follower_high_watermark.is_some(),
+                ) {
+                    let leader = (current.leader_id as i32, current.leader_epoch as i32);
+                    let mut answer = TopicPartition::error(partition_id, error_code);
+                    answer.current_leader = Some(leader);
+                    if leader.0 >= 0 {
+                        leaders.insert(leader.0);
+                    }
+                    partitions.push(PlannedPartition::Answered(answer));
+                    continue;
+                }
+                if let Some(rack) = client_rack.filter(|_| leads) {
+                    preferred_read_replica = select_read_replica(node_id, current, rack, &racks);
+                }
+            }
+            partitions.push(PlannedPartition::Read(PartitionRead {
+                partition,
+                follower_high_watermark,
+                preferred_read_replica,
+            }));
+        }
+        pending.push((responses.len(), topic_id, partitions));
+        responses.push(TopicResponse::new(topic_req.topic_id.0, Vec::new()));
+    }
+
+    let reads: Vec<(&Uuid, &PartitionRead)> = pending
+        .iter()
+        .flat_map(|(_, topic_id, partitions)| {
+            partitions.iter().filter_map(move |planned| match planned {
+                PlannedPartition::Read(read) => Some((topic_id, read)),
+                PlannedPartition::Answered(_) => None,
+            })
+        })
+        .collect();
+    let mut fetched = read_partitions(
+        &record_batches,
+        log_dir,
+        req.isolation_level,
+        req.max_bytes as usize,
+        &reads,
+        &ctx.cancellation,
+    )
+    .into_iter();
+
+    for (i, topic_id, planned) in pending {
+        let topic_name = record_batches
+            .topic(&topic_id)
+            .and_then(|t| t.topic_name.0.as_deref())
+            .unwrap_or_default();
+        let mut error_code = ErrorCode::UnknownTopicId;
+        let mut partitions = vec![];
+        for planned in planned {
+            let read = match planned {
+                PlannedPartition::Answered(answer) => {
+                    partitions.push(answer);
+                    continue;
+                }
+                PlannedPartition::Read(read) => read,
+            };
+            let partition_id = read.partition.partition_index;
+            // A partition whose log can't be read fails on its own; the
+            // others are still answered.
+            let fetched = match fetched.next().expect("a read per partition") {
+                Ok(fetched) => fetched,
+                Err(Error::Cancelled) => return Err(Error::Cancelled),
+                Err(e) => {
+                    warn!(
+                        topic = %topic_name,
+                        partition = partition_id,
+                        error = %format!("{:#}", e),
+                        "failed to read partition log"
+                    );
+                    partitions.push(TopicPartition::error(
+                        partition_id,
+                        ErrorCode::KafkaStorageError,
+                    ));
+                    continue;
+                }
+            };
+            if let Some(fetched) = &fetched {
                 error_code = ErrorCode::None;
-                partition_record_batches.push(BatchBytes { bytes: raw_batch });
+                if is_replica {
+                    isr_manager.record_fetch(
+                        &topic_id.0,
+                        partition_id,
+                        req.replica_id,
+                        read.partition.fetch_offset,
+                        fetched.log.high_watermark,
+                    );
+                }
             }
-            let partition = TopicPartition {
+            let fetched = fetched.unwrap_or_default();
+            partitions.push(TopicPartition {
                 partition_index: partition_id,
                 error_code,
-                high_watermark: 0,
-                last_stable_offset: 0,
-                log_start_offset: 0,
-                aborted_transactions: CompactArray(Vec::new()),
-                preferred_read_replica: 0,
-                record_batches: CompactArray(partition_record_batches),
-            };
-            partitions.push(partition);
+                high_watermark: fetched.log.high_watermark,
+                last_stable_offset: fetched.log.last_stable_offset,
+                log_start_offset: fetched.log.log_start_offset,
+                aborted_transactions: CompactArray(fetched.aborted_transactions),
+                preferred_read_replica: read.preferred_read_replica,
+                records: fetched.records,
+                diverging_epoch: fetched.diverging_epoch,
+                current_leader: None,
+                snapshot_id: None,
+            });
         }
-        responses.push(TopicResponse::new(topic_req.topic_id.0, partitions));
+        responses[i].partitions = CompactArray(partitions);
     }
+    let node_endpoints = record_batches
+        .brokers()
+        .filter(|b| leaders.contains(&b.broker_id))
+        .filter_map(|b| {
+            let endpoint = b.endpoint(&ctx.listener_name)?;
+            Some(NodeEndpoint {
+                node_id: b.broker_id,
+                host: endpoint.host.clone(),
+                port: endpoint.port as i32,
+                rack: b.rack.clone(),
+            })
+        })
+        .collect();
+    Ok((responses, node_endpoints))
+}
+
+/// A partition of a fetch, either answered from the metadata alone or
+/// waiting on its log.
+enum PlannedPartition {
+    Answered(TopicPartition),
+    Read(PartitionRead),
+}
+
+/// A partition that passed its checks and is read from this broker's log.
+struct PartitionRead {
+    partition: Partition,
+    /// Caps what a consumer fetching from a follower sees.
+    follower_high_watermark: Option<i64>,
+    preferred_read_replica: i32,
+}
+
+/// What a fetch got from a partition's log.
+#[derive(Default)]
+struct FetchedPartition {
+    log: PartitionLog,
+    records: Bytes,
+    aborted_transactions: Vec<AbortedTransaction>,
+    diverging_epoch: Option<(i32, i64)>,
+}
+
+/// A partition's log once its batch headers are read, with the bytes a fetch
+/// of it may send before the request's max bytes are shared out.
+struct ScannedPartition {
+    /// `None` for a partition with no log yet, which reads as empty.
+    segment: Option<Segment>,
+    fetched: FetchedPartition,
+    range: Range<usize>,
+}
 
+/// Reads each partition, up to `MAX_CONCURRENT_READS` at once, returning
+/// them in the order asked. The logs are scanned first, then `max_bytes` is
+/// shared out in that order, so that later partitions are left out once it
+/// is spent, and only then are the records read. The first partition with
+/// records sends at least a batch, however large. Once `cancellation` is set
+/// the partitions not yet started are left unread.
+fn read_partitions(
+    record_batches: &RecordBatches,
+    log_dir: &Path,
+    isolation_level: u8,
+    max_bytes: usize,
+    reads: &[(&Uuid, &PartitionRead)],
+    cancellation: &Cancellation,
+) -> Vec<Result<Option<FetchedPartition>, Error>> {
+    let cancelled = || -> Result<(), Error> {
+        if cancellation.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    };
+    let mut scanned = concurrently(reads.to_vec(), |(topic_id, read)| {
+        cancelled()?;
+        scan_partition(record_batches, log_dir, isolation_level, topic_id, read)
+            .map_err(Error::Storage)
+    });
+    let mut sent = 0;
+    for scanned in scanned.iter_mut().flatten().flatten() {
+        let range = &mut scanned.range;
+        let remaining = max_bytes.saturating_sub(sent);
+        let mut end = scanned.fetched.log.limit(range.start, remaining);
+        if sent > 0 && end - range.start > remaining {
+            end = range.start;
+        }
+        range.end = range.end.min(end);
+        sent += range.len();
+    }
+    concurrently(scanned, |scanned| {
+        let Some(mut scanned) = scanned? else {
+            return Ok(None);
+        };
+        cancelled()?;
+        if let Some(segment) = scanned.segment.as_mut() {
+            if !scanned.range.is_empty() {
+                scanned.fetched.records = segment.read(scanned.range).map_err(Error::Storage)?;
+            }
+        }
+        debug!(bytes = scanned.fetched.records.len(), "read partition log");
+        Ok(Some(scanned.fetched))
+    })
+}
+
+/// Runs `f` on each of `items`, on up to `MAX_CONCURRENT_READS` threads,
+/// returning what it gives in the order of `items`.
+fn concurrently<T: Send, R: Send>(items: Vec<T>, f: impl Fn(T) -> R + Sync) -> Vec<R> {
+    if items.len() <= 1 {
+        return items.into_iter().map(f).collect();
+    }
+    let workers = items.len().min(MAX_CONCURRENT_READS);
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let mut done: Vec<_> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let next = queue.lock().unwrap().next();
+                        let Some((i, item)) = next else {
+                            return done;
+                        };
+                        done.push((i, f(item)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("partition log reader panicked"))
+            .collect()
+    });
+    done.sort_by_key(|(i, _)| *i);
+    done.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Reads the batch headers of `read`'s log, and where a fetch of it starts
+/// and stops: from its fetch offset up to its partition max bytes. `None` if
+/// the topic isn't known. A partition with no log yet is empty.
+fn scan_partition(
+    record_batches: &RecordBatches,
+    log_dir: &Path,
+    isolation_level: u8,
+    topic_id: &Uuid,
+    read: &PartitionRead,
+) -> Result<Option<ScannedPartition>> {
+    let partition = &read.partition;
+    let Some(path) =
+        record_batches.segment_for_topic(log_dir, topic_id, partition.partition_index as u32)
+    else {
+        return Ok(None);
+    };
+    let _span = debug_span!("read_partition_log", path = %path.display()).entered();
+    let mut segment = if path.exists() {
+        Some(Segment::open(&path)?)
+    } else {
+        None
+    };
+    let mut log = match &mut segment {
+        Some(segment) => PartitionLog::scan(segment)?,
+        None => PartitionLog::default(),
+    };
+    let mut end = segment.as_ref().map_or(0, |segment| segment.len);
+    if let Some(high_watermark) = read.follower_high_watermark {
+        log.cap(high_watermark);
+        end = log.position(log.high_watermark);
+    }
+    let mut fetched = FetchedPartition::default();
+    // A fetcher whose log stops matching this one's is told where, to
+    // truncate to it, rather than sent records.
+    if partition.last_fetched_epoch >= 0 {
+        let (epoch, end_offset) = log.epochs.end_offset_for(partition.last_fetched_epoch);
+        if epoch != partition.last_fetched_epoch || partition.fetch_offset > end_offset {
+            fetched.diverging_epoch = Some((epoch, end_offset));
+        }
+    }
+    let mut range = 0..0;
+    // A consumer sent elsewhere gets the offsets but no records.
+    if read.preferred_read_replica < 0 && fetched.diverging_epoch.is_none() {
+        let start = log.position(partition.fetch_offset);
+        if isolation_level == READ_COMMITTED {
+            fetched.aborted_transactions = log.aborted_since(partition.fetch_offset);
+            end = log.stable_bytes;
+        }
+        let limit = log.limit(start, partition.partition_max_bytes.max(0) as usize);
+        range = start..end.min(limit).max(start);
+    }
+    fetched.log = log;
+    Ok(Some(ScannedPartition {
+        segment,
+        fetched,
+        range,
+    }))
+}
+
+/// Whether this broker leads `current`, or may serve it as a follower, in
+/// the epoch the fetcher knows it by. A fetcher that sends no epoch, -1,
+/// isn't checked against it.
+fn check_leader(
+    node_id: i32,
+    current: &PartitionValue,
+    fetch: &Partition,
+    as_follower: bool,
+) -> Result<(), ErrorCode> {
+    if current.leader_id as i32 != node_id && !as_follower {
+        return Err(ErrorCode::NotLeaderOrFollower);
+    }
+    let leader_epoch = current.leader_epoch as i32;
+    if fetch.current_leader_epoch < 0 {
+        Ok(())
+    } else if fetch.current_leader_epoch < leader_epoch {
+        Err(ErrorCode::FencedLeaderEpoch)
+    } else if fetch.current_leader_epoch > leader_epoch {
+        Err(ErrorCode::UnknownLeaderEpoch)
+    } else {
+        Ok(())
+    }
+}
+
+/// The in-sync replica a consumer in `rack` should fetch from instead of
+/// this leader, or -1 if the leader is in its rack or no replica is.
+fn select_read_replica(
+    node_id: i32,
+    current: &PartitionValue,
+    rack: &str,
+    racks: &HashMap<i32, &str>,
+) -> i32 {
+    if racks.get(&node_id) == Some(&rack) {
+        return -1;
+    }
+    current
+        .in_sync_replicas
+        .iter()
+        .map(|replica| *replica as i32)
+        .find(|replica| *replica != node_id && racks.get(replica) == Some(&rack))
+        .unwrap_or(-1)
+}
+
+/// A voter's fetch goes to the quorum, which may hold it until the leader
+/// has something new for it.
+fn fetch_metadata(
+    ctx: &RequestContext,
+    authorizer: &dyn Authorizer,
+    quorum: &MetadataQuorum,
+    req: &FetchRequestV16,
+    topic_req: TopicRequest,
+) -> TopicResponse {
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::ClusterAction,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return TopicResponse::error(topic_req, ErrorCode::ClusterAuthorizationFailed);
+    }
+    if !quorum.is_cluster(req.cluster_id.as_deref()) {
+        return TopicResponse::error(topic_req, ErrorCode::InconsistentClusterId);
+    }
+    let partitions = topic_req
+        .partitions
+        .iter()
+        .map(|p| {
+            if p.partition_index != 0 {
+                return TopicPartition::error(
+                    p.partition_index,
+                    ErrorCode::UnknownTopicOrPartition,
+                );
+            }
+            let result = quorum.fetch(MetadataFetch {
+                replica_id: req.replica_id,
+                current_leader_epoch: p.current_leader_epoch,
+                fetch_offset: p.fetch_offset,
+                last_fetched_epoch: p.last_fetched_epoch,
+                max_wait: Duration::from_millis(req.max_wait_ms as u64),
+                max_bytes: p.partition_max_bytes.max(0) as usize,
+            });
+            TopicPartition {
+                partition_index: p.partition_index,
+                error_code: result.error_code,
+                high_watermark: result.high_watermark,
+                last_stable_offset: result.high_watermark,
+                log_start_offset: result.log_start_offset,
+                aborted_transactions: CompactArray(Vec::new()),
+                preferred_read_replica: -1,
+                records: result.records,
+                diverging_epoch: result.diverging_epoch,
+                current_leader: Some(result.current_leader),
+                snapshot_id: result.snapshot_id,
+            }
+        })
+        .collect();
+    TopicResponse::new(topic_req.topic_id.0, partitions)
+}
+
+/// Answers every requested partition with `error_code`.
+pub fn error_response(
+    ctx: &RequestContext,
+    message: &mut Bytes,
+    error_code: ErrorCode,
+) -> Result<FetchResponseV16, DecodeError> {
+    let req: FetchRequestV16 = FetchRequestV16::deserialize(message)?;
+    let responses = req
+        .topics
+        .into_iter()
+        .map(|topic_req| TopicResponse::error(topic_req, error_code))
+        .collect();
     Ok(FetchResponseV16::new(
-        header.correlation_id,
+        ctx.header.correlation_id,
         req.session_id,
         responses,
     ))
 }
 
+#[derive(Clone)]
 pub struct TopicRequest {
-    topic_id: Uuid,
-    partitions: Vec<Partition>,
+    pub topic_id: Uuid,
+    pub partitions: Vec<Partition>,
+}
+
+impl TopicRequest {
+    pub fn new(topic_id: String, partitions: Vec<Partition>) -> Self {
+        Self {
+            topic_id: Uuid(topic_id),
+            partitions,
+        }
+    }
 }
 
 impl Deserialize<TopicRequest> for FetchRequestV16 {
-    fn deserialize(src: &mut Bytes) -> TopicRequest {
-        let topic_id = Uuid::deserialize(src);
-        let partitions = CompactArray::<TopicRequest>::deserialize(src);
-        TagBuffer::deserialize(src);
-        TopicRequest {
+    fn deserialize(src: &mut Bytes) -> Result<TopicRequest, DecodeError> {
+        let topic_id = Uuid::deserialize(src)?;
+        let partitions = CompactArray::<TopicRequest>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(TopicRequest {
             topic_id,
             partitions,
-        }
+        })
+    }
+}
+
+impl Serialize for TopicRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(self.topic_id.serialize());
+        b.put(CompactArray(self.partitions.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
     }
 }
 
 pub struct TopicResponse {
-    topic_id: Uuid,
-    partitions: CompactArray<TopicPartition>,
+    pub topic_id: Uuid,
+    pub partitions: CompactArray<TopicPartition>,
 }
 
 impl TopicResponse {
@@ -149,6 +1224,28 @@ impl TopicResponse {
             partitions: CompactArray(partitions),
         }
     }
+
+    /// Answers every partition of `topic_req` with `error_code`.
+    fn error(topic_req: TopicRequest, error_code: ErrorCode) -> Self {
+        let partitions = topic_req
+            .partitions
+            .iter()
+            .map(|p| TopicPartition::error(p.partition_index, error_code))
+            .collect();
+        Self::new(topic_req.topic_id.0, partitions)
+    }
+}
+
+impl Deserialize<Self> for TopicResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topic_id = Uuid::deserialize(src)?;
+        let partitions = CompactArray::<TopicPartition>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            topic_id,
+            partitions: CompactArray(partitions),
+        })
+    }
 }
 
 impl Serialize for TopicResponse {
@@ -161,96 +1258,552 @@ impl Serialize for TopicResponse {
     }
 }
 
+/// A leader named in a current leader hint.
+#[derive(Clone)]
+pub struct NodeEndpoint {
+    pub node_id: i32,
+    pub host: String,
+    pub port: i32,
+    pub rack: Option<String>,
+}
+
+impl Serialize for NodeEndpoint {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.node_id);
+        b.put(CompactNullableString(Some(self.host.clone())).serialize());
+        b.put_i32(self.port);
+        b.put(CompactNullableString(self.rack.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for NodeEndpoint {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let endpoint = Self {
+            node_id: src.try_get_i32()?,
+            host: CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default(),
+            port: src.try_get_i32()?,
+            rack: CompactNullableString::deserialize(src)?.0,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(endpoint)
+    }
+}
+
 struct ForgottenTopicData {
     topic_id: Uuid,
     partitions: Vec<u32>, // The partitions indexes to forget.
 }
 
 impl Deserialize<ForgottenTopicData> for FetchRequestV16 {
-    fn deserialize(src: &mut Bytes) -> ForgottenTopicData {
+    fn deserialize(src: &mut Bytes) -> Result<ForgottenTopicData, DecodeError> {
         let forgotten_topic_data = ForgottenTopicData {
-            topic_id: Uuid::deserialize(src),
-            partitions: CompactArray::<ForgottenTopicData>::deserialize(src),
+            topic_id: Uuid::deserialize(src)?,
+            partitions: CompactArray::<ForgottenTopicData>::deserialize(src)?,
         };
-        TagBuffer::deserialize(src);
-        forgotten_topic_data
+        TagBuffer::deserialize_fields(src)?;
+        Ok(forgotten_topic_data)
     }
 }
 
 impl Deserialize<u32> for ForgottenTopicData {
-    fn deserialize(src: &mut Bytes) -> u32 {
-        src.get_u32()
+    fn deserialize(src: &mut Bytes) -> Result<u32, DecodeError> {
+        Ok(src.try_get_u32()?)
     }
 }
 
 pub struct TopicPartition {
-    partition_index: u32,
-    error_code: ErrorCode,
-    high_watermark: i64,
-    last_stable_offset: i64,
-    log_start_offset: i64,
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub high_watermark: i64,
+    pub last_stable_offset: i64,
+    pub log_start_offset: i64,
     aborted_transactions: CompactArray<AbortedTransaction>,
     preferred_read_replica: i32,
-    record_batches: CompactArray<BatchBytes>,
+    /// Whole batches, except that the last may be cut short by the size
+    /// limit.
+    pub records: Bytes,
+    /// The epoch, and where it ends, at which the fetcher's log stops
+    /// matching the leader's, tagged field 0.
+    pub diverging_epoch: Option<(i32, i64)>,
+    /// The leader id and epoch as this replica knows them, tagged field 1.
+    pub current_leader: Option<(i32, i32)>,
+    /// The snapshot to fetch instead when the fetch offset is before the
+    /// log start, tagged field 2.
+    pub snapshot_id: Option<SnapshotId>,
+}
+
+impl TopicPartition {
+    fn error(partition_index: i32, error_code: ErrorCode) -> Self {
+        Self {
+            partition_index,
+            error_code,
+            high_watermark: -1,
+            last_stable_offset: -1,
+            log_start_offset: -1,
+            aborted_transactions: CompactArray(Vec::new()),
+            preferred_read_replica: -1,
+            records: Bytes::new(),
+            diverging_epoch: None,
+            current_leader: None,
+            snapshot_id: None,
+        }
+    }
 }
 
 impl Serialize for TopicPartition {
     fn serialize(&self) -> Bytes {
         let mut b = BytesMut::new();
-        b.put_u32(self.partition_index);
+        b.put_i32(self.partition_index);
         b.put_i16(self.error_code.into());
         b.put_i64(self.high_watermark);
         b.put_i64(self.last_stable_offset);
         b.put_i64(self.log_start_offset);
         b.put(self.aborted_transactions.serialize());
         b.put_i32(self.preferred_read_replica);
-        b.put(self.record_batches.serialize());
-        b.put(TagBuffer::serialize());
+        b.put(CompactBytes(self.records.clone()).serialize());
+        let mut tags = Vec::new();
+        if let Some((epoch, end_offset)) = self.diverging_epoch {
+            let mut value = BytesMut::new();
+            value.put_i32(epoch);
+            value.put_i64(end_offset);
+            value.put(TagBuffer::serialize());
+            tags.push((0, value.freeze()));
+        }
+        if let Some((leader_id, leader_epoch)) = self.current_leader {
+            let mut value = BytesMut::new();
+            value.put_i32(leader_id);
+            value.put_i32(leader_epoch);
+            value.put(TagBuffer::serialize());
+            tags.push((1, value.freeze()));
+        }
+        if let Some(id) = self.snapshot_id {
+            let mut value = BytesMut::new();
+            value.put_i64(id.end_offset);
+            value.put_i32(id.epoch);
+            value.put(TagBuffer::serialize());
+            tags.push((2, value.freeze()));
+        }
+        b.put(TagBuffer::serialize_fields(&tags));
         b.freeze()
     }
 }
 
+impl Deserialize<Self> for TopicPartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let partition_index = src.try_get_i32()?;
+        let error_code = ErrorCode::from(src.try_get_i16()?);
+        let high_watermark = src.try_get_i64()?;
+        let last_stable_offset = src.try_get_i64()?;
+        let log_start_offset = src.try_get_i64()?;
+        let aborted_transactions = CompactArray::<AbortedTransaction>::deserialize(src)?;
+        let preferred_read_replica = src.try_get_i32()?;
+        let records = CompactBytes::deserialize(src)?.0;
+        let mut diverging_epoch = None;
+        let mut current_leader = None;
+        let mut snapshot_id = None;
+        for (tag, mut value) in TagBuffer::deserialize_fields(src)? {
+            match tag {
+                0 => diverging_epoch = Some((value.try_get_i32()?, value.try_get_i64()?)),
+                1 => current_leader = Some((value.try_get_i32()?, value.try_get_i32()?)),
+                2 => {
+                    snapshot_id = Some(SnapshotId {
+                        end_offset: value.try_get_i64()?,
+                        epoch: value.try_get_i32()?,
+                    })
+                }
+                _ => {}
+            }
+        }
+        Ok(Self {
+            partition_index,
+            error_code,
+            high_watermark,
+            last_stable_offset,
+            log_start_offset,
+            aborted_transactions: CompactArray(aborted_transactions),
+            preferred_read_replica,
+            records,
+            diverging_epoch,
+            current_leader,
+            snapshot_id,
+        })
+    }
+}
+
 pub struct AbortedTransaction {
-    producer_id: u64,
-    first_offset: u64,
+    producer_id: i64,
+    first_offset: i64,
+}
+
+impl Deserialize<Self> for AbortedTransaction {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let transaction = Self {
+            producer_id: src.try_get_i64()?,
+            first_offset: src.try_get_i64()?,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(transaction)
+    }
 }
 
 impl Serialize for AbortedTransaction {
     fn serialize(&self) -> Bytes {
-        todo!()
+        let mut b = BytesMut::new();
+        b.put_i64(self.producer_id);
+        b.put_i64(self.first_offset);
+        b.put(TagBuffer::serialize());
+        b.freeze()
     }
 }
 
-pub struct BatchBytes {
-    bytes: Bytes,
+/// A partition's batch headers and transaction markers as they are read,
+/// in log order.
+#[derive(Default)]
+struct LogScan {
+    log_start_offset: Option<i64>,
+    high_watermark: i64,
+    batch_ends: Vec<(i64, usize)>,
+    batch_times: Vec<BatchTime>,
+    /// The first offset of each producer's open transaction.
+    open: HashMap<i64, i64>,
+    aborted: Vec<(AbortedTransaction, i64)>,
+    epochs: LeaderEpochs,
+}
+
+impl LogScan {
+    /// Takes in the next batch; `records` need only be given for a
+    /// transaction marker.
+    fn push(&mut self, header: &BatchHeader, records: &[u8]) {
+        self.epochs.push(header);
+        if header.attributes & TRANSACTIONAL_FLAG != 0 {
+            if !header.is_control() {
+                self.open
+                    .entry(header.producer_id)
+                    .or_insert(header.base_offset);
+            } else if let Some(first_offset) = self.open.remove(&header.producer_id) {
+                if control_type(records) == Some(ABORT_MARKER) {
+                    let transaction = AbortedTransaction {
+                        producer_id: header.producer_id,
+                        first_offset,
+                    };
+                    self.aborted.push((transaction, header.base_offset));
+                }
+            }
+        }
+        self.log_start_offset.get_or_insert(header.base_offset);
+        self.batch_times.push(BatchTime {
+            max_timestamp: header.max_timestamp,
+            leader_epoch: header.partition_leader_epoch,
+        });
+        self.high_watermark = header.last_offset() + 1;
+        let end = self.batch_ends.last().map_or(0, |(_, pos)| *pos) + header.size();
+        self.batch_ends.push((self.high_watermark, end));
+    }
+
+    fn finish(self) -> PartitionLog {
+        let Self {
+            log_start_offset,
+            high_watermark,
+            batch_ends,
+            batch_times,
+            open,
+            mut aborted,
+            epochs,
+        } = self;
+        let last_stable_offset = open.into_values().min().unwrap_or(high_watermark);
+        let stable_bytes = batch_ends
+            .iter()
+            .take_while(|(end, _)| *end <= last_stable_offset)
+            .last()
+            .map_or(0, |(_, pos)| *pos);
+        aborted.retain(|(transaction, _)| transaction.first_offset < last_stable_offset);
+        PartitionLog {
+            log_start_offset: log_start_offset.unwrap_or(high_watermark),
+            high_watermark,
+            last_stable_offset,
+            stable_bytes,
+            aborted,
+            batch_ends,
+            batch_times,
+            epochs,
+        }
+    }
 }
 
-impl Serialize for BatchBytes {
-    fn serialize(&self) -> Bytes {
-        self.bytes.clone()
+/// A partition's first segment, open for reading a range at a time rather
+/// than loaded whole.
+pub(crate) struct Segment {
+    /// Buffered, so that skipping from one batch header to the next
+    /// seldom takes a read.
+    file: BufReader<File>,
+    len: usize,
+}
+
+impl Segment {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("open '{}'", path.display()))?;
+        let len = file.metadata()?.len() as usize;
+        Ok(Self {
+            file: BufReader::new(file),
+            len,
+        })
+    }
+
+    /// The bytes in `range`, read `READ_CHUNK_SIZE` at a time.
+    pub(crate) fn read(&mut self, range: Range<usize>) -> Result<Bytes> {
+        let mut bytes = BytesMut::zeroed(range.len());
+        self.file.seek(SeekFrom::Start(range.start as u64))?;
+        for chunk in bytes.chunks_mut(READ_CHUNK_SIZE) {
+            self.file.read_exact(chunk)?;
+        }
+        Ok(bytes.freeze())
     }
 }
 
+/// What a partition's batch headers and transaction markers say about where
+/// its committed data ends.
+#[derive(Default)]
+pub(crate) struct PartitionLog {
+    /// The first batch's base offset, or the high watermark if the log is
+    /// empty.
+    pub(crate) log_start_offset: i64,
+    pub(crate) high_watermark: i64,
+    /// The first offset of the earliest transaction still open, or the high
+    /// watermark if none is.
+    pub(crate) last_stable_offset: i64,
+    /// How many bytes of the log hold only batches below the last stable
+    /// offset.
+    stable_bytes: usize,
+    /// Aborted transactions that began in the stable part, each with the
+    /// offset of the marker that aborted it.
+    aborted: Vec<(AbortedTransaction, i64)>,
+    /// The offset after each batch, and where the batch ends in the log.
+    batch_ends: Vec<(i64, usize)>,
+    /// Each batch's latest timestamp and epoch, in the same order.
+    batch_times: Vec<BatchTime>,
+    pub(crate) epochs: LeaderEpochs,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct BatchTime {
+    max_timestamp: i64,
+    leader_epoch: i32,
+}
+
+impl PartitionLog {
+    /// Reads only batch headers and transaction markers, not the records in
+    /// between. A torn batch at the end of the log, and anything after it,
+    /// is ignored.
+    pub(crate) fn scan(segment: &mut Segment) -> Result<Self> {
+        let mut scan = LogScan::default();
+        let mut header = [0; RECORDS_OFFSET];
+        let mut pos = 0;
+        segment.file.rewind()?;
+        while pos + RECORDS_OFFSET <= segment.len {
+            segment.file.read_exact(&mut header)?;
+            let Some(parsed) = BatchHeader::parse_fixed(&header) else {
+                break;
+            };
+            if pos + parsed.size() > segment.len {
+                break;
+            }
+            // Only a transaction marker's records are looked at.
+            let mut records = vec![];
+            if parsed.attributes & TRANSACTIONAL_FLAG != 0 && parsed.is_control() {
+                records.resize(parsed.size() - RECORDS_OFFSET, 0);
+                segment.file.read_exact(&mut records)?;
+            } else {
+                segment
+                    .file
+                    .seek_relative((parsed.size() - RECORDS_OFFSET) as i64)?;
+            }
+            scan.push(&parsed, &records);
+            pos += parsed.size();
+        }
+        Ok(scan.finish())
+    }
+
+    /// Where to stop reading from `start` to send no more than `max_bytes`,
+    /// on a batch boundary. The first batch is always sent whole, however
+    /// large, so that a fetcher can get past it.
+    fn limit(&self, start: usize, max_bytes: usize) -> usize {
+        let mut limit = start;
+        for &(_, pos) in self.batch_ends.iter().filter(|(_, pos)| *pos > start) {
+            if limit > start && pos - start > max_bytes {
+                break;
+            }
+            limit = pos;
+        }
+        limit
+    }
+
+    /// Leaves out the batches past `high_watermark`, as a follower must
+    /// with what its leader hasn't yet committed.
+    fn cap(&mut self, high_watermark: i64) {
+        if high_watermark >= self.high_watermark {
+            return;
+        }
+        let end = self.position(high_watermark);
+        self.batch_ends.retain(|(_, pos)| *pos <= end);
+        self.batch_times.truncate(self.batch_ends.len());
+        self.high_watermark = self.batch_ends.last().map_or(0, |(offset, _)| *offset);
+        self.last_stable_offset = self.last_stable_offset.min(self.high_watermark);
+        self.stable_bytes = self.stable_bytes.min(end);
+        let last_stable_offset = self.last_stable_offset;
+        self.aborted
+            .retain(|(transaction, _)| transaction.first_offset < last_stable_offset);
+    }
+
+    /// Where the first batch holding `fetch_offset` or later starts.
+    pub(crate) fn position(&self, fetch_offset: i64) -> usize {
+        self.batch_ends
+            .iter()
+            .take_while(|(end, _)| *end <= fetch_offset)
+            .last()
+            .map_or(0, |(_, pos)| *pos)
+    }
+
+    /// Where the batch holding `offset` ends, or the log if none holds it.
+    pub(crate) fn end_position(&self, offset: i64) -> usize {
+        self.batch_ends
+            .iter()
+            .find(|(end, _)| *end > offset)
+            .or(self.batch_ends.last())
+            .map_or(0, |(_, pos)| *pos)
+    }
+
+    /// Where the first batch with a record at `timestamp` or later lies in
+    /// the log, and its epoch; `None` if every record is older. Timestamps
+    /// are taken to rise along the log, as with CreateTime they usually do.
+    pub(crate) fn batch_at_time(&self, timestamp: i64) -> Option<(Range<usize>, i32)> {
+        let index = self
+            .batch_times
+            .iter()
+            .position(|time| time.max_timestamp >= timestamp)?;
+        Some(self.batch_range(index))
+    }
+
+    /// Where the batch holding the log's latest timestamp lies, the first
+    /// such if several do, and its epoch.
+    pub(crate) fn batch_at_max_time(&self) -> Option<(Range<usize>, i32)> {
+        let max = self
+            .batch_times
+            .iter()
+            .map(|time| time.max_timestamp)
+            .max()?;
+        self.batch_at_time(max)
+    }
+
+    /// The epoch of the first batch.
+    pub(crate) fn first_epoch(&self) -> i32 {
+        self.batch_times
+            .first()
+            .map_or(-1, |time| time.leader_epoch)
+    }
+
+    fn batch_range(&self, index: usize) -> (Range<usize>, i32) {
+        let start = index
+            .checked_sub(1)
+            .map_or(0, |previous| self.batch_ends[previous].1);
+        (
+            start..self.batch_ends[index].1,
+            self.batch_times[index].leader_epoch,
+        )
+    }
+
+    /// The aborted transactions a consumer fetching from `fetch_offset` may
+    /// still run into: those not aborted before it.
+    fn aborted_since(&mut self, fetch_offset: i64) -> Vec<AbortedTransaction> {
+        std::mem::take(&mut self.aborted)
+            .into_iter()
+            .filter(|(_, marker_offset)| *marker_offset >= fetch_offset)
+            .map(|(transaction, _)| transaction)
+            .collect()
+    }
+}
+
+/// The type in the key of a control batch's first record: its length,
+/// attributes, timestamp delta, offset delta and key length come first, and
+/// the key starts with a version.
+fn control_type(records: &[u8]) -> Option<i16> {
+    let mut pos = 0;
+    let varint = |pos: &mut usize| {
+        let (value, read) = i64::decode_var(records.get(*pos..)?)?;
+        *pos += read;
+        Some(value)
+    };
+    varint(&mut pos)?;
+    pos += 1;
+    varint(&mut pos)?;
+    varint(&mut pos)?;
+    varint(&mut pos)?;
+    let key = records.get(pos + 2..pos + 4)?;
+    Some(i16::from_be_bytes(key.try_into().unwrap()))
+}
+
+#[allow(dead_code)]
+#[derive(Clone)]
 pub struct Partition {
-    partition_index: u32,
-    current_leader_epoch: u32,
-    fetch_offset: u64,
-    last_fetched_epoch: u32,
-    log_start_offset: u64,
-    partition_max_bytes: u32,
+    pub partition_index: i32,
+    current_leader_epoch: i32,
+    fetch_offset: i64,
+    /// The epoch of the last batch the fetcher has, to tell whether its log
+    /// still matches; -1 to skip the check.
+    pub last_fetched_epoch: i32,
+    log_start_offset: i64,
+    partition_max_bytes: i32,
+}
+
+impl Partition {
+    /// A partition read from `fetch_offset` by a replica that knows its
+    /// leader epoch as `current_leader_epoch`.
+    pub fn new(
+        partition_index: i32,
+        current_leader_epoch: i32,
+        fetch_offset: i64,
+        partition_max_bytes: i32,
+    ) -> Self {
+        Self {
+            partition_index,
+            current_leader_epoch,
+            fetch_offset,
+            last_fetched_epoch: -1,
+            log_start_offset: -1,
+            partition_max_bytes,
+        }
+    }
 }
 
 impl Deserialize<Partition> for TopicRequest {
-    fn deserialize(src: &mut Bytes) -> Partition {
+    fn deserialize(src: &mut Bytes) -> Result<Partition, DecodeError> {
         let partition = Partition {
-            partition_index: src.get_u32(),
-            current_leader_epoch: src.get_u32(),
-            fetch_offset: src.get_u64(),
-            last_fetched_epoch: src.get_u32(),
-            log_start_offset: src.get_u64(),
-            partition_max_bytes: src.get_u32(),
+            partition_index: src.try_get_i32()?,
+            current_leader_epoch: src.try_get_i32()?,
+            fetch_offset: src.try_get_i64()?,
+            last_fetched_epoch: src.try_get_i32()?,
+            log_start_offset: src.try_get_i64()?,
+            partition_max_bytes: src.try_get_i32()?,
         };
-        TagBuffer::deserialize(src);
-        partition
+        TagBuffer::deserialize_fields(src)?;
+        Ok(partition)
+    }
+}
+
+impl Serialize for Partition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i32(self.current_leader_epoch);
+        b.put_i64(self.fetch_offset);
+        b.put_i32(self.last_fetched_epoch);
+        b.put_i64(self.log_start_offset);
+        b.put_i32(self.partition_max_bytes);
+        b.put(TagBuffer::serialize());
+        b.freeze()
     }
 }
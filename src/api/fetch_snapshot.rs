@@ -0,0 +1,499 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::raft::{MetadataQuorum, SnapshotFetch, SnapshotId, METADATA_TOPIC};
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// FetchSnapshot request, v0: a follower whose fetch offset is before the
+/// leader's log start reading the leader's snapshot a chunk at a time.
+#[derive(Clone)]
+pub struct FetchSnapshotRequest {
+    /// Tagged field 0.
+    pub cluster_id: Option<String>,
+    pub replica_id: i32,
+    pub max_bytes: i32,
+    pub topics: Vec<FetchSnapshotRequestTopic>,
+}
+
+#[derive(Clone)]
+pub struct FetchSnapshotRequestTopic {
+    pub name: String,
+    pub partitions: Vec<FetchSnapshotRequestPartition>,
+}
+
+#[derive(Clone)]
+pub struct FetchSnapshotRequestPartition {
+    pub partition: i32,
+    pub current_leader_epoch: i32,
+    pub snapshot_id: SnapshotId,
+    /// Where in the snapshot to read from.
+    pub position: i64,
+}
+
+impl FetchSnapshotRequest {
+    /// The request a follower sends for the next chunk of the metadata
+    /// partition's snapshot.
+    pub fn metadata(cluster_id: &str, replica_id: i32, fetch: &SnapshotFetch) -> Self {
+        Self {
+            cluster_id: Some(cluster_id.to_string()),
+            replica_id,
+            max_bytes: fetch.max_bytes.min(i32::MAX as usize) as i32,
+            topics: vec![FetchSnapshotRequestTopic {
+                name: METADATA_TOPIC.to_string(),
+                partitions: vec![FetchSnapshotRequestPartition {
+                    partition: 0,
+                    current_leader_epoch: fetch.current_leader_epoch,
+                    snapshot_id: fetch.snapshot_id,
+                    position: fetch.position as i64,
+                }],
+            }],
+        }
+    }
+}
+
+fn serialize_snapshot_id(id: SnapshotId) -> Bytes {
+    let mut b = BytesMut::new();
+    b.put_i64(id.end_offset);
+    b.put_i32(id.epoch);
+    b.put(TagBuffer::serialize());
+    b.freeze()
+}
+
+fn deserialize_snapshot_id(src: &mut Bytes) -> Result<SnapshotId, DecodeError> {
+    let id = SnapshotId {
+        end_offset: src.try_get_i64()?,
+        epoch: src.try_get_i32()?,
+    };
+    TagBuffer::deserialize_fields(src)?;
+    Ok(id)
+}
+
+impl Deserialize<Self> for FetchSnapshotRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let replica_id = src.try_get_i32()?;
+        let max_bytes = src.try_get_i32()?;
+        let topics = CompactArray::<FetchSnapshotRequestTopic>::deserialize(src)?;
+        let mut cluster_id = None;
+        for (tag, mut value) in TagBuffer::deserialize_fields(src)? {
+            if tag == 0 {
+                cluster_id = CompactNullableString::deserialize(&mut value)?.0;
+            }
+        }
+        Ok(Self {
+            cluster_id,
+            replica_id,
+            max_bytes,
+            topics,
+        })
+    }
+}
+
+impl Serialize for FetchSnapshotRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.replica_id);
+        b.put_i32(self.max_bytes);
+        b.put(CompactArray(self.topics.clone()).serialize());
+        let mut tags = Vec::new();
+        if self.cluster_id.is_some() {
+            tags.push((
+                0,
+                CompactNullableString(self.cluster_id.clone()).serialize(),
+            ));
+        }
+        b.put(TagBuffer::serialize_fields(&tags));
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for FetchSnapshotRequestTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let name = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let partitions = CompactArray::<FetchSnapshotRequestPartition>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self { name, partitions })
+    }
+}
+
+impl Serialize for FetchSnapshotRequestTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(CompactArray(self.partitions.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for FetchSnapshotRequestPartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let partition = Self {
+            partition: src.try_get_i32()?,
+            current_leader_epoch: src.try_get_i32()?,
+            snapshot_id: deserialize_snapshot_id(src)?,
+            position: src.try_get_i64()?,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(partition)
+    }
+}
+
+impl Serialize for FetchSnapshotRequestPartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition);
+        b.put_i32(self.current_leader_epoch);
+        b.put(serialize_snapshot_id(self.snapshot_id));
+        b.put_i64(self.position);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// FetchSnapshot response, v0.
+pub struct FetchSnapshotResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub topics: CompactArray<FetchSnapshotResponseTopic>,
+}
+
+pub struct FetchSnapshotResponseTopic {
+    pub name: String,
+    pub partitions: CompactArray<FetchSnapshotResponsePartition>,
+}
+
+pub struct FetchSnapshotResponsePartition {
+    pub index: i32,
+    pub error_code: ErrorCode,
+    pub snapshot_id: SnapshotId,
+    /// The leader id and epoch as this voter knows them, tagged field 0.
+    pub current_leader: Option<(i32, i32)>,
+    /// The size of the whole snapshot.
+    pub size: i64,
+    /// Where in the snapshot `unaligned_records` starts.
+    pub position: i64,
+    /// A chunk of the snapshot, which may end partway through a batch.
+    pub unaligned_records: Bytes,
+}
+
+impl FetchSnapshotResponse {
+    fn new(
+        ctx: &RequestContext,
+        error_code: ErrorCode,
+        topics: Vec<FetchSnapshotResponseTopic>,
+    ) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            topics: CompactArray(topics),
+        }
+    }
+
+    /// The answer for the metadata partition, if the response has one.
+    pub fn metadata_partition(&self) -> Option<&FetchSnapshotResponsePartition> {
+        self.topics
+            .0
+            .iter()
+            .filter(|t| t.name == METADATA_TOPIC)
+            .flat_map(|t| &t.partitions.0)
+            .find(|p| p.index == 0)
+    }
+}
+
+impl FetchSnapshotResponsePartition {
+    fn error(index: i32, error_code: ErrorCode, snapshot_id: SnapshotId) -> Self {
+        Self {
+            index,
+            error_code,
+            snapshot_id,
+            current_leader: None,
+            size: -1,
+            position: -1,
+            unaligned_records: Bytes::new(),
+        }
+    }
+}
+
+impl Response for FetchSnapshotResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(self.topics.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms)
+            .field("error_code", format_args!("{:?}", self.error_code))
+            .list("topics", &self.topics.0, |dump, topic| {
+                dump.field("name", &topic.name).list(
+                    "partitions",
+                    &topic.partitions.0,
+                    |dump, p| {
+                        dump.field("index", p.index)
+                            .field("error_code", format_args!("{:?}", p.error_code))
+                            .field(
+                                "snapshot_id",
+                                format_args!(
+                                    "{} at epoch {}",
+                                    p.snapshot_id.end_offset, p.snapshot_id.epoch
+                                ),
+                            )
+                            .field("size", p.size)
+                            .field("position", p.position)
+                            .field("unaligned_records", p.unaligned_records.len());
+                    },
+                );
+            });
+    }
+}
+
+/// Reads a whole response, header included, as the follower gets it.
+impl Deserialize<Self> for FetchSnapshotResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let error_code = ErrorCode::from(src.try_get_i16()?);
+        let topics = CompactArray::<FetchSnapshotResponseTopic>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            header,
+            throttle_time_ms,
+            error_code,
+            topics: CompactArray(topics),
+        })
+    }
+}
+
+impl Serialize for FetchSnapshotResponseTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(self.partitions.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for FetchSnapshotResponseTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let name = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let partitions = CompactArray::<FetchSnapshotResponsePartition>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            name,
+            partitions: CompactArray(partitions),
+        })
+    }
+}
+
+impl Serialize for FetchSnapshotResponsePartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.index);
+        b.put_i16(self.error_code.into());
+        b.put(serialize_snapshot_id(self.snapshot_id));
+        b.put_i64(self.size);
+        b.put_i64(self.position);
+        b.put(CompactBytes(self.unaligned_records.clone()).serialize());
+        let mut tags = Vec::new();
+        if let Some((leader_id, leader_epoch)) = self.current_leader {
+            let mut value = BytesMut::new();
+            value.put_i32(leader_id);
+            value.put_i32(leader_epoch);
+            value.put(TagBuffer::serialize());
+            tags.push((0, value.freeze()));
+        }
+        b.put(TagBuffer::serialize_fields(&tags));
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for FetchSnapshotResponsePartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let index = src.try_get_i32()?;
+        let error_code = ErrorCode::from(src.try_get_i16()?);
+        let snapshot_id = deserialize_snapshot_id(src)?;
+        let size = src.try_get_i64()?;
+        let position = src.try_get_i64()?;
+        let unaligned_records = CompactBytes::deserialize(src)?.0;
+        let mut current_leader = None;
+        for (tag, mut value) in TagBuffer::deserialize_fields(src)? {
+            if tag == 0 {
+                current_leader = Some((value.try_get_i32()?, value.try_get_i32()?));
+            }
+        }
+        Ok(Self {
+            index,
+            error_code,
+            snapshot_id,
+            current_leader,
+            size,
+            position,
+            unaligned_records,
+        })
+    }
+}
+
+pub struct FetchSnapshotHandler {
+    quorum: MetadataQuorum,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl FetchSnapshotHandler {
+    pub fn new(quorum: MetadataQuorum, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self { quorum, authorizer }
+    }
+}
+
+impl ApiHandler for FetchSnapshotHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.quorum, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(FetchSnapshotResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        _: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = FetchSnapshotRequest::deserialize(body)?;
+        dump.nullable("cluster_id", req.cluster_id.as_deref())
+            .field("replica_id", req.replica_id)
+            .field("max_bytes", req.max_bytes)
+            .list("topics", &req.topics, |dump, topic| {
+                dump.field("name", &topic.name)
+                    .list("partitions", &topic.partitions, |dump, p| {
+                        dump.field("partition", p.partition)
+                            .field("current_leader_epoch", p.current_leader_epoch)
+                            .field(
+                                "snapshot_id",
+                                format_args!(
+                                    "{} at epoch {}",
+                                    p.snapshot_id.end_offset, p.snapshot_id.epoch
+                                ),
+                            )
+                            .field("position", p.position);
+                    });
+            });
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Only the metadata partition has snapshots, served by the quorum leader;
+/// any other partition is UNKNOWN_TOPIC_OR_PARTITION.
+pub fn handle_request(
+    ctx: &RequestContext,
+    quorum: &MetadataQuorum,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<FetchSnapshotResponse, Error> {
+    let req = FetchSnapshotRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::FetchSnapshot, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::ClusterAction,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(FetchSnapshotResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    if !quorum.is_cluster(req.cluster_id.as_deref()) {
+        return Ok(FetchSnapshotResponse::new(
+            ctx,
+            ErrorCode::InconsistentClusterId,
+            Vec::new(),
+        ));
+    }
+    let topics = req
+        .topics
+        .into_iter()
+        .map(|topic| {
+            let partitions = topic
+                .partitions
+                .iter()
+                .map(|p| {
+                    if topic.name != METADATA_TOPIC || p.partition != 0 {
+                        return FetchSnapshotResponsePartition::error(
+                            p.partition,
+                            ErrorCode::UnknownTopicOrPartition,
+                            p.snapshot_id,
+                        );
+                    }
+                    if p.position < 0 {
+                        return FetchSnapshotResponsePartition::error(
+                            p.partition,
+                            ErrorCode::PositionOutOfRange,
+                            p.snapshot_id,
+                        );
+                    }
+                    let result = quorum.fetch_snapshot(SnapshotFetch {
+                        replica_id: req.replica_id,
+                        current_leader_epoch: p.current_leader_epoch,
+                        snapshot_id: p.snapshot_id,
+                        position: p.position as u64,
+                        max_bytes: req.max_bytes.max(0) as usize,
+                    });
+                    FetchSnapshotResponsePartition {
+                        index: p.partition,
+                        error_code: result.error_code,
+                        snapshot_id: p.snapshot_id,
+                        current_leader: Some(result.current_leader),
+                        size: result.size,
+                        position: p.position,
+                        unaligned_records: result.records,
+                    }
+                })
+                .collect();
+            FetchSnapshotResponseTopic {
+                name: topic.name,
+                partitions: CompactArray(partitions),
+            }
+        })
+        .collect();
+    Ok(FetchSnapshotResponse::new(ctx, ErrorCode::None, topics))
+}
@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::config::{Config, SharedConfig};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+/// `key_type` 0: a consumer group.
+pub const KEY_TYPE_GROUP: i8 = 0;
+/// `key_type` 1: a transactional id.
+const KEY_TYPE_TRANSACTION: i8 = 1;
+
+pub struct FindCoordinatorRequest {
+    pub key_type: i8,
+    pub coordinator_keys: Vec<String>,
+}
+
+impl FindCoordinatorRequest {
+    /// v3 asks about one key; v4 batches them.
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let (key_type, coordinator_keys) = if api_version >= 4 {
+            let key_type = src.try_get_i8()?;
+            (key_type, CompactArray::<CoordinatorKey>::deserialize(src)?)
+        } else {
+            let key = CoordinatorKey::deserialize(src)?;
+            (src.try_get_i8()?, vec![key])
+        };
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            key_type,
+            coordinator_keys,
+        })
+    }
+}
+
+/// Written as v4.
+impl Serialize for FindCoordinatorRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i8(self.key_type);
+        b.put(
+            CompactArray(
+                self.coordinator_keys
+                    .iter()
+                    .map(|key| CompactNullableString(Some(key.clone())))
+                    .collect(),
+            )
+            .serialize(),
+        );
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+struct CoordinatorKey;
+
+impl Deserialize<String> for CoordinatorKey {
+    fn deserialize(src: &mut Bytes) -> Result<String, DecodeError> {
+        Ok(CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default())
+    }
+}
+
+pub struct Coordinator {
+    pub key: String,
+    pub node_id: i32,
+    pub host: String,
+    pub port: i32,
+    pub error_code: ErrorCode,
+    pub error_message: CompactNullableString,
+}
+
+impl Coordinator {
+    fn error(key: String, error_code: ErrorCode) -> Self {
+        Self {
+            key,
+            node_id: -1,
+            host: String::new(),
+            port: -1,
+            error_code,
+            error_message: CompactNullableString(None),
+        }
+    }
+}
+
+impl Serialize for Coordinator {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.key.clone())).serialize());
+        b.put_i32(self.node_id);
+        b.put(CompactNullableString(Some(self.host.clone())).serialize());
+        b.put_i32(self.port);
+        b.put_i16(self.error_code.into());
+        b.put(self.error_message.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for Coordinator {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let coordinator = Self {
+            key: CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default(),
+            node_id: src.try_get_i32()?,
+            host: CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default(),
+            port: src.try_get_i32()?,
+            error_code: ErrorCode::from(src.try_get_i16()?),
+            error_message: CompactNullableString::deserialize(src)?,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(coordinator)
+    }
+}
+
+/// FindCoordinator response, v3 and v4.
+pub struct FindCoordinatorResponse {
+    api_version: i16,
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    pub coordinators: CompactArray<Coordinator>,
+}
+
+/// Reads a whole v4 response, header included, as a client gets it.
+impl Deserialize<Self> for FindCoordinatorResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let coordinators = CompactArray::<Coordinator>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            api_version: 4,
+            header,
+            throttle_time_ms,
+            coordinators: CompactArray(coordinators),
+        })
+    }
+}
+
+impl Response for FindCoordinatorResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        if self.api_version >= 4 {
+            bytes.put(self.coordinators.serialize());
+        } else {
+            let coordinator = &self.coordinators.0[0];
+            bytes.put_i16(coordinator.error_code.into());
+            bytes.put(coordinator.error_message.serialize());
+            bytes.put_i32(coordinator.node_id);
+            bytes.put(CompactNullableString(Some(coordinator.host.clone())).serialize());
+            bytes.put_i32(coordinator.port);
+        }
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        match self.coordinators.0.as_slice() {
+            [coordinator] => coordinator.error_code,
+            _ => ErrorCode::None,
+        }
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        for coordinator in &mut self.coordinators.0 {
+            if coordinator.error_code != ErrorCode::None {
+                coordinator.error_message = CompactNullableString(Some(error_message.to_string()));
+            }
+        }
+    }
+}
+
+impl FindCoordinatorResponse {
+    fn new(ctx: &RequestContext, coordinators: Vec<Coordinator>) -> Self {
+        Self {
+            api_version: ctx.header.api_version,
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            coordinators: CompactArray(coordinators),
+        }
+    }
+}
+
+pub struct FindCoordinatorHandler {
+    config: Arc<SharedConfig>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl FindCoordinatorHandler {
+    pub fn new(config: Arc<SharedConfig>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self { config, authorizer }
+    }
+}
+
+impl ApiHandler for FindCoordinatorHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(&self.config.get(), ctx, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = FindCoordinatorRequest::deserialize(body, ctx.header.api_version)?;
+        let coordinators = req
+            .coordinator_keys
+            .into_iter()
+            .map(|key| Coordinator::error(key, error_code))
+            .collect();
+        Ok(Box::new(FindCoordinatorResponse::new(ctx, coordinators)))
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Every group and transactional id is coordinated by this broker, as it
+/// is the only one.
+pub fn handle_request(
+    config: &Config,
+    ctx: &RequestContext,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<FindCoordinatorResponse, Error> {
+    let req = FindCoordinatorRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::FindCoordinator, e))?;
+    let (host, port) = config.advertised_address(&ctx.listener_name, ctx.local_address);
+    let coordinators = req
+        .coordinator_keys
+        .into_iter()
+        .map(|key| {
+            let (resource_type, denied) = match req.key_type {
+                KEY_TYPE_GROUP => (ResourceType::Group, ErrorCode::GroupAuthorizationFailed),
+                KEY_TYPE_TRANSACTION => (
+                    ResourceType::TransactionalId,
+                    ErrorCode::TransactionalIdAuthorizationFailed,
+                ),
+                key_type => {
+                    let mut coordinator = Coordinator::error(key, ErrorCode::InvalidRequest);
+                    coordinator.error_message =
+                        CompactNullableString(Some(format!("Unknown key type {}", key_type)));
+                    return coordinator;
+                }
+            };
+            if !authorizer.authorize(ctx, AclOperation::Describe, resource_type, &key) {
+                return Coordinator::error(key, denied);
+            }
+            Coordinator {
+                key,
+                node_id: config.node_id,
+                host: host.clone(),
+                port: port.into(),
+                error_code: ErrorCode::None,
+                error_message: CompactNullableString(None),
+            }
+        })
+        .collect();
+    Ok(FindCoordinatorResponse::new(ctx, coordinators))
+}
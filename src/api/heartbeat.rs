@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::coordinator::GroupCoordinator;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+pub struct HeartbeatRequest {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+}
+
+impl Deserialize<Self> for HeartbeatRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let group_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let generation_id = src.try_get_i32()?;
+        let member_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let group_instance_id = CompactNullableString::deserialize(src)?.0;
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            group_id,
+            generation_id,
+            member_id,
+            group_instance_id,
+        })
+    }
+}
+
+/// Heartbeat response, v4.
+pub struct HeartbeatResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+}
+
+impl Response for HeartbeatResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+}
+
+impl HeartbeatResponse {
+    fn new(ctx: &RequestContext, error_code: ErrorCode) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+        }
+    }
+}
+
+pub struct HeartbeatHandler {
+    coordinator: GroupCoordinator,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl HeartbeatHandler {
+    pub fn new(coordinator: GroupCoordinator, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            coordinator,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for HeartbeatHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.coordinator, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(HeartbeatResponse::new(ctx, error_code)))
+    }
+}
+
+/// REBALANCE_IN_PROGRESS tells the member to rejoin.
+pub fn handle_request(
+    ctx: &RequestContext,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<HeartbeatResponse, Error> {
+    let req =
+        HeartbeatRequest::deserialize(message).map_err(|e| Error::Decode(ApiKey::Heartbeat, e))?;
+    if !authorizer.authorize(ctx, AclOperation::Read, ResourceType::Group, &req.group_id) {
+        return Ok(HeartbeatResponse::new(
+            ctx,
+            ErrorCode::GroupAuthorizationFailed,
+        ));
+    }
+    let error_code = coordinator.heartbeat(req.group_id, req.member_id, req.generation_id);
+    Ok(HeartbeatResponse::new(ctx, error_code))
+}
@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::coordinator::{TransactionCoordinator, NO_PRODUCER_ID};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// InitProducerId request, v2 to v5.
+pub struct InitProducerIdRequest {
+    pub transactional_id: Option<String>,
+    pub transaction_timeout_ms: i32,
+    /// The producer's current id and epoch, since v3, when it is bumping
+    /// its epoch; -1 otherwise.
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+}
+
+impl InitProducerIdRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let transactional_id = CompactNullableString::deserialize(src)?.0;
+        let transaction_timeout_ms = src.try_get_i32()?;
+        let (producer_id, producer_epoch) = if api_version >= 3 {
+            (src.try_get_i64()?, src.try_get_i16()?)
+        } else {
+            (NO_PRODUCER_ID, -1)
+        };
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            transactional_id,
+            transaction_timeout_ms,
+            producer_id,
+            producer_epoch,
+        })
+    }
+}
+
+/// InitProducerId response, v2 to v5.
+pub struct InitProducerIdResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+    producer_id: i64,
+    producer_epoch: i16,
+}
+
+impl Response for InitProducerIdResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put_i64(self.producer_id);
+        bytes.put_i16(self.producer_epoch);
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+}
+
+impl InitProducerIdResponse {
+    fn new(ctx: &RequestContext, answer: Result<(i64, i16), ErrorCode>) -> Self {
+        let (error_code, (producer_id, producer_epoch)) = match answer {
+            Ok(producer) => (ErrorCode::None, producer),
+            Err(error_code) => (error_code, (NO_PRODUCER_ID, -1)),
+        };
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            producer_id,
+            producer_epoch,
+        }
+    }
+}
+
+pub struct InitProducerIdHandler {
+    transactions: Arc<TransactionCoordinator>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl InitProducerIdHandler {
+    pub fn new(transactions: Arc<TransactionCoordinator>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            transactions,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for InitProducerIdHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.transactions, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(InitProducerIdResponse::new(ctx, Err(error_code))))
+    }
+
+    fn describe_request(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = InitProducerIdRequest::deserialize(body, ctx.header.api_version)?;
+        dump.nullable("transactional_id", req.transactional_id.as_deref())
+            .field("transaction_timeout_ms", req.transaction_timeout_ms)
+            .field("producer_id", req.producer_id)
+            .field("producer_epoch", req.producer_epoch);
+        Ok(())
+    }
+}
+
+/// Takes Write on the transactional id, or IdempotentWrite on the cluster
+/// for a producer without one.
+pub fn handle_request(
+    ctx: &RequestContext,
+    transactions: &TransactionCoordinator,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<InitProducerIdResponse, Error> {
+    let req = InitProducerIdRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::InitProducerId, e))?;
+    let authorized = match req.transactional_id.as_deref() {
+        Some(id) => {
+            authorizer.authorize(ctx, AclOperation::Write, ResourceType::TransactionalId, id)
+        }
+        None => authorizer.authorize(
+            ctx,
+            AclOperation::IdempotentWrite,
+            ResourceType::Cluster,
+            CLUSTER_RESOURCE_NAME,
+        ),
+    };
+    if !authorized {
+        let error_code = if req.transactional_id.is_some() {
+            ErrorCode::TransactionalIdAuthorizationFailed
+        } else {
+            ErrorCode::ClusterAuthorizationFailed
+        };
+        return Ok(InitProducerIdResponse::new(ctx, Err(error_code)));
+    }
+    let answer = transactions.init_producer_id(
+        req.transactional_id.as_deref(),
+        req.transaction_timeout_ms,
+        req.producer_id,
+        req.producer_epoch,
+    );
+    Ok(InitProducerIdResponse::new(ctx, answer))
+}
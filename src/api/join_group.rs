@@ -0,0 +1,220 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tracing::debug;
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::coordinator::{GroupCoordinator, GroupMember, JoinGroup, JoinGroupResult};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+pub struct JoinGroupRequest {
+    pub group_id: String,
+    pub session_timeout_ms: i32,
+    pub rebalance_timeout_ms: i32,
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub protocol_type: String,
+    pub protocols: Vec<JoinGroupRequestProtocol>,
+    pub reason: Option<String>,
+}
+
+impl JoinGroupRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let group_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let session_timeout_ms = src.try_get_i32()?;
+        let rebalance_timeout_ms = src.try_get_i32()?;
+        let member_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let group_instance_id = CompactNullableString::deserialize(src)?.0;
+        let protocol_type = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let protocols = CompactArray::<JoinGroupRequestProtocol>::deserialize(src)?;
+        let reason = if api_version >= 8 {
+            CompactNullableString::deserialize(src)?.0
+        } else {
+            None
+        };
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            group_id,
+            session_timeout_ms,
+            rebalance_timeout_ms,
+            member_id,
+            group_instance_id,
+            protocol_type,
+            protocols,
+            reason,
+        })
+    }
+}
+
+pub struct JoinGroupRequestProtocol {
+    pub name: String,
+    pub metadata: Bytes,
+}
+
+impl Deserialize<Self> for JoinGroupRequestProtocol {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let name = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let metadata = CompactBytes::deserialize(src)?.0;
+        TagBuffer::deserialize(src)?;
+        Ok(Self { name, metadata })
+    }
+}
+
+/// JoinGroup response, v6 to v9.
+pub struct JoinGroupResponse {
+    api_version: i16,
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+    generation_id: i32,
+    protocol_type: CompactNullableString,
+    protocol_name: CompactNullableString,
+    leader: String,
+    skip_assignment: bool,
+    member_id: String,
+    members: CompactArray<JoinGroupResponseMember>,
+}
+
+impl Response for JoinGroupResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put_i32(self.generation_id);
+        if self.api_version >= 7 {
+            bytes.put(self.protocol_type.serialize());
+            bytes.put(self.protocol_name.serialize());
+        } else {
+            // Not nullable before v7.
+            let protocol_name = self.protocol_name.0.clone().unwrap_or_default();
+            bytes.put(CompactNullableString(Some(protocol_name)).serialize());
+        }
+        bytes.put(CompactNullableString(Some(self.leader.clone())).serialize());
+        if self.api_version >= 9 {
+            bytes.put_u8(self.skip_assignment.into());
+        }
+        bytes.put(CompactNullableString(Some(self.member_id.clone())).serialize());
+        bytes.put(self.members.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+}
+
+impl JoinGroupResponse {
+    fn new(ctx: &RequestContext, result: JoinGroupResult) -> Self {
+        Self {
+            api_version: ctx.header.api_version,
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code: result.error_code,
+            generation_id: result.generation_id,
+            protocol_type: CompactNullableString(result.protocol_type),
+            protocol_name: CompactNullableString(result.protocol_name),
+            leader: result.leader,
+            skip_assignment: false,
+            member_id: result.member_id,
+            members: CompactArray(
+                result
+                    .members
+                    .into_iter()
+                    .map(JoinGroupResponseMember)
+                    .collect(),
+            ),
+        }
+    }
+}
+
+pub struct JoinGroupResponseMember(GroupMember);
+
+impl Serialize for JoinGroupResponseMember {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.0.member_id.clone())).serialize());
+        b.put(CompactNullableString(self.0.group_instance_id.clone()).serialize());
+        b.put(CompactBytes(self.0.metadata.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+pub struct JoinGroupHandler {
+    coordinator: GroupCoordinator,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl JoinGroupHandler {
+    pub fn new(coordinator: GroupCoordinator, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            coordinator,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for JoinGroupHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.coordinator, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = JoinGroupRequest::deserialize(body, ctx.header.api_version)?;
+        let result = JoinGroupResult::error(req.member_id, error_code);
+        Ok(Box::new(JoinGroupResponse::new(ctx, result)))
+    }
+}
+
+/// Waits for the group's join phase to complete, which takes up to the
+/// longest rebalance timeout of its members when some are slow to rejoin.
+pub fn handle_request(
+    ctx: &RequestContext,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<JoinGroupResponse, Error> {
+    let req = JoinGroupRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::JoinGroup, e))?;
+    if !authorizer.authorize(ctx, AclOperation::Read, ResourceType::Group, &req.group_id) {
+        let result = JoinGroupResult::error(req.member_id, ErrorCode::GroupAuthorizationFailed);
+        return Ok(JoinGroupResponse::new(ctx, result));
+    }
+    if let Some(reason) = &req.reason {
+        debug!(group = %req.group_id, member = %req.member_id, reason, "member rejoining group");
+    }
+    let result = coordinator.join_group(JoinGroup {
+        group_id: req.group_id,
+        member_id: req.member_id,
+        group_instance_id: req.group_instance_id,
+        client_id: ctx.header.client_id.0.clone().unwrap_or_default(),
+        client_host: ctx.client_address.ip().to_string(),
+        session_timeout: Duration::from_millis(req.session_timeout_ms.max(0) as u64),
+        rebalance_timeout: Duration::from_millis(req.rebalance_timeout_ms.max(0) as u64),
+        protocol_type: req.protocol_type,
+        protocols: req
+            .protocols
+            .into_iter()
+            .map(|protocol| (protocol.name, protocol.metadata))
+            .collect(),
+    });
+    Ok(JoinGroupResponse::new(ctx, result))
+}
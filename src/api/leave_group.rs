@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tracing::debug;
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::coordinator::{GroupCoordinator, LeavingMember};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+pub struct LeaveGroupRequest {
+    pub group_id: String,
+    pub members: Vec<LeaveGroupRequestMember>,
+}
+
+impl LeaveGroupRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let group_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let members = CompactArray::deserialize_with(src, |src| {
+            LeaveGroupRequestMember::deserialize(src, api_version)
+        })?;
+        TagBuffer::deserialize(src)?;
+        Ok(Self { group_id, members })
+    }
+}
+
+pub struct LeaveGroupRequestMember {
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl LeaveGroupRequestMember {
+    fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let member_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let group_instance_id = CompactNullableString::deserialize(src)?.0;
+        let reason = if api_version >= 5 {
+            CompactNullableString::deserialize(src)?.0
+        } else {
+            None
+        };
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            member_id,
+            group_instance_id,
+            reason,
+        })
+    }
+}
+
+/// LeaveGroup response, v4 and v5.
+pub struct LeaveGroupResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+    members: CompactArray<LeaveGroupResponseMember>,
+}
+
+impl Response for LeaveGroupResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(self.members.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+}
+
+impl LeaveGroupResponse {
+    fn new(
+        ctx: &RequestContext,
+        error_code: ErrorCode,
+        members: Vec<LeaveGroupResponseMember>,
+    ) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            members: CompactArray(members),
+        }
+    }
+}
+
+pub struct LeaveGroupResponseMember {
+    member_id: String,
+    group_instance_id: Option<String>,
+    error_code: ErrorCode,
+}
+
+impl Serialize for LeaveGroupResponseMember {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.member_id.clone())).serialize());
+        b.put(CompactNullableString(self.group_instance_id.clone()).serialize());
+        b.put_i16(self.error_code.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+pub struct LeaveGroupHandler {
+    coordinator: GroupCoordinator,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl LeaveGroupHandler {
+    pub fn new(coordinator: GroupCoordinator, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            coordinator,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for LeaveGroupHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.coordinator, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(LeaveGroupResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+}
+
+pub fn handle_request(
+    ctx: &RequestContext,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<LeaveGroupResponse, Error> {
+    let req = LeaveGroupRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::LeaveGroup, e))?;
+    if !authorizer.authorize(ctx, AclOperation::Read, ResourceType::Group, &req.group_id) {
+        return Ok(LeaveGroupResponse::new(
+            ctx,
+            ErrorCode::GroupAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    for member in &req.members {
+        if let Some(reason) = &member.reason {
+            debug!(group = %req.group_id, member = %member.member_id, reason, "member leaving group");
+        }
+    }
+    let leaving = req
+        .members
+        .iter()
+        .map(|member| LeavingMember {
+            member_id: member.member_id.clone(),
+            group_instance_id: member.group_instance_id.clone(),
+        })
+        .collect();
+    let error_codes = coordinator.leave_group(req.group_id, leaving);
+    let members = req
+        .members
+        .into_iter()
+        .zip(error_codes)
+        .map(|(member, error_code)| LeaveGroupResponseMember {
+            member_id: member.member_id,
+            group_instance_id: member.group_instance_id,
+            error_code,
+        })
+        .collect();
+    Ok(LeaveGroupResponse::new(ctx, ErrorCode::None, members))
+}
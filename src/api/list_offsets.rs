@@ -0,0 +1,528 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tracing::warn;
+
+use crate::api::fetch::{PartitionLog, Segment};
+use crate::api::produce::PartitionLocks;
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::cluster_metadata::PartitionValue;
+use crate::error::Error;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::record_batch::decode_batches;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// The timestamp that asks for the log start offset.
+pub const EARLIEST_TIMESTAMP: i64 = -2;
+/// The timestamp that asks for the offset the next record will get.
+pub const LATEST_TIMESTAMP: i64 = -1;
+/// The timestamp that asks for the record with the latest timestamp.
+pub const MAX_TIMESTAMP: i64 = -3;
+/// `isolation_level` for reading only committed transactions.
+const READ_COMMITTED: i8 = 1;
+/// The `replica_id` of a client, as opposed to a follower.
+pub const CONSUMER_REPLICA_ID: i32 = -1;
+
+/// ListOffsets request, v6 and v7, which share a layout.
+pub struct ListOffsetsRequest {
+    pub replica_id: i32,
+    pub isolation_level: i8,
+    pub topics: Vec<ListOffsetsTopic>,
+}
+
+#[derive(Clone)]
+pub struct ListOffsetsTopic {
+    pub name: String,
+    pub partitions: Vec<ListOffsetsPartition>,
+}
+
+#[derive(Clone)]
+pub struct ListOffsetsPartition {
+    pub partition_index: i32,
+    /// -1 to skip checking the leader's epoch.
+    pub current_leader_epoch: i32,
+    /// The time to find the first offset at or after, or one of the
+    /// special timestamps.
+    pub timestamp: i64,
+}
+
+impl Deserialize<Self> for ListOffsetsRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let replica_id = src.try_get_i32()?;
+        let isolation_level = src.try_get_i8()?;
+        let topics = CompactArray::deserialize_with(src, |src| {
+            let name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let partitions = CompactArray::deserialize_with(src, |src| {
+                let partition = ListOffsetsPartition {
+                    partition_index: src.try_get_i32()?,
+                    current_leader_epoch: src.try_get_i32()?,
+                    timestamp: src.try_get_i64()?,
+                };
+                TagBuffer::deserialize_fields(src)?;
+                Ok(partition)
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(ListOffsetsTopic { name, partitions })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            replica_id,
+            isolation_level,
+            topics,
+        })
+    }
+}
+
+impl Serialize for ListOffsetsRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.replica_id);
+        b.put_i8(self.isolation_level);
+        b.put(CompactArray(self.topics.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for ListOffsetsTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(CompactArray(self.partitions.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for ListOffsetsPartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i32(self.current_leader_epoch);
+        b.put_i64(self.timestamp);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// ListOffsets response, v6 and v7.
+pub struct ListOffsetsResponse {
+    header: HeaderV1,
+    pub throttle_time_ms: i32,
+    pub topics: Vec<ListOffsetsTopicResponse>,
+}
+
+#[derive(Clone)]
+pub struct ListOffsetsTopicResponse {
+    pub name: String,
+    pub partitions: Vec<ListOffsetsPartitionResponse>,
+}
+
+#[derive(Clone)]
+pub struct ListOffsetsPartitionResponse {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub timestamp: i64,
+    pub offset: i64,
+    pub leader_epoch: i32,
+}
+
+/// Reads a whole response, header included, as a client gets it.
+impl Deserialize<Self> for ListOffsetsResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let topics = CompactArray::deserialize_with(src, |src| {
+            let name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let partitions = CompactArray::deserialize_with(src, |src| {
+                let partition = ListOffsetsPartitionResponse {
+                    partition_index: src.try_get_i32()?,
+                    error_code: ErrorCode::from(src.try_get_i16()?),
+                    timestamp: src.try_get_i64()?,
+                    offset: src.try_get_i64()?,
+                    leader_epoch: src.try_get_i32()?,
+                };
+                TagBuffer::deserialize_fields(src)?;
+                Ok(partition)
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(ListOffsetsTopicResponse { name, partitions })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            header,
+            throttle_time_ms,
+            topics,
+        })
+    }
+}
+
+impl ListOffsetsResponse {
+    pub fn new(ctx: &RequestContext, topics: Vec<ListOffsetsTopicResponse>) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            topics,
+        }
+    }
+}
+
+impl Serialize for ListOffsetsTopicResponse {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(CompactArray(self.partitions.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for ListOffsetsPartitionResponse {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i16(self.error_code.into());
+        b.put_i64(self.timestamp);
+        b.put_i64(self.offset);
+        b.put_i32(self.leader_epoch);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl ListOffsetsPartitionResponse {
+    fn error(partition_index: i32, error_code: ErrorCode) -> Self {
+        Self {
+            partition_index,
+            error_code,
+            timestamp: -1,
+            offset: -1,
+            leader_epoch: -1,
+        }
+    }
+}
+
+impl Response for ListOffsetsResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(CompactArray(self.topics.clone()).serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn topic_partitions(&self) -> Vec<String> {
+        self.topics
+            .iter()
+            .flat_map(|topic| {
+                topic
+                    .partitions
+                    .iter()
+                    .map(move |p| format!("{}-{}", topic.name, p.partition_index))
+            })
+            .collect()
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms).list(
+            "topics",
+            &self.topics,
+            |dump, topic| {
+                dump.field("name", &topic.name)
+                    .list("partitions", &topic.partitions, |dump, p| {
+                        dump.field("partition_index", p.partition_index)
+                            .field("error_code", format_args!("{:?}", p.error_code))
+                            .field("timestamp", p.timestamp)
+                            .field("offset", p.offset)
+                            .field("leader_epoch", p.leader_epoch);
+                    });
+            },
+        );
+    }
+}
+
+/// Looks up offsets by time in the partitions this broker leads.
+pub struct ListOffsetsHandler {
+    node_id: i32,
+    authorizer: Arc<dyn Authorizer>,
+    image: Arc<MetadataImage>,
+    log_dir: PathBuf,
+    /// Where Produce keeps the log offsets of led partitions.
+    appends: Arc<PartitionLocks>,
+}
+
+impl ListOffsetsHandler {
+    pub fn new(
+        node_id: i32,
+        authorizer: Arc<dyn Authorizer>,
+        image: Arc<MetadataImage>,
+        log_dir: PathBuf,
+        appends: Arc<PartitionLocks>,
+    ) -> Self {
+        Self {
+            node_id,
+            authorizer,
+            image,
+            log_dir,
+            appends,
+        }
+    }
+}
+
+impl ApiHandler for ListOffsetsHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(
+            ctx,
+            self.node_id,
+            &*self.authorizer,
+            &self.image,
+            &self.log_dir,
+            &self.appends,
+            body,
+        )?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = ListOffsetsRequest::deserialize(body)?;
+        let topics = req
+            .topics
+            .iter()
+            .map(|topic| ListOffsetsTopicResponse {
+                name: topic.name.clone(),
+                partitions: topic
+                    .partitions
+                    .iter()
+                    .map(|p| ListOffsetsPartitionResponse::error(p.partition_index, error_code))
+                    .collect(),
+            })
+            .collect();
+        Ok(Box::new(ListOffsetsResponse::new(ctx, topics)))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = ListOffsetsRequest::deserialize(body)?;
+        dump.field("replica_id", req.replica_id)
+            .field("isolation_level", req.isolation_level)
+            .list("topics", &req.topics, |dump, topic| {
+                dump.field("name", &topic.name)
+                    .list("partitions", &topic.partitions, |dump, p| {
+                        dump.field("partition_index", p.partition_index)
+                            .field("current_leader_epoch", p.current_leader_epoch)
+                            .field("timestamp", p.timestamp);
+                    });
+            });
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes Describe on each topic. Only the leader answers a consumer; a
+/// follower may ask any replica. The latest offset is the high watermark,
+/// or the last stable offset when reading committed. A timestamp is looked
+/// up by the batches' latest timestamps, then the records of the batch
+/// found; one past every record answers offset -1. The earliest and latest
+/// offsets of a led partition come from the offsets Produce keeps for it,
+/// so only a timestamp lookup reads the log. A partition whose log can't be
+/// read answers KAFKA_STORAGE_ERROR.
+pub fn handle_request(
+    ctx: &RequestContext,
+    node_id: i32,
+    authorizer: &dyn Authorizer,
+    image: &MetadataImage,
+    log_dir: &Path,
+    appends: &PartitionLocks,
+    message: &mut Bytes,
+) -> Result<ListOffsetsResponse, Error> {
+    let req = ListOffsetsRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::ListOffsets, e))?;
+    let record_batches = image.current();
+    let mut topics = Vec::new();
+    for topic in &req.topics {
+        let authorized = authorizer.authorize(
+            ctx,
+            AclOperation::Describe,
+            ResourceType::Topic,
+            &topic.name,
+        );
+        let topic_id = record_batches
+            .topic_by_name(&topic.name)
+            .map(|t| t.topic_id.clone());
+        let mut partitions = Vec::new();
+        for lookup in &topic.partitions {
+            let index = lookup.partition_index;
+            let partition = topic_id
+                .as_ref()
+                .and_then(|id| Some((id, record_batches.partition(id, index)?)));
+            let answer = match partition {
+                _ if !authorized => {
+                    ListOffsetsPartitionResponse::error(index, ErrorCode::TopicAuthorizationFailed)
+                }
+                None => {
+                    ListOffsetsPartitionResponse::error(index, ErrorCode::UnknownTopicOrPartition)
+                }
+                Some((topic_id, current)) => {
+                    match check_leader(node_id, req.replica_id, current, lookup) {
+                        Err(error_code) => ListOffsetsPartitionResponse::error(index, error_code),
+                        Ok(()) => {
+                            let segment =
+                                record_batches.segment_for_topic(log_dir, topic_id, index as u32);
+                            let kept = segment
+                                .as_deref()
+                                .filter(|_| current.leader_id as i32 == node_id)
+                                .map(|segment| {
+                                    (appends, segment, topic_id, current.leader_epoch as i32)
+                                });
+                            list_offset(kept, segment.as_deref(), req.isolation_level, lookup)
+                                .unwrap_or_else(|e| {
+                                    warn!(
+                                        topic = %topic.name,
+                                        partition = index,
+                                        error = %format!("{:#}", e),
+                                        "failed to list offsets"
+                                    );
+                                    ListOffsetsPartitionResponse::error(
+                                        index,
+                                        ErrorCode::KafkaStorageError,
+                                    )
+                                })
+                        }
+                    }
+                }
+            };
+            partitions.push(answer);
+        }
+        topics.push(ListOffsetsTopicResponse {
+            name: topic.name.clone(),
+            partitions,
+        });
+    }
+    Ok(ListOffsetsResponse::new(ctx, topics))
+}
+
+/// Whether this broker may answer for `current` in the epoch the client
+/// knows it by; -1 isn't checked.
+fn check_leader(
+    node_id: i32,
+    replica_id: i32,
+    current: &PartitionValue,
+    lookup: &ListOffsetsPartition,
+) -> Result<(), ErrorCode> {
+    if replica_id == CONSUMER_REPLICA_ID && current.leader_id as i32 != node_id {
+        return Err(ErrorCode::NotLeaderOrFollower);
+    }
+    let leader_epoch = current.leader_epoch as i32;
+    if lookup.current_leader_epoch < 0 {
+        Ok(())
+    } else if lookup.current_leader_epoch < leader_epoch {
+        Err(ErrorCode::FencedLeaderEpoch)
+    } else if lookup.current_leader_epoch > leader_epoch {
+        Err(ErrorCode::UnknownLeaderEpoch)
+    } else {
+        Ok(())
+    }
+}
+
+/// A led partition's segment, with its topic id and leader epoch, and the
+/// locks holding its offsets.
+type KeptOffsets<'a> = (&'a PartitionLocks, &'a Path, &'a Uuid, i32);
+
+/// Answers `lookup` from the partition log in `segment`. A partition with
+/// no log yet is empty. The earliest and latest offsets of a `kept`
+/// partition are answered from its kept offsets instead.
+fn list_offset(
+    kept: Option<KeptOffsets>,
+    segment: Option<&Path>,
+    isolation_level: i8,
+    lookup: &ListOffsetsPartition,
+) -> Result<ListOffsetsPartitionResponse> {
+    let index = lookup.partition_index;
+    let found = |offset, leader_epoch| ListOffsetsPartitionResponse {
+        offset,
+        leader_epoch,
+        ..ListOffsetsPartitionResponse::error(index, ErrorCode::None)
+    };
+    if let Some((appends, segment, topic_id, leader_epoch)) =
+        kept.filter(|(_, segment, _, _)| segment.exists())
+    {
+        match lookup.timestamp {
+            EARLIEST_TIMESTAMP => {
+                let offsets = appends.offsets(segment, topic_id, leader_epoch)?;
+                return Ok(found(offsets.log_start_offset, offsets.first_epoch));
+            }
+            LATEST_TIMESTAMP if isolation_level != READ_COMMITTED => {
+                let offsets = appends.offsets(segment, topic_id, leader_epoch)?;
+                return Ok(found(offsets.log_end_offset, offsets.last_epoch));
+            }
+            _ => {}
+        }
+    }
+    let mut segment = match segment.filter(|path| path.exists()) {
+        Some(path) => Some(Segment::open(path)?),
+        None => None,
+    };
+    let log = match &mut segment {
+        Some(segment) => PartitionLog::scan(segment)?,
+        None => PartitionLog::default(),
+    };
+    Ok(match lookup.timestamp {
+        EARLIEST_TIMESTAMP => found(log.log_start_offset, log.first_epoch()),
+        LATEST_TIMESTAMP if isolation_level == READ_COMMITTED => {
+            found(log.last_stable_offset, log.epochs.last_epoch())
+        }
+        LATEST_TIMESTAMP => found(log.high_watermark, log.epochs.last_epoch()),
+        MAX_TIMESTAMP | 0.. => {
+            let batch = match lookup.timestamp {
+                MAX_TIMESTAMP => log.batch_at_max_time(),
+                timestamp => log.batch_at_time(timestamp),
+            };
+            let (Some(segment), Some((range, leader_epoch))) = (&mut segment, batch) else {
+                return Ok(found(-1, -1));
+            };
+            let records = decode_batches(segment.read(range)?)?;
+            let target = match lookup.timestamp {
+                MAX_TIMESTAMP => records.iter().map(|r| r.timestamp).max(),
+                timestamp => Some(timestamp),
+            };
+            match records
+                .iter()
+                .find(|r| target.is_some_and(|target| r.timestamp >= target))
+            {
+                Some(record) => ListOffsetsPartitionResponse {
+                    timestamp: record.timestamp,
+                    ..found(record.offset, leader_epoch)
+                },
+                None => found(-1, -1),
+            }
+        }
+        _ => ListOffsetsPartitionResponse::error(index, ErrorCode::InvalidRequest),
+    })
+}
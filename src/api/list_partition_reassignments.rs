@@ -0,0 +1,230 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::controller::Controller;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+/// ListPartitionReassignments request, v0.
+pub struct ListPartitionReassignmentsRequest {
+    pub timeout_ms: i32,
+    /// `None` asks for every partition being reassigned.
+    pub topics: Option<Vec<ListPartitionReassignmentsTopic>>,
+}
+
+pub struct ListPartitionReassignmentsTopic {
+    pub name: String,
+    pub partition_indexes: Vec<i32>,
+}
+
+impl Deserialize<Self> for ListPartitionReassignmentsRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let timeout_ms = src.try_get_i32()?;
+        let topics = CompactArray::deserialize_nullable_with(src, |src| {
+            let topic = ListPartitionReassignmentsTopic {
+                name: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                partition_indexes: CompactArray::deserialize_with(src, |src| {
+                    Ok(src.try_get_i32()?)
+                })?,
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(topic)
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self { timeout_ms, topics })
+    }
+}
+
+/// ListPartitionReassignments response, v0.
+pub struct ListPartitionReassignmentsResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+    error_message: Option<String>,
+    topics: CompactArray<OngoingTopicReassignment>,
+}
+
+pub struct OngoingTopicReassignment {
+    pub name: String,
+    pub partitions: CompactArray<OngoingPartitionReassignment>,
+}
+
+pub struct OngoingPartitionReassignment {
+    pub partition_index: u32,
+    pub replicas: Vec<u32>,
+    pub adding_replicas: Vec<u32>,
+    pub removing_replicas: Vec<u32>,
+}
+
+impl Serialize for OngoingTopicReassignment {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(self.partitions.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for OngoingPartitionReassignment {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_u32(self.partition_index);
+        b.put(CompactArray(self.replicas.clone()).serialize());
+        b.put(CompactArray(self.adding_replicas.clone()).serialize());
+        b.put(CompactArray(self.removing_replicas.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl ListPartitionReassignmentsResponse {
+    fn new(
+        ctx: &RequestContext,
+        error_code: ErrorCode,
+        topics: Vec<OngoingTopicReassignment>,
+    ) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            error_message: None,
+            topics: CompactArray(topics),
+        }
+    }
+}
+
+impl Response for ListPartitionReassignmentsResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(CompactNullableString(self.error_message.clone()).serialize());
+        bytes.put(self.topics.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        self.error_message = Some(error_message.to_string());
+    }
+}
+
+/// Served by nodes that are also controllers, from the metadata log as the
+/// active controller has it.
+pub struct ListPartitionReassignmentsHandler {
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl ListPartitionReassignmentsHandler {
+    pub fn new(controller: Arc<Controller>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for ListPartitionReassignmentsHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.controller, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(ListPartitionReassignmentsResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes Describe on the cluster. Partitions not being reassigned, or that
+/// don't exist, are left out.
+pub fn handle_request(
+    ctx: &RequestContext,
+    controller: &Controller,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<ListPartitionReassignmentsResponse, Error> {
+    let req = ListPartitionReassignmentsRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::ListPartitionReassignments, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Describe,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(ListPartitionReassignmentsResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    let metadata = match controller.metadata() {
+        Ok(metadata) => metadata,
+        Err(error_code) => {
+            return Ok(ListPartitionReassignmentsResponse::new(
+                ctx,
+                error_code,
+                Vec::new(),
+            ))
+        }
+    };
+    let wanted = |name: &str, partition: u32| match &req.topics {
+        None => true,
+        Some(topics) => topics
+            .iter()
+            .any(|t| t.name == name && t.partition_indexes.contains(&(partition as i32))),
+    };
+    let topics = metadata
+        .topics()
+        .filter_map(|topic| {
+            let name = topic.topic_name.0.clone().unwrap_or_default();
+            let partitions: Vec<OngoingPartitionReassignment> = metadata
+                .partitions(&topic.topic_id)
+                .filter(|p| p.is_reassigning() && wanted(&name, p.partition_id))
+                .map(|p| OngoingPartitionReassignment {
+                    partition_index: p.partition_id,
+                    replicas: p.replicas.clone(),
+                    adding_replicas: p.adding_replicas.clone(),
+                    removing_replicas: p.removing_replicas.clone(),
+                })
+                .collect();
+            (!partitions.is_empty()).then_some(OngoingTopicReassignment {
+                name,
+                partitions: CompactArray(partitions),
+            })
+        })
+        .collect();
+    Ok(ListPartitionReassignmentsResponse::new(
+        ctx,
+        ErrorCode::None,
+        topics,
+    ))
+}
@@ -0,0 +1,660 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use integer_encoding::*;
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::cluster_metadata::RecordBatches;
+use crate::config::{Config, SharedConfig};
+use crate::controller::{is_valid_topic_name, Controller, NewTopic};
+use crate::error::Error;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+const ZERO_UUID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// Metadata request, flexible versions 9 through 12.
+pub struct MetadataRequest {
+    /// `None` asks for every topic.
+    pub topics: Option<Vec<MetadataRequestTopic>>,
+    pub allow_auto_topic_creation: bool,
+    pub include_cluster_authorized_operations: bool,
+    pub include_topic_authorized_operations: bool,
+}
+
+pub struct MetadataRequestTopic {
+    pub topic_id: Uuid,
+    pub name: CompactNullableString,
+}
+
+impl MetadataRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let (len, read) = u64::decode_var(src).expect("Failed to decode length");
+        src.advance(read);
+        let topics = if len == 0 {
+            None
+        } else {
+            let mut topics = Vec::with_capacity(len as usize - 1);
+            for _ in 1..len {
+                let topic_id = if api_version >= 10 {
+                    Uuid::deserialize(src)?
+                } else {
+                    Uuid(ZERO_UUID.to_string())
+                };
+                let name = CompactNullableString::deserialize(src)?;
+                TagBuffer::deserialize(src)?;
+                topics.push(MetadataRequestTopic { topic_id, name });
+            }
+            Some(topics)
+        };
+        let allow_auto_topic_creation = src.try_get_u8()? != 0;
+        let include_cluster_authorized_operations = api_version <= 10 && src.try_get_u8()? != 0;
+        let include_topic_authorized_operations = src.try_get_u8()? != 0;
+        TagBuffer::deserialize(src)?;
+
+        Ok(Self {
+            topics,
+            allow_auto_topic_creation,
+            include_cluster_authorized_operations,
+            include_topic_authorized_operations,
+        })
+    }
+}
+
+/// Written as v12, which names topics by name or id.
+impl Serialize for MetadataRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        match &self.topics {
+            Some(topics) => {
+                b.put_slice(&((topics.len() + 1) as u64).encode_var_vec());
+                for topic in topics {
+                    b.put(topic.topic_id.serialize());
+                    b.put(topic.name.serialize());
+                    b.put(TagBuffer::serialize());
+                }
+            }
+            None => b.put_u8(0),
+        }
+        b.put_u8(self.allow_auto_topic_creation.into());
+        b.put_u8(self.include_topic_authorized_operations.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl MetadataRequestTopic {
+    /// A topic asked for by name.
+    pub fn named(name: &str) -> Self {
+        Self {
+            topic_id: Uuid(ZERO_UUID.to_string()),
+            name: CompactNullableString(Some(name.to_string())),
+        }
+    }
+}
+
+pub struct MetadataResponse {
+    api_version: i16,
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    pub brokers: CompactArray<MetadataBroker>,
+    pub cluster_id: CompactNullableString,
+    pub controller_id: i32,
+    pub topics: CompactArray<MetadataTopic>,
+    cluster_authorized_operations: i32,
+}
+
+impl Response for MetadataResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(self.brokers.serialize());
+        bytes.put(self.cluster_id.serialize());
+        bytes.put_i32(self.controller_id);
+        bytes.put(self.topics.serialize());
+        if self.api_version <= 10 {
+            bytes.put_i32(self.cluster_authorized_operations);
+        }
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn topic_partitions(&self) -> Vec<String> {
+        self.topics
+            .0
+            .iter()
+            .map(|topic| match &topic.name.0 {
+                Some(name) => name.clone(),
+                None => topic.topic_id.to_string(),
+            })
+            .collect()
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms)
+            .list("brokers", &self.brokers.0, |dump, broker| {
+                dump.field("node_id", broker.node_id)
+                    .field("address", format_args!("{}:{}", broker.host, broker.port))
+                    .string("rack", &broker.rack);
+            })
+            .string("cluster_id", &self.cluster_id)
+            .field("controller_id", self.controller_id)
+            .list("topics", &self.topics.0, |dump, topic| {
+                dump.field("error_code", format_args!("{:?}", topic.error_code))
+                    .string("name", &topic.name)
+                    .field("topic_id", &topic.topic_id)
+                    .field("is_internal", topic.is_internal)
+                    .list("partitions", &topic.partitions.0, |dump, p| {
+                        dump.field("error_code", format_args!("{:?}", p.error_code))
+                            .field("partition", p.partition_index)
+                            .field("leader_id", p.leader_id as i32)
+                            .field("leader_epoch", p.leader_epoch as i32)
+                            .field("replicas", format_args!("{:?}", p.replica_nodes.0))
+                            .field("isr", format_args!("{:?}", p.isr_nodes.0))
+                            .field(
+                                "offline_replicas",
+                                format_args!("{:?}", p.offline_replicas.0),
+                            );
+                    });
+            });
+    }
+}
+
+/// Reads a whole v12 response, header included, as a client gets it.
+impl Deserialize<Self> for MetadataResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let api_version = 12;
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let brokers = CompactArray::deserialize_with(src, |src| {
+            let broker = MetadataBroker {
+                node_id: src.try_get_i32()?,
+                host: CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default(),
+                port: src.try_get_i32()?,
+                rack: CompactNullableString::deserialize(src)?,
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(broker)
+        })?;
+        let cluster_id = CompactNullableString::deserialize(src)?;
+        let controller_id = src.try_get_i32()?;
+        let topics = CompactArray::deserialize_with(src, |src| {
+            let error_code = ErrorCode::from(src.try_get_i16()?);
+            let name = CompactNullableString::deserialize(src)?;
+            let topic_id = Uuid::deserialize(src)?;
+            let is_internal = src.try_get_u8()? != 0;
+            let partitions = CompactArray::deserialize_with(src, |src| {
+                let partition = MetadataPartition {
+                    error_code: ErrorCode::from(src.try_get_i16()?),
+                    partition_index: src.try_get_u32()?,
+                    leader_id: src.try_get_u32()?,
+                    leader_epoch: src.try_get_u32()?,
+                    replica_nodes: CompactArray(CompactArray::deserialize_with(src, |src| {
+                        Ok(src.try_get_u32()?)
+                    })?),
+                    isr_nodes: CompactArray(CompactArray::deserialize_with(src, |src| {
+                        Ok(src.try_get_u32()?)
+                    })?),
+                    offline_replicas: CompactArray(CompactArray::deserialize_with(src, |src| {
+                        Ok(src.try_get_u32()?)
+                    })?),
+                };
+                TagBuffer::deserialize_fields(src)?;
+                Ok(partition)
+            })?;
+            let topic_authorized_operations = src.try_get_i32()?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(MetadataTopic {
+                api_version,
+                error_code,
+                name,
+                topic_id,
+                is_internal,
+                partitions: CompactArray(partitions),
+                topic_authorized_operations,
+            })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            api_version,
+            header,
+            throttle_time_ms,
+            brokers: CompactArray(brokers),
+            cluster_id,
+            controller_id,
+            topics: CompactArray(topics),
+            cluster_authorized_operations: i32::MIN,
+        })
+    }
+}
+
+pub struct MetadataBroker {
+    pub node_id: i32,
+    pub host: String,
+    pub port: i32,
+    pub rack: CompactNullableString,
+}
+
+impl MetadataBroker {
+    /// This broker as seen by a client on the request's listener.
+    pub fn local(config: &Config, ctx: &RequestContext) -> Self {
+        let (host, port) = config.advertised_address(&ctx.listener_name, ctx.local_address);
+        Self {
+            node_id: config.node_id,
+            host,
+            port: port.into(),
+            rack: CompactNullableString(config.rack.clone()),
+        }
+    }
+}
+
+impl MetadataBroker {
+    /// Every live broker reachable on the request's listener, this one
+    /// first, by id. This broker is always listed, registered or not, as it
+    /// is the one answering.
+    pub fn all(config: &Config, ctx: &RequestContext, metadata: &RecordBatches) -> Vec<Self> {
+        let local = Self::local(config, ctx);
+        let mut brokers = Vec::new();
+        for broker in metadata.live_brokers() {
+            if broker.broker_id == config.node_id {
+                continue;
+            }
+            // Brokers not listening on this listener can't be reached by
+            // the client, so they are left out.
+            if let Some(endpoint) = broker.endpoint(&ctx.listener_name) {
+                brokers.push(Self {
+                    node_id: broker.broker_id,
+                    host: endpoint.host.clone(),
+                    port: endpoint.port.into(),
+                    rack: CompactNullableString(broker.rack.clone()),
+                });
+            }
+        }
+        brokers.push(local);
+        brokers.sort_by_key(|b| b.node_id);
+        brokers
+    }
+}
+
+impl Serialize for MetadataBroker {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.node_id);
+        b.put(CompactNullableString(Some(self.host.clone())).serialize());
+        b.put_i32(self.port);
+        b.put(self.rack.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+pub struct MetadataTopic {
+    api_version: i16,
+    pub error_code: ErrorCode,
+    pub name: CompactNullableString,
+    pub topic_id: Uuid,
+    pub is_internal: bool,
+    pub partitions: CompactArray<MetadataPartition>,
+    topic_authorized_operations: i32,
+}
+
+impl Serialize for MetadataTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i16(self.error_code.into());
+        b.put(self.name.serialize());
+        if self.api_version >= 10 {
+            b.put(self.topic_id.serialize());
+        }
+        b.put_u8(self.is_internal.into());
+        b.put(self.partitions.serialize());
+        b.put_i32(self.topic_authorized_operations);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl MetadataTopic {
+    fn unknown(
+        api_version: i16,
+        name: CompactNullableString,
+        topic_id: Uuid,
+        error_code: ErrorCode,
+    ) -> Self {
+        Self {
+            api_version,
+            error_code,
+            name,
+            topic_id,
+            is_internal: false,
+            partitions: CompactArray(Vec::new()),
+            topic_authorized_operations: i32::MIN,
+        }
+    }
+}
+
+pub struct MetadataPartition {
+    pub error_code: ErrorCode,
+    pub partition_index: u32,
+    /// u32::MAX, i.e. -1, when the partition has no leader.
+    pub leader_id: u32,
+    pub leader_epoch: u32,
+    pub replica_nodes: CompactArray<u32>,
+    pub isr_nodes: CompactArray<u32>,
+    pub offline_replicas: CompactArray<u32>,
+}
+
+impl Serialize for MetadataPartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i16(self.error_code.into());
+        b.put_u32(self.partition_index);
+        b.put_u32(self.leader_id);
+        b.put_u32(self.leader_epoch);
+        b.put(self.replica_nodes.serialize());
+        b.put(self.isr_nodes.serialize());
+        b.put(self.offline_replicas.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+pub struct MetadataHandler {
+    config: Arc<SharedConfig>,
+    cluster_id: String,
+    authorizer: Arc<dyn Authorizer>,
+    /// Creates the topics requests ask for that don't exist, on nodes that
+    /// are part of the quorum.
+    controller: Option<Arc<Controller>>,
+    image: Arc<MetadataImage>,
+}
+
+impl MetadataHandler {
+    pub fn new(
+        config: Arc<SharedConfig>,
+        cluster_id: String,
+        authorizer: Arc<dyn Authorizer>,
+        controller: Option<Arc<Controller>>,
+        image: Arc<MetadataImage>,
+    ) -> Self {
+        Self {
+            config,
+            cluster_id,
+            authorizer,
+            controller,
+            image,
+        }
+    }
+}
+
+impl ApiHandler for MetadataHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(
+            &self.config.get(),
+            &self.cluster_id,
+            ctx,
+            &*self.authorizer,
+            self.controller.as_deref(),
+            &self.image,
+            body,
+        )?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(error_response(
+            &self.config.get(),
+            ctx,
+            body,
+            error_code,
+        )?))
+    }
+
+    fn describe_request(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = MetadataRequest::deserialize(body, ctx.header.api_version)?;
+        match &req.topics {
+            Some(topics) => dump.list("topics", topics, |dump, topic| {
+                dump.field("topic_id", &topic.topic_id)
+                    .string("name", &topic.name);
+            }),
+            None => dump.field("topics", "all"),
+        };
+        dump.field("allow_auto_topic_creation", req.allow_auto_topic_creation)
+            .field(
+                "include_cluster_authorized_operations",
+                req.include_cluster_authorized_operations,
+            )
+            .field(
+                "include_topic_authorized_operations",
+                req.include_topic_authorized_operations,
+            );
+        Ok(())
+    }
+
+    /// Topics are auto-created only once the request is committed.
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// A topic named in the request that doesn't exist is created if the
+/// request, the broker and the principal's ACLs allow it, and answered
+/// LEADER_NOT_AVAILABLE so the client asks again once it has partitions.
+pub fn handle_request(
+    config: &Config,
+    cluster_id: &str,
+    ctx: &RequestContext,
+    authorizer: &dyn Authorizer,
+    controller: Option<&Controller>,
+    image: &MetadataImage,
+    message: &mut Bytes,
+) -> Result<MetadataResponse, Error> {
+    let api_version = ctx.header.api_version;
+    let req = MetadataRequest::deserialize(message, api_version)
+        .map_err(|e| Error::Decode(ApiKey::Metadata, e))?;
+    let record_batches = image.current();
+    let topic_authorized_operations = if req.include_topic_authorized_operations {
+        0x0DF
+    } else {
+        i32::MIN
+    };
+
+    let may_describe = |name: &CompactNullableString| {
+        authorizer.authorize(
+            ctx,
+            AclOperation::Describe,
+            ResourceType::Topic,
+            name.0.as_deref().unwrap_or_default(),
+        )
+    };
+
+    let auto_create = req.allow_auto_topic_creation && config.auto_create_topics;
+    let missing_topic = |name: &str| {
+        if !is_valid_topic_name(name) {
+            return ErrorCode::InvalidTopicException;
+        }
+        // Only the controller can create topics, so brokers outside the
+        // quorum don't.
+        let Some(controller) = controller.filter(|_| auto_create) else {
+            return ErrorCode::UnknownTopicOrPartition;
+        };
+        let may_create =
+            authorizer.authorize(
+                ctx,
+                AclOperation::Create,
+                ResourceType::Cluster,
+                CLUSTER_RESOURCE_NAME,
+            ) || authorizer.authorize(ctx, AclOperation::Create, ResourceType::Topic, name);
+        if !may_create {
+            return ErrorCode::TopicAuthorizationFailed;
+        }
+        let topic = NewTopic {
+            name: name.to_string(),
+            num_partitions: config.num_partitions,
+            replication_factor: config.default_replication_factor,
+            assignments: Vec::new(),
+            configs: BTreeMap::new(),
+        };
+        if !ctx.commit.commit() {
+            return ErrorCode::RequestTimedOut;
+        }
+        match controller.create_topic(&topic, false) {
+            // Another request may have created it first.
+            Ok(_) | Err(ErrorCode::TopicAlreadyExists) => ErrorCode::LeaderNotAvailable,
+            Err(error_code) => error_code,
+        }
+    };
+
+    let brokers = MetadataBroker::all(config, ctx, &record_batches);
+    let is_live = |id: u32| brokers.iter().any(|b| b.node_id == id as i32);
+
+    let known_topic = |topic_id: &Uuid, name: &CompactNullableString| {
+        let partitions = record_batches
+            .partitions(topic_id)
+            .map(|p| {
+                // A leader the client can't reach, or no leader at all, is
+                // reported as unavailable so the client retries later.
+                let (error_code, leader_id) = if is_live(p.leader_id) {
+                    (ErrorCode::None, p.leader_id)
+                } else {
+                    (ErrorCode::LeaderNotAvailable, u32::MAX)
+                };
+                MetadataPartition {
+                    error_code,
+                    partition_index: p.partition_id,
+                    leader_id,
+                    leader_epoch: p.leader_epoch,
+                    replica_nodes: CompactArray(p.replicas.clone()),
+                    isr_nodes: CompactArray(p.in_sync_replicas.clone()),
+                    offline_replicas: CompactArray(
+                        p.replicas
+                            .iter()
+                            .copied()
+                            .filter(|&r| !is_live(r))
+                            .collect(),
+                    ),
+                }
+            })
+            .collect();
+        MetadataTopic {
+            api_version,
+            error_code: ErrorCode::None,
+            name: name.clone(),
+            topic_id: topic_id.clone(),
+            is_internal: false,
+            partitions: CompactArray(partitions),
+            topic_authorized_operations,
+        }
+    };
+
+    let topics: Vec<MetadataTopic> = match req.topics {
+        // Topics the principal may not describe are left out silently.
+        None => record_batches
+            .topics()
+            .filter(|t| may_describe(&t.topic_name))
+            .map(|t| known_topic(&t.topic_id, &t.topic_name))
+            .collect(),
+        Some(requested) => requested
+            .into_iter()
+            .map(|requested| {
+                let by_name = requested.name.0.is_some();
+                let found = match requested.name.0.as_deref() {
+                    Some(name) => record_batches.topic_by_name(name),
+                    None => record_batches.topic(&requested.topic_id),
+                };
+                // Checked before existence, so that a denied principal cannot
+                // tell whether a topic exists.
+                if by_name && !may_describe(&requested.name) {
+                    return MetadataTopic::unknown(
+                        api_version,
+                        requested.name,
+                        Uuid(ZERO_UUID.to_string()),
+                        ErrorCode::TopicAuthorizationFailed,
+                    );
+                }
+                match found {
+                    Some(t) if !may_describe(&t.topic_name) => MetadataTopic::unknown(
+                        api_version,
+                        CompactNullableString(None),
+                        requested.topic_id,
+                        ErrorCode::TopicAuthorizationFailed,
+                    ),
+                    Some(t) => known_topic(&t.topic_id, &t.topic_name),
+                    None if by_name => {
+                        let error_code = missing_topic(requested.name.0.as_deref().unwrap());
+                        MetadataTopic::unknown(
+                            api_version,
+                            requested.name,
+                            Uuid(ZERO_UUID.to_string()),
+                            error_code,
+                        )
+                    }
+                    None => MetadataTopic::unknown(
+                        api_version,
+                        requested.name,
+                        requested.topic_id,
+                        ErrorCode::UnknownTopicId,
+                    ),
+                }
+            })
+            .collect(),
+    };
+
+    Ok(MetadataResponse {
+        api_version,
+        header: HeaderV1::new(ctx.header.correlation_id),
+        throttle_time_ms: 0,
+        brokers: CompactArray(brokers),
+        cluster_id: CompactNullableString(Some(cluster_id.to_string())),
+        controller_id: config.node_id,
+        topics: CompactArray(topics),
+        cluster_authorized_operations: if req.include_cluster_authorized_operations {
+            0
+        } else {
+            i32::MIN
+        },
+    })
+}
+
+/// Answers every requested topic with `error_code`.
+pub fn error_response(
+    config: &Config,
+    ctx: &RequestContext,
+    message: &mut Bytes,
+    error_code: ErrorCode,
+) -> Result<MetadataResponse, DecodeError> {
+    let api_version = ctx.header.api_version;
+    let req = MetadataRequest::deserialize(message, api_version)?;
+    let topics: Vec<MetadataTopic> = req
+        .topics
+        .unwrap_or_default()
+        .into_iter()
+        .map(|t| MetadataTopic::unknown(api_version, t.name, t.topic_id, error_code))
+        .collect();
+    Ok(MetadataResponse {
+        api_version,
+        header: HeaderV1::new(ctx.header.correlation_id),
+        throttle_time_ms: 0,
+        brokers: CompactArray(Vec::new()),
+        cluster_id: CompactNullableString(None),
+        controller_id: config.node_id,
+        topics: CompactArray(topics),
+        cluster_authorized_operations: i32::MIN,
+    })
+}
@@ -0,0 +1,362 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use bytes::Bytes;
+use tracing::{debug, info, warn};
+
+use crate::api::ApiHandler;
+use crate::audit::{AuditLog, AuditRecord};
+use crate::config::SharedConfig;
+use crate::error::Error;
+use crate::metrics::Metrics;
+use crate::protocol::*;
+use crate::quota::{ClientQuotaManager, QuotaType};
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A request on its way through the middleware to its handler.
+pub struct Request {
+    pub api_key: ApiKey,
+    pub ctx: Arc<RequestContext>,
+    /// The body after the header, not yet decoded.
+    pub body: Bytes,
+    /// When the request was taken off the connection.
+    pub received: Instant,
+}
+
+/// A response together with its encoding, which is what goes on the wire.
+pub struct Reply {
+    pub response: Box<dyn Response>,
+    pub bytes: Bytes,
+}
+
+impl Reply {
+    pub fn new(response: Box<dyn Response>) -> Self {
+        let bytes = response.as_bytes();
+        Self { response, bytes }
+    }
+}
+
+/// A cross-cutting concern wrapped around every handler: it may answer the
+/// request itself, or pass it on with `next` and inspect or change what comes
+/// back.
+///
+/// Authorization of the resources a request names stays in the handlers, as
+/// only they know which resources those are.
+pub trait Middleware: Send + Sync {
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Reply, Error>>;
+}
+
+/// The rest of the chain below a layer.
+pub struct Next<'a> {
+    pub(crate) layers: &'a [Box<dyn Middleware>],
+    pub(crate) handler: &'a dyn ApiHandler,
+    /// Runs the handler once the innermost layer awaits it.
+    pub(crate) handled: BoxFuture<'a, Result<Box<dyn Response>, Error>>,
+}
+
+impl<'a> Next<'a> {
+    pub fn run(self, request: &'a Request) -> BoxFuture<'a, Result<Reply, Error>> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.call(request, Next { layers, ..self }),
+            None => Box::pin(async move { Ok(Reply::new(self.handled.await?)) }),
+        }
+    }
+
+    /// The request's handler, for answering it with an error instead.
+    pub fn handler(&self) -> &'a dyn ApiHandler {
+        self.handler
+    }
+}
+
+/// Answers REQUEST_TIMED_OUT when the rest of the chain takes longer than
+/// `request.timeout.ms`.
+pub struct TimeoutLayer {
+    config: Arc<SharedConfig>,
+}
+
+impl TimeoutLayer {
+    pub fn new(config: Arc<SharedConfig>) -> Self {
+        Self { config }
+    }
+}
+
+impl Middleware for TimeoutLayer {
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Reply, Error>> {
+        Box::pin(async move {
+            let timeout = self.config.get().request_timeout;
+            let handler = next.handler();
+            if !handler.may_time_out() {
+                return next.run(request).await;
+            }
+            let mut run = next.run(request);
+            match tokio::time::timeout(timeout, &mut run).await {
+                Ok(res) => res,
+                // The handler has started making changes, so the client is
+                // told what they were rather than to retry.
+                Err(_) if !request.ctx.commit.time_out() => {
+                    warn!(timeout = ?timeout, "request timed out while committing, waiting for it");
+                    run.await
+                }
+                Err(_) => {
+                    // A handler on the blocking pool can't be interrupted; its
+                    // result is dropped whenever it does finish, and it makes
+                    // no changes once `time_out` has settled it.
+                    warn!(timeout = ?timeout, "request timed out");
+                    let mut response = handler
+                        .error_response(
+                            &request.ctx,
+                            &mut request.body.clone(),
+                            ErrorCode::RequestTimedOut,
+                        )
+                        .map_err(|e| Error::Decode(request.api_key, e))?;
+                    response.set_error_message(&format!("request timed out after {:?}", timeout));
+                    Ok(Reply::new(response))
+                }
+            }
+        })
+    }
+}
+
+/// Delays responses to clients over their byte-rate quota for `api_key`,
+/// reporting the delay in the response's throttle time. Produce quotas are
+/// measured on the request, Fetch quotas on the response.
+pub struct ThrottleLayer {
+    api_key: ApiKey,
+    quotas: Arc<ClientQuotaManager>,
+}
+
+impl ThrottleLayer {
+    pub fn new(api_key: ApiKey, quotas: Arc<ClientQuotaManager>) -> Self {
+        Self { api_key, quotas }
+    }
+}
+
+impl Middleware for ThrottleLayer {
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Reply, Error>> {
+        Box::pin(async move {
+            let mut reply = next.run(request).await?;
+            if request.api_key != self.api_key {
+                return Ok(reply);
+            }
+            let client_id = request
+                .ctx
+                .header
+                .client_id
+                .0
+                .as_deref()
+                .unwrap_or_default();
+            let user = &request.ctx.principal.name;
+            let bytes = match self.quotas.quota_type() {
+                QuotaType::Produce => request.body.len(),
+                QuotaType::Fetch => reply.bytes.len(),
+            };
+            let throttle = self.quotas.record(user, client_id, bytes);
+            if !throttle.is_zero() {
+                reply
+                    .response
+                    .set_throttle_time_ms(throttle.as_millis() as i32);
+                reply.bytes = reply.response.as_bytes();
+                debug!(throttle = ?throttle, "delaying response for quota violation");
+                tokio::time::sleep(throttle).await;
+            }
+            Ok(reply)
+        })
+    }
+}
+
+/// Topics listed in a slow-request warning before the rest are only counted.
+const SLOW_REQUEST_MAX_TOPICS: usize = 20;
+
+/// Records every request's outcome, latency and size, and warns about those
+/// slower than `request.slow.threshold.ms`.
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+    config: Arc<SharedConfig>,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<Metrics>, config: Arc<SharedConfig>) -> Self {
+        Self { metrics, config }
+    }
+
+    fn log_slow_request(&self, request: &Request, reply: &Reply, latency: Duration) {
+        let topic_partitions = reply.response.topic_partitions();
+        let mut topics = topic_partitions
+            .iter()
+            .take(SLOW_REQUEST_MAX_TOPICS)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(",");
+        if topic_partitions.len() > SLOW_REQUEST_MAX_TOPICS {
+            topics += &format!(
+                " (+{} more)",
+                topic_partitions.len() - SLOW_REQUEST_MAX_TOPICS
+            );
+        }
+        warn!(
+            api_key = ?request.api_key,
+            client_id = request.ctx.header.client_id.0.as_deref().unwrap_or_default(),
+            principal = %request.ctx.principal,
+            latency_ms = latency.as_millis() as u64,
+            request_bytes = request.body.len(),
+            response_bytes = reply.bytes.len(),
+            topics,
+            "slow request"
+        );
+    }
+}
+
+impl Middleware for MetricsLayer {
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Reply, Error>> {
+        Box::pin(async move {
+            let res = next.run(request).await;
+            let latency = request.received.elapsed();
+            let latency_us = latency.as_micros() as u64;
+            let (error_code, response_bytes) = match &res {
+                Ok(reply) => {
+                    let error_code = reply.response.error_code();
+                    debug!(latency_us, error_code = ?error_code, "request completed");
+                    let slow_threshold = self.config.get().slow_request_threshold;
+                    if slow_threshold.is_some_and(|threshold| latency >= threshold) {
+                        self.log_slow_request(request, reply, latency);
+                    }
+                    (error_code, reply.bytes.len())
+                }
+                Err(e) => {
+                    warn!(latency_us, error = %e, "request failed");
+                    (e.error_code(), 0)
+                }
+            };
+            self.metrics.record_request(
+                request.api_key,
+                error_code,
+                latency,
+                request.body.len(),
+                response_bytes,
+            );
+            res
+        })
+    }
+}
+
+/// Writes every request to the audit journal.
+pub struct AuditLayer {
+    audit_log: AuditLog,
+}
+
+impl AuditLayer {
+    pub fn new(audit_log: AuditLog) -> Self {
+        Self { audit_log }
+    }
+}
+
+impl Middleware for AuditLayer {
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Reply, Error>> {
+        Box::pin(async move {
+            let res = next.run(request).await;
+            let ctx = &request.ctx;
+            self.audit_log.record(&AuditRecord {
+                timestamp: SystemTime::now(),
+                api_key: request.api_key,
+                api_version: ctx.header.api_version,
+                correlation_id: ctx.header.correlation_id,
+                client_id: ctx.header.client_id.0.clone().unwrap_or_default(),
+                principal: ctx.principal.clone(),
+                client_address: ctx.client_address,
+                error_code: match &res {
+                    Ok(reply) => reply.response.error_code(),
+                    Err(e) => e.error_code(),
+                },
+                latency: request.received.elapsed(),
+            });
+            res
+        })
+    }
+}
+
+/// Logs every request and its response decoded field by field while
+/// `log.wire.debug` is on.
+pub struct WireDebugLayer {
+    config: Arc<SharedConfig>,
+}
+
+impl WireDebugLayer {
+    pub fn new(config: Arc<SharedConfig>) -> Self {
+        Self { config }
+    }
+}
+
+impl Middleware for WireDebugLayer {
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Reply, Error>> {
+        Box::pin(async move {
+            if !self.config.get().log_wire_debug {
+                return next.run(request).await;
+            }
+            let handler = next.handler();
+            // A body too malformed to describe is the handler's to reject.
+            let mut dump = wire_dump(request, request.body.len());
+            if handler
+                .describe_request(&request.ctx, &mut request.body.clone(), &mut dump)
+                .is_err()
+            {
+                dump = wire_dump(request, request.body.len());
+                dump.field("body", "malformed");
+            }
+            info!("request\n{}", dump);
+
+            let res = next.run(request).await;
+            if let Ok(reply) = &res {
+                let mut dump = wire_dump(request, reply.bytes.len());
+                reply.response.describe(&mut dump);
+                info!("response\n{}", dump);
+            }
+            res
+        })
+    }
+}
+
+/// The dump of a request or response to `request`, started with what the
+/// request header says.
+fn wire_dump(request: &Request, size: usize) -> WireDump {
+    let header = &request.ctx.header;
+    let mut dump = WireDump::default();
+    dump.field(
+        "api",
+        format_args!("{:?} v{}", request.api_key, header.api_version),
+    )
+    .field("correlation_id", header.correlation_id)
+    .nullable("client_id", header.client_id.0.as_deref())
+    .field("size", format_args!("{} bytes", size));
+    dump
+}
@@ -1,4 +1,55 @@
+pub mod add_partitions_to_txn;
+pub mod add_raft_voter;
+pub mod alter_client_quotas;
+pub mod alter_configs;
+pub mod alter_partition;
+pub mod alter_partition_reassignments;
 pub mod api_versions;
+pub mod assign_replicas_to_dirs;
+pub mod begin_quorum_epoch;
+pub mod broker_heartbeat;
+pub mod broker_registration;
 pub mod cluster_metadata;
+pub mod consumer_group_heartbeat;
+pub mod create_delegation_token;
+pub mod create_partitions;
+pub mod create_topics;
+pub mod delete_topics;
+pub mod describe_client_quotas;
+pub mod describe_cluster;
+pub mod describe_configs;
+pub mod describe_delegation_token;
+pub mod describe_log_dirs;
+pub mod describe_quorum;
 pub mod describe_topic_partitions;
+pub mod end_quorum_epoch;
+pub mod end_txn;
+pub mod expire_delegation_token;
 pub mod fetch;
+pub mod fetch_snapshot;
+pub mod find_coordinator;
+pub mod heartbeat;
+pub mod init_producer_id;
+pub mod join_group;
+pub mod leave_group;
+pub mod list_offsets;
+pub mod list_partition_reassignments;
+pub mod metadata;
+mod middleware;
+pub mod offset_commit;
+pub mod offset_delete;
+pub mod offset_fetch;
+pub mod produce;
+mod registry;
+pub mod remove_raft_voter;
+pub mod renew_delegation_token;
+pub mod sasl_authenticate;
+pub mod sasl_handshake;
+pub mod share_fetch;
+pub mod share_group_heartbeat;
+pub mod sync_group;
+pub mod update_features;
+pub mod vote;
+
+pub use middleware::*;
+pub use registry::*;
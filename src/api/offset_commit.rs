@@ -0,0 +1,431 @@
+use std::{sync::Arc, time::SystemTime};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::cluster_metadata::RecordBatches;
+use crate::coordinator::{CommittedOffset, GroupCoordinator, OffsetCommit, TopicPartition};
+use crate::error::Error;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+pub struct OffsetCommitRequest {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub topics: Vec<OffsetCommitRequestTopic>,
+}
+
+impl Deserialize<Self> for OffsetCommitRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let group_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let generation_id = src.try_get_i32()?;
+        let member_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let group_instance_id = CompactNullableString::deserialize(src)?.0;
+        let topics = CompactArray::<OffsetCommitRequestTopic>::deserialize(src)?;
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            group_id,
+            generation_id,
+            member_id,
+            group_instance_id,
+            topics,
+        })
+    }
+}
+
+/// Written as v9, where consumer group members send their member epoch as
+/// the generation id.
+impl Serialize for OffsetCommitRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.group_id.clone())).serialize());
+        b.put_i32(self.generation_id);
+        b.put(CompactNullableString(Some(self.member_id.clone())).serialize());
+        b.put(CompactNullableString(self.group_instance_id.clone()).serialize());
+        b.put(CompactArray(self.topics.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+#[derive(Clone)]
+pub struct OffsetCommitRequestTopic {
+    pub name: String,
+    pub partitions: Vec<OffsetCommitRequestPartition>,
+}
+
+impl Deserialize<Self> for OffsetCommitRequestTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let name = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let partitions = CompactArray::<OffsetCommitRequestPartition>::deserialize(src)?;
+        TagBuffer::deserialize(src)?;
+        Ok(Self { name, partitions })
+    }
+}
+
+impl Serialize for OffsetCommitRequestTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(CompactArray(self.partitions.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+#[derive(Clone)]
+pub struct OffsetCommitRequestPartition {
+    pub partition_index: i32,
+    pub committed_offset: i64,
+    pub committed_leader_epoch: i32,
+    pub committed_metadata: Option<String>,
+}
+
+impl Deserialize<Self> for OffsetCommitRequestPartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let partition_index = src.try_get_i32()?;
+        let committed_offset = src.try_get_i64()?;
+        let committed_leader_epoch = src.try_get_i32()?;
+        let committed_metadata = CompactNullableString::deserialize(src)?.0;
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            partition_index,
+            committed_offset,
+            committed_leader_epoch,
+            committed_metadata,
+        })
+    }
+}
+
+impl Serialize for OffsetCommitRequestPartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i64(self.committed_offset);
+        b.put_i32(self.committed_leader_epoch);
+        b.put(CompactNullableString(self.committed_metadata.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// OffsetCommit response, v8 and v9.
+pub struct OffsetCommitResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    pub topics: CompactArray<OffsetCommitResponseTopic>,
+}
+
+/// Reads a whole response, header included, as a client gets it.
+impl Deserialize<Self> for OffsetCommitResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let topics = CompactArray::deserialize_with(src, |src| {
+            let name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let partitions = CompactArray::deserialize_with(src, |src| {
+                let partition = OffsetCommitResponsePartition {
+                    partition_index: src.try_get_i32()?,
+                    error_code: ErrorCode::from(src.try_get_i16()?),
+                };
+                TagBuffer::deserialize_fields(src)?;
+                Ok(partition)
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(OffsetCommitResponseTopic {
+                name,
+                partitions: CompactArray(partitions),
+            })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            header,
+            throttle_time_ms,
+            topics: CompactArray(topics),
+        })
+    }
+}
+
+impl Response for OffsetCommitResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(self.topics.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn topic_partitions(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for topic in &self.topics.0 {
+            for partition in &topic.partitions.0 {
+                names.push(format!("{}-{}", topic.name, partition.partition_index));
+            }
+        }
+        names
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms).list(
+            "topics",
+            &self.topics.0,
+            |dump, topic| {
+                dump.field("name", &topic.name).list(
+                    "partitions",
+                    &topic.partitions.0,
+                    |dump, p| {
+                        dump.field("partition", p.partition_index)
+                            .field("error_code", format_args!("{:?}", p.error_code));
+                    },
+                );
+            },
+        );
+    }
+}
+
+impl OffsetCommitResponse {
+    fn new(ctx: &RequestContext, topics: Vec<OffsetCommitResponseTopic>) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            topics: CompactArray(topics),
+        }
+    }
+}
+
+pub struct OffsetCommitResponseTopic {
+    pub name: String,
+    pub partitions: CompactArray<OffsetCommitResponsePartition>,
+}
+
+impl Serialize for OffsetCommitResponseTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(self.partitions.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+pub struct OffsetCommitResponsePartition {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+}
+
+impl Serialize for OffsetCommitResponsePartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i16(self.error_code.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+pub struct OffsetCommitHandler {
+    coordinator: GroupCoordinator,
+    authorizer: Arc<dyn Authorizer>,
+    image: Arc<MetadataImage>,
+}
+
+impl OffsetCommitHandler {
+    pub fn new(
+        coordinator: GroupCoordinator,
+        authorizer: Arc<dyn Authorizer>,
+        image: Arc<MetadataImage>,
+    ) -> Self {
+        Self {
+            coordinator,
+            authorizer,
+            image,
+        }
+    }
+}
+
+impl ApiHandler for OffsetCommitHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        Ok(Box::new(handle_request(
+            ctx,
+            &self.coordinator,
+            &*self.authorizer,
+            &self.image,
+            body,
+        )?))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(error_response(ctx, body, error_code)?))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = OffsetCommitRequest::deserialize(body)?;
+        dump.field("group_id", &req.group_id)
+            .field("generation_id", req.generation_id)
+            .field("member_id", &req.member_id)
+            .nullable("group_instance_id", req.group_instance_id.as_deref())
+            .list("topics", &req.topics, |dump, topic| {
+                dump.field("name", &topic.name)
+                    .list("partitions", &topic.partitions, |dump, p| {
+                        dump.field("partition", p.partition_index)
+                            .field("committed_offset", p.committed_offset)
+                            .field("committed_leader_epoch", p.committed_leader_epoch)
+                            .nullable("committed_metadata", p.committed_metadata.as_deref());
+                    });
+            });
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Commits what it can: partitions of unknown or unauthorized topics fail on
+/// their own without holding back the rest.
+pub fn handle_request(
+    ctx: &RequestContext,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    image: &MetadataImage,
+    message: &mut Bytes,
+) -> Result<OffsetCommitResponse, Error> {
+    let mut original = message.clone();
+    let req = OffsetCommitRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::OffsetCommit, e))?;
+    if !authorizer.authorize(ctx, AclOperation::Read, ResourceType::Group, &req.group_id) {
+        return error_response(ctx, &mut original, ErrorCode::GroupAuthorizationFailed)
+            .map_err(|e| Error::Decode(ApiKey::OffsetCommit, e));
+    }
+    let record_batches = image.current();
+
+    // Each partition's error, with `None` for those passed on to be committed.
+    let mut results = Vec::new();
+    let mut offsets = Vec::new();
+    let commit_timestamp = SystemTime::now();
+    for topic in &req.topics {
+        let authorized =
+            authorizer.authorize(ctx, AclOperation::Read, ResourceType::Topic, &topic.name);
+        let known_partitions = known_partitions(&record_batches, &topic.name);
+        for partition in &topic.partitions {
+            let error_code = if !authorized {
+                Some(ErrorCode::TopicAuthorizationFailed)
+            } else if !known_partitions.contains(&partition.partition_index) {
+                Some(ErrorCode::UnknownTopicOrPartition)
+            } else {
+                offsets.push((
+                    TopicPartition {
+                        topic: topic.name.clone(),
+                        partition: partition.partition_index,
+                    },
+                    CommittedOffset {
+                        offset: partition.committed_offset,
+                        leader_epoch: partition.committed_leader_epoch,
+                        metadata: partition.committed_metadata.clone(),
+                        commit_timestamp,
+                    },
+                ));
+                None
+            };
+            results.push(error_code);
+        }
+    }
+
+    let mut committed = if offsets.is_empty() {
+        Vec::new()
+    } else if !ctx.commit.commit() {
+        vec![ErrorCode::RequestTimedOut; offsets.len()]
+    } else {
+        coordinator.commit_offsets(OffsetCommit {
+            group_id: req.group_id,
+            generation_id: req.generation_id,
+            member_id: req.member_id,
+            offsets,
+        })
+    }
+    .into_iter();
+    let mut results = results.into_iter();
+    let topics = req
+        .topics
+        .into_iter()
+        .map(|topic| OffsetCommitResponseTopic {
+            name: topic.name,
+            partitions: CompactArray(
+                topic
+                    .partitions
+                    .iter()
+                    .map(|partition| OffsetCommitResponsePartition {
+                        partition_index: partition.partition_index,
+                        error_code: results
+                            .next()
+                            .flatten()
+                            .or_else(|| committed.next())
+                            .unwrap_or(ErrorCode::UnknownServerError),
+                    })
+                    .collect(),
+            ),
+        })
+        .collect();
+    Ok(OffsetCommitResponse::new(ctx, topics))
+}
+
+/// The partitions of topic `name` in the cluster metadata.
+pub fn known_partitions(record_batches: &RecordBatches, name: &str) -> Vec<i32> {
+    let Some(topic) = record_batches.topic_by_name(name) else {
+        return Vec::new();
+    };
+    record_batches
+        .partitions(&topic.topic_id)
+        .map(|p| p.partition_id as i32)
+        .collect()
+}
+
+/// Answers every partition in the request with `error_code`.
+pub fn error_response(
+    ctx: &RequestContext,
+    message: &mut Bytes,
+    error_code: ErrorCode,
+) -> Result<OffsetCommitResponse, DecodeError> {
+    let req = OffsetCommitRequest::deserialize(message)?;
+    let topics = req
+        .topics
+        .into_iter()
+        .map(|topic| OffsetCommitResponseTopic {
+            name: topic.name,
+            partitions: CompactArray(
+                topic
+                    .partitions
+                    .iter()
+                    .map(|partition| OffsetCommitResponsePartition {
+                        partition_index: partition.partition_index,
+                        error_code,
+                    })
+                    .collect(),
+            ),
+        })
+        .collect();
+    Ok(OffsetCommitResponse::new(ctx, topics))
+}
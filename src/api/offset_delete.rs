@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::offset_commit::known_partitions;
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::coordinator::{GroupCoordinator, TopicPartition};
+use crate::error::Error;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// OffsetDelete request, v0.
+pub struct OffsetDeleteRequest {
+    pub group_id: String,
+    pub topics: Vec<OffsetDeleteRequestTopic>,
+}
+
+pub struct OffsetDeleteRequestTopic {
+    pub name: String,
+    pub partitions: Vec<i32>,
+}
+
+impl Deserialize<Self> for OffsetDeleteRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let group_id = NullableString::deserialize(src)?.0.unwrap_or_default();
+        let topics = Array::<OffsetDeleteRequestTopic>::deserialize(src)?;
+        Ok(Self { group_id, topics })
+    }
+}
+
+impl Deserialize<Self> for OffsetDeleteRequestTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let name = NullableString::deserialize(src)?.0.unwrap_or_default();
+        let count = src.try_get_i32()?.max(0);
+        let partitions = (0..count)
+            .map(|_| src.try_get_i32())
+            .collect::<Result<_, _>>()?;
+        Ok(Self { name, partitions })
+    }
+}
+
+/// OffsetDelete response, v0.
+pub struct OffsetDeleteResponse {
+    header: HeaderV0,
+    error_code: ErrorCode,
+    throttle_time_ms: i32,
+    topics: Array<OffsetDeleteResponseTopic>,
+}
+
+pub struct OffsetDeleteResponseTopic {
+    pub name: String,
+    pub partitions: Vec<(i32, ErrorCode)>,
+}
+
+impl Serialize for OffsetDeleteResponseTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(NullableString(Some(self.name.clone())).serialize());
+        b.put_i32(self.partitions.len() as i32);
+        for (partition_index, error_code) in &self.partitions {
+            b.put_i32(*partition_index);
+            b.put_i16((*error_code).into());
+        }
+        b.freeze()
+    }
+}
+
+impl OffsetDeleteResponse {
+    fn new(
+        ctx: &RequestContext,
+        error_code: ErrorCode,
+        topics: Vec<OffsetDeleteResponseTopic>,
+    ) -> Self {
+        Self {
+            header: HeaderV0::new(ctx.header.correlation_id),
+            error_code,
+            throttle_time_ms: 0,
+            topics: Array(topics),
+        }
+    }
+}
+
+impl Response for OffsetDeleteResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i16(self.error_code.into());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(self.topics.serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+}
+
+pub struct OffsetDeleteHandler {
+    coordinator: GroupCoordinator,
+    authorizer: Arc<dyn Authorizer>,
+    image: Arc<MetadataImage>,
+}
+
+impl OffsetDeleteHandler {
+    pub fn new(
+        coordinator: GroupCoordinator,
+        authorizer: Arc<dyn Authorizer>,
+        image: Arc<MetadataImage>,
+    ) -> Self {
+        Self {
+            coordinator,
+            authorizer,
+            image,
+        }
+    }
+}
+
+impl ApiHandler for OffsetDeleteHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        Ok(Box::new(handle_request(
+            ctx,
+            &self.coordinator,
+            &*self.authorizer,
+            &self.image,
+            body,
+        )?))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(OffsetDeleteResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = OffsetDeleteRequest::deserialize(body)?;
+        dump.field("group_id", &req.group_id)
+            .list("topics", &req.topics, |dump, topic| {
+                dump.field("name", &topic.name)
+                    .list("partitions", &topic.partitions, |dump, p| {
+                        dump.field("partition", p);
+                    });
+            });
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes Delete on the group and Read on each topic. Partitions of unknown
+/// or unauthorized topics fail on their own; the rest go to the
+/// coordinator, which keeps the offsets of topics the group consumes.
+pub fn handle_request(
+    ctx: &RequestContext,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    image: &MetadataImage,
+    message: &mut Bytes,
+) -> Result<OffsetDeleteResponse, Error> {
+    let req = OffsetDeleteRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::OffsetDelete, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Delete,
+        ResourceType::Group,
+        &req.group_id,
+    ) {
+        return Ok(OffsetDeleteResponse::new(
+            ctx,
+            ErrorCode::GroupAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    let record_batches = image.current();
+
+    let mut topics = Vec::new();
+    let mut partitions = Vec::new();
+    for topic in req.topics {
+        let authorized =
+            authorizer.authorize(ctx, AclOperation::Read, ResourceType::Topic, &topic.name);
+        let known_partitions = known_partitions(&record_batches, &topic.name);
+        let results = topic
+            .partitions
+            .into_iter()
+            .map(|partition| {
+                let error_code = if !authorized {
+                    ErrorCode::TopicAuthorizationFailed
+                } else if !known_partitions.contains(&partition) {
+                    ErrorCode::UnknownTopicOrPartition
+                } else {
+                    partitions.push(TopicPartition {
+                        topic: topic.name.clone(),
+                        partition,
+                    });
+                    ErrorCode::None
+                };
+                (partition, error_code)
+            })
+            .collect();
+        topics.push(OffsetDeleteResponseTopic {
+            name: topic.name,
+            partitions: results,
+        });
+    }
+
+    if !ctx.commit.commit() {
+        return Ok(OffsetDeleteResponse::new(
+            ctx,
+            ErrorCode::RequestTimedOut,
+            Vec::new(),
+        ));
+    }
+    let deleted = coordinator.delete_offsets(req.group_id, partitions);
+    if deleted.error_code != ErrorCode::None {
+        return Ok(OffsetDeleteResponse::new(
+            ctx,
+            deleted.error_code,
+            Vec::new(),
+        ));
+    }
+    let mut deleted = deleted.partitions.into_iter();
+    for topic in &mut topics {
+        for (_, error_code) in &mut topic.partitions {
+            if *error_code == ErrorCode::None {
+                *error_code = deleted
+                    .next()
+                    .map_or(ErrorCode::UnknownServerError, |(_, error_code)| error_code);
+            }
+        }
+    }
+    Ok(OffsetDeleteResponse::new(ctx, ErrorCode::None, topics))
+}
@@ -0,0 +1,531 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::coordinator::{GroupCoordinator, OffsetFetch, TopicPartition};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// From v8 a request asks about any number of groups; earlier versions ask
+/// about one, which is read here as a batch of one.
+pub struct OffsetFetchRequest {
+    pub groups: Vec<OffsetFetchRequestGroup>,
+    pub require_stable: bool,
+}
+
+impl OffsetFetchRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let groups = if api_version >= 8 {
+            CompactArray::deserialize_with(src, |src| {
+                let group_id = CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default();
+                let (member_id, member_epoch) = if api_version >= 9 {
+                    (
+                        CompactNullableString::deserialize(src)?.0,
+                        src.try_get_i32()?,
+                    )
+                } else {
+                    (None, -1)
+                };
+                let topics = deserialize_topics(src)?;
+                TagBuffer::deserialize(src)?;
+                Ok(OffsetFetchRequestGroup {
+                    group_id,
+                    member_id,
+                    member_epoch,
+                    topics,
+                })
+            })?
+        } else {
+            let group_id = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let topics = deserialize_topics(src)?;
+            vec![OffsetFetchRequestGroup {
+                group_id,
+                member_id: None,
+                member_epoch: -1,
+                topics,
+            }]
+        };
+        let require_stable = api_version >= 7 && src.try_get_u8()? != 0;
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            groups,
+            require_stable,
+        })
+    }
+}
+
+/// Written as v9, which batches groups and names the asking member.
+impl Serialize for OffsetFetchRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactArray(self.groups.clone()).serialize());
+        b.put_u8(self.require_stable.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for OffsetFetchRequestGroup {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.group_id.clone())).serialize());
+        b.put(CompactNullableString(self.member_id.clone()).serialize());
+        b.put_i32(self.member_epoch);
+        match &self.topics {
+            Some(topics) => b.put(CompactArray(topics.clone()).serialize()),
+            None => b.put_u8(0),
+        }
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for OffsetFetchRequestTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(CompactArray(self.partition_indexes.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// A length of 0 is the null array.
+fn deserialize_topics(
+    src: &mut Bytes,
+) -> Result<Option<Vec<OffsetFetchRequestTopic>>, DecodeError> {
+    if src.first() == Some(&0) {
+        src.advance(1);
+        Ok(None)
+    } else {
+        Ok(Some(CompactArray::<OffsetFetchRequestTopic>::deserialize(
+            src,
+        )?))
+    }
+}
+
+#[derive(Clone)]
+pub struct OffsetFetchRequestGroup {
+    pub group_id: String,
+    /// Sent by members of consumer groups since v9; admin clients send
+    /// neither.
+    pub member_id: Option<String>,
+    pub member_epoch: i32,
+    /// `None` asks for every partition the group has committed.
+    pub topics: Option<Vec<OffsetFetchRequestTopic>>,
+}
+
+#[derive(Clone)]
+pub struct OffsetFetchRequestTopic {
+    pub name: String,
+    pub partition_indexes: Vec<i32>,
+}
+
+impl Deserialize<Self> for OffsetFetchRequestTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let name = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let partition_indexes = CompactArray::<Self>::deserialize(src)?;
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            name,
+            partition_indexes,
+        })
+    }
+}
+
+impl Deserialize<i32> for OffsetFetchRequestTopic {
+    fn deserialize(src: &mut Bytes) -> Result<i32, DecodeError> {
+        Ok(src.try_get_i32()?)
+    }
+}
+
+/// OffsetFetch response, v6 to v9. Before v8 it answers for its one group
+/// at the top level.
+pub struct OffsetFetchResponse {
+    api_version: i16,
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    pub groups: CompactArray<OffsetFetchResponseGroup>,
+}
+
+/// Reads a whole v9 response, header included, as a client gets it.
+impl Deserialize<Self> for OffsetFetchResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let throttle_time_ms = src.try_get_i32()?;
+        let groups = CompactArray::deserialize_with(src, |src| {
+            let group_id = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let topics = CompactArray::deserialize_with(src, |src| {
+                let name = CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default();
+                let partitions = CompactArray::deserialize_with(src, |src| {
+                    let partition = OffsetFetchResponsePartition {
+                        partition_index: src.try_get_i32()?,
+                        committed_offset: src.try_get_i64()?,
+                        committed_leader_epoch: src.try_get_i32()?,
+                        metadata: CompactNullableString::deserialize(src)?,
+                        error_code: ErrorCode::from(src.try_get_i16()?),
+                    };
+                    TagBuffer::deserialize_fields(src)?;
+                    Ok(partition)
+                })?;
+                TagBuffer::deserialize_fields(src)?;
+                Ok(OffsetFetchResponseTopic {
+                    name,
+                    partitions: CompactArray(partitions),
+                })
+            })?;
+            let error_code = ErrorCode::from(src.try_get_i16()?);
+            TagBuffer::deserialize_fields(src)?;
+            Ok(OffsetFetchResponseGroup {
+                group_id,
+                topics: CompactArray(topics),
+                error_code,
+            })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            api_version: 9,
+            header,
+            throttle_time_ms,
+            groups: CompactArray(groups),
+        })
+    }
+}
+
+impl Response for OffsetFetchResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        if self.api_version >= 8 {
+            bytes.put(self.groups.serialize());
+        } else if let Some(group) = self.groups.0.first() {
+            bytes.put(group.topics.serialize());
+            bytes.put_i16(group.error_code.into());
+        }
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.groups
+            .0
+            .iter()
+            .map(|group| group.error_code)
+            .find(|error_code| !matches!(error_code, ErrorCode::None))
+            .unwrap_or(ErrorCode::None)
+    }
+
+    fn topic_partitions(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for group in &self.groups.0 {
+            for topic in &group.topics.0 {
+                for partition in &topic.partitions.0 {
+                    names.push(format!("{}-{}", topic.name, partition.partition_index));
+                }
+            }
+        }
+        names
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms).list(
+            "groups",
+            &self.groups.0,
+            |dump, group| {
+                dump.field("group_id", &group.group_id)
+                    .list("topics", &group.topics.0, |dump, topic| {
+                        dump.field("name", &topic.name).list(
+                            "partitions",
+                            &topic.partitions.0,
+                            |dump, p| {
+                                dump.field("partition", p.partition_index)
+                                    .field("committed_offset", p.committed_offset)
+                                    .field("committed_leader_epoch", p.committed_leader_epoch)
+                                    .string("metadata", &p.metadata)
+                                    .field("error_code", format_args!("{:?}", p.error_code));
+                            },
+                        );
+                    })
+                    .field("error_code", format_args!("{:?}", group.error_code));
+            },
+        );
+    }
+}
+
+impl OffsetFetchResponse {
+    fn new(ctx: &RequestContext, groups: Vec<OffsetFetchResponseGroup>) -> Self {
+        Self {
+            api_version: ctx.header.api_version,
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            groups: CompactArray(groups),
+        }
+    }
+}
+
+pub struct OffsetFetchResponseGroup {
+    pub group_id: String,
+    pub topics: CompactArray<OffsetFetchResponseTopic>,
+    pub error_code: ErrorCode,
+}
+
+impl OffsetFetchResponseGroup {
+    fn error(group_id: String, error_code: ErrorCode) -> Self {
+        Self {
+            group_id,
+            topics: CompactArray(Vec::new()),
+            error_code,
+        }
+    }
+}
+
+impl Serialize for OffsetFetchResponseGroup {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.group_id.clone())).serialize());
+        b.put(self.topics.serialize());
+        b.put_i16(self.error_code.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+pub struct OffsetFetchResponseTopic {
+    pub name: String,
+    pub partitions: CompactArray<OffsetFetchResponsePartition>,
+}
+
+impl Serialize for OffsetFetchResponseTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(self.partitions.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+pub struct OffsetFetchResponsePartition {
+    pub partition_index: i32,
+    /// -1 when the group has committed nothing for the partition.
+    pub committed_offset: i64,
+    pub committed_leader_epoch: i32,
+    pub metadata: CompactNullableString,
+    pub error_code: ErrorCode,
+}
+
+impl OffsetFetchResponsePartition {
+    /// A partition without a committed offset, or one that failed.
+    fn none(partition_index: i32, error_code: ErrorCode) -> Self {
+        Self {
+            partition_index,
+            committed_offset: -1,
+            committed_leader_epoch: -1,
+            metadata: CompactNullableString(Some(String::new())),
+            error_code,
+        }
+    }
+}
+
+impl Serialize for OffsetFetchResponsePartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i64(self.committed_offset);
+        b.put_i32(self.committed_leader_epoch);
+        b.put(self.metadata.serialize());
+        b.put_i16(self.error_code.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+pub struct OffsetFetchHandler {
+    coordinator: GroupCoordinator,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl OffsetFetchHandler {
+    pub fn new(coordinator: GroupCoordinator, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            coordinator,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for OffsetFetchHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.coordinator, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = OffsetFetchRequest::deserialize(body, ctx.header.api_version)?;
+        let groups = req
+            .groups
+            .into_iter()
+            .map(|group| OffsetFetchResponseGroup::error(group.group_id, error_code))
+            .collect();
+        Ok(Box::new(OffsetFetchResponse::new(ctx, groups)))
+    }
+
+    fn describe_request(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = OffsetFetchRequest::deserialize(body, ctx.header.api_version)?;
+        dump.list("groups", &req.groups, |dump, group| {
+            dump.field("group_id", &group.group_id)
+                .nullable("member_id", group.member_id.as_deref())
+                .field("member_epoch", group.member_epoch);
+            match &group.topics {
+                Some(topics) => dump.list("topics", topics, |dump, topic| {
+                    dump.field("name", &topic.name)
+                        .field("partitions", format_args!("{:?}", topic.partition_indexes));
+                }),
+                None => dump.field("topics", "all"),
+            };
+        })
+        .field("require_stable", req.require_stable);
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Each group is answered on its own, so one the principal may not describe
+/// fails without failing the rest of the batch.
+pub fn handle_request(
+    ctx: &RequestContext,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<OffsetFetchResponse, Error> {
+    let req = OffsetFetchRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::OffsetFetch, e))?;
+    let groups = req
+        .groups
+        .into_iter()
+        .map(|group| fetch_group(ctx, coordinator, authorizer, group))
+        .collect();
+    Ok(OffsetFetchResponse::new(ctx, groups))
+}
+
+/// Partitions without a committed offset are answered with offset -1.
+/// Without transactions no offset is ever pending, so `require_stable` has
+/// nothing to wait for.
+fn fetch_group(
+    ctx: &RequestContext,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    group: OffsetFetchRequestGroup,
+) -> OffsetFetchResponseGroup {
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Describe,
+        ResourceType::Group,
+        &group.group_id,
+    ) {
+        return OffsetFetchResponseGroup::error(
+            group.group_id,
+            ErrorCode::GroupAuthorizationFailed,
+        );
+    }
+    let may_describe =
+        |topic: &str| authorizer.authorize(ctx, AclOperation::Describe, ResourceType::Topic, topic);
+
+    // Partitions of topics the principal may not describe are answered with
+    // an error when asked for by name, and left out when all are asked for.
+    let mut denied = Vec::new();
+    let partitions = group.topics.map(|topics| {
+        let mut partitions = Vec::new();
+        for topic in topics {
+            let allowed = may_describe(&topic.name);
+            for partition in topic.partition_indexes {
+                let partition = TopicPartition {
+                    topic: topic.name.clone(),
+                    partition,
+                };
+                if allowed {
+                    partitions.push(partition);
+                } else {
+                    denied.push(partition);
+                }
+            }
+        }
+        partitions
+    });
+    let result = coordinator.fetch_offsets(OffsetFetch {
+        group_id: group.group_id.clone(),
+        member_id: group.member_id,
+        member_epoch: group.member_epoch,
+        partitions,
+    });
+    if !matches!(result.error_code, ErrorCode::None) {
+        return OffsetFetchResponseGroup::error(group.group_id, result.error_code);
+    }
+
+    let mut topics: Vec<OffsetFetchResponseTopic> = Vec::new();
+    let answers = result
+        .offsets
+        .into_iter()
+        .filter(|(partition, _)| may_describe(&partition.topic))
+        .map(|(partition, offset)| {
+            let answer = match offset {
+                Some(offset) => OffsetFetchResponsePartition {
+                    partition_index: partition.partition,
+                    committed_offset: offset.offset,
+                    committed_leader_epoch: offset.leader_epoch,
+                    metadata: CompactNullableString(Some(offset.metadata.unwrap_or_default())),
+                    error_code: ErrorCode::None,
+                },
+                None => OffsetFetchResponsePartition::none(partition.partition, ErrorCode::None),
+            };
+            (partition.topic, answer)
+        })
+        .chain(denied.into_iter().map(|partition| {
+            let answer = OffsetFetchResponsePartition::none(
+                partition.partition,
+                ErrorCode::TopicAuthorizationFailed,
+            );
+            (partition.topic, answer)
+        }));
+    for (name, answer) in answers {
+        match topics.iter_mut().find(|t| t.name == name) {
+            Some(topic) => topic.partitions.0.push(answer),
+            None => topics.push(OffsetFetchResponseTopic {
+                name,
+                partitions: CompactArray(vec![answer]),
+            }),
+        }
+    }
+    OffsetFetchResponseGroup {
+        group_id: group.group_id,
+        topics: CompactArray(topics),
+        error_code: ErrorCode::None,
+    }
+}
@@ -0,0 +1,922 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tracing::{debug, warn};
+
+use crate::api::fetch::FetchPurgatory;
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::cluster_metadata::RecordBatches;
+use crate::coordinator::{MarkerWriter, TopicPartition};
+use crate::error::Error;
+use crate::log_manager::{create_partition_dir, log_end};
+use crate::metadata_image::MetadataImage;
+use crate::partition_rates::{ByteDirection, PartitionRates};
+use crate::protocol::*;
+use crate::record_batch::{
+    encode_marker, is_intact, place_batch, BatchHeader, BATCH_HEADER_SIZE, COMPRESSION_MASK,
+};
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// `acks` that has the leader answer without waiting on followers.
+pub const ACKS_LEADER: i16 = 1;
+/// `acks` that has the leader answer once every in-sync replica has the
+/// records.
+pub const ACKS_ALL: i16 = -1;
+/// `acks` that has the broker send no response at all.
+pub const ACKS_NONE: i16 = 0;
+
+/// Produce request, v9 to v11, which share a layout.
+pub struct ProduceRequest {
+    pub transactional_id: Option<String>,
+    pub acks: i16,
+    pub timeout_ms: i32,
+    pub topic_data: Vec<TopicProduceData>,
+}
+
+#[derive(Clone)]
+pub struct TopicProduceData {
+    pub name: String,
+    pub partition_data: Vec<PartitionProduceData>,
+}
+
+#[derive(Clone)]
+pub struct PartitionProduceData {
+    pub index: i32,
+    /// One or more whole record batches.
+    pub records: Bytes,
+}
+
+impl Deserialize<Self> for ProduceRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let transactional_id = CompactNullableString::deserialize(src)?.0;
+        let acks = src.try_get_i16()?;
+        let timeout_ms = src.try_get_i32()?;
+        let topic_data = CompactArray::deserialize_with(src, |src| {
+            let name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let partition_data = CompactArray::deserialize_with(src, |src| {
+                let index = src.try_get_i32()?;
+                let records = CompactBytes::deserialize(src)?.0;
+                TagBuffer::deserialize_fields(src)?;
+                Ok(PartitionProduceData { index, records })
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(TopicProduceData {
+                name,
+                partition_data,
+            })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            transactional_id,
+            acks,
+            timeout_ms,
+            topic_data,
+        })
+    }
+}
+
+impl Serialize for ProduceRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(self.transactional_id.clone()).serialize());
+        b.put_i16(self.acks);
+        b.put_i32(self.timeout_ms);
+        b.put(CompactArray(self.topic_data.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for TopicProduceData {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(CompactArray(self.partition_data.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for PartitionProduceData {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.index);
+        b.put(CompactBytes(self.records.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// Produce response, v9 to v11. The leader hints v10 added are tagged
+/// fields, left out.
+pub struct ProduceResponse {
+    header: HeaderV1,
+    pub responses: Vec<TopicProduceResponse>,
+    pub throttle_time_ms: i32,
+    /// Whether the producer asked for an answer; with acks=0 it didn't.
+    acks: i16,
+}
+
+#[derive(Clone)]
+pub struct TopicProduceResponse {
+    pub name: String,
+    pub partition_responses: Vec<PartitionProduceResponse>,
+}
+
+#[derive(Clone)]
+pub struct PartitionProduceResponse {
+    pub index: i32,
+    pub error_code: ErrorCode,
+    /// The offset given to the first record written.
+    pub base_offset: i64,
+    /// -1 unless the topic stamps records with the time they were appended.
+    pub log_append_time_ms: i64,
+    pub log_start_offset: i64,
+    /// The batches that failed, and why.
+    pub record_errors: Vec<RecordError>,
+    pub error_message: Option<String>,
+}
+
+/// A batch in a partition's data that failed, by its index there.
+#[derive(Clone)]
+pub struct RecordError {
+    pub batch_index: i32,
+    pub message: Option<String>,
+}
+
+/// Reads a whole response, header included, as a client gets it.
+impl Deserialize<Self> for ProduceResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let responses = CompactArray::deserialize_with(src, |src| {
+            let name = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let partition_responses = CompactArray::deserialize_with(src, |src| {
+                let index = src.try_get_i32()?;
+                let error_code = ErrorCode::from(src.try_get_i16()?);
+                let base_offset = src.try_get_i64()?;
+                let log_append_time_ms = src.try_get_i64()?;
+                let log_start_offset = src.try_get_i64()?;
+                let record_errors = CompactArray::deserialize_with(src, |src| {
+                    let batch_index = src.try_get_i32()?;
+                    let message = CompactNullableString::deserialize(src)?.0;
+                    TagBuffer::deserialize_fields(src)?;
+                    Ok(RecordError {
+                        batch_index,
+                        message,
+                    })
+                })?;
+                let error_message = CompactNullableString::deserialize(src)?.0;
+                TagBuffer::deserialize_fields(src)?;
+                Ok(PartitionProduceResponse {
+                    index,
+                    error_code,
+                    base_offset,
+                    log_append_time_ms,
+                    log_start_offset,
+                    record_errors,
+                    error_message,
+                })
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(TopicProduceResponse {
+                name,
+                partition_responses,
+            })
+        })?;
+        let throttle_time_ms = src.try_get_i32()?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            header,
+            responses,
+            throttle_time_ms,
+            acks: ACKS_LEADER,
+        })
+    }
+}
+
+impl ProduceResponse {
+    pub fn new(ctx: &RequestContext, acks: i16, responses: Vec<TopicProduceResponse>) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            responses,
+            throttle_time_ms: 0,
+            acks,
+        }
+    }
+}
+
+impl Serialize for TopicProduceResponse {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.name.clone())).serialize());
+        b.put(CompactArray(self.partition_responses.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for PartitionProduceResponse {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.index);
+        b.put_i16(self.error_code.into());
+        b.put_i64(self.base_offset);
+        b.put_i64(self.log_append_time_ms);
+        b.put_i64(self.log_start_offset);
+        b.put(CompactArray(self.record_errors.clone()).serialize());
+        b.put(CompactNullableString(self.error_message.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Serialize for RecordError {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.batch_index);
+        b.put(CompactNullableString(self.message.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl PartitionProduceResponse {
+    fn error(index: i32, error_code: ErrorCode) -> Self {
+        Self {
+            index,
+            error_code,
+            base_offset: -1,
+            log_append_time_ms: -1,
+            log_start_offset: -1,
+            record_errors: Vec::new(),
+            error_message: None,
+        }
+    }
+}
+
+impl Response for ProduceResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put(CompactArray(self.responses.clone()).serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn topic_partitions(&self) -> Vec<String> {
+        self.responses
+            .iter()
+            .flat_map(|topic| {
+                topic
+                    .partition_responses
+                    .iter()
+                    .map(move |p| format!("{}-{}", topic.name, p.index))
+            })
+            .collect()
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("throttle_time_ms", self.throttle_time_ms).list(
+            "responses",
+            &self.responses,
+            |dump, topic| {
+                dump.field("name", &topic.name).list(
+                    "partition_responses",
+                    &topic.partition_responses,
+                    |dump, p| {
+                        dump.field("index", p.index)
+                            .field("error_code", format_args!("{:?}", p.error_code))
+                            .field("base_offset", p.base_offset)
+                            .nullable("error_message", p.error_message.as_deref());
+                    },
+                );
+            },
+        );
+    }
+
+    fn is_sent(&self) -> bool {
+        self.acks != ACKS_NONE
+    }
+}
+
+/// Appends produced batches to the partitions this broker leads.
+pub struct ProduceHandler {
+    node_id: i32,
+    authorizer: Arc<dyn Authorizer>,
+    /// Counts the bytes appended to each partition.
+    rates: Arc<PartitionRates>,
+    /// Fetches waiting for the partitions appended to.
+    fetches: Arc<FetchPurgatory>,
+    image: Arc<MetadataImage>,
+    /// Where the partitions this broker leads are written, and Fetch reads
+    /// them from.
+    log_dir: PathBuf,
+    appends: Arc<PartitionLocks>,
+}
+
+impl ProduceHandler {
+    pub fn new(
+        node_id: i32,
+        authorizer: Arc<dyn Authorizer>,
+        rates: Arc<PartitionRates>,
+        fetches: Arc<FetchPurgatory>,
+        image: Arc<MetadataImage>,
+        log_dir: PathBuf,
+        appends: Arc<PartitionLocks>,
+    ) -> Self {
+        Self {
+            node_id,
+            authorizer,
+            rates,
+            fetches,
+            image,
+            log_dir,
+            appends,
+        }
+    }
+}
+
+impl ApiHandler for ProduceHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(
+            ctx,
+            self.node_id,
+            &*self.authorizer,
+            Some(&self.rates),
+            Some(&self.fetches),
+            &self.image,
+            &self.log_dir,
+            &self.appends,
+            body,
+        )?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = ProduceRequest::deserialize(body)?;
+        let responses = req
+            .topic_data
+            .iter()
+            .map(|topic| TopicProduceResponse {
+                name: topic.name.clone(),
+                partition_responses: topic
+                    .partition_data
+                    .iter()
+                    .map(|p| PartitionProduceResponse::error(p.index, error_code))
+                    .collect(),
+            })
+            .collect();
+        Ok(Box::new(ProduceResponse::new(ctx, req.acks, responses)))
+    }
+
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = ProduceRequest::deserialize(body)?;
+        dump.nullable("transactional_id", req.transactional_id.as_deref())
+            .field("acks", req.acks)
+            .field("timeout_ms", req.timeout_ms)
+            .list("topic_data", &req.topic_data, |dump, topic| {
+                dump.field("name", &topic.name).list(
+                    "partition_data",
+                    &topic.partition_data,
+                    |dump, p| {
+                        dump.field("index", p.index).records("records", &p.records);
+                    },
+                );
+            });
+        Ok(())
+    }
+
+    /// Each partition is appended only once the request is committed.
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// One lock per partition directory, so that appends to it happen one at a
+/// time and each batch's base offset follows on from the last. Each lock
+/// holds where the partition's log starts and ends, read from its segments
+/// on the first append rather than on every one. A deleted partition's lock
+/// is removed with its directory.
+#[derive(Default)]
+pub struct PartitionLocks(Mutex<HashMap<PathBuf, Arc<Mutex<Option<LogOffsets>>>>>);
+
+/// A led partition's log offsets, as the appends under one leadership of one
+/// topic left them.
+#[derive(Clone)]
+pub(crate) struct LogOffsets {
+    topic_id: Uuid,
+    leader_epoch: i32,
+    pub(crate) log_start_offset: i64,
+    pub(crate) log_end_offset: i64,
+    /// The epochs of the first and last batches, -1 while the log is empty.
+    pub(crate) first_epoch: i32,
+    pub(crate) last_epoch: i32,
+}
+
+impl LogOffsets {
+    /// Reads the offsets of the log `segment` starts for the appends of
+    /// `topic_id`'s leader in `leader_epoch`, creating the partition's dir if
+    /// it isn't there.
+    fn load(segment: &Path, topic_id: &Uuid, leader_epoch: i32) -> Result<Self> {
+        let dir = partition_dir(segment);
+        create_partition_dir(dir, &topic_id.0)?;
+        let (log_end_offset, last_epoch) = log_end(dir)?.unwrap_or((0, -1));
+        let first = first_batch(segment)?;
+        Ok(Self {
+            topic_id: topic_id.clone(),
+            leader_epoch,
+            log_start_offset: first.as_ref().map_or(log_end_offset, |h| h.base_offset),
+            log_end_offset,
+            first_epoch: first.map_or(-1, |h| h.partition_leader_epoch),
+            last_epoch,
+        })
+    }
+}
+
+/// The offsets in `offsets`, read from `segment`'s log unless kept from
+/// `topic_id`'s leader in `leader_epoch`. Offsets kept from another
+/// leadership, or a deleted topic of the same name, are read again: the
+/// replica fetcher may have written the log since.
+fn current_offsets<'a>(
+    offsets: &'a mut Option<LogOffsets>,
+    segment: &Path,
+    topic_id: &Uuid,
+    leader_epoch: i32,
+) -> Result<&'a mut LogOffsets> {
+    if offsets
+        .as_ref()
+        .is_some_and(|o| o.topic_id != *topic_id || o.leader_epoch != leader_epoch)
+    {
+        *offsets = None;
+    }
+    Ok(match offsets {
+        Some(offsets) => offsets,
+        None => offsets.insert(LogOffsets::load(segment, topic_id, leader_epoch)?),
+    })
+}
+
+impl PartitionLocks {
+    fn get(&self, dir: &Path) -> Arc<Mutex<Option<LogOffsets>>> {
+        let mut locks = self.0.lock().unwrap();
+        locks.entry(dir.to_path_buf()).or_default().clone()
+    }
+
+    /// The offsets of the log `segment` starts, which this broker leads for
+    /// `topic_id` in `leader_epoch`, as appends left them, so that they are
+    /// only read from the log once per leadership.
+    pub(crate) fn offsets(
+        &self,
+        segment: &Path,
+        topic_id: &Uuid,
+        leader_epoch: i32,
+    ) -> Result<LogOffsets> {
+        let lock = self.get(partition_dir(segment));
+        let mut offsets = lock.lock().unwrap();
+        current_offsets(&mut offsets, segment, topic_id, leader_epoch).cloned()
+    }
+
+    /// Drops the locks of the partition directories in `dirs`, which have
+    /// been deleted.
+    pub fn remove(&self, dirs: &[PathBuf]) {
+        let mut locks = self.0.lock().unwrap();
+        for dir in dirs {
+            locks.remove(dir);
+        }
+    }
+}
+
+/// Takes Write on each topic, and on the transactional id if one is given.
+/// Each partition's batches are checked whole before any is appended: they
+/// must be intact, uncompressed v2 data batches, and the partition led by
+/// this broker. The batches are given the offsets following the log's end
+/// and the leader's epoch, and appended to the segment Fetch reads.
+///
+/// Answers aren't held back waiting on followers, so acks=-1 is only taken
+/// for partitions whose ISR is this broker alone, where the leader's log end
+/// is the high watermark. Elsewhere it fails with INVALID_REQUIRED_ACKS
+/// before anything is appended, as does any acks other than -1, 0 or 1.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_request(
+    ctx: &RequestContext,
+    node_id: i32,
+    authorizer: &dyn Authorizer,
+    rates: Option<&PartitionRates>,
+    fetches: Option<&FetchPurgatory>,
+    image: &MetadataImage,
+    log_dir: &Path,
+    appends: &PartitionLocks,
+    message: &mut Bytes,
+) -> Result<ProduceResponse, Error> {
+    let req =
+        ProduceRequest::deserialize(message).map_err(|e| Error::Decode(ApiKey::Produce, e))?;
+    let transaction_denied = req.transactional_id.as_deref().is_some_and(|id| {
+        !authorizer.authorize(ctx, AclOperation::Write, ResourceType::TransactionalId, id)
+    });
+    let record_batches = image.current();
+    let mut responses = Vec::new();
+    for topic in &req.topic_data {
+        let topic_error = if ![ACKS_ALL, ACKS_NONE, ACKS_LEADER].contains(&req.acks) {
+            Some(ErrorCode::InvalidRequiredAcks)
+        } else if transaction_denied {
+            Some(ErrorCode::TransactionalIdAuthorizationFailed)
+        } else if !authorizer.authorize(ctx, AclOperation::Write, ResourceType::Topic, &topic.name)
+        {
+            Some(ErrorCode::TopicAuthorizationFailed)
+        } else {
+            None
+        };
+        let topic_id = record_batches
+            .topic_by_name(&topic.name)
+            .map(|t| t.topic_id.clone());
+        let mut partition_responses = Vec::new();
+        for data in &topic.partition_data {
+            let answer = match (topic_error, &topic_id) {
+                (Some(error_code), _) => PartitionProduceResponse::error(data.index, error_code),
+                (None, None) => {
+                    PartitionProduceResponse::error(data.index, ErrorCode::UnknownTopicOrPartition)
+                }
+                (None, Some(topic_id)) => produce_partition(
+                    ctx,
+                    req.acks,
+                    &record_batches,
+                    node_id,
+                    log_dir,
+                    appends,
+                    topic_id,
+                    data,
+                ),
+            };
+            if answer.error_code == ErrorCode::None {
+                if let Some(rates) = rates {
+                    rates.record(
+                        ByteDirection::In,
+                        &topic.name,
+                        data.index,
+                        data.records.len(),
+                    );
+                }
+                if let Some(fetches) = fetches {
+                    fetches.check_and_complete(&(topic.name.clone(), data.index));
+                }
+            }
+            partition_responses.push(answer);
+        }
+        responses.push(TopicProduceResponse {
+            name: topic.name.clone(),
+            partition_responses,
+        });
+    }
+    Ok(ProduceResponse::new(ctx, req.acks, responses))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn produce_partition(
+    ctx: &RequestContext,
+    acks: i16,
+    record_batches: &RecordBatches,
+    node_id: i32,
+    log_dir: &Path,
+    appends: &PartitionLocks,
+    topic_id: &Uuid,
+    data: &PartitionProduceData,
+) -> PartitionProduceResponse {
+    let index = data.index;
+    let Some(partition) = record_batches.partition(topic_id, index) else {
+        return PartitionProduceResponse::error(index, ErrorCode::UnknownTopicOrPartition);
+    };
+    if partition.leader_id as i32 != node_id {
+        return PartitionProduceResponse::error(index, ErrorCode::NotLeaderOrFollower);
+    }
+    if acks == ACKS_ALL
+        && partition
+            .in_sync_replicas
+            .iter()
+            .any(|&replica| replica as i32 != node_id)
+    {
+        let mut answer = PartitionProduceResponse::error(index, ErrorCode::InvalidRequiredAcks);
+        answer.error_message =
+            Some("acks=-1 isn't supported for partitions with followers in the ISR".to_string());
+        return answer;
+    }
+    let Some(segment) = record_batches.segment_for_topic(log_dir, topic_id, index as u32) else {
+        return PartitionProduceResponse::error(index, ErrorCode::UnknownTopicOrPartition);
+    };
+    if let Err((error_code, batch_index, reason)) = check_batches(&data.records) {
+        let mut answer = PartitionProduceResponse::error(index, error_code);
+        answer.record_errors = vec![RecordError {
+            batch_index,
+            message: Some(reason.to_string()),
+        }];
+        answer.error_message = Some(reason.to_string());
+        return answer;
+    }
+
+    let lock = appends.get(partition_dir(&segment));
+    let mut offsets = lock.lock().unwrap();
+    // A request already answered with REQUEST_TIMED_OUT is retried by its
+    // client, so appending it now would duplicate its records.
+    if !ctx.commit.commit() {
+        return PartitionProduceResponse::error(index, ErrorCode::RequestTimedOut);
+    }
+    let leader_epoch = partition.leader_epoch as i32;
+    match append(
+        &segment,
+        &mut offsets,
+        topic_id,
+        &data.records,
+        leader_epoch,
+    ) {
+        Ok((base_offset, log_start_offset)) => PartitionProduceResponse {
+            base_offset,
+            log_start_offset,
+            ..PartitionProduceResponse::error(index, ErrorCode::None)
+        },
+        Err(e) => {
+            // The write may have got partway, so the log end is read again.
+            *offsets = None;
+            warn!(segment = %segment.display(), error = %format!("{:#}", e), "produce failed");
+            let mut answer = PartitionProduceResponse::error(index, ErrorCode::KafkaStorageError);
+            answer.error_message = Some(format!("{:#}", e));
+            answer
+        }
+    }
+}
+
+/// Checks that `records` is nothing but whole, intact, uncompressed v2 data
+/// batches, returning the index of the first that isn't, why, and the error
+/// to answer with. Compressed batches would need their records rewritten to
+/// be placed, and control batches are only written by the broker.
+fn check_batches(records: &[u8]) -> Result<(), (ErrorCode, i32, &'static str)> {
+    if records.is_empty() {
+        return Err((ErrorCode::CorruptMessage, 0, "no record batches"));
+    }
+    let mut pos = 0;
+    let mut batch_index = 0;
+    while pos < records.len() {
+        let Some(header) = BatchHeader::parse(&records[pos..]) else {
+            return Err((
+                ErrorCode::CorruptMessage,
+                batch_index,
+                "truncated record batch",
+            ));
+        };
+        if !is_intact(&records[pos..pos + header.size()]) {
+            return Err((
+                ErrorCode::CorruptMessage,
+                batch_index,
+                "record batch is not v2 or fails its CRC",
+            ));
+        }
+        if header.record_count <= 0 || header.last_offset_delta != header.record_count - 1 {
+            return Err((
+                ErrorCode::CorruptMessage,
+                batch_index,
+                "last offset delta doesn't match the record count",
+            ));
+        }
+        if header.attributes & COMPRESSION_MASK != 0 {
+            return Err((
+                ErrorCode::UnsupportedCompressionType,
+                batch_index,
+                "compressed record batches aren't supported",
+            ));
+        }
+        if header.is_control() {
+            return Err((
+                ErrorCode::InvalidRecord,
+                batch_index,
+                "clients can't write control batches",
+            ));
+        }
+        pos += header.size();
+        batch_index += 1;
+    }
+    Ok(())
+}
+
+/// Appends `records` to the end of `segment`, numbering its batches on from
+/// the log's end offset, and returns the first batch's base offset and the
+/// log's start offset. `offsets` are read from the partition's dir unless
+/// kept from this leadership, and moved on past the appended batches.
+fn append(
+    segment: &Path,
+    offsets: &mut Option<LogOffsets>,
+    topic_id: &Uuid,
+    records: &Bytes,
+    leader_epoch: i32,
+) -> Result<(i64, i64)> {
+    let offsets = current_offsets(offsets, segment, topic_id, leader_epoch)?;
+    let base_offset = offsets.log_end_offset;
+    let mut placed = BytesMut::from(&records[..]);
+    let mut next_offset = base_offset;
+    let mut pos = 0;
+    while let Some(header) = BatchHeader::parse(&placed[pos..]) {
+        place_batch(&mut placed[pos..], next_offset, leader_epoch);
+        next_offset += header.last_offset() - header.base_offset + 1;
+        pos += header.size();
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(segment)
+        .and_then(|mut f| f.write_all(&placed))
+        .with_context(|| format!("append to '{}'", segment.display()))?;
+    offsets.log_end_offset = next_offset;
+    if offsets.first_epoch < 0 {
+        offsets.first_epoch = leader_epoch;
+    }
+    offsets.last_epoch = leader_epoch;
+    debug!(
+        segment = %segment.display(),
+        base_offset,
+        log_end_offset = next_offset,
+        "appended produced batches"
+    );
+    Ok((base_offset, offsets.log_start_offset))
+}
+
+/// Appends the transaction coordinator's markers to the partitions this
+/// broker leads, as Produce appends batches. Partitions of deleted topics
+/// are skipped, and so are those led elsewhere, which only their leader may
+/// append to.
+pub struct TransactionMarkers {
+    node_id: i32,
+    /// Fetches waiting for the partitions marked.
+    fetches: Arc<FetchPurgatory>,
+    image: Arc<MetadataImage>,
+    log_dir: PathBuf,
+    appends: Arc<PartitionLocks>,
+}
+
+impl TransactionMarkers {
+    pub fn new(
+        node_id: i32,
+        fetches: Arc<FetchPurgatory>,
+        image: Arc<MetadataImage>,
+        log_dir: PathBuf,
+        appends: Arc<PartitionLocks>,
+    ) -> Self {
+        Self {
+            node_id,
+            fetches,
+            image,
+            log_dir,
+            appends,
+        }
+    }
+}
+
+impl MarkerWriter for TransactionMarkers {
+    fn write_markers(
+        &self,
+        partitions: &std::collections::BTreeSet<TopicPartition>,
+        producer_id: i64,
+        producer_epoch: i16,
+        commit: bool,
+    ) -> Result<()> {
+        let record_batches = self.image.current();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        let marker = encode_marker(producer_id, producer_epoch, commit, timestamp);
+        for tp in partitions {
+            let Some(topic_id) = record_batches
+                .topic_by_name(&tp.topic)
+                .map(|t| t.topic_id.clone())
+            else {
+                continue;
+            };
+            let Some(partition) = record_batches.partition(&topic_id, tp.partition) else {
+                continue;
+            };
+            if partition.leader_id as i32 != self.node_id {
+                warn!(
+                    topic = %tp.topic,
+                    partition = tp.partition,
+                    producer_id,
+                    "not writing a transaction marker to a partition led elsewhere"
+                );
+                continue;
+            }
+            let Some(segment) =
+                record_batches.segment_for_topic(&self.log_dir, &topic_id, tp.partition as u32)
+            else {
+                continue;
+            };
+            let lock = self.appends.get(partition_dir(&segment));
+            let mut offsets = lock.lock().unwrap();
+            let leader_epoch = partition.leader_epoch as i32;
+            if let Err(e) = append(&segment, &mut offsets, &topic_id, &marker, leader_epoch) {
+                // The write may have got partway, so the log end is read again.
+                *offsets = None;
+                return Err(e);
+            }
+            drop(offsets);
+            self.fetches
+                .check_and_complete(&(tp.topic.clone(), tp.partition));
+        }
+        Ok(())
+    }
+}
+
+fn partition_dir(segment: &Path) -> &Path {
+    segment.parent().expect("a segment is in a partition dir")
+}
+
+/// The header of the first batch in `segment`, or `None` if it holds none.
+fn first_batch(segment: &Path) -> Result<Option<BatchHeader>> {
+    let mut header = [0; BATCH_HEADER_SIZE];
+    match File::open(segment).and_then(|mut f| f.read_exact(&mut header)) {
+        Ok(()) => Ok(BatchHeader::parse_fixed(&header)),
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::UnexpectedEof) => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("read '{}'", segment.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record_batch::{encode_batch, BatchRecord, CONTROL_FLAG};
+
+    fn batch(attributes: i16) -> Bytes {
+        let record = BatchRecord {
+            key: None,
+            value: Some(Bytes::from_static(b"v")),
+        };
+        encode_batch(0, 0, attributes, &[record], 0)
+    }
+
+    #[test]
+    fn takes_plain_data_batches() {
+        let records = [batch(0), batch(0)].concat();
+        assert!(check_batches(&records).is_ok());
+    }
+
+    #[test]
+    fn refuses_compressed_and_control_batches() {
+        let gzip = [batch(0), batch(1)].concat();
+        assert_eq!(
+            check_batches(&gzip).unwrap_err().0,
+            ErrorCode::UnsupportedCompressionType
+        );
+        assert_eq!(check_batches(&gzip).unwrap_err().1, 1);
+        assert_eq!(
+            check_batches(&batch(CONTROL_FLAG)).unwrap_err().0,
+            ErrorCode::InvalidRecord
+        );
+    }
+
+    #[test]
+    fn record_errors_are_a_compact_array() {
+        let mut answer = PartitionProduceResponse::error(0, ErrorCode::CorruptMessage);
+        answer.record_errors = (0..200)
+            .map(|batch_index| RecordError {
+                batch_index,
+                message: None,
+            })
+            .collect();
+        let response = ProduceResponse {
+            header: HeaderV1::new(7),
+            responses: vec![TopicProduceResponse {
+                name: "t".to_string(),
+                partition_responses: vec![answer],
+            }],
+            throttle_time_ms: 0,
+            acks: ACKS_LEADER,
+        };
+        let mut bytes = response.as_bytes();
+        let decoded = ProduceResponse::deserialize(&mut bytes).unwrap();
+        let errors = &decoded.responses[0].partition_responses[0].record_errors;
+        assert_eq!(errors.len(), 200);
+        assert_eq!(errors[199].batch_index, 199);
+        assert!(bytes.is_empty());
+    }
+}
@@ -0,0 +1,530 @@
+use std::{
+    collections::BTreeMap,
+    ops::RangeInclusive,
+    sync::{Arc, RwLock},
+};
+
+use bytes::Bytes;
+
+use crate::api::{
+    add_partitions_to_txn::AddPartitionsToTxnHandler,
+    add_raft_voter::AddRaftVoterHandler,
+    alter_client_quotas::AlterClientQuotasHandler,
+    alter_configs::AlterConfigsHandler,
+    alter_partition::AlterPartitionHandler,
+    alter_partition_reassignments::AlterPartitionReassignmentsHandler,
+    api_versions::ApiVersionsHandler,
+    assign_replicas_to_dirs::AssignReplicasToDirsHandler,
+    begin_quorum_epoch::BeginQuorumEpochHandler,
+    broker_heartbeat::BrokerHeartbeatHandler,
+    broker_registration::BrokerRegistrationHandler,
+    consumer_group_heartbeat::ConsumerGroupHeartbeatHandler,
+    create_delegation_token::CreateDelegationTokenHandler,
+    create_partitions::CreatePartitionsHandler,
+    create_topics::CreateTopicsHandler,
+    delete_topics::DeleteTopicsHandler,
+    describe_client_quotas::DescribeClientQuotasHandler,
+    describe_cluster::DescribeClusterHandler,
+    describe_delegation_token::DescribeDelegationTokenHandler,
+    describe_log_dirs::DescribeLogDirsHandler,
+    describe_quorum::DescribeQuorumHandler,
+    describe_topic_partitions::DescribeTopicPartitionsHandler,
+    end_quorum_epoch::EndQuorumEpochHandler,
+    end_txn::EndTxnHandler,
+    expire_delegation_token::ExpireDelegationTokenHandler,
+    fetch::{FetchHandler, FetchPurgatory},
+    fetch_snapshot::FetchSnapshotHandler,
+    find_coordinator::FindCoordinatorHandler,
+    heartbeat::HeartbeatHandler,
+    init_producer_id::InitProducerIdHandler,
+    join_group::JoinGroupHandler,
+    leave_group::LeaveGroupHandler,
+    list_offsets::ListOffsetsHandler,
+    list_partition_reassignments::ListPartitionReassignmentsHandler,
+    metadata::MetadataHandler,
+    middleware::{BoxFuture, Middleware, Next, Reply, Request},
+    offset_commit::OffsetCommitHandler,
+    offset_delete::OffsetDeleteHandler,
+    offset_fetch::OffsetFetchHandler,
+    produce::{PartitionLocks, ProduceHandler},
+    remove_raft_voter::RemoveRaftVoterHandler,
+    renew_delegation_token::RenewDelegationTokenHandler,
+    sasl_authenticate::SaslAuthenticateHandler,
+    sasl_handshake::SaslHandshakeHandler,
+    share_fetch::ShareFetchHandler,
+    share_group_heartbeat::ShareGroupHeartbeatHandler,
+    sync_group::SyncGroupHandler,
+    update_features::UpdateFeaturesHandler,
+    vote::VoteHandler,
+};
+use crate::authorizer::Authorizer;
+use crate::config::SharedConfig;
+use crate::controller::Controller;
+use crate::coordinator::{GroupCoordinator, TransactionCoordinator};
+use crate::delegation_token::DelegationTokenManager;
+use crate::error::Error;
+use crate::features::FeatureCache;
+use crate::isr_manager::IsrManager;
+use crate::metadata_image::MetadataImage;
+use crate::partition_rates::PartitionRates;
+use crate::protocol::*;
+use crate::raft::MetadataQuorum;
+use crate::replica_fetcher::ReplicaFetchers;
+use crate::request_context::RequestContext;
+use crate::share_partition::SharePartitionManager;
+use crate::wire_debug::WireDump;
+
+/// Serves one API. Handlers own whatever broker state they need, so the
+/// dispatcher only has to find the right one.
+pub trait ApiHandler: Send + Sync {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error>;
+
+    /// The response for a request that is rejected without being handled,
+    /// e.g. because it timed out or its handler panicked. Fails for a body
+    /// too malformed to answer, where the response echoes the request.
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError>;
+
+    /// Writes the request's fields for the wire-debug log. Requests that
+    /// aren't described are logged by size alone.
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        _body: &mut Bytes,
+        _dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        Ok(())
+    }
+
+    /// Whether a request that runs past the request timeout may be answered
+    /// with REQUEST_TIMED_OUT. Only handlers that change nothing, or that
+    /// call [`RequestCommit::commit`](crate::request_context::RequestCommit::commit)
+    /// before their first change, may be; requests to the rest are waited
+    /// for however long they take.
+    fn may_time_out(&self) -> bool {
+        false
+    }
+}
+
+/// The versions of every registered API. The ApiVersions handler holds a
+/// copy, so it advertises whatever is registered when it is asked, in
+/// whichever order registration happened.
+#[derive(Clone, Default)]
+pub struct SupportedVersions(Arc<RwLock<BTreeMap<ApiKey, RangeInclusive<i16>>>>);
+
+impl SupportedVersions {
+    pub fn get(&self, api_key: ApiKey) -> Option<RangeInclusive<i16>> {
+        self.0.read().unwrap().get(&api_key).cloned()
+    }
+
+    /// Every registered API with its supported versions, ordered by key.
+    pub fn all(&self) -> Vec<(ApiKey, RangeInclusive<i16>)> {
+        let versions = self.0.read().unwrap();
+        versions
+            .iter()
+            .map(|(&key, versions)| (key, versions.clone()))
+            .collect()
+    }
+}
+
+/// The APIs the broker serves, with the versions of each it understands, and
+/// the middleware every request passes through on the way to its handler.
+/// ApiVersions advertises exactly what is registered here.
+#[derive(Default)]
+pub struct ApiRegistry {
+    apis: BTreeMap<ApiKey, Box<dyn ApiHandler>>,
+    versions: SupportedVersions,
+    layers: Vec<Box<dyn Middleware>>,
+}
+
+impl ApiRegistry {
+    /// Every API the broker serves, and the quorum's and the controller's
+    /// own when this node is one of the metadata quorum's voters.
+    #[allow(clippy::too_many_arguments)]
+    pub fn broker(
+        config: Arc<SharedConfig>,
+        cluster_id: String,
+        authorizer: Arc<dyn Authorizer>,
+        coordinator: GroupCoordinator,
+        transactions: Arc<TransactionCoordinator>,
+        quorum: Option<MetadataQuorum>,
+        controller: Option<Arc<Controller>>,
+        isr_manager: Arc<IsrManager>,
+        replica_fetchers: Arc<ReplicaFetchers>,
+        partition_rates: Arc<PartitionRates>,
+        fetches: Arc<FetchPurgatory>,
+        appends: Arc<PartitionLocks>,
+        image: Arc<MetadataImage>,
+        delegation_tokens: Arc<DelegationTokenManager>,
+        features: Arc<FeatureCache>,
+    ) -> Self {
+        let mut apis = Self::default();
+        apis.register(
+            ApiKey::Produce,
+            9..=11,
+            ProduceHandler::new(
+                config.get().node_id,
+                authorizer.clone(),
+                partition_rates.clone(),
+                fetches.clone(),
+                image.clone(),
+                config.get().log_dirs[0].clone(),
+                appends.clone(),
+            ),
+        );
+        apis.register(
+            ApiKey::ListOffsets,
+            6..=7,
+            ListOffsetsHandler::new(
+                config.get().node_id,
+                authorizer.clone(),
+                image.clone(),
+                config.get().log_dirs[0].clone(),
+                appends,
+            ),
+        );
+        apis.register(
+            ApiKey::Fetch,
+            16..=16,
+            FetchHandler::new(
+                config.get().node_id,
+                authorizer.clone(),
+                quorum.clone(),
+                isr_manager,
+                replica_fetchers,
+                partition_rates,
+                fetches,
+                image.clone(),
+                config.get().log_dirs[0].clone(),
+                config.get().fetch_session_cache_slots,
+            ),
+        );
+        if let Some(quorum) = quorum {
+            apis.register(
+                ApiKey::Vote,
+                0..=0,
+                VoteHandler::new(quorum.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::BeginQuorumEpoch,
+                0..=0,
+                BeginQuorumEpochHandler::new(quorum.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::EndQuorumEpoch,
+                0..=0,
+                EndQuorumEpochHandler::new(quorum.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::FetchSnapshot,
+                0..=0,
+                FetchSnapshotHandler::new(quorum.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::DescribeQuorum,
+                0..=1,
+                DescribeQuorumHandler::new(quorum.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::AddRaftVoter,
+                0..=0,
+                AddRaftVoterHandler::new(quorum.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::RemoveRaftVoter,
+                0..=0,
+                RemoveRaftVoterHandler::new(quorum, authorizer.clone()),
+            );
+        }
+        if let Some(controller) = controller.clone() {
+            apis.register(
+                ApiKey::AlterPartition,
+                2..=2,
+                AlterPartitionHandler::new(controller.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::BrokerRegistration,
+                0..=1,
+                BrokerRegistrationHandler::new(controller.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::BrokerHeartbeat,
+                0..=1,
+                BrokerHeartbeatHandler::new(controller.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::AssignReplicasToDirs,
+                0..=0,
+                AssignReplicasToDirsHandler::new(controller.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::CreateTopics,
+                5..=7,
+                CreateTopicsHandler::new(config.clone(), controller.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::DeleteTopics,
+                4..=6,
+                DeleteTopicsHandler::new(
+                    controller.clone(),
+                    coordinator.clone(),
+                    authorizer.clone(),
+                ),
+            );
+            apis.register(
+                ApiKey::CreatePartitions,
+                2..=3,
+                CreatePartitionsHandler::new(
+                    config.clone(),
+                    controller.clone(),
+                    authorizer.clone(),
+                ),
+            );
+            apis.register(
+                ApiKey::AlterConfigs,
+                0..=1,
+                AlterConfigsHandler::new(controller.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::AlterPartitionReassignments,
+                0..=0,
+                AlterPartitionReassignmentsHandler::new(controller.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::ListPartitionReassignments,
+                0..=0,
+                ListPartitionReassignmentsHandler::new(controller.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::AlterClientQuotas,
+                1..=1,
+                AlterClientQuotasHandler::new(controller.clone(), authorizer.clone()),
+            );
+            apis.register(
+                ApiKey::UpdateFeatures,
+                0..=1,
+                UpdateFeaturesHandler::new(controller, authorizer.clone()),
+            );
+        }
+        apis.register(
+            ApiKey::Metadata,
+            9..=12,
+            MetadataHandler::new(
+                config.clone(),
+                cluster_id.clone(),
+                authorizer.clone(),
+                controller,
+                image.clone(),
+            ),
+        );
+        apis.register(
+            ApiKey::OffsetCommit,
+            8..=9,
+            OffsetCommitHandler::new(coordinator.clone(), authorizer.clone(), image.clone()),
+        );
+        apis.register(
+            ApiKey::OffsetFetch,
+            6..=9,
+            OffsetFetchHandler::new(coordinator.clone(), authorizer.clone()),
+        );
+        apis.register(
+            ApiKey::OffsetDelete,
+            0..=0,
+            OffsetDeleteHandler::new(coordinator.clone(), authorizer.clone(), image.clone()),
+        );
+        apis.register(
+            ApiKey::FindCoordinator,
+            3..=4,
+            FindCoordinatorHandler::new(config.clone(), authorizer.clone()),
+        );
+        apis.register(
+            ApiKey::JoinGroup,
+            6..=9,
+            JoinGroupHandler::new(coordinator.clone(), authorizer.clone()),
+        );
+        apis.register(
+            ApiKey::Heartbeat,
+            4..=4,
+            HeartbeatHandler::new(coordinator.clone(), authorizer.clone()),
+        );
+        apis.register(
+            ApiKey::LeaveGroup,
+            4..=5,
+            LeaveGroupHandler::new(coordinator.clone(), authorizer.clone()),
+        );
+        apis.register(
+            ApiKey::SyncGroup,
+            4..=5,
+            SyncGroupHandler::new(coordinator.clone(), authorizer.clone()),
+        );
+        apis.register(
+            ApiKey::InitProducerId,
+            2..=5,
+            InitProducerIdHandler::new(transactions.clone(), authorizer.clone()),
+        );
+        apis.register(
+            ApiKey::AddPartitionsToTxn,
+            3..=3,
+            AddPartitionsToTxnHandler::new(transactions.clone(), authorizer.clone(), image.clone()),
+        );
+        apis.register(
+            ApiKey::EndTxn,
+            3..=4,
+            EndTxnHandler::new(transactions, authorizer.clone()),
+        );
+        apis.register(ApiKey::SaslHandshake, 1..=1, SaslHandshakeHandler);
+        apis.register(ApiKey::SaslAuthenticate, 0..=2, SaslAuthenticateHandler);
+        apis.register(
+            ApiKey::DescribeCluster,
+            0..=1,
+            DescribeClusterHandler::new(config.clone(), cluster_id, image.clone()),
+        );
+        apis.register(
+            ApiKey::ConsumerGroupHeartbeat,
+            0..=0,
+            ConsumerGroupHeartbeatHandler::new(
+                coordinator.clone(),
+                authorizer.clone(),
+                image.clone(),
+                features.clone(),
+            ),
+        );
+        apis.register(
+            ApiKey::ShareGroupHeartbeat,
+            0..=0,
+            ShareGroupHeartbeatHandler::new(
+                coordinator,
+                authorizer.clone(),
+                image.clone(),
+                features.clone(),
+            ),
+        );
+        apis.register(
+            ApiKey::ShareFetch,
+            0..=0,
+            ShareFetchHandler::new(
+                config.get().node_id,
+                authorizer.clone(),
+                image.clone(),
+                config.get().log_dirs[0].clone(),
+                features.clone(),
+                SharePartitionManager::new(&config.get().group_settings),
+            ),
+        );
+        apis.register(
+            ApiKey::DescribeLogDirs,
+            2..=4,
+            DescribeLogDirsHandler::new(
+                config.get().node_id,
+                authorizer.clone(),
+                image.clone(),
+                config.get().log_dirs.clone(),
+            ),
+        );
+        apis.register(
+            ApiKey::CreateDelegationToken,
+            2..=3,
+            CreateDelegationTokenHandler::new(
+                config.clone(),
+                authorizer.clone(),
+                delegation_tokens.clone(),
+            ),
+        );
+        apis.register(
+            ApiKey::RenewDelegationToken,
+            2..=2,
+            RenewDelegationTokenHandler::new(config.clone(), delegation_tokens.clone()),
+        );
+        apis.register(
+            ApiKey::ExpireDelegationToken,
+            2..=2,
+            ExpireDelegationTokenHandler::new(config.clone(), delegation_tokens.clone()),
+        );
+        apis.register(
+            ApiKey::DescribeDelegationToken,
+            2..=3,
+            DescribeDelegationTokenHandler::new(config, authorizer.clone(), delegation_tokens),
+        );
+        apis.register(
+            ApiKey::DescribeClientQuotas,
+            1..=1,
+            DescribeClientQuotasHandler::new(authorizer.clone(), image.clone()),
+        );
+        apis.register(
+            ApiKey::DescribeTopicPartitions,
+            0..=0,
+            DescribeTopicPartitionsHandler::new(authorizer, image),
+        );
+        apis.register(
+            ApiKey::ApiVersions,
+            0..=4,
+            ApiVersionsHandler::new(apis.supported_versions(), features),
+        );
+        apis
+    }
+
+    /// Registers `handler` for `versions` of `api_key`, replacing any handler
+    /// registered for it before.
+    pub fn register(
+        &mut self,
+        api_key: ApiKey,
+        versions: RangeInclusive<i16>,
+        handler: impl ApiHandler + 'static,
+    ) {
+        self.apis.insert(api_key, Box::new(handler));
+        self.versions.0.write().unwrap().insert(api_key, versions);
+    }
+
+    /// Wraps every handler in `layer`. Layers run in the order they are added,
+    /// the first outermost.
+    pub fn layer(&mut self, layer: impl Middleware + 'static) {
+        self.layers.push(Box::new(layer));
+    }
+
+    /// Passes `request` down the middleware to `handled`, which produces the
+    /// handler's response when awaited and is dropped unawaited if a layer
+    /// answers the request itself.
+    pub fn serve<'a>(
+        &'a self,
+        request: &'a Request,
+        handled: BoxFuture<'a, Result<Box<dyn Response>, Error>>,
+    ) -> BoxFuture<'a, Result<Reply, Error>> {
+        let Some(handler) = self.handler(request.api_key) else {
+            let api_key = request.api_key;
+            return Box::pin(async move {
+                Err(Error::UnsupportedApi(format!(
+                    "{:?} is not served",
+                    api_key
+                )))
+            });
+        };
+        Next {
+            layers: &self.layers,
+            handler,
+            handled,
+        }
+        .run(request)
+    }
+
+    /// The handler for `api_key`, if it is served at all.
+    pub fn handler(&self, api_key: ApiKey) -> Option<&dyn ApiHandler> {
+        self.apis.get(&api_key).map(|handler| handler.as_ref())
+    }
+
+    /// Whether `api_version` of `api_key` is one its handler parses.
+    pub fn supports(&self, api_key: ApiKey, api_version: i16) -> bool {
+        self.versions
+            .get(api_key)
+            .is_some_and(|versions| versions.contains(&api_version))
+    }
+
+    /// The versions of every registered API, kept up to date as more are
+    /// registered.
+    pub fn supported_versions(&self) -> SupportedVersions {
+        self.versions.clone()
+    }
+}
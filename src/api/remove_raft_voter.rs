@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use bytes::{Buf, Bytes};
+
+use crate::api::add_raft_voter::RaftVoterResponse;
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::raft::MetadataQuorum;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// RemoveRaftVoter request, v0.
+pub struct RemoveRaftVoterRequest {
+    pub cluster_id: Option<String>,
+    pub voter_id: i32,
+    pub voter_directory_id: Uuid,
+}
+
+impl Deserialize<Self> for RemoveRaftVoterRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let cluster_id = CompactNullableString::deserialize(src)?.0;
+        let voter_id = src.try_get_i32()?;
+        let voter_directory_id = Uuid::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            cluster_id,
+            voter_id,
+            voter_directory_id,
+        })
+    }
+}
+
+/// Served by the quorum's voters; only the leader can change the voter set.
+pub struct RemoveRaftVoterHandler {
+    quorum: MetadataQuorum,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl RemoveRaftVoterHandler {
+    pub fn new(quorum: MetadataQuorum, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self { quorum, authorizer }
+    }
+}
+
+impl ApiHandler for RemoveRaftVoterHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.quorum, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(RaftVoterResponse::new(ctx, error_code)))
+    }
+
+    fn describe_request(
+        &self,
+        _: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = RemoveRaftVoterRequest::deserialize(body)?;
+        dump.nullable("cluster_id", req.cluster_id.as_deref())
+            .field("voter_id", req.voter_id)
+            .field("voter_directory_id", &req.voter_directory_id);
+        Ok(())
+    }
+}
+
+/// Takes Alter on the cluster. The last voter can't be removed; a leader
+/// that removes itself resigns once the change is committed.
+pub fn handle_request(
+    ctx: &RequestContext,
+    quorum: &MetadataQuorum,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<RaftVoterResponse, Error> {
+    let req = RemoveRaftVoterRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::RemoveRaftVoter, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Alter,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(RaftVoterResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+        ));
+    }
+    if !quorum.is_cluster(req.cluster_id.as_deref()) {
+        return Ok(RaftVoterResponse::new(
+            ctx,
+            ErrorCode::InconsistentClusterId,
+        ));
+    }
+    let error_code = quorum
+        .remove_voter(req.voter_id)
+        .err()
+        .unwrap_or(ErrorCode::None);
+    Ok(RaftVoterResponse::new(ctx, error_code))
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::config::SharedConfig;
+use crate::delegation_token::{token_requests_allowed, DelegationTokenManager};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// RenewDelegationToken request, v2.
+pub struct RenewDelegationTokenRequest {
+    pub hmac: Bytes,
+    /// -1 for the configured expiry time.
+    pub renew_period_ms: i64,
+}
+
+impl Deserialize<Self> for RenewDelegationTokenRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let hmac = CompactBytes::deserialize(src)?.0;
+        let renew_period_ms = src.try_get_i64()?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            hmac,
+            renew_period_ms,
+        })
+    }
+}
+
+/// RenewDelegationToken response, v2.
+pub struct RenewDelegationTokenResponse {
+    header: HeaderV1,
+    error_code: ErrorCode,
+    expiry_timestamp_ms: i64,
+    throttle_time_ms: i32,
+}
+
+impl RenewDelegationTokenResponse {
+    fn new(ctx: &RequestContext, result: Result<i64, ErrorCode>) -> Self {
+        let (error_code, expiry_timestamp_ms) = match result {
+            Ok(expiry_timestamp_ms) => (ErrorCode::None, expiry_timestamp_ms),
+            Err(error_code) => (error_code, -1),
+        };
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            error_code,
+            expiry_timestamp_ms,
+            throttle_time_ms: 0,
+        }
+    }
+}
+
+impl Response for RenewDelegationTokenResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i16(self.error_code.into());
+        bytes.put_i64(self.expiry_timestamp_ms);
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+}
+
+/// Only the token's owner and renewers may renew it.
+pub struct RenewDelegationTokenHandler {
+    config: Arc<SharedConfig>,
+    tokens: Arc<DelegationTokenManager>,
+}
+
+impl RenewDelegationTokenHandler {
+    pub fn new(config: Arc<SharedConfig>, tokens: Arc<DelegationTokenManager>) -> Self {
+        Self { config, tokens }
+    }
+}
+
+impl ApiHandler for RenewDelegationTokenHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let req = RenewDelegationTokenRequest::deserialize(body)
+            .map_err(|e| Error::Decode(ApiKey::RenewDelegationToken, e))?;
+        let result = if !token_requests_allowed(&self.config.get(), ctx) {
+            Err(ErrorCode::DelegationTokenRequestNotAllowed)
+        } else if !ctx.commit.commit() {
+            Err(ErrorCode::RequestTimedOut)
+        } else {
+            self.tokens
+                .renew(&req.hmac, &ctx.principal, req.renew_period_ms)
+        };
+        Ok(Box::new(RenewDelegationTokenResponse::new(ctx, result)))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(RenewDelegationTokenResponse::new(
+            ctx,
+            Err(error_code),
+        )))
+    }
+
+    /// Leaves out the HMAC, which is the token's password.
+    fn describe_request(
+        &self,
+        _ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = RenewDelegationTokenRequest::deserialize(body)?;
+        dump.field("renew_period_ms", req.renew_period_ms);
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
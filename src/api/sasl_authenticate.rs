@@ -0,0 +1,127 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::sasl::Authenticator;
+use crate::security::KafkaPrincipal;
+
+pub struct SaslAuthenticateRequest {
+    pub auth_bytes: Bytes,
+}
+
+impl SaslAuthenticateRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let auth_bytes = if api_version >= 2 {
+            let auth_bytes = CompactBytes::deserialize(src)?.0;
+            TagBuffer::deserialize(src)?;
+            auth_bytes
+        } else {
+            KafkaBytes::deserialize(src)?.0
+        };
+        Ok(Self { auth_bytes })
+    }
+}
+
+/// SaslAuthenticate response, v0 through v2.
+pub struct SaslAuthenticateResponse {
+    api_version: i16,
+    correlation_id: i32,
+    error_code: ErrorCode,
+    error_message: Option<String>,
+    auth_bytes: Bytes,
+    session_lifetime_ms: i64,
+}
+
+impl Response for SaslAuthenticateResponse {
+    fn as_bytes(&self) -> Bytes {
+        let flexible = self.api_version >= 2;
+        let mut bytes = if flexible {
+            BytesMut::from(HeaderV1::new(self.correlation_id).serialize())
+        } else {
+            BytesMut::from(HeaderV0::new(self.correlation_id).serialize())
+        };
+        bytes.put_i16(self.error_code.into());
+        if flexible {
+            bytes.put(CompactNullableString(self.error_message.clone()).serialize());
+            bytes.put(CompactBytes(self.auth_bytes.clone()).serialize());
+        } else {
+            bytes.put(NullableString(self.error_message.clone()).serialize());
+            bytes.put(KafkaBytes(self.auth_bytes.clone()).serialize());
+        }
+        if self.api_version >= 1 {
+            bytes.put_i64(self.session_lifetime_ms);
+        }
+        if flexible {
+            bytes.put(TagBuffer::serialize());
+        }
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        self.error_message = Some(error_message.to_string());
+    }
+}
+
+impl SaslAuthenticateResponse {
+    pub fn error(ctx: &RequestContext, error_code: ErrorCode, error_message: &str) -> Self {
+        Self {
+            api_version: ctx.header.api_version,
+            correlation_id: ctx.header.correlation_id,
+            error_code,
+            error_message: Some(error_message.to_string()),
+            auth_bytes: Bytes::new(),
+            session_lifetime_ms: 0,
+        }
+    }
+}
+
+/// Runs one SaslAuthenticate round, returning the authenticated principal once
+/// the exchange completes.
+/// Registered so ApiVersions advertises SaslAuthenticate; see
+/// [`SaslHandshakeHandler`](crate::api::sasl_handshake::SaslHandshakeHandler).
+pub struct SaslAuthenticateHandler;
+
+impl ApiHandler for SaslAuthenticateHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        self.error_response(ctx, body, ErrorCode::IllegalSaslState)
+            .map_err(|e| Error::Decode(ApiKey::SaslAuthenticate, e))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(SaslAuthenticateResponse::error(
+            ctx,
+            error_code,
+            "Unexpected SaslAuthenticate request",
+        )))
+    }
+}
+
+pub fn handle_request(
+    ctx: &RequestContext,
+    message: &mut Bytes,
+    authenticator: &mut Authenticator,
+) -> Result<(SaslAuthenticateResponse, Option<KafkaPrincipal>), Error> {
+    let req = SaslAuthenticateRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::SaslAuthenticate, e))?;
+    let outcome = authenticator.authenticate(&req.auth_bytes);
+    let response = SaslAuthenticateResponse {
+        api_version: ctx.header.api_version,
+        correlation_id: ctx.header.correlation_id,
+        error_code: outcome.error_code,
+        error_message: outcome.error_message,
+        auth_bytes: Bytes::from(outcome.auth_bytes),
+        session_lifetime_ms: 0,
+    };
+    Ok((response, outcome.principal))
+}
@@ -0,0 +1,96 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::sasl::Authenticator;
+
+pub struct SaslHandshakeRequestV1 {
+    pub mechanism: NullableString,
+}
+
+impl Deserialize<Self> for SaslHandshakeRequestV1 {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self {
+            mechanism: NullableString::deserialize(src)?,
+        })
+    }
+}
+
+pub struct SaslHandshakeResponseV1 {
+    header: HeaderV0,
+    error_code: ErrorCode,
+    mechanisms: Array<NullableString>,
+}
+
+impl Response for SaslHandshakeResponseV1 {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i16(self.error_code.into());
+        bytes.put(self.mechanisms.serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+}
+
+impl SaslHandshakeResponseV1 {
+    pub fn error(ctx: &RequestContext, error_code: ErrorCode) -> Self {
+        Self {
+            header: HeaderV0::new(ctx.header.correlation_id),
+            error_code,
+            mechanisms: Array(Vec::new()),
+        }
+    }
+}
+
+/// Registered so ApiVersions advertises SaslHandshake. On SASL listeners the
+/// connection's authenticator answers it; reaching this handler means the
+/// listener has no SASL, so the request is out of place.
+pub struct SaslHandshakeHandler;
+
+impl ApiHandler for SaslHandshakeHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        self.error_response(ctx, body, ErrorCode::IllegalSaslState)
+            .map_err(|e| Error::Decode(ApiKey::SaslHandshake, e))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(SaslHandshakeResponseV1::error(ctx, error_code)))
+    }
+}
+
+pub fn handle_request(
+    ctx: &RequestContext,
+    message: &mut Bytes,
+    authenticator: &mut Authenticator,
+) -> Result<SaslHandshakeResponseV1, Error> {
+    let req = SaslHandshakeRequestV1::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::SaslHandshake, e))?;
+    let error_code = if ctx.header.api_version != 1 {
+        // v0 carries raw SASL tokens after the handshake, which we don't speak.
+        authenticator.fail();
+        ErrorCode::UnsupportedVersion
+    } else {
+        authenticator.handshake(req.mechanism.0.as_deref().unwrap_or_default())
+    };
+
+    let mechanisms = authenticator
+        .enabled_mechanisms()
+        .iter()
+        .map(|m| NullableString(Some(m.clone())))
+        .collect();
+    Ok(SaslHandshakeResponseV1 {
+        header: HeaderV0::new(ctx.header.correlation_id),
+        error_code,
+        mechanisms: Array(mechanisms),
+    })
+}
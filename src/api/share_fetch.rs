@@ -0,0 +1,561 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::fetch::{NodeEndpoint, PartitionLog, Segment};
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::error::Error;
+use crate::features::FeatureCache;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::share_partition::{
+    Acknowledgement, AcquiredRecords, SharePartitionManager, SHARE_FINAL_EPOCH, SHARE_INITIAL_EPOCH,
+};
+use crate::wire_debug::WireDump;
+
+/// ShareFetch request, v0.
+pub struct ShareFetchRequest {
+    pub group_id: String,
+    pub member_id: String,
+    /// 0 opens a share session, -1 closes it; otherwise the session's next
+    /// epoch.
+    pub share_session_epoch: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub topics: Vec<ShareFetchTopic>,
+    pub forgotten_topics_data: Vec<ShareFetchTopic>,
+}
+
+/// A topic's partitions, to fetch from or to forget.
+pub struct ShareFetchTopic {
+    pub topic_id: Uuid,
+    pub partitions: Vec<ShareFetchPartition>,
+}
+
+pub struct ShareFetchPartition {
+    pub partition_index: i32,
+    pub partition_max_bytes: i32,
+    /// Records fetched before, as the member acknowledges them.
+    pub acknowledgement_batches: Vec<Acknowledgement>,
+}
+
+impl Deserialize<Self> for ShareFetchRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let group_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let member_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let share_session_epoch = src.try_get_i32()?;
+        let max_wait_ms = src.try_get_i32()?;
+        let min_bytes = src.try_get_i32()?;
+        let max_bytes = src.try_get_i32()?;
+        let topics = CompactArray::deserialize_with(src, |src| {
+            let topic_id = Uuid::deserialize(src)?;
+            let partitions = CompactArray::deserialize_with(src, |src| {
+                let partition_index = src.try_get_i32()?;
+                let partition_max_bytes = src.try_get_i32()?;
+                let acknowledgement_batches = CompactArray::deserialize_with(src, |src| {
+                    let first_offset = src.try_get_i64()?;
+                    let last_offset = src.try_get_i64()?;
+                    let types = CompactArray::deserialize_with(src, |src| Ok(src.try_get_i8()?))?;
+                    TagBuffer::deserialize_fields(src)?;
+                    Ok(Acknowledgement {
+                        first_offset,
+                        last_offset,
+                        types,
+                    })
+                })?;
+                TagBuffer::deserialize_fields(src)?;
+                Ok(ShareFetchPartition {
+                    partition_index,
+                    partition_max_bytes,
+                    acknowledgement_batches,
+                })
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(ShareFetchTopic {
+                topic_id,
+                partitions,
+            })
+        })?;
+        let forgotten_topics_data = CompactArray::deserialize_with(src, |src| {
+            let topic_id = Uuid::deserialize(src)?;
+            let partitions = CompactArray::deserialize_with(src, |src| {
+                Ok(ShareFetchPartition {
+                    partition_index: src.try_get_i32()?,
+                    partition_max_bytes: 0,
+                    acknowledgement_batches: Vec::new(),
+                })
+            })?;
+            TagBuffer::deserialize_fields(src)?;
+            Ok(ShareFetchTopic {
+                topic_id,
+                partitions,
+            })
+        })?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            group_id,
+            member_id,
+            share_session_epoch,
+            max_wait_ms,
+            min_bytes,
+            max_bytes,
+            topics,
+            forgotten_topics_data,
+        })
+    }
+}
+
+/// ShareFetch response, v0.
+pub struct ShareFetchResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+    error_message: Option<String>,
+    responses: Vec<ShareFetchTopicResponse>,
+    node_endpoints: Vec<NodeEndpoint>,
+}
+
+pub struct ShareFetchTopicResponse {
+    pub topic_id: Uuid,
+    pub partitions: Vec<ShareFetchPartitionResponse>,
+}
+
+pub struct ShareFetchPartitionResponse {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    /// How the partition's acknowledgements went.
+    pub acknowledge_error_code: ErrorCode,
+    /// The leader id and epoch as this broker knows them.
+    pub current_leader: (i32, i32),
+    /// Whole batches covering the acquired records, which may hold records
+    /// the member didn't acquire and must skip.
+    pub records: Bytes,
+    pub acquired_records: Vec<AcquiredRecords>,
+}
+
+impl ShareFetchPartitionResponse {
+    fn new(partition_index: i32) -> Self {
+        Self {
+            partition_index,
+            error_code: ErrorCode::None,
+            acknowledge_error_code: ErrorCode::None,
+            current_leader: (-1, -1),
+            records: Bytes::new(),
+            acquired_records: Vec::new(),
+        }
+    }
+}
+
+impl ShareFetchResponse {
+    fn new(ctx: &RequestContext, error_code: ErrorCode) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            error_message: None,
+            responses: Vec::new(),
+            node_endpoints: Vec::new(),
+        }
+    }
+}
+
+impl Response for ShareFetchResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(CompactNullableString(self.error_message.clone()).serialize());
+        bytes.put_slice(&integer_encoding::VarInt::encode_var_vec(
+            self.responses.len() as u32 + 1,
+        ));
+        for topic in &self.responses {
+            bytes.put(topic.topic_id.serialize());
+            bytes.put_slice(&integer_encoding::VarInt::encode_var_vec(
+                topic.partitions.len() as u32 + 1,
+            ));
+            for p in &topic.partitions {
+                bytes.put_i32(p.partition_index);
+                bytes.put_i16(p.error_code.into());
+                bytes.put(CompactNullableString(None).serialize());
+                bytes.put_i16(p.acknowledge_error_code.into());
+                bytes.put(CompactNullableString(None).serialize());
+                bytes.put_i32(p.current_leader.0);
+                bytes.put_i32(p.current_leader.1);
+                bytes.put(TagBuffer::serialize());
+                bytes.put(CompactBytes(p.records.clone()).serialize());
+                bytes.put_slice(&integer_encoding::VarInt::encode_var_vec(
+                    p.acquired_records.len() as u32 + 1,
+                ));
+                for acquired in &p.acquired_records {
+                    bytes.put_i64(acquired.first_offset);
+                    bytes.put_i64(acquired.last_offset);
+                    bytes.put_i16(acquired.delivery_count);
+                    bytes.put(TagBuffer::serialize());
+                }
+                bytes.put(TagBuffer::serialize());
+            }
+            bytes.put(TagBuffer::serialize());
+        }
+        bytes.put(CompactArray(self.node_endpoints.clone()).serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        if self.error_code != ErrorCode::None {
+            return self.error_code;
+        }
+        self.responses
+            .iter()
+            .flat_map(|t| &t.partitions)
+            .flat_map(|p| [p.error_code, p.acknowledge_error_code])
+            .find(|&e| e != ErrorCode::None)
+            .unwrap_or(ErrorCode::None)
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        self.error_message = Some(error_message.to_string());
+    }
+
+    fn describe(&self, dump: &mut WireDump) {
+        dump.field("error_code", format_args!("{:?}", self.error_code))
+            .nullable("error_message", self.error_message.as_deref())
+            .list("responses", &self.responses, |dump, topic| {
+                dump.field("topic_id", &topic.topic_id).list(
+                    "partitions",
+                    &topic.partitions,
+                    |dump, p| {
+                        dump.field("partition_index", p.partition_index)
+                            .field("error_code", format_args!("{:?}", p.error_code))
+                            .field(
+                                "acknowledge_error_code",
+                                format_args!("{:?}", p.acknowledge_error_code),
+                            )
+                            .records("records", &p.records)
+                            .list("acquired_records", &p.acquired_records, |dump, a| {
+                                dump.field("first_offset", a.first_offset)
+                                    .field("last_offset", a.last_offset)
+                                    .field("delivery_count", a.delivery_count);
+                            });
+                    },
+                );
+            });
+    }
+}
+
+/// Served once share.version 1 is finalized; before that share groups are
+/// off and members are told the API is unsupported.
+pub struct ShareFetchHandler {
+    /// Only partitions led by this broker are served.
+    node_id: i32,
+    authorizer: Arc<dyn Authorizer>,
+    image: Arc<MetadataImage>,
+    /// Where the partitions this broker leads are read from.
+    log_dir: PathBuf,
+    features: Arc<FeatureCache>,
+    share_partitions: SharePartitionManager,
+}
+
+impl ShareFetchHandler {
+    pub fn new(
+        node_id: i32,
+        authorizer: Arc<dyn Authorizer>,
+        image: Arc<MetadataImage>,
+        log_dir: PathBuf,
+        features: Arc<FeatureCache>,
+        share_partitions: SharePartitionManager,
+    ) -> Self {
+        Self {
+            node_id,
+            authorizer,
+            image,
+            log_dir,
+            features,
+            share_partitions,
+        }
+    }
+}
+
+impl ApiHandler for ShareFetchHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        if !self.features.finalized().enables(ApiKey::ShareFetch) {
+            return self
+                .error_response(ctx, body, ErrorCode::UnsupportedVersion)
+                .map_err(|e| Error::Decode(ApiKey::ShareFetch, e));
+        }
+        let res = handle_request(
+            ctx,
+            self.node_id,
+            &*self.authorizer,
+            &self.image,
+            &self.log_dir,
+            &self.share_partitions,
+            body,
+        )?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(ShareFetchResponse::new(ctx, error_code)))
+    }
+
+    fn describe_request(
+        &self,
+        _: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = ShareFetchRequest::deserialize(body)?;
+        dump.field("group_id", &req.group_id)
+            .field("member_id", &req.member_id)
+            .field("share_session_epoch", req.share_session_epoch)
+            .field("max_wait_ms", req.max_wait_ms)
+            .field("max_bytes", req.max_bytes)
+            .list("topics", &req.topics, |dump, topic| {
+                dump.field("topic_id", &topic.topic_id).list(
+                    "partitions",
+                    &topic.partitions,
+                    |dump, p| {
+                        dump.field("partition_index", p.partition_index).list(
+                            "acknowledgement_batches",
+                            &p.acknowledgement_batches,
+                            |dump, ack| {
+                                dump.field("first_offset", ack.first_offset)
+                                    .field("last_offset", ack.last_offset)
+                                    .field("types", format_args!("{:?}", ack.types));
+                            },
+                        );
+                    },
+                );
+            })
+            .list(
+                "forgotten_topics_data",
+                &req.forgotten_topics_data,
+                |dump, topic| {
+                    let partitions: Vec<_> =
+                        topic.partitions.iter().map(|p| p.partition_index).collect();
+                    dump.field("topic_id", &topic.topic_id)
+                        .field("partitions", format_args!("{:?}", partitions));
+                },
+            );
+        Ok(())
+    }
+}
+
+/// Takes Read on the group, and on each topic. The member's
+/// acknowledgements are applied before anything is acquired, so what it
+/// releases can come straight back to it. Nothing waits for records to
+/// arrive: a fetch is answered with whatever is there, up to `max_bytes`
+/// across partitions. A fetch in an incremental session is answered only
+/// for partitions with records, errors or acknowledgements.
+pub fn handle_request(
+    ctx: &RequestContext,
+    node_id: i32,
+    authorizer: &dyn Authorizer,
+    image: &MetadataImage,
+    log_dir: &Path,
+    share_partitions: &SharePartitionManager,
+    message: &mut Bytes,
+) -> Result<ShareFetchResponse, Error> {
+    let req = ShareFetchRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::ShareFetch, e))?;
+    if req.group_id.is_empty() || req.member_id.is_empty() {
+        let mut res = ShareFetchResponse::new(ctx, ErrorCode::InvalidRequest);
+        res.set_error_message("GroupId and MemberId can't be empty.");
+        return Ok(res);
+    }
+    if !authorizer.authorize(ctx, AclOperation::Read, ResourceType::Group, &req.group_id) {
+        return Ok(ShareFetchResponse::new(
+            ctx,
+            ErrorCode::GroupAuthorizationFailed,
+        ));
+    }
+    let partitions_of = |topics: &[ShareFetchTopic]| -> Vec<(String, i32)> {
+        topics
+            .iter()
+            .flat_map(|t| {
+                t.partitions
+                    .iter()
+                    .map(|p| (t.topic_id.0.clone(), p.partition_index))
+            })
+            .collect()
+    };
+    let fetching = match share_partitions.begin_session(
+        &req.group_id,
+        &req.member_id,
+        req.share_session_epoch,
+        &partitions_of(&req.topics),
+        &partitions_of(&req.forgotten_topics_data),
+    ) {
+        Ok(fetching) => fetching,
+        Err(error_code) => return Ok(ShareFetchResponse::new(ctx, error_code)),
+    };
+
+    let record_batches = image.current();
+    let mut answers: BTreeMap<(String, i32), ShareFetchPartitionResponse> = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    // Checks a partition is one this broker serves the member, setting the
+    // answer's error if not.
+    let mut check = |topic_id: &Uuid, answer: &mut ShareFetchPartitionResponse| -> bool {
+        let Some(topic) = record_batches.topic(topic_id) else {
+            answer.error_code = ErrorCode::UnknownTopicId;
+            return false;
+        };
+        let topic_name = topic.topic_name.0.as_deref().unwrap_or_default();
+        if !authorizer.authorize(ctx, AclOperation::Read, ResourceType::Topic, topic_name) {
+            answer.error_code = ErrorCode::TopicAuthorizationFailed;
+            return false;
+        }
+        let Some(current) = record_batches.partition(topic_id, answer.partition_index) else {
+            answer.error_code = ErrorCode::UnknownTopicOrPartition;
+            return false;
+        };
+        answer.current_leader = (current.leader_id as i32, current.leader_epoch as i32);
+        if current.leader_id as i32 != node_id {
+            answer.error_code = ErrorCode::NotLeaderOrFollower;
+            if current.leader_id as i32 >= 0 {
+                leaders.insert(current.leader_id as i32);
+            }
+            return false;
+        }
+        true
+    };
+
+    for topic in &req.topics {
+        for p in &topic.partitions {
+            if p.acknowledgement_batches.is_empty() {
+                continue;
+            }
+            let mut answer = ShareFetchPartitionResponse::new(p.partition_index);
+            if check(&topic.topic_id, &mut answer) {
+                let key = (
+                    req.group_id.clone(),
+                    topic.topic_id.0.clone(),
+                    p.partition_index,
+                );
+                if let Err(error_code) =
+                    share_partitions.acknowledge(&key, &req.member_id, &p.acknowledgement_batches)
+                {
+                    answer.acknowledge_error_code = error_code;
+                }
+            } else {
+                answer.acknowledge_error_code = answer.error_code;
+            }
+            answers.insert((topic.topic_id.0.clone(), p.partition_index), answer);
+        }
+    }
+    if req.share_session_epoch == SHARE_FINAL_EPOCH {
+        share_partitions.release_member(&req.group_id, &req.member_id);
+    }
+
+    let max_bytes = req.max_bytes.max(0) as usize;
+    let mut total_bytes = 0;
+    for (topic_id, partition_index) in fetching {
+        let answer = answers
+            .entry((topic_id.clone(), partition_index))
+            .or_insert_with(|| ShareFetchPartitionResponse::new(partition_index));
+        if answer.error_code != ErrorCode::None {
+            continue;
+        }
+        let topic_id = Uuid(topic_id);
+        if !check(&topic_id, answer) || total_bytes >= max_bytes {
+            continue;
+        }
+        let Some(path) =
+            record_batches.segment_for_topic(log_dir, &topic_id, partition_index as u32)
+        else {
+            continue;
+        };
+        let context = || {
+            format!(
+                "read messages for topic '{}' in partition '{}'",
+                topic_id, partition_index
+            )
+        };
+        let mut segment = Segment::open(&path)
+            .with_context(context)
+            .map_err(Error::Storage)?;
+        let log = PartitionLog::scan(&mut segment)
+            .with_context(context)
+            .map_err(Error::Storage)?;
+        let key = (req.group_id.clone(), topic_id.0.clone(), partition_index);
+        let acquired = share_partitions.acquire(&key, &req.member_id, log.high_watermark);
+        // Acquired records come in offset order.
+        if let (Some(first), Some(last)) = (acquired.first(), acquired.last()) {
+            let start = log.position(first.first_offset);
+            let end = log.end_position(last.last_offset);
+            answer.records = segment
+                .read(start..end.max(start))
+                .with_context(context)
+                .map_err(Error::Storage)?;
+            total_bytes += answer.records.len();
+        }
+        answer.acquired_records = acquired;
+    }
+
+    let incremental = req.share_session_epoch != SHARE_INITIAL_EPOCH;
+    let acknowledged: BTreeSet<_> = req
+        .topics
+        .iter()
+        .flat_map(|t| {
+            t.partitions
+                .iter()
+                .filter(|p| !p.acknowledgement_batches.is_empty())
+                .map(|p| (t.topic_id.0.clone(), p.partition_index))
+        })
+        .collect();
+    let mut responses: Vec<ShareFetchTopicResponse> = Vec::new();
+    for ((topic_id, partition_index), answer) in answers {
+        if incremental
+            && answer.acquired_records.is_empty()
+            && answer.error_code == ErrorCode::None
+            && !acknowledged.contains(&(topic_id.clone(), partition_index))
+        {
+            continue;
+        }
+        match responses.last_mut() {
+            Some(topic) if topic.topic_id.0 == topic_id => topic.partitions.push(answer),
+            _ => responses.push(ShareFetchTopicResponse {
+                topic_id: Uuid(topic_id),
+                partitions: vec![answer],
+            }),
+        }
+    }
+    let node_endpoints = record_batches
+        .brokers()
+        .filter(|b| leaders.contains(&b.broker_id))
+        .filter_map(|b| {
+            let endpoint = b.endpoint(&ctx.listener_name)?;
+            Some(NodeEndpoint {
+                node_id: b.broker_id,
+                host: endpoint.host.clone(),
+                port: endpoint.port as i32,
+                rack: b.rack.clone(),
+            })
+        })
+        .collect();
+
+    let mut res = ShareFetchResponse::new(ctx, ErrorCode::None);
+    res.responses = responses;
+    res.node_endpoints = node_endpoints;
+    Ok(res)
+}
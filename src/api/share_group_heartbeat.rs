@@ -0,0 +1,171 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use bytes::{Buf, Bytes};
+
+use crate::api::consumer_group_heartbeat::{metadata_topics, ConsumerGroupHeartbeatResponse};
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::coordinator::{ConsumerGroupHeartbeatResult, GroupCoordinator, ShareGroupHeartbeat};
+use crate::error::Error;
+use crate::features::FeatureCache;
+use crate::metadata_image::MetadataImage;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// ShareGroupHeartbeat request, v0.
+pub struct ShareGroupHeartbeatRequest {
+    pub group_id: String,
+    pub member_id: String,
+    pub member_epoch: i32,
+    pub rack_id: Option<String>,
+    /// `None` when unchanged since the last heartbeat.
+    pub subscribed_topic_names: Option<Vec<String>>,
+}
+
+impl Deserialize<Self> for ShareGroupHeartbeatRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let group_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let member_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let member_epoch = src.try_get_i32()?;
+        let rack_id = CompactNullableString::deserialize(src)?.0;
+        // A length of 0 is the null array.
+        let subscribed_topic_names = if src.first() == Some(&0) {
+            src.advance(1);
+            None
+        } else {
+            Some(CompactArray::deserialize_with(src, |src| {
+                Ok(CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default())
+            })?)
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            group_id,
+            member_id,
+            member_epoch,
+            rack_id,
+            subscribed_topic_names,
+        })
+    }
+}
+
+/// Served once share.version 1 is finalized; before that share groups are
+/// off and members are told the API is unsupported.
+pub struct ShareGroupHeartbeatHandler {
+    coordinator: GroupCoordinator,
+    authorizer: Arc<dyn Authorizer>,
+    image: Arc<MetadataImage>,
+    features: Arc<FeatureCache>,
+}
+
+impl ShareGroupHeartbeatHandler {
+    pub fn new(
+        coordinator: GroupCoordinator,
+        authorizer: Arc<dyn Authorizer>,
+        image: Arc<MetadataImage>,
+        features: Arc<FeatureCache>,
+    ) -> Self {
+        Self {
+            coordinator,
+            authorizer,
+            image,
+            features,
+        }
+    }
+}
+
+impl ApiHandler for ShareGroupHeartbeatHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        if !self
+            .features
+            .finalized()
+            .enables(ApiKey::ShareGroupHeartbeat)
+        {
+            return self
+                .error_response(ctx, body, ErrorCode::UnsupportedVersion)
+                .map_err(|e| Error::Decode(ApiKey::ShareGroupHeartbeat, e));
+        }
+        Ok(Box::new(handle_request(
+            ctx,
+            &self.coordinator,
+            &*self.authorizer,
+            &self.image,
+            body,
+        )?))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        let req = ShareGroupHeartbeatRequest::deserialize(body)?;
+        let result = ConsumerGroupHeartbeatResult::error(req.member_id, error_code, None);
+        Ok(Box::new(ConsumerGroupHeartbeatResponse::new(
+            ctx,
+            result,
+            &[],
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        _: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = ShareGroupHeartbeatRequest::deserialize(body)?;
+        dump.field("group_id", &req.group_id)
+            .field("member_id", &req.member_id)
+            .field("member_epoch", req.member_epoch)
+            .nullable("rack_id", req.rack_id.as_deref())
+            .field(
+                "subscribed_topic_names",
+                format_args!("{:?}", req.subscribed_topic_names),
+            );
+        Ok(())
+    }
+}
+
+/// Takes Read on the group. Topics are named by id on the wire, as for
+/// consumer groups.
+pub fn handle_request(
+    ctx: &RequestContext,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    image: &MetadataImage,
+    message: &mut Bytes,
+) -> Result<ConsumerGroupHeartbeatResponse, Error> {
+    let req = ShareGroupHeartbeatRequest::deserialize(message)
+        .map_err(|e| Error::Decode(ApiKey::ShareGroupHeartbeat, e))?;
+    if !authorizer.authorize(ctx, AclOperation::Read, ResourceType::Group, &req.group_id) {
+        let result = ConsumerGroupHeartbeatResult::error(
+            req.member_id,
+            ErrorCode::GroupAuthorizationFailed,
+            None,
+        );
+        return Ok(ConsumerGroupHeartbeatResponse::new(ctx, result, &[]));
+    }
+    let record_batches = image.current();
+    let topics = metadata_topics(&record_batches);
+    let result = coordinator.share_group_heartbeat(ShareGroupHeartbeat {
+        group_id: req.group_id,
+        member_id: req.member_id,
+        member_epoch: req.member_epoch,
+        client_id: ctx.header.client_id.0.clone().unwrap_or_default(),
+        client_host: ctx.client_address.ip().to_string(),
+        subscribed_topic_names: req.subscribed_topic_names,
+        topics: topics
+            .iter()
+            .map(|topic| (topic.name.clone(), topic.partition_count))
+            .collect::<BTreeMap<_, _>>(),
+    });
+    Ok(ConsumerGroupHeartbeatResponse::new(ctx, result, &topics))
+}
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType};
+use crate::coordinator::{GroupCoordinator, SyncGroup, SyncGroupResult};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+
+pub struct SyncGroupRequest {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub protocol_type: Option<String>,
+    pub protocol_name: Option<String>,
+    pub assignments: Vec<SyncGroupRequestAssignment>,
+}
+
+impl SyncGroupRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let group_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let generation_id = src.try_get_i32()?;
+        let member_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let group_instance_id = CompactNullableString::deserialize(src)?.0;
+        let (protocol_type, protocol_name) = if api_version >= 5 {
+            (
+                CompactNullableString::deserialize(src)?.0,
+                CompactNullableString::deserialize(src)?.0,
+            )
+        } else {
+            (None, None)
+        };
+        let assignments = CompactArray::<SyncGroupRequestAssignment>::deserialize(src)?;
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            group_id,
+            generation_id,
+            member_id,
+            group_instance_id,
+            protocol_type,
+            protocol_name,
+            assignments,
+        })
+    }
+}
+
+pub struct SyncGroupRequestAssignment {
+    pub member_id: String,
+    pub assignment: Bytes,
+}
+
+impl Deserialize<Self> for SyncGroupRequestAssignment {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let member_id = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let assignment = CompactBytes::deserialize(src)?.0;
+        TagBuffer::deserialize(src)?;
+        Ok(Self {
+            member_id,
+            assignment,
+        })
+    }
+}
+
+/// SyncGroup response, v4 and v5.
+pub struct SyncGroupResponse {
+    api_version: i16,
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+    protocol_type: CompactNullableString,
+    protocol_name: CompactNullableString,
+    assignment: CompactBytes,
+}
+
+impl Response for SyncGroupResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        if self.api_version >= 5 {
+            bytes.put(self.protocol_type.serialize());
+            bytes.put(self.protocol_name.serialize());
+        }
+        bytes.put(self.assignment.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+}
+
+impl SyncGroupResponse {
+    fn new(ctx: &RequestContext, result: SyncGroupResult) -> Self {
+        Self {
+            api_version: ctx.header.api_version,
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code: result.error_code,
+            protocol_type: CompactNullableString(result.protocol_type),
+            protocol_name: CompactNullableString(result.protocol_name),
+            assignment: CompactBytes(result.assignment),
+        }
+    }
+}
+
+pub struct SyncGroupHandler {
+    coordinator: GroupCoordinator,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl SyncGroupHandler {
+    pub fn new(coordinator: GroupCoordinator, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            coordinator,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for SyncGroupHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.coordinator, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(SyncGroupResponse::new(
+            ctx,
+            SyncGroupResult::error(error_code),
+        )))
+    }
+}
+
+/// Followers wait here until the leader sends the assignment.
+pub fn handle_request(
+    ctx: &RequestContext,
+    coordinator: &GroupCoordinator,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<SyncGroupResponse, Error> {
+    let req = SyncGroupRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::SyncGroup, e))?;
+    if !authorizer.authorize(ctx, AclOperation::Read, ResourceType::Group, &req.group_id) {
+        let result = SyncGroupResult::error(ErrorCode::GroupAuthorizationFailed);
+        return Ok(SyncGroupResponse::new(ctx, result));
+    }
+    let result = coordinator.sync_group(SyncGroup {
+        group_id: req.group_id,
+        generation_id: req.generation_id,
+        member_id: req.member_id,
+        protocol_type: req.protocol_type,
+        protocol_name: req.protocol_name,
+        assignments: req
+            .assignments
+            .into_iter()
+            .map(|a| (a.member_id, a.assignment))
+            .collect(),
+    });
+    Ok(SyncGroupResponse::new(ctx, result))
+}
@@ -0,0 +1,260 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::controller::Controller;
+use crate::error::Error;
+use crate::protocol::*;
+use crate::request_context::RequestContext;
+use crate::wire_debug::WireDump;
+
+/// How a v1 update may move a feature's level.
+const UPGRADE: i8 = 1;
+const SAFE_DOWNGRADE: i8 = 2;
+const UNSAFE_DOWNGRADE: i8 = 3;
+
+/// UpdateFeatures request, v0 and v1.
+pub struct UpdateFeaturesRequest {
+    pub timeout_ms: i32,
+    pub updates: Vec<FeatureUpdate>,
+    /// Since v1.
+    pub validate_only: bool,
+}
+
+pub struct FeatureUpdate {
+    pub feature: String,
+    /// The level to finalize; 0 turns the feature off.
+    pub max_version_level: i16,
+    /// v0's allow_downgrade is read as a safe downgrade.
+    pub upgrade_type: i8,
+}
+
+impl UpdateFeaturesRequest {
+    pub fn deserialize(src: &mut Bytes, api_version: i16) -> Result<Self, DecodeError> {
+        let timeout_ms = src.try_get_i32()?;
+        let updates = CompactArray::deserialize_with(src, |src| {
+            let feature = CompactNullableString::deserialize(src)?
+                .0
+                .unwrap_or_default();
+            let max_version_level = src.try_get_i16()?;
+            let upgrade_type = if api_version >= 1 {
+                src.try_get_i8()?
+            } else if src.try_get_u8()? != 0 {
+                SAFE_DOWNGRADE
+            } else {
+                UPGRADE
+            };
+            TagBuffer::deserialize_fields(src)?;
+            Ok(FeatureUpdate {
+                feature,
+                max_version_level,
+                upgrade_type,
+            })
+        })?;
+        let validate_only = api_version >= 1 && src.try_get_u8()? != 0;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            timeout_ms,
+            updates,
+            validate_only,
+        })
+    }
+}
+
+/// UpdateFeatures response, v0 and v1.
+pub struct UpdateFeaturesResponse {
+    header: HeaderV1,
+    throttle_time_ms: i32,
+    error_code: ErrorCode,
+    error_message: Option<String>,
+    results: CompactArray<UpdateFeaturesResult>,
+}
+
+pub struct UpdateFeaturesResult {
+    pub feature: String,
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+}
+
+impl Serialize for UpdateFeaturesResult {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.feature.clone())).serialize());
+        b.put_i16(self.error_code.into());
+        b.put(CompactNullableString(self.error_message.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl UpdateFeaturesResponse {
+    fn new(
+        ctx: &RequestContext,
+        error_code: ErrorCode,
+        results: Vec<UpdateFeaturesResult>,
+    ) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            throttle_time_ms: 0,
+            error_code,
+            error_message: None,
+            results: CompactArray(results),
+        }
+    }
+}
+
+impl Response for UpdateFeaturesResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i32(self.throttle_time_ms);
+        bytes.put_i16(self.error_code.into());
+        bytes.put(CompactNullableString(self.error_message.clone()).serialize());
+        bytes.put(self.results.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        if self.error_code != ErrorCode::None {
+            return self.error_code;
+        }
+        self.results
+            .0
+            .iter()
+            .map(|r| r.error_code)
+            .find(|&e| e != ErrorCode::None)
+            .unwrap_or(ErrorCode::None)
+    }
+
+    fn set_throttle_time_ms(&mut self, throttle_time_ms: i32) {
+        self.throttle_time_ms = throttle_time_ms;
+    }
+
+    fn set_error_message(&mut self, error_message: &str) {
+        self.error_message = Some(error_message.to_string());
+    }
+}
+
+/// Served by nodes that are also controllers, which finalize the new levels
+/// in the metadata log.
+pub struct UpdateFeaturesHandler {
+    controller: Arc<Controller>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl UpdateFeaturesHandler {
+    pub fn new(controller: Arc<Controller>, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self {
+            controller,
+            authorizer,
+        }
+    }
+}
+
+impl ApiHandler for UpdateFeaturesHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.controller, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(UpdateFeaturesResponse::new(
+            ctx,
+            error_code,
+            Vec::new(),
+        )))
+    }
+
+    fn describe_request(
+        &self,
+        ctx: &RequestContext,
+        body: &mut Bytes,
+        dump: &mut WireDump,
+    ) -> Result<(), DecodeError> {
+        let req = UpdateFeaturesRequest::deserialize(body, ctx.header.api_version)?;
+        dump.field("timeout_ms", req.timeout_ms)
+            .list("updates", &req.updates, |dump, update| {
+                dump.field("feature", &update.feature)
+                    .field("max_version_level", update.max_version_level)
+                    .field("upgrade_type", update.upgrade_type);
+            })
+            .field("validate_only", req.validate_only);
+        Ok(())
+    }
+
+    fn may_time_out(&self) -> bool {
+        true
+    }
+}
+
+/// Takes Alter on the cluster. Each feature is updated on its own, and a
+/// feature named twice is an invalid request.
+pub fn handle_request(
+    ctx: &RequestContext,
+    controller: &Controller,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<UpdateFeaturesResponse, Error> {
+    let req = UpdateFeaturesRequest::deserialize(message, ctx.header.api_version)
+        .map_err(|e| Error::Decode(ApiKey::UpdateFeatures, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::Alter,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(UpdateFeaturesResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    let mut seen = HashSet::new();
+    let results = req
+        .updates
+        .into_iter()
+        .map(|update| {
+            let error_code = if !seen.insert(update.feature.clone()) {
+                ErrorCode::InvalidRequest
+            } else if !req.validate_only && !ctx.commit.commit() {
+                ErrorCode::RequestTimedOut
+            } else {
+                update_feature(controller, &update, req.validate_only)
+                    .err()
+                    .unwrap_or(ErrorCode::None)
+            };
+            UpdateFeaturesResult {
+                feature: update.feature,
+                error_code,
+                error_message: None,
+            }
+        })
+        .collect();
+    Ok(UpdateFeaturesResponse::new(ctx, ErrorCode::None, results))
+}
+
+fn update_feature(
+    controller: &Controller,
+    update: &FeatureUpdate,
+    validate_only: bool,
+) -> Result<(), ErrorCode> {
+    let allow_downgrade = match update.upgrade_type {
+        UPGRADE => false,
+        SAFE_DOWNGRADE | UNSAFE_DOWNGRADE => true,
+        _ => return Err(ErrorCode::InvalidRequest),
+    };
+    controller.update_feature(
+        &update.feature,
+        update.max_version_level,
+        allow_downgrade,
+        validate_only,
+    )
+}
@@ -0,0 +1,337 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::api::ApiHandler;
+use crate::authorizer::{AclOperation, Authorizer, ResourceType, CLUSTER_RESOURCE_NAME};
+use crate::error::Error;
+use crate::protocol::*;
+use crate::raft::{MetadataQuorum, Vote, METADATA_TOPIC};
+use crate::request_context::RequestContext;
+
+/// Vote request, v0: a candidate asking for this voter's vote.
+#[derive(Clone)]
+pub struct VoteRequest {
+    pub cluster_id: Option<String>,
+    pub topics: Vec<VoteRequestTopic>,
+}
+
+#[derive(Clone)]
+pub struct VoteRequestTopic {
+    pub topic_name: String,
+    pub partitions: Vec<VoteRequestPartition>,
+}
+
+#[derive(Clone)]
+pub struct VoteRequestPartition {
+    pub partition_index: i32,
+    pub candidate_epoch: i32,
+    pub candidate_id: i32,
+    pub last_offset_epoch: i32,
+    pub last_offset: i64,
+}
+
+impl VoteRequest {
+    /// The request a candidate sends for the metadata partition.
+    pub fn metadata(cluster_id: &str, vote: &Vote) -> Self {
+        Self {
+            cluster_id: Some(cluster_id.to_string()),
+            topics: vec![VoteRequestTopic {
+                topic_name: METADATA_TOPIC.to_string(),
+                partitions: vec![VoteRequestPartition {
+                    partition_index: 0,
+                    candidate_epoch: vote.candidate_epoch,
+                    candidate_id: vote.candidate_id,
+                    last_offset_epoch: vote.last_offset_epoch,
+                    last_offset: vote.last_offset,
+                }],
+            }],
+        }
+    }
+}
+
+impl Deserialize<Self> for VoteRequest {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let cluster_id = CompactNullableString::deserialize(src)?.0;
+        let topics = CompactArray::<VoteRequestTopic>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self { cluster_id, topics })
+    }
+}
+
+impl Serialize for VoteRequest {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(self.cluster_id.clone()).serialize());
+        b.put(CompactArray(self.topics.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for VoteRequestTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topic_name = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let partitions = CompactArray::<VoteRequestPartition>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            topic_name,
+            partitions,
+        })
+    }
+}
+
+impl Serialize for VoteRequestTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.topic_name.clone())).serialize());
+        b.put(CompactArray(self.partitions.clone()).serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for VoteRequestPartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let partition = Self {
+            partition_index: src.try_get_i32()?,
+            candidate_epoch: src.try_get_i32()?,
+            candidate_id: src.try_get_i32()?,
+            last_offset_epoch: src.try_get_i32()?,
+            last_offset: src.try_get_i64()?,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(partition)
+    }
+}
+
+impl Serialize for VoteRequestPartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i32(self.candidate_epoch);
+        b.put_i32(self.candidate_id);
+        b.put_i32(self.last_offset_epoch);
+        b.put_i64(self.last_offset);
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+/// Vote response, v0.
+pub struct VoteResponse {
+    header: HeaderV1,
+    pub error_code: ErrorCode,
+    pub topics: CompactArray<VoteResponseTopic>,
+}
+
+pub struct VoteResponseTopic {
+    pub topic_name: String,
+    pub partitions: CompactArray<VoteResponsePartition>,
+}
+
+pub struct VoteResponsePartition {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub vote_granted: bool,
+}
+
+impl VoteResponse {
+    fn new(ctx: &RequestContext, error_code: ErrorCode, topics: Vec<VoteResponseTopic>) -> Self {
+        Self {
+            header: HeaderV1::new(ctx.header.correlation_id),
+            error_code,
+            topics: CompactArray(topics),
+        }
+    }
+
+    /// The answer for the metadata partition, if the response has one.
+    pub fn metadata_partition(&self) -> Option<&VoteResponsePartition> {
+        self.topics
+            .0
+            .iter()
+            .filter(|t| t.topic_name == METADATA_TOPIC)
+            .flat_map(|t| &t.partitions.0)
+            .find(|p| p.partition_index == 0)
+    }
+}
+
+impl Response for VoteResponse {
+    fn as_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::from(self.header.serialize());
+        bytes.put_i16(self.error_code.into());
+        bytes.put(self.topics.serialize());
+        bytes.put(TagBuffer::serialize());
+        bytes.freeze()
+    }
+
+    fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+}
+
+/// Reads a whole response, header included, as the candidate gets it.
+impl Deserialize<Self> for VoteResponse {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let header = HeaderV1::deserialize(src)?;
+        let error_code = ErrorCode::from(src.try_get_i16()?);
+        let topics = CompactArray::<VoteResponseTopic>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            header,
+            error_code,
+            topics: CompactArray(topics),
+        })
+    }
+}
+
+impl Serialize for VoteResponseTopic {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put(CompactNullableString(Some(self.topic_name.clone())).serialize());
+        b.put(self.partitions.serialize());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for VoteResponseTopic {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let topic_name = CompactNullableString::deserialize(src)?
+            .0
+            .unwrap_or_default();
+        let partitions = CompactArray::<VoteResponsePartition>::deserialize(src)?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self {
+            topic_name,
+            partitions: CompactArray(partitions),
+        })
+    }
+}
+
+impl Serialize for VoteResponsePartition {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.partition_index);
+        b.put_i16(self.error_code.into());
+        b.put_i32(self.leader_id);
+        b.put_i32(self.leader_epoch);
+        b.put_u8(self.vote_granted.into());
+        b.put(TagBuffer::serialize());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for VoteResponsePartition {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let partition = Self {
+            partition_index: src.try_get_i32()?,
+            error_code: ErrorCode::from(src.try_get_i16()?),
+            leader_id: src.try_get_i32()?,
+            leader_epoch: src.try_get_i32()?,
+            vote_granted: src.try_get_u8()? != 0,
+        };
+        TagBuffer::deserialize_fields(src)?;
+        Ok(partition)
+    }
+}
+
+pub struct VoteHandler {
+    quorum: MetadataQuorum,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl VoteHandler {
+    pub fn new(quorum: MetadataQuorum, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self { quorum, authorizer }
+    }
+}
+
+impl ApiHandler for VoteHandler {
+    fn handle(&self, ctx: &RequestContext, body: &mut Bytes) -> Result<Box<dyn Response>, Error> {
+        let res = handle_request(ctx, &self.quorum, &*self.authorizer, body)?;
+        Ok(Box::new(res))
+    }
+
+    fn error_response(
+        &self,
+        ctx: &RequestContext,
+        _: &mut Bytes,
+        error_code: ErrorCode,
+    ) -> Result<Box<dyn Response>, DecodeError> {
+        Ok(Box::new(VoteResponse::new(ctx, error_code, Vec::new())))
+    }
+}
+
+/// Only the metadata partition has a quorum; any other partition is
+/// UNKNOWN_TOPIC_OR_PARTITION.
+pub fn handle_request(
+    ctx: &RequestContext,
+    quorum: &MetadataQuorum,
+    authorizer: &dyn Authorizer,
+    message: &mut Bytes,
+) -> Result<VoteResponse, Error> {
+    let req = VoteRequest::deserialize(message).map_err(|e| Error::Decode(ApiKey::Vote, e))?;
+    if !authorizer.authorize(
+        ctx,
+        AclOperation::ClusterAction,
+        ResourceType::Cluster,
+        CLUSTER_RESOURCE_NAME,
+    ) {
+        return Ok(VoteResponse::new(
+            ctx,
+            ErrorCode::ClusterAuthorizationFailed,
+            Vec::new(),
+        ));
+    }
+    if !quorum.is_cluster(req.cluster_id.as_deref()) {
+        return Ok(VoteResponse::new(
+            ctx,
+            ErrorCode::InconsistentClusterId,
+            Vec::new(),
+        ));
+    }
+    let topics = req
+        .topics
+        .into_iter()
+        .map(|topic| {
+            let partitions = topic
+                .partitions
+                .iter()
+                .map(|p| {
+                    if topic.topic_name != METADATA_TOPIC || p.partition_index != 0 {
+                        return VoteResponsePartition {
+                            partition_index: p.partition_index,
+                            error_code: ErrorCode::UnknownTopicOrPartition,
+                            leader_id: -1,
+                            leader_epoch: -1,
+                            vote_granted: false,
+                        };
+                    }
+                    let result = quorum.vote(Vote {
+                        candidate_epoch: p.candidate_epoch,
+                        candidate_id: p.candidate_id,
+                        last_offset_epoch: p.last_offset_epoch,
+                        last_offset: p.last_offset,
+                    });
+                    VoteResponsePartition {
+                        partition_index: p.partition_index,
+                        error_code: result.error_code,
+                        leader_id: result.leader_id,
+                        leader_epoch: result.leader_epoch,
+                        vote_granted: result.vote_granted,
+                    }
+                })
+                .collect();
+            VoteResponseTopic {
+                topic_name: topic.topic_name,
+                partitions: CompactArray(partitions),
+            }
+        })
+        .collect();
+    Ok(VoteResponse::new(ctx, ErrorCode::None, topics))
+}
@@ -0,0 +1,162 @@
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use tracing::error;
+
+use crate::{
+    protocol::{ApiKey, ErrorCode},
+    security::KafkaPrincipal,
+};
+
+/// Where the audit journal goes and when it rolls over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditLogSettings {
+    pub path: PathBuf,
+    /// The journal is rolled once it would grow past this.
+    pub max_bytes: u64,
+    /// Rolled files kept as `<path>.1` (newest) to `<path>.<max_files>`.
+    pub max_files: usize,
+}
+
+/// One handled request, as written to the audit journal.
+pub struct AuditRecord {
+    pub timestamp: SystemTime,
+    pub api_key: ApiKey,
+    pub api_version: i16,
+    pub correlation_id: i32,
+    pub client_id: String,
+    pub principal: KafkaPrincipal,
+    pub client_address: SocketAddr,
+    pub error_code: ErrorCode,
+    pub latency: Duration,
+}
+
+impl Display for AuditRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let timestamp_ms = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        write!(
+            f,
+            "timestamp_ms={} api_key={:?} api_version={} correlation_id={} client_id={:?} \
+             principal={} client_address={} error_code={:?} latency_us={}",
+            timestamp_ms,
+            self.api_key,
+            self.api_version,
+            self.correlation_id,
+            self.client_id,
+            self.principal,
+            self.client_address,
+            self.error_code,
+            self.latency.as_micros(),
+        )
+    }
+}
+
+/// An append-only journal of every request, one line each, for security
+/// audits and postmortems.
+///
+/// Lines are written by a dedicated thread so request handling never waits on
+/// the disk; the file is flushed whenever the thread catches up.
+pub struct AuditLog {
+    lines: mpsc::Sender<String>,
+}
+
+impl AuditLog {
+    pub fn open(settings: AuditLogSettings) -> Result<Self> {
+        let mut writer = JournalWriter::open(settings)?;
+        let (lines, rx) = mpsc::channel::<String>();
+        std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || {
+                while let Ok(line) = rx.recv() {
+                    let mut res = writer.write(&line);
+                    while let (Ok(()), Ok(line)) = (&res, rx.try_recv()) {
+                        res = writer.write(&line);
+                    }
+                    if let Err(e) = res.and_then(|()| writer.flush()) {
+                        error!(error = %e, "failed to write audit log");
+                    }
+                }
+            })
+            .context("start audit log writer")?;
+        Ok(Self { lines })
+    }
+
+    pub fn record(&self, record: &AuditRecord) {
+        // The writer only stops once every sender is gone.
+        let _ = self.lines.send(record.to_string());
+    }
+}
+
+struct JournalWriter {
+    settings: AuditLogSettings,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl JournalWriter {
+    fn open(settings: AuditLogSettings) -> Result<Self> {
+        let (file, size) = open_append(&settings.path)?;
+        Ok(Self {
+            settings,
+            file,
+            size,
+        })
+    }
+
+    fn write(&mut self, line: &str) -> Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.settings.max_bytes {
+            self.roll()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1`, dropping the oldest, and starts a
+    /// new file at `path`.
+    fn roll(&mut self) -> Result<()> {
+        self.file.flush()?;
+        let path = &self.settings.path;
+        let rolled = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+        if self.settings.max_files == 0 {
+            std::fs::remove_file(path)?;
+        } else {
+            for n in (1..self.settings.max_files).rev() {
+                if rolled(n).exists() {
+                    std::fs::rename(rolled(n), rolled(n + 1))?;
+                }
+            }
+            std::fs::rename(path, rolled(1))?;
+        }
+        (self.file, self.size) = open_append(path)?;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open audit log '{}'", path.display()))?;
+    let size = file.metadata()?.len();
+    Ok((BufWriter::new(file), size))
+}
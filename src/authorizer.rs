@@ -0,0 +1,322 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{request_context::RequestContext, security::KafkaPrincipal};
+
+/// Matches any principal name or client host in an ACL.
+pub const WILDCARD: &str = "*";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclOperation {
+    All,
+    Read,
+    Write,
+    Create,
+    Delete,
+    Alter,
+    Describe,
+    ClusterAction,
+    DescribeConfigs,
+    AlterConfigs,
+    IdempotentWrite,
+    CreateTokens,
+    DescribeTokens,
+}
+
+impl AclOperation {
+    /// Whether an ACL granting `self` also grants `requested`. As in Kafka,
+    /// being allowed to read, write, delete or alter a resource implies being
+    /// allowed to describe it, and altering configs implies describing them.
+    fn implies(self, requested: AclOperation) -> bool {
+        use AclOperation::*;
+        match (self, requested) {
+            (All, _) => true,
+            (Read | Write | Delete | Alter, Describe) => true,
+            (AlterConfigs, DescribeConfigs) => true,
+            (granted, requested) => granted == requested,
+        }
+    }
+}
+
+impl FromStr for AclOperation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "").as_str() {
+            "all" => Ok(Self::All),
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            "create" => Ok(Self::Create),
+            "delete" => Ok(Self::Delete),
+            "alter" => Ok(Self::Alter),
+            "describe" => Ok(Self::Describe),
+            "clusteraction" => Ok(Self::ClusterAction),
+            "describeconfigs" => Ok(Self::DescribeConfigs),
+            "alterconfigs" => Ok(Self::AlterConfigs),
+            "idempotentwrite" => Ok(Self::IdempotentWrite),
+            "createtokens" => Ok(Self::CreateTokens),
+            "describetokens" => Ok(Self::DescribeTokens),
+            _ => Err(anyhow!("unknown ACL operation '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceType {
+    Topic,
+    Group,
+    Cluster,
+    TransactionalId,
+    /// A user, by name, that delegation tokens are created for.
+    User,
+}
+
+impl FromStr for ResourceType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "").as_str() {
+            "topic" => Ok(Self::Topic),
+            "group" => Ok(Self::Group),
+            "cluster" => Ok(Self::Cluster),
+            "transactionalid" => Ok(Self::TransactionalId),
+            "user" => Ok(Self::User),
+            _ => Err(anyhow!("unknown resource type '{}'", s)),
+        }
+    }
+}
+
+impl Display for ResourceType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Topic => "Topic",
+            Self::Group => "Group",
+            Self::Cluster => "Cluster",
+            Self::TransactionalId => "TransactionalId",
+            Self::User => "User",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The cluster is a single resource with this name.
+pub const CLUSTER_RESOURCE_NAME: &str = "kafka-cluster";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclPermission {
+    Allow,
+    Deny,
+}
+
+/// The resources an ACL applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourcePattern {
+    /// Every resource of the type.
+    Any(ResourceType),
+    Literal(ResourceType, String),
+    /// Every resource whose name starts with the prefix.
+    Prefixed(ResourceType, String),
+}
+
+impl ResourcePattern {
+    fn matches(&self, resource_type: ResourceType, name: &str) -> bool {
+        match self {
+            Self::Any(t) => *t == resource_type,
+            Self::Literal(t, literal) => *t == resource_type && literal == name,
+            Self::Prefixed(t, prefix) => *t == resource_type && name.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+/// One access control entry: `principal`, connecting from `host`, is allowed
+/// or denied `operation` on the resources matching `pattern`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclBinding {
+    pub permission: AclPermission,
+    /// A principal whose name is [`WILDCARD`] matches every principal of its
+    /// type.
+    pub principal: KafkaPrincipal,
+    pub operation: AclOperation,
+    pub pattern: ResourcePattern,
+    /// A client IP address, or [`WILDCARD`].
+    pub host: String,
+}
+
+impl AclBinding {
+    fn matches_principal(&self, ctx: &RequestContext) -> bool {
+        self.principal.principal_type == ctx.principal.principal_type
+            && (self.principal.name == WILDCARD || self.principal.name == ctx.principal.name)
+    }
+
+    fn matches_host(&self, ctx: &RequestContext) -> bool {
+        self.host == WILDCARD || self.host == ctx.client_address.ip().to_string()
+    }
+}
+
+impl FromStr for AclBinding {
+    type Err = anyhow::Error;
+
+    /// Parses `<allow|deny> <principal> <operation> <type>:<name> [from <host>]`.
+    /// A name of `*` matches every resource of the type and a name ending in
+    /// `*` matches by prefix.
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let host = match fields.as_slice() {
+            [_, _, _, _] => WILDCARD,
+            [_, _, _, _, from, host] if from.eq_ignore_ascii_case("from") => host,
+            _ => {
+                return Err(anyhow!(
+                    "expected '<allow|deny> <principal> <operation> <type>:<name> [from <host>]'"
+                ))
+            }
+        };
+        let permission = match fields[0].to_lowercase().as_str() {
+            "allow" => AclPermission::Allow,
+            "deny" => AclPermission::Deny,
+            other => return Err(anyhow!("unknown ACL permission '{}'", other)),
+        };
+        let (resource_type, name) = fields[3]
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid resource '{}', expected <type>:<name>", fields[3]))?;
+        let resource_type: ResourceType = resource_type.parse()?;
+        let pattern = match name {
+            "" => return Err(anyhow!("resource '{}' has no name", fields[3])),
+            WILDCARD => ResourcePattern::Any(resource_type),
+            _ => match name.strip_suffix('*') {
+                Some(prefix) => ResourcePattern::Prefixed(resource_type, prefix.to_string()),
+                None => ResourcePattern::Literal(resource_type, name.to_string()),
+            },
+        };
+        Ok(Self {
+            permission,
+            principal: fields[1].parse()?,
+            operation: fields[2].parse()?,
+            pattern,
+            host: host.to_string(),
+        })
+    }
+}
+
+/// Decides whether a request may perform an operation on a resource.
+///
+/// Handlers ask once per resource they touch and answer the resources that
+/// are denied with the matching `*_AUTHORIZATION_FAILED` error, leaving the
+/// rest of the request to proceed.
+pub trait Authorizer: Send + Sync {
+    fn authorize(
+        &self,
+        ctx: &RequestContext,
+        operation: AclOperation,
+        resource_type: ResourceType,
+        name: &str,
+    ) -> bool;
+}
+
+/// Used when no `authorizer.class.name` is configured: everything is allowed.
+pub struct AllowAllAuthorizer;
+
+impl Authorizer for AllowAllAuthorizer {
+    fn authorize(&self, _: &RequestContext, _: AclOperation, _: ResourceType, _: &str) -> bool {
+        true
+    }
+}
+
+/// How the [`AclAuthorizer`] is set up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorizerSettings {
+    /// ACLs, one per line in the [`AclBinding`] syntax. Blank lines and lines
+    /// starting with `#` are ignored.
+    pub acl_file: Option<PathBuf>,
+    /// Principals that are allowed everything, from `super.users`.
+    pub super_users: Vec<KafkaPrincipal>,
+    /// From `allow.everyone.if.no.acl.found`: whether a resource that no ACL
+    /// matches is open to everyone rather than to super users only.
+    pub allow_everyone_if_no_acl_found: bool,
+}
+
+/// Authorizes requests against a fixed set of ACLs, following the rules of
+/// Kafka's `StandardAuthorizer`: super users are always allowed, a matching
+/// DENY beats any ALLOW, and otherwise an operation needs a matching ALLOW.
+pub struct AclAuthorizer {
+    acls: Vec<AclBinding>,
+    super_users: Vec<KafkaPrincipal>,
+    allow_everyone_if_no_acl_found: bool,
+}
+
+impl AclAuthorizer {
+    pub fn new(settings: &AuthorizerSettings) -> Result<Self> {
+        let acls = match &settings.acl_file {
+            Some(path) => read_acls(path)?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            acls,
+            super_users: settings.super_users.clone(),
+            allow_everyone_if_no_acl_found: settings.allow_everyone_if_no_acl_found,
+        })
+    }
+
+    pub fn acls(&self) -> &[AclBinding] {
+        &self.acls
+    }
+}
+
+impl Authorizer for AclAuthorizer {
+    fn authorize(
+        &self,
+        ctx: &RequestContext,
+        operation: AclOperation,
+        resource_type: ResourceType,
+        name: &str,
+    ) -> bool {
+        if self.super_users.contains(&ctx.principal) {
+            return true;
+        }
+        let mut resource_acls = self
+            .acls
+            .iter()
+            .filter(|acl| acl.pattern.matches(resource_type, name))
+            .peekable();
+        if resource_acls.peek().is_none() {
+            return self.allow_everyone_if_no_acl_found;
+        }
+        let mut allowed = false;
+        for acl in resource_acls {
+            if !acl.matches_principal(ctx) || !acl.matches_host(ctx) {
+                continue;
+            }
+            match acl.permission {
+                // A DENY covers only its own operation, not the ones an ALLOW of
+                // it would imply.
+                AclPermission::Deny
+                    if acl.operation == operation || acl.operation == AclOperation::All =>
+                {
+                    return false
+                }
+                AclPermission::Deny => {}
+                AclPermission::Allow => allowed |= acl.operation.implies(operation),
+            }
+        }
+        allowed
+    }
+}
+
+fn read_acls(path: &Path) -> Result<Vec<AclBinding>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("read ACL file '{}'", path.display()))?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            line.parse()
+                .with_context(|| format!("{}:{}: invalid ACL", path.display(), i + 1))
+        })
+        .collect()
+}
@@ -0,0 +1,288 @@
+//! The broker's side of its registration, as Kafka's
+//! `BrokerLifecycleManager` does it: a BrokerRegistration to the active
+//! controller on startup, which gives the broker its epoch, then a
+//! BrokerHeartbeat every `broker.heartbeat.interval.ms`, which keeps the
+//! broker unfenced, and so eligible to lead partitions. Between heartbeats
+//! the broker tells the controller which log directory each of its replicas
+//! lives in, with AssignReplicasToDirs, wherever the metadata log has it
+//! elsewhere or nowhere.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::api::assign_replicas_to_dirs::{
+    AssignReplicasToDirsDirectory, AssignReplicasToDirsRequest, AssignReplicasToDirsResponse,
+    AssignReplicasToDirsTopic,
+};
+use crate::api::broker_heartbeat::{BrokerHeartbeatRequest, BrokerHeartbeatResponse};
+use crate::api::broker_registration::{BrokerRegistrationRequest, BrokerRegistrationResponse};
+use crate::api::cluster_metadata::{BrokerEndpoint, RecordBatches};
+use crate::client::ControllerChannel;
+use crate::config::Config;
+use crate::features::broker_features;
+use crate::listener::ListenerType;
+use crate::log_manager::partition_log_dir;
+use crate::meta_properties::log_dir_ids;
+use crate::protocol::{ApiKey, ErrorCode, Uuid};
+
+/// How often the broker heartbeats and how long the controller waits for
+/// one before fencing it.
+#[derive(Debug, Clone)]
+pub struct BrokerLifecycleSettings {
+    /// `broker.heartbeat.interval.ms`.
+    pub heartbeat_interval: Duration,
+    /// `broker.session.timeout.ms`, used by the controller.
+    pub session_timeout: Duration,
+}
+
+impl Default for BrokerLifecycleSettings {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_millis(2_000),
+            session_timeout: Duration::from_millis(9_000),
+        }
+    }
+}
+
+pub struct BrokerLifecycle {
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl BrokerLifecycle {
+    /// Registers with the controllers in `controller.quorum.voters`, if
+    /// there are any, advertising the broker listeners.
+    pub fn start(config: &Config, cluster_id: String) -> Self {
+        let controllers = config.isr.controllers.clone();
+        if controllers.is_empty() {
+            return Self {
+                task: Mutex::new(None),
+            };
+        }
+        let listeners = config
+            .advertised_listeners
+            .iter()
+            .filter(|e| e.unix_path.is_none())
+            .filter(|e| config.listener_type(&e.listener_name) == ListenerType::Broker)
+            .map(|e| BrokerEndpoint {
+                name: e.listener_name.clone(),
+                host: e.host.clone(),
+                port: e.port,
+                security_protocol: e.security_protocol.id(),
+            })
+            .collect();
+        let registration = BrokerRegistrationRequest {
+            broker_id: config.node_id,
+            cluster_id,
+            incarnation_id: Uuid::random(),
+            listeners,
+            features: broker_features(),
+            rack: config.rack.clone(),
+        };
+        let interval = config.lifecycle.heartbeat_interval;
+        let controller = ControllerChannel::new(
+            format!("broker-{}-heartbeat", config.node_id),
+            controllers,
+            interval,
+        );
+        let task = tokio::spawn(run(
+            registration,
+            controller,
+            interval,
+            config.metadata_log_file(),
+            config.log_dirs.clone(),
+        ));
+        Self {
+            task: Mutex::new(Some(task)),
+        }
+    }
+
+    pub fn shutdown(&self) {
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+/// Registers, then heartbeats until the controller no longer knows the
+/// registration, and registers again.
+async fn run(
+    registration: BrokerRegistrationRequest,
+    mut controller: ControllerChannel,
+    interval: Duration,
+    metadata_log_file: PathBuf,
+    log_dirs: Vec<PathBuf>,
+) {
+    let mut tick = tokio::time::interval(interval);
+    loop {
+        let broker_epoch = loop {
+            tick.tick().await;
+            let res = controller
+                .send::<BrokerRegistrationResponse>(ApiKey::BrokerRegistration, 1, &registration)
+                .await;
+            match res {
+                Ok(res) if res.error_code == ErrorCode::None => break res.broker_epoch,
+                Ok(res) => warn!(error = ?res.error_code, "controller rejected registration"),
+                Err(e) => debug!(error = %e, "failed to register"),
+            }
+        };
+        info!(broker_epoch, "registered with the controller");
+        heartbeat(
+            registration.broker_id,
+            broker_epoch,
+            &mut controller,
+            &mut tick,
+            &metadata_log_file,
+            &log_dirs,
+        )
+        .await;
+    }
+}
+
+async fn heartbeat(
+    broker_id: i32,
+    broker_epoch: i64,
+    controller: &mut ControllerChannel,
+    tick: &mut tokio::time::Interval,
+    metadata_log_file: &Path,
+    log_dirs: &[PathBuf],
+) {
+    let mut fenced = None;
+    loop {
+        tick.tick().await;
+        let req = BrokerHeartbeatRequest {
+            broker_id,
+            broker_epoch,
+            current_metadata_offset: metadata_offset(metadata_log_file),
+            want_fence: false,
+            want_shut_down: false,
+        };
+        let res = controller
+            .send::<BrokerHeartbeatResponse>(ApiKey::BrokerHeartbeat, 1, &req)
+            .await;
+        match res {
+            Ok(res) if res.error_code == ErrorCode::None => {
+                if fenced != Some(res.is_fenced) {
+                    info!(fenced = res.is_fenced, "controller answered heartbeat");
+                    fenced = Some(res.is_fenced);
+                }
+                assign_directories(
+                    broker_id,
+                    broker_epoch,
+                    controller,
+                    metadata_log_file,
+                    log_dirs,
+                )
+                .await;
+            }
+            Ok(res)
+                if matches!(
+                    res.error_code,
+                    ErrorCode::StaleBrokerEpoch | ErrorCode::BrokerIdNotRegistered
+                ) =>
+            {
+                warn!(error = ?res.error_code, "registration lost, registering again");
+                return;
+            }
+            Ok(res) => debug!(error = ?res.error_code, "controller rejected heartbeat"),
+            Err(e) => warn!(error = %e, "failed to send heartbeat"),
+        }
+    }
+}
+
+/// The last offset of the metadata log, or -1.
+fn metadata_offset(metadata_log_file: &Path) -> i64 {
+    RecordBatches::from_file(metadata_log_file).map_or(-1, |m| m.end_offset() - 1)
+}
+
+/// Sends the controller the directory of every replica of this broker
+/// that the metadata log has in some other directory, or in none. A
+/// replica lives in the log dir holding its partition directory, and one
+/// not created yet goes in the first. Nothing is sent for a metadata log
+/// that doesn't carry directories.
+async fn assign_directories(
+    broker_id: i32,
+    broker_epoch: i64,
+    controller: &mut ControllerChannel,
+    metadata_log_file: &Path,
+    log_dirs: &[PathBuf],
+) {
+    let Ok(metadata) = RecordBatches::from_file(metadata_log_file) else {
+        return;
+    };
+    let ids = log_dir_ids(log_dirs);
+    let mut assignments: BTreeMap<String, BTreeMap<String, Vec<i32>>> = BTreeMap::new();
+    for topic in metadata.topics() {
+        let Some(name) = topic.topic_name.0.as_deref() else {
+            continue;
+        };
+        for p in metadata.partitions(&topic.topic_id) {
+            let Some(current) = p
+                .replicas
+                .iter()
+                .position(|&r| r as i32 == broker_id)
+                .and_then(|i| p.directories.get(i))
+            else {
+                continue;
+            };
+            let dir = partition_log_dir(log_dirs, &format!("{}-{}", name, p.partition_id));
+            let Some((_, id)) = ids.iter().find(|(d, _)| d == dir) else {
+                continue;
+            };
+            if id != current {
+                assignments
+                    .entry(id.0.clone())
+                    .or_default()
+                    .entry(topic.topic_id.0.clone())
+                    .or_default()
+                    .push(p.partition_id as i32);
+            }
+        }
+    }
+    if assignments.is_empty() {
+        return;
+    }
+    let req = AssignReplicasToDirsRequest {
+        broker_id,
+        broker_epoch,
+        directories: assignments
+            .into_iter()
+            .map(|(id, topics)| AssignReplicasToDirsDirectory {
+                id: Uuid(id),
+                topics: topics
+                    .into_iter()
+                    .map(|(topic_id, partitions)| AssignReplicasToDirsTopic {
+                        topic_id: Uuid(topic_id),
+                        partitions,
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+    let res = controller
+        .send::<AssignReplicasToDirsResponse>(ApiKey::AssignReplicasToDirs, 0, &req)
+        .await;
+    match res {
+        Ok(res) if res.error_code == ErrorCode::None => {
+            for directory in &res.directories {
+                for (topic_id, partitions) in &directory.topics {
+                    for (partition, error_code) in partitions {
+                        if *error_code == ErrorCode::None {
+                            info!(directory = %directory.id, topic_id = %topic_id, partition, "assigned replica to directory");
+                        } else {
+                            debug!(directory = %directory.id, topic_id = %topic_id, partition, error = ?error_code, "controller rejected directory assignment");
+                        }
+                    }
+                }
+            }
+        }
+        Ok(res) => debug!(error = ?res.error_code, "controller rejected directory assignments"),
+        Err(e) => warn!(error = %e, "failed to assign replicas to directories"),
+    }
+}
@@ -0,0 +1,147 @@
+//! The capture file `proxy` writes and `replay` reads: a header line, then
+//! one line per frame, in the order the proxy saw them, of the form
+//!
+//! ```text
+//! <microseconds since the epoch> <connection> <request|response> <hex>
+//! ```
+//!
+//! The hex is the frame without its size prefix. Text keeps captures easy to
+//! read, trim and attach to bug reports.
+
+use std::fmt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub const HEADER: &str = "# kafka wire capture v1";
+
+/// Frames bigger than this are taken to be garbage rather than Kafka.
+const MAX_FRAME_SIZE: usize = 100 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Client to broker.
+    Request,
+    /// Broker to client.
+    Response,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Request => "request",
+            Self::Response => "response",
+        })
+    }
+}
+
+pub struct Frame {
+    pub timestamp_us: u64,
+    pub connection: u64,
+    pub direction: Direction,
+    pub data: Bytes,
+}
+
+impl Frame {
+    pub fn new(connection: u64, direction: Direction, data: Bytes) -> Self {
+        let timestamp_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        Self {
+            timestamp_us,
+            connection,
+            direction,
+            data,
+        }
+    }
+
+    /// The correlation id, which leads a response and follows the api key
+    /// and version in a request.
+    pub fn correlation_id(&self) -> Option<i32> {
+        let at = match self.direction {
+            Direction::Request => 4,
+            Direction::Response => 0,
+        };
+        let bytes = self.data.get(at..at + 4)?;
+        Some(i32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// The api key and version of a request.
+    pub fn api(&self) -> Option<(i16, i16)> {
+        let header = self.data.get(..4)?;
+        Some((
+            i16::from_be_bytes([header[0], header[1]]),
+            i16::from_be_bytes([header[2], header[3]]),
+        ))
+    }
+
+    pub fn to_line(&self) -> String {
+        format!(
+            "{} {} {} {}\n",
+            self.timestamp_us,
+            self.connection,
+            self.direction,
+            hex::encode(&self.data)
+        )
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let mut fields = line.split(' ');
+        let mut field = || fields.next().ok_or_else(|| anyhow!("too few fields"));
+        let timestamp_us = field()?.parse().context("bad timestamp")?;
+        let connection = field()?.parse().context("bad connection")?;
+        let direction = match field()? {
+            "request" => Direction::Request,
+            "response" => Direction::Response,
+            other => bail!("bad direction {:?}", other),
+        };
+        let data = hex::decode(field()?).context("bad frame")?;
+        Ok(Self {
+            timestamp_us,
+            connection,
+            direction,
+            data: data.into(),
+        })
+    }
+}
+
+/// Every frame in the capture at `path`.
+pub fn read_capture(path: &Path) -> Result<Vec<Frame>> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("read '{}'", path.display()))?;
+    let mut lines = text.lines().enumerate();
+    if lines.next().map(|(_, line)| line) != Some(HEADER) {
+        bail!("'{}' is not a wire capture", path.display());
+    }
+    lines
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| Frame::parse(line).with_context(|| format!("line {}", i + 1)))
+        .collect()
+}
+
+/// Reads one size-prefixed frame, or `None` at a clean end of stream.
+pub async fn read_frame(stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<Bytes>> {
+    let mut size = [0u8; 4];
+    match stream.read_exact(&mut size).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let size = i32::from_be_bytes(size);
+    if size < 0 || size as usize > MAX_FRAME_SIZE {
+        bail!("bad frame size {}", size);
+    }
+    let mut data = vec![0; size as usize];
+    stream.read_exact(&mut data).await?;
+    Ok(Some(data.into()))
+}
+
+pub async fn write_frame(stream: &mut (impl AsyncWrite + Unpin), data: &[u8]) -> Result<()> {
+    stream.write_all(&(data.len() as i32).to_be_bytes()).await?;
+    stream.write_all(data).await?;
+    stream.flush().await?;
+    Ok(())
+}
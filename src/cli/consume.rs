@@ -0,0 +1,281 @@
+//! `consume`: prints the records of a topic as they arrive.
+
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use tokio::io::{stdout, AsyncWriteExt};
+use tokio::time::Instant;
+
+use crate::cli::{bootstrap_servers, Flags};
+use crate::client::{Consumer, ConsumerConfig, ConsumerRecord, OffsetReset};
+use crate::coordinator::TopicPartition;
+
+const USAGE: &str = "\
+usage: consume --topic TOPIC [options]
+
+Prints each record of TOPIC on its own line, from the end of the topic
+unless told otherwise, until interrupted.
+
+options:
+  --bootstrap-server HOST:PORT[,...]  brokers to connect to (localhost:9092)
+  --group GROUP                       join GROUP and commit what was read
+  --from-beginning                    start where there is no committed offset
+                                      from the beginning rather than the end
+  --partition N                       read only partition N
+  --offset OFFSET                     start partition N at OFFSET, earliest
+                                      or latest; needs --partition
+  --format FORMAT                     text, hex or json (text)
+  --print-key                         print keys before values in text and hex
+  --key-separator SEP                 what goes between key and value (tab)
+  --max-messages N                    exit after N records
+  --timeout-ms MS                     exit after MS without a record";
+
+/// How each record is printed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Keys and values as UTF-8, replacing what isn't.
+    Text,
+    Hex,
+    /// One JSON object per record, with its topic, partition, offset,
+    /// timestamp and headers.
+    Json,
+}
+
+/// Where `--offset` starts the partition.
+enum StartOffset {
+    Earliest,
+    Latest,
+    At(i64),
+}
+
+pub async fn run(args: Vec<String>) -> Result<()> {
+    let mut flags = Flags::parse(args, &["help", "from-beginning", "print-key"])?;
+    if flags.switch("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let topic = flags.required("topic")?;
+    let group_id = flags.value("group")?;
+    let from_beginning = flags.switch("from-beginning");
+    let partition: Option<i32> = flags.parsed("partition")?;
+    let offset = match flags.value("offset")?.as_deref() {
+        None => None,
+        Some("earliest") => Some(StartOffset::Earliest),
+        Some("latest") => Some(StartOffset::Latest),
+        Some(offset) => match offset.parse() {
+            Ok(offset) if offset >= 0 => Some(StartOffset::At(offset)),
+            _ => bail!("bad --offset {:?}", offset),
+        },
+    };
+    let format = match flags.value("format")?.as_deref() {
+        None | Some("text") => Format::Text,
+        Some("hex") => Format::Hex,
+        Some("json") => Format::Json,
+        Some(format) => bail!("bad --format {:?}", format),
+    };
+    let print_key = flags.switch("print-key");
+    let key_separator = flags
+        .value("key-separator")?
+        .unwrap_or_else(|| "\t".to_string());
+    let max_messages: Option<u64> = flags.parsed("max-messages")?;
+    let timeout = flags
+        .parsed::<u64>("timeout-ms")?
+        .map(Duration::from_millis);
+    let config = ConsumerConfig {
+        bootstrap_servers: bootstrap_servers(&mut flags)?,
+        client_id: "console-consumer".to_string(),
+        group_id: group_id.clone(),
+        auto_offset_reset: if from_beginning {
+            OffsetReset::Earliest
+        } else {
+            OffsetReset::Latest
+        },
+        ..Default::default()
+    };
+    flags.finish()?;
+    if offset.is_some() && partition.is_none() {
+        bail!("--offset needs --partition");
+    }
+    if partition.is_some() && group_id.is_some() {
+        bail!("--partition can't be used with --group");
+    }
+
+    let commit = group_id.is_some();
+    let mut consumer = Consumer::connect(config).await?;
+    match (group_id, partition) {
+        (Some(_), _) => consumer.subscribe(&[topic.as_str()])?,
+        (None, Some(partition)) => {
+            let partition = TopicPartition {
+                topic: topic.clone(),
+                partition,
+            };
+            consumer.assign(vec![partition.clone()]).await?;
+            match offset {
+                Some(StartOffset::Earliest) => consumer.seek_to_beginning(&[partition])?,
+                Some(StartOffset::Latest) => consumer.seek_to_end(&[partition]).await?,
+                Some(StartOffset::At(offset)) => consumer.seek(&partition, offset)?,
+                None => {}
+            }
+        }
+        (None, None) => {
+            let partitions = consumer
+                .partitions_for(&topic)
+                .await?
+                .into_iter()
+                .map(|partition| TopicPartition {
+                    topic: topic.clone(),
+                    partition,
+                })
+                .collect();
+            consumer.assign(partitions).await?;
+        }
+    }
+
+    let printer = Printer {
+        format,
+        print_key,
+        key_separator,
+    };
+    let res = tokio::select! {
+        res = read(&mut consumer, &printer, commit, max_messages, timeout) => res,
+        res = tokio::signal::ctrl_c() => res.map_err(Into::into),
+    };
+    consumer.close().await?;
+    res
+}
+
+/// Polls and prints until `max_messages` are printed or `timeout` passes
+/// without a record, committing what was printed if `commit`.
+async fn read(
+    consumer: &mut Consumer,
+    printer: &Printer,
+    commit: bool,
+    max_messages: Option<u64>,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    let mut out = stdout();
+    let mut printed = 0;
+    let mut last_record = Instant::now();
+    loop {
+        let poll_timeout = match timeout {
+            Some(timeout) => timeout.saturating_sub(last_record.elapsed()),
+            None => Duration::from_secs(1),
+        };
+        let records = consumer.poll(poll_timeout).await?;
+        if records.is_empty() {
+            if timeout.is_some_and(|timeout| last_record.elapsed() >= timeout) {
+                return Ok(());
+            }
+            continue;
+        }
+        last_record = Instant::now();
+        let wanted = max_messages.map_or(records.len(), |max| (max - printed) as usize);
+        let (to_print, rest) = records.split_at(wanted.min(records.len()));
+        let mut text = String::new();
+        for record in to_print {
+            printer.print(&mut text, record);
+        }
+        printed += to_print.len() as u64;
+        out.write_all(text.as_bytes()).await?;
+        out.flush().await?;
+        // Records polled but not printed are left for the next reader.
+        let mut rewound = Vec::new();
+        for record in rest {
+            let partition = TopicPartition {
+                topic: record.topic.clone(),
+                partition: record.partition,
+            };
+            if !rewound.contains(&partition) {
+                consumer.seek(&partition, record.offset)?;
+                rewound.push(partition);
+            }
+        }
+        if commit {
+            consumer.commit().await?;
+        }
+        if max_messages.is_some_and(|max| printed >= max) {
+            return Ok(());
+        }
+    }
+}
+
+struct Printer {
+    format: Format,
+    print_key: bool,
+    key_separator: String,
+}
+
+impl Printer {
+    fn print(&self, out: &mut String, record: &ConsumerRecord) {
+        match self.format {
+            Format::Text | Format::Hex => {
+                if self.print_key {
+                    self.field(out, &record.key);
+                    out.push_str(&self.key_separator);
+                }
+                self.field(out, &record.value);
+            }
+            Format::Json => {
+                let _ = write!(
+                    out,
+                    "{{\"topic\":{},\"partition\":{},\"offset\":{},\"timestamp\":{},\"key\":",
+                    json_string(&record.topic),
+                    record.partition,
+                    record.offset,
+                    record.timestamp
+                );
+                json_bytes(out, &record.key);
+                out.push_str(",\"value\":");
+                json_bytes(out, &record.value);
+                out.push_str(",\"headers\":{");
+                for (i, (name, value)) in record.headers.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&json_string(name));
+                    out.push(':');
+                    json_bytes(out, value);
+                }
+                out.push_str("}}");
+            }
+        }
+        out.push('\n');
+    }
+
+    fn field(&self, out: &mut String, bytes: &Option<Bytes>) {
+        match (bytes, self.format) {
+            (None, _) => out.push_str("null"),
+            (Some(bytes), Format::Hex) => out.push_str(&hex::encode(bytes)),
+            (Some(bytes), _) => out.push_str(&String::from_utf8_lossy(bytes)),
+        }
+    }
+}
+
+fn json_bytes(out: &mut String, bytes: &Option<Bytes>) {
+    match bytes {
+        Some(bytes) => out.push_str(&json_string(&String::from_utf8_lossy(bytes))),
+        None => out.push_str("null"),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,289 @@
+//! `format`: prepares the log directories of a node that has never run, as
+//! `kafka-storage.sh format` does, so that it starts in a known cluster with
+//! the feature levels and SCRAM users it is given.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{
+    engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD},
+    Engine,
+};
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+
+use crate::api::cluster_metadata::{FeatureLevelValue, UserScramCredentialValue};
+use crate::cli::Flags;
+use crate::config::Config;
+use crate::features::{bootstrap_features, supported_feature};
+use crate::meta_properties::{random_id, MetaProperties};
+use crate::protocol::cluster_metadata_log_file;
+use crate::record_batch::{encode_batch, BatchRecord};
+use crate::scram::{ScramCredential, ScramMechanism, DEFAULT_SCRAM_ITERATIONS};
+
+const USAGE: &str = "\
+usage: format [options]
+
+Creates each of the node's log dirs with a meta.properties naming the
+cluster, the node and the dir. On a controller the metadata log is started
+with the feature levels and SCRAM users given.
+
+options:
+  --config FILE                       the node's server properties, for
+                                      node.id, log.dirs and the quorum
+  --cluster-id ID                     the cluster the node joins; a new
+                                      one is generated if not given
+  --feature NAME=LEVEL                finalize a feature at LEVEL rather
+                                      than its latest; may be repeated
+  --add-scram MECHANISM=[name=USER,password=PASSWORD]
+                                      add a SCRAM user; salt=BASE64 and
+                                      iterations=N may also be given; may be
+                                      repeated
+  --ignore-formatted                  leave dirs already formatted alone
+                                      rather than failing";
+
+/// The iteration counts Kafka accepts for SCRAM credentials.
+const MIN_SCRAM_ITERATIONS: u32 = 4096;
+const MAX_SCRAM_ITERATIONS: u32 = 16384;
+
+pub async fn run(args: Vec<String>) -> Result<()> {
+    let mut flags = Flags::parse(args, &["help", "ignore-formatted"])?;
+    if flags.switch("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let config = match flags.value("config")? {
+        Some(path) => Config::from_file(&path)?,
+        None => Config::default(),
+    };
+    let cluster_id = flags.value("cluster-id")?;
+    if let Some(id) = &cluster_id {
+        if URL_SAFE_NO_PAD.decode(id).map_or(true, |b| b.len() != 16) {
+            bail!("--cluster-id {:?} is not a base64 id of 16 bytes", id);
+        }
+    }
+    let features = flags.values("feature")?;
+    let scram_users = flags.values("add-scram")?;
+    let ignore_formatted = flags.switch("ignore-formatted");
+    flags.finish()?;
+
+    // Dirs added to a formatted node join the cluster it is in.
+    let existing = match formatted_cluster_id(&config.log_dirs)? {
+        Some(_) if !ignore_formatted => bail!("log dirs are already formatted"),
+        existing => existing,
+    };
+    let cluster_id = match (existing, cluster_id) {
+        (Some(existing), Some(given)) if existing != given => bail!(
+            "log dirs are formatted for cluster {}, not {}",
+            existing,
+            given
+        ),
+        (Some(id), _) | (None, Some(id)) => id,
+        (None, None) => random_id(),
+    };
+
+    let mut records = feature_records(&features)?;
+    for user in &scram_users {
+        records.push(scram_record(&cluster_id, user)?.record());
+    }
+    let formatted = format(&config, &cluster_id, &records)?;
+    if formatted.is_empty() {
+        println!("already formatted for cluster {}", cluster_id);
+    } else {
+        println!(
+            "formatted {} for cluster {}",
+            describe_dirs(&formatted),
+            cluster_id
+        );
+    }
+    Ok(())
+}
+
+/// The cluster the log dirs already formatted belong to, if any are.
+fn formatted_cluster_id(log_dirs: &[PathBuf]) -> Result<Option<String>> {
+    let mut cluster_id: Option<String> = None;
+    for dir in log_dirs {
+        let Some(meta) = MetaProperties::read(dir)? else {
+            continue;
+        };
+        match &cluster_id {
+            Some(id) if *id != meta.cluster_id => bail!(
+                "log dirs belong to different clusters ({} and {})",
+                id,
+                meta.cluster_id
+            ),
+            _ => cluster_id = Some(meta.cluster_id),
+        }
+    }
+    Ok(cluster_id)
+}
+
+/// Writes `meta.properties` to each log dir that lacks one and, on a
+/// controller whose metadata log is empty, starts it with `records`.
+/// Returns the dirs formatted.
+fn format(config: &Config, cluster_id: &str, records: &[BatchRecord]) -> Result<Vec<PathBuf>> {
+    let mut missing = Vec::new();
+    for dir in &config.log_dirs {
+        if MetaProperties::read(dir)?.is_none() {
+            missing.push(dir.clone());
+        }
+    }
+    // The quorum keeps its log in the first log dir.
+    let metadata_log = config
+        .quorum
+        .as_ref()
+        .map(|_| cluster_metadata_log_file(&config.log_dirs[0]));
+    if let Some(path) = &metadata_log {
+        if has_records(path)? && missing.contains(&config.log_dirs[0]) {
+            bail!(
+                "metadata log '{}' has records but no meta.properties",
+                path.display()
+            );
+        }
+    }
+
+    for dir in &missing {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("create log dir '{}'", dir.display()))?;
+        MetaProperties {
+            cluster_id: cluster_id.to_string(),
+            node_id: config.node_id,
+            directory_id: Some(random_id()),
+        }
+        .write(dir)?;
+    }
+    if let Some(path) = metadata_log {
+        if !has_records(&path)? {
+            write_bootstrap(&path, records)?;
+        }
+    }
+    Ok(missing)
+}
+
+fn has_records(path: &Path) -> Result<bool> {
+    match std::fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len() > 0),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("read '{}'", path.display())),
+    }
+}
+
+/// The first batch of the metadata log, at epoch 0 like one a leader of
+/// that epoch would have appended.
+fn write_bootstrap(path: &Path, records: &[BatchRecord]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("create '{}'", dir.display()))?;
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    std::fs::write(path, encode_batch(0, 0, 0, records, timestamp))
+        .with_context(|| format!("write '{}'", path.display()))
+}
+
+/// A FeatureLevelRecord for each supported feature, at its latest level
+/// unless `overrides`, each `NAME=LEVEL`, say otherwise. Features left at
+/// level 0 are off and get no record.
+fn feature_records(overrides: &[String]) -> Result<Vec<BatchRecord>> {
+    let mut levels: BTreeMap<String, i16> = bootstrap_features()
+        .into_iter()
+        .map(|f| (f.name, f.level))
+        .collect();
+    for feature in overrides {
+        let (name, level) = feature
+            .split_once('=')
+            .ok_or_else(|| anyhow!("--feature {:?} should be NAME=LEVEL", feature))?;
+        let supported =
+            supported_feature(name).ok_or_else(|| anyhow!("unknown feature '{}'", name))?;
+        let level: i16 = level
+            .parse()
+            .with_context(|| format!("bad level in --feature {:?}", feature))?;
+        if !(supported.min_level..=supported.max_level).contains(&level) {
+            bail!(
+                "{} must be between {} and {}, not {}",
+                name,
+                supported.min_level,
+                supported.max_level,
+                level
+            );
+        }
+        levels.insert(name.to_string(), level);
+    }
+    Ok(levels
+        .into_iter()
+        .filter(|(_, level)| *level > 0)
+        .map(|(name, level)| FeatureLevelValue { name, level }.record())
+        .collect())
+}
+
+/// Reads `MECHANISM=[name=USER,password=PASSWORD,...]`. Without a salt one
+/// is derived from the cluster id, user and mechanism, so that every voter
+/// formatted with the same arguments starts with the same metadata log.
+fn scram_record(cluster_id: &str, arg: &str) -> Result<UserScramCredentialValue> {
+    let bad = || {
+        anyhow!(
+            "--add-scram {:?} should be MECHANISM=[name=USER,password=PASSWORD]",
+            arg
+        )
+    };
+    let (mechanism, fields) = arg.split_once('=').ok_or_else(bad)?;
+    let mechanism = ScramMechanism::from_name(mechanism)
+        .ok_or_else(|| anyhow!("unknown SCRAM mechanism '{}'", mechanism))?;
+    let fields = fields
+        .strip_prefix('[')
+        .and_then(|f| f.strip_suffix(']'))
+        .ok_or_else(bad)?;
+    let mut values = BTreeMap::new();
+    for field in fields.split(',') {
+        let (key, value) = field.split_once('=').ok_or_else(bad)?;
+        let value = value.trim_matches('"');
+        if values.insert(key.trim(), value).is_some() {
+            bail!("--add-scram {:?} gives {} twice", arg, key.trim());
+        }
+    }
+    let mut take = |key: &str| values.remove(key);
+    let name = take("name").ok_or_else(|| anyhow!("--add-scram {:?} needs a name", arg))?;
+    let password =
+        take("password").ok_or_else(|| anyhow!("--add-scram {:?} needs a password", arg))?;
+    let salt = match take("salt") {
+        Some(salt) => BASE64
+            .decode(salt)
+            .with_context(|| format!("bad salt in --add-scram {:?}", arg))?,
+        None => Sha256::digest(format!("{}:{}:{}", cluster_id, name, mechanism.name())).to_vec(),
+    };
+    let iterations = match take("iterations") {
+        Some(n) => n
+            .parse()
+            .with_context(|| format!("bad iterations in --add-scram {:?}", arg))?,
+        None => DEFAULT_SCRAM_ITERATIONS,
+    };
+    if let Some(key) = values.keys().next() {
+        bail!("--add-scram {:?} has unknown field {}", arg, key);
+    }
+    if !(MIN_SCRAM_ITERATIONS..=MAX_SCRAM_ITERATIONS).contains(&iterations) {
+        bail!(
+            "SCRAM iterations must be between {} and {}, not {}",
+            MIN_SCRAM_ITERATIONS,
+            MAX_SCRAM_ITERATIONS,
+            iterations
+        );
+    }
+    let credential = ScramCredential::from_password(mechanism, password, salt, iterations);
+    Ok(UserScramCredentialValue {
+        name: name.to_string(),
+        mechanism: mechanism.type_id(),
+        salt: Bytes::from(credential.salt),
+        stored_key: Bytes::from(credential.stored_key),
+        server_key: Bytes::from(credential.server_key),
+        iterations: iterations as i32,
+    })
+}
+
+fn describe_dirs(dirs: &[PathBuf]) -> String {
+    dirs.iter()
+        .map(|dir| dir.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
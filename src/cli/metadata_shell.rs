@@ -0,0 +1,246 @@
+//! `metadata-shell`: answers queries about the topics, partitions, configs
+//! and brokers recorded in a cluster metadata log, without a running broker.
+
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use tokio::io::{stdin, stdout, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::api::cluster_metadata::{
+    PartitionValue, RecordBatches, TopicValue, BROKER_CONFIG_RESOURCE, TOPIC_CONFIG_RESOURCE,
+};
+use crate::cli::Flags;
+use crate::config::DEFAULT_LOG_DIR;
+use crate::protocol::cluster_metadata_log_file;
+
+const USAGE: &str = "\
+usage: metadata-shell [options]
+
+Reads a cluster metadata log and answers queries about it: each --command
+in turn, or else one per line of stdin until `exit`.
+
+options:
+  --log-dir DIR                       the log directory holding
+                                      __cluster_metadata-0
+                                      (/tmp/kraft-combined-logs)
+  --file FILE                         the metadata log segment itself
+  --command COMMAND                   run COMMAND and exit; may be repeated
+
+commands:
+  topics                              list every topic
+  topic NAME                          show a topic, its configs and its
+                                      partitions
+  partition NAME INDEX                show one partition of a topic
+  configs topic NAME                  show the configs set on a topic
+  configs broker [ID]                 show the configs set on a broker, or
+                                      the cluster-wide broker defaults
+  brokers                             show every registered broker
+  reload                              read the log again
+  help                                show the commands
+  exit                                leave the shell";
+
+pub async fn run(args: Vec<String>) -> Result<()> {
+    let mut flags = Flags::parse(args, &["help"])?;
+    if flags.switch("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let file = match (flags.value("file")?, flags.value("log-dir")?) {
+        (Some(_), Some(_)) => bail!("give --file or --log-dir, not both"),
+        (Some(file), None) => PathBuf::from(file),
+        (None, log_dir) => {
+            cluster_metadata_log_file(Path::new(log_dir.as_deref().unwrap_or(DEFAULT_LOG_DIR)))
+        }
+    };
+    let commands = flags.values("command")?;
+    flags.finish()?;
+
+    let mut shell = Shell {
+        metadata: load(&file)?,
+        file,
+    };
+    if !commands.is_empty() {
+        for command in commands {
+            if !shell.run(&command)? {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    let interactive = std::io::stdin().is_terminal();
+    let mut lines = BufReader::new(stdin()).lines();
+    loop {
+        if interactive {
+            let mut stdout = stdout();
+            stdout.write_all(b">> ").await?;
+            stdout.flush().await?;
+        }
+        let Some(line) = lines.next_line().await? else {
+            return Ok(());
+        };
+        // A mistyped query shouldn't end an interactive session.
+        match shell.run(&line) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) if interactive => eprintln!("{:#}", e),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn load(file: &Path) -> Result<RecordBatches> {
+    RecordBatches::from_file(file).with_context(|| format!("read '{}'", file.display()))
+}
+
+struct Shell {
+    file: PathBuf,
+    metadata: RecordBatches,
+}
+
+impl Shell {
+    /// Runs one command line, returning whether to carry on.
+    fn run(&mut self, line: &str) -> Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            [] => {}
+            ["topics"] => self.topics(),
+            ["topic", name] => self.topic(name)?,
+            ["partition", name, index] => {
+                let index = index
+                    .parse()
+                    .with_context(|| format!("bad partition {:?}", index))?;
+                self.partition(name, index)?;
+            }
+            ["configs", "topic", name] => self.configs(TOPIC_CONFIG_RESOURCE, name),
+            ["configs", "broker"] => self.configs(BROKER_CONFIG_RESOURCE, ""),
+            ["configs", "broker", id] => self.configs(BROKER_CONFIG_RESOURCE, id),
+            ["brokers"] => self.brokers(),
+            ["reload"] => self.metadata = load(&self.file)?,
+            ["help"] => println!("{}", USAGE),
+            ["exit"] | ["quit"] => return Ok(false),
+            _ => bail!("unknown command {:?}, try `help`", line.trim()),
+        }
+        Ok(true)
+    }
+
+    fn topics(&self) {
+        let mut topics: Vec<&TopicValue> = self.metadata.topics().collect();
+        topics.sort_by(|a, b| a.topic_name.0.cmp(&b.topic_name.0));
+        for topic in topics {
+            println!(
+                "{}\t{}\t{} partitions",
+                topic.topic_name.0.as_deref().unwrap_or_default(),
+                topic.topic_id,
+                self.metadata.partitions(&topic.topic_id).count()
+            );
+        }
+    }
+
+    fn topic(&self, name: &str) -> Result<()> {
+        let topic = self.find_topic(name)?;
+        let mut partitions: Vec<&PartitionValue> =
+            self.metadata.partitions(&topic.topic_id).collect();
+        partitions.sort_by_key(|p| p.partition_id);
+        println!(
+            "Topic: {}\tTopicId: {}\tPartitionCount: {}",
+            name,
+            topic.topic_id,
+            partitions.len()
+        );
+        let configs = self.metadata.configs(TOPIC_CONFIG_RESOURCE, name);
+        if !configs.is_empty() {
+            let configs: Vec<String> = configs
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            println!("\tConfigs: {}", configs.join(","));
+        }
+        for partition in partitions {
+            println!(
+                "\tPartition: {}\tLeader: {}\tLeaderEpoch: {}\tReplicas: {}\tIsr: {}",
+                partition.partition_id,
+                leader(partition),
+                partition.leader_epoch as i32,
+                join(&partition.replicas),
+                join(&partition.in_sync_replicas)
+            );
+        }
+        Ok(())
+    }
+
+    fn partition(&self, name: &str, index: u32) -> Result<()> {
+        let topic = self.find_topic(name)?;
+        let Some(partition) = self
+            .metadata
+            .partitions(&topic.topic_id)
+            .find(|p| p.partition_id == index)
+        else {
+            bail!("topic {} has no partition {}", name, index);
+        };
+        println!("Topic: {}", name);
+        println!("TopicId: {}", topic.topic_id);
+        println!("Partition: {}", partition.partition_id);
+        println!("Leader: {}", leader(partition));
+        println!("LeaderEpoch: {}", partition.leader_epoch as i32);
+        println!("PartitionEpoch: {}", partition.partition_epoch as i32);
+        println!("Replicas: {}", join(&partition.replicas));
+        println!("Isr: {}", join(&partition.in_sync_replicas));
+        println!("AddingReplicas: {}", join(&partition.adding_replicas));
+        println!("RemovingReplicas: {}", join(&partition.removing_replicas));
+        let directories: Vec<String> = partition
+            .directories
+            .iter()
+            .map(|dir| dir.to_string())
+            .collect();
+        println!("Directories: {}", directories.join(","));
+        Ok(())
+    }
+
+    fn configs(&self, resource_type: i8, resource_name: &str) {
+        for (key, value) in self.metadata.configs(resource_type, resource_name) {
+            println!("{}={}", key, value);
+        }
+    }
+
+    fn brokers(&self) {
+        for broker in self.metadata.brokers() {
+            let endpoints: Vec<String> = broker
+                .endpoints
+                .iter()
+                .map(|e| format!("{}://{}:{}", e.name, e.host, e.port))
+                .collect();
+            println!(
+                "Broker: {}\tEpoch: {}\tIncarnationId: {}\tRack: {}\tFenced: {}\tEndpoints: {}",
+                broker.broker_id,
+                broker.broker_epoch,
+                broker.incarnation_id,
+                broker.rack.as_deref().unwrap_or("none"),
+                broker.fenced,
+                endpoints.join(",")
+            );
+        }
+    }
+
+    fn find_topic(&self, name: &str) -> Result<&TopicValue> {
+        match self.metadata.topic_by_name(name) {
+            Some(topic) => Ok(topic),
+            None => bail!("no topic {}", name),
+        }
+    }
+}
+
+fn leader(partition: &PartitionValue) -> String {
+    match partition.leader_id as i32 {
+        -1 => "none".to_string(),
+        leader => leader.to_string(),
+    }
+}
+
+fn join(ids: &[u32]) -> String {
+    ids.iter()
+        .map(|id| (*id as i32).to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,141 @@
+//! Subcommands for working with a running cluster from a shell, built on
+//! the client library, in the manner of Kafka's console tools.
+
+mod capture;
+mod consume;
+mod format;
+mod metadata_shell;
+mod produce;
+mod proxy;
+mod replay;
+mod topics;
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+/// Every subcommand, by the name it is run with.
+pub const COMMANDS: &[&str] = &[
+    "consume",
+    "format",
+    "metadata-shell",
+    "produce",
+    "proxy",
+    "replay",
+    "topics",
+];
+
+/// Runs `command` with the arguments that follow its name.
+pub async fn run_command(command: &str, args: Vec<String>) -> Result<()> {
+    match command {
+        "consume" => consume::run(args).await,
+        "format" => format::run(args).await,
+        "metadata-shell" => metadata_shell::run(args).await,
+        "produce" => produce::run(args).await,
+        "proxy" => proxy::run(args).await,
+        "replay" => replay::run(args).await,
+        "topics" => topics::run(args).await,
+        _ => bail!("unknown command {:?}", command),
+    }
+}
+
+/// Command-line flags, given as `--name value`, `--name=value`, or `--name`
+/// alone for switches. Each is taken out as it is read, so that whatever
+/// is left over can be reported.
+struct Flags {
+    values: Vec<(String, Option<String>)>,
+}
+
+impl Flags {
+    /// Reads `args`, taking `switches` to stand alone and every other flag
+    /// to have a value.
+    fn parse(args: Vec<String>, switches: &[&str]) -> Result<Self> {
+        let mut values = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                bail!("unexpected argument {:?}", arg);
+            };
+            if let Some((name, value)) = flag.split_once('=') {
+                values.push((name.to_string(), Some(value.to_string())));
+            } else if switches.contains(&flag) {
+                values.push((flag.to_string(), None));
+            } else {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow!("--{} needs a value", flag))?;
+                values.push((flag.to_string(), Some(value)));
+            }
+        }
+        Ok(Self { values })
+    }
+
+    /// The value of `name`, the last one if given more than once.
+    fn value(&mut self, name: &str) -> Result<Option<String>> {
+        Ok(self.values(name)?.pop())
+    }
+
+    /// Every value of `name`, for flags that may be given more than once.
+    fn values(&mut self, name: &str) -> Result<Vec<String>> {
+        let mut found = Vec::new();
+        while let Some(value) = self.value_once(name)? {
+            found.push(value);
+        }
+        Ok(found)
+    }
+
+    fn value_once(&mut self, name: &str) -> Result<Option<String>> {
+        let Some(i) = self.values.iter().position(|(flag, _)| flag == name) else {
+            return Ok(None);
+        };
+        let (_, value) = self.values.remove(i);
+        value
+            .map(Some)
+            .ok_or_else(|| anyhow!("--{} needs a value", name))
+    }
+
+    fn required(&mut self, name: &str) -> Result<String> {
+        self.value(name)?
+            .ok_or_else(|| anyhow!("--{} is required", name))
+    }
+
+    fn parsed<T>(&mut self, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.value(name)?
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| anyhow!("{}", e))
+                    .with_context(|| format!("bad --{} {:?}", name, value))
+            })
+            .transpose()
+    }
+
+    fn switch(&mut self, name: &str) -> bool {
+        let before = self.values.len();
+        self.values.retain(|(flag, _)| flag != name);
+        self.values.len() != before
+    }
+
+    /// Fails on any flag that wasn't read.
+    fn finish(self) -> Result<()> {
+        match self.values.first() {
+            Some((name, _)) => bail!("unknown flag --{}", name),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `--bootstrap-server`, a comma-separated list of `host:port`.
+fn bootstrap_servers(flags: &mut Flags) -> Result<Vec<String>> {
+    Ok(flags
+        .value("bootstrap-server")?
+        .unwrap_or_else(|| "localhost:9092".to_string())
+        .split(',')
+        .map(|server| server.trim().to_string())
+        .filter(|server| !server.is_empty())
+        .collect())
+}
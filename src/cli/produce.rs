@@ -0,0 +1,81 @@
+//! `produce`: writes each line of stdin to a topic as a record.
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use tokio::io::{stdin, AsyncBufReadExt, BufReader};
+
+use crate::api::produce::{ACKS_ALL, ACKS_LEADER, ACKS_NONE};
+use crate::cli::{bootstrap_servers, Flags};
+use crate::client::{Producer, ProducerConfig, ProducerRecord};
+
+const USAGE: &str = "\
+usage: produce --topic TOPIC [options] < records
+
+Writes each line of stdin to TOPIC as one record.
+
+options:
+  --bootstrap-server HOST:PORT[,...]  brokers to connect to (localhost:9092)
+  --partition N                       write every record to partition N
+  --key-separator SEP                 read each line as KEY SEP VALUE
+  --acks ACKS                         all, 1 or 0 (all)";
+
+pub async fn run(args: Vec<String>) -> Result<()> {
+    let mut flags = Flags::parse(args, &["help"])?;
+    if flags.switch("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let topic = flags.required("topic")?;
+    let partition = flags.parsed("partition")?;
+    let key_separator = flags.value("key-separator")?;
+    if key_separator.as_deref() == Some("") {
+        bail!("--key-separator can't be empty");
+    }
+    let config = ProducerConfig {
+        bootstrap_servers: bootstrap_servers(&mut flags)?,
+        client_id: "console-producer".to_string(),
+        acks: match flags.value("acks")?.as_deref() {
+            None | Some("all") | Some("-1") => ACKS_ALL,
+            Some("1") => ACKS_LEADER,
+            Some("0") => ACKS_NONE,
+            Some(acks) => bail!("bad --acks {:?}", acks),
+        },
+        ..Default::default()
+    };
+    flags.finish()?;
+
+    let mut producer = Producer::connect(config).await?;
+    let mut stdin = BufReader::new(stdin());
+    let mut line = String::new();
+    let mut line_number = 0;
+    loop {
+        line.clear();
+        if stdin.read_line(&mut line).await? == 0 {
+            break;
+        }
+        line_number += 1;
+        let text = line.strip_suffix('\n').unwrap_or(&line);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let (key, value) = match &key_separator {
+            Some(separator) => match text.split_once(separator.as_str()) {
+                Some((key, value)) => (Some(key), value),
+                None => bail!("no key separator on line {}", line_number),
+            },
+            None => (None, text),
+        };
+        producer
+            .send(ProducerRecord {
+                topic: topic.clone(),
+                partition,
+                key: key.map(|key| Bytes::copy_from_slice(key.as_bytes())),
+                value: Some(Bytes::copy_from_slice(value.as_bytes())),
+            })
+            .await?;
+        // Typed lines go out as they are entered; piped ones are batched.
+        if stdin.buffer().is_empty() {
+            producer.flush().await?;
+        }
+    }
+    producer.flush().await?;
+    Ok(())
+}
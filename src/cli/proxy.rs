@@ -0,0 +1,133 @@
+//! `proxy`: forwards connections to a broker, recording every request and
+//! response that passes through to a capture file for `replay`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::cli::capture::{read_frame, write_frame, Direction, Frame, HEADER};
+use crate::cli::Flags;
+
+const USAGE: &str = "\
+usage: proxy --broker HOST:PORT --output FILE [options]
+
+Accepts connections and forwards each to the broker, writing every request
+and response to FILE as it passes, until interrupted. Clients that follow
+the metadata to the broker's advertised address leave the proxy, so point
+them at it with bootstrap-only settings or a single advertised listener.
+
+options:
+  --listen HOST:PORT                  where to accept connections
+                                      (127.0.0.1:9093)";
+
+pub async fn run(args: Vec<String>) -> Result<()> {
+    let mut flags = Flags::parse(args, &["help"])?;
+    if flags.switch("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let broker = flags.required("broker")?;
+    let output = flags.required("output")?;
+    let listen = flags
+        .value("listen")?
+        .unwrap_or_else(|| "127.0.0.1:9093".to_string());
+    flags.finish()?;
+
+    let listener = TcpListener::bind(&listen)
+        .await
+        .with_context(|| format!("listen on {}", listen))?;
+    let mut file = BufWriter::new(
+        File::create(&output)
+            .await
+            .with_context(|| format!("create '{}'", output))?,
+    );
+    file.write_all(format!("{}\n", HEADER).as_bytes()).await?;
+    file.flush().await?;
+    eprintln!(
+        "forwarding {} to {}, capturing to {}",
+        listener.local_addr()?,
+        broker,
+        output
+    );
+
+    let (frames_tx, mut frames) = mpsc::unbounded_channel::<Frame>();
+    let next_connection = Arc::new(AtomicU64::new(1));
+    let accept = async {
+        loop {
+            let (client, peer) = listener.accept().await?;
+            let connection = next_connection.fetch_add(1, Ordering::Relaxed);
+            debug!(connection, peer = %peer, "accepted connection");
+            tokio::spawn(forward(
+                client,
+                broker.clone(),
+                connection,
+                frames_tx.clone(),
+            ));
+        }
+    };
+    let write = async {
+        while let Some(frame) = frames.recv().await {
+            file.write_all(frame.to_line().as_bytes()).await?;
+            // Frames are written as they come, so a capture survives a kill.
+            if frames.is_empty() {
+                file.flush().await?;
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let res: Result<()> = tokio::select! {
+        res = accept => res,
+        res = write => res,
+        res = tokio::signal::ctrl_c() => res.map_err(Into::into),
+    };
+    file.flush().await?;
+    res
+}
+
+/// Relays frames between `client` and a new connection to `broker` until
+/// either side closes.
+async fn forward(
+    client: TcpStream,
+    broker: String,
+    connection: u64,
+    frames: mpsc::UnboundedSender<Frame>,
+) {
+    let upstream = match TcpStream::connect(&broker).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            warn!(connection, broker = %broker, error = %e, "failed to connect to the broker");
+            return;
+        }
+    };
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut broker_read, mut broker_write) = upstream.into_split();
+    let requests = async {
+        while let Some(data) = read_frame(&mut client_read).await? {
+            let _ = frames.send(Frame::new(connection, Direction::Request, data.clone()));
+            write_frame(&mut broker_write, &data).await?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let responses = async {
+        while let Some(data) = read_frame(&mut broker_read).await? {
+            let _ = frames.send(Frame::new(connection, Direction::Response, data.clone()));
+            write_frame(&mut client_write, &data).await?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    // Whichever side closes first ends the connection for both.
+    let res = tokio::select! {
+        res = requests => res,
+        res = responses => res,
+    };
+    match res {
+        Ok(()) => debug!(connection, "connection closed"),
+        Err(e) => debug!(connection, error = %e, "connection closed with error"),
+    }
+}
@@ -0,0 +1,151 @@
+//! `replay`: sends the requests of a `proxy` capture to a broker and checks
+//! its responses against the captured ones.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+use crate::cli::capture::{read_capture, read_frame, write_frame, Direction, Frame};
+use crate::cli::Flags;
+use crate::protocol::ApiKey;
+
+const USAGE: &str = "\
+usage: replay --input FILE [options]
+
+Sends the requests captured in FILE to a broker, each captured connection
+over a connection of its own and in the order they were captured, and
+compares each response with the captured one byte for byte. Responses that
+carry times or generated ids will differ even when nothing is wrong.
+
+options:
+  --bootstrap-server HOST:PORT        the broker to replay against
+                                      (localhost:9092)
+  --connection N                      replay only captured connection N
+  --keep-timing                       wait between requests as long as the
+                                      client did
+  --verbose                           print every request, not only those
+                                      whose responses differ";
+
+pub async fn run(args: Vec<String>) -> Result<()> {
+    let mut flags = Flags::parse(args, &["help", "keep-timing", "verbose"])?;
+    if flags.switch("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let input = PathBuf::from(flags.required("input")?);
+    let broker = flags
+        .value("bootstrap-server")?
+        .unwrap_or_else(|| "localhost:9092".to_string());
+    let only: Option<u64> = flags.parsed("connection")?;
+    let keep_timing = flags.switch("keep-timing");
+    let verbose = flags.switch("verbose");
+    flags.finish()?;
+
+    let frames: Vec<Frame> = read_capture(&input)?
+        .into_iter()
+        .filter(|frame| only.is_none_or(|only| frame.connection == only))
+        .collect();
+    if frames.is_empty() {
+        bail!("nothing to replay");
+    }
+
+    let mut connections: HashMap<u64, Replayed> = HashMap::new();
+    let first_timestamp = frames[0].timestamp_us;
+    let start = Instant::now();
+    let (mut sent, mut differed) = (0, 0);
+    for frame in &frames {
+        let replayed = match connections.get_mut(&frame.connection) {
+            Some(replayed) => replayed,
+            None => {
+                let stream = TcpStream::connect(&broker)
+                    .await
+                    .with_context(|| format!("connect to {}", broker))?;
+                connections.entry(frame.connection).or_insert(Replayed {
+                    stream,
+                    apis: HashMap::new(),
+                })
+            }
+        };
+        match frame.direction {
+            Direction::Request => {
+                if keep_timing {
+                    let offset = frame.timestamp_us.saturating_sub(first_timestamp);
+                    tokio::time::sleep_until(start + Duration::from_micros(offset)).await;
+                }
+                if let (Some(correlation_id), Some(api)) = (frame.correlation_id(), frame.api()) {
+                    replayed.apis.insert(correlation_id, api);
+                }
+                write_frame(&mut replayed.stream, &frame.data).await?;
+                sent += 1;
+            }
+            Direction::Response => {
+                let Some(actual) = read_frame(&mut replayed.stream).await? else {
+                    bail!(
+                        "connection {}: broker closed the connection",
+                        frame.connection
+                    );
+                };
+                let correlation_id = frame.correlation_id().unwrap_or(-1);
+                let api = match replayed.apis.get(&correlation_id) {
+                    Some(&(key, version)) => match ApiKey::try_from(key) {
+                        Ok(key) => format!("{:?} v{}", key, version),
+                        Err(_) => format!("api {} v{}", key, version),
+                    },
+                    None => "unknown api".to_string(),
+                };
+                match first_difference(&frame.data, &actual) {
+                    None if verbose => println!(
+                        "connection {} correlation {} {}: same",
+                        frame.connection, correlation_id, api
+                    ),
+                    None => {}
+                    Some(at) => {
+                        differed += 1;
+                        println!(
+                            "connection {} correlation {} {}: differs at byte {} \
+                             ({} bytes captured, {} replayed)",
+                            frame.connection,
+                            correlation_id,
+                            api,
+                            at,
+                            frame.data.len(),
+                            actual.len()
+                        );
+                    }
+                }
+            }
+        }
+    }
+    println!(
+        "replayed {} requests over {} connections, {} responses differed",
+        sent,
+        connections.len(),
+        differed
+    );
+    Ok(())
+}
+
+/// A captured connection being replayed, with the api of each request sent
+/// on it by correlation id.
+struct Replayed {
+    stream: TcpStream,
+    apis: HashMap<i32, (i16, i16)>,
+}
+
+/// Where `actual` first differs from `expected`, if it does.
+fn first_difference(expected: &Bytes, actual: &Bytes) -> Option<usize> {
+    if expected == actual {
+        return None;
+    }
+    let common = expected
+        .iter()
+        .zip(actual.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    Some(common)
+}
@@ -0,0 +1,233 @@
+//! `topics`: lists, describes, creates, deletes and reconfigures topics
+//! through the admin APIs of a running broker.
+
+use anyhow::{bail, Context, Result};
+
+use crate::api::alter_configs::{AlterConfigsResource, AlterableConfig};
+use crate::api::cluster_metadata::TOPIC_CONFIG_RESOURCE;
+use crate::api::create_topics::{CreatableTopic, CreatableTopicConfig};
+use crate::cli::{bootstrap_servers, Flags};
+use crate::client::{Admin, AdminConfig};
+use crate::protocol::ErrorCode;
+
+const USAGE: &str = "\
+usage: topics ACTION [options]
+
+actions:
+  list [--exclude-internal]           print the name of every topic
+  describe [--topic TOPIC ...]        print the partitions of each topic, or
+                                      of every topic
+  create --topic TOPIC [--partitions N] [--replication-factor N]
+         [--config KEY=VALUE ...]     create a topic, taking the broker's
+                                      defaults for what isn't given
+  delete --topic TOPIC ...            delete topics
+  alter --topic TOPIC --config KEY=VALUE ...
+                                      replace a topic's configs; those not
+                                      given go back to their defaults
+
+options:
+  --bootstrap-server HOST:PORT[,...]  brokers to connect to (localhost:9092)";
+
+pub async fn run(mut args: Vec<String>) -> Result<()> {
+    if args.is_empty() {
+        bail!("{}", USAGE);
+    }
+    let action = args.remove(0);
+    let mut flags = Flags::parse(args, &["help", "exclude-internal"])?;
+    if action == "--help" || flags.switch("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let config = AdminConfig {
+        bootstrap_servers: bootstrap_servers(&mut flags)?,
+        client_id: "topics".to_string(),
+        ..Default::default()
+    };
+    match action.as_str() {
+        "list" => {
+            let exclude_internal = flags.switch("exclude-internal");
+            flags.finish()?;
+            list(Admin::new(config)?, exclude_internal).await
+        }
+        "describe" => {
+            let topics = flags.values("topic")?;
+            flags.finish()?;
+            describe(Admin::new(config)?, topics).await
+        }
+        "create" => {
+            let topic = CreatableTopic {
+                name: flags.required("topic")?,
+                num_partitions: flags.parsed("partitions")?.unwrap_or(-1),
+                replication_factor: flags.parsed("replication-factor")?.unwrap_or(-1),
+                assignments: Vec::new(),
+                configs: configs(&mut flags)?
+                    .into_iter()
+                    .map(|(name, value)| CreatableTopicConfig {
+                        name,
+                        value: Some(value),
+                    })
+                    .collect(),
+            };
+            flags.finish()?;
+            create(Admin::new(config)?, topic).await
+        }
+        "delete" => {
+            let topics = flags.values("topic")?;
+            flags.finish()?;
+            if topics.is_empty() {
+                bail!("--topic is required");
+            }
+            delete(Admin::new(config)?, topics).await
+        }
+        "alter" => {
+            let resource = AlterConfigsResource {
+                resource_type: TOPIC_CONFIG_RESOURCE,
+                resource_name: flags.required("topic")?,
+                configs: configs(&mut flags)?
+                    .into_iter()
+                    .map(|(name, value)| AlterableConfig {
+                        name,
+                        value: Some(value),
+                    })
+                    .collect(),
+            };
+            flags.finish()?;
+            if resource.configs.is_empty() {
+                bail!("--config is required");
+            }
+            alter(Admin::new(config)?, resource).await
+        }
+        _ => bail!("unknown action {:?}\n\n{}", action, USAGE),
+    }
+}
+
+async fn list(mut admin: Admin, exclude_internal: bool) -> Result<()> {
+    let mut names: Vec<String> = admin
+        .describe_topics(None)
+        .await?
+        .into_iter()
+        .filter(|topic| !(exclude_internal && topic.is_internal))
+        .filter_map(|topic| topic.name.0)
+        .collect();
+    names.sort();
+    for name in names {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+async fn describe(mut admin: Admin, topics: Vec<String>) -> Result<()> {
+    let names: Vec<&str> = topics.iter().map(String::as_str).collect();
+    let mut described = admin
+        .describe_topics((!names.is_empty()).then_some(&names[..]))
+        .await?;
+    described.sort_by(|a, b| a.name.0.cmp(&b.name.0));
+    let mut failed = false;
+    for topic in described {
+        let name = topic.name.0.unwrap_or_default();
+        if topic.error_code != ErrorCode::None {
+            eprintln!("Topic: {}\tError: {:?}", name, topic.error_code);
+            failed = true;
+            continue;
+        }
+        let replication_factor = topic
+            .partitions
+            .0
+            .first()
+            .map_or(0, |p| p.replica_nodes.0.len());
+        println!(
+            "Topic: {}\tTopicId: {}\tPartitionCount: {}\tReplicationFactor: {}",
+            name,
+            topic.topic_id,
+            topic.partitions.0.len(),
+            replication_factor
+        );
+        for partition in &topic.partitions.0 {
+            let leader = match partition.leader_id as i32 {
+                -1 => "none".to_string(),
+                leader => leader.to_string(),
+            };
+            println!(
+                "\tTopic: {}\tPartition: {}\tLeader: {}\tReplicas: {}\tIsr: {}",
+                name,
+                partition.partition_index,
+                leader,
+                join(&partition.replica_nodes.0),
+                join(&partition.isr_nodes.0)
+            );
+        }
+    }
+    if failed {
+        bail!("some topics could not be described");
+    }
+    Ok(())
+}
+
+async fn create(mut admin: Admin, topic: CreatableTopic) -> Result<()> {
+    let name = topic.name.clone();
+    let result = admin
+        .create_topics(vec![topic], false)
+        .await?
+        .into_iter()
+        .next()
+        .context("no result for topic")?;
+    check(&name, result.error_code, result.error_message)?;
+    println!("Created topic {}.", name);
+    Ok(())
+}
+
+async fn delete(mut admin: Admin, topics: Vec<String>) -> Result<()> {
+    let names: Vec<&str> = topics.iter().map(String::as_str).collect();
+    let mut failed = false;
+    for result in admin.delete_topics(&names).await? {
+        let name = result.name.unwrap_or_default();
+        if let Err(e) = check(&name, result.error_code, result.error_message) {
+            eprintln!("{:#}", e);
+            failed = true;
+        }
+    }
+    if failed {
+        bail!("some topics could not be deleted");
+    }
+    Ok(())
+}
+
+async fn alter(mut admin: Admin, resource: AlterConfigsResource) -> Result<()> {
+    let name = resource.resource_name.clone();
+    let result = admin
+        .alter_configs(vec![resource], false)
+        .await?
+        .into_iter()
+        .next()
+        .context("no result for topic")?;
+    check(&name, result.error_code, result.error_message)?;
+    println!("Updated configs of topic {}.", name);
+    Ok(())
+}
+
+/// Every `--config KEY=VALUE`.
+fn configs(flags: &mut Flags) -> Result<Vec<(String, String)>> {
+    flags
+        .values("config")?
+        .into_iter()
+        .map(|config| match config.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            _ => bail!("bad --config {:?}, expected KEY=VALUE", config),
+        })
+        .collect()
+}
+
+fn check(topic: &str, error_code: ErrorCode, error_message: Option<String>) -> Result<()> {
+    match (error_code, error_message) {
+        (ErrorCode::None, _) => Ok(()),
+        (error_code, Some(message)) => bail!("topic {}: {:?}: {}", topic, error_code, message),
+        (error_code, None) => bail!("topic {}: {:?}", topic, error_code),
+    }
+}
+
+fn join(ids: &[u32]) -> String {
+    ids.iter()
+        .map(|id| (*id as i32).to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,257 @@
+//! An admin client for tools and tests that manage a cluster over the wire:
+//! topics, configs, the cluster's brokers and groups' committed offsets.
+//! Every request goes to one broker, the first bootstrap server that
+//! answers, and moves on to the next one if that connection fails.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::api::alter_configs::{
+    AlterConfigsRequest, AlterConfigsResource, AlterConfigsResourceResponse, AlterConfigsResponse,
+};
+use crate::api::create_topics::{
+    CreatableTopic, CreatableTopicResult, CreateTopicsRequest, CreateTopicsResponse,
+};
+use crate::api::delete_topics::{
+    DeletableTopicResult, DeleteTopicState, DeleteTopicsRequest, DeleteTopicsResponse,
+};
+use crate::api::describe_cluster::{
+    DescribeClusterRequest, DescribeClusterResponse, ENDPOINT_TYPE_BROKERS,
+};
+use crate::api::describe_configs::{
+    DescribeConfigsRequest, DescribeConfigsResource, DescribeConfigsResponse, DescribeConfigsResult,
+};
+use crate::api::metadata::{
+    MetadataRequest, MetadataRequestTopic, MetadataResponse, MetadataTopic,
+};
+use crate::api::offset_fetch::{
+    OffsetFetchRequest, OffsetFetchRequestGroup, OffsetFetchRequestTopic, OffsetFetchResponse,
+    OffsetFetchResponseTopic,
+};
+use crate::client::Brokers;
+use crate::coordinator::TopicPartition;
+use crate::protocol::{ApiKey, ErrorCode, Uuid};
+
+#[derive(Debug, Clone)]
+pub struct AdminConfig {
+    /// `host:port` of brokers to send requests to, tried in order.
+    pub bootstrap_servers: Vec<String>,
+    pub client_id: String,
+    /// How long a request may take, also sent as the timeout of requests
+    /// that carry one.
+    pub request_timeout: Duration,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            bootstrap_servers: vec!["localhost:9092".to_string()],
+            client_id: "admin".to_string(),
+            request_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+pub struct Admin {
+    config: AdminConfig,
+    brokers: Brokers,
+}
+
+impl Admin {
+    /// Checks the bootstrap servers; the first request connects.
+    pub fn new(config: AdminConfig) -> Result<Self> {
+        let brokers = Brokers::new(
+            &config.bootstrap_servers,
+            &config.client_id,
+            config.request_timeout,
+        )?;
+        Ok(Self { config, brokers })
+    }
+
+    /// Creates `topics`, or with `validate_only` only checks that they could
+    /// be, answering for each topic on its own.
+    pub async fn create_topics(
+        &mut self,
+        topics: Vec<CreatableTopic>,
+        validate_only: bool,
+    ) -> Result<Vec<CreatableTopicResult>> {
+        let req = CreateTopicsRequest {
+            topics,
+            timeout_ms: self.timeout_ms(),
+            validate_only,
+        };
+        let res: CreateTopicsResponse = self
+            .brokers
+            .send_to_any(ApiKey::CreateTopics, 7, &req)
+            .await?;
+        Ok(res.topics)
+    }
+
+    /// Creates one topic with the broker's default configs, returning its
+    /// id. -1 partitions or replicas takes the broker's default.
+    pub async fn create_topic(
+        &mut self,
+        name: &str,
+        num_partitions: i32,
+        replication_factor: i16,
+    ) -> Result<Uuid> {
+        let topic = CreatableTopic {
+            name: name.to_string(),
+            num_partitions,
+            replication_factor,
+            assignments: Vec::new(),
+            configs: Vec::new(),
+        };
+        let result = self
+            .create_topics(vec![topic], false)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("no result for topic {:?}", name))?;
+        check(result.error_code, result.error_message.as_deref())?;
+        Ok(result.topic_id)
+    }
+
+    /// Deletes the topics named `names`, answering for each on its own.
+    pub async fn delete_topics(&mut self, names: &[&str]) -> Result<Vec<DeletableTopicResult>> {
+        let req = DeleteTopicsRequest {
+            topics: names
+                .iter()
+                .map(|name| DeleteTopicState::named(name))
+                .collect(),
+            timeout_ms: self.timeout_ms(),
+        };
+        let res: DeleteTopicsResponse = self
+            .brokers
+            .send_to_any(ApiKey::DeleteTopics, 6, &req)
+            .await?;
+        Ok(res.responses)
+    }
+
+    /// Each of the topics named, or every topic with `None`, with the
+    /// leader and replicas of each of its partitions. A topic that can't be
+    /// described has its error code set.
+    pub async fn describe_topics(&mut self, names: Option<&[&str]>) -> Result<Vec<MetadataTopic>> {
+        let req = MetadataRequest {
+            topics: names.map(|names| {
+                names
+                    .iter()
+                    .map(|name| MetadataRequestTopic::named(name))
+                    .collect()
+            }),
+            allow_auto_topic_creation: false,
+            include_cluster_authorized_operations: false,
+            include_topic_authorized_operations: false,
+        };
+        let res: MetadataResponse = self.brokers.send_to_any(ApiKey::Metadata, 12, &req).await?;
+        Ok(res.topics.0)
+    }
+
+    /// The cluster id, the active controller and the brokers clients can
+    /// reach.
+    pub async fn describe_cluster(&mut self) -> Result<DescribeClusterResponse> {
+        let req = DescribeClusterRequest {
+            include_cluster_authorized_operations: false,
+            endpoint_type: ENDPOINT_TYPE_BROKERS,
+        };
+        let res: DescribeClusterResponse = self
+            .brokers
+            .send_to_any(ApiKey::DescribeCluster, 1, &req)
+            .await?;
+        check(res.error_code, res.error_message.0.as_deref())?;
+        Ok(res)
+    }
+
+    /// The configs of each of `resources`, answering for each on its own.
+    pub async fn describe_configs(
+        &mut self,
+        resources: Vec<DescribeConfigsResource>,
+    ) -> Result<Vec<DescribeConfigsResult>> {
+        let req = DescribeConfigsRequest {
+            resources,
+            include_synonyms: false,
+            include_documentation: false,
+        };
+        let res: DescribeConfigsResponse = self
+            .brokers
+            .send_to_any(ApiKey::DescribeConfigs, 4, &req)
+            .await?;
+        Ok(res.results)
+    }
+
+    /// Replaces the configs of each of `resources` with the ones given, or
+    /// with `validate_only` only checks them, answering for each on its own.
+    /// The broker asked has to be a controller.
+    pub async fn alter_configs(
+        &mut self,
+        resources: Vec<AlterConfigsResource>,
+        validate_only: bool,
+    ) -> Result<Vec<AlterConfigsResourceResponse>> {
+        let req = AlterConfigsRequest {
+            resources,
+            validate_only,
+        };
+        let res: AlterConfigsResponse = self
+            .brokers
+            .send_to_any(ApiKey::AlterConfigs, 1, &req)
+            .await?;
+        Ok(res.responses.0)
+    }
+
+    /// The group's committed offsets for `partitions`, or for every
+    /// partition it has committed with `None`. A partition without one has
+    /// offset -1. The broker asked has to be the group's coordinator.
+    pub async fn list_group_offsets(
+        &mut self,
+        group_id: &str,
+        partitions: Option<&[TopicPartition]>,
+    ) -> Result<Vec<OffsetFetchResponseTopic>> {
+        let topics = partitions.map(|partitions| {
+            let mut topics: Vec<OffsetFetchRequestTopic> = Vec::new();
+            for partition in partitions {
+                match topics.iter_mut().find(|t| t.name == partition.topic) {
+                    Some(topic) => topic.partition_indexes.push(partition.partition),
+                    None => topics.push(OffsetFetchRequestTopic {
+                        name: partition.topic.clone(),
+                        partition_indexes: vec![partition.partition],
+                    }),
+                }
+            }
+            topics
+        });
+        let req = OffsetFetchRequest {
+            groups: vec![OffsetFetchRequestGroup {
+                group_id: group_id.to_string(),
+                member_id: None,
+                member_epoch: -1,
+                topics,
+            }],
+            require_stable: true,
+        };
+        let res: OffsetFetchResponse = self
+            .brokers
+            .send_to_any(ApiKey::OffsetFetch, 9, &req)
+            .await?;
+        let group = res
+            .groups
+            .0
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("no result for group {:?}", group_id))?;
+        check(group.error_code, None)?;
+        Ok(group.topics.0)
+    }
+
+    fn timeout_ms(&self) -> i32 {
+        self.config.request_timeout.as_millis() as i32
+    }
+}
+
+fn check(error_code: ErrorCode, error_message: Option<&str>) -> Result<()> {
+    match (error_code, error_message) {
+        (ErrorCode::None, _) => Ok(()),
+        (error_code, Some(message)) => bail!("{:?}: {}", error_code, message),
+        (error_code, None) => bail!("{:?}", error_code),
+    }
+}
@@ -0,0 +1,969 @@
+//! A consumer for applications embedding this crate: it follows partition
+//! leaders through Metadata, reads with Fetch, and either reads the
+//! partitions it is given or joins a consumer group, whose coordinator
+//! assigns it partitions of the topics it subscribes to.
+//!
+//! Nothing runs in the background. Heartbeats, offset lookups and fetches
+//! all happen inside [`Consumer::poll`], so a consumer that stops polling
+//! for longer than the group's session timeout is taken out of its group.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::api::consumer_group_heartbeat::{
+    ConsumerGroupHeartbeatRequest, ConsumerGroupHeartbeatResponse, TopicPartitions,
+};
+use crate::api::fetch::{FetchRequestV16, FetchResponseV16, Partition, TopicRequest};
+use crate::api::find_coordinator::{
+    FindCoordinatorRequest, FindCoordinatorResponse, KEY_TYPE_GROUP,
+};
+use crate::api::list_offsets::{
+    ListOffsetsPartition, ListOffsetsRequest, ListOffsetsResponse, ListOffsetsTopic,
+    CONSUMER_REPLICA_ID, LATEST_TIMESTAMP,
+};
+use crate::api::metadata::{MetadataRequest, MetadataRequestTopic, MetadataResponse};
+use crate::api::offset_commit::{
+    OffsetCommitRequest, OffsetCommitRequestPartition, OffsetCommitRequestTopic,
+    OffsetCommitResponse,
+};
+use crate::api::offset_fetch::{
+    OffsetFetchRequest, OffsetFetchRequestGroup, OffsetFetchRequestTopic, OffsetFetchResponse,
+};
+use crate::client::Brokers;
+use crate::coordinator::TopicPartition;
+use crate::protocol::{ApiKey, ErrorCode, Uuid};
+use crate::record_batch::decode_batches;
+
+/// The member epoch that tells the coordinator a member is leaving.
+const LEAVE_GROUP_MEMBER_EPOCH: i32 = -1;
+
+/// Where to start reading a partition that has no committed offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetReset {
+    Earliest,
+    Latest,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsumerConfig {
+    /// `host:port` of brokers to learn the cluster from, tried in order.
+    pub bootstrap_servers: Vec<String>,
+    pub client_id: String,
+    /// Needed to subscribe and to commit offsets.
+    pub group_id: Option<String>,
+    /// How long a poll waits between fetches that return nothing.
+    pub fetch_max_wait: Duration,
+    pub fetch_min_bytes: u32,
+    pub fetch_max_bytes: u32,
+    pub max_partition_fetch_bytes: i32,
+    pub auto_offset_reset: OffsetReset,
+    /// How long the coordinator gives this member to let go of partitions
+    /// it is moving elsewhere.
+    pub rebalance_timeout: Duration,
+    pub request_timeout: Duration,
+}
+
+impl Default for ConsumerConfig {
+    fn default() -> Self {
+        Self {
+            bootstrap_servers: vec!["localhost:9092".to_string()],
+            client_id: "consumer".to_string(),
+            group_id: None,
+            fetch_max_wait: Duration::from_millis(500),
+            fetch_min_bytes: 1,
+            fetch_max_bytes: 50 * 1024 * 1024,
+            max_partition_fetch_bytes: 1024 * 1024,
+            auto_offset_reset: OffsetReset::Earliest,
+            rebalance_timeout: Duration::from_secs(300),
+            request_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A record as [`Consumer::poll`] returns it.
+#[derive(Debug, Clone)]
+pub struct ConsumerRecord {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub timestamp: i64,
+    pub key: Option<Bytes>,
+    pub value: Option<Bytes>,
+    pub headers: Vec<(String, Option<Bytes>)>,
+}
+
+struct TopicMetadata {
+    id: Uuid,
+    /// Each partition's leader and leader epoch; -1 without a leader.
+    partitions: BTreeMap<i32, (i32, i32)>,
+}
+
+/// This consumer's place in its group, once it has subscribed.
+struct Membership {
+    coordinator: Option<i32>,
+    /// Empty until the coordinator gives the member an id.
+    member_id: String,
+    /// 0 until the member has joined.
+    member_epoch: i32,
+    heartbeat_interval: Duration,
+    next_heartbeat: Instant,
+    /// Whether the coordinator has yet to hear the current subscription.
+    subscription_changed: bool,
+}
+
+pub struct Consumer {
+    config: ConsumerConfig,
+    brokers: Brokers,
+    topics: HashMap<String, TopicMetadata>,
+    metadata_stale: bool,
+    /// `None` when partitions are assigned by hand.
+    subscription: Option<BTreeSet<String>>,
+    /// The next offset to read from each partition, `None` until it is
+    /// looked up.
+    assignment: BTreeMap<TopicPartition, Option<i64>>,
+    membership: Option<Membership>,
+}
+
+impl Consumer {
+    /// Reads the cluster's brokers from the first bootstrap server that
+    /// answers.
+    pub async fn connect(config: ConsumerConfig) -> Result<Self> {
+        let brokers = Brokers::new(
+            &config.bootstrap_servers,
+            &config.client_id,
+            config.request_timeout,
+        )?;
+        let mut consumer = Self {
+            config,
+            brokers,
+            topics: HashMap::new(),
+            metadata_stale: true,
+            subscription: None,
+            assignment: BTreeMap::new(),
+            membership: None,
+        };
+        consumer.refresh_metadata().await?;
+        Ok(consumer)
+    }
+
+    /// Joins the consumer group on the next poll, which then reads whatever
+    /// partitions of `topics` the coordinator assigns. Replaces any earlier
+    /// subscription or assignment.
+    pub fn subscribe(&mut self, topics: &[&str]) -> Result<()> {
+        if self.config.group_id.is_none() {
+            bail!("subscribing takes a group id");
+        }
+        let topics: BTreeSet<_> = topics.iter().map(|t| t.to_string()).collect();
+        if self.subscription.as_ref() == Some(&topics) {
+            return Ok(());
+        }
+        self.subscription = Some(topics);
+        self.metadata_stale = true;
+        match &mut self.membership {
+            Some(membership) => {
+                membership.subscription_changed = true;
+                membership.next_heartbeat = Instant::now();
+            }
+            None => {
+                self.assignment.clear();
+                self.membership = Some(Membership {
+                    coordinator: None,
+                    member_id: String::new(),
+                    member_epoch: 0,
+                    heartbeat_interval: Duration::ZERO,
+                    next_heartbeat: Instant::now(),
+                    subscription_changed: true,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads exactly `partitions`, outside any group. Replaces any earlier
+    /// subscription or assignment; a group member leaves its group first.
+    pub async fn assign(&mut self, partitions: Vec<TopicPartition>) -> Result<()> {
+        self.leave_group().await?;
+        self.subscription = None;
+        self.assignment = partitions.into_iter().map(|p| (p, None)).collect();
+        self.metadata_stale = true;
+        Ok(())
+    }
+
+    /// The partitions being read, whether assigned by hand or by the group.
+    pub fn assignment(&self) -> Vec<TopicPartition> {
+        self.assignment.keys().cloned().collect()
+    }
+
+    /// Reads `partition` from `offset` on the next poll.
+    pub fn seek(&mut self, partition: &TopicPartition, offset: i64) -> Result<()> {
+        let position = self.assignment.get_mut(partition).with_context(|| {
+            format!("{}-{} isn't assigned", partition.topic, partition.partition)
+        })?;
+        *position = Some(offset);
+        Ok(())
+    }
+
+    /// Reads each of `partitions` from the start of its log on the next
+    /// poll.
+    pub fn seek_to_beginning(&mut self, partitions: &[TopicPartition]) -> Result<()> {
+        for partition in partitions {
+            self.seek(partition, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Reads each of `partitions` from the end of its log, only getting
+    /// records written from now on.
+    pub async fn seek_to_end(&mut self, partitions: &[TopicPartition]) -> Result<()> {
+        if self.metadata_stale {
+            self.refresh_metadata().await?;
+        }
+        let latest = self.latest_offsets(partitions).await?;
+        for partition in partitions {
+            let offset = latest.get(partition).with_context(|| {
+                format!(
+                    "no end offset for {}-{}",
+                    partition.topic, partition.partition
+                )
+            })?;
+            self.seek(partition, *offset)?;
+        }
+        Ok(())
+    }
+
+    /// The partitions `topic` has, as the cluster's metadata lists them.
+    pub async fn partitions_for(&mut self, topic: &str) -> Result<Vec<i32>> {
+        let req = MetadataRequest {
+            topics: Some(vec![MetadataRequestTopic::named(topic)]),
+            allow_auto_topic_creation: false,
+            include_cluster_authorized_operations: false,
+            include_topic_authorized_operations: false,
+        };
+        let res: MetadataResponse = self.brokers.send_to_any(ApiKey::Metadata, 12, &req).await?;
+        let metadata = res
+            .topics
+            .0
+            .iter()
+            .find(|t| t.name.0.as_deref() == Some(topic))
+            .with_context(|| format!("no metadata for topic {:?}", topic))?;
+        if metadata.error_code != ErrorCode::None {
+            bail!("topic {:?}: {:?}", topic, metadata.error_code);
+        }
+        Ok(metadata
+            .partitions
+            .0
+            .iter()
+            .map(|p| p.partition_index as i32)
+            .collect())
+    }
+
+    /// The offset the next record read from `partition` will have, once
+    /// known.
+    pub fn position(&self, partition: &TopicPartition) -> Option<i64> {
+        self.assignment.get(partition).copied().flatten()
+    }
+
+    /// Heartbeats if one is due, then fetches until there are records or
+    /// `timeout` runs out.
+    pub async fn poll(&mut self, timeout: Duration) -> Result<Vec<ConsumerRecord>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.heartbeat_if_due().await?;
+            if self.metadata_stale {
+                if let Err(e) = self.refresh_metadata().await {
+                    debug!(error = %e, "failed to refresh metadata");
+                }
+            }
+            self.update_positions().await?;
+            let records = self.fetch().await?;
+            let now = Instant::now();
+            if !records.is_empty() || now >= deadline {
+                return Ok(records);
+            }
+            let mut wake = deadline.min(now + self.config.fetch_max_wait);
+            if let Some(membership) = &self.membership {
+                wake = wake.min(membership.next_heartbeat);
+            }
+            tokio::time::sleep_until(wake).await;
+        }
+    }
+
+    /// Commits the position of every assigned partition whose position is
+    /// known, as the group's offsets.
+    pub async fn commit(&mut self) -> Result<()> {
+        let group_id = self
+            .config
+            .group_id
+            .clone()
+            .context("committing takes a group id")?;
+        let mut topics: Vec<OffsetCommitRequestTopic> = Vec::new();
+        for (partition, position) in &self.assignment {
+            let Some(offset) = position else {
+                continue;
+            };
+            let committed = OffsetCommitRequestPartition {
+                partition_index: partition.partition,
+                committed_offset: *offset,
+                committed_leader_epoch: -1,
+                committed_metadata: None,
+            };
+            match topics.iter_mut().find(|t| t.name == partition.topic) {
+                Some(topic) => topic.partitions.push(committed),
+                None => topics.push(OffsetCommitRequestTopic {
+                    name: partition.topic.clone(),
+                    partitions: vec![committed],
+                }),
+            }
+        }
+        if topics.is_empty() {
+            return Ok(());
+        }
+        // Consumers outside a group commit as the group's simple consumers
+        // do, with no member id and generation -1.
+        let (generation_id, member_id) = match &self.membership {
+            Some(membership) => (membership.member_epoch, membership.member_id.clone()),
+            None => (-1, String::new()),
+        };
+        let req = OffsetCommitRequest {
+            group_id,
+            generation_id,
+            member_id,
+            group_instance_id: None,
+            topics,
+        };
+        let coordinator = self.coordinator().await?;
+        let res: OffsetCommitResponse = self
+            .brokers
+            .send_to(coordinator, ApiKey::OffsetCommit, 9, &req)
+            .await?;
+        for topic in &res.topics.0 {
+            for partition in &topic.partitions.0 {
+                match partition.error_code {
+                    ErrorCode::None => {}
+                    ErrorCode::CoordinatorNotAvailable => {
+                        self.lose_coordinator();
+                        bail!("group coordinator not available");
+                    }
+                    error_code => bail!(
+                        "failed to commit {}-{}: {:?}",
+                        topic.name,
+                        partition.partition_index,
+                        error_code
+                    ),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the group, if this consumer joined one.
+    pub async fn close(mut self) -> Result<()> {
+        self.leave_group().await
+    }
+
+    /// Every topic being read or subscribed to.
+    fn wanted_topics(&self) -> BTreeSet<String> {
+        let mut topics: BTreeSet<_> = self.assignment.keys().map(|p| p.topic.clone()).collect();
+        if let Some(subscription) = &self.subscription {
+            topics.extend(subscription.iter().cloned());
+        }
+        topics
+    }
+
+    /// Reads where the brokers are and who leads the wanted topics'
+    /// partitions.
+    async fn refresh_metadata(&mut self) -> Result<()> {
+        let req = MetadataRequest {
+            topics: Some(
+                self.wanted_topics()
+                    .iter()
+                    .map(|name| MetadataRequestTopic::named(name))
+                    .collect(),
+            ),
+            allow_auto_topic_creation: false,
+            include_cluster_authorized_operations: false,
+            include_topic_authorized_operations: false,
+        };
+        let res: MetadataResponse = self.brokers.send_to_any(ApiKey::Metadata, 12, &req).await?;
+        self.brokers.update(&res.brokers.0);
+        self.topics.clear();
+        for topic in &res.topics.0 {
+            let Some(name) = topic.name.0.clone() else {
+                continue;
+            };
+            if topic.error_code != ErrorCode::None {
+                debug!(topic = %name, error = ?topic.error_code, "topic has no metadata");
+                continue;
+            }
+            let partitions = topic
+                .partitions
+                .0
+                .iter()
+                .map(|p| {
+                    let leader = if p.error_code == ErrorCode::None {
+                        p.leader_id as i32
+                    } else {
+                        -1
+                    };
+                    (p.partition_index as i32, (leader, p.leader_epoch as i32))
+                })
+                .collect();
+            self.topics.insert(
+                name,
+                TopicMetadata {
+                    id: topic.topic_id.clone(),
+                    partitions,
+                },
+            );
+        }
+        self.metadata_stale = false;
+        Ok(())
+    }
+
+    async fn heartbeat_if_due(&mut self) -> Result<()> {
+        let Some(membership) = &self.membership else {
+            return Ok(());
+        };
+        if membership.next_heartbeat > Instant::now() {
+            return Ok(());
+        }
+        self.heartbeat().await
+    }
+
+    /// Joins the group, or tells the coordinator this member is alive and
+    /// which partitions it owns, taking whatever assignment it sends back.
+    async fn heartbeat(&mut self) -> Result<()> {
+        let group_id = self.config.group_id.clone().unwrap_or_default();
+        let coordinator = match self.coordinator().await {
+            Ok(coordinator) => coordinator,
+            Err(e) => {
+                self.retry_heartbeat();
+                debug!(error = %e, "failed to find group coordinator");
+                return Ok(());
+            }
+        };
+        let membership = self.membership.as_ref().unwrap();
+        let joining = membership.member_epoch == 0;
+        let req = ConsumerGroupHeartbeatRequest {
+            group_id,
+            member_id: membership.member_id.clone(),
+            member_epoch: membership.member_epoch,
+            instance_id: None,
+            rack_id: None,
+            rebalance_timeout_ms: if joining {
+                self.config.rebalance_timeout.as_millis() as i32
+            } else {
+                -1
+            },
+            subscribed_topic_names: (joining || membership.subscription_changed)
+                .then(|| self.subscription.iter().flatten().cloned().collect()),
+            server_assignor: None,
+            topic_partitions: Some(self.owned_partitions()),
+        };
+        let res: ConsumerGroupHeartbeatResponse = match self
+            .brokers
+            .send_to(coordinator, ApiKey::ConsumerGroupHeartbeat, 0, &req)
+            .await
+        {
+            Ok(res) => res,
+            Err(e) => {
+                debug!(error = %e, "failed to heartbeat");
+                self.lose_coordinator();
+                self.retry_heartbeat();
+                return Ok(());
+            }
+        };
+        match res.error_code {
+            ErrorCode::None => {}
+            ErrorCode::CoordinatorNotAvailable => {
+                self.lose_coordinator();
+                self.retry_heartbeat();
+                return Ok(());
+            }
+            // The coordinator no longer counts this member in the group, so
+            // its partitions may already be someone else's.
+            ErrorCode::UnknownMemberId | ErrorCode::FencedMemberEpoch => {
+                warn!(error = ?res.error_code, "lost group membership, joining again");
+                let membership = self.membership.as_mut().unwrap();
+                if res.error_code == ErrorCode::UnknownMemberId {
+                    membership.member_id.clear();
+                }
+                membership.member_epoch = 0;
+                membership.next_heartbeat = Instant::now();
+                self.assignment.clear();
+                return Ok(());
+            }
+            error_code => bail!("group heartbeat failed: {:?}", error_code),
+        }
+
+        let membership = self.membership.as_mut().unwrap();
+        if let Some(member_id) = res.member_id.0 {
+            membership.member_id = member_id;
+        }
+        if joining {
+            info!(member_id = %membership.member_id, "joined group");
+        }
+        membership.member_epoch = res.member_epoch;
+        membership.subscription_changed = false;
+        membership.heartbeat_interval =
+            Duration::from_millis(res.heartbeat_interval_ms.max(0) as u64);
+        membership.next_heartbeat = Instant::now() + membership.heartbeat_interval;
+        if let Some(assignment) = res.assignment {
+            if self.take_assignment(assignment.0).await? {
+                // The coordinator learns the new assignment was taken from
+                // the next heartbeat, so send it straight away.
+                self.membership.as_mut().unwrap().next_heartbeat = Instant::now();
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the assignment with `assigned`, keeping the positions of
+    /// partitions kept. Whether anything changed.
+    async fn take_assignment(&mut self, assigned: Vec<TopicPartitions>) -> Result<bool> {
+        let unknown = |topics: &HashMap<String, TopicMetadata>| {
+            assigned
+                .iter()
+                .any(|t| !topics.values().any(|topic| topic.id == t.topic_id))
+        };
+        if unknown(&self.topics) {
+            self.refresh_metadata().await?;
+        }
+        let mut partitions = BTreeSet::new();
+        for topic_partitions in &assigned {
+            let Some((name, _)) = self
+                .topics
+                .iter()
+                .find(|(_, topic)| topic.id == topic_partitions.topic_id)
+            else {
+                warn!(topic_id = %topic_partitions.topic_id, "assigned a topic with no metadata");
+                continue;
+            };
+            for &partition in &topic_partitions.partitions {
+                partitions.insert(TopicPartition {
+                    topic: name.clone(),
+                    partition,
+                });
+            }
+        }
+        let before: BTreeSet<_> = self.assignment.keys().cloned().collect();
+        if before == partitions {
+            return Ok(false);
+        }
+        info!(
+            partitions = ?partitions
+                .iter()
+                .map(|p| format!("{}-{}", p.topic, p.partition))
+                .collect::<Vec<_>>(),
+            "group assignment changed"
+        );
+        self.assignment.retain(|p, _| partitions.contains(p));
+        for partition in partitions {
+            self.assignment.entry(partition).or_insert(None);
+        }
+        Ok(true)
+    }
+
+    /// The assignment as the coordinator names it, by topic id.
+    fn owned_partitions(&self) -> Vec<TopicPartitions> {
+        let mut owned: Vec<TopicPartitions> = Vec::new();
+        for partition in self.assignment.keys() {
+            let Some(topic) = self.topics.get(&partition.topic) else {
+                continue;
+            };
+            match owned.iter_mut().find(|t| t.topic_id == topic.id) {
+                Some(t) => t.partitions.push(partition.partition),
+                None => owned.push(TopicPartitions {
+                    topic_id: topic.id.clone(),
+                    partitions: vec![partition.partition],
+                }),
+            }
+        }
+        owned
+    }
+
+    fn retry_heartbeat(&mut self) {
+        if let Some(membership) = &mut self.membership {
+            membership.next_heartbeat = Instant::now() + self.config.fetch_max_wait;
+        }
+    }
+
+    async fn leave_group(&mut self) -> Result<()> {
+        let Some(membership) = self.membership.take() else {
+            return Ok(());
+        };
+        self.assignment.clear();
+        if membership.member_epoch <= 0 {
+            return Ok(());
+        }
+        let Some(coordinator) = membership.coordinator else {
+            return Ok(());
+        };
+        let req = ConsumerGroupHeartbeatRequest {
+            group_id: self.config.group_id.clone().unwrap_or_default(),
+            member_id: membership.member_id.clone(),
+            member_epoch: LEAVE_GROUP_MEMBER_EPOCH,
+            instance_id: None,
+            rack_id: None,
+            rebalance_timeout_ms: -1,
+            subscribed_topic_names: None,
+            server_assignor: None,
+            topic_partitions: None,
+        };
+        let res: ConsumerGroupHeartbeatResponse = self
+            .brokers
+            .send_to(coordinator, ApiKey::ConsumerGroupHeartbeat, 0, &req)
+            .await?;
+        if res.error_code != ErrorCode::None {
+            bail!("failed to leave group: {:?}", res.error_code);
+        }
+        info!(member_id = %membership.member_id, "left group");
+        Ok(())
+    }
+
+    /// The broker coordinating the group, looked up if not yet known.
+    async fn coordinator(&mut self) -> Result<i32> {
+        if let Some(coordinator) = self.membership.as_ref().and_then(|m| m.coordinator) {
+            return Ok(coordinator);
+        }
+        let group_id = self.config.group_id.clone().context("no group id")?;
+        let req = FindCoordinatorRequest {
+            key_type: KEY_TYPE_GROUP,
+            coordinator_keys: vec![group_id],
+        };
+        let res: FindCoordinatorResponse = self
+            .brokers
+            .send_to_any(ApiKey::FindCoordinator, 4, &req)
+            .await?;
+        let coordinator = res
+            .coordinators
+            .0
+            .first()
+            .context("no coordinator in response")?;
+        if coordinator.error_code != ErrorCode::None {
+            bail!("failed to find coordinator: {:?}", coordinator.error_code);
+        }
+        self.brokers.insert(
+            coordinator.node_id,
+            coordinator.host.clone(),
+            coordinator.port as u16,
+        );
+        if let Some(membership) = &mut self.membership {
+            membership.coordinator = Some(coordinator.node_id);
+        }
+        Ok(coordinator.node_id)
+    }
+
+    fn lose_coordinator(&mut self) {
+        if let Some(membership) = &mut self.membership {
+            if let Some(coordinator) = membership.coordinator.take() {
+                self.brokers.disconnect(coordinator);
+            }
+        }
+    }
+
+    /// Looks up where to start reading partitions with no position yet:
+    /// the group's committed offset, or else the start or end of the log as
+    /// `auto_offset_reset` says.
+    async fn update_positions(&mut self) -> Result<()> {
+        let unknown: Vec<TopicPartition> = self
+            .assignment
+            .iter()
+            .filter(|(_, position)| position.is_none())
+            .map(|(partition, _)| partition.clone())
+            .collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        let mut committed = HashMap::new();
+        if let Some(group_id) = self.config.group_id.clone() {
+            committed = self.committed(group_id, &unknown).await?;
+        }
+        let uncommitted: Vec<TopicPartition> = unknown
+            .iter()
+            .filter(|p| !committed.contains_key(*p))
+            .cloned()
+            .collect();
+        let reset = match self.config.auto_offset_reset {
+            // The broker keeps every log from offset 0, and a fetch from
+            // there starts at the first batch it still has.
+            OffsetReset::Earliest => uncommitted.into_iter().map(|p| (p, 0)).collect(),
+            OffsetReset::Latest => self.latest_offsets(&uncommitted).await?,
+        };
+        for partition in unknown {
+            // Partitions whose leader couldn't say are looked up again on
+            // the next poll.
+            if let Some(offset) = committed.get(&partition).or(reset.get(&partition)) {
+                self.assignment.insert(partition, Some(*offset));
+            }
+        }
+        Ok(())
+    }
+
+    /// The end of each of `partitions`' logs, from their leaders, leaving
+    /// out those whose leader isn't known or has moved.
+    async fn latest_offsets(
+        &mut self,
+        partitions: &[TopicPartition],
+    ) -> Result<HashMap<TopicPartition, i64>> {
+        let mut by_leader: BTreeMap<i32, Vec<ListOffsetsTopic>> = BTreeMap::new();
+        for partition in partitions {
+            let Some((leader, leader_epoch)) = self
+                .topics
+                .get(&partition.topic)
+                .and_then(|t| t.partitions.get(&partition.partition))
+                .copied()
+                .filter(|(leader, _)| *leader >= 0)
+            else {
+                self.metadata_stale = true;
+                continue;
+            };
+            let lookup = ListOffsetsPartition {
+                partition_index: partition.partition,
+                current_leader_epoch: leader_epoch,
+                timestamp: LATEST_TIMESTAMP,
+            };
+            let topics = by_leader.entry(leader).or_default();
+            match topics.iter_mut().find(|t| t.name == partition.topic) {
+                Some(topic) => topic.partitions.push(lookup),
+                None => topics.push(ListOffsetsTopic {
+                    name: partition.topic.clone(),
+                    partitions: vec![lookup],
+                }),
+            }
+        }
+
+        let mut offsets = HashMap::new();
+        for (leader, topics) in by_leader {
+            let req = ListOffsetsRequest {
+                replica_id: CONSUMER_REPLICA_ID,
+                isolation_level: 0,
+                topics,
+            };
+            let res: ListOffsetsResponse = self
+                .brokers
+                .send_to(leader, ApiKey::ListOffsets, 7, &req)
+                .await?;
+            for topic in res.topics {
+                for partition in topic.partitions {
+                    match partition.error_code {
+                        ErrorCode::None => {
+                            offsets.insert(
+                                TopicPartition {
+                                    topic: topic.name.clone(),
+                                    partition: partition.partition_index,
+                                },
+                                partition.offset,
+                            );
+                        }
+                        ErrorCode::NotLeaderOrFollower
+                        | ErrorCode::FencedLeaderEpoch
+                        | ErrorCode::UnknownLeaderEpoch
+                        | ErrorCode::LeaderNotAvailable => self.metadata_stale = true,
+                        error_code => bail!(
+                            "failed to list offsets of {}-{}: {:?}",
+                            topic.name,
+                            partition.partition_index,
+                            error_code
+                        ),
+                    }
+                }
+            }
+        }
+        Ok(offsets)
+    }
+
+    /// The group's committed offsets for `partitions`, leaving out those
+    /// with none.
+    async fn committed(
+        &mut self,
+        group_id: String,
+        partitions: &[TopicPartition],
+    ) -> Result<HashMap<TopicPartition, i64>> {
+        let mut topics: Vec<OffsetFetchRequestTopic> = Vec::new();
+        for partition in partitions {
+            match topics.iter_mut().find(|t| t.name == partition.topic) {
+                Some(topic) => topic.partition_indexes.push(partition.partition),
+                None => topics.push(OffsetFetchRequestTopic {
+                    name: partition.topic.clone(),
+                    partition_indexes: vec![partition.partition],
+                }),
+            }
+        }
+        let (member_id, member_epoch) = match &self.membership {
+            Some(membership) => (Some(membership.member_id.clone()), membership.member_epoch),
+            None => (None, -1),
+        };
+        let req = OffsetFetchRequest {
+            groups: vec![OffsetFetchRequestGroup {
+                group_id,
+                member_id,
+                member_epoch,
+                topics: Some(topics),
+            }],
+            require_stable: true,
+        };
+        let coordinator = self.coordinator().await?;
+        let res: OffsetFetchResponse = match self
+            .brokers
+            .send_to(coordinator, ApiKey::OffsetFetch, 9, &req)
+            .await
+        {
+            Ok(res) => res,
+            Err(e) => {
+                self.lose_coordinator();
+                return Err(e);
+            }
+        };
+        let group = res.groups.0.first().context("no group in response")?;
+        if group.error_code != ErrorCode::None {
+            if group.error_code == ErrorCode::CoordinatorNotAvailable {
+                self.lose_coordinator();
+            }
+            bail!("failed to fetch committed offsets: {:?}", group.error_code);
+        }
+        let mut committed = HashMap::new();
+        for topic in &group.topics.0 {
+            for partition in &topic.partitions.0 {
+                if partition.error_code != ErrorCode::None {
+                    bail!(
+                        "failed to fetch committed offset of {}-{}: {:?}",
+                        topic.name,
+                        partition.partition_index,
+                        partition.error_code
+                    );
+                }
+                if partition.committed_offset >= 0 {
+                    committed.insert(
+                        TopicPartition {
+                            topic: topic.name.clone(),
+                            partition: partition.partition_index,
+                        },
+                        partition.committed_offset,
+                    );
+                }
+            }
+        }
+        Ok(committed)
+    }
+
+    /// One fetch from the leader of each assigned partition, moving each
+    /// partition's position past the records read from it.
+    async fn fetch(&mut self) -> Result<Vec<ConsumerRecord>> {
+        let mut by_leader: BTreeMap<i32, Vec<(TopicPartition, i64)>> = BTreeMap::new();
+        for (partition, position) in &self.assignment {
+            let Some(position) = position else {
+                continue;
+            };
+            let leader = self
+                .topics
+                .get(&partition.topic)
+                .and_then(|t| t.partitions.get(&partition.partition))
+                .map_or(-1, |(leader, _)| *leader);
+            if leader < 0 {
+                self.metadata_stale = true;
+                continue;
+            }
+            by_leader
+                .entry(leader)
+                .or_default()
+                .push((partition.clone(), *position));
+        }
+
+        let mut records = Vec::new();
+        for (leader, partitions) in by_leader {
+            let mut topics: Vec<(Uuid, Vec<Partition>)> = Vec::new();
+            for (partition, position) in &partitions {
+                let topic = &self.topics[&partition.topic];
+                let leader_epoch = topic.partitions[&partition.partition].1;
+                let fetch = Partition::new(
+                    partition.partition,
+                    leader_epoch,
+                    *position,
+                    self.config.max_partition_fetch_bytes,
+                );
+                match topics.iter_mut().find(|(id, _)| *id == topic.id) {
+                    Some((_, fetches)) => fetches.push(fetch),
+                    None => topics.push((topic.id.clone(), vec![fetch])),
+                }
+            }
+            let req = FetchRequestV16::consumer(
+                self.config.fetch_max_wait,
+                self.config.fetch_min_bytes,
+                self.config.fetch_max_bytes,
+                topics
+                    .into_iter()
+                    .map(|(id, fetches)| TopicRequest::new(id.0, fetches))
+                    .collect(),
+            );
+            let res: FetchResponseV16 =
+                match self.brokers.send_to(leader, ApiKey::Fetch, 16, &req).await {
+                    Ok(res) => res,
+                    Err(e) => {
+                        debug!(leader, error = %e, "failed to fetch");
+                        self.metadata_stale = true;
+                        continue;
+                    }
+                };
+            if res.error_code != ErrorCode::None {
+                bail!("fetch failed: {:?}", res.error_code);
+            }
+            for (topic_id, answer) in res.partitions() {
+                let Some((name, _)) = self.topics.iter().find(|(_, t)| t.id.0 == topic_id) else {
+                    continue;
+                };
+                let partition = TopicPartition {
+                    topic: name.clone(),
+                    partition: answer.partition_index,
+                };
+                match answer.error_code {
+                    ErrorCode::None => {}
+                    ErrorCode::NotLeaderOrFollower
+                    | ErrorCode::FencedLeaderEpoch
+                    | ErrorCode::UnknownLeaderEpoch
+                    | ErrorCode::LeaderNotAvailable
+                    | ErrorCode::UnknownTopicId
+                    | ErrorCode::UnknownTopicOrPartition => {
+                        self.metadata_stale = true;
+                        continue;
+                    }
+                    error_code => bail!(
+                        "failed to fetch {}-{}: {:?}",
+                        partition.topic,
+                        partition.partition,
+                        error_code
+                    ),
+                }
+                // The partition may have been unassigned by a heartbeat
+                // since the fetch went out.
+                let Some(Some(position)) = self.assignment.get(&partition).copied() else {
+                    continue;
+                };
+                let mut next = position;
+                // The first batch may start before the position.
+                for record in decode_batches(answer.records.clone())?
+                    .into_iter()
+                    .filter(|r| r.offset >= position)
+                {
+                    next = record.offset + 1;
+                    records.push(ConsumerRecord {
+                        topic: partition.topic.clone(),
+                        partition: partition.partition,
+                        offset: record.offset,
+                        timestamp: record.timestamp,
+                        key: record.key,
+                        value: record.value,
+                        headers: record.headers,
+                    });
+                }
+                self.assignment.insert(partition, Some(next));
+            }
+        }
+        Ok(records)
+    }
+}
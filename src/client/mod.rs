@@ -0,0 +1,349 @@
+//! Requests from this broker to another one, such as a quorum voter or a
+//! partition leader, and the clients built on them for applications.
+
+mod admin;
+mod consumer;
+mod producer;
+
+pub use admin::*;
+pub use consumer::*;
+pub use producer::*;
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{Buf, BufMut, BytesMut};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tracing::debug;
+
+use crate::api::metadata::MetadataBroker;
+use crate::frame::FrameDecoder;
+use crate::protocol::{
+    ApiKey, Deserialize, ErrorCode, NullableString, Response, Serialize, TagBuffer,
+};
+use crate::raft::Voter;
+
+/// Responses bigger than this are taken to be garbage.
+const MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+/// A plaintext connection carrying one request at a time.
+pub struct Connection {
+    stream: TcpStream,
+    frames: FrameDecoder,
+    client_id: String,
+    correlation_id: i32,
+}
+
+impl Connection {
+    pub async fn connect(host: &str, port: u16, client_id: &str) -> Result<Self> {
+        let stream = TcpStream::connect((host, port))
+            .await
+            .with_context(|| format!("connect to {}:{}", host, port))?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            frames: FrameDecoder::new(MAX_RESPONSE_SIZE),
+            client_id: client_id.to_string(),
+            correlation_id: 0,
+        })
+    }
+
+    /// Sends `request` as version `api_version` of `api_key` and reads the
+    /// response, header included, as `R`.
+    pub async fn send<R: Deserialize<R>>(
+        &mut self,
+        api_key: ApiKey,
+        api_version: i16,
+        request: &impl Serialize,
+    ) -> Result<R> {
+        self.write_request(api_key, api_version, request).await?;
+        let mut data = self
+            .frames
+            .read_frame(&mut self.stream)
+            .await
+            .context("read response")?;
+        if data.len() < 4 {
+            return Err(anyhow!("response size {} out of range", data.len()));
+        }
+        // Every response header starts with the correlation id.
+        let correlation_id = data.clone().get_i32();
+        if correlation_id != self.correlation_id {
+            return Err(anyhow!(
+                "response has correlation id {}, expected {}",
+                correlation_id,
+                self.correlation_id
+            ));
+        }
+        R::deserialize(&mut data).with_context(|| format!("malformed {:?} response", api_key))
+    }
+
+    /// Sends `request` without waiting for a response, for requests the
+    /// broker doesn't answer, such as a produce with acks 0.
+    pub async fn send_without_response(
+        &mut self,
+        api_key: ApiKey,
+        api_version: i16,
+        request: &impl Serialize,
+    ) -> Result<()> {
+        self.write_request(api_key, api_version, request).await
+    }
+
+    async fn write_request(
+        &mut self,
+        api_key: ApiKey,
+        api_version: i16,
+        request: &impl Serialize,
+    ) -> Result<()> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let mut header = BytesMut::new();
+        header.put_i16(api_key.into());
+        header.put_i16(api_version);
+        header.put_i32(self.correlation_id);
+        header.put(NullableString(Some(self.client_id.clone())).serialize());
+        if api_key.is_flexible(api_version) {
+            header.put(TagBuffer::serialize());
+        }
+        let body = request.serialize();
+        let mut frame = BytesMut::with_capacity(4 + header.len() + body.len());
+        frame.put_i32((header.len() + body.len()) as i32);
+        frame.put(header);
+        frame.put(body);
+        self.stream.write_all(&frame).await?;
+        Ok(())
+    }
+}
+
+/// Reads a `host:port` address, as bootstrap servers are given.
+fn parse_address(address: &str) -> Result<(String, u16)> {
+    let (host, port) = address
+        .rsplit_once(':')
+        .with_context(|| format!("address {:?} has no port", address))?;
+    let port = port
+        .parse()
+        .with_context(|| format!("address {:?} has a bad port", address))?;
+    Ok((host.to_string(), port))
+}
+
+/// Connections to a cluster's brokers, opened when first needed, for
+/// clients that send each request to a particular broker.
+struct Brokers {
+    client_id: String,
+    request_timeout: Duration,
+    /// `host:port` of brokers to learn the cluster from, tried in order.
+    bootstrap: Vec<(String, u16)>,
+    /// Where each known broker listens, by id.
+    addresses: HashMap<i32, (String, u16)>,
+    connections: HashMap<i32, Connection>,
+    /// The connection requests for any broker go over, such as metadata
+    /// and coordinator lookups.
+    any_broker: Option<Connection>,
+}
+
+impl Brokers {
+    fn new(
+        bootstrap_servers: &[String],
+        client_id: &str,
+        request_timeout: Duration,
+    ) -> Result<Self> {
+        let bootstrap = bootstrap_servers
+            .iter()
+            .map(|server| parse_address(server))
+            .collect::<Result<Vec<_>>>()?;
+        if bootstrap.is_empty() {
+            bail!("no bootstrap servers");
+        }
+        Ok(Self {
+            client_id: client_id.to_string(),
+            request_timeout,
+            bootstrap,
+            addresses: HashMap::new(),
+            connections: HashMap::new(),
+            any_broker: None,
+        })
+    }
+
+    /// Takes the brokers of a metadata response as the ones there are,
+    /// closing connections to any that are gone.
+    fn update(&mut self, brokers: &[MetadataBroker]) {
+        self.addresses = brokers
+            .iter()
+            .map(|b| (b.node_id, (b.host.clone(), b.port as u16)))
+            .collect();
+        let addresses = &self.addresses;
+        self.connections
+            .retain(|node_id, _| addresses.contains_key(node_id));
+    }
+
+    /// Records where a broker listens, such as a group coordinator, without
+    /// forgetting the others.
+    fn insert(&mut self, node_id: i32, host: String, port: u16) {
+        self.addresses.insert(node_id, (host, port));
+    }
+
+    fn disconnect(&mut self, node_id: i32) {
+        self.connections.remove(&node_id);
+    }
+
+    async fn send_to<R: Deserialize<R>>(
+        &mut self,
+        node_id: i32,
+        api_key: ApiKey,
+        api_version: i16,
+        request: &impl Serialize,
+    ) -> Result<R> {
+        let timeout = self.request_timeout;
+        let res = async {
+            let connection = self.connection(node_id).await?;
+            tokio::time::timeout(timeout, connection.send(api_key, api_version, request))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("timed out")))
+        }
+        .await;
+        if res.is_err() {
+            self.connections.remove(&node_id);
+        }
+        res.with_context(|| format!("{:?} to broker {}", api_key, node_id))
+    }
+
+    /// Like `send_to`, for requests the broker doesn't answer.
+    async fn send_to_without_response(
+        &mut self,
+        node_id: i32,
+        api_key: ApiKey,
+        api_version: i16,
+        request: &impl Serialize,
+    ) -> Result<()> {
+        let timeout = self.request_timeout;
+        let res = async {
+            let connection = self.connection(node_id).await?;
+            tokio::time::timeout(
+                timeout,
+                connection.send_without_response(api_key, api_version, request),
+            )
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("timed out")))
+        }
+        .await;
+        if res.is_err() {
+            self.connections.remove(&node_id);
+        }
+        res.with_context(|| format!("{:?} to broker {}", api_key, node_id))
+    }
+
+    async fn connection(&mut self, node_id: i32) -> Result<&mut Connection> {
+        if !self.connections.contains_key(&node_id) {
+            let (host, port) = self
+                .addresses
+                .get(&node_id)
+                .cloned()
+                .ok_or_else(|| anyhow!("no address for broker {}", node_id))?;
+            let connection = tokio::time::timeout(
+                self.request_timeout,
+                Connection::connect(&host, port, &self.client_id),
+            )
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("timed out connecting to {}:{}", host, port)))?;
+            self.connections.insert(node_id, connection);
+        }
+        Ok(self.connections.get_mut(&node_id).unwrap())
+    }
+
+    /// Sends to whichever broker answers, trying the known brokers and then
+    /// the bootstrap servers.
+    async fn send_to_any<R: Deserialize<R>>(
+        &mut self,
+        api_key: ApiKey,
+        api_version: i16,
+        request: &impl Serialize,
+    ) -> Result<R> {
+        let timeout = self.request_timeout;
+        if let Some(connection) = &mut self.any_broker {
+            let res = tokio::time::timeout(timeout, connection.send(api_key, api_version, request))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("timed out")));
+            match res {
+                Ok(res) => return Ok(res),
+                Err(e) => debug!(error = %e, "lost connection"),
+            }
+            self.any_broker = None;
+        }
+        let mut addresses: Vec<_> = self.addresses.values().cloned().collect();
+        addresses.extend(self.bootstrap.iter().cloned());
+        let mut last_error = anyhow!("no brokers");
+        for (host, port) in addresses {
+            let res = tokio::time::timeout(timeout, async {
+                let mut connection = Connection::connect(&host, port, &self.client_id).await?;
+                let res = connection.send(api_key, api_version, request).await?;
+                Ok((connection, res))
+            })
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("timed out")));
+            match res {
+                Ok((connection, res)) => {
+                    self.any_broker = Some(connection);
+                    return Ok(res);
+                }
+                Err(e) => last_error = e.context(format!("{:?} to {}:{}", api_key, host, port)),
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// Requests to the active controller, which is whichever of the quorum's
+/// voters answers without NOT_CONTROLLER.
+pub struct ControllerChannel {
+    client_id: String,
+    controllers: Vec<Voter>,
+    /// The voter tried first, the last one that answered as controller.
+    next: usize,
+    connection: Option<Connection>,
+    request_timeout: Duration,
+}
+
+impl ControllerChannel {
+    pub fn new(client_id: String, controllers: Vec<Voter>, request_timeout: Duration) -> Self {
+        Self {
+            client_id,
+            controllers,
+            next: 0,
+            connection: None,
+            request_timeout,
+        }
+    }
+
+    /// Sends `request` to each voter in turn until one answers as the
+    /// controller.
+    pub async fn send<R: Deserialize<R> + Response>(
+        &mut self,
+        api_key: ApiKey,
+        api_version: i16,
+        request: &impl Serialize,
+    ) -> Result<R> {
+        let mut last_error = anyhow!("no controllers");
+        for _ in 0..self.controllers.len() {
+            let voter = self.controllers[self.next].clone();
+            let sent = tokio::time::timeout(self.request_timeout, async {
+                if self.connection.is_none() {
+                    let connection =
+                        Connection::connect(&voter.host, voter.port, &self.client_id).await?;
+                    self.connection = Some(connection);
+                }
+                let connection = self.connection.as_mut().unwrap();
+                connection.send::<R>(api_key, api_version, request).await
+            })
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("timed out")));
+            match sent {
+                Ok(response) if response.error_code() != ErrorCode::NotController => {
+                    return Ok(response);
+                }
+                Ok(_) => last_error = anyhow!("voter {} is not the controller", voter.id),
+                Err(e) => last_error = e.context(format!("voter {}", voter.id)),
+            }
+            self.connection = None;
+            self.next = (self.next + 1) % self.controllers.len();
+        }
+        Err(last_error)
+    }
+}
@@ -0,0 +1,421 @@
+//! A producer for applications embedding this crate: it follows partition
+//! leaders through Metadata and writes with Produce.
+//!
+//! Records are held until [`Producer::flush`], or until enough of them are
+//! waiting to fill a batch, and then go out as one batch per partition in
+//! one request per leader, uncompressed. Like the consumer, nothing runs in
+//! the background.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::api::metadata::{MetadataRequest, MetadataRequestTopic, MetadataResponse};
+use crate::api::produce::{
+    PartitionProduceData, ProduceRequest, ProduceResponse, TopicProduceData, ACKS_ALL, ACKS_NONE,
+};
+use crate::client::Brokers;
+use crate::coordinator::TopicPartition;
+use crate::protocol::{ApiKey, ErrorCode};
+use crate::record_batch::{encode_batch, BatchRecord};
+
+/// How long to wait for new metadata before retrying a failed send.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct ProducerConfig {
+    /// `host:port` of brokers to learn the cluster from, tried in order.
+    pub bootstrap_servers: Vec<String>,
+    pub client_id: String,
+    /// How many replicas must have the records before the leader answers:
+    /// 1 for the leader alone, -1 for every in-sync replica, or 0 for no
+    /// answer at all.
+    pub acks: i16,
+    /// How many bytes of keys and values may wait before they are sent
+    /// without a flush.
+    pub batch_size: usize,
+    /// How many more times a partition is tried after its leader fails or
+    /// moves.
+    pub retries: u32,
+    pub request_timeout: Duration,
+}
+
+impl Default for ProducerConfig {
+    fn default() -> Self {
+        Self {
+            bootstrap_servers: vec!["localhost:9092".to_string()],
+            client_id: "producer".to_string(),
+            acks: ACKS_ALL,
+            batch_size: 16 * 1024,
+            retries: 3,
+            request_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A record for [`Producer::send`].
+#[derive(Debug, Clone)]
+pub struct ProducerRecord {
+    pub topic: String,
+    /// Where the record goes; if `None`, picked from the key's hash, or in
+    /// turn for records without a key.
+    pub partition: Option<i32>,
+    pub key: Option<Bytes>,
+    pub value: Option<Bytes>,
+}
+
+/// Where a record was written, as [`Producer::flush`] reports it.
+#[derive(Debug, Clone)]
+pub struct RecordMetadata {
+    pub topic: String,
+    pub partition: i32,
+    /// `None` with acks 0, as the broker doesn't say.
+    pub offset: Option<i64>,
+}
+
+/// One partition's waiting records, encoded as a batch.
+struct PendingBatch {
+    partition: TopicPartition,
+    records: Bytes,
+    count: usize,
+    /// Why the last attempt to send the batch failed.
+    error: Option<String>,
+}
+
+pub struct Producer {
+    config: ProducerConfig,
+    brokers: Brokers,
+    /// Each known topic's partition leaders, -1 without a leader.
+    topics: HashMap<String, BTreeMap<i32, i32>>,
+    /// Records waiting to be sent, by partition.
+    pending: BTreeMap<TopicPartition, Vec<BatchRecord>>,
+    pending_bytes: usize,
+    /// The partition the next record without a key or partition goes to,
+    /// by topic.
+    next_partition: HashMap<String, usize>,
+    /// Records sent since the last flush.
+    sent: Vec<RecordMetadata>,
+}
+
+impl Producer {
+    /// Reads the cluster's brokers from the first bootstrap server that
+    /// answers.
+    pub async fn connect(config: ProducerConfig) -> Result<Self> {
+        let brokers = Brokers::new(
+            &config.bootstrap_servers,
+            &config.client_id,
+            config.request_timeout,
+        )?;
+        let mut producer = Self {
+            config,
+            brokers,
+            topics: HashMap::new(),
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+            next_partition: HashMap::new(),
+            sent: Vec::new(),
+        };
+        producer.refresh_metadata().await?;
+        Ok(producer)
+    }
+
+    /// Adds `record` to its partition's next batch, sending everything
+    /// waiting once a batch's worth is.
+    pub async fn send(&mut self, record: ProducerRecord) -> Result<()> {
+        if !self.topics.contains_key(&record.topic) {
+            self.topics.insert(record.topic.clone(), BTreeMap::new());
+            self.refresh_metadata().await?;
+        }
+        let partitions = &self.topics[&record.topic];
+        if partitions.is_empty() {
+            self.topics.remove(&record.topic);
+            bail!("unknown topic {:?}", record.topic);
+        }
+        let partition = match (record.partition, &record.key) {
+            (Some(partition), _) => {
+                if !partitions.contains_key(&partition) {
+                    bail!("topic {:?} has no partition {}", record.topic, partition);
+                }
+                partition
+            }
+            (None, Some(key)) => {
+                let index = (murmur2(key) & 0x7fffffff) as usize % partitions.len();
+                *partitions.keys().nth(index).unwrap()
+            }
+            (None, None) => {
+                let next = self.next_partition.entry(record.topic.clone()).or_default();
+                let index = *next % partitions.len();
+                *next = next.wrapping_add(1);
+                *partitions.keys().nth(index).unwrap()
+            }
+        };
+        self.pending_bytes += record.key.as_ref().map_or(0, |k| k.len())
+            + record.value.as_ref().map_or(0, |v| v.len());
+        self.pending
+            .entry(TopicPartition {
+                topic: record.topic,
+                partition,
+            })
+            .or_default()
+            .push(BatchRecord {
+                key: record.key,
+                value: record.value,
+            });
+        if self.pending_bytes >= self.config.batch_size {
+            self.send_pending().await?;
+        }
+        Ok(())
+    }
+
+    /// Sends every waiting record, returning where each record sent since
+    /// the last flush was written.
+    pub async fn flush(&mut self) -> Result<Vec<RecordMetadata>> {
+        self.send_pending().await?;
+        Ok(std::mem::take(&mut self.sent))
+    }
+
+    /// Reads where the brokers are and who leads the known topics'
+    /// partitions.
+    async fn refresh_metadata(&mut self) -> Result<()> {
+        let req = MetadataRequest {
+            topics: Some(
+                self.topics
+                    .keys()
+                    .map(|name| MetadataRequestTopic::named(name))
+                    .collect(),
+            ),
+            allow_auto_topic_creation: false,
+            include_cluster_authorized_operations: false,
+            include_topic_authorized_operations: false,
+        };
+        let res: MetadataResponse = self.brokers.send_to_any(ApiKey::Metadata, 12, &req).await?;
+        self.brokers.update(&res.brokers.0);
+        for topic in &res.topics.0 {
+            let Some(name) = &topic.name.0 else {
+                continue;
+            };
+            let Some(leaders) = self.topics.get_mut(name) else {
+                continue;
+            };
+            leaders.clear();
+            if topic.error_code != ErrorCode::None {
+                debug!(topic = %name, error = ?topic.error_code, "topic has no metadata");
+                continue;
+            }
+            for p in &topic.partitions.0 {
+                let leader = if p.error_code == ErrorCode::None {
+                    p.leader_id as i32
+                } else {
+                    -1
+                };
+                leaders.insert(p.partition_index as i32, leader);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the waiting records to their leaders, retrying partitions whose
+    /// leader can't take them once metadata has caught up.
+    async fn send_pending(&mut self) -> Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        let mut batches: Vec<PendingBatch> = std::mem::take(&mut self.pending)
+            .into_iter()
+            .map(|(partition, records)| PendingBatch {
+                partition,
+                // Uncompressed, as `connect` allows no other codec.
+                records: encode_batch(0, -1, 0, &records, timestamp),
+                count: records.len(),
+                error: None,
+            })
+            .collect();
+        self.pending_bytes = 0;
+
+        for attempt in 0..=self.config.retries {
+            if attempt > 0 {
+                tokio::time::sleep(RETRY_BACKOFF).await;
+                self.refresh_metadata().await?;
+            }
+            let mut by_leader: BTreeMap<i32, Vec<PendingBatch>> = BTreeMap::new();
+            let mut retry = Vec::new();
+            for batch in batches {
+                let leader = self
+                    .topics
+                    .get(&batch.partition.topic)
+                    .and_then(|p| p.get(&batch.partition.partition))
+                    .copied()
+                    .unwrap_or(-1);
+                if leader < 0 {
+                    retry.push(PendingBatch {
+                        error: Some("no leader".to_string()),
+                        ..batch
+                    });
+                } else {
+                    by_leader.entry(leader).or_default().push(batch);
+                }
+            }
+            for (leader, batches) in by_leader {
+                retry.extend(self.send_to_leader(leader, batches).await?);
+            }
+            if retry.is_empty() {
+                return Ok(());
+            }
+            batches = retry;
+        }
+        let batch = &batches[0];
+        bail!(
+            "failed to produce to {}-{} after {} retries: {}",
+            batch.partition.topic,
+            batch.partition.partition,
+            self.config.retries,
+            batch.error.as_deref().unwrap_or_default()
+        )
+    }
+
+    /// Sends `batches` to `leader`, returning the ones to try again.
+    async fn send_to_leader(
+        &mut self,
+        leader: i32,
+        batches: Vec<PendingBatch>,
+    ) -> Result<Vec<PendingBatch>> {
+        let mut topic_data: Vec<TopicProduceData> = Vec::new();
+        for batch in &batches {
+            let data = PartitionProduceData {
+                index: batch.partition.partition,
+                records: batch.records.clone(),
+            };
+            match topic_data
+                .iter_mut()
+                .find(|t| t.name == batch.partition.topic)
+            {
+                Some(topic) => topic.partition_data.push(data),
+                None => topic_data.push(TopicProduceData {
+                    name: batch.partition.topic.clone(),
+                    partition_data: vec![data],
+                }),
+            }
+        }
+        let req = ProduceRequest {
+            transactional_id: None,
+            acks: self.config.acks,
+            timeout_ms: self.config.request_timeout.as_millis() as i32,
+            topic_data,
+        };
+
+        if self.config.acks == ACKS_NONE {
+            if let Err(e) = self
+                .brokers
+                .send_to_without_response(leader, ApiKey::Produce, 9, &req)
+                .await
+            {
+                debug!(leader, error = %e, "failed to produce");
+                return Ok(failed(batches, &e));
+            }
+            for batch in &batches {
+                self.record_sent(batch, None);
+            }
+            return Ok(Vec::new());
+        }
+
+        let res: ProduceResponse =
+            match self.brokers.send_to(leader, ApiKey::Produce, 9, &req).await {
+                Ok(res) => res,
+                Err(e) => {
+                    debug!(leader, error = %e, "failed to produce");
+                    return Ok(failed(batches, &e));
+                }
+            };
+        let mut retry = Vec::new();
+        for batch in batches {
+            let answer = res
+                .responses
+                .iter()
+                .filter(|t| t.name == batch.partition.topic)
+                .flat_map(|t| &t.partition_responses)
+                .find(|p| p.index == batch.partition.partition)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no answer for {}-{}",
+                        batch.partition.topic,
+                        batch.partition.partition
+                    )
+                })?;
+            match answer.error_code {
+                ErrorCode::None => self.record_sent(&batch, Some(answer.base_offset)),
+                ErrorCode::NotLeaderOrFollower
+                | ErrorCode::LeaderNotAvailable
+                | ErrorCode::UnknownTopicOrPartition
+                | ErrorCode::RequestTimedOut => retry.push(PendingBatch {
+                    error: Some(format!("{:?}", answer.error_code)),
+                    ..batch
+                }),
+                error_code => bail!(
+                    "failed to produce to {}-{}: {:?}{}",
+                    batch.partition.topic,
+                    batch.partition.partition,
+                    error_code,
+                    answer
+                        .error_message
+                        .as_deref()
+                        .map(|m| format!(": {}", m))
+                        .unwrap_or_default()
+                ),
+            }
+        }
+        Ok(retry)
+    }
+
+    fn record_sent(&mut self, batch: &PendingBatch, base_offset: Option<i64>) {
+        for i in 0..batch.count {
+            self.sent.push(RecordMetadata {
+                topic: batch.partition.topic.clone(),
+                partition: batch.partition.partition,
+                offset: base_offset.map(|offset| offset + i as i64),
+            });
+        }
+    }
+}
+
+fn failed(batches: Vec<PendingBatch>, error: &anyhow::Error) -> Vec<PendingBatch> {
+    batches
+        .into_iter()
+        .map(|batch| PendingBatch {
+            error: Some(format!("{:#}", error)),
+            ..batch
+        })
+        .collect()
+}
+
+/// The Java client's murmur2 hash, so that keyed records land on the same
+/// partitions as they would from it.
+fn murmur2(data: &[u8]) -> i32 {
+    const SEED: u32 = 0x9747b28c;
+    const M: u32 = 0x5bd1e995;
+    const R: u32 = 24;
+
+    let mut h = SEED ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate().rev() {
+            h ^= (*byte as u32) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h as i32
+}
@@ -0,0 +1,1156 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    audit::AuditLogSettings,
+    authorizer::AuthorizerSettings,
+    broker_lifecycle::BrokerLifecycleSettings,
+    coordinator::{GroupSettings, SERVER_ASSIGNORS},
+    delegation_token::DelegationTokenSettings,
+    isr_manager::IsrSettings,
+    listener::{Endpoint, Keepalive, ListenerType, SecurityProtocol, SocketOptions},
+    oauth::OAuthBearerSettings,
+    protocol::cluster_metadata_log_file,
+    quota::{QuotaSettings, QuotaWindow},
+    raft::{QuorumSettings, Voter},
+    replica_fetcher::ReplicaFetcherSettings,
+    sasl::parse_jaas_users,
+    tls::{SslClientAuth, SslSettings},
+};
+
+pub const DEFAULT_LISTENERS: &str = "PLAINTEXT://127.0.0.1:9092";
+pub const DEFAULT_LOG_DIR: &str = "/tmp/kraft-combined-logs";
+
+/// Properties that take effect when the config is reloaded. Everything else
+/// needs a restart.
+pub const RELOADABLE_PROPERTIES: &[&str] = &[
+    "log.level",
+    "log.wire.debug",
+    "max.connection.creation.rate",
+    "max.connection.creation.rate.per.ip",
+    "max.connections",
+    "max.connections.per.ip",
+    "max.connections.per.ip.overrides",
+    "quota.consumer.default",
+    "quota.consumer.override",
+    "quota.producer.default",
+    "quota.producer.override",
+    "request.slow.threshold.ms",
+];
+
+/// Broker settings, read from a Kafka-style `server.properties` file.
+///
+/// Keys that are not recognised are kept in `properties` so later lookups can
+/// still see them, but do not affect the broker.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub properties: HashMap<String, String>,
+    pub node_id: i32,
+    /// The rack this broker is in, from `broker.rack`, for consumers to
+    /// fetch from a replica in their own.
+    pub rack: Option<String>,
+    pub listeners: Vec<Endpoint>,
+    /// What clients are told to connect to, per listener. Defaults to
+    /// `listeners`, minus the controller listeners.
+    pub advertised_listeners: Vec<Endpoint>,
+    /// Listeners that serve only controller APIs, from
+    /// `controller.listener.names`. They are never advertised to clients and
+    /// are exempt from the broker-wide connection limits.
+    pub controller_listener_names: Vec<String>,
+    /// The metadata quorum this node votes in. `None` unless
+    /// `controller.quorum.voters` lists `node.id` and `process.roles`, if
+    /// set, includes `controller`.
+    pub quorum: Option<QuorumSettings>,
+    /// How partitions led by other brokers are fetched.
+    pub replica_fetcher: ReplicaFetcherSettings,
+    /// When followers of the partitions this broker leads leave the ISR.
+    pub isr: IsrSettings,
+    /// How often this broker heartbeats to the controller, and how long the
+    /// controller waits for a heartbeat.
+    pub lifecycle: BrokerLifecycleSettings,
+    /// From `log.dirs`, or `log.dir` when that is unset.
+    pub log_dirs: Vec<PathBuf>,
+    /// Where the cluster metadata log is read from, from `metadata.log.dir`.
+    /// Brokers don't replicate the metadata log, so by default every node on
+    /// a host reads the one the controller writes under `DEFAULT_LOG_DIR`.
+    pub metadata_log_dir: PathBuf,
+    pub connections_max_idle: Duration,
+    /// Requests bigger than this close the connection, from
+    /// `socket.request.max.bytes`.
+    pub socket_request_max_bytes: usize,
+    pub socket_options: SocketOptions,
+    /// Requests read from a connection and processed concurrently before
+    /// their responses have been written.
+    pub max_in_flight: usize,
+    /// How long a handler may run before the client gets REQUEST_TIMED_OUT.
+    pub request_timeout: Duration,
+    /// Requests taking at least this long are logged as slow; `None` disables it.
+    pub slow_request_threshold: Option<Duration>,
+    /// Caps on the bytes of requests read but not yet processed, broker-wide
+    /// and per connection. `None` is unbounded.
+    pub queued_max_request_bytes: Option<usize>,
+    pub queued_max_request_bytes_per_connection: Option<usize>,
+    /// Caps the bytes of requests being handled plus responses not yet
+    /// written, broker-wide. Connections stop taking new requests while it is
+    /// exceeded. `None` is unbounded.
+    pub in_flight_max_bytes: Option<usize>,
+    /// How many incremental fetch sessions are kept, from
+    /// `max.incremental.fetch.session.cache.slots`; 0 disables them.
+    pub fetch_session_cache_slots: usize,
+    /// Whether a Metadata request may create the topics it names that don't
+    /// exist, from `auto.create.topics.enable`.
+    pub auto_create_topics: bool,
+    /// The partitions and replicas of a topic created without saying, from
+    /// `num.partitions` and `default.replication.factor`.
+    pub num_partitions: i32,
+    pub default_replication_factor: i16,
+    /// Where the plain-text HTTP admin endpoint listens; `None` disables it.
+    pub admin_listener: Option<SocketAddr>,
+    /// The request audit journal; `None` disables it.
+    pub audit_log: Option<AuditLogSettings>,
+    /// ACL authorization, enabled by setting `authorizer.class.name`; `None`
+    /// allows every request.
+    pub authorizer: Option<AuthorizerSettings>,
+    /// How often request metrics are summarised in the log; `None` disables it.
+    pub metrics_summary_interval: Option<Duration>,
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    pub max_connections_per_ip_overrides: HashMap<IpAddr, usize>,
+    /// Per-listener caps from `listener.name.<listener>.max.connections`.
+    pub listener_max_connections: HashMap<String, usize>,
+    /// New connections per second, broker-wide and per client IP. `None` is
+    /// unlimited.
+    pub max_connection_creation_rate: Option<u32>,
+    pub max_connection_creation_rate_per_ip: Option<u32>,
+    pub producer_quotas: QuotaSettings,
+    pub consumer_quotas: QuotaSettings,
+    pub quota_window: QuotaWindow,
+    /// Passwords from the JAAS `user_<name>` options, used by PLAIN and to
+    /// derive SCRAM credentials.
+    pub sasl_users: HashMap<String, String>,
+    /// Delegation tokens, enabled by setting `delegation.token.secret.key`;
+    /// `None` disables them.
+    pub delegation_tokens: Option<DelegationTokenSettings>,
+    /// Where OAUTHBEARER tokens are validated against, from
+    /// `sasl.oauthbearer.jwks.endpoint.url`; `None` rejects every token.
+    pub oauthbearer: Option<OAuthBearerSettings>,
+    pub group_settings: GroupSettings,
+    /// A `tracing` filter directive such as `info` or `kafka_starter_rust=debug`.
+    /// When unset, `RUST_LOG` applies.
+    pub log_level: Option<String>,
+    /// Log each span as it closes with how long it was busy and idle, from
+    /// `log.span.timing`. Request, handler and log read spans carry the
+    /// correlation id of their request, so this times each step of one.
+    pub log_span_timing: bool,
+    /// Log every request and response decoded field by field, from
+    /// `log.wire.debug`.
+    pub log_wire_debug: bool,
+}
+
+/// The config in effect, replaced as a whole when the file is reloaded.
+/// Readers hold on to the [`Config`] they got for as long as they need a
+/// consistent view.
+pub struct SharedConfig(RwLock<Arc<Config>>);
+
+impl SharedConfig {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self(RwLock::new(config))
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, config: Arc<Config>) {
+        *self.0.write().unwrap() = config;
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let listeners = Endpoint::parse_list(DEFAULT_LISTENERS, &HashMap::new())
+            .expect("valid default listeners");
+        Self {
+            properties: HashMap::new(),
+            node_id: 1,
+            rack: None,
+            advertised_listeners: listeners.clone(),
+            listeners,
+            controller_listener_names: Vec::new(),
+            quorum: None,
+            replica_fetcher: ReplicaFetcherSettings::default(),
+            isr: IsrSettings::default(),
+            lifecycle: BrokerLifecycleSettings::default(),
+            log_dirs: vec![PathBuf::from(DEFAULT_LOG_DIR)],
+            metadata_log_dir: PathBuf::from(DEFAULT_LOG_DIR),
+            connections_max_idle: Duration::from_millis(600_000),
+            socket_request_max_bytes: 100 * 1024 * 1024,
+            socket_options: SocketOptions::default(),
+            max_in_flight: 5,
+            request_timeout: Duration::from_millis(30_000),
+            slow_request_threshold: None,
+            queued_max_request_bytes: None,
+            queued_max_request_bytes_per_connection: None,
+            in_flight_max_bytes: None,
+            fetch_session_cache_slots: 1000,
+            auto_create_topics: true,
+            num_partitions: 1,
+            default_replication_factor: 1,
+            admin_listener: None,
+            audit_log: None,
+            authorizer: None,
+            metrics_summary_interval: Some(Duration::from_secs(60)),
+            max_connections: i32::MAX as usize,
+            max_connections_per_ip: i32::MAX as usize,
+            max_connections_per_ip_overrides: HashMap::new(),
+            listener_max_connections: HashMap::new(),
+            max_connection_creation_rate: None,
+            max_connection_creation_rate_per_ip: None,
+            producer_quotas: QuotaSettings::default(),
+            consumer_quotas: QuotaSettings::default(),
+            quota_window: QuotaWindow::default(),
+            sasl_users: HashMap::new(),
+            delegation_tokens: None,
+            oauthbearer: None,
+            group_settings: GroupSettings::default(),
+            log_level: None,
+            log_span_timing: false,
+            log_wire_debug: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_properties(read_properties(path)?)
+    }
+
+    pub fn from_properties(properties: HashMap<String, String>) -> Result<Self> {
+        let defaults = Self::default();
+        let node_id = parse_or(&properties, "node.id", defaults.node_id)?;
+        let rack = properties.get("broker.rack").cloned();
+        let protocol_map = match properties.get("listener.security.protocol.map") {
+            Some(value) => parse_protocol_map(value)?,
+            None => HashMap::new(),
+        };
+        let listeners = match properties.get("listeners") {
+            Some(value) => Endpoint::parse_list(value, &protocol_map)?,
+            None => defaults.listeners,
+        };
+        let controller_listener_names = properties
+            .get("controller.listener.names")
+            .map(|value| parse_list(value))
+            .unwrap_or_default();
+        for name in &controller_listener_names {
+            if !listeners.iter().any(|l| l.listener_name == *name) {
+                return Err(anyhow!(
+                    "controller listener '{}' has no matching entry in listeners",
+                    name
+                ));
+            }
+        }
+        if listeners
+            .iter()
+            .all(|l| controller_listener_names.contains(&l.listener_name))
+        {
+            return Err(anyhow!(
+                "listeners must include at least one listener that is not a controller listener"
+            ));
+        }
+        let advertised_listeners = match properties.get("advertised.listeners") {
+            Some(value) => Endpoint::parse_list(value, &protocol_map)?,
+            None => listeners
+                .iter()
+                .filter(|l| !controller_listener_names.contains(&l.listener_name))
+                .cloned()
+                .collect(),
+        };
+        for advertised in &advertised_listeners {
+            if controller_listener_names.contains(&advertised.listener_name) {
+                return Err(anyhow!(
+                    "advertised.listeners must not include controller listener '{}'",
+                    advertised.listener_name
+                ));
+            }
+            if !listeners
+                .iter()
+                .any(|l| l.listener_name == advertised.listener_name)
+            {
+                return Err(anyhow!(
+                    "advertised listener '{}' has no matching entry in listeners",
+                    advertised.listener_name
+                ));
+            }
+        }
+        let quorum = parse_quorum_settings(&properties, node_id)?;
+        if quorum.is_some() && controller_listener_names.is_empty() {
+            return Err(anyhow!(
+                "controller.listener.names must be set on a metadata quorum voter"
+            ));
+        }
+        let replica_fetcher = parse_replica_fetcher_settings(&properties)?;
+        if let Some(name) = properties.get("inter.broker.listener.name") {
+            if !listeners.iter().any(|l| l.listener_name == *name)
+                || controller_listener_names.contains(name)
+            {
+                return Err(anyhow!(
+                    "inter.broker.listener.name '{}' must name a broker listener",
+                    name
+                ));
+            }
+        }
+        let isr = parse_isr_settings(&properties)?;
+        let lifecycle = parse_lifecycle_settings(&properties)?;
+        let log_dirs = match properties
+            .get("log.dirs")
+            .or_else(|| properties.get("log.dir"))
+        {
+            Some(value) => parse_list(value).into_iter().map(PathBuf::from).collect(),
+            None => defaults.log_dirs,
+        };
+        if log_dirs.is_empty() {
+            return Err(anyhow!("log.dirs must not be empty"));
+        }
+        let metadata_log_dir = properties
+            .get("metadata.log.dir")
+            .map_or(defaults.metadata_log_dir, PathBuf::from);
+        let connections_max_idle = Duration::from_millis(parse_or(
+            &properties,
+            "connections.max.idle.ms",
+            defaults.connections_max_idle.as_millis() as u64,
+        )?);
+        let socket_request_max_bytes: usize = parse_or(
+            &properties,
+            "socket.request.max.bytes",
+            defaults.socket_request_max_bytes,
+        )?;
+        if !(1..=i32::MAX as usize).contains(&socket_request_max_bytes) {
+            return Err(anyhow!(
+                "socket.request.max.bytes must be between 1 and {}",
+                i32::MAX
+            ));
+        }
+        let socket_options = SocketOptions {
+            tcp_nodelay: parse_or(
+                &properties,
+                "socket.tcp.nodelay",
+                defaults.socket_options.tcp_nodelay,
+            )?,
+            send_buffer_bytes: parse_buffer_size(&properties, "socket.send.buffer.bytes")?
+                .unwrap_or(defaults.socket_options.send_buffer_bytes),
+            receive_buffer_bytes: parse_buffer_size(&properties, "socket.receive.buffer.bytes")?
+                .unwrap_or(defaults.socket_options.receive_buffer_bytes),
+            listen_backlog: parse_or(
+                &properties,
+                "socket.listen.backlog.size",
+                defaults.socket_options.listen_backlog,
+            )?,
+            keepalive: parse_keepalive(&properties)?,
+        };
+        let max_in_flight = parse_or(&properties, "max.in.flight", defaults.max_in_flight)?;
+        if max_in_flight == 0 {
+            return Err(anyhow!("max.in.flight must be at least 1"));
+        }
+        let fetch_session_cache_slots = parse_or(
+            &properties,
+            "max.incremental.fetch.session.cache.slots",
+            defaults.fetch_session_cache_slots,
+        )?;
+        let auto_create_topics = parse_or(
+            &properties,
+            "auto.create.topics.enable",
+            defaults.auto_create_topics,
+        )?;
+        let num_partitions = parse_or(&properties, "num.partitions", defaults.num_partitions)?;
+        if num_partitions < 1 {
+            return Err(anyhow!("num.partitions must be at least 1"));
+        }
+        let default_replication_factor = parse_or(
+            &properties,
+            "default.replication.factor",
+            defaults.default_replication_factor,
+        )?;
+        if default_replication_factor < 1 {
+            return Err(anyhow!("default.replication.factor must be at least 1"));
+        }
+        let request_timeout = Duration::from_millis(parse_or(
+            &properties,
+            "request.timeout.ms",
+            defaults.request_timeout.as_millis() as u64,
+        )?);
+        let slow_request_threshold =
+            match parse_or::<u64>(&properties, "request.slow.threshold.ms", 0)? {
+                0 => None,
+                ms => Some(Duration::from_millis(ms)),
+            };
+        let queued_max_request_bytes = parse_byte_limit(&properties, "queued.max.request.bytes")?;
+        let queued_max_request_bytes_per_connection =
+            parse_byte_limit(&properties, "queued.max.request.bytes.per.connection")?;
+        let in_flight_max_bytes = parse_byte_limit(&properties, "in.flight.max.bytes")?;
+        let admin_listener =
+            match properties.get("admin.listener") {
+                Some(value) => Some(value.parse().map_err(|e| {
+                    anyhow!("invalid value '{}' for 'admin.listener': {}", value, e)
+                })?),
+                None => None,
+            };
+        let audit_log = match properties.get("audit.log.file") {
+            Some(path) => Some(AuditLogSettings {
+                path: PathBuf::from(path),
+                max_bytes: parse_or(&properties, "audit.log.max.bytes", 100 * 1024 * 1024)?,
+                max_files: parse_or(&properties, "audit.log.max.files", 10)?,
+            }),
+            None => None,
+        };
+        let authorizer = match properties.get("authorizer.class.name") {
+            Some(class_name) if !class_name.trim().is_empty() => Some(AuthorizerSettings {
+                acl_file: properties.get("authorizer.acl.file").map(PathBuf::from),
+                super_users: properties
+                    .get("super.users")
+                    .map(|value| {
+                        value
+                            .split(';')
+                            .map(str::trim)
+                            .filter(|user| !user.is_empty())
+                            .map(str::parse)
+                            .collect::<Result<_>>()
+                    })
+                    .transpose()
+                    .context("invalid value for 'super.users'")?
+                    .unwrap_or_default(),
+                allow_everyone_if_no_acl_found: parse_or(
+                    &properties,
+                    "allow.everyone.if.no.acl.found",
+                    false,
+                )?,
+            }),
+            _ => None,
+        };
+        let metrics_summary_interval = match parse_or(
+            &properties,
+            "metrics.summary.interval.ms",
+            defaults
+                .metrics_summary_interval
+                .map_or(0, |d| d.as_millis() as u64),
+        )? {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        };
+        let max_connections = parse_or(&properties, "max.connections", defaults.max_connections)?;
+        let max_connections_per_ip = parse_or(
+            &properties,
+            "max.connections.per.ip",
+            defaults.max_connections_per_ip,
+        )?;
+        let max_connections_per_ip_overrides =
+            match properties.get("max.connections.per.ip.overrides") {
+                Some(value) => parse_ip_overrides(value)?,
+                None => defaults.max_connections_per_ip_overrides,
+            };
+        let mut listener_max_connections = HashMap::new();
+        for listener in &listeners {
+            let key = format!(
+                "listener.name.{}.max.connections",
+                listener.listener_name.to_lowercase()
+            );
+            if properties.contains_key(&key) {
+                listener_max_connections.insert(
+                    listener.listener_name.clone(),
+                    parse_or(&properties, &key, 0)?,
+                );
+            }
+        }
+        let max_connection_creation_rate = parse_rate(&properties, "max.connection.creation.rate")?;
+        let max_connection_creation_rate_per_ip =
+            parse_rate(&properties, "max.connection.creation.rate.per.ip")?;
+        let producer_quotas = parse_quota_settings(&properties, "quota.producer")?;
+        let consumer_quotas = parse_quota_settings(&properties, "quota.consumer")?;
+        let quota_window = QuotaWindow {
+            samples: parse_or(
+                &properties,
+                "quota.window.num",
+                defaults.quota_window.samples,
+            )?,
+            window: Duration::from_secs(parse_or(
+                &properties,
+                "quota.window.size.seconds",
+                defaults.quota_window.window.as_secs(),
+            )?),
+        };
+        if quota_window.samples == 0 || quota_window.window.is_zero() {
+            return Err(anyhow!(
+                "quota.window.num and quota.window.size.seconds must be at least 1"
+            ));
+        }
+        let sasl_users = properties
+            .get("plain.sasl.jaas.config")
+            .or_else(|| properties.get("sasl.jaas.config"))
+            .map(|value| parse_jaas_users(value))
+            .unwrap_or(defaults.sasl_users);
+        // `delegation.token.master.key` is the name the secret had before
+        // Kafka 2.8.
+        let delegation_tokens = match properties
+            .get("delegation.token.secret.key")
+            .or_else(|| properties.get("delegation.token.master.key"))
+        {
+            Some(secret) if !secret.is_empty() => Some(DelegationTokenSettings {
+                secret: secret.clone(),
+                max_lifetime: Duration::from_millis(parse_or(
+                    &properties,
+                    "delegation.token.max.lifetime.ms",
+                    7 * 24 * 60 * 60 * 1000,
+                )?),
+                expiry_time: Duration::from_millis(parse_or(
+                    &properties,
+                    "delegation.token.expiry.time.ms",
+                    24 * 60 * 60 * 1000,
+                )?),
+            }),
+            _ => None,
+        };
+        let oauthbearer = match properties.get("sasl.oauthbearer.jwks.endpoint.url") {
+            Some(url) if !url.is_empty() => Some(OAuthBearerSettings {
+                jwks_endpoint: url.parse()?,
+                jwks_refresh: Duration::from_millis(parse_or(
+                    &properties,
+                    "sasl.oauthbearer.jwks.endpoint.refresh.ms",
+                    60 * 60 * 1000,
+                )?),
+                sub_claim_name: properties
+                    .get("sasl.oauthbearer.sub.claim.name")
+                    .cloned()
+                    .unwrap_or_else(|| "sub".to_string()),
+                expected_audience: properties
+                    .get("sasl.oauthbearer.expected.audience")
+                    .map(|value| parse_list(value))
+                    .unwrap_or_default(),
+                expected_issuer: properties
+                    .get("sasl.oauthbearer.expected.issuer")
+                    .filter(|issuer| !issuer.is_empty())
+                    .cloned(),
+                clock_skew: Duration::from_secs(parse_or(
+                    &properties,
+                    "sasl.oauthbearer.clock.skew.seconds",
+                    30,
+                )?),
+            }),
+            _ => None,
+        };
+        if oauthbearer
+            .as_ref()
+            .is_some_and(|settings| settings.jwks_refresh.is_zero())
+        {
+            return Err(anyhow!(
+                "sasl.oauthbearer.jwks.endpoint.refresh.ms must be at least 1"
+            ));
+        }
+        let group_settings = GroupSettings {
+            min_session_timeout: Duration::from_millis(parse_or(
+                &properties,
+                "group.min.session.timeout.ms",
+                defaults.group_settings.min_session_timeout.as_millis() as u64,
+            )?),
+            max_session_timeout: Duration::from_millis(parse_or(
+                &properties,
+                "group.max.session.timeout.ms",
+                defaults.group_settings.max_session_timeout.as_millis() as u64,
+            )?),
+            offset_metadata_max_bytes: parse_or(
+                &properties,
+                "offset.metadata.max.bytes",
+                defaults.group_settings.offset_metadata_max_bytes,
+            )?,
+            consumer_assignors: properties
+                .get("group.consumer.assignors")
+                .map(|value| parse_list(value))
+                .unwrap_or(defaults.group_settings.consumer_assignors),
+            consumer_heartbeat_interval: Duration::from_millis(parse_or(
+                &properties,
+                "group.consumer.heartbeat.interval.ms",
+                defaults
+                    .group_settings
+                    .consumer_heartbeat_interval
+                    .as_millis() as u64,
+            )?),
+            consumer_session_timeout: Duration::from_millis(parse_or(
+                &properties,
+                "group.consumer.session.timeout.ms",
+                defaults.group_settings.consumer_session_timeout.as_millis() as u64,
+            )?),
+            share_heartbeat_interval: Duration::from_millis(parse_or(
+                &properties,
+                "group.share.heartbeat.interval.ms",
+                defaults.group_settings.share_heartbeat_interval.as_millis() as u64,
+            )?),
+            share_session_timeout: Duration::from_millis(parse_or(
+                &properties,
+                "group.share.session.timeout.ms",
+                defaults.group_settings.share_session_timeout.as_millis() as u64,
+            )?),
+            share_record_lock_duration: Duration::from_millis(parse_or(
+                &properties,
+                "group.share.record.lock.duration.ms",
+                defaults
+                    .group_settings
+                    .share_record_lock_duration
+                    .as_millis() as u64,
+            )?),
+            share_delivery_attempt_limit: parse_or(
+                &properties,
+                "group.share.delivery.attempt.limit",
+                defaults.group_settings.share_delivery_attempt_limit,
+            )?,
+            share_partition_max_record_locks: parse_or(
+                &properties,
+                "group.share.partition.max.record.locks",
+                defaults.group_settings.share_partition_max_record_locks,
+            )?,
+            initial_rebalance_delay: Duration::from_millis(parse_or(
+                &properties,
+                "group.initial.rebalance.delay.ms",
+                defaults.group_settings.initial_rebalance_delay.as_millis() as u64,
+            )?),
+            offsets_retention: Duration::from_secs(
+                60 * parse_or(
+                    &properties,
+                    "offsets.retention.minutes",
+                    defaults.group_settings.offsets_retention.as_secs() / 60,
+                )?,
+            ),
+            offsets_retention_check_interval: Duration::from_millis(parse_or(
+                &properties,
+                "offsets.retention.check.interval.ms",
+                defaults
+                    .group_settings
+                    .offsets_retention_check_interval
+                    .as_millis() as u64,
+            )?),
+        };
+        if group_settings.min_session_timeout > group_settings.max_session_timeout {
+            return Err(anyhow!(
+                "group.min.session.timeout.ms must not exceed group.max.session.timeout.ms"
+            ));
+        }
+        if group_settings.consumer_heartbeat_interval >= group_settings.consumer_session_timeout {
+            return Err(anyhow!(
+                "group.consumer.heartbeat.interval.ms must be less than group.consumer.session.timeout.ms"
+            ));
+        }
+        if group_settings.share_heartbeat_interval >= group_settings.share_session_timeout {
+            return Err(anyhow!(
+                "group.share.heartbeat.interval.ms must be less than group.share.session.timeout.ms"
+            ));
+        }
+        if group_settings.share_record_lock_duration.is_zero() {
+            return Err(anyhow!(
+                "group.share.record.lock.duration.ms must be at least 1"
+            ));
+        }
+        if group_settings.share_delivery_attempt_limit < 1 {
+            return Err(anyhow!(
+                "group.share.delivery.attempt.limit must be at least 1"
+            ));
+        }
+        if group_settings.share_partition_max_record_locks == 0 {
+            return Err(anyhow!(
+                "group.share.partition.max.record.locks must be at least 1"
+            ));
+        }
+        if group_settings.offsets_retention.is_zero() {
+            return Err(anyhow!("offsets.retention.minutes must be at least 1"));
+        }
+        if group_settings.offsets_retention_check_interval.is_zero() {
+            return Err(anyhow!(
+                "offsets.retention.check.interval.ms must be at least 1"
+            ));
+        }
+        if group_settings.consumer_assignors.is_empty() {
+            return Err(anyhow!("group.consumer.assignors must not be empty"));
+        }
+        if let Some(unknown) = group_settings
+            .consumer_assignors
+            .iter()
+            .find(|name| !SERVER_ASSIGNORS.contains(&name.as_str()))
+        {
+            return Err(anyhow!(
+                "unknown assignor '{}' in group.consumer.assignors; expected one of {}",
+                unknown,
+                SERVER_ASSIGNORS.join(", ")
+            ));
+        }
+        let log_level = properties.get("log.level").cloned();
+        if let Some(level) = &log_level {
+            EnvFilter::try_new(level)
+                .map_err(|e| anyhow!("invalid value '{}' for 'log.level': {}", level, e))?;
+        }
+
+        let log_span_timing = parse_or(&properties, "log.span.timing", defaults.log_span_timing)?;
+        let log_wire_debug = parse_or(&properties, "log.wire.debug", defaults.log_wire_debug)?;
+
+        Ok(Self {
+            properties,
+            node_id,
+            rack,
+            listeners,
+            advertised_listeners,
+            controller_listener_names,
+            quorum,
+            replica_fetcher,
+            isr,
+            lifecycle,
+            log_dirs,
+            metadata_log_dir,
+            connections_max_idle,
+            socket_request_max_bytes,
+            socket_options,
+            max_in_flight,
+            request_timeout,
+            slow_request_threshold,
+            queued_max_request_bytes,
+            queued_max_request_bytes_per_connection,
+            in_flight_max_bytes,
+            fetch_session_cache_slots,
+            auto_create_topics,
+            num_partitions,
+            default_replication_factor,
+            admin_listener,
+            audit_log,
+            authorizer,
+            metrics_summary_interval,
+            max_connections,
+            max_connections_per_ip,
+            max_connections_per_ip_overrides,
+            listener_max_connections,
+            max_connection_creation_rate,
+            max_connection_creation_rate_per_ip,
+            producer_quotas,
+            consumer_quotas,
+            quota_window,
+            sasl_users,
+            delegation_tokens,
+            oauthbearer,
+            group_settings,
+            log_level,
+            log_span_timing,
+            log_wire_debug,
+        })
+    }
+
+    /// Builds the config that results from reloading `properties`: the
+    /// [`RELOADABLE_PROPERTIES`] are taken from `properties`, everything else
+    /// stays as it is. Also returns the other keys that changed, which only
+    /// take effect after a restart.
+    ///
+    /// The result is fully validated, so an error leaves the current config
+    /// untouched.
+    pub fn reload(&self, properties: &HashMap<String, String>) -> Result<(Self, Vec<String>)> {
+        let mut merged = self.properties.clone();
+        for &key in RELOADABLE_PROPERTIES {
+            match properties.get(key) {
+                Some(value) => merged.insert(key.to_string(), value.clone()),
+                None => merged.remove(key),
+            };
+        }
+        let mut needs_restart: Vec<String> = properties
+            .keys()
+            .chain(self.properties.keys())
+            .filter(|key| !RELOADABLE_PROPERTIES.contains(&key.as_str()))
+            .filter(|key| properties.get(*key) != self.properties.get(*key))
+            .cloned()
+            .collect();
+        needs_restart.sort();
+        needs_restart.dedup();
+        Ok((Self::from_properties(merged)?, needs_restart))
+    }
+
+    /// The first segment of the cluster metadata log this node reads.
+    pub fn metadata_log_file(&self) -> PathBuf {
+        cluster_metadata_log_file(&self.metadata_log_dir)
+    }
+
+    pub fn listener_type(&self, listener_name: &str) -> ListenerType {
+        if self
+            .controller_listener_names
+            .iter()
+            .any(|name| name == listener_name)
+        {
+            ListenerType::Controller
+        } else {
+            ListenerType::Broker
+        }
+    }
+
+    /// Looks up `key`, preferring the `listener.name.<listener>.<key>` override.
+    pub fn listener_property(&self, listener_name: &str, key: &str) -> Option<&String> {
+        let prefixed = format!("listener.name.{}.{}", listener_name.to_lowercase(), key);
+        self.properties
+            .get(&prefixed)
+            .or_else(|| self.properties.get(key))
+    }
+
+    pub fn ssl_settings(&self, listener_name: &str) -> Result<SslSettings> {
+        let client_auth = match self.listener_property(listener_name, "ssl.client.auth") {
+            Some(value) => value.parse()?,
+            None => SslClientAuth::None,
+        };
+        Ok(SslSettings {
+            keystore_location: self
+                .listener_property(listener_name, "ssl.keystore.location")
+                .map(PathBuf::from),
+            truststore_location: self
+                .listener_property(listener_name, "ssl.truststore.location")
+                .map(PathBuf::from),
+            client_auth,
+        })
+    }
+
+    /// The SASL mechanisms a listener accepts; empty unless it is a SASL listener.
+    pub fn sasl_enabled_mechanisms(&self, endpoint: &Endpoint) -> Vec<String> {
+        if !endpoint.security_protocol.uses_sasl() {
+            return Vec::new();
+        }
+        self.listener_property(&endpoint.listener_name, "sasl.enabled.mechanisms")
+            .map(|value| parse_list(value))
+            .unwrap_or_else(|| vec!["GSSAPI".to_string()])
+    }
+
+    /// The host and port advertised to clients connected through
+    /// `listener_name`. When the listener is advertised for both address
+    /// families, the one the client connected over wins. A missing or wildcard
+    /// advertised host falls back to the address the client actually
+    /// connected to.
+    pub fn advertised_address(&self, listener_name: &str, local: SocketAddr) -> (String, u16) {
+        let mut candidates = self
+            .advertised_listeners
+            .iter()
+            .filter(|l| l.listener_name == listener_name);
+        let advertised = candidates
+            .clone()
+            .find(|l| l.ip().is_some_and(|ip| ip.is_ipv4() == local.is_ipv4()))
+            .or_else(|| candidates.next());
+        match advertised {
+            Some(endpoint) if !endpoint.host.is_empty() && !is_wildcard(&endpoint.host) => {
+                (endpoint.host.clone(), endpoint.port)
+            }
+            Some(endpoint) => (local.ip().to_string(), endpoint.port),
+            None => (local.ip().to_string(), local.port()),
+        }
+    }
+}
+
+fn is_wildcard(host: &str) -> bool {
+    host.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified())
+}
+
+pub fn read_properties(path: impl AsRef<Path>) -> Result<HashMap<String, String>> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("read config file '{}'", path.display()))?;
+    Ok(parse_properties(&contents))
+}
+
+/// Parses `key=value` (or `key: value`) lines, skipping blanks and `#`/`!` comments.
+pub fn parse_properties(contents: &str) -> HashMap<String, String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| {
+            let idx = line.find(['=', ':'])?;
+            let (key, value) = line.split_at(idx);
+            Some((key.trim().to_string(), value[1..].trim().to_string()))
+        })
+        .collect()
+}
+
+/// Splits a comma-separated list, dropping empty entries.
+pub fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_or<T>(properties: &HashMap<String, String>, key: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match properties.get(key) {
+        Some(value) => value
+            .parse()
+            .map_err(|e| anyhow!("invalid value '{}' for '{}': {}", value, key, e)),
+        None => Ok(default),
+    }
+}
+
+/// `controller.quorum.*`, or `None` when this node isn't a voter.
+fn parse_quorum_settings(
+    properties: &HashMap<String, String>,
+    node_id: i32,
+) -> Result<Option<QuorumSettings>> {
+    let Some(value) = properties.get("controller.quorum.voters") else {
+        return Ok(None);
+    };
+    let voters = Voter::parse_list(value)?;
+    let is_controller = properties
+        .get("process.roles")
+        .is_none_or(|roles| parse_list(roles).iter().any(|r| r == "controller"));
+    if !is_controller || !voters.iter().any(|v| v.id == node_id) {
+        return Ok(None);
+    }
+    let defaults = QuorumSettings::default();
+    let millis = |key, default: Duration| -> Result<Duration> {
+        match parse_or(properties, key, default.as_millis() as u64)? {
+            0 => Err(anyhow!("'{}' must be positive", key)),
+            ms => Ok(Duration::from_millis(ms)),
+        }
+    };
+    Ok(Some(QuorumSettings {
+        voters,
+        election_timeout: millis(
+            "controller.quorum.election.timeout.ms",
+            defaults.election_timeout,
+        )?,
+        election_backoff_max: millis(
+            "controller.quorum.election.backoff.max.ms",
+            defaults.election_backoff_max,
+        )?,
+        fetch_timeout: millis("controller.quorum.fetch.timeout.ms", defaults.fetch_timeout)?,
+        request_timeout: millis(
+            "controller.quorum.request.timeout.ms",
+            defaults.request_timeout,
+        )?,
+        snapshot_interval_bytes: match parse_or(
+            properties,
+            "metadata.log.max.record.bytes.between.snapshots",
+            defaults.snapshot_interval_bytes as u64,
+        )? {
+            0 => {
+                return Err(anyhow!(
+                    "'metadata.log.max.record.bytes.between.snapshots' must be positive"
+                ))
+            }
+            bytes => bytes as usize,
+        },
+    }))
+}
+
+/// `replica.*` and `inter.broker.listener.name`.
+fn parse_replica_fetcher_settings(
+    properties: &HashMap<String, String>,
+) -> Result<ReplicaFetcherSettings> {
+    let defaults = ReplicaFetcherSettings::default();
+    let millis = |key, default: Duration| -> Result<Duration> {
+        match parse_or(properties, key, default.as_millis() as u64)? {
+            0 => Err(anyhow!("'{}' must be positive", key)),
+            ms => Ok(Duration::from_millis(ms)),
+        }
+    };
+    let settings = ReplicaFetcherSettings {
+        listener_name: properties
+            .get("inter.broker.listener.name")
+            .cloned()
+            .unwrap_or(defaults.listener_name),
+        max_wait: millis("replica.fetch.wait.max.ms", defaults.max_wait)?,
+        min_bytes: parse_or(properties, "replica.fetch.min.bytes", defaults.min_bytes)?,
+        max_bytes: parse_or(properties, "replica.fetch.max.bytes", defaults.max_bytes)?,
+        response_max_bytes: parse_or(
+            properties,
+            "replica.fetch.response.max.bytes",
+            defaults.response_max_bytes,
+        )?,
+        backoff: millis("replica.fetch.backoff.ms", defaults.backoff)?,
+        socket_timeout: millis("replica.socket.timeout.ms", defaults.socket_timeout)?,
+    };
+    if settings.max_bytes <= 0 || settings.response_max_bytes == 0 {
+        return Err(anyhow!(
+            "replica.fetch.max.bytes and replica.fetch.response.max.bytes must be positive"
+        ));
+    }
+    if settings.socket_timeout < settings.max_wait {
+        return Err(anyhow!(
+            "replica.socket.timeout.ms must be at least replica.fetch.wait.max.ms"
+        ));
+    }
+    Ok(settings)
+}
+
+/// `replica.lag.time.max.ms`, and the controllers every broker sends ISR
+/// changes to.
+fn parse_isr_settings(properties: &HashMap<String, String>) -> Result<IsrSettings> {
+    let defaults = IsrSettings::default();
+    let lag_time_max = match parse_or(
+        properties,
+        "replica.lag.time.max.ms",
+        defaults.lag_time_max.as_millis() as u64,
+    )? {
+        0 => return Err(anyhow!("'replica.lag.time.max.ms' must be positive")),
+        ms => Duration::from_millis(ms),
+    };
+    let controllers = match properties.get("controller.quorum.voters") {
+        Some(value) => Voter::parse_list(value)?,
+        None => defaults.controllers,
+    };
+    Ok(IsrSettings {
+        lag_time_max,
+        controllers,
+    })
+}
+
+fn parse_lifecycle_settings(
+    properties: &HashMap<String, String>,
+) -> Result<BrokerLifecycleSettings> {
+    let defaults = BrokerLifecycleSettings::default();
+    let millis = |key, default: Duration| -> Result<Duration> {
+        match parse_or(properties, key, default.as_millis() as u64)? {
+            0 => Err(anyhow!("'{}' must be positive", key)),
+            ms => Ok(Duration::from_millis(ms)),
+        }
+    };
+    let heartbeat_interval = millis("broker.heartbeat.interval.ms", defaults.heartbeat_interval)?;
+    let session_timeout = millis("broker.session.timeout.ms", defaults.session_timeout)?;
+    if session_timeout <= heartbeat_interval {
+        return Err(anyhow!(
+            "'broker.session.timeout.ms' must be greater than 'broker.heartbeat.interval.ms'"
+        ));
+    }
+    Ok(BrokerLifecycleSettings {
+        heartbeat_interval,
+        session_timeout,
+    })
+}
+
+/// `socket.keepalive.*`, or `None` when `socket.keepalive.enable` is false.
+fn parse_keepalive(properties: &HashMap<String, String>) -> Result<Option<Keepalive>> {
+    if !parse_or(properties, "socket.keepalive.enable", true)? {
+        return Ok(None);
+    }
+    let defaults = Keepalive::default();
+    let millis = |key, default: Duration| -> Result<Duration> {
+        match parse_or(properties, key, default.as_millis() as u64)? {
+            0 => Err(anyhow!("'{}' must be positive", key)),
+            ms => Ok(Duration::from_millis(ms)),
+        }
+    };
+    let keepalive = Keepalive {
+        idle: millis("socket.keepalive.idle.ms", defaults.idle)?,
+        interval: millis("socket.keepalive.interval.ms", defaults.interval)?,
+        probes: parse_or(properties, "socket.keepalive.probes", defaults.probes)?,
+    };
+    if keepalive.probes == 0 {
+        return Err(anyhow!("'socket.keepalive.probes' must be positive"));
+    }
+    Ok(Some(keepalive))
+}
+
+/// Reads a socket buffer size where `-1` means the OS default. The outer
+/// `None` means the key is absent.
+fn parse_buffer_size(
+    properties: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<Option<u32>>> {
+    if !properties.contains_key(key) {
+        return Ok(None);
+    }
+    let size: i64 = parse_or(properties, key, -1)?;
+    match size {
+        -1 => Ok(Some(None)),
+        size if size > 0 && size <= u32::MAX as i64 => Ok(Some(Some(size as u32))),
+        _ => Err(anyhow!("'{}' must be positive or -1", key)),
+    }
+}
+
+/// Reads a byte limit where `-1` (the default) means unbounded.
+fn parse_byte_limit(properties: &HashMap<String, String>, key: &str) -> Result<Option<usize>> {
+    let limit: i64 = parse_or(properties, key, -1)?;
+    match limit {
+        -1 => Ok(None),
+        limit if limit > 0 => Ok(Some(limit as usize)),
+        _ => Err(anyhow!("'{}' must be positive or -1", key)),
+    }
+}
+
+/// Reads a per-second rate that must be at least 1. `None` means the key is
+/// absent.
+fn parse_rate(properties: &HashMap<String, String>, key: &str) -> Result<Option<u32>> {
+    match properties.get(key) {
+        Some(_) => match parse_or(properties, key, 0)? {
+            0 => Err(anyhow!("{} must be at least 1", key)),
+            rate => Ok(Some(rate)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Reads `<prefix>.default` (bytes per second) and `<prefix>.override`, a list
+/// of `client-id:bytes` pairs.
+fn parse_quota_settings(
+    properties: &HashMap<String, String>,
+    prefix: &str,
+) -> Result<QuotaSettings> {
+    let default_key = format!("{}.default", prefix);
+    let default = match properties.get(&default_key) {
+        Some(_) => Some(parse_or(properties, &default_key, 0.0)?),
+        None => None,
+    };
+    let override_key = format!("{}.override", prefix);
+    let overrides = parse_list(properties.get(&override_key).map_or("", String::as_str))
+        .into_iter()
+        .map(|entry| {
+            let (client_id, rate) = entry
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("invalid {} entry '{}'", override_key, entry))?;
+            let rate = rate
+                .parse()
+                .with_context(|| format!("invalid {} rate '{}'", override_key, rate))?;
+            Ok((client_id.to_string(), rate))
+        })
+        .collect::<Result<_>>()?;
+    Ok(QuotaSettings { default, overrides })
+}
+
+/// Parses `NAME:PROTOCOL` pairs, e.g. `INTERNAL:PLAINTEXT,EXTERNAL:SSL`.
+fn parse_protocol_map(value: &str) -> Result<HashMap<String, SecurityProtocol>> {
+    parse_list(value)
+        .into_iter()
+        .map(|entry| {
+            let (name, protocol) = entry.split_once(':').ok_or_else(|| {
+                anyhow!("invalid listener.security.protocol.map entry '{}'", entry)
+            })?;
+            Ok((name.to_uppercase(), protocol.parse()?))
+        })
+        .collect()
+}
+
+/// Parses `ip:count` pairs separated by commas, e.g. `127.0.0.1:200,::1:50`.
+fn parse_ip_overrides(value: &str) -> Result<HashMap<IpAddr, usize>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (ip, count) = entry
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("invalid per-ip override '{}'", entry))?;
+            let ip = ip
+                .parse()
+                .with_context(|| format!("invalid per-ip override address '{}'", ip))?;
+            let count = count
+                .parse()
+                .with_context(|| format!("invalid per-ip override count '{}'", count))?;
+            Ok((ip, count))
+        })
+        .collect()
+}
@@ -0,0 +1,340 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{config::Config, metrics::Metrics};
+
+pub const CONNECTION_COUNT_METRIC: &str = "kafka_server_connections";
+pub const CONNECTIONS_PER_IP_METRIC: &str = "kafka_server_connections_per_ip";
+pub const CONNECTIONS_REJECTED_METRIC: &str = "kafka_server_connections_rejected_total";
+pub const CONNECTIONS_THROTTLED_METRIC: &str = "kafka_server_connections_throttled_total";
+/// Connections closed because the peer stopped answering without closing.
+pub const CONNECTIONS_REAPED_METRIC: &str = "kafka_server_connections_reaped_total";
+
+/// Per-IP creation rate trackers are swept for idle ones past this many.
+const RATE_PURGE_THRESHOLD: usize = 1024;
+
+/// Enforces `max.connections` and `max.connections.per.ip`, and the
+/// connection creation rates `max.connection.creation.rate` and
+/// `max.connection.creation.rate.per.ip`.
+///
+/// The broker-wide limits delay accepts until a slot frees up or the rate
+/// allows another connection, matching Kafka's behaviour of leaving excess
+/// connections in the listen backlog. The per-IP connection limit rejects the
+/// connection outright, while the per-IP rate holds it back before its first
+/// request is read, so one client reconnecting in a loop only slows itself.
+///
+/// A listener may also have its own cap, `listener.name.<listener>.max.connections`.
+/// Controller listeners are held only to theirs, so a flood of client
+/// connections cannot lock out controller traffic.
+pub struct ConnectionQuotas {
+    limits: Mutex<Limits>,
+    slots: Arc<Semaphore>,
+    listener_slots: HashMap<String, Arc<Semaphore>>,
+    controller_listeners: Vec<String>,
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+    creation_rate: Mutex<Option<CreationRate>>,
+    creation_rate_per_ip: Mutex<HashMap<IpAddr, CreationRate>>,
+    metrics: Arc<Metrics>,
+}
+
+struct Limits {
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    per_ip_overrides: HashMap<IpAddr, usize>,
+    max_creation_rate: Option<u32>,
+    max_creation_rate_per_ip: Option<u32>,
+}
+
+impl Limits {
+    fn new(config: &Config) -> Self {
+        Self {
+            max_connections: config.max_connections.min(Semaphore::MAX_PERMITS),
+            max_connections_per_ip: config.max_connections_per_ip,
+            per_ip_overrides: config.max_connections_per_ip_overrides.clone(),
+            max_creation_rate: config.max_connection_creation_rate,
+            max_creation_rate_per_ip: config.max_connection_creation_rate_per_ip,
+        }
+    }
+}
+
+/// Paces connection creation to `rate` per second, allowing a burst of up to
+/// one second's worth after a quiet spell.
+struct CreationRate {
+    rate: f64,
+    /// Connections that may be created right away. Negative when connections
+    /// have been admitted ahead of the rate and are waiting their turn.
+    credit: f64,
+    updated: Instant,
+}
+
+impl CreationRate {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            credit: rate as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes a turn and returns how long to wait before it comes up.
+    fn admit(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        self.credit -= 1.0;
+        if self.credit >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.credit / self.rate)
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.credit = (self.credit + elapsed * self.rate).min(self.rate);
+        self.updated = now;
+    }
+
+    fn is_idle(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.credit >= self.rate
+    }
+}
+
+impl ConnectionQuotas {
+    pub fn new(config: &Config, metrics: Arc<Metrics>) -> Arc<Self> {
+        let limits = Limits::new(config);
+        metrics.set_gauge(CONNECTION_COUNT_METRIC, &[], 0);
+        let listener_slots = config
+            .listener_max_connections
+            .iter()
+            .map(|(name, &max)| {
+                let max = max.min(Semaphore::MAX_PERMITS);
+                (name.clone(), Arc::new(Semaphore::new(max)))
+            })
+            .collect();
+        Arc::new(Self {
+            slots: Arc::new(Semaphore::new(limits.max_connections)),
+            listener_slots,
+            controller_listeners: config.controller_listener_names.clone(),
+            creation_rate: Mutex::new(limits.max_creation_rate.map(CreationRate::new)),
+            creation_rate_per_ip: Mutex::new(HashMap::new()),
+            limits: Mutex::new(limits),
+            per_ip: Mutex::new(HashMap::new()),
+            metrics,
+        })
+    }
+
+    /// Applies new limits, as on a config reload. Existing connections are
+    /// kept; a lower limit only holds back new ones until enough close.
+    pub fn reconfigure(&self, config: &Config) {
+        let new = Limits::new(config);
+        let mut limits = self.limits.lock().unwrap();
+        let old_max = limits.max_connections;
+        if new.max_creation_rate != limits.max_creation_rate {
+            *self.creation_rate.lock().unwrap() = new.max_creation_rate.map(CreationRate::new);
+        }
+        if new.max_creation_rate_per_ip != limits.max_creation_rate_per_ip {
+            self.creation_rate_per_ip.lock().unwrap().clear();
+        }
+        *limits = new;
+        let new_max = limits.max_connections;
+        drop(limits);
+
+        if new_max > old_max {
+            self.slots.add_permits(new_max - old_max);
+        } else if new_max < old_max {
+            // Slots held by open connections can't be taken back now, so the
+            // rest are retired as those connections close.
+            let excess = old_max - new_max;
+            let remaining = excess - self.slots.forget_permits(excess);
+            if remaining > 0 {
+                let slots = self.slots.clone();
+                tokio::spawn(async move {
+                    if let Ok(permits) = slots
+                        .acquire_many_owned(remaining.min(u32::MAX as usize) as u32)
+                        .await
+                    {
+                        permits.forget();
+                    }
+                });
+            }
+        }
+    }
+
+    /// Waits until there is room for another connection on `listener_name`,
+    /// reserving it.
+    pub async fn reserve(&self, listener_name: &str) -> ConnectionSlot {
+        let listener = match self.listener_slots.get(listener_name) {
+            Some(slots) => Some(acquire(slots).await),
+            None => None,
+        };
+        let broker = if self.is_controller_listener(listener_name) {
+            None
+        } else {
+            Some(acquire(&self.slots).await)
+        };
+        ConnectionSlot {
+            _broker: broker,
+            _listener: listener,
+        }
+    }
+
+    /// Waits until `max.connection.creation.rate` allows another connection
+    /// on `listener_name`.
+    pub async fn pace_creation(&self, listener_name: &str) {
+        if self.is_controller_listener(listener_name) {
+            return;
+        }
+        let delay = match &mut *self.creation_rate.lock().unwrap() {
+            Some(rate) => rate.admit(Instant::now()),
+            None => return,
+        };
+        self.throttle(delay, "broker").await;
+    }
+
+    /// Waits until `max.connection.creation.rate.per.ip` allows another
+    /// connection from `ip`.
+    pub async fn pace_creation_from(&self, ip: IpAddr) {
+        let Some(max_rate) = self.limits.lock().unwrap().max_creation_rate_per_ip else {
+            return;
+        };
+        let delay = {
+            let now = Instant::now();
+            let mut rates = self.creation_rate_per_ip.lock().unwrap();
+            if rates.len() >= RATE_PURGE_THRESHOLD {
+                rates.retain(|_, rate| !rate.is_idle(now));
+            }
+            rates
+                .entry(ip)
+                .or_insert_with(|| CreationRate::new(max_rate))
+                .admit(now)
+        };
+        self.throttle(delay, "ip").await;
+    }
+
+    async fn throttle(&self, delay: Duration, scope: &str) {
+        if delay.is_zero() {
+            return;
+        }
+        self.metrics
+            .incr_counter(CONNECTIONS_THROTTLED_METRIC, &[("scope", scope)], 1);
+        tokio::time::sleep(delay).await;
+    }
+
+    /// Registers an accepted connection, or returns `None` if `ip` is already
+    /// at its per-IP limit. The connection is released when the returned
+    /// permit is dropped.
+    pub fn try_acquire(
+        self: &Arc<Self>,
+        ip: IpAddr,
+        slot: ConnectionSlot,
+    ) -> Option<ConnectionPermit> {
+        let limit = self.limit_for(ip);
+        let mut per_ip = self.per_ip.lock().unwrap();
+        let count = per_ip.entry(ip).or_default();
+        if *count >= limit {
+            if *count == 0 {
+                per_ip.remove(&ip);
+            }
+            drop(per_ip);
+            self.metrics
+                .incr_counter(CONNECTIONS_REJECTED_METRIC, &[("reason", "per_ip")], 1);
+            return None;
+        }
+        *count += 1;
+        let ip_count = *count;
+        let total = per_ip.values().sum::<usize>();
+        drop(per_ip);
+
+        self.record(ip, ip_count, total);
+        Some(ConnectionPermit {
+            quotas: self.clone(),
+            ip,
+            _slot: slot,
+        })
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.per_ip.lock().unwrap().values().sum()
+    }
+
+    fn is_controller_listener(&self, listener_name: &str) -> bool {
+        self.controller_listeners
+            .iter()
+            .any(|name| name == listener_name)
+    }
+
+    fn limit_for(&self, ip: IpAddr) -> usize {
+        let limits = self.limits.lock().unwrap();
+        limits
+            .per_ip_overrides
+            .get(&ip)
+            .copied()
+            .unwrap_or(limits.max_connections_per_ip)
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut per_ip = self.per_ip.lock().unwrap();
+        let ip_count = match per_ip.get_mut(&ip) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            }
+            None => return,
+        };
+        if ip_count == 0 {
+            per_ip.remove(&ip);
+        }
+        let total = per_ip.values().sum::<usize>();
+        drop(per_ip);
+
+        self.record(ip, ip_count, total);
+    }
+
+    fn record(&self, ip: IpAddr, ip_count: usize, total: usize) {
+        let ip = ip.to_string();
+        let labels = [("ip", ip.as_str())];
+        if ip_count == 0 {
+            self.metrics
+                .remove_gauge(CONNECTIONS_PER_IP_METRIC, &labels);
+        } else {
+            self.metrics
+                .set_gauge(CONNECTIONS_PER_IP_METRIC, &labels, ip_count as i64);
+        }
+        self.metrics
+            .set_gauge(CONNECTION_COUNT_METRIC, &[], total as i64);
+    }
+}
+
+async fn acquire(slots: &Arc<Semaphore>) -> OwnedSemaphorePermit {
+    slots
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("connection semaphore is never closed")
+}
+
+/// Room for one connection under the broker-wide and listener caps that
+/// apply to it, reserved before the accept.
+pub struct ConnectionSlot {
+    _broker: Option<OwnedSemaphorePermit>,
+    _listener: Option<OwnedSemaphorePermit>,
+}
+
+/// A live connection counted against the quotas.
+pub struct ConnectionPermit {
+    quotas: Arc<ConnectionQuotas>,
+    ip: IpAddr,
+    _slot: ConnectionSlot,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.quotas.release(self.ip);
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use crate::request_context::Session;
+
+/// Every open connection and what it is doing, for the admin endpoint.
+#[derive(Default)]
+pub struct ConnectionRegistry {
+    next_id: AtomicU64,
+    connections: Mutex<BTreeMap<u64, Arc<ConnectionState>>>,
+}
+
+/// Live state of one connection. Counters are updated by the connection task
+/// as it goes, so a snapshot is always current.
+pub struct ConnectionState {
+    pub id: u64,
+    pub listener_name: String,
+    pub peer: SocketAddr,
+    pub opened_at: Instant,
+    identity: Mutex<Identity>,
+    in_flight: AtomicUsize,
+    requests: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+#[derive(Clone, Default)]
+struct Identity {
+    client_id: String,
+    principal: String,
+    auth_state: &'static str,
+}
+
+impl ConnectionRegistry {
+    /// Adds a connection; it is removed again when the returned handle drops.
+    pub fn register(
+        self: &Arc<Self>,
+        listener_name: &str,
+        peer: SocketAddr,
+    ) -> RegisteredConnection {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let state = Arc::new(ConnectionState {
+            id,
+            listener_name: listener_name.to_string(),
+            peer,
+            opened_at: Instant::now(),
+            identity: Mutex::default(),
+            in_flight: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+        });
+        self.connections.lock().unwrap().insert(id, state.clone());
+        RegisteredConnection {
+            registry: self.clone(),
+            state,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn connections(&self) -> Vec<Arc<ConnectionState>> {
+        self.connections.lock().unwrap().values().cloned().collect()
+    }
+
+    /// One line of `key=value` pairs per connection, oldest first.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for conn in self.connections() {
+            let identity = conn.identity.lock().unwrap().clone();
+            let _ = writeln!(
+                out,
+                "id={} listener={} peer={} client_id={:?} principal={} auth={} \
+                 in_flight={} requests={} bytes_received={} bytes_sent={} age_ms={}",
+                conn.id,
+                conn.listener_name,
+                conn.peer,
+                identity.client_id,
+                identity.principal,
+                identity.auth_state,
+                conn.in_flight.load(Ordering::Relaxed),
+                conn.requests.load(Ordering::Relaxed),
+                conn.bytes_received.load(Ordering::Relaxed),
+                conn.bytes_sent.load(Ordering::Relaxed),
+                conn.opened_at.elapsed().as_millis(),
+            );
+        }
+        out
+    }
+}
+
+impl ConnectionState {
+    /// Picks up the principal and SASL state after they may have changed.
+    pub fn update_session(&self, session: &Session) {
+        let mut identity = self.identity.lock().unwrap();
+        identity.principal = session.principal.to_string();
+        identity.auth_state = session.auth_state();
+    }
+
+    /// Counts a new request from `client_id`.
+    pub fn record_request(&self, client_id: &str) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let mut identity = self.identity.lock().unwrap();
+        if identity.client_id != client_id {
+            identity.client_id = client_id.to_string();
+        }
+    }
+
+    pub fn set_in_flight(&self, n: usize) {
+        self.in_flight.store(n, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_received(&self, n: usize) {
+        self.bytes_received.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_sent(&self, n: usize) {
+        self.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+/// A connection's entry in the [`ConnectionRegistry`], removed when dropped.
+pub struct RegisteredConnection {
+    registry: Arc<ConnectionRegistry>,
+    state: Arc<ConnectionState>,
+}
+
+impl Deref for RegisteredConnection {
+    type Target = ConnectionState;
+
+    fn deref(&self) -> &ConnectionState {
+        &self.state
+    }
+}
+
+impl Drop for RegisteredConnection {
+    fn drop(&mut self) {
+        self.registry
+            .connections
+            .lock()
+            .unwrap()
+            .remove(&self.state.id);
+    }
+}
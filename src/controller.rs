@@ -0,0 +1,894 @@
+//! What the active controller does beyond replicating the metadata log:
+//! register brokers, fence those that stop heartbeating and unfence them
+//! when they come back, move the leadership of partitions off fenced
+//! brokers, create topics and partitions, set configs and client quotas and
+//! carry out partition reassignments and finalize feature levels, as
+//! Kafka's `ClusterControlManager`, `ReplicationControlManager`,
+//! `ConfigurationControlManager`, `ClientQuotaControlManager` and
+//! `FeatureControlManager` do.
+//!
+//! Every change is a record appended through the metadata quorum, so only
+//! the quorum leader makes any; the other voters answer NOT_CONTROLLER.
+//! Nothing is changed in place: brokers see a change once they read it
+//! from the log.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{error, info, warn};
+
+use crate::api::cluster_metadata::{
+    BrokerChangeValue, BrokerEndpoint, BrokerFeature, BrokerValue, ClientQuotaValue, ConfigValue,
+    FeatureLevelValue, PartitionChangeValue, PartitionValue, QuotaComponents, RecordBatches,
+    RemoveTopicValue, TopicValue, TOPIC_CONFIG_RESOURCE,
+};
+use crate::features::{supported_feature, METADATA_VERSION};
+use crate::protocol::{CompactNullableString, ErrorCode, Uuid};
+use crate::quota::{QuotaEntity, CONSUMER_BYTE_RATE, PRODUCER_BYTE_RATE};
+use crate::raft::MetadataQuorum;
+use crate::record_batch::BatchRecord;
+
+/// How often brokers' heartbeats are checked and leaders elected.
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The longest topic name Kafka allows.
+const MAX_TOPIC_NAME_LENGTH: usize = 249;
+
+/// The directory of a replica placed before any log directory was picked
+/// for it.
+pub const UNASSIGNED_DIRECTORY: &str = "00000000-0000-0000-0000-000000000000";
+
+pub struct HeartbeatResult {
+    pub error_code: ErrorCode,
+    pub is_fenced: bool,
+}
+
+/// A broker asking to join the cluster.
+pub struct BrokerRegistration {
+    pub broker_id: i32,
+    pub cluster_id: String,
+    pub incarnation_id: Uuid,
+    pub endpoints: Vec<BrokerEndpoint>,
+    pub rack: Option<String>,
+    pub features: Vec<BrokerFeature>,
+}
+
+/// A topic to create. Without `assignments`, the controller spreads
+/// `num_partitions` partitions of `replication_factor` replicas over the
+/// unfenced brokers.
+pub struct NewTopic {
+    pub name: String,
+    pub num_partitions: i32,
+    pub replication_factor: i16,
+    /// Each partition's replicas, the preferred leader first.
+    pub assignments: Vec<Vec<i32>>,
+    pub configs: BTreeMap<String, String>,
+}
+
+/// The last heartbeat from a broker.
+struct Heartbeat {
+    at: Instant,
+    want_fence: bool,
+}
+
+pub struct Controller {
+    quorum: MetadataQuorum,
+    /// How long a broker may go without heartbeating before it is fenced,
+    /// `broker.session.timeout.ms`.
+    session_timeout: Duration,
+    /// Heartbeats since this node last became the active controller.
+    heartbeats: Mutex<HashMap<i32, Heartbeat>>,
+    /// When this node became the active controller, or `None` while it
+    /// isn't.
+    active_since: Mutex<Option<Instant>>,
+    /// Held from reading the metadata log until the records made from it are
+    /// committed, so two changes can't both build on the same state.
+    changes: Mutex<()>,
+    stop: watch::Sender<bool>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Controller {
+    pub fn start(quorum: MetadataQuorum, session_timeout: Duration) -> Arc<Self> {
+        let controller = Arc::new(Self {
+            quorum,
+            session_timeout,
+            heartbeats: Mutex::new(HashMap::new()),
+            active_since: Mutex::new(None),
+            changes: Mutex::new(()),
+            stop: watch::channel(false).0,
+            task: Mutex::new(None),
+        });
+        let task = tokio::spawn(controller.clone().run());
+        *controller.task.lock().unwrap() = Some(task);
+        controller
+    }
+
+    pub fn quorum(&self) -> &MetadataQuorum {
+        &self.quorum
+    }
+
+    /// Held by whoever appends records made from the current metadata.
+    pub fn lock_changes(&self) -> MutexGuard<'_, ()> {
+        self.changes.lock().unwrap()
+    }
+
+    /// Notes that `broker_id` is alive and answers with whether it is
+    /// fenced. A broker that wants to be fenced is, at the next check.
+    pub fn heartbeat(
+        &self,
+        broker_id: i32,
+        broker_epoch: i64,
+        want_fence: bool,
+    ) -> HeartbeatResult {
+        let answer = |error_code, is_fenced| HeartbeatResult {
+            error_code,
+            is_fenced,
+        };
+        let metadata = match self.metadata() {
+            Ok(metadata) => metadata,
+            Err(error_code) => return answer(error_code, true),
+        };
+        let Some(broker) = metadata.brokers().find(|b| b.broker_id == broker_id) else {
+            return answer(ErrorCode::BrokerIdNotRegistered, true);
+        };
+        if broker.broker_epoch != broker_epoch {
+            return answer(ErrorCode::StaleBrokerEpoch, true);
+        }
+        self.heartbeats.lock().unwrap().insert(
+            broker_id,
+            Heartbeat {
+                at: Instant::now(),
+                want_fence,
+            },
+        );
+        answer(ErrorCode::None, broker.fenced)
+    }
+
+    /// Registers a broker, fenced until its first heartbeat, and answers
+    /// with its new broker epoch: the offset its registration is expected
+    /// at. A broker that restarts while its old registration's session is
+    /// still alive has to wait for it to expire, and one that can't run at
+    /// the finalized feature levels isn't let in.
+    pub fn register_broker(&self, registration: BrokerRegistration) -> Result<i64, ErrorCode> {
+        let _changes = self.lock_changes();
+        if !self.quorum.is_cluster(Some(&registration.cluster_id)) {
+            return Err(ErrorCode::InconsistentClusterId);
+        }
+        let metadata = self.metadata()?;
+        let finalized = metadata.finalized_features();
+        let existing = metadata
+            .brokers()
+            .find(|b| b.broker_id == registration.broker_id);
+        if let Some(existing) = existing {
+            if existing.incarnation_id == registration.incarnation_id {
+                return Ok(existing.broker_epoch);
+            }
+            let alive = self
+                .heartbeats
+                .lock()
+                .unwrap()
+                .get(&registration.broker_id)
+                .is_some_and(|h| h.at.elapsed() <= self.session_timeout);
+            if alive && !existing.fenced {
+                return Err(ErrorCode::DuplicateBrokerRegistration);
+            }
+        }
+        let broker = BrokerValue {
+            broker_id: registration.broker_id,
+            incarnation_id: registration.incarnation_id,
+            broker_epoch: metadata.end_offset(),
+            endpoints: registration.endpoints,
+            rack: registration.rack,
+            fenced: true,
+            features: registration.features,
+        };
+        if let Some((name, level)) = finalized
+            .levels
+            .iter()
+            .find(|(name, level)| !broker.supports(name, **level))
+        {
+            warn!(
+                broker = broker.broker_id,
+                feature = %name,
+                level,
+                "broker doesn't support a finalized feature level"
+            );
+            return Err(ErrorCode::UnsupportedVersion);
+        }
+        self.quorum
+            .append(vec![broker.record(finalized.metadata_version())])?;
+        self.heartbeats.lock().unwrap().remove(&broker.broker_id);
+        info!(
+            broker = broker.broker_id,
+            broker_epoch = broker.broker_epoch,
+            "registered broker"
+        );
+        Ok(broker.broker_epoch)
+    }
+
+    /// Creates a topic with its partitions and configs, answering with its
+    /// new id. With `validate_only` nothing is appended.
+    pub fn create_topic(&self, topic: &NewTopic, validate_only: bool) -> Result<Uuid, ErrorCode> {
+        let _changes = self.lock_changes();
+        let metadata = self.metadata()?;
+        if !is_valid_topic_name(&topic.name) {
+            return Err(ErrorCode::InvalidTopicException);
+        }
+        if metadata.topic_by_name(&topic.name).is_some() {
+            return Err(ErrorCode::TopicAlreadyExists);
+        }
+        let assignments = if topic.assignments.is_empty() {
+            if topic.num_partitions <= 0 {
+                return Err(ErrorCode::InvalidPartitions);
+            }
+            assign_replicas(&metadata, 0, topic.num_partitions, topic.replication_factor)?
+        } else {
+            check_assignments(&metadata, &topic.assignments, None)?;
+            topic.assignments.clone()
+        };
+
+        let topic_id = Uuid::random();
+        let mut records = vec![TopicValue {
+            topic_name: CompactNullableString(Some(topic.name.clone())),
+            topic_id: topic_id.clone(),
+        }
+        .record()];
+        records.extend(partition_records(
+            &topic_id,
+            0,
+            &assignments,
+            metadata.finalized_features().metadata_version(),
+        ));
+        records.extend(topic.configs.iter().map(|(name, value)| {
+            ConfigValue {
+                resource_type: TOPIC_CONFIG_RESOURCE,
+                resource_name: topic.name.clone(),
+                name: name.clone(),
+                value: Some(value.clone()),
+            }
+            .record()
+        }));
+        if !validate_only {
+            self.quorum.append(records)?;
+            info!(
+                topic = %topic.name,
+                topic_id = %topic_id,
+                partitions = assignments.len(),
+                "created topic"
+            );
+        }
+        Ok(topic_id)
+    }
+
+    /// Deletes a topic with its partitions and configs, answering with the
+    /// name it had.
+    pub fn delete_topic(&self, topic_id: &Uuid) -> Result<String, ErrorCode> {
+        let _changes = self.lock_changes();
+        let metadata = self.metadata()?;
+        let Some(name) = metadata
+            .topic(topic_id)
+            .and_then(|topic| topic.topic_name.0.clone())
+        else {
+            return Err(ErrorCode::UnknownTopicId);
+        };
+        // Written as removals, so a topic created later under the same name
+        // doesn't inherit them.
+        let mut records: Vec<BatchRecord> = metadata
+            .configs(TOPIC_CONFIG_RESOURCE, &name)
+            .into_keys()
+            .map(|config| {
+                ConfigValue {
+                    resource_type: TOPIC_CONFIG_RESOURCE,
+                    resource_name: name.clone(),
+                    name: config,
+                    value: None,
+                }
+                .record()
+            })
+            .collect();
+        records.push(
+            RemoveTopicValue {
+                topic_id: topic_id.clone(),
+            }
+            .record(),
+        );
+        self.quorum.append(records)?;
+        info!(topic = %name, topic_id = %topic_id, "deleted topic");
+        Ok(name)
+    }
+
+    /// Grows a topic to `count` partitions, placing the new ones on
+    /// `assignments` if given and otherwise spreading them like the first.
+    pub fn create_partitions(
+        &self,
+        topic_name: &str,
+        count: i32,
+        assignments: &[Vec<i32>],
+        validate_only: bool,
+    ) -> Result<(), ErrorCode> {
+        let _changes = self.lock_changes();
+        let metadata = self.metadata()?;
+        let Some(topic) = metadata.topic_by_name(topic_name) else {
+            return Err(ErrorCode::UnknownTopicOrPartition);
+        };
+        let existing: Vec<&PartitionValue> = metadata.partitions(&topic.topic_id).collect();
+        let current = existing.len() as i32;
+        if count <= current {
+            return Err(ErrorCode::InvalidPartitions);
+        }
+        let replication_factor = existing.first().map_or(1, |p| p.replicas.len() as i16);
+        let new = if assignments.is_empty() {
+            assign_replicas(&metadata, current, count - current, replication_factor)?
+        } else {
+            if assignments.len() as i32 != count - current {
+                return Err(ErrorCode::InvalidReplicaAssignment);
+            }
+            check_assignments(&metadata, assignments, Some(replication_factor))?;
+            assignments.to_vec()
+        };
+        if !validate_only {
+            let records = partition_records(
+                &topic.topic_id,
+                current as u32,
+                &new,
+                metadata.finalized_features().metadata_version(),
+            );
+            self.quorum.append(records)?;
+            info!(topic = topic_name, partitions = count, "created partitions");
+        }
+        Ok(())
+    }
+
+    /// Replaces a resource's configs with `configs`: those given are set,
+    /// and any set before but not given are removed. Topics have to exist.
+    pub fn alter_configs(
+        &self,
+        resource_type: i8,
+        resource_name: &str,
+        configs: &BTreeMap<String, String>,
+        validate_only: bool,
+    ) -> Result<(), ErrorCode> {
+        let _changes = self.lock_changes();
+        let metadata = self.metadata()?;
+        if resource_type == TOPIC_CONFIG_RESOURCE && metadata.topic_by_name(resource_name).is_none()
+        {
+            return Err(ErrorCode::UnknownTopicOrPartition);
+        }
+        if configs.keys().any(|name| name.is_empty()) {
+            return Err(ErrorCode::InvalidConfig);
+        }
+        let current = metadata.configs(resource_type, resource_name);
+        let config = |name: &String, value: Option<String>| {
+            ConfigValue {
+                resource_type,
+                resource_name: resource_name.to_string(),
+                name: name.clone(),
+                value,
+            }
+            .record()
+        };
+        let mut records: Vec<BatchRecord> = configs
+            .iter()
+            .filter(|(name, value)| current.get(*name) != Some(value))
+            .map(|(name, value)| config(name, Some(value.clone())))
+            .collect();
+        records.extend(
+            current
+                .keys()
+                .filter(|name| !configs.contains_key(*name))
+                .map(|name| config(name, None)),
+        );
+        if !validate_only {
+            self.quorum.append(records)?;
+        }
+        Ok(())
+    }
+
+    /// Sets each quota in `changes` on a client entity, or with `None`
+    /// removes it. Quotas the entity already has as given are left alone.
+    pub fn alter_client_quotas(
+        &self,
+        entity: &QuotaComponents,
+        changes: &BTreeMap<String, Option<f64>>,
+        validate_only: bool,
+    ) -> Result<(), ErrorCode> {
+        if QuotaEntity::from_components(entity).is_none() {
+            return Err(ErrorCode::InvalidRequest);
+        }
+        for (key, value) in changes {
+            if ![PRODUCER_BYTE_RATE, CONSUMER_BYTE_RATE].contains(&key.as_str()) {
+                return Err(ErrorCode::InvalidRequest);
+            }
+            if value.is_some_and(|value| !(value.is_finite() && value > 0.0)) {
+                return Err(ErrorCode::InvalidRequest);
+            }
+        }
+        let _changes = self.lock_changes();
+        let current = self
+            .metadata()?
+            .client_quotas()
+            .remove(entity)
+            .unwrap_or_default();
+        let records: Vec<BatchRecord> = changes
+            .iter()
+            .filter(|(key, value)| current.get(*key) != value.as_ref())
+            .map(|(key, value)| {
+                ClientQuotaValue {
+                    entity: entity.clone(),
+                    key: key.clone(),
+                    value: value.unwrap_or_default(),
+                    remove: value.is_none(),
+                }
+                .record()
+            })
+            .collect();
+        if !validate_only {
+            self.quorum.append(records)?;
+        }
+        Ok(())
+    }
+
+    /// Starts moving a partition to the `target` replicas, or with `None`
+    /// cancels its reassignment. Until the new replicas have caught up the
+    /// partition keeps its old ones too; the rest is left to `check`, which
+    /// completes the reassignment once they are all in the ISR.
+    pub fn alter_reassignment(
+        &self,
+        topic_name: &str,
+        partition_index: i32,
+        target: Option<&[i32]>,
+    ) -> Result<(), ErrorCode> {
+        let _changes = self.lock_changes();
+        let metadata = self.metadata()?;
+        let p = metadata
+            .topic_by_name(topic_name)
+            .and_then(|t| metadata.partition(&t.topic_id, partition_index))
+            .ok_or(ErrorCode::UnknownTopicOrPartition)?;
+        // A reassignment replacing another starts over from the replicas
+        // the partition had before either.
+        let original = p.original_replicas();
+        let (replicas, adding, removing) = match target {
+            Some(target) => {
+                check_assignments(&metadata, &[target.to_vec()], None)?;
+                let target: Vec<u32> = target.iter().map(|&id| id as u32).collect();
+                let adding: Vec<u32> = target
+                    .iter()
+                    .copied()
+                    .filter(|r| !original.contains(r))
+                    .collect();
+                let removing: Vec<u32> = original
+                    .iter()
+                    .copied()
+                    .filter(|r| !target.contains(r))
+                    .collect();
+                (
+                    target.iter().chain(&removing).copied().collect(),
+                    adding,
+                    removing,
+                )
+            }
+            None if !p.is_reassigning() => return Err(ErrorCode::NoReassignmentInProgress),
+            None => (original, Vec::new(), Vec::new()),
+        };
+        // Replicas dropped from the partition leave its ISR and leadership.
+        let isr: Vec<u32> = p
+            .in_sync_replicas
+            .iter()
+            .copied()
+            .filter(|r| replicas.contains(r))
+            .collect();
+        let leader = if replicas.contains(&p.leader_id) {
+            None
+        } else {
+            Some(isr.first().map_or(-1, |&id| id as i32))
+        };
+        let change = PartitionChangeValue {
+            partition_id: p.partition_id,
+            topic_id: p.topic_id.clone(),
+            isr: (isr != p.in_sync_replicas).then_some(isr),
+            leader,
+            directories: directories_for(p, &replicas),
+            replicas: Some(replicas),
+            removing_replicas: Some(removing),
+            adding_replicas: Some(adding),
+        };
+        self.quorum.append(vec![change.record()])?;
+        info!(
+            topic = topic_name,
+            partition = partition_index,
+            replicas = ?change.replicas,
+            "altered partition reassignment"
+        );
+        Ok(())
+    }
+
+    /// Finalizes `level` of the feature `name`, or with 0 turns it off.
+    /// Every registered broker has to support the new level, and lowering
+    /// one takes `allow_downgrade`. metadata.version is never lowered: the
+    /// log already holds records only its current level can read.
+    pub fn update_feature(
+        &self,
+        name: &str,
+        level: i16,
+        allow_downgrade: bool,
+        validate_only: bool,
+    ) -> Result<(), ErrorCode> {
+        let Some(supported) = supported_feature(name) else {
+            return Err(ErrorCode::InvalidUpdateVersion);
+        };
+        if level != 0 && !(supported.min_level..=supported.max_level).contains(&level) {
+            return Err(ErrorCode::InvalidUpdateVersion);
+        }
+        let _changes = self.lock_changes();
+        let metadata = self.metadata()?;
+        let finalized = metadata.finalized_features();
+        let current = if name == METADATA_VERSION {
+            finalized.metadata_version()
+        } else {
+            finalized.level(name)
+        };
+        if level == current {
+            return Ok(());
+        }
+        if level < current && (!allow_downgrade || name == METADATA_VERSION) {
+            return Err(ErrorCode::InvalidUpdateVersion);
+        }
+        if let Some(broker) = metadata.brokers().find(|b| !b.supports(name, level)) {
+            warn!(
+                broker = broker.broker_id,
+                feature = name,
+                level,
+                "broker doesn't support the feature level"
+            );
+            return Err(ErrorCode::InvalidUpdateVersion);
+        }
+        if !validate_only {
+            let record = FeatureLevelValue {
+                name: name.to_string(),
+                level,
+            }
+            .record();
+            self.quorum.append(vec![record])?;
+            info!(
+                feature = name,
+                from = current,
+                to = level,
+                "updated feature level"
+            );
+        }
+        Ok(())
+    }
+
+    /// The metadata log, if this node is the active controller.
+    pub fn metadata(&self) -> Result<RecordBatches, ErrorCode> {
+        self.quorum.append(Vec::new())?;
+        RecordBatches::from_file(self.quorum.log_file()).map_err(|e| {
+            error!(error = %e, "failed to read the metadata log");
+            ErrorCode::UnknownServerError
+        })
+    }
+
+    /// Stops the periodic checks, waiting for one under way on the blocking
+    /// pool so that it appends nothing after this returns.
+    pub async fn shutdown(&self) {
+        self.stop.send_replace(true);
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut tick = tokio::time::interval(CHECK_INTERVAL);
+        let mut stop = self.stop.subscribe();
+        loop {
+            tokio::select! {
+                biased;
+                _ = stop.wait_for(|&stop| stop) => return,
+                _ = tick.tick() => {}
+            }
+            let controller = self.clone();
+            match tokio::task::spawn_blocking(move || controller.check()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!(error = %e, "controller check failed"),
+                Err(e) => error!(error = %e, "controller check panicked"),
+            }
+        }
+    }
+
+    /// Fences and unfences brokers by their heartbeats, then elects new
+    /// leaders for the partitions whose leader is fenced or gone and
+    /// completes the reassignments whose new replicas have caught up.
+    fn check(&self) -> Result<()> {
+        let _changes = self.lock_changes();
+        if self.quorum.append(Vec::new()).is_err() {
+            *self.active_since.lock().unwrap() = None;
+            return Ok(());
+        }
+        let now = Instant::now();
+        let active_since = *self.active_since.lock().unwrap().get_or_insert_with(|| {
+            // Heartbeats from an earlier term say nothing about now; every
+            // broker gets a full session to reach the new controller.
+            self.heartbeats.lock().unwrap().clear();
+            info!("now the active controller");
+            now
+        });
+        let metadata = RecordBatches::from_file(self.quorum.log_file())?;
+        let metadata_version = metadata.finalized_features().metadata_version();
+
+        let mut records = Vec::new();
+        let mut fenced = HashSet::new();
+        {
+            let heartbeats = self.heartbeats.lock().unwrap();
+            for broker in metadata.brokers() {
+                let heartbeat = heartbeats.get(&broker.broker_id);
+                let last = heartbeat.map_or(active_since, |h| h.at);
+                let expired = now - last > self.session_timeout;
+                let want_fence = heartbeat.is_some_and(|h| h.want_fence);
+                // A fenced broker only comes back by heartbeating.
+                let fence = expired || want_fence || (broker.fenced && heartbeat.is_none());
+                if fence {
+                    fenced.insert(broker.broker_id);
+                }
+                if fence == broker.fenced {
+                    continue;
+                }
+                if fence {
+                    warn!(broker = broker.broker_id, expired, "fencing broker");
+                } else {
+                    info!(broker = broker.broker_id, "unfencing broker");
+                }
+                let change = BrokerChangeValue {
+                    broker_id: broker.broker_id,
+                    broker_epoch: broker.broker_epoch,
+                    fenced: Some(fence),
+                };
+                records.push(change.record(metadata_version));
+            }
+        }
+
+        let registered: HashSet<i32> = metadata.brokers().map(|b| b.broker_id).collect();
+        for topic in metadata.topics() {
+            let topic_name = topic.topic_name.0.as_deref().unwrap_or_default();
+            for p in metadata.partitions(&topic.topic_id) {
+                // A reassignment waits for the election to be read back, so
+                // the two changes are never made from the same state.
+                if let Some(change) = elect(p, &registered, &fenced) {
+                    info!(
+                        topic = topic_name,
+                        partition = p.partition_id,
+                        leader = ?change.leader,
+                        isr = ?change.isr,
+                        "changing partition leadership"
+                    );
+                    records.push(change.record());
+                } else if let Some(change) = complete_reassignment(p, &registered, &fenced) {
+                    info!(
+                        topic = topic_name,
+                        partition = p.partition_id,
+                        replicas = ?change.replicas,
+                        leader = ?change.leader,
+                        "completing partition reassignment"
+                    );
+                    records.push(change.record());
+                }
+            }
+        }
+
+        if !records.is_empty() {
+            if let Err(error_code) = self.quorum.append(records) {
+                warn!(error = ?error_code, "failed to append controller changes");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The change that takes `fenced` brokers out of a partition's ISR and, if
+/// its leader is fenced or there is none, makes the first live replica left
+/// in the ISR the leader. The last member of an ISR stays in it, so the
+/// partition can come back when that broker does. Brokers with no
+/// registration are left alone.
+fn elect(
+    p: &PartitionValue,
+    registered: &HashSet<i32>,
+    fenced: &HashSet<i32>,
+) -> Option<PartitionChangeValue> {
+    let leader = p.leader_id as i32;
+    let isr: Vec<i32> = p.in_sync_replicas.iter().map(|&id| id as i32).collect();
+    let mut new_isr: Vec<i32> = isr
+        .iter()
+        .copied()
+        .filter(|id| !fenced.contains(id))
+        .collect();
+    if new_isr.is_empty() {
+        new_isr = isr.clone();
+    }
+    let live = |id: &i32| registered.contains(id) && !fenced.contains(id);
+    let new_leader = if leader >= 0 && !fenced.contains(&leader) {
+        leader
+    } else {
+        p.replicas
+            .iter()
+            .map(|&id| id as i32)
+            .find(|id| new_isr.contains(id) && live(id))
+            .unwrap_or(-1)
+    };
+    if new_leader == leader && new_isr == isr {
+        return None;
+    }
+    Some(PartitionChangeValue {
+        partition_id: p.partition_id,
+        topic_id: p.topic_id.clone(),
+        isr: (new_isr != isr).then(|| new_isr.iter().map(|&id| id as u32).collect()),
+        leader: (new_leader != leader).then_some(new_leader),
+        replicas: None,
+        removing_replicas: None,
+        adding_replicas: None,
+        directories: None,
+    })
+}
+
+/// Once every added replica is in the ISR, the change that drops the
+/// removed replicas from the partition and its ISR, moving leadership to a
+/// live target replica if the leader is being removed.
+fn complete_reassignment(
+    p: &PartitionValue,
+    registered: &HashSet<i32>,
+    fenced: &HashSet<i32>,
+) -> Option<PartitionChangeValue> {
+    if !p.is_reassigning()
+        || !p
+            .adding_replicas
+            .iter()
+            .all(|r| p.in_sync_replicas.contains(r))
+    {
+        return None;
+    }
+    let target = p.target_replicas();
+    let isr: Vec<u32> = p
+        .in_sync_replicas
+        .iter()
+        .copied()
+        .filter(|r| target.contains(r))
+        .collect();
+    if isr.is_empty() {
+        return None;
+    }
+    let live = |id: &u32| registered.contains(&(*id as i32)) && !fenced.contains(&(*id as i32));
+    let leader = if target.contains(&p.leader_id) {
+        None
+    } else {
+        // No live replica to lead yet; try again at the next check.
+        Some(*target.iter().find(|id| isr.contains(id) && live(id))? as i32)
+    };
+    Some(PartitionChangeValue {
+        partition_id: p.partition_id,
+        topic_id: p.topic_id.clone(),
+        isr: (isr != p.in_sync_replicas).then_some(isr),
+        leader,
+        directories: directories_for(p, &target),
+        replicas: Some(target),
+        removing_replicas: Some(Vec::new()),
+        adding_replicas: Some(Vec::new()),
+    })
+}
+
+/// The directories of `replicas` once they replace the partition's: each
+/// replica it already had keeps its directory and a new one has none yet.
+/// `None` for a partition written without directories.
+fn directories_for(p: &PartitionValue, replicas: &[u32]) -> Option<Vec<Uuid>> {
+    if p.directories.is_empty() {
+        return None;
+    }
+    let directories = replicas
+        .iter()
+        .map(|r| {
+            p.replicas
+                .iter()
+                .position(|old| old == r)
+                .and_then(|i| p.directories.get(i).cloned())
+                .unwrap_or_else(|| Uuid(UNASSIGNED_DIRECTORY.to_string()))
+        })
+        .collect();
+    Some(directories)
+}
+
+/// Kafka's rules: up to 249 ASCII letters, digits, '.', '_' and '-', and
+/// not "." or "..".
+pub fn is_valid_topic_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.len() <= MAX_TOPIC_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Replicas for `count` new partitions numbered from `first`, spread
+/// round-robin over the unfenced brokers so each partition's preferred
+/// leader is a different broker from the last.
+fn assign_replicas(
+    metadata: &RecordBatches,
+    first: i32,
+    count: i32,
+    replication_factor: i16,
+) -> Result<Vec<Vec<i32>>, ErrorCode> {
+    let brokers: Vec<i32> = metadata
+        .brokers()
+        .filter(|b| !b.fenced)
+        .map(|b| b.broker_id)
+        .collect();
+    if replication_factor <= 0 || replication_factor as usize > brokers.len() {
+        return Err(ErrorCode::InvalidReplicationFactor);
+    }
+    let assignments = (first..first + count)
+        .map(|p| {
+            (0..replication_factor as usize)
+                .map(|r| brokers[(p as usize + r) % brokers.len()])
+                .collect()
+        })
+        .collect();
+    Ok(assignments)
+}
+
+/// Explicit assignments have to name registered brokers, each at most once
+/// per partition, and give every partition the same number of replicas.
+fn check_assignments(
+    metadata: &RecordBatches,
+    assignments: &[Vec<i32>],
+    replication_factor: Option<i16>,
+) -> Result<(), ErrorCode> {
+    let registered: HashSet<i32> = metadata.brokers().map(|b| b.broker_id).collect();
+    let replication_factor =
+        replication_factor.map_or_else(|| assignments[0].len(), |rf| rf as usize);
+    for replicas in assignments {
+        let unique: HashSet<&i32> = replicas.iter().collect();
+        if replicas.is_empty()
+            || replicas.len() != replication_factor
+            || unique.len() != replicas.len()
+            || replicas.iter().any(|id| !registered.contains(id))
+        {
+            return Err(ErrorCode::InvalidReplicaAssignment);
+        }
+    }
+    Ok(())
+}
+
+/// PartitionRecords for new partitions numbered from `first`, each led by
+/// its first replica with every replica in sync, in the version
+/// `metadata_version` writes.
+pub fn partition_records(
+    topic_id: &Uuid,
+    first: u32,
+    assignments: &[Vec<i32>],
+    metadata_version: i16,
+) -> Vec<BatchRecord> {
+    assignments
+        .iter()
+        .zip(first..)
+        .map(|(replicas, partition_id)| {
+            let replicas: Vec<u32> = replicas.iter().map(|&id| id as u32).collect();
+            PartitionValue {
+                partition_id,
+                topic_id: topic_id.clone(),
+                in_sync_replicas: replicas.clone(),
+                removing_replicas: Vec::new(),
+                adding_replicas: Vec::new(),
+                leader_id: replicas[0],
+                leader_epoch: 0,
+                partition_epoch: 0,
+                directories: vec![Uuid(UNASSIGNED_DIRECTORY.to_string()); replicas.len()],
+                replicas,
+            }
+            .record(metadata_version)
+        })
+        .collect()
+}
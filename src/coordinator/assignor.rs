@@ -0,0 +1,172 @@
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet};
+
+/// Partitions by topic name.
+pub type Assignment = BTreeMap<String, BTreeSet<i32>>;
+
+/// What an assignor knows about a member.
+pub(super) struct MemberSpec<'a> {
+    pub(super) subscribed_topics: &'a BTreeSet<String>,
+    /// The member's share of the previous target assignment, for assignors
+    /// that try to keep partitions where they are.
+    pub(super) assignment: &'a Assignment,
+}
+
+/// A server-side assignor for consumer groups (KIP-848), chosen by name
+/// through the member's `server_assignor` or `group.consumer.assignors`.
+pub(super) trait PartitionAssignor: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Each member's target assignment, given the partition count of every
+    /// topic that exists. Subscribed topics that don't exist are skipped.
+    fn assign(
+        &self,
+        members: &BTreeMap<String, MemberSpec<'_>>,
+        topics: &BTreeMap<String, i32>,
+    ) -> BTreeMap<String, Assignment>;
+}
+
+/// The server-side assignors, by name.
+pub const SERVER_ASSIGNORS: &[&str] = &["uniform", "range"];
+
+/// The assignor registered as `name`.
+pub(super) fn assignor(name: &str) -> Option<&'static dyn PartitionAssignor> {
+    match name {
+        "uniform" => Some(&UniformAssignor),
+        "range" => Some(&RangeAssignor),
+        _ => None,
+    }
+}
+
+/// Spreads partitions so member counts differ by at most one where their
+/// subscriptions allow it, leaving partitions with their current owner
+/// unless that is what unbalances them.
+pub(super) struct UniformAssignor;
+
+impl PartitionAssignor for UniformAssignor {
+    fn name(&self) -> &'static str {
+        "uniform"
+    }
+
+    fn assign(
+        &self,
+        members: &BTreeMap<String, MemberSpec<'_>>,
+        topics: &BTreeMap<String, i32>,
+    ) -> BTreeMap<String, Assignment> {
+        let subscribers = |topic: &str| -> Vec<&String> {
+            members
+                .iter()
+                .filter(|(_, spec)| spec.subscribed_topics.contains(topic))
+                .map(|(member_id, _)| member_id)
+                .collect()
+        };
+
+        let mut owners: BTreeMap<(String, i32), &String> = BTreeMap::new();
+        let mut counts: BTreeMap<&String, usize> = members.keys().map(|m| (m, 0)).collect();
+        for (member_id, spec) in members {
+            for (topic, partitions) in spec.assignment {
+                let Some(&count) = topics.get(topic) else {
+                    continue;
+                };
+                if !spec.subscribed_topics.contains(topic) {
+                    continue;
+                }
+                for &partition in partitions.iter().filter(|&&p| p < count) {
+                    if let Entry::Vacant(entry) = owners.entry((topic.clone(), partition)) {
+                        entry.insert(member_id);
+                        *counts.entry(member_id).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        // New partitions go to the least loaded subscriber.
+        for (topic, &count) in topics {
+            let eligible = subscribers(topic);
+            for partition in 0..count {
+                let key = (topic.clone(), partition);
+                if owners.contains_key(&key) {
+                    continue;
+                }
+                let Some(&member_id) = eligible.iter().min_by_key(|m| (counts[*m], **m)) else {
+                    break;
+                };
+                owners.insert(key, member_id);
+                *counts.entry(member_id).or_default() += 1;
+            }
+        }
+
+        // Then move partitions off members holding two or more than a
+        // subscriber of the same topic, until none do.
+        loop {
+            let moved = owners.iter().find_map(|((topic, partition), owner)| {
+                let target = subscribers(topic)
+                    .into_iter()
+                    .filter(|m| counts[m] + 1 < counts[owner])
+                    .min_by_key(|m| (counts[m], *m))?;
+                Some(((topic.clone(), *partition), *owner, target))
+            });
+            let Some((key, from, to)) = moved else {
+                break;
+            };
+            *counts.entry(from).or_default() -= 1;
+            *counts.entry(to).or_default() += 1;
+            owners.insert(key, to);
+        }
+
+        let mut assignments: BTreeMap<String, Assignment> = members
+            .keys()
+            .map(|member_id| (member_id.clone(), Assignment::new()))
+            .collect();
+        for ((topic, partition), owner) in owners {
+            if let Some(assignment) = assignments.get_mut(owner) {
+                assignment.entry(topic).or_default().insert(partition);
+            }
+        }
+        assignments
+    }
+}
+
+/// Gives each subscriber of a topic a contiguous range of its partitions,
+/// the first `partitions % subscribers` of them, by member id, one extra.
+pub(super) struct RangeAssignor;
+
+impl PartitionAssignor for RangeAssignor {
+    fn name(&self) -> &'static str {
+        "range"
+    }
+
+    fn assign(
+        &self,
+        members: &BTreeMap<String, MemberSpec<'_>>,
+        topics: &BTreeMap<String, i32>,
+    ) -> BTreeMap<String, Assignment> {
+        let mut assignments: BTreeMap<String, Assignment> = members
+            .keys()
+            .map(|member_id| (member_id.clone(), Assignment::new()))
+            .collect();
+        for (topic, &count) in topics {
+            let subscribers: Vec<_> = members
+                .iter()
+                .filter(|(_, spec)| spec.subscribed_topics.contains(topic))
+                .map(|(member_id, _)| member_id)
+                .collect();
+            if subscribers.is_empty() {
+                continue;
+            }
+            let per_member = count / subscribers.len() as i32;
+            let extra = count % subscribers.len() as i32;
+            let mut start = 0;
+            for (i, member_id) in subscribers.into_iter().enumerate() {
+                let len = per_member + i32::from((i as i32) < extra);
+                if len > 0 {
+                    assignments
+                        .entry(member_id.clone())
+                        .or_default()
+                        .insert(topic.clone(), (start..start + len).collect());
+                }
+                start += len;
+            }
+        }
+        assignments
+    }
+}
@@ -0,0 +1,770 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use tokio::{sync::oneshot, time::Instant};
+use tracing::{debug, info, warn};
+
+use super::consumer_protocol::{MemberAssignment, Subscription, CONSUMER_PROTOCOL_TYPE};
+use super::offsets_topic::{GroupMetadata, MemberMetadata};
+use super::{GroupMember, GroupState, JoinGroup, JoinGroupResult, LeavingMember, SyncGroupResult};
+use crate::protocol::ErrorCode;
+
+pub(super) struct ClassicMember {
+    member_id: String,
+    group_instance_id: Option<String>,
+    client_id: String,
+    client_host: String,
+    session_timeout: Duration,
+    rebalance_timeout: Duration,
+    /// When the member is dropped unless it heartbeats, syncs or joins.
+    session_deadline: Instant,
+    protocol_type: String,
+    protocols: Vec<(String, Bytes)>,
+    assignment: Bytes,
+    /// Answered when the join phase completes.
+    awaiting_join: Option<oneshot::Sender<JoinGroupResult>>,
+    /// Answered when the leader's assignment arrives.
+    awaiting_sync: Option<oneshot::Sender<SyncGroupResult>>,
+}
+
+impl ClassicMember {
+    fn metadata(&self, protocol: &str) -> Bytes {
+        self.protocols
+            .iter()
+            .find(|(name, _)| name == protocol)
+            .map(|(_, metadata)| metadata.clone())
+            .unwrap_or_default()
+    }
+
+    fn supports(&self, protocol: &str) -> bool {
+        self.protocols.iter().any(|(name, _)| name == protocol)
+    }
+
+    /// A member with a JoinGroup or SyncGroup in flight is waiting on the
+    /// group, not the other way round, so its session can't expire.
+    fn is_awaiting(&self) -> bool {
+        self.awaiting_join.is_some() || self.awaiting_sync.is_some()
+    }
+}
+
+/// The wait before the first generation of an empty group completes, so
+/// members starting together land in one rebalance (KIP-134).
+struct InitialJoin {
+    /// Pushed back by `delay` each time a member joins, up to `limit`.
+    deadline: Instant,
+    limit: Instant,
+    delay: Duration,
+}
+
+/// A group using the classic protocol, where members rebalance together
+/// through JoinGroup and SyncGroup and the leader computes the assignment.
+pub(super) struct ClassicGroup {
+    pub(super) group_id: String,
+    pub(super) state: GroupState,
+    pub(super) generation_id: i32,
+    pub(super) protocol_type: Option<String>,
+    pub(super) protocol_name: Option<String>,
+    pub(super) leader_id: Option<String>,
+    pub(super) members: BTreeMap<String, ClassicMember>,
+    /// Ids handed out with MEMBER_ID_REQUIRED, until the member joins with
+    /// one or its session timeout passes.
+    pub(super) pending_members: HashMap<String, Instant>,
+    /// When the rebalance in progress stops waiting for members to join or
+    /// for the leader to sync.
+    rebalance_deadline: Option<Instant>,
+    initial_join: Option<InitialJoin>,
+    /// When the group last lost its last member, for offset retention.
+    empty_since: Option<SystemTime>,
+    /// Whether the group's metadata changed since it was last written to
+    /// the offsets topic.
+    unstored: bool,
+    /// Whether the offsets topic holds the group's metadata.
+    stored: bool,
+    /// When the rebalance in progress started.
+    rebalance_started: Option<Instant>,
+    /// How long the last rebalance took, until it is reported.
+    completed_rebalance: Option<Duration>,
+}
+
+impl ClassicGroup {
+    pub(super) fn new(group_id: String) -> Self {
+        Self {
+            group_id,
+            state: GroupState::Empty,
+            generation_id: 0,
+            protocol_type: None,
+            protocol_name: None,
+            leader_id: None,
+            members: BTreeMap::new(),
+            pending_members: HashMap::new(),
+            rebalance_deadline: None,
+            initial_join: None,
+            empty_since: None,
+            unstored: false,
+            stored: false,
+            rebalance_started: None,
+            completed_rebalance: None,
+        }
+    }
+
+    /// Rebuilds a group from the metadata it last stored. Its members carry
+    /// on in that generation, each with a fresh session to heartbeat in.
+    pub(super) fn restore(group_id: String, metadata: GroupMetadata, now: Instant) -> Self {
+        let protocol_type = metadata.protocol_type.clone().unwrap_or_default();
+        let protocol_name = metadata.protocol_name.clone().unwrap_or_default();
+        let members: BTreeMap<_, _> = metadata
+            .members
+            .into_iter()
+            .map(|member| {
+                let restored = ClassicMember {
+                    member_id: member.member_id.clone(),
+                    group_instance_id: member.group_instance_id,
+                    client_id: member.client_id,
+                    client_host: member.client_host,
+                    session_timeout: member.session_timeout,
+                    rebalance_timeout: member.rebalance_timeout,
+                    session_deadline: now + member.session_timeout,
+                    protocol_type: protocol_type.clone(),
+                    protocols: vec![(protocol_name.clone(), member.subscription)],
+                    assignment: member.assignment,
+                    awaiting_join: None,
+                    awaiting_sync: None,
+                };
+                (member.member_id, restored)
+            })
+            .collect();
+        let state = if members.is_empty() {
+            GroupState::Empty
+        } else {
+            GroupState::Stable
+        };
+        Self {
+            group_id,
+            state,
+            generation_id: metadata.generation_id,
+            protocol_type: metadata.protocol_type,
+            protocol_name: metadata.protocol_name,
+            leader_id: metadata.leader_id,
+            members,
+            pending_members: HashMap::new(),
+            rebalance_deadline: None,
+            initial_join: None,
+            empty_since: (state == GroupState::Empty).then_some(metadata.state_timestamp),
+            unstored: false,
+            stored: true,
+            rebalance_started: None,
+            completed_rebalance: None,
+        }
+    }
+
+    /// The group's metadata if it changed since it was last stored, which
+    /// is when a generation gets its assignment or the group empties.
+    pub(super) fn unstored_metadata(&mut self) -> Option<GroupMetadata> {
+        if !std::mem::take(&mut self.unstored) {
+            return None;
+        }
+        self.stored = true;
+        let protocol = self.protocol_name.clone().unwrap_or_default();
+        Some(GroupMetadata {
+            protocol_type: self.protocol_type.clone(),
+            generation_id: self.generation_id,
+            protocol_name: self.protocol_name.clone(),
+            leader_id: self.leader_id.clone(),
+            state_timestamp: self.empty_since.unwrap_or_else(SystemTime::now),
+            members: self
+                .members
+                .values()
+                .map(|member| MemberMetadata {
+                    member_id: member.member_id.clone(),
+                    group_instance_id: member.group_instance_id.clone(),
+                    client_id: member.client_id.clone(),
+                    client_host: member.client_host.clone(),
+                    rebalance_timeout: member.rebalance_timeout,
+                    session_timeout: member.session_timeout,
+                    subscription: member.metadata(&protocol),
+                    assignment: member.assignment.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    /// How long the last rebalance took, once, after it completes.
+    pub(super) fn take_completed_rebalance(&mut self) -> Option<Duration> {
+        self.completed_rebalance.take()
+    }
+
+    fn rebalance_completed(&mut self, now: Instant) {
+        if let Some(started) = self.rebalance_started.take() {
+            self.completed_rebalance = Some(now - started);
+        }
+    }
+
+    /// Whether the offsets topic holds the group's metadata, which has to be
+    /// deleted along with the group.
+    pub(super) fn is_stored(&self) -> bool {
+        self.stored
+    }
+
+    /// Whether the group has no members, nor any about to join.
+    pub(super) fn is_empty(&self) -> bool {
+        self.state == GroupState::Empty && self.pending_members.is_empty()
+    }
+
+    pub(super) fn transition(&mut self, state: GroupState) {
+        debug!(group = %self.group_id, from = %self.state, to = %state, "group state changed");
+        if state == GroupState::Empty && self.state != GroupState::Empty {
+            self.empty_since = Some(SystemTime::now());
+        }
+        self.state = state;
+    }
+
+    /// When the group became empty, if it ever had members.
+    pub(super) fn empty_since(&self) -> Option<SystemTime> {
+        self.empty_since
+    }
+
+    /// Every topic a member subscribes to, or `None` unless the members are
+    /// consumers whose subscriptions can be read.
+    pub(super) fn subscribed_topics(&self) -> Option<BTreeSet<String>> {
+        if self.is_empty() || self.protocol_type.as_deref() != Some(CONSUMER_PROTOCOL_TYPE) {
+            return None;
+        }
+        Some(
+            self.subscriptions()
+                .into_values()
+                .flat_map(|subscription| subscription.topics)
+                .collect(),
+        )
+    }
+
+    /// Whether a member speaking `protocol_type` with `protocols` can join:
+    /// it has to share a protocol with every current member.
+    pub(super) fn supports_protocols(
+        &self,
+        protocol_type: &str,
+        protocols: &[(String, Bytes)],
+    ) -> bool {
+        let Some(first) = self.members.values().next() else {
+            return true;
+        };
+        first.protocol_type == protocol_type
+            && protocols
+                .iter()
+                .any(|(name, _)| self.members.values().all(|m| m.supports(name)))
+    }
+
+    pub(super) fn join(
+        &mut self,
+        join: JoinGroup,
+        reply: oneshot::Sender<JoinGroupResult>,
+        now: Instant,
+        initial_rebalance_delay: Duration,
+    ) {
+        self.touch(&join.member_id, now);
+        let unchanged = self
+            .members
+            .get(&join.member_id)
+            .map(|member| member.protocols == join.protocols);
+        let is_leader = self.leader_id.as_ref() == Some(&join.member_id);
+        match (unchanged, self.state) {
+            // A member that missed its JoinGroup response: answer with the
+            // generation it already belongs to rather than rebalancing.
+            (Some(true), GroupState::CompletingRebalance) => {
+                let _ = reply.send(self.join_result(&join.member_id));
+                return;
+            }
+            (Some(true), GroupState::Stable) if !is_leader => {
+                let _ = reply.send(self.join_result(&join.member_id));
+                return;
+            }
+            _ => {}
+        }
+
+        if unchanged.is_none() {
+            info!(
+                group = %self.group_id,
+                member = %join.member_id,
+                client_id = %join.client_id,
+                client_host = %join.client_host,
+                "member joined group"
+            );
+        }
+        let member = self
+            .members
+            .entry(join.member_id.clone())
+            .or_insert_with(|| ClassicMember {
+                member_id: join.member_id.clone(),
+                group_instance_id: None,
+                client_id: String::new(),
+                client_host: String::new(),
+                session_timeout: Duration::ZERO,
+                rebalance_timeout: Duration::ZERO,
+                session_deadline: now,
+                protocol_type: String::new(),
+                protocols: Vec::new(),
+                assignment: Bytes::new(),
+                awaiting_join: None,
+                awaiting_sync: None,
+            });
+        member.group_instance_id = join.group_instance_id;
+        member.client_id = join.client_id;
+        member.client_host = join.client_host;
+        member.session_timeout = join.session_timeout;
+        member.rebalance_timeout = join.rebalance_timeout;
+        member.session_deadline = now + join.session_timeout;
+        member.protocol_type = join.protocol_type;
+        member.protocols = join.protocols;
+        member.awaiting_join = Some(reply);
+
+        if self.state == GroupState::Empty && !initial_rebalance_delay.is_zero() {
+            let limit = now + self.rebalance_timeout();
+            self.initial_join = Some(InitialJoin {
+                deadline: limit.min(now + initial_rebalance_delay),
+                limit,
+                delay: initial_rebalance_delay,
+            });
+        } else if let Some(initial) = self.initial_join.as_mut().filter(|_| unchanged.is_none()) {
+            initial.deadline = initial.limit.min(now + initial.delay);
+        }
+        if self.state != GroupState::PreparingRebalance {
+            self.prepare_rebalance(now);
+        }
+        self.try_complete_join(now);
+    }
+
+    /// Restarts a member's session timeout.
+    pub(super) fn touch(&mut self, member_id: &str, now: Instant) {
+        if let Some(member) = self.members.get_mut(member_id) {
+            member.session_deadline = now + member.session_timeout;
+        }
+    }
+
+    fn join_result(&self, member_id: &str) -> JoinGroupResult {
+        let protocol = self.protocol_name.clone().unwrap_or_default();
+        let is_leader = self.leader_id.as_deref() == Some(member_id);
+        let members = if is_leader {
+            self.members
+                .values()
+                .map(|member| GroupMember {
+                    member_id: member.member_id.clone(),
+                    group_instance_id: member.group_instance_id.clone(),
+                    metadata: member.metadata(&protocol),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        JoinGroupResult {
+            error_code: ErrorCode::None,
+            generation_id: self.generation_id,
+            protocol_type: self.protocol_type.clone(),
+            protocol_name: self.protocol_name.clone(),
+            leader: self.leader_id.clone().unwrap_or_default(),
+            member_id: member_id.to_string(),
+            members,
+        }
+    }
+
+    pub(super) fn sync_result(&self, member_id: &str) -> SyncGroupResult {
+        SyncGroupResult {
+            error_code: ErrorCode::None,
+            protocol_type: self.protocol_type.clone(),
+            protocol_name: self.protocol_name.clone(),
+            assignment: self
+                .members
+                .get(member_id)
+                .map(|member| member.assignment.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Starts a rebalance: every member has to join again within the longest
+    /// of their rebalance timeouts.
+    fn prepare_rebalance(&mut self, now: Instant) {
+        if self.state == GroupState::CompletingRebalance {
+            for member in self.members.values_mut() {
+                if let Some(reply) = member.awaiting_sync.take() {
+                    member.session_deadline = now + member.session_timeout;
+                    let _ = reply.send(SyncGroupResult::error(ErrorCode::RebalanceInProgress));
+                }
+            }
+        }
+        self.rebalance_deadline = Some(now + self.rebalance_timeout());
+        self.rebalance_started.get_or_insert(now);
+        info!(
+            group = %self.group_id,
+            generation = self.generation_id,
+            members = self.members.len(),
+            "preparing to rebalance group"
+        );
+        self.transition(GroupState::PreparingRebalance);
+    }
+
+    fn rebalance_timeout(&self) -> Duration {
+        self.members
+            .values()
+            .map(|member| member.rebalance_timeout)
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn try_complete_join(&mut self, now: Instant) {
+        if self.state == GroupState::PreparingRebalance
+            && self.initial_join.is_none()
+            && self.members.values().all(|m| m.awaiting_join.is_some())
+        {
+            self.complete_join(now);
+        }
+    }
+
+    /// Starts the next generation with the members that joined, and sends
+    /// the leader what it needs to assign partitions.
+    fn complete_join(&mut self, now: Instant) {
+        self.generation_id += 1;
+        self.initial_join = None;
+        if self.members.is_empty() {
+            self.protocol_name = None;
+            self.leader_id = None;
+            self.rebalance_deadline = None;
+            info!(group = %self.group_id, generation = self.generation_id, "group is empty");
+            self.transition(GroupState::Empty);
+            self.unstored = true;
+            self.rebalance_completed(now);
+            return;
+        }
+
+        self.protocol_type = self
+            .members
+            .values()
+            .next()
+            .map(|m| m.protocol_type.clone());
+        self.protocol_name = self.select_protocol();
+        if !self
+            .leader_id
+            .as_ref()
+            .is_some_and(|leader| self.members.contains_key(leader))
+        {
+            self.leader_id = self.members.keys().next().cloned();
+        }
+        self.rebalance_deadline = Some(now + self.rebalance_timeout());
+        let subscriptions = self.subscriptions();
+        for (member_id, subscription) in &subscriptions {
+            debug!(
+                group = %self.group_id,
+                member = %member_id,
+                topics = ?subscription.topics,
+                owned = ?subscription.owned_partitions,
+                owned_generation = subscription.generation_id,
+                "member subscription"
+            );
+        }
+        let topics: BTreeSet<_> = subscriptions
+            .values()
+            .flat_map(|subscription| subscription.topics.iter())
+            .collect();
+        info!(
+            group = %self.group_id,
+            generation = self.generation_id,
+            protocol = self.protocol_name.as_deref().unwrap_or_default(),
+            leader = self.leader_id.as_deref().unwrap_or_default(),
+            members = self.members.len(),
+            topics = ?topics,
+            "group joined; awaiting assignment"
+        );
+        self.transition(GroupState::CompletingRebalance);
+
+        let waiting: Vec<_> = self
+            .members
+            .values_mut()
+            .filter_map(|m| {
+                let reply = m.awaiting_join.take()?;
+                // Sessions restart from the response, not the request.
+                m.session_deadline = now + m.session_timeout;
+                Some((m.member_id.clone(), reply))
+            })
+            .collect();
+        for (member_id, reply) in waiting {
+            let _ = reply.send(self.join_result(&member_id));
+        }
+    }
+
+    /// Each member's subscription under the selected protocol, when the
+    /// members are consumers. The metadata itself is handed to the leader as
+    /// it came, so cooperative assignors see the owned partitions and user
+    /// data exactly as members sent them.
+    fn subscriptions(&self) -> BTreeMap<&str, Subscription> {
+        if self.protocol_type.as_deref() != Some(CONSUMER_PROTOCOL_TYPE) {
+            return BTreeMap::new();
+        }
+        let protocol = self.protocol_name.clone().unwrap_or_default();
+        self.members
+            .values()
+            .filter_map(
+                |member| match Subscription::decode(member.metadata(&protocol)) {
+                    Ok(subscription) => Some((member.member_id.as_str(), subscription)),
+                    Err(e) => {
+                        warn!(
+                            group = %self.group_id,
+                            member = %member.member_id,
+                            error = %e,
+                            "unreadable consumer subscription"
+                        );
+                        None
+                    }
+                },
+            )
+            .collect()
+    }
+
+    /// Checks a consumer group's assignment for partitions given to more
+    /// than one member. Cooperative assignors revoke a partition in one
+    /// generation and hand it out in the next, so one that moves straight
+    /// from its owner to another member is logged too.
+    fn check_assignment(&self) {
+        if self.protocol_type.as_deref() != Some(CONSUMER_PROTOCOL_TYPE) {
+            return;
+        }
+        let mut owned: HashMap<(String, i32), &str> = HashMap::new();
+        for (member_id, subscription) in self.subscriptions() {
+            for (topic, partitions) in subscription.owned_partitions {
+                for partition in partitions {
+                    owned.insert((topic.clone(), partition), member_id);
+                }
+            }
+        }
+        let mut assigned: HashMap<(String, i32), &str> = HashMap::new();
+        for member in self.members.values() {
+            let assignment = match MemberAssignment::decode(member.assignment.clone()) {
+                Ok(assignment) => assignment,
+                Err(e) => {
+                    warn!(
+                        group = %self.group_id,
+                        member = %member.member_id,
+                        error = %e,
+                        "unreadable consumer assignment"
+                    );
+                    continue;
+                }
+            };
+            debug!(
+                group = %self.group_id,
+                member = %member.member_id,
+                partitions = ?assignment.partitions,
+                "member assignment"
+            );
+            for (topic, partitions) in assignment.partitions {
+                for partition in partitions {
+                    let key = (topic.clone(), partition);
+                    if let Some(other) = assigned.insert(key.clone(), &member.member_id) {
+                        warn!(
+                            group = %self.group_id,
+                            topic = %topic,
+                            partition,
+                            members = ?[other, member.member_id.as_str()],
+                            "partition assigned to more than one member"
+                        );
+                    }
+                    if let Some(owner) = owned.get(&key).filter(|&&o| o != member.member_id) {
+                        debug!(
+                            group = %self.group_id,
+                            topic = %topic,
+                            partition,
+                            from = %owner,
+                            to = %member.member_id,
+                            "partition moved without being revoked first"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// The protocol every member supports that most members prefer, ties
+    /// going to the one listed first by the first member.
+    fn select_protocol(&self) -> Option<String> {
+        let first = self.members.values().next()?;
+        let candidates: Vec<&str> = first
+            .protocols
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| self.members.values().all(|m| m.supports(name)))
+            .collect();
+        let mut votes = vec![0; candidates.len()];
+        for member in self.members.values() {
+            let vote = member
+                .protocols
+                .iter()
+                .find_map(|(name, _)| candidates.iter().position(|c| c == name));
+            if let Some(i) = vote {
+                votes[i] += 1;
+            }
+        }
+        let max = votes.iter().copied().max()?;
+        let winner = votes.iter().position(|&v| v == max)?;
+        Some(candidates[winner].to_string())
+    }
+
+    /// Holds a member's SyncGroup until the leader's assignment arrives.
+    pub(super) fn await_sync(&mut self, member_id: &str, reply: oneshot::Sender<SyncGroupResult>) {
+        if let Some(member) = self.members.get_mut(member_id) {
+            member.awaiting_sync = Some(reply);
+        }
+    }
+
+    /// Takes the leader's assignment and hands every waiting member its share.
+    pub(super) fn assign(&mut self, assignments: Vec<(String, Bytes)>, now: Instant) {
+        let mut assignments: HashMap<_, _> = assignments.into_iter().collect();
+        for member in self.members.values_mut() {
+            member.assignment = assignments.remove(&member.member_id).unwrap_or_default();
+        }
+        self.rebalance_deadline = None;
+        self.check_assignment();
+        info!(group = %self.group_id, generation = self.generation_id, "group is stable");
+        self.transition(GroupState::Stable);
+        self.unstored = true;
+        self.rebalance_completed(now);
+
+        let waiting: Vec<_> = self
+            .members
+            .values_mut()
+            .filter_map(|m| {
+                let reply = m.awaiting_sync.take()?;
+                m.session_deadline = now + m.session_timeout;
+                Some((m.member_id.clone(), reply))
+            })
+            .collect();
+        for (member_id, reply) in waiting {
+            let _ = reply.send(self.sync_result(&member_id));
+        }
+    }
+
+    pub(super) fn find_member(&self, leaving: &LeavingMember) -> Option<String> {
+        if !leaving.member_id.is_empty() {
+            return self
+                .members
+                .contains_key(&leaving.member_id)
+                .then(|| leaving.member_id.clone());
+        }
+        let instance_id = leaving.group_instance_id.as_ref()?;
+        self.members
+            .values()
+            .find(|m| m.group_instance_id.as_ref() == Some(instance_id))
+            .map(|m| m.member_id.clone())
+    }
+
+    /// Removes a member, failing any request of its still waiting.
+    pub(super) fn remove_member(&mut self, member_id: &str) {
+        let Some(member) = self.members.remove(member_id) else {
+            return;
+        };
+        if let Some(reply) = member.awaiting_join {
+            let _ = reply.send(JoinGroupResult::error(
+                member.member_id.clone(),
+                ErrorCode::UnknownMemberId,
+            ));
+        }
+        if let Some(reply) = member.awaiting_sync {
+            let _ = reply.send(SyncGroupResult::error(ErrorCode::UnknownMemberId));
+        }
+    }
+
+    /// Rebalances after members are removed, or finishes a rebalance that
+    /// was only waiting for them.
+    pub(super) fn members_removed(&mut self, now: Instant) {
+        match self.state {
+            GroupState::Stable | GroupState::CompletingRebalance => self.prepare_rebalance(now),
+            GroupState::PreparingRebalance => {}
+            GroupState::Empty | GroupState::Dead => return,
+        }
+        self.try_complete_join(now);
+    }
+
+    /// The next time the group has to act without being asked.
+    pub(super) fn next_deadline(&self) -> Option<Instant> {
+        self.rebalance_deadline
+            .into_iter()
+            .chain(self.initial_join.as_ref().map(|initial| initial.deadline))
+            .chain(self.pending_members.values().copied())
+            .chain(
+                self.members
+                    .values()
+                    .filter(|m| !m.is_awaiting())
+                    .map(|m| m.session_deadline),
+            )
+            .min()
+    }
+
+    /// Acts on every deadline that has passed: forgets pending members,
+    /// ends the initial delay or a rebalance that took too long, and drops
+    /// members whose session timed out.
+    pub(super) fn expire(&mut self, now: Instant) {
+        self.pending_members.retain(|_, deadline| *deadline > now);
+        if self
+            .initial_join
+            .as_ref()
+            .is_some_and(|initial| initial.deadline <= now)
+        {
+            self.initial_join = None;
+            self.try_complete_join(now);
+        }
+        if self
+            .rebalance_deadline
+            .is_some_and(|deadline| deadline <= now)
+        {
+            self.rebalance_timed_out(now);
+        }
+
+        let expired: Vec<_> = self
+            .members
+            .values()
+            .filter(|m| !m.is_awaiting() && m.session_deadline <= now)
+            .map(|m| m.member_id.clone())
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        for member_id in &expired {
+            info!(group = %self.group_id, member = %member_id, "member session expired");
+            self.remove_member(member_id);
+        }
+        self.members_removed(now);
+    }
+
+    /// Gives up on members that did not join, or sync, in time.
+    fn rebalance_timed_out(&mut self, now: Instant) {
+        let stragglers: Vec<_> = match self.state {
+            GroupState::PreparingRebalance => self
+                .members
+                .values()
+                .filter(|m| m.awaiting_join.is_none())
+                .map(|m| m.member_id.clone())
+                .collect(),
+            GroupState::CompletingRebalance => self
+                .members
+                .values()
+                .filter(|m| m.awaiting_sync.is_none())
+                .map(|m| m.member_id.clone())
+                .collect(),
+            _ => {
+                self.rebalance_deadline = None;
+                return;
+            }
+        };
+        warn!(
+            group = %self.group_id,
+            state = %self.state,
+            members = ?stragglers,
+            "rebalance timed out; removing members that did not respond"
+        );
+        for member_id in &stragglers {
+            self.remove_member(member_id);
+        }
+        match self.state {
+            GroupState::PreparingRebalance => self.complete_join(now),
+            _ => self.members_removed(now),
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, SystemTime},
+};
+
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use super::assignor::{assignor, Assignment, MemberSpec, UniformAssignor};
+use super::{ConsumerGroupHeartbeat, ConsumerGroupHeartbeatResult, GroupSettings};
+use crate::meta_properties::random_id;
+use crate::protocol::ErrorCode;
+
+pub(super) struct ConsumerMember {
+    member_id: String,
+    /// The epoch of the target assignment the member has caught up with.
+    member_epoch: i32,
+    /// The epoch before the last bump, which a heartbeat whose response was
+    /// lost may still carry.
+    previous_member_epoch: i32,
+    rebalance_timeout: Duration,
+    /// When the member is dropped unless it heartbeats.
+    session_deadline: Instant,
+    /// When the member is dropped unless it has released `revoking`.
+    revocation_deadline: Option<Instant>,
+    subscribed_topics: BTreeSet<String>,
+    server_assignor: Option<String>,
+    /// What the member owns, as far as the coordinator has told it.
+    assigned: Assignment,
+    /// Partitions taken from the member that it has not confirmed giving up.
+    /// Nobody else gets them until it does.
+    revoking: Assignment,
+}
+
+impl ConsumerMember {
+    fn new(member_id: String, now: Instant) -> Self {
+        Self {
+            member_id,
+            member_epoch: 0,
+            previous_member_epoch: 0,
+            rebalance_timeout: Duration::ZERO,
+            session_deadline: now,
+            revocation_deadline: None,
+            subscribed_topics: BTreeSet::new(),
+            server_assignor: None,
+            assigned: Assignment::new(),
+            revoking: Assignment::new(),
+        }
+    }
+}
+
+/// A group using the consumer protocol (KIP-848), where the coordinator
+/// computes the assignment and members converge on it one heartbeat at a
+/// time.
+pub(super) struct ConsumerGroup {
+    group_id: String,
+    /// Bumped whenever membership, subscriptions or subscribed topic
+    /// metadata change.
+    group_epoch: i32,
+    /// The group epoch `target` was computed at.
+    assignment_epoch: i32,
+    members: BTreeMap<String, ConsumerMember>,
+    /// Where each member's partitions should end up.
+    target: BTreeMap<String, Assignment>,
+    /// The partition count of each subscribed topic as of `group_epoch`.
+    subscribed_topics: BTreeMap<String, i32>,
+    /// When the group last lost its last member, for offset retention.
+    empty_since: Option<SystemTime>,
+    /// When the group epoch first moved past what every member has
+    /// reconciled to.
+    rebalance_started: Option<Instant>,
+    /// How long the last rebalance took, until it is reported.
+    completed_rebalance: Option<Duration>,
+}
+
+impl ConsumerGroup {
+    pub(super) fn new(group_id: String) -> Self {
+        Self {
+            group_id,
+            group_epoch: 0,
+            assignment_epoch: 0,
+            members: BTreeMap::new(),
+            target: BTreeMap::new(),
+            subscribed_topics: BTreeMap::new(),
+            empty_since: None,
+            rebalance_started: None,
+            completed_rebalance: None,
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// When the group became empty, if it ever had members.
+    pub(super) fn empty_since(&self) -> Option<SystemTime> {
+        self.empty_since
+    }
+
+    /// Every topic a member subscribes to.
+    pub(super) fn subscribed_topics(&self) -> BTreeSet<String> {
+        self.members
+            .values()
+            .flat_map(|member| member.subscribed_topics.iter().cloned())
+            .collect()
+    }
+
+    pub(super) fn member_count(&self) -> usize {
+        self.members.len()
+    }
+
+    /// The epoch of `member_id`, for checking offset commits.
+    pub(super) fn member_epoch(&self, member_id: &str) -> Option<i32> {
+        self.members.get(member_id).map(|m| m.member_epoch)
+    }
+
+    pub(super) fn heartbeat(
+        &mut self,
+        heartbeat: ConsumerGroupHeartbeat,
+        settings: &GroupSettings,
+        now: Instant,
+    ) -> ConsumerGroupHeartbeatResult {
+        let result = |member_id: String, member_epoch, assignment| ConsumerGroupHeartbeatResult {
+            error_code: ErrorCode::None,
+            error_message: None,
+            member_id,
+            member_epoch,
+            heartbeat_interval: settings.consumer_heartbeat_interval,
+            assignment,
+        };
+        let error = |error_code| {
+            ConsumerGroupHeartbeatResult::error(heartbeat.member_id.clone(), error_code, None)
+        };
+
+        if heartbeat.member_epoch < 0 {
+            if self.members.remove(&heartbeat.member_id).is_none() {
+                return error(ErrorCode::UnknownMemberId);
+            }
+            self.target.remove(&heartbeat.member_id);
+            info!(group = %self.group_id, member = %heartbeat.member_id, "member left group");
+            self.bump_group_epoch(now);
+            self.check_rebalance(now);
+            return result(heartbeat.member_id, heartbeat.member_epoch, None);
+        }
+
+        let mut changed = false;
+        let member_id = if heartbeat.member_epoch == 0 {
+            let member_id = if heartbeat.member_id.is_empty() {
+                random_id()
+            } else {
+                heartbeat.member_id.clone()
+            };
+            match self.members.get_mut(&member_id) {
+                // A member that rejoins has lost its partitions and starts
+                // over.
+                Some(member) => {
+                    member.member_epoch = 0;
+                    member.previous_member_epoch = 0;
+                    member.assigned.clear();
+                    member.revoking.clear();
+                    member.revocation_deadline = None;
+                }
+                None => {
+                    info!(
+                        group = %self.group_id,
+                        member = %member_id,
+                        client_id = %heartbeat.client_id,
+                        client_host = %heartbeat.client_host,
+                        "member joined group"
+                    );
+                    self.members.insert(
+                        member_id.clone(),
+                        ConsumerMember::new(member_id.clone(), now),
+                    );
+                    changed = true;
+                }
+            }
+            member_id
+        } else {
+            let Some(member) = self.members.get(&heartbeat.member_id) else {
+                return error(ErrorCode::UnknownMemberId);
+            };
+            // A member one epoch behind only missed our last response, as
+            // long as it doesn't claim partitions it was never given.
+            let lagging = heartbeat.member_epoch == member.previous_member_epoch
+                && heartbeat
+                    .owned_partitions
+                    .as_ref()
+                    .is_some_and(|owned| is_subset(owned, &member.assigned));
+            if heartbeat.member_epoch != member.member_epoch && !lagging {
+                return error(ErrorCode::FencedMemberEpoch);
+            }
+            heartbeat.member_id.clone()
+        };
+
+        let member = self.members.get_mut(&member_id).unwrap();
+        member.session_deadline = now + settings.consumer_session_timeout;
+        if let Some(rebalance_timeout) = heartbeat.rebalance_timeout {
+            member.rebalance_timeout = rebalance_timeout;
+        }
+        if let Some(names) = &heartbeat.subscribed_topic_names {
+            let subscribed: BTreeSet<_> = names.iter().cloned().collect();
+            changed |= subscribed != member.subscribed_topics;
+            member.subscribed_topics = subscribed;
+        }
+        if heartbeat.server_assignor.is_some()
+            && heartbeat.server_assignor != member.server_assignor
+        {
+            changed = true;
+            member.server_assignor = heartbeat.server_assignor.clone();
+        }
+        let subscribed_topics = self.subscribed_topic_metadata(&heartbeat.topics);
+        if subscribed_topics != self.subscribed_topics {
+            self.subscribed_topics = subscribed_topics;
+            changed = true;
+        }
+        if changed {
+            self.bump_group_epoch(now);
+        }
+        if self.group_epoch > self.assignment_epoch {
+            self.compute_target(settings);
+        }
+
+        let reassigned = self.reconcile(&member_id, heartbeat.owned_partitions.as_ref(), now);
+        self.check_rebalance(now);
+        let member = &self.members[&member_id];
+        let send_assignment = reassigned
+            || heartbeat.member_epoch == 0
+            || heartbeat
+                .owned_partitions
+                .as_ref()
+                .is_some_and(|owned| *owned != member.assigned);
+        result(
+            member_id,
+            member.member_epoch,
+            send_assignment.then(|| member.assigned.clone()),
+        )
+    }
+
+    /// The next time a member's session or revocation runs out.
+    pub(super) fn next_deadline(&self) -> Option<Instant> {
+        self.members
+            .values()
+            .flat_map(|m| std::iter::once(m.session_deadline).chain(m.revocation_deadline))
+            .min()
+    }
+
+    /// Drops members that stopped heartbeating, or that held on to revoked
+    /// partitions past their rebalance timeout, so the rest of the group
+    /// can have their partitions.
+    pub(super) fn expire(&mut self, now: Instant) {
+        let expired: Vec<_> = self
+            .members
+            .values()
+            .filter(|m| {
+                m.session_deadline <= now || m.revocation_deadline.is_some_and(|d| d <= now)
+            })
+            .map(|m| m.member_id.clone())
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        for member_id in &expired {
+            if let Some(member) = self.members.remove(member_id) {
+                if member.session_deadline <= now {
+                    info!(group = %self.group_id, member = %member_id, "member session expired");
+                } else {
+                    warn!(
+                        group = %self.group_id,
+                        member = %member_id,
+                        "member did not revoke partitions within its rebalance timeout"
+                    );
+                }
+            }
+            self.target.remove(member_id);
+        }
+        self.bump_group_epoch(now);
+        self.check_rebalance(now);
+    }
+
+    /// `Assigning` until the target assignment catches up with the group
+    /// epoch, then `Reconciling` until every member has its target.
+    pub(super) fn state_name(&self) -> &'static str {
+        if self.members.is_empty() {
+            "Empty"
+        } else if self.assignment_epoch < self.group_epoch {
+            "Assigning"
+        } else if !self.is_reconciled() {
+            "Reconciling"
+        } else {
+            "Stable"
+        }
+    }
+
+    fn is_reconciled(&self) -> bool {
+        self.members.values().all(|member| {
+            member.member_epoch == self.assignment_epoch
+                && member.revoking.is_empty()
+                && self
+                    .target
+                    .get(&member.member_id)
+                    .map_or(member.assigned.is_empty(), |target| {
+                        *target == member.assigned
+                    })
+        })
+    }
+
+    /// How long the last rebalance took, once, after it completes.
+    pub(super) fn take_completed_rebalance(&mut self) -> Option<Duration> {
+        self.completed_rebalance.take()
+    }
+
+    /// Ends the rebalance in progress if the group has settled.
+    fn check_rebalance(&mut self, now: Instant) {
+        let settled = self.members.is_empty()
+            || (self.assignment_epoch == self.group_epoch && self.is_reconciled());
+        if settled {
+            if let Some(started) = self.rebalance_started.take() {
+                self.completed_rebalance = Some(now - started);
+            }
+        }
+    }
+
+    fn bump_group_epoch(&mut self, now: Instant) {
+        self.group_epoch += 1;
+        self.rebalance_started.get_or_insert(now);
+        if !self.members.is_empty() {
+            self.empty_since = None;
+        } else if self.empty_since.is_none() {
+            self.empty_since = Some(SystemTime::now());
+        }
+        debug!(group = %self.group_id, epoch = self.group_epoch, "group epoch bumped");
+    }
+
+    /// The partition count of every topic some member subscribes to, out of
+    /// `topics`.
+    fn subscribed_topic_metadata(&self, topics: &BTreeMap<String, i32>) -> BTreeMap<String, i32> {
+        self.members
+            .values()
+            .flat_map(|member| &member.subscribed_topics)
+            .filter_map(|topic| topics.get(topic).map(|&count| (topic.clone(), count)))
+            .collect()
+    }
+
+    /// Recomputes the target assignment with the assignor most members ask
+    /// for, or the first configured one if none ask.
+    fn compute_target(&mut self, settings: &GroupSettings) {
+        let mut votes: BTreeMap<&str, usize> = BTreeMap::new();
+        for member in self.members.values() {
+            if let Some(name) = &member.server_assignor {
+                *votes.entry(name.as_str()).or_default() += 1;
+            }
+        }
+        let name = votes
+            .iter()
+            .max_by_key(|(_, count)| **count)
+            .map(|(&name, _)| name)
+            .or_else(|| settings.consumer_assignors.first().map(String::as_str));
+        let assignor = name.and_then(assignor).unwrap_or(&UniformAssignor);
+
+        let empty = Assignment::new();
+        let specs: BTreeMap<_, _> = self
+            .members
+            .iter()
+            .map(|(member_id, member)| {
+                let spec = MemberSpec {
+                    subscribed_topics: &member.subscribed_topics,
+                    assignment: self.target.get(member_id).unwrap_or(&empty),
+                };
+                (member_id.clone(), spec)
+            })
+            .collect();
+        let target = assignor.assign(&specs, &self.subscribed_topics);
+        self.target = target;
+        self.assignment_epoch = self.group_epoch;
+        info!(
+            group = %self.group_id,
+            epoch = self.assignment_epoch,
+            assignor = %assignor.name(),
+            members = self.members.len(),
+            "computed target assignment"
+        );
+    }
+
+    /// Moves a member one step towards its target: partitions it must give
+    /// up are revoked first, and only once it confirms having released them
+    /// does it advance to the target epoch and get partitions that others
+    /// have released. Returns whether its assignment changed.
+    fn reconcile(&mut self, member_id: &str, owned: Option<&Assignment>, now: Instant) -> bool {
+        let target = self.target.get(member_id).cloned().unwrap_or_default();
+        let held_by_others = self
+            .members
+            .values()
+            .filter(|m| m.member_id != member_id)
+            .flat_map(|m| partitions(&m.assigned).chain(partitions(&m.revoking)))
+            .collect::<BTreeSet<_>>();
+        let assignment_epoch = self.assignment_epoch;
+        let Some(member) = self.members.get_mut(member_id) else {
+            return false;
+        };
+
+        if !member.revoking.is_empty() {
+            match owned {
+                Some(owned) if partitions(owned).all(|p| !contains(&member.revoking, &p)) => {
+                    member.revoking.clear();
+                    member.revocation_deadline = None;
+                }
+                _ => return false,
+            }
+        }
+
+        let revoked: Vec<_> = partitions(&member.assigned)
+            .filter(|p| !contains(&target, p))
+            .collect();
+        if !revoked.is_empty() {
+            for (topic, partition) in revoked {
+                remove(&mut member.assigned, &topic, partition);
+                member.revoking.entry(topic).or_default().insert(partition);
+            }
+            member.revocation_deadline = Some(now + member.rebalance_timeout);
+            return true;
+        }
+
+        let mut assigned = Assignment::new();
+        for (topic, partition) in partitions(&target) {
+            if !held_by_others.contains(&(topic.clone(), partition)) {
+                assigned.entry(topic).or_default().insert(partition);
+            }
+        }
+        if member.member_epoch != assignment_epoch {
+            member.previous_member_epoch = member.member_epoch;
+            member.member_epoch = assignment_epoch;
+        }
+        let changed = assigned != member.assigned;
+        member.assigned = assigned;
+        changed
+    }
+}
+
+fn partitions(assignment: &Assignment) -> impl Iterator<Item = (String, i32)> + '_ {
+    assignment
+        .iter()
+        .flat_map(|(topic, partitions)| partitions.iter().map(|&p| (topic.clone(), p)))
+}
+
+fn contains(assignment: &Assignment, (topic, partition): &(String, i32)) -> bool {
+    assignment
+        .get(topic)
+        .is_some_and(|partitions| partitions.contains(partition))
+}
+
+fn remove(assignment: &mut Assignment, topic: &str, partition: i32) {
+    if let Some(partitions) = assignment.get_mut(topic) {
+        partitions.remove(&partition);
+        if partitions.is_empty() {
+            assignment.remove(topic);
+        }
+    }
+}
+
+fn is_subset(owned: &Assignment, assigned: &Assignment) -> bool {
+    partitions(owned).all(|p| contains(assigned, &p))
+}
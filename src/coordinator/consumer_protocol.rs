@@ -0,0 +1,142 @@
+//! The metadata consumers embed in JoinGroup and SyncGroup when their
+//! protocol type is `consumer`. The coordinator forwards it untouched; it
+//! only reads it to know what members subscribe to and own.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{ensure, Result};
+use bytes::{Buf, Bytes};
+
+pub const CONSUMER_PROTOCOL_TYPE: &str = "consumer";
+
+/// A member's subscription, versions 0 to 3. Later versions are read as far
+/// as the fields known here, as the Java client does.
+#[derive(Debug, Default)]
+pub struct Subscription {
+    pub topics: Vec<String>,
+    pub user_data: Option<Bytes>,
+    /// The partitions the member still owns, which cooperative assignors
+    /// keep in place across a rebalance. Since v1.
+    pub owned_partitions: BTreeMap<String, BTreeSet<i32>>,
+    /// The generation `owned_partitions` were assigned in, -1 if unknown.
+    /// Since v2.
+    pub generation_id: i32,
+    /// Since v3.
+    pub rack_id: Option<String>,
+}
+
+impl Subscription {
+    pub fn decode(mut src: Bytes) -> Result<Self> {
+        let version = read_i16(&mut src)?;
+        ensure!(version >= 0, "invalid subscription version {}", version);
+        let topics = read_array(&mut src, read_string)?;
+        let user_data = read_nullable_bytes(&mut src)?;
+        let owned_partitions = if version >= 1 {
+            read_topic_partitions(&mut src)?
+        } else {
+            BTreeMap::new()
+        };
+        let generation_id = if version >= 2 {
+            read_i32(&mut src)?
+        } else {
+            -1
+        };
+        let rack_id = if version >= 3 {
+            read_nullable_string(&mut src)?
+        } else {
+            None
+        };
+        Ok(Self {
+            topics,
+            user_data,
+            owned_partitions,
+            generation_id,
+            rack_id,
+        })
+    }
+}
+
+/// What the leader handed a member in SyncGroup.
+#[derive(Debug, Default)]
+pub struct MemberAssignment {
+    pub partitions: BTreeMap<String, BTreeSet<i32>>,
+    pub user_data: Option<Bytes>,
+}
+
+impl MemberAssignment {
+    pub fn decode(mut src: Bytes) -> Result<Self> {
+        // An empty assignment, as members the leader left out get.
+        if src.is_empty() {
+            return Ok(Self::default());
+        }
+        let version = read_i16(&mut src)?;
+        ensure!(version >= 0, "invalid assignment version {}", version);
+        let partitions = read_topic_partitions(&mut src)?;
+        let user_data = read_nullable_bytes(&mut src)?;
+        Ok(Self {
+            partitions,
+            user_data,
+        })
+    }
+}
+
+fn read_i16(src: &mut Bytes) -> Result<i16> {
+    ensure!(src.remaining() >= 2, "truncated consumer protocol metadata");
+    Ok(src.get_i16())
+}
+
+fn read_i32(src: &mut Bytes) -> Result<i32> {
+    ensure!(src.remaining() >= 4, "truncated consumer protocol metadata");
+    Ok(src.get_i32())
+}
+
+fn read_string(src: &mut Bytes) -> Result<String> {
+    Ok(read_nullable_string(src)?.unwrap_or_default())
+}
+
+fn read_nullable_string(src: &mut Bytes) -> Result<Option<String>> {
+    let len = read_i16(src)?;
+    if len < 0 {
+        return Ok(None);
+    }
+    ensure!(
+        src.remaining() >= len as usize,
+        "truncated consumer protocol metadata"
+    );
+    Ok(Some(String::from_utf8(
+        src.split_to(len as usize).to_vec(),
+    )?))
+}
+
+fn read_nullable_bytes(src: &mut Bytes) -> Result<Option<Bytes>> {
+    let len = read_i32(src)?;
+    if len < 0 {
+        return Ok(None);
+    }
+    ensure!(
+        src.remaining() >= len as usize,
+        "truncated consumer protocol metadata"
+    );
+    Ok(Some(src.split_to(len as usize)))
+}
+
+fn read_array<T>(src: &mut Bytes, item: fn(&mut Bytes) -> Result<T>) -> Result<Vec<T>> {
+    let len = read_i32(src)?;
+    (0..len.max(0)).map(|_| item(src)).collect()
+}
+
+fn read_topic_partitions(src: &mut Bytes) -> Result<BTreeMap<String, BTreeSet<i32>>> {
+    let topics = read_array(src, |src| {
+        let topic = read_string(src)?;
+        let partitions = read_array(src, read_i32)?;
+        Ok((topic, partitions))
+    })?;
+    let mut partitions: BTreeMap<String, BTreeSet<i32>> = BTreeMap::new();
+    for (topic, topic_partitions) in topics {
+        partitions
+            .entry(topic)
+            .or_default()
+            .extend(topic_partitions);
+    }
+    Ok(partitions)
+}
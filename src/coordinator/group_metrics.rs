@@ -0,0 +1,85 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use crate::metrics::Metrics;
+
+pub const GROUP_STATE_METRIC: &str = "kafka_coordinator_group_state";
+pub const GROUP_MEMBERS_METRIC: &str = "kafka_coordinator_group_members";
+pub const GROUP_REBALANCES_METRIC: &str = "kafka_coordinator_group_rebalances_total";
+pub const GROUP_REBALANCE_LATENCY_METRIC: &str =
+    "kafka_coordinator_group_rebalance_latency_seconds";
+pub const GROUP_OFFSET_COMMITS_METRIC: &str = "kafka_coordinator_group_offset_commits_total";
+pub const GROUP_MAX_LAG_METRIC: &str = "kafka_coordinator_group_max_lag";
+
+/// Per-group metrics, labelled by group id.
+///
+/// A group's state is a gauge of 1 labelled with the state it is in, so the
+/// series for the state it left has to be removed; this remembers which
+/// one that is.
+pub(super) struct GroupMetrics {
+    metrics: Arc<Metrics>,
+    states: HashMap<String, &'static str>,
+}
+
+impl GroupMetrics {
+    pub(super) fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            metrics,
+            states: HashMap::new(),
+        }
+    }
+
+    pub(super) fn update(&mut self, group_id: &str, state: &'static str, members: usize) {
+        if let Some(previous) = self.states.insert(group_id.to_string(), state) {
+            if previous != state {
+                self.metrics.remove_gauge(
+                    GROUP_STATE_METRIC,
+                    &[("group", group_id), ("state", previous)],
+                );
+            }
+        }
+        self.metrics.set_gauge(
+            GROUP_STATE_METRIC,
+            &[("group", group_id), ("state", state)],
+            1,
+        );
+        self.metrics
+            .set_gauge(GROUP_MEMBERS_METRIC, &[("group", group_id)], members as i64);
+    }
+
+    pub(super) fn rebalance_completed(&self, group_id: &str, latency: Duration) {
+        let labels = [("group", group_id)];
+        self.metrics
+            .incr_counter(GROUP_REBALANCES_METRIC, &labels, 1);
+        self.metrics.observe_histogram(
+            GROUP_REBALANCE_LATENCY_METRIC,
+            &labels,
+            latency.as_secs_f64(),
+        );
+    }
+
+    pub(super) fn offsets_committed(&self, group_id: &str, count: usize) {
+        self.metrics.incr_counter(
+            GROUP_OFFSET_COMMITS_METRIC,
+            &[("group", group_id)],
+            count as u64,
+        );
+    }
+
+    pub(super) fn max_lag(&self, group_id: &str, lag: i64) {
+        self.metrics
+            .set_gauge(GROUP_MAX_LAG_METRIC, &[("group", group_id)], lag);
+    }
+
+    /// Drops the gauges of a group that no longer exists. Its counters stay,
+    /// as counters do.
+    pub(super) fn remove(&mut self, group_id: &str) {
+        let Some(state) = self.states.remove(group_id) else {
+            return;
+        };
+        let labels = [("group", group_id)];
+        self.metrics
+            .remove_gauge(GROUP_STATE_METRIC, &[("group", group_id), ("state", state)]);
+        self.metrics.remove_gauge(GROUP_MEMBERS_METRIC, &labels);
+        self.metrics.remove_gauge(GROUP_MAX_LAG_METRIC, &labels);
+    }
+}
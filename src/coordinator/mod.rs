@@ -0,0 +1,1425 @@
+mod assignor;
+mod classic;
+mod consumer;
+mod consumer_protocol;
+mod group_metrics;
+mod offsets_topic;
+mod share;
+mod transaction;
+mod transaction_log;
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{debug, info, warn};
+
+pub use assignor::{Assignment, SERVER_ASSIGNORS};
+use classic::ClassicGroup;
+use consumer::ConsumerGroup;
+pub use consumer_protocol::*;
+pub use group_metrics::*;
+pub use offsets_topic::OFFSETS_TOPIC;
+use offsets_topic::{LoadedGroups, OffsetsLog, OffsetsRecord};
+use share::ShareGroup;
+pub use transaction::{
+    MarkerWriter, TransactionCoordinator, MAX_TRANSACTION_TIMEOUT, NO_PRODUCER_ID,
+};
+pub use transaction_log::TRANSACTION_STATE_TOPIC;
+
+use crate::error::Error;
+use crate::log_manager::log_end_offset;
+use crate::meta_properties::random_id;
+use crate::metrics::Metrics;
+use crate::protocol::ErrorCode;
+
+/// Commands queued for the coordinator before callers block on sending.
+const COMMAND_QUEUE_SIZE: usize = 1024;
+
+/// How often each group's lag is measured against the partitions' log ends.
+const LAG_SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct GroupSettings {
+    /// The session timeouts members may ask for, from
+    /// `group.min.session.timeout.ms` and `group.max.session.timeout.ms`.
+    pub min_session_timeout: Duration,
+    pub max_session_timeout: Duration,
+    /// The longest metadata string an offset commit may carry, from
+    /// `offset.metadata.max.bytes`.
+    pub offset_metadata_max_bytes: usize,
+    /// The server-side assignors consumer groups may use, the first being
+    /// the default, from `group.consumer.assignors`.
+    pub consumer_assignors: Vec<String>,
+    /// How often consumer group members heartbeat, from
+    /// `group.consumer.heartbeat.interval.ms`.
+    pub consumer_heartbeat_interval: Duration,
+    /// How long a consumer group member may go without a heartbeat, from
+    /// `group.consumer.session.timeout.ms`.
+    pub consumer_session_timeout: Duration,
+    /// How often share group members heartbeat, from
+    /// `group.share.heartbeat.interval.ms`.
+    pub share_heartbeat_interval: Duration,
+    /// How long a share group member may go without a heartbeat, from
+    /// `group.share.session.timeout.ms`.
+    pub share_session_timeout: Duration,
+    /// How long a share group member holds the records it fetched before
+    /// they go to someone else, from `group.share.record.lock.duration.ms`.
+    pub share_record_lock_duration: Duration,
+    /// How many times a record is delivered before it is given up on, from
+    /// `group.share.delivery.attempt.limit`.
+    pub share_delivery_attempt_limit: i16,
+    /// How many records of a share partition may be in flight at once, from
+    /// `group.share.partition.max.record.locks`.
+    pub share_partition_max_record_locks: usize,
+    /// How long the first rebalance of an empty classic group waits for
+    /// more members, from `group.initial.rebalance.delay.ms`.
+    pub initial_rebalance_delay: Duration,
+    /// How long committed offsets are kept once nothing uses them, from
+    /// `offsets.retention.minutes`.
+    pub offsets_retention: Duration,
+    /// How often expired offsets are looked for, from
+    /// `offsets.retention.check.interval.ms`.
+    pub offsets_retention_check_interval: Duration,
+}
+
+impl Default for GroupSettings {
+    fn default() -> Self {
+        Self {
+            min_session_timeout: Duration::from_millis(6_000),
+            max_session_timeout: Duration::from_millis(1_800_000),
+            offset_metadata_max_bytes: 4096,
+            consumer_assignors: SERVER_ASSIGNORS.iter().map(|s| s.to_string()).collect(),
+            consumer_heartbeat_interval: Duration::from_millis(5_000),
+            consumer_session_timeout: Duration::from_millis(45_000),
+            share_heartbeat_interval: Duration::from_millis(5_000),
+            share_session_timeout: Duration::from_millis(45_000),
+            share_record_lock_duration: Duration::from_millis(30_000),
+            share_delivery_attempt_limit: 5,
+            share_partition_max_record_locks: 200,
+            initial_rebalance_delay: Duration::from_millis(3_000),
+            offsets_retention: Duration::from_secs(7 * 24 * 60 * 60),
+            offsets_retention_check_interval: Duration::from_millis(600_000),
+        }
+    }
+}
+
+/// Where a classic consumer group is in its rebalance protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupState {
+    /// No members, though committed offsets may remain.
+    Empty,
+    /// Waiting for every member to send JoinGroup.
+    PreparingRebalance,
+    /// Waiting for the leader's assignment in SyncGroup.
+    CompletingRebalance,
+    Stable,
+    /// About to be removed.
+    Dead,
+}
+
+impl GroupState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupState::Empty => "Empty",
+            GroupState::PreparingRebalance => "PreparingRebalance",
+            GroupState::CompletingRebalance => "CompletingRebalance",
+            GroupState::Stable => "Stable",
+            GroupState::Dead => "Dead",
+        }
+    }
+}
+
+impl Display for GroupState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TopicPartition {
+    pub topic: String,
+    pub partition: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CommittedOffset {
+    pub offset: i64,
+    pub leader_epoch: i32,
+    pub metadata: Option<String>,
+    pub commit_timestamp: SystemTime,
+}
+
+pub struct JoinGroup {
+    pub group_id: String,
+    /// Empty on a member's first join; the coordinator hands out its id.
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub client_id: String,
+    pub client_host: String,
+    pub session_timeout: Duration,
+    pub rebalance_timeout: Duration,
+    pub protocol_type: String,
+    /// The assignment protocols the member supports with its metadata for
+    /// each, most preferred first.
+    pub protocols: Vec<(String, Bytes)>,
+}
+
+pub struct JoinGroupResult {
+    pub error_code: ErrorCode,
+    pub generation_id: i32,
+    pub protocol_type: Option<String>,
+    pub protocol_name: Option<String>,
+    pub leader: String,
+    pub member_id: String,
+    /// Every member with its metadata for the chosen protocol, for the leader
+    /// to compute the assignment from. Empty for everyone else.
+    pub members: Vec<GroupMember>,
+}
+
+impl JoinGroupResult {
+    pub fn error(member_id: String, error_code: ErrorCode) -> Self {
+        Self {
+            error_code,
+            generation_id: -1,
+            protocol_type: None,
+            protocol_name: None,
+            leader: String::new(),
+            member_id,
+            members: Vec::new(),
+        }
+    }
+}
+
+pub struct GroupMember {
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub metadata: Bytes,
+}
+
+pub struct SyncGroup {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    /// Checked against the group's when given.
+    pub protocol_type: Option<String>,
+    pub protocol_name: Option<String>,
+    /// Each member's assignment; only the leader sends any.
+    pub assignments: Vec<(String, Bytes)>,
+}
+
+pub struct SyncGroupResult {
+    pub error_code: ErrorCode,
+    pub protocol_type: Option<String>,
+    pub protocol_name: Option<String>,
+    pub assignment: Bytes,
+}
+
+impl SyncGroupResult {
+    pub fn error(error_code: ErrorCode) -> Self {
+        Self {
+            error_code,
+            protocol_type: None,
+            protocol_name: None,
+            assignment: Bytes::new(),
+        }
+    }
+}
+
+/// A member leaving, named by its member id or, for static members, its
+/// instance id.
+pub struct LeavingMember {
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+}
+
+pub struct OffsetCommit {
+    pub group_id: String,
+    /// -1 with an empty member id commits for a group that uses no group
+    /// management, which the group must then be empty for. Members of
+    /// consumer groups send their member epoch.
+    pub generation_id: i32,
+    pub member_id: String,
+    pub offsets: Vec<(TopicPartition, CommittedOffset)>,
+}
+
+pub struct OffsetFetch {
+    pub group_id: String,
+    /// Members of consumer groups send their member id and epoch, which are
+    /// checked like a commit's; admin clients send neither.
+    pub member_id: Option<String>,
+    pub member_epoch: i32,
+    /// `None` asks for every partition the group has committed.
+    pub partitions: Option<Vec<TopicPartition>>,
+}
+
+pub struct OffsetFetchResult {
+    pub error_code: ErrorCode,
+    /// Each partition asked for, with its committed offset if it has one.
+    pub offsets: Vec<(TopicPartition, Option<CommittedOffset>)>,
+}
+
+impl OffsetFetchResult {
+    pub fn error(error_code: ErrorCode) -> Self {
+        Self {
+            error_code,
+            offsets: Vec::new(),
+        }
+    }
+}
+
+pub struct OffsetDeleteResult {
+    pub error_code: ErrorCode,
+    /// Each partition asked for, with its own error.
+    pub partitions: Vec<(TopicPartition, ErrorCode)>,
+}
+
+impl OffsetDeleteResult {
+    pub fn error(error_code: ErrorCode) -> Self {
+        Self {
+            error_code,
+            partitions: Vec::new(),
+        }
+    }
+}
+
+pub struct ConsumerGroupHeartbeat {
+    pub group_id: String,
+    /// Empty on a member's first heartbeat; the coordinator hands out its id.
+    pub member_id: String,
+    /// 0 to join, -1 to leave, -2 for a static member leaving; otherwise the
+    /// epoch the member last heard.
+    pub member_epoch: i32,
+    pub instance_id: Option<String>,
+    pub client_id: String,
+    pub client_host: String,
+    /// `None` when unchanged since the last heartbeat.
+    pub rebalance_timeout: Option<Duration>,
+    pub subscribed_topic_names: Option<Vec<String>>,
+    pub server_assignor: Option<String>,
+    /// The partitions the member owns, sent when joining and after its
+    /// assignment changes.
+    pub owned_partitions: Option<Assignment>,
+    /// The partition count of every topic in the cluster.
+    pub topics: BTreeMap<String, i32>,
+}
+
+pub struct ConsumerGroupHeartbeatResult {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub member_id: String,
+    pub member_epoch: i32,
+    pub heartbeat_interval: Duration,
+    /// The member's partitions, when they changed.
+    pub assignment: Option<Assignment>,
+}
+
+impl ConsumerGroupHeartbeatResult {
+    pub fn error(member_id: String, error_code: ErrorCode, error_message: Option<String>) -> Self {
+        Self {
+            error_code,
+            error_message,
+            member_id,
+            member_epoch: -1,
+            heartbeat_interval: Duration::ZERO,
+            assignment: None,
+        }
+    }
+}
+
+pub struct ShareGroupHeartbeat {
+    pub group_id: String,
+    /// Empty on a member's first heartbeat; the coordinator hands out its id.
+    pub member_id: String,
+    /// 0 to join, -1 to leave; otherwise the epoch the member last heard.
+    pub member_epoch: i32,
+    pub client_id: String,
+    pub client_host: String,
+    /// `None` when unchanged since the last heartbeat.
+    pub subscribed_topic_names: Option<Vec<String>>,
+    /// The partition count of every topic in the cluster.
+    pub topics: BTreeMap<String, i32>,
+}
+
+enum Command {
+    Join(JoinGroup, oneshot::Sender<JoinGroupResult>),
+    Sync(SyncGroup, oneshot::Sender<SyncGroupResult>),
+    Heartbeat {
+        group_id: String,
+        member_id: String,
+        generation_id: i32,
+        reply: oneshot::Sender<ErrorCode>,
+    },
+    Leave {
+        group_id: String,
+        members: Vec<LeavingMember>,
+        reply: oneshot::Sender<Vec<ErrorCode>>,
+    },
+    CommitOffsets(OffsetCommit, oneshot::Sender<Vec<ErrorCode>>),
+    ConsumerHeartbeat(
+        ConsumerGroupHeartbeat,
+        oneshot::Sender<ConsumerGroupHeartbeatResult>,
+    ),
+    /// Answered like a consumer group heartbeat, which carries the same
+    /// fields.
+    ShareHeartbeat(
+        ShareGroupHeartbeat,
+        oneshot::Sender<ConsumerGroupHeartbeatResult>,
+    ),
+    FetchOffsets(OffsetFetch, oneshot::Sender<OffsetFetchResult>),
+    DeleteOffsets {
+        group_id: String,
+        partitions: Vec<TopicPartition>,
+        reply: oneshot::Sender<OffsetDeleteResult>,
+    },
+    /// Touches every group that committed offsets for the topics.
+    RemoveTopics(Vec<String>, oneshot::Sender<()>),
+}
+
+impl Command {
+    /// The one group the command is for, if it is for one.
+    fn group_id(&self) -> Option<&str> {
+        Some(match self {
+            Command::Join(join, _) => &join.group_id,
+            Command::Sync(sync, _) => &sync.group_id,
+            Command::Heartbeat { group_id, .. }
+            | Command::Leave { group_id, .. }
+            | Command::DeleteOffsets { group_id, .. } => group_id,
+            Command::CommitOffsets(commit, _) => &commit.group_id,
+            Command::FetchOffsets(fetch, _) => &fetch.group_id,
+            Command::ConsumerHeartbeat(heartbeat, _) => &heartbeat.group_id,
+            Command::ShareHeartbeat(heartbeat, _) => &heartbeat.group_id,
+            Command::RemoveTopics(..) => return None,
+        })
+    }
+}
+
+/// The consumer group coordinator, for both the classic rebalance protocol
+/// and the consumer protocol of KIP-848, and for the share groups of
+/// KIP-932.
+///
+/// Every group lives in one task that takes commands over a channel, so
+/// requests from any connection see group state change in a single order and
+/// rebalance deadlines fire without a request to drive them. This handle is
+/// cheap to clone. Its calls block until the group answers, which for
+/// JoinGroup and SyncGroup can be a whole rebalance, so they belong on the
+/// blocking pool, which is where handlers run.
+#[derive(Clone)]
+pub struct GroupCoordinator {
+    commands: mpsc::Sender<Command>,
+    stop: Arc<watch::Sender<bool>>,
+    task: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl GroupCoordinator {
+    /// Loads the groups and committed offsets kept under `log_dir` and
+    /// starts the coordinator task on the current runtime. Group lag is
+    /// measured against the partitions in `log_dir` too.
+    pub fn start(settings: GroupSettings, log_dir: &Path, metrics: Arc<Metrics>) -> Result<Self> {
+        let (log, loaded) = OffsetsLog::open(log_dir)?;
+        let (commands, rx) = mpsc::channel(COMMAND_QUEUE_SIZE);
+        let coordinator = Coordinator::new(settings, log, loaded, log_dir, metrics);
+        let (stop, stopped) = watch::channel(false);
+        let task = tokio::spawn(coordinator.run(rx, stopped));
+        Ok(Self {
+            commands,
+            stop: Arc::new(stop),
+            task: Arc::new(Mutex::new(Some(task))),
+        })
+    }
+
+    /// Stops the coordinator task once it is done with the command it is
+    /// on, so nothing is written to `__consumer_offsets` after this returns.
+    /// Calls made since are answered with a coordinator error.
+    pub async fn shutdown(&self) {
+        self.stop.send_replace(true);
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+    }
+
+    /// Adds or rejoins a member, answering once the group's join phase
+    /// completes.
+    pub fn join_group(&self, join: JoinGroup) -> JoinGroupResult {
+        let member_id = join.member_id.clone();
+        self.call(|reply| Command::Join(join, reply))
+            .unwrap_or_else(|e| JoinGroupResult::error(member_id, e.error_code()))
+    }
+
+    /// Hands out the leader's assignment, answering once the leader has sent
+    /// it.
+    pub fn sync_group(&self, sync: SyncGroup) -> SyncGroupResult {
+        self.call(|reply| Command::Sync(sync, reply))
+            .unwrap_or_else(|e| SyncGroupResult::error(e.error_code()))
+    }
+
+    pub fn heartbeat(&self, group_id: String, member_id: String, generation_id: i32) -> ErrorCode {
+        self.call(|reply| Command::Heartbeat {
+            group_id,
+            member_id,
+            generation_id,
+            reply,
+        })
+        .unwrap_or_else(|e| e.error_code())
+    }
+
+    /// Removes `members` from the group, with an error code for each.
+    pub fn leave_group(&self, group_id: String, members: Vec<LeavingMember>) -> Vec<ErrorCode> {
+        let count = members.len();
+        self.call(|reply| Command::Leave {
+            group_id,
+            members,
+            reply,
+        })
+        .unwrap_or_else(|e| vec![e.error_code(); count])
+    }
+
+    /// Stores the offsets, with an error code for each.
+    pub fn commit_offsets(&self, commit: OffsetCommit) -> Vec<ErrorCode> {
+        let count = commit.offsets.len();
+        self.call(|reply| Command::CommitOffsets(commit, reply))
+            .unwrap_or_else(|e| vec![e.error_code(); count])
+    }
+
+    /// Joins, leaves or heartbeats a consumer group member, with the
+    /// member's next step towards its target assignment.
+    pub fn consumer_group_heartbeat(
+        &self,
+        heartbeat: ConsumerGroupHeartbeat,
+    ) -> ConsumerGroupHeartbeatResult {
+        let member_id = heartbeat.member_id.clone();
+        self.call(|reply| Command::ConsumerHeartbeat(heartbeat, reply))
+            .unwrap_or_else(|e| {
+                ConsumerGroupHeartbeatResult::error(member_id, e.error_code(), None)
+            })
+    }
+
+    /// Joins, leaves or heartbeats a share group member, with the
+    /// partitions it shares when they changed.
+    pub fn share_group_heartbeat(
+        &self,
+        heartbeat: ShareGroupHeartbeat,
+    ) -> ConsumerGroupHeartbeatResult {
+        let member_id = heartbeat.member_id.clone();
+        self.call(|reply| Command::ShareHeartbeat(heartbeat, reply))
+            .unwrap_or_else(|e| {
+                ConsumerGroupHeartbeatResult::error(member_id, e.error_code(), None)
+            })
+    }
+
+    /// The group's committed offsets for the partitions asked for, or for
+    /// every partition it has committed.
+    pub fn fetch_offsets(&self, fetch: OffsetFetch) -> OffsetFetchResult {
+        self.call(|reply| Command::FetchOffsets(fetch, reply))
+            .unwrap_or_else(|e| OffsetFetchResult::error(e.error_code()))
+    }
+
+    /// Deletes the group's committed offsets for `partitions`, unless the
+    /// group is consuming them.
+    pub fn delete_offsets(
+        &self,
+        group_id: String,
+        partitions: Vec<TopicPartition>,
+    ) -> OffsetDeleteResult {
+        self.call(|reply| Command::DeleteOffsets {
+            group_id,
+            partitions,
+            reply,
+        })
+        .unwrap_or_else(|e| OffsetDeleteResult::error(e.error_code()))
+    }
+
+    /// Deletes every group's committed offsets for `topics`, which have been
+    /// deleted, so that a topic created again under one of their names is
+    /// consumed from the start rather than from offsets into the old one.
+    pub fn remove_topics(&self, topics: Vec<String>) -> Result<(), Error> {
+        self.call(|reply| Command::RemoveTopics(topics, reply))
+    }
+
+    fn call<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> Result<T, Error> {
+        let (reply, rx) = oneshot::channel();
+        self.commands
+            .blocking_send(command(reply))
+            .map_err(|_| Error::Coordinator("stopped".to_string()))?;
+        rx.blocking_recv()
+            .map_err(|_| Error::Coordinator("dropped the request".to_string()))
+    }
+}
+
+/// The state owned by the coordinator task.
+struct Coordinator {
+    settings: GroupSettings,
+    /// A group id belongs to a classic group, a consumer group or a share
+    /// group, never more than one.
+    classic_groups: HashMap<String, ClassicGroup>,
+    consumer_groups: HashMap<String, ConsumerGroup>,
+    share_groups: HashMap<String, ShareGroup>,
+    offsets: HashMap<String, BTreeMap<TopicPartition, CommittedOffset>>,
+    /// Where every commit is written before it is acknowledged, and classic
+    /// groups' metadata as each generation settles.
+    log: OffsetsLog,
+    log_dir: PathBuf,
+    metrics: GroupMetrics,
+}
+
+impl Coordinator {
+    fn new(
+        settings: GroupSettings,
+        log: OffsetsLog,
+        loaded: LoadedGroups,
+        log_dir: &Path,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let now = Instant::now();
+        let mut classic_groups: HashMap<_, _> = loaded
+            .groups
+            .into_iter()
+            .map(|(group_id, metadata)| {
+                let group = ClassicGroup::restore(group_id.clone(), metadata, now);
+                info!(
+                    group = %group_id,
+                    state = %group.state,
+                    generation = group.generation_id,
+                    members = group.members.len(),
+                    "restored group"
+                );
+                (group_id, group)
+            })
+            .collect();
+        // A group known only from its offsets is empty until someone joins.
+        for group_id in loaded.offsets.keys() {
+            classic_groups
+                .entry(group_id.clone())
+                .or_insert_with(|| ClassicGroup::new(group_id.clone()));
+        }
+        let mut coordinator = Self {
+            settings,
+            classic_groups,
+            consumer_groups: HashMap::new(),
+            share_groups: HashMap::new(),
+            offsets: loaded.offsets,
+            log,
+            log_dir: log_dir.to_path_buf(),
+            metrics: GroupMetrics::new(metrics),
+        };
+        let group_ids: Vec<_> = coordinator.classic_groups.keys().cloned().collect();
+        for group_id in group_ids {
+            coordinator.report_group(&group_id);
+            coordinator.remove_if_dead(&group_id);
+        }
+        coordinator
+    }
+
+    async fn run(mut self, mut commands: mpsc::Receiver<Command>, mut stop: watch::Receiver<bool>) {
+        let period = self.settings.offsets_retention_check_interval;
+        let mut retention_check = tokio::time::interval_at(Instant::now() + period, period);
+        let mut lag_sample = tokio::time::interval(LAG_SAMPLE_INTERVAL);
+        while !*stop.borrow() {
+            let deadline = self.next_deadline();
+            tokio::select! {
+                _ = stop.wait_for(|&stop| stop) => return,
+                command = commands.recv() => match command {
+                    Some(command) => {
+                        let group_id = command.group_id().map(str::to_string);
+                        self.handle(command);
+                        if let Some(group_id) = group_id {
+                            self.store_group(&group_id);
+                            self.report_group(&group_id);
+                            self.remove_if_dead(&group_id);
+                        }
+                    }
+                    None => return,
+                },
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)),
+                    if deadline.is_some() =>
+                {
+                    self.expire(Instant::now());
+                }
+                _ = retention_check.tick() => self.expire_offsets(SystemTime::now()),
+                _ = lag_sample.tick() => self.sample_lag(),
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Join(join, reply) => self.join(join, reply),
+            Command::Sync(sync, reply) => self.sync(sync, reply),
+            Command::Heartbeat {
+                group_id,
+                member_id,
+                generation_id,
+                reply,
+            } => {
+                let _ = reply.send(self.heartbeat(&group_id, &member_id, generation_id));
+            }
+            Command::Leave {
+                group_id,
+                members,
+                reply,
+            } => {
+                let _ = reply.send(self.leave(&group_id, &members));
+            }
+            Command::CommitOffsets(commit, reply) => {
+                let _ = reply.send(self.commit_offsets(commit));
+            }
+            Command::ConsumerHeartbeat(heartbeat, reply) => {
+                let _ = reply.send(self.consumer_heartbeat(heartbeat));
+            }
+            Command::ShareHeartbeat(heartbeat, reply) => {
+                let _ = reply.send(self.share_heartbeat(heartbeat));
+            }
+            Command::FetchOffsets(fetch, reply) => {
+                let _ = reply.send(self.fetch_offsets(fetch));
+            }
+            Command::DeleteOffsets {
+                group_id,
+                partitions,
+                reply,
+            } => {
+                let _ = reply.send(self.delete_offsets(&group_id, partitions));
+            }
+            Command::RemoveTopics(topics, reply) => {
+                self.remove_topics(&topics);
+                let _ = reply.send(());
+            }
+        }
+    }
+
+    fn join(&mut self, join: JoinGroup, reply: oneshot::Sender<JoinGroupResult>) {
+        let error = |error_code| JoinGroupResult::error(join.member_id.clone(), error_code);
+        if join.group_id.is_empty() {
+            let _ = reply.send(error(ErrorCode::InvalidGroupId));
+            return;
+        }
+        if join.session_timeout < self.settings.min_session_timeout
+            || join.session_timeout > self.settings.max_session_timeout
+        {
+            let _ = reply.send(error(ErrorCode::InvalidSessionTimeout));
+            return;
+        }
+        if join.protocol_type.is_empty() || join.protocols.is_empty() {
+            let _ = reply.send(error(ErrorCode::InconsistentGroupProtocol));
+            return;
+        }
+        if self.consumer_groups.contains_key(&join.group_id)
+            || self.share_groups.contains_key(&join.group_id)
+        {
+            let _ = reply.send(error(ErrorCode::InconsistentGroupProtocol));
+            return;
+        }
+        let group = self
+            .classic_groups
+            .entry(join.group_id.clone())
+            .or_insert_with(|| ClassicGroup::new(join.group_id.clone()));
+        if !group.supports_protocols(&join.protocol_type, &join.protocols) {
+            let _ = reply.send(error(ErrorCode::InconsistentGroupProtocol));
+            return;
+        }
+
+        let now = Instant::now();
+        if join.member_id.is_empty() {
+            // KIP-394: a new member gets its id first and joins with it, so a
+            // client that times out mid-join doesn't leave a stray member.
+            let member_id = format!("{}-{}", join.client_id, random_id());
+            group
+                .pending_members
+                .insert(member_id.clone(), now + join.session_timeout);
+            let _ = reply.send(JoinGroupResult::error(
+                member_id,
+                ErrorCode::MemberIdRequired,
+            ));
+            return;
+        }
+        let pending = group.pending_members.remove(&join.member_id).is_some();
+        if !pending && !group.members.contains_key(&join.member_id) {
+            let _ = reply.send(error(ErrorCode::UnknownMemberId));
+            return;
+        }
+        group.join(join, reply, now, self.settings.initial_rebalance_delay);
+    }
+
+    fn sync(&mut self, sync: SyncGroup, reply: oneshot::Sender<SyncGroupResult>) {
+        let Some(group) = self.classic_groups.get_mut(&sync.group_id) else {
+            let _ = reply.send(SyncGroupResult::error(ErrorCode::UnknownMemberId));
+            return;
+        };
+        let error_code = if !group.members.contains_key(&sync.member_id) {
+            ErrorCode::UnknownMemberId
+        } else if sync.generation_id != group.generation_id {
+            ErrorCode::IllegalGeneration
+        } else if sync
+            .protocol_type
+            .as_ref()
+            .is_some_and(|t| Some(t) != group.protocol_type.as_ref())
+            || sync
+                .protocol_name
+                .as_ref()
+                .is_some_and(|n| Some(n) != group.protocol_name.as_ref())
+        {
+            ErrorCode::InconsistentGroupProtocol
+        } else {
+            ErrorCode::None
+        };
+        if !matches!(error_code, ErrorCode::None) {
+            let _ = reply.send(SyncGroupResult::error(error_code));
+            return;
+        }
+        group.touch(&sync.member_id, Instant::now());
+
+        match group.state {
+            GroupState::Empty | GroupState::Dead => {
+                let _ = reply.send(SyncGroupResult::error(ErrorCode::UnknownMemberId));
+            }
+            GroupState::PreparingRebalance => {
+                let _ = reply.send(SyncGroupResult::error(ErrorCode::RebalanceInProgress));
+            }
+            GroupState::CompletingRebalance => {
+                group.await_sync(&sync.member_id, reply);
+                if group.leader_id.as_ref() == Some(&sync.member_id) {
+                    group.assign(sync.assignments, Instant::now());
+                }
+            }
+            GroupState::Stable => {
+                let _ = reply.send(group.sync_result(&sync.member_id));
+            }
+        }
+    }
+
+    fn heartbeat(&mut self, group_id: &str, member_id: &str, generation_id: i32) -> ErrorCode {
+        let Some(group) = self.classic_groups.get_mut(group_id) else {
+            return ErrorCode::UnknownMemberId;
+        };
+        if !group.members.contains_key(member_id) {
+            return ErrorCode::UnknownMemberId;
+        }
+        if generation_id != group.generation_id {
+            return ErrorCode::IllegalGeneration;
+        }
+        group.touch(member_id, Instant::now());
+        match group.state {
+            GroupState::Empty | GroupState::Dead => ErrorCode::UnknownMemberId,
+            GroupState::PreparingRebalance => ErrorCode::RebalanceInProgress,
+            GroupState::CompletingRebalance | GroupState::Stable => ErrorCode::None,
+        }
+    }
+
+    fn leave(&mut self, group_id: &str, members: &[LeavingMember]) -> Vec<ErrorCode> {
+        let Some(group) = self.classic_groups.get_mut(group_id) else {
+            return vec![ErrorCode::UnknownMemberId; members.len()];
+        };
+        let results: Vec<_> = members
+            .iter()
+            .map(|leaving| match group.find_member(leaving) {
+                Some(member_id) => {
+                    info!(group = %group_id, member = %member_id, "member left group");
+                    group.remove_member(&member_id);
+                    ErrorCode::None
+                }
+                None => ErrorCode::UnknownMemberId,
+            })
+            .collect();
+        if results.iter().any(|e| matches!(e, ErrorCode::None)) {
+            group.members_removed(Instant::now());
+        }
+        results
+    }
+
+    fn commit_offsets(&mut self, commit: OffsetCommit) -> Vec<ErrorCode> {
+        let simple = commit.generation_id < 0 && commit.member_id.is_empty();
+        let error_code = if let Some(group) = self.consumer_groups.get(&commit.group_id) {
+            match group.member_epoch(&commit.member_id) {
+                _ if simple => ErrorCode::None,
+                None => ErrorCode::UnknownMemberId,
+                Some(epoch) if epoch != commit.generation_id => ErrorCode::StaleMemberEpoch,
+                Some(_) => ErrorCode::None,
+            }
+        } else {
+            match self.classic_groups.get(&commit.group_id) {
+                None if simple => ErrorCode::None,
+                None => ErrorCode::IllegalGeneration,
+                Some(group) if simple => match group.state {
+                    GroupState::Empty => ErrorCode::None,
+                    _ => ErrorCode::UnknownMemberId,
+                },
+                Some(group) => {
+                    if !group.members.contains_key(&commit.member_id) {
+                        ErrorCode::UnknownMemberId
+                    } else if commit.generation_id != group.generation_id {
+                        ErrorCode::IllegalGeneration
+                    } else if group.state == GroupState::CompletingRebalance {
+                        ErrorCode::RebalanceInProgress
+                    } else {
+                        ErrorCode::None
+                    }
+                }
+            }
+        };
+        if !matches!(error_code, ErrorCode::None) {
+            return vec![error_code; commit.offsets.len()];
+        }
+
+        if let Some(group) = self.classic_groups.get_mut(&commit.group_id) {
+            group.touch(&commit.member_id, Instant::now());
+        }
+        let max_metadata = self.settings.offset_metadata_max_bytes;
+        if !self.consumer_groups.contains_key(&commit.group_id) {
+            self.classic_groups
+                .entry(commit.group_id.clone())
+                .or_insert_with(|| ClassicGroup::new(commit.group_id.clone()));
+        }
+        let (valid, results): (Vec<_>, Vec<_>) = commit
+            .offsets
+            .into_iter()
+            .map(|(partition, offset)| {
+                if offset
+                    .metadata
+                    .as_ref()
+                    .is_some_and(|m| m.len() > max_metadata)
+                {
+                    (None, ErrorCode::OffsetMetadataTooLarge)
+                } else {
+                    (Some((partition, offset)), ErrorCode::None)
+                }
+            })
+            .unzip();
+        let records: Vec<_> = valid
+            .iter()
+            .flatten()
+            .map(|(partition, offset)| OffsetsRecord::OffsetCommit {
+                group_id: commit.group_id.clone(),
+                partition: partition.clone(),
+                offset: Some(offset.clone()),
+            })
+            .collect();
+        if let Err(e) = self.log.append(&records) {
+            warn!(group = %commit.group_id, error = %e, "failed to write committed offsets");
+            return results
+                .into_iter()
+                .map(|error_code| match error_code {
+                    ErrorCode::None => ErrorCode::CoordinatorNotAvailable,
+                    error_code => error_code,
+                })
+                .collect();
+        }
+
+        self.metrics
+            .offsets_committed(&commit.group_id, records.len());
+        let offsets = self.offsets.entry(commit.group_id.clone()).or_default();
+        for (partition, offset) in valid.into_iter().flatten() {
+            debug!(
+                group = %commit.group_id,
+                topic = %partition.topic,
+                partition = partition.partition,
+                offset = offset.offset,
+                "committed offset"
+            );
+            offsets.insert(partition, offset);
+        }
+        results
+    }
+
+    fn fetch_offsets(&self, fetch: OffsetFetch) -> OffsetFetchResult {
+        if let (Some(group), Some(member_id)) =
+            (self.consumer_groups.get(&fetch.group_id), &fetch.member_id)
+        {
+            match group.member_epoch(member_id) {
+                None => return OffsetFetchResult::error(ErrorCode::UnknownMemberId),
+                Some(epoch) if epoch != fetch.member_epoch => {
+                    return OffsetFetchResult::error(ErrorCode::StaleMemberEpoch);
+                }
+                Some(_) => {}
+            }
+        }
+        let offsets = self.offsets.get(&fetch.group_id);
+        let offsets = match fetch.partitions {
+            Some(partitions) => partitions
+                .into_iter()
+                .map(|partition| {
+                    let offset = offsets.and_then(|o| o.get(&partition)).cloned();
+                    (partition, offset)
+                })
+                .collect(),
+            None => offsets
+                .into_iter()
+                .flatten()
+                .map(|(partition, offset)| (partition.clone(), Some(offset.clone())))
+                .collect(),
+        };
+        OffsetFetchResult {
+            error_code: ErrorCode::None,
+            offsets,
+        }
+    }
+
+    /// Writes a tombstone for each committed offset deleted. Offsets of
+    /// topics a member subscribes to stay, and a non-empty classic group
+    /// whose subscriptions can't be read keeps all of them.
+    fn delete_offsets(
+        &mut self,
+        group_id: &str,
+        partitions: Vec<TopicPartition>,
+    ) -> OffsetDeleteResult {
+        let subscribed = if let Some(group) = self.classic_groups.get(group_id) {
+            match group.subscribed_topics() {
+                Some(topics) => topics,
+                None if group.is_empty() => BTreeSet::new(),
+                None => return OffsetDeleteResult::error(ErrorCode::NonEmptyGroup),
+            }
+        } else if let Some(group) = self.consumer_groups.get(group_id) {
+            group.subscribed_topics()
+        } else {
+            return OffsetDeleteResult::error(ErrorCode::GroupIdNotFound);
+        };
+
+        let results: Vec<_> = partitions
+            .into_iter()
+            .map(|partition| {
+                let error_code = if subscribed.contains(&partition.topic) {
+                    ErrorCode::GroupSubscribedToTopic
+                } else {
+                    ErrorCode::None
+                };
+                (partition, error_code)
+            })
+            .collect();
+        let offsets = self.offsets.get(group_id);
+        let deleted: Vec<_> = results
+            .iter()
+            .filter(|(partition, error_code)| {
+                *error_code == ErrorCode::None
+                    && offsets.is_some_and(|offsets| offsets.contains_key(partition))
+            })
+            .map(|(partition, _)| partition.clone())
+            .collect();
+        let tombstones: Vec<_> = deleted
+            .iter()
+            .map(|partition| OffsetsRecord::OffsetCommit {
+                group_id: group_id.to_string(),
+                partition: partition.clone(),
+                offset: None,
+            })
+            .collect();
+        if let Err(e) = self.log.append(&tombstones) {
+            warn!(group = %group_id, error = %e, "failed to write tombstones for deleted offsets");
+            return OffsetDeleteResult::error(ErrorCode::CoordinatorNotAvailable);
+        }
+        if let Some(offsets) = self.offsets.get_mut(group_id) {
+            for partition in &deleted {
+                debug!(
+                    group = %group_id,
+                    topic = %partition.topic,
+                    partition = partition.partition,
+                    "deleted committed offset"
+                );
+                offsets.remove(partition);
+            }
+            if offsets.is_empty() {
+                self.offsets.remove(group_id);
+            }
+        }
+        OffsetDeleteResult {
+            error_code: ErrorCode::None,
+            partitions: results,
+        }
+    }
+
+    fn consumer_heartbeat(
+        &mut self,
+        heartbeat: ConsumerGroupHeartbeat,
+    ) -> ConsumerGroupHeartbeatResult {
+        let error = |error_code, message: String| {
+            ConsumerGroupHeartbeatResult::error(
+                heartbeat.member_id.clone(),
+                error_code,
+                Some(message),
+            )
+        };
+        let invalid = |message: &str| error(ErrorCode::InvalidRequest, message.to_string());
+        if heartbeat.group_id.is_empty() {
+            return invalid("GroupId can't be empty.");
+        }
+        if heartbeat.member_epoch != 0 && heartbeat.member_id.is_empty() {
+            return invalid("MemberId can't be empty.");
+        }
+        if heartbeat.member_epoch == 0 {
+            if heartbeat.rebalance_timeout.is_none() {
+                return invalid("RebalanceTimeoutMs must be provided in first request.");
+            }
+            if heartbeat.subscribed_topic_names.is_none() {
+                return invalid("SubscribedTopicNames must be set in first request.");
+            }
+            if heartbeat
+                .owned_partitions
+                .as_ref()
+                .is_some_and(|owned| !owned.is_empty())
+            {
+                return invalid("TopicPartitions must be empty when (re-)joining.");
+            }
+        }
+        if heartbeat.member_epoch == -2 && heartbeat.instance_id.is_none() {
+            return invalid("InstanceId can't be null when MemberEpoch is -2.");
+        }
+        if let Some(assignor) = &heartbeat.server_assignor {
+            if !self.settings.consumer_assignors.contains(assignor) {
+                return error(
+                    ErrorCode::UnsupportedAssignor,
+                    format!("ServerAssignor {} is not supported.", assignor),
+                );
+            }
+        }
+        match self.classic_groups.get(&heartbeat.group_id) {
+            Some(group) if !group.is_empty() => {
+                return error(
+                    ErrorCode::GroupIdNotFound,
+                    format!("Group {} is not a consumer group.", heartbeat.group_id),
+                );
+            }
+            // An empty classic group, kept around for its offsets, becomes
+            // a consumer group.
+            Some(_) => self.remove_classic_group(&heartbeat.group_id),
+            None => {}
+        }
+        if self.share_groups.contains_key(&heartbeat.group_id) {
+            return error(
+                ErrorCode::GroupIdNotFound,
+                format!("Group {} is not a consumer group.", heartbeat.group_id),
+            );
+        }
+        let settings = &self.settings;
+        self.consumer_groups
+            .entry(heartbeat.group_id.clone())
+            .or_insert_with(|| ConsumerGroup::new(heartbeat.group_id.clone()))
+            .heartbeat(heartbeat, settings, Instant::now())
+    }
+
+    fn share_heartbeat(&mut self, heartbeat: ShareGroupHeartbeat) -> ConsumerGroupHeartbeatResult {
+        let error = |error_code, message: String| {
+            ConsumerGroupHeartbeatResult::error(
+                heartbeat.member_id.clone(),
+                error_code,
+                Some(message),
+            )
+        };
+        let invalid = |message: &str| error(ErrorCode::InvalidRequest, message.to_string());
+        if heartbeat.group_id.is_empty() {
+            return invalid("GroupId can't be empty.");
+        }
+        if heartbeat.member_epoch != 0 && heartbeat.member_id.is_empty() {
+            return invalid("MemberId can't be empty.");
+        }
+        if heartbeat.member_epoch == 0 && heartbeat.subscribed_topic_names.is_none() {
+            return invalid("SubscribedTopicNames must be set in first request.");
+        }
+        // A classic group outlives its members for as long as it has
+        // committed offsets, which share groups have no use for.
+        if self.consumer_groups.contains_key(&heartbeat.group_id)
+            || self.classic_groups.contains_key(&heartbeat.group_id)
+        {
+            return error(
+                ErrorCode::GroupIdNotFound,
+                format!("Group {} is not a share group.", heartbeat.group_id),
+            );
+        }
+        let settings = &self.settings;
+        self.share_groups
+            .entry(heartbeat.group_id.clone())
+            .or_insert_with(|| ShareGroup::new(heartbeat.group_id.clone()))
+            .heartbeat(heartbeat, settings, Instant::now())
+    }
+
+    /// The next time a group has to act without being asked.
+    fn next_deadline(&self) -> Option<Instant> {
+        let classic = self
+            .classic_groups
+            .values()
+            .filter_map(|g| g.next_deadline());
+        let consumer = self
+            .consumer_groups
+            .values()
+            .filter_map(|g| g.next_deadline());
+        let share = self.share_groups.values().filter_map(|g| g.next_deadline());
+        classic.chain(consumer).chain(share).min()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        for group in self.classic_groups.values_mut() {
+            group.expire(now);
+        }
+        for group in self.consumer_groups.values_mut() {
+            group.expire(now);
+        }
+        for group in self.share_groups.values_mut() {
+            group.expire(now);
+        }
+        let group_ids: Vec<_> = self
+            .classic_groups
+            .keys()
+            .chain(self.consumer_groups.keys())
+            .chain(self.share_groups.keys())
+            .cloned()
+            .collect();
+        for group_id in &group_ids {
+            self.store_group(group_id);
+            self.report_group(group_id);
+        }
+        let dead: Vec<_> = self
+            .classic_groups
+            .iter()
+            .filter(|(_, group)| group.is_empty())
+            .map(|(group_id, _)| group_id.clone())
+            .chain(
+                self.consumer_groups
+                    .iter()
+                    .filter(|(_, group)| group.is_empty())
+                    .map(|(group_id, _)| group_id.clone()),
+            )
+            .chain(
+                self.share_groups
+                    .iter()
+                    .filter(|(_, group)| group.is_empty())
+                    .map(|(group_id, _)| group_id.clone()),
+            )
+            .collect();
+        for group_id in dead {
+            self.remove_if_dead(&group_id);
+        }
+    }
+
+    /// Deletes committed offsets nobody has used for `offsets.retention.minutes`:
+    /// all of an empty group's, counted from the commit or from when the
+    /// group emptied, whichever is later, and a consumer group's for topics
+    /// it no longer subscribes to (KIP-496), counted from the commit. Each
+    /// gets a tombstone in the offsets topic, so the log forgets it too.
+    fn expire_offsets(&mut self, now: SystemTime) {
+        let retention = self.settings.offsets_retention;
+        let mut expired = Vec::new();
+        for (group_id, offsets) in &self.offsets {
+            let classic = self.classic_groups.get(group_id);
+            let consumer = self.consumer_groups.get(group_id);
+            let empty = classic.is_none_or(ClassicGroup::is_empty)
+                && consumer.is_none_or(ConsumerGroup::is_empty);
+            let empty_since = classic
+                .and_then(ClassicGroup::empty_since)
+                .or_else(|| consumer.and_then(ConsumerGroup::empty_since));
+            let subscribed: Option<BTreeSet<String>> = classic
+                .and_then(ClassicGroup::subscribed_topics)
+                .or_else(|| consumer.map(ConsumerGroup::subscribed_topics));
+            for (partition, offset) in offsets {
+                let since = if empty {
+                    empty_since.map_or(offset.commit_timestamp, |t| t.max(offset.commit_timestamp))
+                } else if subscribed
+                    .as_ref()
+                    .is_some_and(|topics| !topics.contains(&partition.topic))
+                {
+                    offset.commit_timestamp
+                } else {
+                    continue;
+                };
+                if since + retention <= now {
+                    expired.push((group_id.clone(), partition.clone()));
+                }
+            }
+        }
+        if expired.is_empty() {
+            return;
+        }
+        for (group_id, partition) in &expired {
+            debug!(
+                group = %group_id,
+                topic = %partition.topic,
+                partition = partition.partition,
+                "expired committed offset"
+            );
+        }
+        match self.remove_offsets(&expired) {
+            Ok(groups) => info!(
+                offsets = expired.len(),
+                groups, "removed expired committed offsets"
+            ),
+            Err(e) => warn!(error = %e, "failed to write tombstones for expired offsets"),
+        }
+    }
+
+    /// Deletes every group's committed offsets for the deleted `topics`.
+    fn remove_topics(&mut self, topics: &[String]) {
+        let removed: Vec<_> = self
+            .offsets
+            .iter()
+            .flat_map(|(group_id, offsets)| {
+                offsets
+                    .keys()
+                    .filter(|partition| topics.contains(&partition.topic))
+                    .map(move |partition| (group_id.clone(), partition.clone()))
+            })
+            .collect();
+        if removed.is_empty() {
+            return;
+        }
+        match self.remove_offsets(&removed) {
+            Ok(groups) => info!(
+                topics = ?topics,
+                offsets = removed.len(),
+                groups,
+                "removed committed offsets of deleted topics"
+            ),
+            Err(e) => warn!(error = %e, "failed to write tombstones for deleted topics"),
+        }
+    }
+
+    /// Writes a tombstone for each of `removed` and forgets them, answering
+    /// with how many groups they belonged to. Groups left with nothing are
+    /// removed.
+    fn remove_offsets(&mut self, removed: &[(String, TopicPartition)]) -> Result<usize> {
+        let tombstones: Vec<_> = removed
+            .iter()
+            .map(|(group_id, partition)| OffsetsRecord::OffsetCommit {
+                group_id: group_id.clone(),
+                partition: partition.clone(),
+                offset: None,
+            })
+            .collect();
+        self.log.append(&tombstones)?;
+        let mut groups = BTreeSet::new();
+        for (group_id, partition) in removed {
+            if let Some(offsets) = self.offsets.get_mut(group_id) {
+                offsets.remove(partition);
+                if offsets.is_empty() {
+                    self.offsets.remove(group_id);
+                }
+            }
+            groups.insert(group_id.clone());
+        }
+        let count = groups.len();
+        for group_id in groups {
+            self.remove_if_dead(&group_id);
+        }
+        Ok(count)
+    }
+
+    /// Writes a classic group's metadata to the offsets topic if it changed.
+    fn store_group(&mut self, group_id: &str) {
+        let Some(metadata) = self
+            .classic_groups
+            .get_mut(group_id)
+            .and_then(ClassicGroup::unstored_metadata)
+        else {
+            return;
+        };
+        let generation = metadata.generation_id;
+        let record = OffsetsRecord::GroupMetadata {
+            group_id: group_id.to_string(),
+            metadata: Some(metadata),
+        };
+        match self.log.append(&[record]) {
+            Ok(()) => debug!(group = %group_id, generation, "stored group metadata"),
+            Err(e) => warn!(group = %group_id, error = %e, "failed to store group metadata"),
+        }
+    }
+
+    /// Brings a group's metrics up to date.
+    fn report_group(&mut self, group_id: &str) {
+        let (state, members, rebalance) = if let Some(group) = self.classic_groups.get_mut(group_id)
+        {
+            let rebalance = group.take_completed_rebalance();
+            (group.state.as_str(), group.members.len(), rebalance)
+        } else if let Some(group) = self.consumer_groups.get_mut(group_id) {
+            let rebalance = group.take_completed_rebalance();
+            (group.state_name(), group.member_count(), rebalance)
+        } else if let Some(group) = self.share_groups.get(group_id) {
+            (group.state_name(), group.member_count(), None)
+        } else {
+            return;
+        };
+        if let Some(latency) = rebalance {
+            self.metrics.rebalance_completed(group_id, latency);
+        }
+        self.metrics.update(group_id, state, members);
+    }
+
+    /// Sets each group's lag: how far its committed offsets trail the ends
+    /// of their partitions, at the worst partition. Partitions with no log
+    /// here don't count.
+    fn sample_lag(&mut self) {
+        let mut log_ends: HashMap<&TopicPartition, Option<i64>> = HashMap::new();
+        for (group_id, offsets) in &self.offsets {
+            let mut max_lag = 0;
+            for (partition, offset) in offsets {
+                let log_end = *log_ends.entry(partition).or_insert_with(|| {
+                    let dir = self
+                        .log_dir
+                        .join(format!("{}-{}", partition.topic, partition.partition));
+                    log_end_offset(&dir).unwrap_or_else(|e| {
+                        debug!(partition = %dir.display(), error = %e, "can't read log end offset");
+                        None
+                    })
+                });
+                if let Some(log_end) = log_end {
+                    max_lag = max_lag.max(log_end - offset.offset);
+                }
+            }
+            self.metrics.max_lag(group_id, max_lag);
+        }
+    }
+
+    /// Removes a classic group, deleting its stored metadata.
+    fn remove_classic_group(&mut self, group_id: &str) {
+        let Some(mut group) = self.classic_groups.remove(group_id) else {
+            return;
+        };
+        self.metrics.remove(group_id);
+        group.transition(GroupState::Dead);
+        if !group.is_stored() {
+            return;
+        }
+        let tombstone = OffsetsRecord::GroupMetadata {
+            group_id: group_id.to_string(),
+            metadata: None,
+        };
+        if let Err(e) = self.log.append(&[tombstone]) {
+            warn!(group = %group_id, error = %e, "failed to delete group metadata");
+        }
+    }
+
+    /// Drops a group with no members and nothing committed.
+    fn remove_if_dead(&mut self, group_id: &str) {
+        if self.offsets.get(group_id).is_some_and(|o| !o.is_empty()) {
+            return;
+        }
+        if self
+            .classic_groups
+            .get(group_id)
+            .is_some_and(|group| group.is_empty())
+        {
+            self.remove_classic_group(group_id);
+        }
+        if self
+            .consumer_groups
+            .get(group_id)
+            .is_some_and(|group| group.is_empty())
+        {
+            self.consumer_groups.remove(group_id);
+            self.metrics.remove(group_id);
+        }
+        if self
+            .share_groups
+            .get(group_id)
+            .is_some_and(|group| group.is_empty())
+        {
+            self.share_groups.remove(group_id);
+            self.metrics.remove(group_id);
+        }
+    }
+}
@@ -0,0 +1,635 @@
+//! The `__consumer_offsets` topic, where committed offsets and classic group
+//! metadata outlive the broker. Records use the same keys and values as
+//! Kafka's, so each commit is keyed by group and partition, each group's
+//! metadata by group, and compaction keeps only the latest; an offset that
+//! expires or a group that is removed is written as a tombstone, a key with
+//! a null value.
+//!
+//! A single broker coordinates every group, so the topic has one partition
+//! and one segment, appended to by the coordinator task alone.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use integer_encoding::VarInt;
+use tracing::{info, warn};
+
+use super::{CommittedOffset, TopicPartition};
+use crate::record_batch::{
+    encode_batch, BatchRecord, BATCH_HEADER_SIZE, BATCH_LENGTH_OFFSET, COMPRESSION_MASK, MAGIC,
+};
+
+pub const OFFSETS_TOPIC: &str = "__consumer_offsets";
+
+pub(super) const SEGMENT_FILE: &str = "00000000000000000000.log";
+
+/// The key version of an offset commit; 0 and 1 share a layout.
+const OFFSET_COMMIT_KEY_VERSION: i16 = 1;
+/// The last value version that isn't flexible.
+const OFFSET_COMMIT_VALUE_VERSION: i16 = 3;
+const GROUP_METADATA_KEY_VERSION: i16 = 2;
+/// The last value version that isn't flexible.
+const GROUP_METADATA_VALUE_VERSION: i16 = 3;
+
+/// A record in the offsets topic.
+#[derive(Debug, Clone)]
+pub(super) enum OffsetsRecord {
+    /// A group's committed offset for a partition; `None` deletes it.
+    OffsetCommit {
+        group_id: String,
+        partition: TopicPartition,
+        offset: Option<CommittedOffset>,
+    },
+    /// A classic group's generation and members; `None` deletes it.
+    GroupMetadata {
+        group_id: String,
+        metadata: Option<GroupMetadata>,
+    },
+}
+
+/// What a classic group needs to carry on after a restart: its generation
+/// and what each member subscribed to and was assigned in it.
+#[derive(Debug, Clone)]
+pub(super) struct GroupMetadata {
+    pub(super) protocol_type: Option<String>,
+    pub(super) generation_id: i32,
+    pub(super) protocol_name: Option<String>,
+    pub(super) leader_id: Option<String>,
+    /// When the group entered its current state.
+    pub(super) state_timestamp: SystemTime,
+    pub(super) members: Vec<MemberMetadata>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct MemberMetadata {
+    pub(super) member_id: String,
+    pub(super) group_instance_id: Option<String>,
+    pub(super) client_id: String,
+    pub(super) client_host: String,
+    pub(super) rebalance_timeout: Duration,
+    pub(super) session_timeout: Duration,
+    /// The member's metadata for the group's protocol.
+    pub(super) subscription: Bytes,
+    pub(super) assignment: Bytes,
+}
+
+/// A record of a compacted coordinator topic: its key, and its value or
+/// `None` for a tombstone.
+pub(super) trait CompactedRecord {
+    fn key(&self) -> Bytes;
+    fn value(&self) -> Option<Bytes>;
+}
+
+impl CompactedRecord for OffsetsRecord {
+    fn key(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        match self {
+            OffsetsRecord::OffsetCommit {
+                group_id,
+                partition,
+                ..
+            } => {
+                b.put_i16(OFFSET_COMMIT_KEY_VERSION);
+                put_string(&mut b, group_id);
+                put_string(&mut b, &partition.topic);
+                b.put_i32(partition.partition);
+            }
+            OffsetsRecord::GroupMetadata { group_id, .. } => {
+                b.put_i16(GROUP_METADATA_KEY_VERSION);
+                put_string(&mut b, group_id);
+            }
+        }
+        b.freeze()
+    }
+
+    fn value(&self) -> Option<Bytes> {
+        match self {
+            OffsetsRecord::OffsetCommit { offset, .. } => {
+                let offset = offset.as_ref()?;
+                let mut b = BytesMut::new();
+                b.put_i16(OFFSET_COMMIT_VALUE_VERSION);
+                b.put_i64(offset.offset);
+                b.put_i32(offset.leader_epoch);
+                put_string(&mut b, offset.metadata.as_deref().unwrap_or_default());
+                b.put_i64(millis(offset.commit_timestamp));
+                Some(b.freeze())
+            }
+            OffsetsRecord::GroupMetadata { metadata, .. } => {
+                let metadata = metadata.as_ref()?;
+                let mut b = BytesMut::new();
+                b.put_i16(GROUP_METADATA_VALUE_VERSION);
+                put_string(
+                    &mut b,
+                    metadata.protocol_type.as_deref().unwrap_or_default(),
+                );
+                b.put_i32(metadata.generation_id);
+                put_nullable_string(&mut b, metadata.protocol_name.as_deref());
+                put_nullable_string(&mut b, metadata.leader_id.as_deref());
+                b.put_i64(millis(metadata.state_timestamp));
+                b.put_i32(metadata.members.len() as i32);
+                for member in &metadata.members {
+                    put_string(&mut b, &member.member_id);
+                    put_nullable_string(&mut b, member.group_instance_id.as_deref());
+                    put_string(&mut b, &member.client_id);
+                    put_string(&mut b, &member.client_host);
+                    b.put_i32(member.rebalance_timeout.as_millis() as i32);
+                    b.put_i32(member.session_timeout.as_millis() as i32);
+                    put_bytes(&mut b, &member.subscription);
+                    put_bytes(&mut b, &member.assignment);
+                }
+                Some(b.freeze())
+            }
+        }
+    }
+}
+
+impl OffsetsRecord {
+    fn decode(mut key: Bytes, value: Option<Bytes>) -> Result<Option<Self>> {
+        let version = read_i16(&mut key)?;
+        match version {
+            0 | 1 => {
+                let group_id = read_string(&mut key)?;
+                let topic = read_string(&mut key)?;
+                let partition = read_i32(&mut key)?;
+                let offset = value.map(decode_offset_commit_value).transpose()?;
+                Ok(Some(OffsetsRecord::OffsetCommit {
+                    group_id,
+                    partition: TopicPartition { topic, partition },
+                    offset,
+                }))
+            }
+            2 => {
+                let group_id = read_string(&mut key)?;
+                let metadata = value.map(decode_group_metadata_value).transpose()?;
+                Ok(Some(OffsetsRecord::GroupMetadata { group_id, metadata }))
+            }
+            // Records of consumer groups and anything newer aren't kept here.
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Values v0 to v3. v1 carries an expire timestamp, which is ignored as
+/// retention is the broker's to decide.
+fn decode_offset_commit_value(mut src: Bytes) -> Result<CommittedOffset> {
+    let version = read_i16(&mut src)?;
+    ensure!(
+        (0..=OFFSET_COMMIT_VALUE_VERSION).contains(&version),
+        "unsupported offset commit value version {}",
+        version
+    );
+    let offset = read_i64(&mut src)?;
+    let leader_epoch = if version >= 3 {
+        read_i32(&mut src)?
+    } else {
+        -1
+    };
+    let metadata = read_string(&mut src)?;
+    let commit_timestamp = read_i64(&mut src)?;
+    Ok(CommittedOffset {
+        offset,
+        leader_epoch,
+        metadata: (!metadata.is_empty()).then_some(metadata),
+        commit_timestamp: UNIX_EPOCH + Duration::from_millis(commit_timestamp.max(0) as u64),
+    })
+}
+
+/// Values v0 to v3. Members have a rebalance timeout since v1, and a group
+/// instance id since v3; the state timestamp came in v2.
+fn decode_group_metadata_value(mut src: Bytes) -> Result<GroupMetadata> {
+    let version = read_i16(&mut src)?;
+    ensure!(
+        (0..=GROUP_METADATA_VALUE_VERSION).contains(&version),
+        "unsupported group metadata value version {}",
+        version
+    );
+    let protocol_type = read_string(&mut src)?;
+    let generation_id = read_i32(&mut src)?;
+    let protocol_name = read_nullable_string(&mut src)?;
+    let leader_id = read_nullable_string(&mut src)?;
+    let state_timestamp = if version >= 2 {
+        read_i64(&mut src)?
+    } else {
+        -1
+    };
+    let count = read_i32(&mut src)?;
+    let members = (0..count.max(0))
+        .map(|_| {
+            let member_id = read_string(&mut src)?;
+            let group_instance_id = if version >= 3 {
+                read_nullable_string(&mut src)?
+            } else {
+                None
+            };
+            let client_id = read_string(&mut src)?;
+            let client_host = read_string(&mut src)?;
+            let rebalance_timeout = if version >= 1 {
+                Some(read_i32(&mut src)?)
+            } else {
+                None
+            };
+            let session_timeout = read_i32(&mut src)?;
+            let subscription = read_bytes(&mut src)?;
+            let assignment = read_bytes(&mut src)?;
+            let millis = |ms: i32| Duration::from_millis(ms.max(0) as u64);
+            Ok(MemberMetadata {
+                member_id,
+                group_instance_id,
+                client_id,
+                client_host,
+                rebalance_timeout: millis(rebalance_timeout.unwrap_or(session_timeout)),
+                session_timeout: millis(session_timeout),
+                subscription,
+                assignment,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(GroupMetadata {
+        protocol_type: (!protocol_type.is_empty()).then_some(protocol_type),
+        generation_id,
+        protocol_name,
+        leader_id,
+        state_timestamp: if state_timestamp < 0 {
+            SystemTime::now()
+        } else {
+            UNIX_EPOCH + Duration::from_millis(state_timestamp as u64)
+        },
+        members,
+    })
+}
+
+/// What the log holds, by group.
+#[derive(Default)]
+pub(super) struct LoadedGroups {
+    pub(super) offsets: HashMap<String, BTreeMap<TopicPartition, CommittedOffset>>,
+    pub(super) groups: HashMap<String, GroupMetadata>,
+}
+
+impl LoadedGroups {
+    fn apply(&mut self, record: OffsetsRecord) {
+        match record {
+            OffsetsRecord::OffsetCommit {
+                group_id,
+                partition,
+                offset: Some(offset),
+            } => {
+                self.offsets
+                    .entry(group_id)
+                    .or_default()
+                    .insert(partition, offset);
+            }
+            OffsetsRecord::OffsetCommit {
+                group_id,
+                partition,
+                offset: None,
+            } => {
+                if let Some(group) = self.offsets.get_mut(&group_id) {
+                    group.remove(&partition);
+                    if group.is_empty() {
+                        self.offsets.remove(&group_id);
+                    }
+                }
+            }
+            OffsetsRecord::GroupMetadata {
+                group_id,
+                metadata: Some(metadata),
+            } => {
+                self.groups.insert(group_id, metadata);
+            }
+            OffsetsRecord::GroupMetadata {
+                group_id,
+                metadata: None,
+            } => {
+                self.groups.remove(&group_id);
+            }
+        }
+    }
+
+    /// The records it takes to rebuild this.
+    fn records(&self) -> Vec<OffsetsRecord> {
+        let groups = self
+            .groups
+            .iter()
+            .map(|(group_id, metadata)| OffsetsRecord::GroupMetadata {
+                group_id: group_id.clone(),
+                metadata: Some(metadata.clone()),
+            });
+        let offsets = self.offsets.iter().flat_map(|(group_id, partitions)| {
+            partitions
+                .iter()
+                .map(|(partition, offset)| OffsetsRecord::OffsetCommit {
+                    group_id: group_id.clone(),
+                    partition: partition.clone(),
+                    offset: Some(offset.clone()),
+                })
+        });
+        groups.chain(offsets).collect()
+    }
+}
+
+/// The offsets topic's only partition.
+pub(super) struct OffsetsLog {
+    path: PathBuf,
+    file: File,
+    next_offset: i64,
+}
+
+impl OffsetsLog {
+    /// Opens the partition under `log_dir` and replays it, creating it if
+    /// missing. The segment is compacted first when most of its records
+    /// have been superseded or deleted.
+    pub(super) fn open(log_dir: &Path) -> Result<(Self, LoadedGroups)> {
+        let dir = log_dir.join(format!("{}-0", OFFSETS_TOPIC));
+        std::fs::create_dir_all(&dir).with_context(|| format!("create '{}'", dir.display()))?;
+        let path = dir.join(SEGMENT_FILE);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("read '{}'", path.display())),
+        };
+
+        let mut loaded = LoadedGroups::default();
+        let mut records = 0;
+        let mut next_offset = 0;
+        for LogRecord { offset, key, value } in read_records(Bytes::from(data))? {
+            records += 1;
+            next_offset = offset + 1;
+            match key.map(|key| OffsetsRecord::decode(key, value)) {
+                Some(Ok(Some(record))) => loaded.apply(record),
+                Some(Ok(None)) | None => {}
+                Some(Err(e)) => {
+                    warn!(offset, error = %e, "skipping unreadable offsets record");
+                }
+            }
+        }
+
+        let live = loaded.records();
+        if records > 2 * live.len() {
+            compact(&path, &live, next_offset)?;
+            info!(records, live = live.len(), "compacted offsets topic");
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("open '{}'", path.display()))?;
+        info!(
+            groups = loaded.groups.len(),
+            offsets = loaded.offsets.values().map(BTreeMap::len).sum::<usize>(),
+            "loaded groups and committed offsets"
+        );
+        Ok((
+            Self {
+                path,
+                file,
+                next_offset,
+            },
+            loaded,
+        ))
+    }
+
+    /// Appends `records` as one batch. Nothing is written if it fails.
+    pub(super) fn append(&mut self, records: &[OffsetsRecord]) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let batch = encode_records(self.next_offset, records, SystemTime::now());
+        self.file
+            .write_all(&batch)
+            .with_context(|| format!("append to '{}'", self.path.display()))?;
+        self.next_offset += records.len() as i64;
+        Ok(())
+    }
+}
+
+/// Rewrites the segment with only the live records, keeping offsets
+/// increasing past what the old segment held.
+pub(super) fn compact(
+    path: &Path,
+    records: &[impl CompactedRecord],
+    next_offset: i64,
+) -> Result<()> {
+    let base_offset = next_offset - records.len() as i64;
+    let data = if records.is_empty() {
+        Bytes::new()
+    } else {
+        encode_records(base_offset, records, SystemTime::now())
+    };
+    let tmp = path.with_extension("log.cleaned");
+    File::create(&tmp)
+        .and_then(|mut f| f.write_all(&data).and_then(|()| f.sync_all()))
+        .with_context(|| format!("write '{}'", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("replace '{}'", path.display()))
+}
+
+pub(super) fn encode_records(
+    base_offset: i64,
+    records: &[impl CompactedRecord],
+    now: SystemTime,
+) -> Bytes {
+    let records: Vec<_> = records
+        .iter()
+        .map(|record| BatchRecord {
+            key: Some(record.key()),
+            value: record.value(),
+        })
+        .collect();
+    encode_batch(base_offset, 0, 0, &records, millis(now))
+}
+
+pub(super) struct LogRecord {
+    pub(super) offset: i64,
+    pub(super) key: Option<Bytes>,
+    pub(super) value: Option<Bytes>,
+}
+
+/// Every record in `data`. The log manager has already cut off anything
+/// torn or corrupt at the end.
+pub(super) fn read_records(mut data: Bytes) -> Result<Vec<LogRecord>> {
+    let mut records = Vec::new();
+    while data.remaining() >= BATCH_HEADER_SIZE {
+        let base_offset = data.get_i64();
+        let length = data.get_i32();
+        ensure!(
+            length >= (BATCH_HEADER_SIZE - BATCH_LENGTH_OFFSET) as i32
+                && data.remaining() >= length as usize,
+            "truncated batch at offset {}",
+            base_offset
+        );
+        let mut batch = data.split_to(length as usize);
+        batch.advance(4); // partition leader epoch
+        let magic = batch.get_i8();
+        ensure!(magic == MAGIC, "unsupported batch magic {}", magic);
+        batch.advance(4); // crc
+        let attributes = batch.get_i16();
+        if attributes & COMPRESSION_MASK != 0 {
+            bail!("compressed batch at offset {}", base_offset);
+        }
+        // Last offset delta, timestamps, producer id, epoch and sequence.
+        batch.advance(4 + 8 + 8 + 8 + 2 + 4);
+        let count = batch.get_i32();
+        for _ in 0..count {
+            let length = read_varint(&mut batch)?;
+            ensure!(
+                length >= 0 && batch.remaining() >= length as usize,
+                "truncated record at offset {}",
+                base_offset
+            );
+            let mut record = batch.split_to(length as usize);
+            ensure!(record.has_remaining(), "empty record");
+            record.advance(1); // attributes
+            read_varint(&mut record)?; // timestamp delta
+            let offset_delta = read_varint(&mut record)?;
+            let key = read_varint_bytes(&mut record)?;
+            let value = read_varint_bytes(&mut record)?;
+            records.push(LogRecord {
+                offset: base_offset + offset_delta,
+                key,
+                value,
+            });
+        }
+    }
+    Ok(records)
+}
+
+pub(super) fn millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+pub(super) fn put_string(b: &mut BytesMut, s: &str) {
+    b.put_i16(s.len() as i16);
+    b.put_slice(s.as_bytes());
+}
+
+pub(super) fn put_nullable_string(b: &mut BytesMut, s: Option<&str>) {
+    match s {
+        Some(s) => put_string(b, s),
+        None => b.put_i16(-1),
+    }
+}
+
+pub(super) fn put_bytes(b: &mut BytesMut, bytes: &[u8]) {
+    b.put_i32(bytes.len() as i32);
+    b.put_slice(bytes);
+}
+
+pub(super) fn read_varint(src: &mut Bytes) -> Result<i64> {
+    let Some((n, read)) = i64::decode_var(src) else {
+        bail!("truncated varint");
+    };
+    src.advance(read);
+    Ok(n)
+}
+
+pub(super) fn read_varint_bytes(src: &mut Bytes) -> Result<Option<Bytes>> {
+    let len = read_varint(src)?;
+    if len < 0 {
+        return Ok(None);
+    }
+    ensure!(src.remaining() >= len as usize, "truncated record");
+    Ok(Some(src.split_to(len as usize)))
+}
+
+pub(super) fn read_i8(src: &mut Bytes) -> Result<i8> {
+    ensure!(src.has_remaining(), "truncated record");
+    Ok(src.get_i8())
+}
+
+pub(super) fn read_i16(src: &mut Bytes) -> Result<i16> {
+    ensure!(src.remaining() >= 2, "truncated record");
+    Ok(src.get_i16())
+}
+
+pub(super) fn read_i32(src: &mut Bytes) -> Result<i32> {
+    ensure!(src.remaining() >= 4, "truncated record");
+    Ok(src.get_i32())
+}
+
+pub(super) fn read_i64(src: &mut Bytes) -> Result<i64> {
+    ensure!(src.remaining() >= 8, "truncated record");
+    Ok(src.get_i64())
+}
+
+pub(super) fn read_string(src: &mut Bytes) -> Result<String> {
+    Ok(read_nullable_string(src)?.unwrap_or_default())
+}
+
+pub(super) fn read_bytes(src: &mut Bytes) -> Result<Bytes> {
+    let len = read_i32(src)?;
+    if len < 0 {
+        return Ok(Bytes::new());
+    }
+    ensure!(src.remaining() >= len as usize, "truncated record");
+    Ok(src.split_to(len as usize))
+}
+
+pub(super) fn read_nullable_string(src: &mut Bytes) -> Result<Option<String>> {
+    let len = read_i16(src)?;
+    if len < 0 {
+        return Ok(None);
+    }
+    ensure!(src.remaining() >= len as usize, "truncated record");
+    Ok(Some(String::from_utf8(
+        src.split_to(len as usize).to_vec(),
+    )?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(group_id: &str, partition: i32, offset: Option<i64>) -> OffsetsRecord {
+        OffsetsRecord::OffsetCommit {
+            group_id: group_id.to_string(),
+            partition: TopicPartition {
+                topic: "orders".to_string(),
+                partition,
+            },
+            offset: offset.map(|offset| CommittedOffset {
+                offset,
+                leader_epoch: 0,
+                metadata: Some("checkpoint".to_string()),
+                commit_timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_000),
+            }),
+        }
+    }
+
+    #[test]
+    fn committed_offsets_outlive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("offsets-{:016x}", rand::random::<u64>()));
+        let (mut log, loaded) = OffsetsLog::open(&dir).unwrap();
+        assert!(loaded.offsets.is_empty());
+        log.append(&[commit("audit", 0, Some(5)), commit("audit", 1, Some(7))])
+            .unwrap();
+        log.append(&[commit("audit", 0, Some(9)), commit("audit", 1, None)])
+            .unwrap();
+        drop(log);
+
+        // Reopened twice: compaction on the first open must keep the same
+        // offsets the second one reads back.
+        for _ in 0..2 {
+            let (_, loaded) = OffsetsLog::open(&dir).unwrap();
+            let offsets = &loaded.offsets["audit"];
+            assert_eq!(offsets.len(), 1);
+            let offset = &offsets[&TopicPartition {
+                topic: "orders".to_string(),
+                partition: 0,
+            }];
+            assert_eq!(offset.offset, 9);
+            assert_eq!(offset.metadata.as_deref(), Some("checkpoint"));
+            assert_eq!(
+                offset.commit_timestamp,
+                UNIX_EPOCH + Duration::from_millis(1_700_000_000_000)
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
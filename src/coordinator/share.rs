@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+use super::assignor::Assignment;
+use super::{ConsumerGroupHeartbeatResult, GroupSettings, ShareGroupHeartbeat};
+use crate::meta_properties::random_id;
+use crate::protocol::ErrorCode;
+
+struct ShareMember {
+    member_id: String,
+    /// The group epoch the member's assignment was last brought up to.
+    member_epoch: i32,
+    /// When the member is dropped unless it heartbeats.
+    session_deadline: Instant,
+    subscribed_topics: BTreeSet<String>,
+    assigned: Assignment,
+}
+
+/// A share group (KIP-932), whose members consume their partitions
+/// together rather than each owning some. Every member is assigned every
+/// partition of the topics it subscribes to, and the share partitions hand
+/// each record to one of them at a time, so nothing has to be revoked
+/// before it moves and a member is up to date after a single heartbeat.
+pub(super) struct ShareGroup {
+    group_id: String,
+    /// Bumped whenever membership, subscriptions or subscribed topic
+    /// metadata change.
+    group_epoch: i32,
+    members: BTreeMap<String, ShareMember>,
+    /// The partition count of each subscribed topic as of `group_epoch`.
+    subscribed_topics: BTreeMap<String, i32>,
+}
+
+impl ShareGroup {
+    pub(super) fn new(group_id: String) -> Self {
+        Self {
+            group_id,
+            group_epoch: 0,
+            members: BTreeMap::new(),
+            subscribed_topics: BTreeMap::new(),
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub(super) fn member_count(&self) -> usize {
+        self.members.len()
+    }
+
+    pub(super) fn state_name(&self) -> &'static str {
+        if self.members.is_empty() {
+            "Empty"
+        } else {
+            "Stable"
+        }
+    }
+
+    pub(super) fn heartbeat(
+        &mut self,
+        heartbeat: ShareGroupHeartbeat,
+        settings: &GroupSettings,
+        now: Instant,
+    ) -> ConsumerGroupHeartbeatResult {
+        let error = |error_code| {
+            ConsumerGroupHeartbeatResult::error(heartbeat.member_id.clone(), error_code, None)
+        };
+
+        if heartbeat.member_epoch < 0 {
+            if self.members.remove(&heartbeat.member_id).is_none() {
+                return error(ErrorCode::UnknownMemberId);
+            }
+            info!(group = %self.group_id, member = %heartbeat.member_id, "member left share group");
+            self.bump_group_epoch();
+            return ConsumerGroupHeartbeatResult {
+                error_code: ErrorCode::None,
+                error_message: None,
+                member_id: heartbeat.member_id,
+                member_epoch: heartbeat.member_epoch,
+                heartbeat_interval: settings.share_heartbeat_interval,
+                assignment: None,
+            };
+        }
+
+        let mut changed = false;
+        let member_id = if heartbeat.member_epoch == 0 {
+            let member_id = if heartbeat.member_id.is_empty() {
+                random_id()
+            } else {
+                heartbeat.member_id.clone()
+            };
+            if !self.members.contains_key(&member_id) {
+                info!(
+                    group = %self.group_id,
+                    member = %member_id,
+                    client_id = %heartbeat.client_id,
+                    client_host = %heartbeat.client_host,
+                    "member joined share group"
+                );
+                self.members.insert(
+                    member_id.clone(),
+                    ShareMember {
+                        member_id: member_id.clone(),
+                        member_epoch: 0,
+                        session_deadline: now,
+                        subscribed_topics: BTreeSet::new(),
+                        assigned: Assignment::new(),
+                    },
+                );
+                changed = true;
+            }
+            member_id
+        } else {
+            let Some(member) = self.members.get(&heartbeat.member_id) else {
+                return error(ErrorCode::UnknownMemberId);
+            };
+            if heartbeat.member_epoch != member.member_epoch {
+                return error(ErrorCode::FencedMemberEpoch);
+            }
+            heartbeat.member_id.clone()
+        };
+
+        let member = self.members.get_mut(&member_id).unwrap();
+        member.session_deadline = now + settings.share_session_timeout;
+        if let Some(names) = &heartbeat.subscribed_topic_names {
+            let subscribed: BTreeSet<_> = names.iter().cloned().collect();
+            changed |= subscribed != member.subscribed_topics;
+            member.subscribed_topics = subscribed;
+        }
+        let subscribed_topics = self.subscribed_topic_metadata(&heartbeat.topics);
+        if subscribed_topics != self.subscribed_topics {
+            self.subscribed_topics = subscribed_topics;
+            changed = true;
+        }
+        if changed {
+            self.bump_group_epoch();
+        }
+
+        let group_epoch = self.group_epoch;
+        let topics = &self.subscribed_topics;
+        let member = self.members.get_mut(&member_id).unwrap();
+        let assigned: Assignment = member
+            .subscribed_topics
+            .iter()
+            .filter_map(|topic| {
+                let count = *topics.get(topic)?;
+                Some((topic.clone(), (0..count).collect()))
+            })
+            .collect();
+        let reassigned = assigned != member.assigned || heartbeat.member_epoch == 0;
+        member.assigned = assigned;
+        member.member_epoch = group_epoch;
+        ConsumerGroupHeartbeatResult {
+            error_code: ErrorCode::None,
+            error_message: None,
+            member_id,
+            member_epoch: member.member_epoch,
+            heartbeat_interval: settings.share_heartbeat_interval,
+            assignment: reassigned.then(|| member.assigned.clone()),
+        }
+    }
+
+    /// The next time a member's session runs out.
+    pub(super) fn next_deadline(&self) -> Option<Instant> {
+        self.members.values().map(|m| m.session_deadline).min()
+    }
+
+    /// Drops members that stopped heartbeating. Whatever records they had
+    /// acquired go back to the others once their locks run out.
+    pub(super) fn expire(&mut self, now: Instant) {
+        let expired: Vec<_> = self
+            .members
+            .values()
+            .filter(|m| m.session_deadline <= now)
+            .map(|m| m.member_id.clone())
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        for member_id in &expired {
+            self.members.remove(member_id);
+            info!(group = %self.group_id, member = %member_id, "share group member session expired");
+        }
+        self.bump_group_epoch();
+    }
+
+    fn bump_group_epoch(&mut self) {
+        self.group_epoch += 1;
+        debug!(group = %self.group_id, epoch = self.group_epoch, "share group epoch bumped");
+    }
+
+    /// The partition count of every topic some member subscribes to, out of
+    /// `topics`.
+    fn subscribed_topic_metadata(&self, topics: &BTreeMap<String, i32>) -> BTreeMap<String, i32> {
+        self.members
+            .values()
+            .flat_map(|member| &member.subscribed_topics)
+            .filter_map(|topic| topics.get(topic).map(|&count| (topic.clone(), count)))
+            .collect()
+    }
+}
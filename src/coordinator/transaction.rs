@@ -0,0 +1,471 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use tracing::{debug, info, warn};
+
+use super::transaction_log::{
+    TransactionLog, TransactionMetadata, TransactionRecord, TransactionState,
+};
+use super::TopicPartition;
+use crate::protocol::ErrorCode;
+
+/// The longest transaction timeout a producer may ask for, Kafka's default
+/// `transaction.max.timeout.ms`.
+pub const MAX_TRANSACTION_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// The producer id of a producer that doesn't have one yet.
+pub const NO_PRODUCER_ID: i64 = -1;
+
+/// Ends a transaction on its partitions, with a commit or abort marker in
+/// each.
+pub trait MarkerWriter: Send + Sync {
+    fn write_markers(
+        &self,
+        partitions: &BTreeSet<TopicPartition>,
+        producer_id: i64,
+        producer_epoch: i16,
+        commit: bool,
+    ) -> Result<()>;
+}
+
+/// The transaction coordinator: hands out producer ids and tracks each
+/// transactional id's producer epoch and open transaction, writing every
+/// change to `__transaction_state` before answering, so that a restarted
+/// broker fences the same producers and finishes the same transactions.
+///
+/// Ending a transaction is written as prepared, then the markers, then as
+/// complete. A commit or abort that was prepared but didn't complete, say
+/// for a restart, is finished when the producer retries it or is
+/// initialized again. A transaction left open is aborted when its
+/// transactional id's producer is next initialized.
+pub struct TransactionCoordinator {
+    state: Mutex<Transactions>,
+    markers: Arc<dyn MarkerWriter>,
+}
+
+struct Transactions {
+    log: TransactionLog,
+    transactions: HashMap<String, TransactionMetadata>,
+    /// Ids of producers without a transactional id aren't written down, so
+    /// after a restart only those of transactional ids are never reused.
+    next_producer_id: i64,
+}
+
+impl TransactionCoordinator {
+    /// Loads the transaction state kept under `log_dir`.
+    pub fn open(log_dir: &Path, markers: Arc<dyn MarkerWriter>) -> Result<Self> {
+        let (log, transactions) = TransactionLog::open(log_dir)?;
+        let next_producer_id = transactions
+            .values()
+            .map(|metadata| metadata.producer_id + 1)
+            .max()
+            .unwrap_or(0);
+        Ok(Self {
+            state: Mutex::new(Transactions {
+                log,
+                transactions,
+                next_producer_id,
+            }),
+            markers,
+        })
+    }
+
+    /// A producer id and epoch for a producer. Without a transactional id
+    /// it is a fresh id. With one it is the id's producer with its epoch
+    /// bumped, fencing the previous instance and ending any transaction it
+    /// left; `producer_id` and `producer_epoch`, when given, must be the
+    /// id's current ones.
+    pub fn init_producer_id(
+        &self,
+        transactional_id: Option<&str>,
+        transaction_timeout_ms: i32,
+        producer_id: i64,
+        producer_epoch: i16,
+    ) -> Result<(i64, i16), ErrorCode> {
+        let mut state = self.state.lock().unwrap();
+        let Some(transactional_id) = transactional_id else {
+            return Ok((state.allocate_producer_id(), 0));
+        };
+        let timeout = Duration::from_millis(transaction_timeout_ms.max(0) as u64);
+        if transaction_timeout_ms <= 0 || timeout > MAX_TRANSACTION_TIMEOUT {
+            return Err(ErrorCode::InvalidTransactionTimeout);
+        }
+        let (producer_id, producer_epoch) = match state.transactions.get(transactional_id) {
+            None => (state.allocate_producer_id(), 0),
+            Some(current) => {
+                if producer_id != NO_PRODUCER_ID
+                    && (producer_id, producer_epoch)
+                        != (current.producer_id, current.producer_epoch)
+                {
+                    return Err(ErrorCode::InvalidProducerEpoch);
+                }
+                let commit = match current.state {
+                    TransactionState::PrepareCommit => Some(true),
+                    TransactionState::Ongoing
+                    | TransactionState::PrepareAbort
+                    | TransactionState::PrepareEpochFence => Some(false),
+                    _ => None,
+                };
+                if let Some(commit) = commit {
+                    info!(
+                        transactional_id = %transactional_id,
+                        producer_id = current.producer_id,
+                        commit,
+                        "ending the transaction of a fenced producer"
+                    );
+                    self.write_markers(current, commit)?;
+                }
+                // An exhausted epoch starts over under a new producer id.
+                if current.producer_epoch >= i16::MAX - 1 {
+                    (state.allocate_producer_id(), 0)
+                } else {
+                    (current.producer_id, current.producer_epoch + 1)
+                }
+            }
+        };
+        state.store(
+            transactional_id,
+            TransactionMetadata {
+                producer_id,
+                producer_epoch,
+                timeout,
+                state: TransactionState::Empty,
+                partitions: BTreeSet::new(),
+                last_update: SystemTime::now(),
+                start: None,
+            },
+        )?;
+        Ok((producer_id, producer_epoch))
+    }
+
+    /// Adds `partitions` to the producer's transaction, starting one if
+    /// none is open.
+    pub fn add_partitions(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+        partitions: impl IntoIterator<Item = TopicPartition>,
+    ) -> ErrorCode {
+        let mut state = self.state.lock().unwrap();
+        let current = match state.current(transactional_id, producer_id, producer_epoch) {
+            Ok(current) => current,
+            Err(error_code) => return error_code,
+        };
+        let mut next = current.clone();
+        let now = SystemTime::now();
+        match next.state {
+            TransactionState::Ongoing => {}
+            TransactionState::PrepareCommit
+            | TransactionState::PrepareAbort
+            | TransactionState::PrepareEpochFence => return ErrorCode::ConcurrentTransactions,
+            _ => {
+                next.state = TransactionState::Ongoing;
+                next.partitions.clear();
+                next.start = Some(now);
+            }
+        }
+        next.partitions.extend(partitions);
+        if next == *current {
+            return ErrorCode::None;
+        }
+        next.last_update = now;
+        match state.store(transactional_id, next) {
+            Ok(()) => ErrorCode::None,
+            Err(error_code) => error_code,
+        }
+    }
+
+    /// Commits or aborts the producer's transaction. Retrying the same end
+    /// of a transaction finishes it if it didn't complete, and succeeds
+    /// again if it did.
+    pub fn end_transaction(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+        commit: bool,
+    ) -> ErrorCode {
+        let mut state = self.state.lock().unwrap();
+        let current = match state.current(transactional_id, producer_id, producer_epoch) {
+            Ok(current) => current.clone(),
+            Err(error_code) => return error_code,
+        };
+        let prepared =
+            match (current.state, commit) {
+                (TransactionState::Ongoing, _) => {
+                    let prepared = TransactionMetadata {
+                        state: if commit {
+                            TransactionState::PrepareCommit
+                        } else {
+                            TransactionState::PrepareAbort
+                        },
+                        last_update: SystemTime::now(),
+                        ..current
+                    };
+                    if let Err(error_code) = state.store(transactional_id, prepared.clone()) {
+                        return error_code;
+                    }
+                    prepared
+                }
+                (TransactionState::PrepareCommit, true)
+                | (TransactionState::PrepareAbort, false) => current,
+                (TransactionState::CompleteCommit, true)
+                | (TransactionState::CompleteAbort, false) => return ErrorCode::None,
+                _ => return ErrorCode::InvalidTxnState,
+            };
+        if let Err(error_code) = self.write_markers(&prepared, commit) {
+            return error_code;
+        }
+        let completed = TransactionMetadata {
+            state: if commit {
+                TransactionState::CompleteCommit
+            } else {
+                TransactionState::CompleteAbort
+            },
+            partitions: BTreeSet::new(),
+            last_update: SystemTime::now(),
+            ..prepared
+        };
+        debug!(
+            transactional_id = %transactional_id,
+            producer_id,
+            state = ?completed.state,
+            "ended transaction"
+        );
+        match state.store(transactional_id, completed) {
+            Ok(()) => ErrorCode::None,
+            Err(error_code) => error_code,
+        }
+    }
+
+    fn write_markers(&self, metadata: &TransactionMetadata, commit: bool) -> Result<(), ErrorCode> {
+        self.markers
+            .write_markers(
+                &metadata.partitions,
+                metadata.producer_id,
+                metadata.producer_epoch,
+                commit,
+            )
+            .map_err(|e| {
+                warn!(
+                    producer_id = metadata.producer_id,
+                    error = %format!("{:#}", e),
+                    "failed to write transaction markers"
+                );
+                ErrorCode::CoordinatorNotAvailable
+            })
+    }
+}
+
+impl Transactions {
+    fn allocate_producer_id(&mut self) -> i64 {
+        let producer_id = self.next_producer_id;
+        self.next_producer_id += 1;
+        producer_id
+    }
+
+    /// The transactional id's metadata, if `producer_id` and
+    /// `producer_epoch` are its current producer's.
+    fn current(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+    ) -> Result<&TransactionMetadata, ErrorCode> {
+        let current = self
+            .transactions
+            .get(transactional_id)
+            .filter(|current| {
+                current.producer_id == producer_id && current.state != TransactionState::Dead
+            })
+            .ok_or(ErrorCode::InvalidProducerIdMapping)?;
+        if current.producer_epoch != producer_epoch {
+            return Err(ErrorCode::InvalidProducerEpoch);
+        }
+        Ok(current)
+    }
+
+    /// Writes `metadata` down, then takes it as the id's current state.
+    fn store(
+        &mut self,
+        transactional_id: &str,
+        metadata: TransactionMetadata,
+    ) -> Result<(), ErrorCode> {
+        let record = TransactionRecord {
+            transactional_id: transactional_id.to_string(),
+            metadata: Some(metadata),
+        };
+        if let Err(e) = self.log.append(std::slice::from_ref(&record)) {
+            warn!(transactional_id = %transactional_id, error = %e, "failed to write transaction state");
+            return Err(ErrorCode::CoordinatorNotAvailable);
+        }
+        if let Some(metadata) = record.metadata {
+            self.transactions
+                .insert(transactional_id.to_string(), metadata);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps the markers it is asked to write, and fails while `fail` is
+    /// set.
+    #[derive(Default)]
+    struct Markers {
+        written: Mutex<Vec<(TopicPartition, i64, bool)>>,
+        fail: std::sync::atomic::AtomicBool,
+    }
+
+    impl MarkerWriter for Markers {
+        fn write_markers(
+            &self,
+            partitions: &BTreeSet<TopicPartition>,
+            producer_id: i64,
+            _producer_epoch: i16,
+            commit: bool,
+        ) -> Result<()> {
+            anyhow::ensure!(
+                !self.fail.load(std::sync::atomic::Ordering::Relaxed),
+                "log dir offline"
+            );
+            let mut written = self.written.lock().unwrap();
+            written.extend(partitions.iter().map(|p| (p.clone(), producer_id, commit)));
+            Ok(())
+        }
+    }
+
+    fn scratch_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("transactions-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn partition(topic: &str, partition: i32) -> TopicPartition {
+        TopicPartition {
+            topic: topic.to_string(),
+            partition,
+        }
+    }
+
+    #[test]
+    fn transactions_survive_a_restart() {
+        let log_dir = scratch_dir();
+        let markers = Arc::new(Markers::default());
+        let coordinator = TransactionCoordinator::open(&log_dir, markers.clone()).unwrap();
+        let (producer_id, epoch) = coordinator
+            .init_producer_id(Some("txn"), 60_000, NO_PRODUCER_ID, -1)
+            .unwrap();
+        assert_eq!(epoch, 0);
+        let partitions = [partition("orders", 0), partition("orders", 1)];
+        assert_eq!(
+            coordinator.add_partitions("txn", producer_id, epoch, partitions.clone()),
+            ErrorCode::None
+        );
+        drop(coordinator);
+
+        let coordinator = TransactionCoordinator::open(&log_dir, markers.clone()).unwrap();
+        {
+            let state = coordinator.state.lock().unwrap();
+            let metadata = &state.transactions["txn"];
+            assert_eq!(metadata.state, TransactionState::Ongoing);
+            assert_eq!(metadata.partitions, BTreeSet::from(partitions.clone()));
+            assert_eq!(metadata.timeout, Duration::from_secs(60));
+            assert!(state.next_producer_id > producer_id);
+        }
+        assert_eq!(
+            coordinator.end_transaction("txn", producer_id, epoch, true),
+            ErrorCode::None
+        );
+        assert_eq!(
+            *markers.written.lock().unwrap(),
+            partitions.map(|p| (p, producer_id, true))
+        );
+        // A retried commit succeeds; an abort of the committed one doesn't.
+        assert_eq!(
+            coordinator.end_transaction("txn", producer_id, epoch, true),
+            ErrorCode::None
+        );
+        assert_eq!(
+            coordinator.end_transaction("txn", producer_id, epoch, false),
+            ErrorCode::InvalidTxnState
+        );
+        assert_eq!(markers.written.lock().unwrap().len(), 2);
+        drop(coordinator);
+
+        // The restarted coordinator fences the old epoch.
+        let coordinator = TransactionCoordinator::open(&log_dir, markers).unwrap();
+        assert_eq!(
+            coordinator.init_producer_id(Some("txn"), 60_000, NO_PRODUCER_ID, -1),
+            Ok((producer_id, epoch + 1))
+        );
+        assert_eq!(
+            coordinator.add_partitions("txn", producer_id, epoch, [partition("orders", 0)]),
+            ErrorCode::InvalidProducerEpoch
+        );
+        assert_eq!(
+            coordinator.init_producer_id(None, 0, NO_PRODUCER_ID, -1),
+            Ok((producer_id + 1, 0))
+        );
+        std::fs::remove_dir_all(&log_dir).unwrap();
+    }
+
+    #[test]
+    fn finishes_a_prepared_commit_after_a_restart() {
+        let log_dir = scratch_dir();
+        let markers = Arc::new(Markers::default());
+        let coordinator = TransactionCoordinator::open(&log_dir, markers.clone()).unwrap();
+        let (producer_id, epoch) = coordinator
+            .init_producer_id(Some("txn"), 60_000, NO_PRODUCER_ID, -1)
+            .unwrap();
+        coordinator.add_partitions("txn", producer_id, epoch, [partition("orders", 0)]);
+        markers
+            .fail
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(
+            coordinator.end_transaction("txn", producer_id, epoch, true),
+            ErrorCode::CoordinatorNotAvailable
+        );
+        drop(coordinator);
+
+        markers
+            .fail
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+        let coordinator = TransactionCoordinator::open(&log_dir, markers.clone()).unwrap();
+        assert_eq!(
+            coordinator.state.lock().unwrap().transactions["txn"].state,
+            TransactionState::PrepareCommit
+        );
+        // Neither new partitions nor the other outcome are taken meanwhile.
+        assert_eq!(
+            coordinator.add_partitions("txn", producer_id, epoch, [partition("orders", 1)]),
+            ErrorCode::ConcurrentTransactions
+        );
+        assert_eq!(
+            coordinator.end_transaction("txn", producer_id, epoch, false),
+            ErrorCode::InvalidTxnState
+        );
+        assert_eq!(
+            coordinator.end_transaction("txn", producer_id, epoch, true),
+            ErrorCode::None
+        );
+        assert_eq!(
+            *markers.written.lock().unwrap(),
+            [(partition("orders", 0), producer_id, true)]
+        );
+        drop(coordinator);
+
+        let (_, loaded) = TransactionLog::open(&log_dir).unwrap();
+        assert_eq!(loaded["txn"].state, TransactionState::CompleteCommit);
+        assert!(loaded["txn"].partitions.is_empty());
+        std::fs::remove_dir_all(&log_dir).unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+//! The `__transaction_state` topic, where each transactional id's producer
+//! and the transaction it has open outlive the broker. Records use Kafka's
+//! keys and values, one per transactional id, and compaction keeps only the
+//! latest; an id that is removed is written as a tombstone.
+//!
+//! Like `__consumer_offsets`, the topic has one partition and one segment,
+//! appended to by the transaction coordinator alone.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{ensure, Context, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use tracing::{info, warn};
+
+use super::offsets_topic::{
+    compact, encode_records, millis, put_string, read_i16, read_i32, read_i64, read_i8,
+    read_records, read_string, CompactedRecord, LogRecord, SEGMENT_FILE,
+};
+use super::TopicPartition;
+
+pub const TRANSACTION_STATE_TOPIC: &str = "__transaction_state";
+
+const TRANSACTION_LOG_KEY_VERSION: i16 = 0;
+/// The last value version that isn't flexible.
+const TRANSACTION_LOG_VALUE_VERSION: i16 = 0;
+
+/// Where a transactional id's transaction is, with Kafka's status codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TransactionState {
+    /// No transaction has started since the producer was initialized.
+    Empty,
+    Ongoing,
+    PrepareCommit,
+    PrepareAbort,
+    CompleteCommit,
+    CompleteAbort,
+    Dead,
+    PrepareEpochFence,
+}
+
+impl TransactionState {
+    fn code(self) -> i8 {
+        match self {
+            TransactionState::Empty => 0,
+            TransactionState::Ongoing => 1,
+            TransactionState::PrepareCommit => 2,
+            TransactionState::PrepareAbort => 3,
+            TransactionState::CompleteCommit => 4,
+            TransactionState::CompleteAbort => 5,
+            TransactionState::Dead => 6,
+            TransactionState::PrepareEpochFence => 7,
+        }
+    }
+
+    fn from_code(code: i8) -> Option<Self> {
+        Some(match code {
+            0 => TransactionState::Empty,
+            1 => TransactionState::Ongoing,
+            2 => TransactionState::PrepareCommit,
+            3 => TransactionState::PrepareAbort,
+            4 => TransactionState::CompleteCommit,
+            5 => TransactionState::CompleteAbort,
+            6 => TransactionState::Dead,
+            7 => TransactionState::PrepareEpochFence,
+            _ => return None,
+        })
+    }
+}
+
+/// A transactional id's producer, and the partitions of its open
+/// transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct TransactionMetadata {
+    pub(super) producer_id: i64,
+    pub(super) producer_epoch: i16,
+    pub(super) timeout: Duration,
+    pub(super) state: TransactionState,
+    pub(super) partitions: BTreeSet<TopicPartition>,
+    pub(super) last_update: SystemTime,
+    /// When the open transaction added its first partition.
+    pub(super) start: Option<SystemTime>,
+}
+
+/// A record in the transaction state topic; `None` deletes the id.
+#[derive(Debug, Clone)]
+pub(super) struct TransactionRecord {
+    pub(super) transactional_id: String,
+    pub(super) metadata: Option<TransactionMetadata>,
+}
+
+impl CompactedRecord for TransactionRecord {
+    fn key(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i16(TRANSACTION_LOG_KEY_VERSION);
+        put_string(&mut b, &self.transactional_id);
+        b.freeze()
+    }
+
+    fn value(&self) -> Option<Bytes> {
+        let metadata = self.metadata.as_ref()?;
+        let mut b = BytesMut::new();
+        b.put_i16(TRANSACTION_LOG_VALUE_VERSION);
+        b.put_i64(metadata.producer_id);
+        b.put_i16(metadata.producer_epoch);
+        b.put_i32(metadata.timeout.as_millis() as i32);
+        b.put_i8(metadata.state.code());
+        let mut topics: Vec<(&str, Vec<i32>)> = Vec::new();
+        for partition in &metadata.partitions {
+            match topics.last_mut() {
+                Some((topic, partitions)) if *topic == partition.topic => {
+                    partitions.push(partition.partition)
+                }
+                _ => topics.push((&partition.topic, vec![partition.partition])),
+            }
+        }
+        b.put_i32(topics.len() as i32);
+        for (topic, partitions) in topics {
+            put_string(&mut b, topic);
+            b.put_i32(partitions.len() as i32);
+            for partition in partitions {
+                b.put_i32(partition);
+            }
+        }
+        b.put_i64(millis(metadata.last_update));
+        b.put_i64(metadata.start.map_or(-1, millis));
+        Some(b.freeze())
+    }
+}
+
+impl TransactionRecord {
+    fn decode(mut key: Bytes, value: Option<Bytes>) -> Result<Option<Self>> {
+        if read_i16(&mut key)? != TRANSACTION_LOG_KEY_VERSION {
+            return Ok(None);
+        }
+        let transactional_id = read_string(&mut key)?;
+        let metadata = value.map(decode_transaction_value).transpose()?;
+        Ok(Some(TransactionRecord {
+            transactional_id,
+            metadata,
+        }))
+    }
+}
+
+fn decode_transaction_value(mut src: Bytes) -> Result<TransactionMetadata> {
+    let version = read_i16(&mut src)?;
+    ensure!(
+        version == TRANSACTION_LOG_VALUE_VERSION,
+        "unsupported transaction log value version {}",
+        version
+    );
+    let producer_id = read_i64(&mut src)?;
+    let producer_epoch = read_i16(&mut src)?;
+    let timeout = read_i32(&mut src)?;
+    let status = read_i8(&mut src)?;
+    let state = TransactionState::from_code(status)
+        .with_context(|| format!("unknown transaction status {}", status))?;
+    let mut partitions = BTreeSet::new();
+    for _ in 0..read_i32(&mut src)?.max(0) {
+        let topic = read_string(&mut src)?;
+        for _ in 0..read_i32(&mut src)?.max(0) {
+            partitions.insert(TopicPartition {
+                topic: topic.clone(),
+                partition: read_i32(&mut src)?,
+            });
+        }
+    }
+    let last_update = read_i64(&mut src)?;
+    let start = read_i64(&mut src)?;
+    let time = |ms: i64| UNIX_EPOCH + Duration::from_millis(ms.max(0) as u64);
+    Ok(TransactionMetadata {
+        producer_id,
+        producer_epoch,
+        timeout: Duration::from_millis(timeout.max(0) as u64),
+        state,
+        partitions,
+        last_update: time(last_update),
+        start: (start >= 0).then(|| time(start)),
+    })
+}
+
+/// The transaction state topic's only partition.
+pub(super) struct TransactionLog {
+    path: PathBuf,
+    file: File,
+    next_offset: i64,
+}
+
+impl TransactionLog {
+    /// Opens the partition under `log_dir` and replays it into each
+    /// transactional id's latest metadata, creating it if missing. The
+    /// segment is compacted first when most of its records are stale.
+    pub(super) fn open(log_dir: &Path) -> Result<(Self, HashMap<String, TransactionMetadata>)> {
+        let dir = log_dir.join(format!("{}-0", TRANSACTION_STATE_TOPIC));
+        std::fs::create_dir_all(&dir).with_context(|| format!("create '{}'", dir.display()))?;
+        let path = dir.join(SEGMENT_FILE);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("read '{}'", path.display())),
+        };
+
+        let mut loaded = HashMap::new();
+        let mut records = 0;
+        let mut next_offset = 0;
+        for LogRecord { offset, key, value } in read_records(Bytes::from(data))? {
+            records += 1;
+            next_offset = offset + 1;
+            match key.map(|key| TransactionRecord::decode(key, value)) {
+                Some(Ok(Some(TransactionRecord {
+                    transactional_id,
+                    metadata: Some(metadata),
+                }))) => {
+                    loaded.insert(transactional_id, metadata);
+                }
+                Some(Ok(Some(TransactionRecord {
+                    transactional_id,
+                    metadata: None,
+                }))) => {
+                    loaded.remove(&transactional_id);
+                }
+                Some(Ok(None)) | None => {}
+                Some(Err(e)) => {
+                    warn!(offset, error = %e, "skipping unreadable transaction record");
+                }
+            }
+        }
+
+        let live: Vec<_> = loaded
+            .iter()
+            .map(|(transactional_id, metadata)| TransactionRecord {
+                transactional_id: transactional_id.clone(),
+                metadata: Some(metadata.clone()),
+            })
+            .collect();
+        if records > 2 * live.len() {
+            compact(&path, &live, next_offset)?;
+            info!(
+                records,
+                live = live.len(),
+                "compacted transaction state topic"
+            );
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("open '{}'", path.display()))?;
+        info!(transactions = loaded.len(), "loaded transaction state");
+        Ok((
+            Self {
+                path,
+                file,
+                next_offset,
+            },
+            loaded,
+        ))
+    }
+
+    /// Appends `records` as one batch. Nothing is written if it fails.
+    pub(super) fn append(&mut self, records: &[TransactionRecord]) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let batch = encode_records(self.next_offset, records, SystemTime::now());
+        self.file
+            .write_all(&batch)
+            .with_context(|| format!("append to '{}'", self.path.display()))?;
+        self.next_offset += records.len() as i64;
+        Ok(())
+    }
+}
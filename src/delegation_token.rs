@@ -0,0 +1,268 @@
+//! Delegation tokens, as in KIP-48: short-lived credentials a SASL user
+//! issues for itself or on behalf of another. A token is a random id and the
+//! HMAC of that id under `delegation.token.secret.key`; clients authenticate
+//! with it over SCRAM, the id as the user name and the base64 HMAC as the
+//! password.
+//!
+//! Tokens are kept in the memory of the broker that issued them.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::Sha512;
+
+use crate::config::Config;
+use crate::protocol::ErrorCode;
+use crate::request_context::RequestContext;
+use crate::scram::{ScramCredential, ScramMechanism, DEFAULT_SCRAM_ITERATIONS};
+use crate::security::KafkaPrincipal;
+
+/// The secret tokens are signed with and how long they last.
+#[derive(Clone, PartialEq)]
+pub struct DelegationTokenSettings {
+    pub secret: String,
+    /// The longest a token can be renewed for, from
+    /// `delegation.token.max.lifetime.ms`.
+    pub max_lifetime: Duration,
+    /// How long a token lasts between renewals when the request doesn't say,
+    /// from `delegation.token.expiry.time.ms`.
+    pub expiry_time: Duration,
+}
+
+impl fmt::Debug for DelegationTokenSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DelegationTokenSettings")
+            .field("secret", &"[hidden]")
+            .field("max_lifetime", &self.max_lifetime)
+            .field("expiry_time", &self.expiry_time)
+            .finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct DelegationToken {
+    pub token_id: String,
+    pub hmac: Vec<u8>,
+    /// Whom clients authenticating with the token act as.
+    pub owner: KafkaPrincipal,
+    /// Who created the token, the owner unless created on its behalf.
+    pub requester: KafkaPrincipal,
+    /// Besides the owner, who may renew and expire the token.
+    pub renewers: Vec<KafkaPrincipal>,
+    pub issue_timestamp_ms: i64,
+    pub expiry_timestamp_ms: i64,
+    pub max_timestamp_ms: i64,
+    /// The SCRAM credential of each mechanism, derived from the HMAC.
+    credentials: HashMap<ScramMechanism, ScramCredential>,
+}
+
+impl DelegationToken {
+    /// Whether `principal` may renew, expire and describe the token.
+    pub fn is_renewer(&self, principal: &KafkaPrincipal) -> bool {
+        self.owner == *principal || self.renewers.contains(principal)
+    }
+}
+
+/// The tokens this broker has issued. With no settings, tokens are disabled
+/// and every request for one fails with DELEGATION_TOKEN_AUTH_DISABLED.
+pub struct DelegationTokenManager {
+    settings: Option<DelegationTokenSettings>,
+    tokens: Mutex<HashMap<String, DelegationToken>>,
+}
+
+impl DelegationTokenManager {
+    pub fn new(settings: Option<DelegationTokenSettings>) -> Self {
+        Self {
+            settings,
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.settings.is_some()
+    }
+
+    /// Issues a token for `owner` that lasts until renewed, for at most
+    /// `max_lifetime_ms`, or the configured maximum when that is not
+    /// positive.
+    pub fn create(
+        &self,
+        owner: KafkaPrincipal,
+        requester: KafkaPrincipal,
+        renewers: Vec<KafkaPrincipal>,
+        max_lifetime_ms: i64,
+    ) -> Result<DelegationToken, ErrorCode> {
+        let settings = self.settings()?;
+        let now = now_ms();
+        let max_lifetime_ms = match max_lifetime_ms {
+            ms if ms > 0 => ms.min(settings.max_lifetime.as_millis() as i64),
+            _ => settings.max_lifetime.as_millis() as i64,
+        };
+        let max_timestamp_ms = now + max_lifetime_ms;
+        let token_id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(22)
+            .map(char::from)
+            .collect();
+        let hmac = sign(&settings.secret, &token_id);
+        let password = BASE64.encode(&hmac);
+        let credentials = ScramMechanism::ALL
+            .into_iter()
+            .map(|mechanism| {
+                let credential =
+                    ScramCredential::generate(mechanism, &password, DEFAULT_SCRAM_ITERATIONS);
+                (mechanism, credential)
+            })
+            .collect();
+        let token = DelegationToken {
+            token_id: token_id.clone(),
+            hmac,
+            owner,
+            requester,
+            renewers,
+            issue_timestamp_ms: now,
+            expiry_timestamp_ms: max_timestamp_ms
+                .min(now + settings.expiry_time.as_millis() as i64),
+            max_timestamp_ms,
+            credentials,
+        };
+        self.tokens.lock().unwrap().insert(token_id, token.clone());
+        Ok(token)
+    }
+
+    /// Extends the token with `hmac` by `renew_period_ms`, or by the
+    /// configured expiry time when that is negative, up to its maximum
+    /// lifetime. Returns its new expiry timestamp.
+    pub fn renew(
+        &self,
+        hmac: &[u8],
+        requester: &KafkaPrincipal,
+        renew_period_ms: i64,
+    ) -> Result<i64, ErrorCode> {
+        let settings = self.settings()?;
+        let renew_period_ms = match renew_period_ms {
+            ms if ms < 0 => settings.expiry_time.as_millis() as i64,
+            ms => ms,
+        };
+        self.update(hmac, requester, |token, now| {
+            token.expiry_timestamp_ms = token.max_timestamp_ms.min(now + renew_period_ms);
+            Ok(token.expiry_timestamp_ms)
+        })
+    }
+
+    /// Makes the token with `hmac` expire in `expiry_time_period_ms`, or
+    /// straight away when that is negative. Returns its new expiry
+    /// timestamp.
+    pub fn expire(
+        &self,
+        hmac: &[u8],
+        requester: &KafkaPrincipal,
+        expiry_time_period_ms: i64,
+    ) -> Result<i64, ErrorCode> {
+        self.settings()?;
+        self.update(hmac, requester, |token, now| {
+            token.expiry_timestamp_ms = token
+                .max_timestamp_ms
+                .min(now + expiry_time_period_ms.max(0));
+            Ok(token.expiry_timestamp_ms)
+        })
+    }
+
+    /// The live tokens owned by one of `owners`, or by anyone for `None`,
+    /// that `visible` lets the requester see.
+    pub fn describe(
+        &self,
+        owners: Option<&[KafkaPrincipal]>,
+        visible: impl Fn(&DelegationToken) -> bool,
+    ) -> Result<Vec<DelegationToken>, ErrorCode> {
+        self.settings()?;
+        let mut tokens = self.tokens.lock().unwrap();
+        expire_tokens(&mut tokens, now_ms());
+        let mut described: Vec<DelegationToken> = tokens
+            .values()
+            .filter(|token| owners.is_none_or(|owners| owners.contains(&token.owner)))
+            .filter(|token| visible(token))
+            .cloned()
+            .collect();
+        described.sort_by_key(|token| token.issue_timestamp_ms);
+        Ok(described)
+    }
+
+    /// The SCRAM credential of a live token and the owner a client
+    /// authenticating with it acts as.
+    pub fn scram_credential(
+        &self,
+        mechanism: ScramMechanism,
+        token_id: &str,
+    ) -> Option<(ScramCredential, KafkaPrincipal)> {
+        let mut tokens = self.tokens.lock().unwrap();
+        expire_tokens(&mut tokens, now_ms());
+        let token = tokens.get(token_id)?;
+        let credential = token.credentials.get(&mechanism)?.clone();
+        Some((credential, token.owner.clone()))
+    }
+
+    fn settings(&self) -> Result<&DelegationTokenSettings, ErrorCode> {
+        self.settings
+            .as_ref()
+            .ok_or(ErrorCode::DelegationTokenAuthDisabled)
+    }
+
+    /// Applies `change` to the live token with `hmac`, if `requester` may
+    /// change it.
+    fn update(
+        &self,
+        hmac: &[u8],
+        requester: &KafkaPrincipal,
+        change: impl FnOnce(&mut DelegationToken, i64) -> Result<i64, ErrorCode>,
+    ) -> Result<i64, ErrorCode> {
+        let now = now_ms();
+        let mut tokens = self.tokens.lock().unwrap();
+        expire_tokens(&mut tokens, now);
+        let token = tokens
+            .values_mut()
+            .find(|token| token.hmac == hmac)
+            .ok_or(ErrorCode::DelegationTokenNotFound)?;
+        if !token.is_renewer(requester) {
+            return Err(ErrorCode::DelegationTokenOwnerMismatch);
+        }
+        change(token, now)
+    }
+}
+
+/// Whether the connection may create, renew, expire and describe tokens:
+/// only clients that authenticated over SASL with something other than a
+/// token may.
+pub fn token_requests_allowed(config: &Config, ctx: &RequestContext) -> bool {
+    !ctx.token_authenticated
+        && config
+            .listeners
+            .iter()
+            .find(|endpoint| endpoint.listener_name == ctx.listener_name)
+            .is_some_and(|endpoint| endpoint.security_protocol.uses_sasl())
+}
+
+/// Forgets tokens past their expiry.
+fn expire_tokens(tokens: &mut HashMap<String, DelegationToken>, now: i64) {
+    tokens.retain(|_, token| token.expiry_timestamp_ms > now);
+}
+
+fn sign(secret: &str, token_id: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha512>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key");
+    mac.update(token_id.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
@@ -0,0 +1,314 @@
+//! A broker run inside the current process, on ports the OS picks and over a
+//! log directory of its own that goes away with it, for tests and tools that
+//! need a real broker to talk to.
+
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use tokio::{sync::oneshot, task::JoinHandle};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::api::cluster_metadata::{ConfigValue, RecordBatches, TopicValue, TOPIC_CONFIG_RESOURCE};
+use crate::client::{Admin, AdminConfig};
+use crate::config::Config;
+use crate::controller::partition_records;
+use crate::features::{bootstrap_features, METADATA_VERSION_LATEST};
+use crate::log_level::LogLevel;
+use crate::log_manager::create_partition_dir;
+use crate::protocol::{cluster_metadata_log_file, CompactNullableString, ErrorCode, Uuid};
+use crate::record_batch::{encode_batch, BatchRecord};
+use crate::server::{bind_listeners, serve_listeners};
+
+/// The node id of every ephemeral broker; each is a cluster of its own.
+const NODE_ID: i32 = 1;
+
+const SEGMENT_FILE: &str = "00000000000000000000.log";
+
+/// How long `start_ephemeral` waits for the broker to register and lead
+/// its partitions.
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+const READY_POLL: Duration = Duration::from_millis(20);
+
+/// A topic an ephemeral broker starts with. Every partition has the broker
+/// as its only replica.
+#[derive(Default)]
+pub struct FixtureTopic {
+    pub name: String,
+    pub partitions: i32,
+    pub configs: BTreeMap<String, String>,
+    /// The records partitions start with, by partition index, from offset 0.
+    pub records: BTreeMap<i32, Vec<BatchRecord>>,
+}
+
+/// A broker and controller that make up a cluster of one, listening on
+/// 127.0.0.1. It runs until `shutdown`, or is stopped abruptly when dropped;
+/// either way its log directory is removed.
+pub struct Broker {
+    address: SocketAddr,
+    log_dir: PathBuf,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<Result<()>>>,
+}
+
+impl Broker {
+    /// Starts a broker in a new temporary log directory with `topics`
+    /// already created. By the time this returns the broker has registered
+    /// with its controller and leads every partition, so clients can
+    /// connect, produce and fetch straight away.
+    pub async fn start_ephemeral(topics: &[FixtureTopic]) -> Result<Self> {
+        let log_dir =
+            std::env::temp_dir().join(format!("kafka-ephemeral-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&log_dir)
+            .with_context(|| format!("create '{}'", log_dir.display()))?;
+        let res = Self::start_in(log_dir.clone(), topics).await;
+        if res.is_err() {
+            let _ = std::fs::remove_dir_all(&log_dir);
+        }
+        res
+    }
+
+    async fn start_in(log_dir: PathBuf, topics: &[FixtureTopic]) -> Result<Self> {
+        seed(&log_dir, topics)?;
+        let dir = log_dir.display().to_string();
+        let properties = [
+            ("node.id", NODE_ID.to_string()),
+            ("process.roles", "broker,controller".to_string()),
+            (
+                "listeners",
+                "PLAINTEXT://127.0.0.1:0,CONTROLLER://127.0.0.1:0".to_string(),
+            ),
+            (
+                "listener.security.protocol.map",
+                "PLAINTEXT:PLAINTEXT,CONTROLLER:PLAINTEXT".to_string(),
+            ),
+            ("controller.listener.names", "CONTROLLER".to_string()),
+            (
+                "controller.quorum.voters",
+                format!("{}@127.0.0.1:0", NODE_ID),
+            ),
+            // The only voter has nobody to wait for.
+            ("controller.quorum.election.timeout.ms", "50".to_string()),
+            ("broker.heartbeat.interval.ms", "200".to_string()),
+            ("log.dirs", dir.clone()),
+            ("metadata.log.dir", dir),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+        let mut config = Config::from_properties(properties)?;
+        let sockets = bind_listeners(&mut config).await?;
+        let port = config
+            .listeners
+            .iter()
+            .find(|l| l.listener_name == "PLAINTEXT")
+            .ok_or_else(|| anyhow!("no PLAINTEXT listener"))?
+            .port;
+
+        // Nothing reloads the filter of an embedded broker; logging is
+        // whatever subscriber the embedding process installed.
+        let (_, handle) = reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new("info"));
+        let (shutdown, stopped) = oneshot::channel();
+        let task = tokio::spawn(serve_listeners(
+            config,
+            sockets,
+            None,
+            Arc::new(LogLevel::new(handle)),
+            async {
+                let _ = stopped.await;
+                Ok(())
+            },
+        ));
+        let mut broker = Self {
+            address: SocketAddr::from(([127, 0, 0, 1], port)),
+            log_dir,
+            shutdown: Some(shutdown),
+            task: Some(task),
+        };
+        // Dropped on failure, which stops the broker.
+        broker.wait_until_ready().await?;
+        Ok(broker)
+    }
+
+    /// Waits for the metadata log to show this broker registered and
+    /// unfenced, and leading every partition, and for the broker to serve
+    /// that metadata. Until then its controller turns down topic changes
+    /// and its partitions have no leader.
+    async fn wait_until_ready(&mut self) -> Result<()> {
+        let metadata_log = cluster_metadata_log_file(&self.log_dir);
+        let mut admin = Admin::new(AdminConfig {
+            bootstrap_servers: vec![self.bootstrap_servers()],
+            ..Default::default()
+        })?;
+        let deadline = tokio::time::Instant::now() + READY_TIMEOUT;
+        loop {
+            // The log may be read mid-write; the next poll sees all of it.
+            if let Ok(metadata) = RecordBatches::from_file(&metadata_log) {
+                if is_ready(&metadata) && serves_ready(&mut admin, &metadata).await {
+                    return Ok(());
+                }
+            }
+            if self.task.as_ref().is_some_and(|task| task.is_finished()) {
+                let task = self.task.take().expect("task is set");
+                task.await.context("broker task failed")??;
+                bail!("broker stopped before it was ready");
+            }
+            if tokio::time::Instant::now() >= deadline {
+                bail!("broker not ready after {:?}", READY_TIMEOUT);
+            }
+            tokio::time::sleep(READY_POLL).await;
+        }
+    }
+
+    /// Where clients connect.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The address as a `bootstrap.servers` entry.
+    pub fn bootstrap_servers(&self) -> String {
+        self.address.to_string()
+    }
+
+    pub fn log_dir(&self) -> &Path {
+        &self.log_dir
+    }
+
+    /// Stops the broker the way a signal stops the binary, waits for it and
+    /// removes its log directory. An error the broker stopped with, e.g.
+    /// one it failed to start with, is returned.
+    pub async fn shutdown(mut self) -> Result<()> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let res = match self.task.take() {
+            Some(task) => task.await.context("broker task failed")?,
+            None => Ok(()),
+        };
+        std::fs::remove_dir_all(&self.log_dir)
+            .with_context(|| format!("remove '{}'", self.log_dir.display()))?;
+        res
+    }
+}
+
+impl Drop for Broker {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+            let _ = std::fs::remove_dir_all(&self.log_dir);
+        }
+    }
+}
+
+/// Whether the broker is live and leads every partition in `metadata`.
+fn is_ready(metadata: &RecordBatches) -> bool {
+    metadata.live_brokers().any(|b| b.broker_id == NODE_ID)
+        && metadata.topics().all(|topic| {
+            metadata
+                .partitions(&topic.topic_id)
+                .all(|p| p.leader_id == NODE_ID as u32)
+        })
+}
+
+/// Whether the broker answers Metadata requests with every topic in
+/// `metadata` and itself as the leader of each partition. The metadata it
+/// serves is only published once it notices the log change.
+async fn serves_ready(admin: &mut Admin, metadata: &RecordBatches) -> bool {
+    let Ok(topics) = admin.describe_topics(None).await else {
+        return false;
+    };
+    topics.len() == metadata.topics().count()
+        && topics.iter().all(|topic| {
+            topic.error_code == ErrorCode::None
+                && topic
+                    .partitions
+                    .0
+                    .iter()
+                    .all(|p| p.error_code == ErrorCode::None && p.leader_id == NODE_ID as u32)
+        })
+}
+
+/// Writes the metadata log finalizing the latest feature levels and
+/// creating `topics`, and the first segment of each partition, empty for
+/// those without fixture records.
+fn seed(log_dir: &Path, topics: &[FixtureTopic]) -> Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    let mut records: Vec<BatchRecord> = bootstrap_features().iter().map(|f| f.record()).collect();
+    for topic in topics {
+        if topic.partitions <= 0 {
+            bail!(
+                "fixture topic '{}' needs at least one partition",
+                topic.name
+            );
+        }
+        let topic_id = Uuid::random();
+        records.push(
+            TopicValue {
+                topic_name: CompactNullableString(Some(topic.name.clone())),
+                topic_id: topic_id.clone(),
+            }
+            .record(),
+        );
+        let assignments = vec![vec![NODE_ID]; topic.partitions as usize];
+        records.extend(partition_records(
+            &topic_id,
+            0,
+            &assignments,
+            METADATA_VERSION_LATEST,
+        ));
+        records.extend(topic.configs.iter().map(|(name, value)| {
+            ConfigValue {
+                resource_type: TOPIC_CONFIG_RESOURCE,
+                resource_name: topic.name.clone(),
+                name: name.clone(),
+                value: Some(value.clone()),
+            }
+            .record()
+        }));
+        if let Some(&partition) = topic
+            .records
+            .keys()
+            .find(|p| !(0..topic.partitions).contains(p))
+        {
+            bail!(
+                "fixture topic '{}' has no partition {}",
+                topic.name,
+                partition
+            );
+        }
+        for partition in 0..topic.partitions {
+            let dir = log_dir.join(format!("{}-{}", topic.name, partition));
+            create_partition_dir(&dir, &topic_id.0)?;
+            let path = dir.join(SEGMENT_FILE);
+            match topic.records.get(&partition) {
+                Some(batch) if !batch.is_empty() => write_segment(&path, batch, timestamp)?,
+                _ => write_empty_segment(&path)?,
+            }
+        }
+    }
+    write_segment(&cluster_metadata_log_file(log_dir), &records, timestamp)
+}
+
+fn write_segment(path: &Path, records: &[BatchRecord], timestamp: i64) -> Result<()> {
+    write_file(path, &encode_batch(0, 0, 0, records, timestamp))
+}
+
+/// The segment of a partition nothing has been written to, as CreateTopics
+/// leaves it.
+fn write_empty_segment(path: &Path) -> Result<()> {
+    write_file(path, &[])
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("create '{}'", dir.display()))?;
+    }
+    std::fs::write(path, contents).with_context(|| format!("write '{}'", path.display()))
+}
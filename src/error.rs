@@ -0,0 +1,81 @@
+//! Why a request failed, as the connection serving it needs to know: which
+//! error code answers it, and whether the connection can carry on.
+
+use std::io::ErrorKind;
+
+use crate::protocol::{ApiKey, DecodeError, ErrorCode};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A request header that can't be decoded. Without its correlation id
+    /// there is nothing to answer.
+    #[error("malformed request header")]
+    MalformedHeader,
+    /// A request for an API this broker doesn't serve, or doesn't serve on
+    /// the listener it came in on. Kafka closes the connection for these.
+    #[error("{0}")]
+    UnsupportedApi(String),
+    /// A request at a version of its API this broker doesn't parse, which is
+    /// turned away before its body is decoded. As in Kafka, the connection
+    /// is closed, as there is no layout to answer it in.
+    #[error("{0:?} v{1} is not supported")]
+    UnsupportedVersion(ApiKey, i16),
+    /// A request body that doesn't decode. As in Kafka, the connection is
+    /// closed, as what else it holds can't be trusted either.
+    #[error("malformed {0:?} request: {1}")]
+    Decode(ApiKey, DecodeError),
+    /// Reading or writing a log on disk.
+    #[error("storage error: {0:#}")]
+    Storage(anyhow::Error),
+    /// The group coordinator couldn't serve the request.
+    #[error("group coordinator: {0}")]
+    Coordinator(String),
+    /// The connection's own reads and writes.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The client went away before the request was answered.
+    #[error("client disconnected")]
+    Cancelled,
+    /// A handler that panicked, with the reason it gave.
+    #[error("request handler panicked: {0}")]
+    Panic(String),
+}
+
+impl Error {
+    /// The code a response to the failed request carries.
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            Self::MalformedHeader | Self::Decode(..) => ErrorCode::InvalidRequest,
+            Self::UnsupportedApi(_) | Self::UnsupportedVersion(..) => ErrorCode::UnsupportedVersion,
+            Self::Storage(_) => ErrorCode::KafkaStorageError,
+            Self::Coordinator(_) => ErrorCode::CoordinatorNotAvailable,
+            Self::Io(_) | Self::Cancelled | Self::Panic(_) => ErrorCode::UnknownServerError,
+        }
+    }
+
+    /// Whether the connection carries on after answering the request with
+    /// [`Error::error_code`]. Only failures that leave nothing to answer, or
+    /// nobody to answer, close it.
+    pub fn keeps_connection(&self) -> bool {
+        match self {
+            Self::MalformedHeader
+            | Self::UnsupportedApi(_)
+            | Self::UnsupportedVersion(..)
+            | Self::Decode(..) => false,
+            Self::Cancelled => false,
+            Self::Io(e) => !is_disconnect(e),
+            Self::Storage(_) | Self::Coordinator(_) | Self::Panic(_) => true,
+        }
+    }
+}
+
+/// Whether `e` is the peer going away rather than something going wrong.
+pub fn is_disconnect(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+    )
+}
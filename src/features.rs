@@ -0,0 +1,143 @@
+//! Feature levels, as in KIP-584: each feature a broker supports has a range
+//! of levels, and the level in use cluster-wide is finalized in the metadata
+//! log by FeatureLevelRecords, which UpdateFeatures appends. Behavior that
+//! changed between levels follows the finalized one, so a cluster keeps
+//! writing what its oldest broker can read until it is upgraded.
+
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use crate::api::cluster_metadata::{BrokerFeature, FeatureLevelValue, RecordBatches};
+use crate::protocol::ApiKey;
+
+pub const METADATA_VERSION: &str = "metadata.version";
+pub const GROUP_VERSION: &str = "group.version";
+pub const SHARE_VERSION: &str = "share.version";
+
+/// metadata.version levels, numbered as Kafka's MetadataVersion. 3.0-IV1 is
+/// the first KRaft release, and the level of a log that finalizes none.
+pub const METADATA_VERSION_3_0_IV1: i16 = 1;
+/// Fencing is recorded in BrokerRegistrationChangeRecords and brokers
+/// register with RegisterBrokerRecord version 1.
+pub const METADATA_VERSION_3_3_IV3: i16 = 7;
+/// PartitionRecords carry each replica's log directory.
+pub const METADATA_VERSION_3_7_IV2: i16 = 17;
+/// 3.9-IV0.
+pub const METADATA_VERSION_LATEST: i16 = 21;
+
+/// A feature this broker supports, and the levels of it.
+pub struct SupportedFeature {
+    pub name: &'static str,
+    pub min_level: i16,
+    pub max_level: i16,
+}
+
+pub const SUPPORTED_FEATURES: &[SupportedFeature] = &[
+    SupportedFeature {
+        name: METADATA_VERSION,
+        min_level: METADATA_VERSION_3_0_IV1,
+        max_level: METADATA_VERSION_LATEST,
+    },
+    // Level 1 turns on the consumer group protocol of KIP-848.
+    SupportedFeature {
+        name: GROUP_VERSION,
+        min_level: 0,
+        max_level: 1,
+    },
+    // Level 1 turns on the share groups of KIP-932.
+    SupportedFeature {
+        name: SHARE_VERSION,
+        min_level: 0,
+        max_level: 1,
+    },
+];
+
+pub fn supported_feature(name: &str) -> Option<&'static SupportedFeature> {
+    SUPPORTED_FEATURES.iter().find(|f| f.name == name)
+}
+
+/// The features this broker registers with the controller as supported.
+pub fn broker_features() -> Vec<BrokerFeature> {
+    SUPPORTED_FEATURES
+        .iter()
+        .map(|f| BrokerFeature {
+            name: f.name.to_string(),
+            min_supported_version: f.min_level,
+            max_supported_version: f.max_level,
+        })
+        .collect()
+}
+
+/// The levels a new cluster starts at: the latest of each feature.
+pub fn bootstrap_features() -> Vec<FeatureLevelValue> {
+    SUPPORTED_FEATURES
+        .iter()
+        .map(|f| FeatureLevelValue {
+            name: f.name.to_string(),
+            level: f.max_level,
+        })
+        .collect()
+}
+
+/// The finalized level of each feature, as of some offset of the metadata
+/// log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FinalizedFeatures {
+    /// The offset of the last FeatureLevelRecord, or -1 without one.
+    pub epoch: i64,
+    /// Features at level 0 are left out.
+    pub levels: BTreeMap<String, i16>,
+}
+
+impl Default for FinalizedFeatures {
+    fn default() -> Self {
+        Self {
+            epoch: -1,
+            levels: BTreeMap::new(),
+        }
+    }
+}
+
+impl FinalizedFeatures {
+    /// The finalized level of `name`, 0 if it has none.
+    pub fn level(&self, name: &str) -> i16 {
+        self.levels.get(name).copied().unwrap_or(0)
+    }
+
+    pub fn metadata_version(&self) -> i16 {
+        self.levels
+            .get(METADATA_VERSION)
+            .copied()
+            .unwrap_or(METADATA_VERSION_3_0_IV1)
+    }
+
+    /// Whether `api_key` is served at these levels.
+    pub fn enables(&self, api_key: ApiKey) -> bool {
+        match api_key {
+            ApiKey::ConsumerGroupHeartbeat => self.level(GROUP_VERSION) >= 1,
+            ApiKey::ShareGroupHeartbeat | ApiKey::ShareFetch => self.level(SHARE_VERSION) >= 1,
+            _ => true,
+        }
+    }
+}
+
+/// The finalized features as of the last metadata this broker read.
+#[derive(Default)]
+pub struct FeatureCache {
+    finalized: RwLock<FinalizedFeatures>,
+}
+
+impl FeatureCache {
+    pub fn update(&self, metadata: &RecordBatches) {
+        let finalized = metadata.finalized_features();
+        let mut current = self.finalized.write().unwrap();
+        if *current != finalized {
+            tracing::info!(epoch = finalized.epoch, levels = ?finalized.levels, "finalized features changed");
+            *current = finalized;
+        }
+    }
+
+    pub fn finalized(&self) -> FinalizedFeatures {
+        self.finalized.read().unwrap().clone()
+    }
+}
@@ -0,0 +1,249 @@
+//! Incremental fetch sessions, as in KIP-227: a fetcher names the partitions
+//! it follows once, then sends only the ones whose fetch position moved and
+//! hears back only about the ones with something new.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound::{Excluded, Unbounded};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::fetch::{Partition, TopicPartition, TopicRequest, TopicResponse};
+use crate::protocol::{ErrorCode, Uuid};
+
+/// The epoch of a fetch that opens a session.
+pub const INITIAL_EPOCH: i32 = 0;
+/// The epoch of a fetch that uses no session, closing the one it names.
+pub const FINAL_EPOCH: i32 = -1;
+
+/// How a fetch is answered, given the session it names.
+pub enum SessionFetch {
+    /// Every partition requested is answered, and none remembered.
+    Sessionless,
+    /// Every partition requested is answered, and the new session remembers
+    /// them.
+    Full { session_id: u32 },
+    /// Every partition of the session is fetched, but only those with
+    /// something new are answered.
+    Incremental {
+        session_id: u32,
+        topics: Vec<TopicRequest>,
+    },
+}
+
+impl SessionFetch {
+    /// The session id the response carries, 0 for none.
+    pub fn session_id(&self) -> u32 {
+        match self {
+            Self::Sessionless => 0,
+            Self::Full { session_id } | Self::Incremental { session_id, .. } => *session_id,
+        }
+    }
+}
+
+/// The fetch sessions this broker serves, up to `slots` of them. Opening one
+/// more evicts the session used least recently.
+pub struct FetchSessionCache {
+    slots: usize,
+    sessions: Mutex<HashMap<u32, Session>>,
+}
+
+struct Session {
+    /// The epoch the next fetch in the session must carry.
+    next_epoch: i32,
+    partitions: BTreeMap<(String, i32), CachedPartition>,
+    /// The partition the next fetch starts from: the one after the last to
+    /// get records, so that partitions left out when a fetch's max bytes ran
+    /// out are served first next time.
+    first: Option<(String, i32)>,
+    last_used: Instant,
+}
+
+/// A partition of a session: how it is fetched, and what the fetcher was
+/// last told about it.
+struct CachedPartition {
+    fetch: Partition,
+    high_watermark: i64,
+    last_stable_offset: i64,
+    log_start_offset: i64,
+}
+
+impl CachedPartition {
+    fn new(fetch: Partition) -> Self {
+        Self {
+            fetch,
+            high_watermark: -1,
+            last_stable_offset: -1,
+            log_start_offset: -1,
+        }
+    }
+
+    /// Records what `answer` tells the fetcher, returning whether it tells
+    /// it anything new.
+    fn update(&mut self, answer: &TopicPartition) -> bool {
+        let changed = answer.error_code != ErrorCode::None
+            || !answer.records.is_empty()
+            || answer.high_watermark != self.high_watermark
+            || answer.last_stable_offset != self.last_stable_offset
+            || answer.log_start_offset != self.log_start_offset;
+        self.high_watermark = answer.high_watermark;
+        self.last_stable_offset = answer.last_stable_offset;
+        self.log_start_offset = answer.log_start_offset;
+        changed
+    }
+}
+
+impl FetchSessionCache {
+    pub fn new(slots: usize) -> Self {
+        Self {
+            slots,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Resolves the session a fetch names. A fetch at the final epoch closes
+    /// it, one at the initial epoch replaces it with a new session of the
+    /// partitions in `topics`, and any other must carry the session's next
+    /// epoch: its `topics` are added to the session, `forgotten` partitions
+    /// dropped from it, and the whole session fetched, starting after the
+    /// last partition to get records.
+    pub fn begin(
+        &self,
+        session_id: u32,
+        epoch: i32,
+        topics: &[TopicRequest],
+        forgotten: impl IntoIterator<Item = (String, i32)>,
+    ) -> Result<SessionFetch, ErrorCode> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if epoch == FINAL_EPOCH || epoch == INITIAL_EPOCH {
+            sessions.remove(&session_id);
+        }
+        if epoch == FINAL_EPOCH || (epoch == INITIAL_EPOCH && self.slots == 0) {
+            return Ok(SessionFetch::Sessionless);
+        }
+        if epoch == INITIAL_EPOCH {
+            if sessions.len() >= self.slots {
+                let oldest = sessions
+                    .iter()
+                    .min_by_key(|(_, session)| session.last_used)
+                    .map(|(id, _)| *id);
+                if let Some(oldest) = oldest {
+                    sessions.remove(&oldest);
+                }
+            }
+            let session_id = loop {
+                let id = rand::random::<u32>() & i32::MAX as u32;
+                if id != 0 && !sessions.contains_key(&id) {
+                    break id;
+                }
+            };
+            let mut session = Session {
+                next_epoch: INITIAL_EPOCH + 1,
+                partitions: BTreeMap::new(),
+                first: None,
+                last_used: Instant::now(),
+            };
+            session.add(topics);
+            sessions.insert(session_id, session);
+            return Ok(SessionFetch::Full { session_id });
+        }
+
+        let Some(session) = sessions.get_mut(&session_id) else {
+            return Err(ErrorCode::FetchSessionIdNotFound);
+        };
+        if epoch != session.next_epoch {
+            return Err(ErrorCode::InvalidFetchSessionEpoch);
+        }
+        // Epochs wrap around to 1, never back to the initial epoch.
+        session.next_epoch = epoch.checked_add(1).unwrap_or(1);
+        session.last_used = Instant::now();
+        session.add(topics);
+        for key in forgotten {
+            session.partitions.remove(&key);
+        }
+
+        let mut topics: Vec<TopicRequest> = Vec::new();
+        for ((topic_id, _), cached) in session.rotated() {
+            match topics.last_mut() {
+                Some(topic) if topic.topic_id.0 == *topic_id => {
+                    topic.partitions.push(cached.fetch.clone())
+                }
+                _ => topics.push(TopicRequest::new(
+                    topic_id.clone(),
+                    vec![cached.fetch.clone()],
+                )),
+            }
+        }
+        Ok(SessionFetch::Incremental { session_id, topics })
+    }
+
+    /// Remembers what `responses` tell the fetcher of each partition of its
+    /// session, and for an incremental fetch leaves out the partitions they
+    /// tell nothing new. The next fetch of the session starts after the last
+    /// partition they send records for.
+    pub fn complete(
+        &self,
+        fetch: &SessionFetch,
+        mut responses: Vec<TopicResponse>,
+    ) -> Vec<TopicResponse> {
+        let incremental = matches!(fetch, SessionFetch::Incremental { .. });
+        let mut sessions = self.sessions.lock().unwrap();
+        // The session may have been evicted while the fetch was served.
+        let Some(session) = sessions.get_mut(&fetch.session_id()) else {
+            return responses;
+        };
+        let mut last_with_records = None;
+        for topic in &mut responses {
+            let Uuid(topic_id) = &topic.topic_id;
+            topic.partitions.0.retain(|answer| {
+                let key = (topic_id.clone(), answer.partition_index);
+                if !answer.records.is_empty() {
+                    last_with_records = Some(key.clone());
+                }
+                match session.partitions.get_mut(&key) {
+                    Some(cached) => cached.update(answer) || !incremental,
+                    None => true,
+                }
+            });
+        }
+        if let Some(last) = last_with_records {
+            session.first = session
+                .partitions
+                .range((Excluded(last), Unbounded))
+                .next()
+                .map(|(key, _)| key.clone());
+        }
+        if incremental {
+            responses.retain(|topic| !topic.partitions.0.is_empty());
+        }
+        responses
+    }
+}
+
+impl Session {
+    /// The partitions of the session, starting from `first`.
+    fn rotated(&self) -> impl Iterator<Item = (&(String, i32), &CachedPartition)> {
+        let before_first =
+            |(key, _): &(&(String, i32), _)| self.first.as_ref().is_some_and(|first| *key < first);
+        let partitions = self.partitions.iter();
+        partitions
+            .clone()
+            .skip_while(before_first)
+            .chain(partitions.take_while(before_first))
+    }
+
+    /// Adds the partitions of `topics`, or updates how they are fetched.
+    fn add(&mut self, topics: &[TopicRequest]) {
+        for topic in topics {
+            for fetch in &topic.partitions {
+                let key = (topic.topic_id.0.clone(), fetch.partition_index);
+                match self.partitions.get_mut(&key) {
+                    Some(cached) => cached.fetch = fetch.clone(),
+                    None => {
+                        self.partitions
+                            .insert(key, CachedPartition::new(fetch.clone()));
+                    }
+                }
+            }
+        }
+    }
+}
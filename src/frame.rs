@@ -0,0 +1,136 @@
+//! The size-prefixed frames every request and response is sent in.
+
+use anyhow::{anyhow, bail, Result};
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// How much room a decoder starts with, enough for most requests and
+/// responses without growing.
+const INITIAL_CAPACITY: usize = 4096;
+
+/// Splits frames off the bytes read from one connection. The buffer is kept
+/// for the life of the connection, so reading a frame doesn't allocate, and
+/// frames already read past the one being handled stay buffered for the next.
+pub struct FrameDecoder {
+    buf: BytesMut,
+    /// Frames bigger than this are refused before any room is made for them.
+    max_len: usize,
+}
+
+impl FrameDecoder {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            buf: BytesMut::with_capacity(INITIAL_CAPACITY),
+            max_len,
+        }
+    }
+
+    /// Reads more of the connection into the buffer, returning how many
+    /// bytes came; 0 once the peer has closed it.
+    pub async fn read_from<R>(&mut self, reader: &mut R) -> std::io::Result<usize>
+    where
+        R: AsyncRead + Unpin,
+    {
+        reader.read_buf(&mut self.buf).await
+    }
+
+    /// Whether nothing of the next frame has arrived.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// The size of the frame at the front of the buffer, once its prefix has
+    /// arrived. A negative size, or one over the limit, is an error; nothing
+    /// after it can be framed, so the connection has to be closed.
+    pub fn frame_len(&self) -> Result<Option<usize>> {
+        let Some(prefix) = self.buf.get(..4) else {
+            return Ok(None);
+        };
+        let len = i32::from_be_bytes(prefix.try_into().unwrap());
+        if len < 0 || len as usize > self.max_len {
+            bail!("frame size {} out of range (max {})", len, self.max_len);
+        }
+        Ok(Some(len as usize))
+    }
+
+    /// Splits the frame at the front of the buffer off, if it has fully
+    /// arrived, making room for the rest of it if not.
+    pub fn next_frame(&mut self) -> Result<Option<Bytes>> {
+        let Some(msg_len) = self.frame_len()? else {
+            return Ok(None);
+        };
+        if self.buf.len() < 4 + msg_len {
+            self.buf.reserve(4 + msg_len - self.buf.len());
+            return Ok(None);
+        }
+        self.buf.advance(4);
+        Ok(Some(self.buf.split_to(msg_len).freeze()))
+    }
+
+    /// Reads until a whole frame has arrived and returns it.
+    pub async fn read_frame<R>(&mut self, reader: &mut R) -> Result<Bytes>
+    where
+        R: AsyncRead + Unpin,
+    {
+        loop {
+            if let Some(frame) = self.next_frame()? {
+                return Ok(frame);
+            }
+            if self.read_from(reader).await? == 0 {
+                return Err(anyhow!("connection closed mid-frame"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoder_with(max_len: usize, bytes: &[u8]) -> FrameDecoder {
+        let mut decoder = FrameDecoder::new(max_len);
+        decoder.buf.extend_from_slice(bytes);
+        decoder
+    }
+
+    #[test]
+    fn splits_whole_frames_and_keeps_the_rest() {
+        let mut decoder = decoder_with(16, b"\0\0\0\x02ab\0\0\0\x03c");
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), "ab");
+        assert_eq!(decoder.frame_len().unwrap(), Some(3));
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.buf.extend_from_slice(b"de");
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), "cde");
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_whole_size_prefix() {
+        let mut decoder = decoder_with(16, b"\0\0");
+        assert_eq!(decoder.frame_len().unwrap(), None);
+        assert!(decoder.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_negative_and_oversized_frames() {
+        assert!(decoder_with(16, &(-1i32).to_be_bytes())
+            .frame_len()
+            .is_err());
+        assert!(decoder_with(16, &17i32.to_be_bytes()).frame_len().is_err());
+        assert_eq!(
+            decoder_with(16, &16i32.to_be_bytes()).frame_len().unwrap(),
+            Some(16)
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_a_connection_closed_mid_frame() {
+        let mut decoder = FrameDecoder::new(16);
+        let mut reader: &[u8] = b"\0\0\0\x04ab";
+        assert!(decoder.read_frame(&mut reader).await.is_err());
+
+        let mut decoder = FrameDecoder::new(16);
+        let mut reader: &[u8] = b"\0\0\0\x02ab";
+        assert_eq!(decoder.read_frame(&mut reader).await.unwrap(), "ab");
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::api::cluster_metadata::RecordBatches;
+
+/// Liveness and readiness as reported by the admin endpoint's probes.
+///
+/// The broker is live as long as it can answer at all. It is ready once its
+/// listeners are accepting, the cluster metadata log loads and every log
+/// directory is writable; it stops being ready when shutdown begins.
+pub struct Health {
+    log_dirs: Vec<PathBuf>,
+    metadata_log_file: PathBuf,
+    serving: AtomicBool,
+}
+
+/// The outcome of one readiness check.
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub result: Result<(), String>,
+}
+
+impl Health {
+    pub fn new(log_dirs: Vec<PathBuf>, metadata_log_file: PathBuf) -> Self {
+        Self {
+            log_dirs,
+            metadata_log_file,
+            serving: AtomicBool::new(false),
+        }
+    }
+
+    /// Marks whether the listeners are accepting connections.
+    pub fn set_serving(&self, serving: bool) {
+        self.serving.store(serving, Ordering::Relaxed);
+    }
+
+    pub fn readiness(&self) -> Vec<HealthCheck> {
+        let serving = if self.serving.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err("listeners are not accepting connections".to_string())
+        };
+        let metadata = if self.metadata_log_file.exists() {
+            RecordBatches::from_file(&self.metadata_log_file)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        } else {
+            // A broker without a metadata log serves an empty cluster.
+            Ok(())
+        };
+        let log_dirs = self.log_dirs.iter().try_for_each(|dir| {
+            let probe = dir.join(".health-check");
+            std::fs::write(&probe, b"")
+                .and_then(|()| std::fs::remove_file(&probe))
+                .map_err(|e| format!("{}: {}", dir.display(), e))
+        });
+        vec![
+            HealthCheck {
+                name: "listeners",
+                result: serving,
+            },
+            HealthCheck {
+                name: "metadata",
+                result: metadata,
+            },
+            HealthCheck {
+                name: "log_dirs",
+                result: log_dirs,
+            },
+        ]
+    }
+}
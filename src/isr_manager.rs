@@ -0,0 +1,530 @@
+//! Keeps the in-sync replica set of the partitions this broker leads, in the
+//! manner of Kafka's `Partition.maybeExpandIsr`/`maybeShrinkIsr` and
+//! `AlterPartitionManager`: followers that fetch up to the log end join the
+//! ISR, followers that haven't for `replica.lag.time.max.ms` leave it, and
+//! each change goes to the controller as an AlterPartition request, which
+//! records it in the metadata log.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+use crate::api::alter_partition::{
+    AlterPartitionPartition, AlterPartitionRequest, AlterPartitionResponse, AlterPartitionTopic,
+    RECOVERED,
+};
+use crate::api::cluster_metadata::RecordBatches;
+use crate::client::ControllerChannel;
+use crate::metrics::Metrics;
+use crate::protocol::{ApiKey, ErrorCode, Uuid};
+use crate::raft::Voter;
+
+pub const ISR_SHRINKS_METRIC: &str = "kafka_server_isr_shrinks_total";
+pub const ISR_EXPANDS_METRIC: &str = "kafka_server_isr_expands_total";
+pub const UNDER_REPLICATED_METRIC: &str = "kafka_server_under_replicated_partitions";
+
+/// How long one AlterPartition request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// The pause after a change no controller took, before it is proposed again.
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// When followers leave the ISR and where changes to it go.
+#[derive(Debug, Clone)]
+pub struct IsrSettings {
+    /// How long a follower may go without fetching up to the leader's log
+    /// end before it is dropped from the ISR, `replica.lag.time.max.ms`.
+    pub lag_time_max: Duration,
+    /// From `controller.quorum.voters`. Without any, the ISR never changes.
+    pub controllers: Vec<Voter>,
+}
+
+impl Default for IsrSettings {
+    fn default() -> Self {
+        Self {
+            lag_time_max: Duration::from_millis(30_000),
+            controllers: Vec::new(),
+        }
+    }
+}
+
+/// A partition this broker leads, as the controller last confirmed it.
+#[derive(Debug, Clone)]
+pub struct LedPartition {
+    pub topic_name: String,
+    pub replicas: Vec<i32>,
+    pub isr: Vec<i32>,
+    pub leader_epoch: i32,
+    pub partition_epoch: i32,
+    /// When each follower last fetched up to the log end. Followers that
+    /// haven't since this broker started count from then.
+    caught_up: HashMap<i32, Instant>,
+    /// Set while an AlterPartition for the partition is outstanding.
+    pending: bool,
+}
+
+impl LedPartition {
+    fn is_under_replicated(&self) -> bool {
+        self.isr.len() < self.replicas.len()
+    }
+}
+
+/// Led partitions by topic id and partition.
+type LedPartitions = Arc<Mutex<BTreeMap<(String, i32), LedPartition>>>;
+
+/// A proposed ISR for one partition.
+struct IsrChange {
+    topic_id: String,
+    partition_index: i32,
+    new_isr: Vec<i32>,
+}
+
+/// The ISR of every partition this broker leads, and the task that sends
+/// changes to it to the controller.
+pub struct IsrManager {
+    node_id: i32,
+    /// This broker's registration as the metadata log has it, or -1.
+    broker_epoch: Arc<AtomicI64>,
+    partitions: LedPartitions,
+    metrics: Arc<Metrics>,
+    changes: mpsc::UnboundedSender<IsrChange>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl IsrManager {
+    /// Loads the partitions the metadata log lists this broker as the
+    /// leader of, and starts checking their followers.
+    pub fn start(
+        settings: IsrSettings,
+        node_id: i32,
+        metadata_log_file: &Path,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self> {
+        let partitions = LedPartitions::default();
+        let (changes, rx) = mpsc::unbounded_channel();
+        let broker_epoch = Arc::new(AtomicI64::new(-1));
+        if metadata_log_file.exists() {
+            let metadata = RecordBatches::from_file(metadata_log_file)?;
+            broker_epoch.store(registered_epoch(&metadata, node_id), Ordering::Relaxed);
+            *partitions.lock().unwrap() = led_partitions(&metadata, node_id);
+        }
+        let mut manager = Self {
+            node_id,
+            broker_epoch: broker_epoch.clone(),
+            partitions: partitions.clone(),
+            metrics: metrics.clone(),
+            changes,
+            task: Mutex::new(None),
+        };
+        let led = partitions.lock().unwrap().len();
+        update_under_replicated(&metrics, &partitions.lock().unwrap());
+        if settings.controllers.is_empty() {
+            if led > 0 {
+                warn!("no controller.quorum.voters, the ISR of led partitions won't change");
+            }
+            return Ok(manager);
+        }
+        let sender = AlterPartitionSender {
+            node_id,
+            broker_epoch,
+            controller: ControllerChannel::new(
+                format!("broker-{}-alter-partition", node_id),
+                settings.controllers,
+                REQUEST_TIMEOUT,
+            ),
+            partitions,
+            metrics,
+            metadata_log_file: metadata_log_file.to_path_buf(),
+        };
+        manager.task = Mutex::new(Some(tokio::spawn(sender.run(rx, settings.lag_time_max))));
+        Ok(manager)
+    }
+
+    /// Notes a follower's fetch of a led partition. A fetch from the log
+    /// end counts as caught up, and brings a follower outside the ISR back
+    /// into it.
+    pub fn record_fetch(
+        &self,
+        topic_id: &str,
+        partition_index: i32,
+        replica_id: i32,
+        fetch_offset: i64,
+        log_end_offset: i64,
+    ) {
+        if fetch_offset < log_end_offset || replica_id == self.node_id {
+            return;
+        }
+        let mut partitions = self.partitions.lock().unwrap();
+        let Some(p) = partitions.get_mut(&(topic_id.to_string(), partition_index)) else {
+            return;
+        };
+        if !p.replicas.contains(&replica_id) {
+            return;
+        }
+        p.caught_up.insert(replica_id, Instant::now());
+        if !p.isr.contains(&replica_id) && !p.pending {
+            let mut new_isr = p.isr.clone();
+            new_isr.push(replica_id);
+            debug!(
+                topic = %p.topic_name,
+                partition = partition_index,
+                replica = replica_id,
+                "follower caught up, expanding the ISR"
+            );
+            propose(&self.changes, topic_id, partition_index, p, new_isr);
+        }
+    }
+
+    /// Brings the led partitions in line with `metadata`: partitions this
+    /// broker has been elected to lead are added, ones it no longer leads
+    /// dropped, and the ISR of the rest taken from the metadata log where it
+    /// is newer.
+    pub fn update(&self, metadata: &RecordBatches) {
+        self.broker_epoch
+            .store(registered_epoch(metadata, self.node_id), Ordering::Relaxed);
+        let mut partitions = self.partitions.lock().unwrap();
+        let fresh = led_partitions(metadata, self.node_id);
+        partitions.retain(|key, p| {
+            let led = fresh.contains_key(key);
+            if !led {
+                info!(topic = %p.topic_name, partition = key.1, "no longer leading partition");
+            }
+            led
+        });
+        for (key, fresh) in fresh {
+            match partitions.get_mut(&key) {
+                Some(p) if p.leader_epoch == fresh.leader_epoch => {
+                    if fresh.partition_epoch > p.partition_epoch {
+                        p.replicas = fresh.replicas;
+                        p.isr = fresh.isr;
+                        p.partition_epoch = fresh.partition_epoch;
+                    }
+                }
+                _ => {
+                    info!(
+                        topic = %fresh.topic_name,
+                        partition = key.1,
+                        leader_epoch = fresh.leader_epoch,
+                        isr = ?fresh.isr,
+                        "leading partition"
+                    );
+                    partitions.insert(key, fresh);
+                }
+            }
+        }
+        update_under_replicated(&self.metrics, &partitions);
+    }
+
+    /// Each led partition, by topic id and partition.
+    pub fn partitions(&self) -> BTreeMap<(String, i32), LedPartition> {
+        self.partitions.lock().unwrap().clone()
+    }
+
+    /// One line per led partition.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for ((_, partition), p) in self.partitions() {
+            let _ = writeln!(
+                out,
+                "topic={} partition={} leader_epoch={} partition_epoch={} replicas={:?} \
+                 isr={:?} pending={}",
+                p.topic_name,
+                partition,
+                p.leader_epoch,
+                p.partition_epoch,
+                p.replicas,
+                p.isr,
+                p.pending,
+            );
+        }
+        out
+    }
+
+    /// Stops sending ISR changes and waits for the sender to stop. It only
+    /// talks to the controller, so it is stopped wherever it is; a change it
+    /// was sending is proposed again after the next start.
+    pub async fn shutdown(&self) {
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+}
+
+fn registered_epoch(metadata: &RecordBatches, node_id: i32) -> i64 {
+    metadata
+        .brokers()
+        .find(|b| b.broker_id == node_id)
+        .map_or(-1, |b| b.broker_epoch)
+}
+
+fn led_partitions(metadata: &RecordBatches, node_id: i32) -> BTreeMap<(String, i32), LedPartition> {
+    let now = Instant::now();
+    let mut led = BTreeMap::new();
+    for topic in metadata.topics() {
+        for p in metadata.partitions(&topic.topic_id) {
+            if p.leader_id as i32 != node_id {
+                continue;
+            }
+            let replicas: Vec<i32> = p.replicas.iter().map(|&r| r as i32).collect();
+            led.insert(
+                (topic.topic_id.0.clone(), p.partition_id as i32),
+                LedPartition {
+                    topic_name: topic.topic_name.0.clone().unwrap_or_default(),
+                    caught_up: replicas.iter().map(|&r| (r, now)).collect(),
+                    replicas,
+                    isr: p.in_sync_replicas.iter().map(|&r| r as i32).collect(),
+                    leader_epoch: p.leader_epoch as i32,
+                    partition_epoch: p.partition_epoch as i32,
+                    pending: false,
+                },
+            );
+        }
+    }
+    led
+}
+
+fn check_lagging(
+    partitions: &LedPartitions,
+    changes: &mpsc::UnboundedSender<IsrChange>,
+    node_id: i32,
+    lag_time_max: Duration,
+) {
+    let now = Instant::now();
+    let mut partitions = partitions.lock().unwrap();
+    for ((topic_id, partition_index), p) in partitions.iter_mut() {
+        if p.pending {
+            continue;
+        }
+        let lagging: Vec<i32> = p
+            .isr
+            .iter()
+            .copied()
+            .filter(|&id| id != node_id)
+            .filter(|id| {
+                p.caught_up
+                    .get(id)
+                    .is_none_or(|&at| now - at > lag_time_max)
+            })
+            .collect();
+        if lagging.is_empty() {
+            continue;
+        }
+        info!(
+            topic = %p.topic_name,
+            partition = partition_index,
+            ?lagging,
+            "followers fell behind, shrinking the ISR"
+        );
+        let new_isr = p
+            .isr
+            .iter()
+            .copied()
+            .filter(|id| !lagging.contains(id))
+            .collect();
+        propose(changes, topic_id, *partition_index, p, new_isr);
+    }
+}
+
+fn propose(
+    changes: &mpsc::UnboundedSender<IsrChange>,
+    topic_id: &str,
+    partition_index: i32,
+    partition: &mut LedPartition,
+    new_isr: Vec<i32>,
+) {
+    let change = IsrChange {
+        topic_id: topic_id.to_string(),
+        partition_index,
+        new_isr,
+    };
+    partition.pending = changes.send(change).is_ok();
+}
+
+fn update_under_replicated(metrics: &Metrics, partitions: &BTreeMap<(String, i32), LedPartition>) {
+    let count = partitions
+        .values()
+        .filter(|p| p.is_under_replicated())
+        .count();
+    metrics.set_gauge(UNDER_REPLICATED_METRIC, &[], count as i64);
+}
+
+/// Sends ISR changes to whichever voter is the controller, one at a time.
+struct AlterPartitionSender {
+    node_id: i32,
+    broker_epoch: Arc<AtomicI64>,
+    controller: ControllerChannel,
+    partitions: LedPartitions,
+    metrics: Arc<Metrics>,
+    metadata_log_file: PathBuf,
+}
+
+impl AlterPartitionSender {
+    async fn run(
+        mut self,
+        mut changes: mpsc::UnboundedReceiver<IsrChange>,
+        lag_time_max: Duration,
+    ) {
+        // Kafka checks twice per lag time, so a follower is out within one
+        // and a half lag times of its last catch-up.
+        let mut tick = tokio::time::interval(lag_time_max / 2);
+        let (retry, mut retries) = mpsc::unbounded_channel();
+        loop {
+            tokio::select! {
+                change = changes.recv() => match change {
+                    Some(change) => self.send(change, &retry).await,
+                    None => return,
+                },
+                Some(change) = retries.recv() => self.send(change, &retry).await,
+                _ = tick.tick() => {
+                    check_lagging(&self.partitions, &retry, self.node_id, lag_time_max);
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, change: IsrChange, retry: &mpsc::UnboundedSender<IsrChange>) {
+        let key = (change.topic_id.clone(), change.partition_index);
+        let Some(partition) = self.partitions.lock().unwrap().get(&key).cloned() else {
+            return;
+        };
+        let request = AlterPartitionRequest {
+            broker_id: self.node_id,
+            broker_epoch: self.broker_epoch.load(Ordering::Relaxed),
+            topics: vec![AlterPartitionTopic {
+                topic_id: Uuid(change.topic_id.clone()),
+                partitions: vec![AlterPartitionPartition {
+                    partition_index: change.partition_index,
+                    leader_epoch: partition.leader_epoch,
+                    new_isr: change.new_isr.clone(),
+                    leader_recovery_state: RECOVERED,
+                    partition_epoch: partition.partition_epoch,
+                }],
+            }],
+        };
+        let response = self
+            .controller
+            .send::<AlterPartitionResponse>(ApiKey::AlterPartition, 2, &request)
+            .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                warn!(
+                    topic = %partition.topic_name,
+                    partition = change.partition_index,
+                    error = %e,
+                    "failed to send ISR change, retrying"
+                );
+                tokio::time::sleep(RETRY_BACKOFF).await;
+                let _ = retry.send(change);
+                return;
+            }
+        };
+        let answer = response
+            .topics
+            .0
+            .iter()
+            .filter(|t| t.topic_id.0 == change.topic_id)
+            .flat_map(|t| &t.partitions.0)
+            .find(|p| p.partition_index == change.partition_index);
+
+        let mut partitions = self.partitions.lock().unwrap();
+        let Some(p) = partitions.get_mut(&key) else {
+            return;
+        };
+        p.pending = false;
+        match answer {
+            Some(answer) if answer.error_code == ErrorCode::None => {
+                let old_len = p.isr.len();
+                p.isr = answer.isr.clone();
+                p.leader_epoch = answer.leader_epoch;
+                p.partition_epoch = answer.partition_epoch;
+                let partition_label = change.partition_index.to_string();
+                let labels = [
+                    ("topic", p.topic_name.as_str()),
+                    ("partition", &partition_label),
+                ];
+                if p.isr.len() < old_len {
+                    self.metrics.incr_counter(ISR_SHRINKS_METRIC, &labels, 1);
+                } else if p.isr.len() > old_len {
+                    self.metrics.incr_counter(ISR_EXPANDS_METRIC, &labels, 1);
+                }
+                info!(
+                    topic = %p.topic_name,
+                    partition = change.partition_index,
+                    isr = ?p.isr,
+                    partition_epoch = p.partition_epoch,
+                    "ISR updated"
+                );
+                if answer.leader_id != self.node_id {
+                    partitions.remove(&key);
+                }
+            }
+            Some(answer) => {
+                warn!(
+                    topic = %p.topic_name,
+                    partition = change.partition_index,
+                    error = ?answer.error_code,
+                    "controller rejected ISR change"
+                );
+                // The partition moved on without this broker hearing of it.
+                if matches!(
+                    answer.error_code,
+                    ErrorCode::FencedLeaderEpoch
+                        | ErrorCode::InvalidUpdateVersion
+                        | ErrorCode::NotLeaderOrFollower
+                ) {
+                    reload(&mut partitions, &key, self.node_id, &self.metadata_log_file);
+                }
+            }
+            None => warn!(
+                topic = %p.topic_name,
+                partition = change.partition_index,
+                error = ?response.error_code,
+                "controller didn't answer for the ISR change"
+            ),
+        }
+        update_under_replicated(&self.metrics, &partitions);
+    }
+}
+
+/// Rereads one partition from the metadata log, dropping it if this broker
+/// no longer leads it.
+fn reload(
+    partitions: &mut BTreeMap<(String, i32), LedPartition>,
+    key: &(String, i32),
+    node_id: i32,
+    metadata_log_file: &Path,
+) {
+    let metadata = match RecordBatches::from_file(metadata_log_file) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            warn!(error = %e, "failed to reread the metadata log");
+            return;
+        }
+    };
+    let mut led = led_partitions(&metadata, node_id);
+    match (led.remove(key), partitions.get_mut(key)) {
+        (Some(fresh), Some(p)) => {
+            p.replicas = fresh.replicas;
+            p.isr = fresh.isr;
+            p.leader_epoch = fresh.leader_epoch;
+            p.partition_epoch = fresh.partition_epoch;
+        }
+        (None, _) => {
+            partitions.remove(key);
+        }
+        (Some(_), None) => {}
+    }
+}
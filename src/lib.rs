@@ -1,5 +1,89 @@
+mod admin;
 mod api;
+mod audit;
+mod authorizer;
+mod broker_lifecycle;
+mod cli;
+mod client;
+mod config;
+mod connection_quotas;
+mod connection_registry;
+mod controller;
+mod coordinator;
+mod delegation_token;
+mod embedded;
+mod error;
+mod features;
+mod fetch_session;
+mod frame;
+mod health;
+mod isr_manager;
+mod listener;
+mod log_level;
+mod log_manager;
+mod memory_pool;
+mod meta_properties;
+mod metadata_image;
+mod metrics;
+mod oauth;
+mod partition_rates;
 mod protocol;
+mod purgatory;
+mod quota;
+mod raft;
+mod record_batch;
+mod replica_fetcher;
+mod request_context;
+mod runtime_metrics;
+mod sasl;
+mod scram;
+mod security;
+mod server;
+mod share_partition;
+mod tls;
+mod wire_debug;
 
+pub use admin::*;
 pub use api::*;
+pub use audit::*;
+pub use authorizer::*;
+pub use broker_lifecycle::*;
+pub use cli::*;
+pub use client::*;
+pub use config::*;
+pub use connection_quotas::*;
+pub use connection_registry::*;
+pub use controller::*;
+pub use coordinator::*;
+pub use delegation_token::*;
+pub use embedded::*;
+pub use error::*;
+pub use features::*;
+pub use fetch_session::*;
+pub use frame::*;
+pub use health::*;
+pub use isr_manager::*;
+pub use listener::*;
+pub use log_level::*;
+pub use log_manager::*;
+pub use memory_pool::*;
+pub use meta_properties::*;
+pub use metadata_image::*;
+pub use metrics::*;
+pub use oauth::*;
+pub use partition_rates::*;
 pub use protocol::*;
+pub use purgatory::*;
+pub use quota::*;
+pub use raft::*;
+pub use record_batch::*;
+pub use replica_fetcher::*;
+pub use request_context::*;
+pub use runtime_metrics::*;
+pub use sasl::*;
+pub use scram::*;
+pub use security::*;
+pub use server::*;
+pub use share_partition::*;
+pub use tls::*;
+pub use wire_debug::*;
@@ -0,0 +1,468 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{lookup_host, TcpListener, TcpSocket, TcpStream, UnixListener, UnixStream},
+};
+
+use crate::config::parse_list;
+
+/// What a listener serves: client and inter-broker traffic, or the controller
+/// APIs of `controller.listener.names`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListenerType {
+    Broker,
+    Controller,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityProtocol {
+    Plaintext,
+    Ssl,
+    SaslPlaintext,
+    SaslSsl,
+}
+
+impl SecurityProtocol {
+    pub fn uses_tls(&self) -> bool {
+        matches!(self, Self::Ssl | Self::SaslSsl)
+    }
+
+    pub fn uses_sasl(&self) -> bool {
+        matches!(self, Self::SaslPlaintext | Self::SaslSsl)
+    }
+
+    /// The id Kafka gives the protocol in broker registrations.
+    pub fn id(&self) -> i16 {
+        match self {
+            Self::Plaintext => 0,
+            Self::Ssl => 1,
+            Self::SaslPlaintext => 2,
+            Self::SaslSsl => 3,
+        }
+    }
+}
+
+impl FromStr for SecurityProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_uppercase().as_str() {
+            "PLAINTEXT" => Ok(Self::Plaintext),
+            "SSL" => Ok(Self::Ssl),
+            "SASL_PLAINTEXT" => Ok(Self::SaslPlaintext),
+            "SASL_SSL" => Ok(Self::SaslSsl),
+            other => Err(anyhow!("unknown security protocol '{}'", other)),
+        }
+    }
+}
+
+impl Display for SecurityProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Plaintext => "PLAINTEXT",
+            Self::Ssl => "SSL",
+            Self::SaslPlaintext => "SASL_PLAINTEXT",
+            Self::SaslSsl => "SASL_SSL",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The address reported for clients connected over a Unix domain socket,
+/// which have no IP address of their own.
+pub const UNIX_SOCKET_ADDR: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+
+/// One `NAME://host:port` entry of `listeners` or `advertised.listeners`, or
+/// `NAME:///path/to/socket` for a Unix domain socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub listener_name: String,
+    pub security_protocol: SecurityProtocol,
+    /// Empty means every interface when binding.
+    pub host: String,
+    pub port: u16,
+    /// Set for Unix domain socket listeners, which have no host or port.
+    pub unix_path: Option<PathBuf>,
+}
+
+impl Endpoint {
+    /// Parses a comma-separated listener list. Names are resolved to a
+    /// security protocol through `protocol_map`, falling back to the name
+    /// itself for the standard `PLAINTEXT`/`SSL`/`SASL_*` listeners.
+    ///
+    /// As in KIP-797, a name may appear twice if one entry is an IPv4 and the
+    /// other an IPv6 address, so one listener can serve both families.
+    pub fn parse_list(
+        value: &str,
+        protocol_map: &HashMap<String, SecurityProtocol>,
+    ) -> Result<Vec<Self>> {
+        let mut endpoints: Vec<Self> = Vec::new();
+        for entry in parse_list(value) {
+            let endpoint = Self::parse(&entry, protocol_map)?;
+            if endpoints
+                .iter()
+                .any(|e| e.listener_name == endpoint.listener_name && !e.other_family(&endpoint))
+            {
+                return Err(anyhow!(
+                    "duplicate listener name '{}'",
+                    endpoint.listener_name
+                ));
+            }
+            endpoints.push(endpoint);
+        }
+        Ok(endpoints)
+    }
+
+    fn parse(entry: &str, protocol_map: &HashMap<String, SecurityProtocol>) -> Result<Self> {
+        let (name, address) = entry
+            .split_once("://")
+            .ok_or_else(|| anyhow!("invalid listener '{}', expected NAME://host:port", entry))?;
+        let listener_name = name.to_uppercase();
+        let security_protocol = match protocol_map.get(&listener_name) {
+            Some(protocol) => *protocol,
+            None => listener_name.parse().with_context(|| {
+                format!(
+                    "listener '{}' is missing from listener.security.protocol.map",
+                    listener_name
+                )
+            })?,
+        };
+        if address.starts_with('/') {
+            return Ok(Self {
+                listener_name,
+                security_protocol,
+                host: String::new(),
+                port: 0,
+                unix_path: Some(PathBuf::from(address)),
+            });
+        }
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("listener '{}' is missing a port", entry))?;
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        let port = port
+            .parse()
+            .with_context(|| format!("invalid port in listener '{}'", entry))?;
+        Ok(Self {
+            listener_name,
+            security_protocol,
+            host: host.to_string(),
+            port,
+            unix_path: None,
+        })
+    }
+
+    /// The host as an IP address, if it is one rather than a name.
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.host.parse().ok()
+    }
+
+    /// Whether both endpoints are IP addresses of different families.
+    pub fn other_family(&self, other: &Endpoint) -> bool {
+        match (self.ip(), other.ip()) {
+            (Some(a), Some(b)) => a.is_ipv4() != b.is_ipv4(),
+            _ => false,
+        }
+    }
+
+    /// The address to bind, with an empty host meaning all interfaces.
+    pub fn bind_address(&self) -> String {
+        if self.host.is_empty() {
+            return format!("0.0.0.0:{}", self.port);
+        }
+        format!("{}:{}", bracket_ipv6(&self.host), self.port)
+    }
+}
+
+/// Wraps IPv6 literals in brackets so a port can follow them.
+fn bracket_ipv6(host: &str) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{}]", host),
+        _ => host.to_string(),
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.unix_path {
+            Some(path) => write!(f, "{}://{}", self.listener_name, path.display()),
+            None => write!(
+                f,
+                "{}://{}:{}",
+                self.listener_name,
+                bracket_ipv6(&self.host),
+                self.port
+            ),
+        }
+    }
+}
+
+/// `socket.*` tuning applied to listeners and accepted connections.
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    pub tcp_nodelay: bool,
+    /// `SO_SNDBUF`/`SO_RCVBUF`; `None` keeps the OS default.
+    pub send_buffer_bytes: Option<u32>,
+    pub receive_buffer_bytes: Option<u32>,
+    pub listen_backlog: u32,
+    /// `None` when `socket.keepalive.enable` is false.
+    pub keepalive: Option<Keepalive>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            tcp_nodelay: true,
+            send_buffer_bytes: Some(102_400),
+            receive_buffer_bytes: Some(102_400),
+            listen_backlog: 50,
+            keepalive: Some(Keepalive::default()),
+        }
+    }
+}
+
+/// TCP keepalive for accepted connections, so a peer that vanished without
+/// closing its end (a crashed host, a dropped NAT mapping) is noticed instead
+/// of holding a connection slot forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// Silence before the first probe, from `socket.keepalive.idle.ms`.
+    pub idle: Duration,
+    /// Between unanswered probes, from `socket.keepalive.interval.ms`.
+    pub interval: Duration,
+    /// Unanswered probes after which the peer is given up on, from
+    /// `socket.keepalive.probes`.
+    pub probes: u32,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            idle: Duration::from_secs(60),
+            interval: Duration::from_secs(10),
+            probes: 6,
+        }
+    }
+}
+
+impl Keepalive {
+    /// How long an unreachable peer goes unnoticed at most.
+    pub fn timeout(&self) -> Duration {
+        self.idle + self.interval * self.probes
+    }
+}
+
+impl SocketOptions {
+    /// Binds a listening socket for `endpoint`. Buffer sizes are set before
+    /// `listen` so accepted sockets inherit them, which matters for the receive
+    /// window advertised during the handshake.
+    ///
+    /// An IPv6 socket also accepts IPv4 connections unless `v6_only`, which is
+    /// needed when an IPv4 listener shares its port.
+    pub async fn bind(&self, endpoint: &Endpoint, v6_only: bool) -> Result<ListenerSocket> {
+        if let Some(path) = &endpoint.unix_path {
+            // A socket file left behind by a previous run would make bind fail.
+            if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                std::fs::remove_file(path)?;
+            }
+            return Ok(ListenerSocket::Unix(UnixListener::bind(path)?));
+        }
+        let addr: SocketAddr = lookup_host(endpoint.bind_address())
+            .await?
+            .next()
+            .ok_or_else(|| {
+                anyhow!(
+                    "'{}' did not resolve to an address",
+                    endpoint.bind_address()
+                )
+            })?;
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            let socket = TcpSocket::new_v6()?;
+            socket2::SockRef::from(&socket).set_only_v6(v6_only)?;
+            socket
+        };
+        socket.set_reuseaddr(true)?;
+        if let Some(size) = self.send_buffer_bytes {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.receive_buffer_bytes {
+            socket.set_recv_buffer_size(size)?;
+        }
+        socket.bind(addr)?;
+        Ok(ListenerSocket::Tcp(socket.listen(self.listen_backlog)?))
+    }
+
+    /// Applies the per-connection options to an accepted socket.
+    pub fn apply(&self, stream: &ClientStream) -> Result<()> {
+        if let ClientStream::Tcp(stream) = stream {
+            stream.set_nodelay(self.tcp_nodelay)?;
+            if let Some(keepalive) = &self.keepalive {
+                let socket = socket2::SockRef::from(stream);
+                socket.set_tcp_keepalive(
+                    &socket2::TcpKeepalive::new()
+                        .with_time(keepalive.idle)
+                        .with_interval(keepalive.interval)
+                        .with_retries(keepalive.probes),
+                )?;
+                // Keepalive only probes a quiet connection; this covers one
+                // whose responses are going unacknowledged.
+                socket.set_tcp_user_timeout(Some(keepalive.timeout()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A bound listener, over TCP or a Unix domain socket.
+pub enum ListenerSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl ListenerSocket {
+    /// The port a TCP listener is bound to.
+    pub fn local_port(&self) -> io::Result<u16> {
+        match self {
+            Self::Tcp(listener) => Ok(listener.local_addr()?.port()),
+            Self::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "a Unix domain socket has no port",
+            )),
+        }
+    }
+
+    /// Accepts a connection and returns it with the client's address, which is
+    /// [`UNIX_SOCKET_ADDR`] for Unix domain sockets.
+    pub async fn accept(&self) -> io::Result<(ClientStream, SocketAddr)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((ClientStream::Tcp(stream), canonical(peer)))
+            }
+            Self::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((ClientStream::Unix(stream), UNIX_SOCKET_ADDR))
+            }
+        }
+    }
+}
+
+/// An accepted client connection, over TCP or a Unix domain socket.
+pub enum ClientStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl ClientStream {
+    /// The broker-side address; [`UNIX_SOCKET_ADDR`] for Unix domain sockets.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Self::Tcp(stream) => stream.local_addr().map(canonical),
+            Self::Unix(_) => Ok(UNIX_SOCKET_ADDR),
+        }
+    }
+
+    /// A second handle on a TCP socket for checking on the peer. Unix domain
+    /// sockets have no peer that can vanish unnoticed.
+    pub fn peer_probe(&self) -> io::Result<Option<PeerProbe>> {
+        match self {
+            Self::Tcp(stream) => Ok(Some(PeerProbe(socket2::SockRef::from(stream).try_clone()?))),
+            Self::Unix(_) => Ok(None),
+        }
+    }
+}
+
+/// Sees the error the OS leaves on a socket whose peer stopped answering, even
+/// while the connection is not being read from, e.g. because its requests are
+/// waiting for memory.
+pub struct PeerProbe(socket2::Socket);
+
+impl PeerProbe {
+    /// The pending socket error, such as `ETIMEDOUT` once keepalive probes or
+    /// retransmissions have gone unanswered. Reading it clears it.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.0.take_error()
+    }
+}
+
+/// Unwraps IPv4-mapped IPv6 addresses, which is how IPv4 clients of a
+/// dual-stack socket appear.
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            Self::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Self::Tcp(stream) => stream.is_write_vectored(),
+            Self::Unix(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::{anyhow, Context, Result};
+use tracing::info;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::config::Config;
+
+/// The broker's log filter, which can be swapped while it runs: by a config
+/// reload, or from the admin endpoint to turn on debug logging for a live
+/// broker.
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogLevel {
+    pub fn new(handle: reload::Handle<EnvFilter, Registry>) -> Self {
+        Self { handle }
+    }
+
+    /// The filter in effect, in `RUST_LOG` directive syntax.
+    pub fn current(&self) -> Result<String> {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .context("read log filter")
+    }
+
+    /// Replaces the filter with `directives`, e.g.
+    /// `info,kafka_starter_rust=debug`. Invalid directives leave the current
+    /// filter in place.
+    pub fn set(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::try_new(directives)
+            .map_err(|e| anyhow!("invalid log filter '{}': {}", directives, e))?;
+        self.replace(filter)?;
+        info!(filter = directives, "log filter changed");
+        Ok(())
+    }
+
+    /// Replaces the filter with one already built.
+    pub fn replace(&self, filter: EnvFilter) -> Result<()> {
+        self.handle.reload(filter).context("reload log filter")
+    }
+}
+
+/// `log.level` when set, else `RUST_LOG`, else `info`.
+pub fn build_log_filter(config: &Config) -> Result<EnvFilter> {
+    match &config.log_level {
+        Some(level) => EnvFilter::try_new(level).context("parse log.level"),
+        None => Ok(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))),
+    }
+}
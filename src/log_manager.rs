@@ -0,0 +1,427 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+
+use crate::record_batch::{is_intact, BatchHeader, BATCH_HEADER_SIZE};
+
+/// Written to each log dir after a clean shutdown. Its presence at startup
+/// means every segment was flushed, so recovery can be skipped.
+pub const CLEAN_SHUTDOWN_FILE: &str = ".kafka_cleanshutdown";
+
+/// Names the topic id a partition directory holds replicas of, so that a
+/// broker can tell its replicas of a deleted topic from those of a later
+/// topic of the same name.
+pub const PARTITION_METADATA_FILE: &str = "partition.metadata";
+
+const LOG_FILE_SUFFIX: &str = ".log";
+/// Ends the name a partition directory is renamed to once its topic is
+/// deleted, as in Kafka.
+const DELETE_DIR_SUFFIX: &str = "-delete";
+
+/// The log segments under the configured log dirs: every `*.log` file in a
+/// partition directory such as `__cluster_metadata-0`.
+pub struct LogManager {
+    log_dirs: Vec<PathBuf>,
+}
+
+/// What startup found in one log dir.
+#[derive(Debug, Default)]
+pub struct RecoveryStats {
+    pub segments: usize,
+    /// Bytes cut off segment tails because they held a torn or corrupt batch.
+    pub truncated_bytes: u64,
+}
+
+impl LogManager {
+    pub fn new(log_dirs: Vec<PathBuf>) -> Self {
+        Self { log_dirs }
+    }
+
+    /// Loads every log dir. A dir shut down cleanly is trusted as is;
+    /// otherwise each segment is scanned and truncated after its last valid
+    /// batch, as a crash may have left a partial write.
+    ///
+    /// The clean-shutdown marker is removed either way, so a crash from here
+    /// on is detected at the next start. Partitions left renamed for
+    /// deletion are removed first.
+    pub fn startup(&self) -> Result<()> {
+        for dir in &self.log_dirs {
+            remove_deleted_partitions(dir)?;
+            let marker = dir.join(CLEAN_SHUTDOWN_FILE);
+            if marker.exists() {
+                info!(log_dir = %dir.display(), "log dir was shut down cleanly, skipping recovery");
+                std::fs::remove_file(&marker)
+                    .with_context(|| format!("remove '{}'", marker.display()))?;
+                continue;
+            }
+            let start = Instant::now();
+            let stats = self.recover(dir)?;
+            info!(
+                log_dir = %dir.display(),
+                segments = stats.segments,
+                truncated_bytes = stats.truncated_bytes,
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                "recovered log dir"
+            );
+        }
+        Ok(())
+    }
+
+    /// Flushes every segment to disk and, if `drained`, marks each log dir
+    /// as cleanly shut down. Without `drained` handlers may still be
+    /// appending, so no dir is marked and each is recovered next time, as is
+    /// a dir that fails.
+    pub fn shutdown(&self, drained: bool) {
+        if !drained {
+            warn!("requests were still being handled, log dirs will be recovered at next start");
+        }
+        for dir in &self.log_dirs {
+            let res = segments(dir).and_then(|segments| {
+                for segment in segments {
+                    File::open(&segment)
+                        .and_then(|f| f.sync_all())
+                        .with_context(|| format!("flush '{}'", segment.display()))?;
+                }
+                if !drained {
+                    return Ok(());
+                }
+                let marker = dir.join(CLEAN_SHUTDOWN_FILE);
+                File::create(&marker)
+                    .and_then(|f| f.sync_all())
+                    .with_context(|| format!("write '{}'", marker.display()))
+            });
+            if let Err(e) = res {
+                warn!(log_dir = %dir.display(), error = %e, "failed to shut down log dir cleanly");
+            }
+        }
+    }
+
+    fn recover(&self, dir: &Path) -> Result<RecoveryStats> {
+        let mut stats = RecoveryStats::default();
+        for segment in segments(dir)? {
+            stats.segments += 1;
+            let truncated = recover_segment(&segment)?;
+            if truncated > 0 {
+                warn!(
+                    segment = %segment.display(),
+                    truncated_bytes = truncated,
+                    "truncated invalid bytes at the end of segment"
+                );
+            }
+            stats.truncated_bytes += truncated;
+        }
+        Ok(stats)
+    }
+}
+
+/// Deletes the partition directory `dir`. It is renamed out of the way
+/// first, tagged with `unique_id` so that a later partition of the same name
+/// can't collide with it, and then removed; startup removes it if a crash
+/// comes in between.
+pub fn delete_partition_dir(dir: &Path, unique_id: &str) -> Result<()> {
+    let Some(name) = dir.file_name().and_then(|n| n.to_str()) else {
+        return Ok(());
+    };
+    let renamed = dir.with_file_name(format!("{}.{}{}", name, unique_id, DELETE_DIR_SUFFIX));
+    match std::fs::rename(dir, &renamed) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("rename '{}'", dir.display())),
+    }
+    std::fs::remove_dir_all(&renamed).with_context(|| format!("remove '{}'", renamed.display()))
+}
+
+/// Held while a partition directory is created or deleted, so that a
+/// topic's replicas being removed can't take a new topic's directory of the
+/// same name with them.
+static PARTITION_DIRS: Mutex<()> = Mutex::new(());
+
+/// Creates the partition directory `dir` for the topic `topic_id` unless it
+/// is there already. A directory left by a deleted topic of the same name,
+/// which this broker hasn't got round to removing, is deleted first.
+pub fn create_partition_dir(dir: &Path, topic_id: &str) -> Result<()> {
+    let _guard = PARTITION_DIRS.lock().unwrap_or_else(|e| e.into_inner());
+    match partition_topic_id(dir)? {
+        Some(existing) if existing == topic_id => return Ok(()),
+        Some(existing) => delete_partition_dir(dir, &existing.replace('-', ""))?,
+        None => {}
+    }
+    std::fs::create_dir_all(dir).with_context(|| format!("create '{}'", dir.display()))?;
+    let path = dir.join(PARTITION_METADATA_FILE);
+    std::fs::write(&path, format!("version: 0\ntopic_id: {}\n", topic_id))
+        .with_context(|| format!("write '{}'", path.display()))
+}
+
+/// The topic id in the partition directory `dir`'s metadata file, or `None`
+/// if it has none.
+pub fn partition_topic_id(dir: &Path) -> Result<Option<String>> {
+    let path = dir.join(PARTITION_METADATA_FILE);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("read '{}'", path.display())),
+    };
+    Ok(contents
+        .lines()
+        .find_map(|line| line.strip_prefix("topic_id:"))
+        .map(|id| id.trim().to_string()))
+}
+
+/// Deletes this broker's replicas of the topics in `removed`, the ids of
+/// topics the metadata log has a RemoveTopicRecord for, and returns the
+/// directories it deleted. Each broker calls this as it sees the topics go,
+/// so replicas are removed wherever they are, including on a broker that
+/// was down when the topic was deleted.
+pub fn delete_removed_partitions(log_dirs: &[PathBuf], removed: &HashSet<String>) -> Vec<PathBuf> {
+    let mut deleted = Vec::new();
+    if removed.is_empty() {
+        return deleted;
+    }
+    let _guard = PARTITION_DIRS.lock().unwrap_or_else(|e| e.into_inner());
+    for log_dir in log_dirs {
+        let Ok(entries) = std::fs::read_dir(log_dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let dir = entry.path();
+            let topic_id = match partition_topic_id(&dir) {
+                Ok(Some(topic_id)) if removed.contains(&topic_id) => topic_id,
+                _ => continue,
+            };
+            match delete_partition_dir(&dir, &topic_id.replace('-', "")) {
+                Ok(()) => {
+                    info!(dir = %dir.display(), topic_id, "deleted replica of removed topic");
+                    deleted.push(dir);
+                }
+                Err(e) => warn!(
+                    dir = %dir.display(),
+                    error = %format!("{:#}", e),
+                    "failed to delete partition directory"
+                ),
+            }
+        }
+    }
+    deleted
+}
+
+fn remove_deleted_partitions(dir: &Path) -> Result<()> {
+    let partitions = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("list '{}'", dir.display())),
+    };
+    for partition in partitions {
+        let partition = partition?.path();
+        if partition.is_dir()
+            && partition
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.ends_with(DELETE_DIR_SUFFIX))
+        {
+            info!(dir = %partition.display(), "removing partition left for deletion");
+            std::fs::remove_dir_all(&partition)
+                .with_context(|| format!("remove '{}'", partition.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// The segment files in `dir`'s partition directories. A missing log dir has
+/// none.
+fn segments(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut segments = Vec::new();
+    let partitions = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(segments),
+        Err(e) => return Err(e).with_context(|| format!("list '{}'", dir.display())),
+    };
+    for partition in partitions {
+        let partition = partition?.path();
+        if !partition.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&partition)? {
+            let path = entry?.path();
+            if path.is_file()
+                && path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.ends_with(LOG_FILE_SUFFIX))
+            {
+                segments.push(path);
+            }
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Truncates `path` after its last complete batch with a valid CRC and
+/// returns how many bytes were cut.
+fn recover_segment(path: &Path) -> Result<u64> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .with_context(|| format!("read '{}'", path.display()))?;
+    let valid = valid_prefix(&data);
+    let truncated = (data.len() - valid) as u64;
+    if truncated > 0 {
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|f| f.set_len(valid as u64).and_then(|()| f.sync_all()))
+            .with_context(|| format!("truncate '{}'", path.display()))?;
+    }
+    Ok(truncated)
+}
+
+/// The length of the longest run of whole, intact batches at the start of
+/// `data`.
+fn valid_prefix(data: &[u8]) -> usize {
+    let mut pos = 0;
+    while let Some(header) = BatchHeader::parse(&data[pos..]) {
+        if !is_intact(&data[pos..pos + header.size()]) {
+            break;
+        }
+        pos += header.size();
+    }
+    pos
+}
+
+/// The log dir holding the partition directory `name`, such as `foo-0`.
+/// A partition no dir holds yet is placed in the first.
+pub fn partition_log_dir<'a>(log_dirs: &'a [PathBuf], name: &str) -> &'a Path {
+    log_dirs
+        .iter()
+        .find(|dir| dir.join(name).is_dir())
+        .unwrap_or(&log_dirs[0])
+}
+
+/// The bytes in the segments of the partition in `dir`; 0 if it has none.
+pub fn partition_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|n| n.ends_with(LOG_FILE_SUFFIX))
+        })
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// The offset the next record appended to the partition in `dir` would get,
+/// or `None` if it has no segments. Only the last segment's batch headers
+/// are read.
+pub fn log_end_offset(dir: &Path) -> Result<Option<i64>> {
+    Ok(log_end(dir)?.map(|(end, _)| end))
+}
+
+/// [`log_end_offset`], with the leader epoch of the log's last batch, -1 if
+/// it has none.
+pub fn log_end(dir: &Path) -> Result<Option<(i64, i32)>> {
+    let mut last = None;
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("list '{}'", dir.display())),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.ends_with(LOG_FILE_SUFFIX))
+            && last.as_ref().is_none_or(|last| path > *last)
+        {
+            last = Some(path);
+        }
+    }
+    let Some(segment) = last else {
+        return Ok(None);
+    };
+
+    let mut file = File::open(&segment).with_context(|| format!("open '{}'", segment.display()))?;
+    let len = file.metadata()?.len();
+    // The segment's base offset, from its name, is where an empty one ends.
+    let mut end = segment
+        .file_stem()
+        .and_then(|n| n.to_str())
+        .and_then(|n| n.parse::<i64>().ok())
+        .unwrap_or(0);
+    let mut epoch = -1;
+    let mut pos = 0;
+    let mut buf = [0; BATCH_HEADER_SIZE];
+    while pos + BATCH_HEADER_SIZE as u64 <= len {
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut buf)?;
+        let Some(header) = BatchHeader::parse_fixed(&buf) else {
+            break;
+        };
+        end = header.last_offset() + 1;
+        epoch = header.partition_leader_epoch;
+        pos += header.size() as u64;
+    }
+    Ok(Some((end, epoch)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_ID: &str = "11111111-1111-1111-1111-111111111111";
+    const NEW_ID: &str = "22222222-2222-2222-2222-222222222222";
+
+    fn scratch_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("log-manager-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn removed_topics_lose_only_their_own_replicas() {
+        let log_dir = scratch_dir();
+        create_partition_dir(&log_dir.join("old-0"), OLD_ID).unwrap();
+        create_partition_dir(&log_dir.join("new-0"), NEW_ID).unwrap();
+        std::fs::create_dir(log_dir.join("unnamed-0")).unwrap();
+
+        let removed = HashSet::from([OLD_ID.to_string()]);
+        let deleted = delete_removed_partitions(std::slice::from_ref(&log_dir), &removed);
+        assert_eq!(deleted, vec![log_dir.join("old-0")]);
+        assert!(!log_dir.join("old-0").exists());
+        assert!(log_dir.join("new-0").is_dir());
+        assert!(log_dir.join("unnamed-0").is_dir());
+        std::fs::remove_dir_all(&log_dir).unwrap();
+    }
+
+    #[test]
+    fn a_topic_of_the_same_name_replaces_a_stale_replica() {
+        let log_dir = scratch_dir();
+        let dir = log_dir.join("orders-0");
+        create_partition_dir(&dir, OLD_ID).unwrap();
+        std::fs::write(dir.join("00000000000000000000.log"), b"old records").unwrap();
+
+        create_partition_dir(&dir, NEW_ID).unwrap();
+        assert_eq!(partition_topic_id(&dir).unwrap().as_deref(), Some(NEW_ID));
+        assert!(!dir.join("00000000000000000000.log").exists());
+
+        // Removing the old topic afterwards leaves the new one alone.
+        let removed = HashSet::from([OLD_ID.to_string()]);
+        assert!(delete_removed_partitions(std::slice::from_ref(&log_dir), &removed).is_empty());
+        assert!(dir.is_dir());
+        std::fs::remove_dir_all(&log_dir).unwrap();
+    }
+}
@@ -1,80 +1,55 @@
-use anyhow::{anyhow, Result};
-use bytes::{BufMut, Bytes, BytesMut};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing_subscriber::{
+    fmt::format::FmtSpan, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter,
 };
 
 use kafka_starter_rust::*;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    println!("Logs from your program will appear here!");
-
-    let listener = TcpListener::bind("127.0.0.1:9092").await?;
-
-    loop {
-        let (stream, _) = listener.accept().await?;
-        tokio::spawn(async move {
-            println!("accepted new connection");
-            if let Err(e) = handle_conn(stream).await {
-                eprintln!("error: {}", e);
-            }
-        });
+    let mut args = std::env::args().skip(1);
+    let first = args.next();
+    if let Some(command) = first.as_deref().filter(|arg| COMMANDS.contains(arg)) {
+        // Tools keep stdout for their output and only log what went wrong.
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .with_env_filter(
+                EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+            )
+            .init();
+        return run_command(command, args.collect()).await;
     }
-}
-
-async fn handle_conn(mut stream: TcpStream) -> Result<()> {
-    loop {
-        let mut message = get_message(&mut stream).await?;
-        let resp = process_message(&mut message)?;
-        let resp_msg = create_response_message(resp.as_bytes());
-        println!("response: {:?}", resp_msg.to_vec());
-        stream.write(&resp_msg).await?;
-    }
-}
 
-async fn get_message(stream: &mut TcpStream) -> Result<Bytes> {
-    let mut len_buf = [0; 4];
-    stream.read_exact(&mut len_buf).await?;
-
-    let msg_len = i32::from_be_bytes(len_buf) as usize;
-    let mut msg_buf = vec![0; msg_len];
-    stream.read_exact(&mut msg_buf).await?;
-
-    Ok(Bytes::from(msg_buf))
-}
-
-fn process_message(message: &mut Bytes) -> Result<Box<dyn Response + Send>> {
-    let header = HeaderV2::deserialize(message);
-    let request_api_key = match ApiKey::try_from(header.api_key) {
-        Ok(key) => key,
-        Err(_) => {
-            return Err(anyhow!("Invalid request api key, {:?}", header.api_key));
-        }
-    };
-    println!("request: {:?}", message.to_vec());
-    let response: Box<dyn Response + Send> = match request_api_key {
-        ApiKey::Fetch => {
-            let res = fetch::handle_request(header, message)?;
-            Box::new(res)
-        }
-        ApiKey::ApiVersions => {
-            let res = api_versions::ApiVersionsResponseV3::new(header);
-            Box::new(res)
-        }
-        ApiKey::DescribeTopicPartitions => {
-            let res = describe_topic_partitions::handle_request(header, message)?;
-            Box::new(res)
-        }
+    let config_path = first.map(PathBuf::from);
+    let config = match &config_path {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
     };
-    Ok(response)
+    let (filter, log_filter) = reload::Layer::new(build_log_filter(&config)?);
+    let log_level = Arc::new(LogLevel::new(log_filter));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer().with_span_events(if config.log_span_timing {
+                FmtSpan::CLOSE
+            } else {
+                FmtSpan::NONE
+            }),
+        )
+        .init();
+
+    run_broker(config, config_path, log_level, shutdown_signal()).await
 }
 
-fn create_response_message(src: Bytes) -> Bytes {
-    let mut bytes = BytesMut::with_capacity(src.len() + 4);
-    let msg_size = src.len() as i32;
-    bytes.put_i32(msg_size);
-    bytes.put_slice(&src);
-    bytes.freeze()
+/// Resolves on the first SIGINT or SIGTERM.
+async fn shutdown_signal() -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res?,
+        _ = sigterm.recv() => {}
+    }
+    Ok(())
 }
@@ -0,0 +1,139 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::metrics::Metrics;
+
+pub const IN_FLIGHT_BYTES_METRIC: &str = "kafka_server_in_flight_bytes";
+
+/// Caps the bytes of requests that have been read but not yet answered.
+///
+/// Callers wait for room before reading a request body, so an exhausted pool
+/// leaves data in the socket and TCP flow control pushes back on the client.
+pub struct MemoryPool {
+    capacity: usize,
+    bytes: Arc<Semaphore>,
+}
+
+impl MemoryPool {
+    /// `None` means unbounded.
+    pub fn new(capacity: Option<usize>) -> Arc<Self> {
+        let capacity = capacity
+            .unwrap_or(Semaphore::MAX_PERMITS)
+            .min(Semaphore::MAX_PERMITS)
+            .min(u32::MAX as usize);
+        Arc::new(Self {
+            capacity,
+            bytes: Arc::new(Semaphore::new(capacity)),
+        })
+    }
+
+    /// Waits until `size` bytes are free. A request larger than the whole pool
+    /// waits for the pool to drain instead of never being admitted.
+    pub async fn acquire(&self, size: usize) -> MemoryPermit {
+        let permit = self
+            .bytes
+            .clone()
+            .acquire_many_owned(self.clamp(size))
+            .await
+            .expect("memory pool semaphore is never closed");
+        MemoryPermit { _bytes: permit }
+    }
+
+    pub fn try_acquire(&self, size: usize) -> Option<MemoryPermit> {
+        self.bytes
+            .clone()
+            .try_acquire_many_owned(self.clamp(size))
+            .ok()
+            .map(|permit| MemoryPermit { _bytes: permit })
+    }
+
+    pub fn available(&self) -> usize {
+        self.bytes.available_permits()
+    }
+
+    fn clamp(&self, size: usize) -> u32 {
+        size.min(self.capacity) as u32
+    }
+}
+
+/// Bytes held in a [`MemoryPool`], returned when dropped.
+pub struct MemoryPermit {
+    _bytes: OwnedSemaphorePermit,
+}
+
+/// A broker-wide cap on the bytes of requests being processed plus responses
+/// waiting to be written, from `in.flight.max.bytes`.
+///
+/// Unlike a [`MemoryPool`], charging never waits: a response that has been
+/// built has to be held somewhere. Instead connections stop taking new
+/// requests while the budget is exceeded, until enough responses are written.
+pub struct MemoryBudget {
+    limit: usize,
+    used: AtomicUsize,
+    freed: Notify,
+    metrics: Arc<Metrics>,
+}
+
+impl MemoryBudget {
+    /// `None` means unbounded.
+    pub fn new(limit: Option<usize>, metrics: Arc<Metrics>) -> Arc<Self> {
+        metrics.set_gauge(IN_FLIGHT_BYTES_METRIC, &[], 0);
+        Arc::new(Self {
+            limit: limit.unwrap_or(usize::MAX),
+            used: AtomicUsize::new(0),
+            freed: Notify::new(),
+            metrics,
+        })
+    }
+
+    /// Counts `bytes` against the budget until the returned charge is dropped.
+    pub fn charge(self: &Arc<Self>, bytes: usize) -> BudgetCharge {
+        let used = self.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.metrics
+            .set_gauge(IN_FLIGHT_BYTES_METRIC, &[], used as i64);
+        BudgetCharge {
+            budget: self.clone(),
+            bytes,
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.used() >= self.limit
+    }
+
+    /// Waits until the budget is no longer exceeded.
+    pub async fn wait_for_room(&self) {
+        loop {
+            // Registered before the check, so a release in between is seen.
+            let freed = self.freed.notified();
+            if !self.is_exceeded() {
+                return;
+            }
+            freed.await;
+        }
+    }
+}
+
+/// Bytes counted against a [`MemoryBudget`], released when dropped.
+pub struct BudgetCharge {
+    budget: Arc<MemoryBudget>,
+    bytes: usize,
+}
+
+impl Drop for BudgetCharge {
+    fn drop(&mut self) {
+        let used = self.budget.used.fetch_sub(self.bytes, Ordering::Relaxed) - self.bytes;
+        self.budget
+            .metrics
+            .set_gauge(IN_FLIGHT_BYTES_METRIC, &[], used as i64);
+        self.budget.freed.notify_waiters();
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use tracing::info;
+
+use crate::config::parse_properties;
+use crate::protocol::Uuid;
+
+pub const META_PROPERTIES_FILE: &str = "meta.properties";
+
+/// The only `meta.properties` version written by KRaft brokers.
+const META_PROPERTIES_VERSION: &str = "1";
+
+/// The identity recorded in a log directory's `meta.properties`, which ties
+/// the directory to one cluster and one node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaProperties {
+    pub cluster_id: String,
+    pub node_id: i32,
+    /// Unique to the directory. Files written before Kafka 3.7 lack it; one is
+    /// generated when they are loaded.
+    pub directory_id: Option<String>,
+}
+
+impl MetaProperties {
+    /// Reads `meta.properties` from `dir`, or returns `None` if there is none.
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(META_PROPERTIES_FILE);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("read '{}'", path.display())),
+        };
+        Self::from_properties(&parse_properties(&contents))
+            .with_context(|| format!("invalid '{}'", path.display()))
+            .map(Some)
+    }
+
+    /// The directory id as the metadata log and the protocol carry it.
+    pub fn directory_uuid(&self) -> Option<Uuid> {
+        let bytes = URL_SAFE_NO_PAD.decode(self.directory_id.as_ref()?).ok()?;
+        Some(Uuid::from_bytes(bytes.try_into().ok()?))
+    }
+
+    fn from_properties(properties: &HashMap<String, String>) -> Result<Self> {
+        let get = |key: &str| {
+            properties
+                .get(key)
+                .ok_or_else(|| anyhow!("missing '{}'", key))
+        };
+        let version = get("version")?;
+        if version != META_PROPERTIES_VERSION {
+            return Err(anyhow!(
+                "unsupported version {}, expected {}",
+                version,
+                META_PROPERTIES_VERSION
+            ));
+        }
+        let node_id = get("node.id")?;
+        Ok(Self {
+            cluster_id: get("cluster.id")?.clone(),
+            node_id: node_id
+                .parse()
+                .map_err(|e| anyhow!("invalid node.id '{}': {}", node_id, e))?,
+            directory_id: properties.get("directory.id").cloned(),
+        })
+    }
+
+    /// Writes `meta.properties` to `dir`, replacing it atomically.
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(META_PROPERTIES_FILE);
+        let tmp = dir.join(format!("{}.tmp", META_PROPERTIES_FILE));
+        let mut contents = format!("#\ncluster.id={}\n", self.cluster_id);
+        if let Some(directory_id) = &self.directory_id {
+            contents += &format!("directory.id={}\n", directory_id);
+        }
+        contents += &format!(
+            "node.id={}\nversion={}\n",
+            self.node_id, META_PROPERTIES_VERSION
+        );
+        std::fs::write(&tmp, contents)
+            .and_then(|()| std::fs::rename(&tmp, &path))
+            .with_context(|| format!("write '{}'", path.display()))
+    }
+}
+
+/// Loads `meta.properties` from every log directory, creating it where it is
+/// missing, and returns the cluster id.
+///
+/// Every directory must belong to the same cluster and to `node_id`, and no
+/// two may share a directory id. New directories join the cluster the others
+/// already belong to; when there are none, a new cluster id is generated.
+pub fn ensure_meta_properties(log_dirs: &[PathBuf], node_id: i32) -> Result<String> {
+    let mut existing = Vec::new();
+    let mut missing = Vec::new();
+    for dir in log_dirs {
+        match MetaProperties::read(dir)? {
+            Some(meta) => existing.push((dir, meta)),
+            None => missing.push(dir),
+        }
+    }
+
+    for (dir, meta) in &existing {
+        if meta.node_id != node_id {
+            return Err(anyhow!(
+                "log dir '{}' belongs to node {}, but node.id is {}",
+                dir.display(),
+                meta.node_id,
+                node_id
+            ));
+        }
+    }
+    for pair in existing.windows(2) {
+        let ((dir_a, a), (dir_b, b)) = (&pair[0], &pair[1]);
+        if a.cluster_id != b.cluster_id {
+            return Err(anyhow!(
+                "log dirs '{}' and '{}' belong to different clusters ({} and {})",
+                dir_a.display(),
+                dir_b.display(),
+                a.cluster_id,
+                b.cluster_id
+            ));
+        }
+    }
+    for (i, (dir, meta)) in existing.iter().enumerate() {
+        let Some(directory_id) = &meta.directory_id else {
+            continue;
+        };
+        if let Some((other, _)) = existing[..i]
+            .iter()
+            .find(|(_, m)| m.directory_id.as_ref() == Some(directory_id))
+        {
+            return Err(anyhow!(
+                "log dirs '{}' and '{}' have the same directory.id {}",
+                other.display(),
+                dir.display(),
+                directory_id
+            ));
+        }
+    }
+    for (dir, meta) in &mut existing {
+        if meta.directory_id.is_none() {
+            meta.directory_id = Some(random_id());
+            meta.write(dir)?;
+            info!(log_dir = %dir.display(), "added directory.id to meta.properties");
+        }
+    }
+
+    let cluster_id = match existing.first() {
+        Some((_, meta)) => meta.cluster_id.clone(),
+        None => {
+            let cluster_id = random_id();
+            info!(cluster_id = %cluster_id, "formatting log dirs for a new cluster");
+            cluster_id
+        }
+    };
+    for dir in missing {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("create log dir '{}'", dir.display()))?;
+        let meta = MetaProperties {
+            cluster_id: cluster_id.clone(),
+            node_id,
+            directory_id: Some(random_id()),
+        };
+        meta.write(dir)?;
+        info!(log_dir = %dir.display(), "wrote meta.properties");
+    }
+    Ok(cluster_id)
+}
+
+/// Each log dir with the directory id in its `meta.properties`. Dirs
+/// without one are left out.
+pub fn log_dir_ids(log_dirs: &[PathBuf]) -> Vec<(PathBuf, Uuid)> {
+    log_dirs
+        .iter()
+        .filter_map(|dir| {
+            let meta = MetaProperties::read(dir).ok().flatten()?;
+            Some((dir.clone(), meta.directory_uuid()?))
+        })
+        .collect()
+}
+
+/// A random 128-bit id in Kafka's base64 form, e.g. `MkU3OEVBNTcwNTJENDM2Qg`.
+pub fn random_id() -> String {
+    loop {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let id = URL_SAFE_NO_PAD.encode(bytes);
+        // Kafka never hands out ids starting with '-', which would read as a
+        // command-line flag.
+        if !id.starts_with('-') {
+            return id;
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! The cluster metadata as this broker last read it from the metadata log.
+//! Handlers read it from here rather than from the log, which is read again
+//! only when it changes.
+
+use std::{
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use tracing::warn;
+
+use crate::api::cluster_metadata::RecordBatches;
+
+/// The latest metadata, swapped whole whenever the metadata log changes so
+/// that a reader sees one version throughout its request.
+#[derive(Default)]
+pub struct MetadataImage {
+    current: RwLock<Arc<RecordBatches>>,
+}
+
+impl MetadataImage {
+    /// Reads the metadata log at `path`. A node that has none yet, or one
+    /// that can't be read, starts from empty metadata until the log is next
+    /// published.
+    pub fn load(path: &Path) -> Self {
+        let image = Self::default();
+        if path.exists() {
+            match RecordBatches::from_file(path) {
+                Ok(metadata) => {
+                    image.publish(metadata);
+                }
+                Err(e) => warn!(error = %e, "failed to read the metadata log"),
+            }
+        }
+        image
+    }
+
+    /// The metadata as last published.
+    pub fn current(&self) -> Arc<RecordBatches> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Makes `metadata` what later readers see, and returns it.
+    pub fn publish(&self, metadata: RecordBatches) -> Arc<RecordBatches> {
+        let metadata = Arc::new(metadata);
+        *self.current.write().unwrap() = metadata.clone();
+        metadata
+    }
+}
@@ -0,0 +1,283 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration};
+
+use crate::protocol::{ApiKey, ErrorCode};
+
+pub const REQUESTS_METRIC: &str = "kafka_server_requests_total";
+pub const REQUEST_LATENCY_METRIC: &str = "kafka_server_request_latency_seconds";
+pub const REQUEST_BYTES_METRIC: &str = "kafka_server_request_bytes_total";
+pub const RESPONSE_BYTES_METRIC: &str = "kafka_server_response_bytes_total";
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 14] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Process-wide metrics registry.
+///
+/// Metrics are identified by a name plus a set of label pairs and are rendered
+/// in the Prometheus text exposition format.
+#[derive(Default)]
+pub struct Metrics {
+    inner: Mutex<Registry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<MetricKey, u64>,
+    gauges: BTreeMap<MetricKey, i64>,
+    histograms: BTreeMap<MetricKey, Histogram>,
+}
+
+/// A fixed-bucket histogram over [`LATENCY_BUCKETS`].
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    /// Per-bucket (not cumulative) counts; the extra last bucket is `+Inf`.
+    pub buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        let idx = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[idx] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (bucket, n) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += n;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    /// The upper bound of the bucket holding quantile `q`; `+Inf` when it
+    /// falls past the last bucket.
+    pub fn quantile(&self, q: f64) -> f64 {
+        let rank = (q * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (idx, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank && seen > 0 {
+                return LATENCY_BUCKETS.get(idx).copied().unwrap_or(f64::INFINITY);
+            }
+        }
+        0.0
+    }
+}
+
+/// Request totals for one API key, summed over error codes.
+#[derive(Debug, Clone)]
+pub struct RequestSummary {
+    pub api_key: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub latency: Histogram,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct MetricKey {
+    name: String,
+    labels: Vec<(String, String)>,
+}
+
+impl MetricKey {
+    fn new(name: &str, labels: &[(&str, &str)]) -> Self {
+        Self {
+            name: name.to_string(),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn incr_counter(&self, name: &str, labels: &[(&str, &str)], n: u64) {
+        let mut registry = self.inner.lock().unwrap();
+        *registry
+            .counters
+            .entry(MetricKey::new(name, labels))
+            .or_default() += n;
+    }
+
+    pub fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: i64) {
+        let mut registry = self.inner.lock().unwrap();
+        registry.gauges.insert(MetricKey::new(name, labels), value);
+    }
+
+    pub fn remove_gauge(&self, name: &str, labels: &[(&str, &str)]) {
+        let mut registry = self.inner.lock().unwrap();
+        registry.gauges.remove(&MetricKey::new(name, labels));
+    }
+
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        let registry = self.inner.lock().unwrap();
+        registry
+            .counters
+            .get(&MetricKey::new(name, labels))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> i64 {
+        let registry = self.inner.lock().unwrap();
+        registry
+            .gauges
+            .get(&MetricKey::new(name, labels))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn observe_histogram(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let mut registry = self.inner.lock().unwrap();
+        registry
+            .histograms
+            .entry(MetricKey::new(name, labels))
+            .or_default()
+            .observe(value);
+    }
+
+    pub fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Histogram {
+        let registry = self.inner.lock().unwrap();
+        registry
+            .histograms
+            .get(&MetricKey::new(name, labels))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Records one completed request against its API key and the top-level
+    /// error code of its response.
+    pub fn record_request(
+        &self,
+        api_key: ApiKey,
+        error_code: ErrorCode,
+        latency: Duration,
+        request_bytes: usize,
+        response_bytes: usize,
+    ) {
+        let api_key = format!("{:?}", api_key);
+        let error_code = format!("{:?}", error_code);
+        let labels = [
+            ("api_key", api_key.as_str()),
+            ("error_code", error_code.as_str()),
+        ];
+        self.incr_counter(REQUESTS_METRIC, &labels, 1);
+        self.observe_histogram(REQUEST_LATENCY_METRIC, &labels, latency.as_secs_f64());
+        let labels = [("api_key", api_key.as_str())];
+        self.incr_counter(REQUEST_BYTES_METRIC, &labels, request_bytes as u64);
+        self.incr_counter(RESPONSE_BYTES_METRIC, &labels, response_bytes as u64);
+    }
+
+    /// Per-API request counts and latencies, merged across error codes.
+    pub fn request_summary(&self) -> Vec<RequestSummary> {
+        let registry = self.inner.lock().unwrap();
+        let mut summaries: BTreeMap<&str, RequestSummary> = BTreeMap::new();
+        for (key, histogram) in &registry.histograms {
+            if key.name != REQUEST_LATENCY_METRIC {
+                continue;
+            }
+            let label = |name: &str| {
+                key.labels
+                    .iter()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.as_str())
+                    .unwrap_or_default()
+            };
+            let api_key = label("api_key");
+            let summary = summaries.entry(api_key).or_insert_with(|| RequestSummary {
+                api_key: api_key.to_string(),
+                requests: 0,
+                errors: 0,
+                latency: Histogram::default(),
+            });
+            summary.requests += histogram.count;
+            if label("error_code") != format!("{:?}", ErrorCode::None) {
+                summary.errors += histogram.count;
+            }
+            summary.latency.merge(histogram);
+        }
+        summaries.into_values().collect()
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let registry = self.inner.lock().unwrap();
+        let mut out = String::new();
+        render_family(&mut out, "counter", &registry.counters);
+        render_family(&mut out, "gauge", &registry.gauges);
+        render_histograms(&mut out, &registry.histograms);
+        out
+    }
+}
+
+fn render_family<V: std::fmt::Display>(
+    out: &mut String,
+    kind: &str,
+    values: &BTreeMap<MetricKey, V>,
+) {
+    let mut last_name = None;
+    for (key, value) in values {
+        if last_name != Some(&key.name) {
+            let _ = writeln!(out, "# TYPE {} {}", key.name, kind);
+            last_name = Some(&key.name);
+        }
+        let _ = writeln!(out, "{}{} {}", key.name, format_labels(&key.labels), value);
+    }
+}
+
+fn render_histograms(out: &mut String, values: &BTreeMap<MetricKey, Histogram>) {
+    let mut last_name = None;
+    for (key, histogram) in values {
+        if last_name != Some(&key.name) {
+            let _ = writeln!(out, "# TYPE {} histogram", key.name);
+            last_name = Some(&key.name);
+        }
+        let mut cumulative = 0;
+        for (idx, n) in histogram.buckets.iter().enumerate() {
+            cumulative += n;
+            let le = LATENCY_BUCKETS
+                .get(idx)
+                .map(|bound| bound.to_string())
+                .unwrap_or_else(|| "+Inf".to_string());
+            let mut labels = key.labels.clone();
+            labels.push(("le".to_string(), le));
+            let _ = writeln!(
+                out,
+                "{}_bucket{} {}",
+                key.name,
+                format_labels(&labels),
+                cumulative
+            );
+        }
+        let labels = format_labels(&key.labels);
+        let _ = writeln!(out, "{}_sum{} {}", key.name, labels, histogram.sum);
+        let _ = writeln!(out, "{}_count{} {}", key.name, labels, histogram.count);
+    }
+}
+
+fn format_labels(labels: &[(String, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
@@ -0,0 +1,558 @@
+//! SASL/OAUTHBEARER (RFC 7628, KIP-255 and KIP-768): clients authenticate
+//! with a JWT issued by an identity provider. The broker checks the token's
+//! signature against the keys of the JWK set at
+//! `sasl.oauthbearer.jwks.endpoint.url`, either an `https://` endpoint
+//! fetched every `sasl.oauthbearer.jwks.endpoint.refresh.ms` or a `file:`
+//! holding the keys, then its expiry, audience and issuer, and the client
+//! acts as the principal named by its `sasl.oauthbearer.sub.claim.name`
+//! claim.
+//!
+//! Tokens are signed with RS256, RS384 or RS512 under an RSA key, or with
+//! HS256, HS384 or HS512 under a symmetric one. The signature and the
+//! standard claims are checked by `jsonwebtoken`.
+
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    errors::ErrorKind,
+    jwk::{AlgorithmParameters, Jwk, PublicKeyUse},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest::{header::ACCEPT, Url};
+use serde_json::Value;
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls;
+use tracing::{debug, warn};
+
+use crate::sasl::{SaslServer, SaslStep};
+use crate::security::KafkaPrincipal;
+
+pub const OAUTHBEARER_MECHANISM: &str = "OAUTHBEARER";
+
+/// What the server sends a client whose token was rejected, before failing
+/// the exchange once the client acknowledges it.
+const INVALID_TOKEN_CHALLENGE: &[u8] = br#"{"status":"invalid_token"}"#;
+
+/// How long fetching the JWK set may take.
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The shortest RSA modulus accepted, in bytes, as RFC 7518 requires.
+const MIN_MODULUS_BYTES: usize = 256;
+
+/// Where the keys come from and what a token has to claim.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OAuthBearerSettings {
+    /// `sasl.oauthbearer.jwks.endpoint.url`.
+    pub jwks_endpoint: JwksEndpoint,
+    /// `sasl.oauthbearer.jwks.endpoint.refresh.ms`.
+    pub jwks_refresh: Duration,
+    /// The claim naming the principal, `sasl.oauthbearer.sub.claim.name`.
+    pub sub_claim_name: String,
+    /// `sasl.oauthbearer.expected.audience`; when set, a token's `aud` has
+    /// to include one of them.
+    pub expected_audience: Vec<String>,
+    /// `sasl.oauthbearer.expected.issuer`.
+    pub expected_issuer: Option<String>,
+    /// How far clocks may disagree when checking times,
+    /// `sasl.oauthbearer.clock.skew.seconds`.
+    pub clock_skew: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JwksEndpoint {
+    /// Only `https://`: keys fetched in the clear could be swapped for an
+    /// attacker's by anyone on the path.
+    Https(Url),
+    File(PathBuf),
+}
+
+impl std::str::FromStr for JwksEndpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        if let Some(path) = url.strip_prefix("file:") {
+            // Both file:/path and file:///path name /path.
+            let path = path.strip_prefix("//").unwrap_or(path);
+            return Ok(Self::File(PathBuf::from(path)));
+        }
+        let parsed = Url::parse(url).with_context(|| format!("invalid JWKS endpoint '{}'", url))?;
+        if parsed.scheme() != "https" {
+            return Err(anyhow!(
+                "unsupported JWKS endpoint '{}', expected an https:// or file: URL",
+                url
+            ));
+        }
+        if parsed.host_str().is_none_or(str::is_empty) {
+            return Err(anyhow!("JWKS endpoint '{}' has no host", url));
+        }
+        Ok(Self::Https(parsed))
+    }
+}
+
+impl fmt::Display for JwksEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwksEndpoint::Https(url) => write!(f, "{}", url),
+            JwksEndpoint::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+/// One verification key of the JWK set.
+struct KeyEntry {
+    kid: Option<String>,
+    /// Whether this is an RSA key rather than a symmetric one, so that a
+    /// token is only checked against keys of the kind its `alg` names.
+    rsa: bool,
+    key: DecodingKey,
+}
+
+/// Validates OAUTHBEARER tokens against the current JWK set, which it keeps
+/// refreshed in the background.
+pub struct OAuthBearerValidator {
+    settings: OAuthBearerSettings,
+    keys: RwLock<Vec<KeyEntry>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl OAuthBearerValidator {
+    /// Loads the JWK set, and again every `jwks_refresh`. A set that fails
+    /// to load leaves the last one in place.
+    pub fn start(settings: OAuthBearerSettings) -> Arc<Self> {
+        let validator = Arc::new(Self {
+            settings,
+            keys: RwLock::new(Vec::new()),
+            task: Mutex::new(None),
+        });
+        let task = tokio::spawn(validator.clone().refresh_keys());
+        *validator.task.lock().unwrap() = Some(task);
+        validator
+    }
+
+    pub fn shutdown(&self) {
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+
+    async fn refresh_keys(self: Arc<Self>) {
+        let mut tick = tokio::time::interval(self.settings.jwks_refresh);
+        loop {
+            tick.tick().await;
+            let endpoint = &self.settings.jwks_endpoint;
+            match load_jwks(endpoint).await {
+                Ok(keys) => {
+                    debug!(endpoint = %endpoint, keys = keys.len(), "loaded JWK set");
+                    *self.keys.write().unwrap() = keys;
+                }
+                Err(e) => warn!(endpoint = %endpoint, error = %e, "failed to load JWK set"),
+            }
+        }
+    }
+
+    /// The principal a token authenticates, or why it doesn't.
+    pub fn validate(&self, token: &str) -> Result<KafkaPrincipal, String> {
+        let header = jsonwebtoken::decode_header(token).map_err(|e| rejection(&e))?;
+        let alg = header.alg;
+        let rsa = match alg {
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => true,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => false,
+            _ => return Err(format!("unsupported token algorithm {:?}", alg)),
+        };
+        let validation = self.validation(alg);
+        let keys = self.keys.read().unwrap();
+        let candidates = keys
+            .iter()
+            .filter(|entry| entry.rsa == rsa)
+            .filter(|entry| header.kid.is_none() || entry.kid == header.kid);
+        let mut found = false;
+        for entry in candidates {
+            match jsonwebtoken::decode::<Value>(token, &entry.key, &validation) {
+                Ok(token) => return self.check_claims(&token.claims),
+                Err(e) if *e.kind() == ErrorKind::InvalidSignature => found = true,
+                Err(e) => return Err(rejection(&e)),
+            }
+        }
+        if found {
+            Err("token signature is invalid".to_string())
+        } else {
+            Err(format!(
+                "no {:?} key{} in the JWK set",
+                alg,
+                header
+                    .kid
+                    .map(|kid| format!(" with kid {}", kid))
+                    .unwrap_or_default()
+            ))
+        }
+    }
+
+    /// What `jsonwebtoken` checks of a token signed with `alg`: its
+    /// signature, then `exp`, `nbf`, `aud` and `iss`.
+    fn validation(&self, alg: Algorithm) -> Validation {
+        let settings = &self.settings;
+        let mut validation = Validation::new(alg);
+        validation.leeway = settings.clock_skew.as_secs();
+        validation.validate_nbf = true;
+        if settings.expected_audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&settings.expected_audience);
+        }
+        if let Some(issuer) = &settings.expected_issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        validation
+    }
+
+    /// The claims `jsonwebtoken` leaves alone: `iat` and the subject.
+    fn check_claims(&self, claims: &Value) -> Result<KafkaPrincipal, String> {
+        let settings = &self.settings;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let skew = settings.clock_skew.as_secs_f64();
+        if claims
+            .get("iat")
+            .and_then(Value::as_f64)
+            .is_some_and(|iat| iat - skew > now)
+        {
+            return Err("token was issued in the future".to_string());
+        }
+        match claims.get(&settings.sub_claim_name).and_then(Value::as_str) {
+            Some(sub) if !sub.trim().is_empty() => Ok(KafkaPrincipal::user(sub)),
+            _ => Err(format!("token has no {} claim", settings.sub_claim_name)),
+        }
+    }
+}
+
+/// Why `jsonwebtoken` refused a token, as the client is told.
+fn rejection(e: &jsonwebtoken::errors::Error) -> String {
+    match e.kind() {
+        ErrorKind::ExpiredSignature => "token has expired".to_string(),
+        ErrorKind::ImmatureSignature => "token is not valid yet".to_string(),
+        ErrorKind::InvalidAudience => "token audience is not expected".to_string(),
+        ErrorKind::InvalidIssuer => "token issuer is not expected".to_string(),
+        ErrorKind::MissingRequiredClaim(claim) => format!("token has no {} claim", claim),
+        ErrorKind::InvalidToken => "token is not a signed JWT".to_string(),
+        ErrorKind::Base64(_) => "token is not base64url".to_string(),
+        ErrorKind::Json(_) | ErrorKind::Utf8(_) => "token is not JSON".to_string(),
+        _ => format!("token is invalid: {}", e),
+    }
+}
+
+/// The verification keys of the JWK set at `endpoint`. Keys of other types,
+/// only for encryption, or RSA keys shorter than RFC 7518 allows are
+/// skipped.
+async fn load_jwks(endpoint: &JwksEndpoint) -> Result<Vec<KeyEntry>> {
+    let body = match endpoint {
+        JwksEndpoint::File(path) => tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("read '{}'", path.display()))?,
+        JwksEndpoint::Https(url) => fetch(url).await?,
+    };
+    parse_jwks(&body)
+}
+
+fn parse_jwks(body: &str) -> Result<Vec<KeyEntry>> {
+    let jwks: Value = serde_json::from_str(body).context("JWK set is not JSON")?;
+    let keys = jwks
+        .get("keys")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("JWK set has no keys"))?;
+    let keys = keys
+        .iter()
+        .filter_map(|key| {
+            let jwk: Jwk = serde_json::from_value(key.clone()).ok()?;
+            if jwk.common.public_key_use == Some(PublicKeyUse::Encryption) {
+                return None;
+            }
+            let rsa = match &jwk.algorithm {
+                AlgorithmParameters::RSA(params) => {
+                    let n = URL_SAFE_NO_PAD.decode(&params.n).ok()?;
+                    let len = n.iter().skip_while(|&&b| b == 0).count();
+                    if len < MIN_MODULUS_BYTES {
+                        return None;
+                    }
+                    true
+                }
+                AlgorithmParameters::OctetKey(_) => false,
+                _ => return None,
+            };
+            Some(KeyEntry {
+                kid: jwk.common.key_id.clone(),
+                rsa,
+                key: DecodingKey::from_jwk(&jwk).ok()?,
+            })
+        })
+        .collect();
+    Ok(keys)
+}
+
+/// The body of a `GET` of `url` answered with a success status.
+async fn fetch(url: &Url) -> Result<String> {
+    // The process's TLS provider is ring, as for the listeners; it may
+    // already be installed.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let client = reqwest::Client::builder()
+        .https_only(true)
+        .timeout(JWKS_FETCH_TIMEOUT)
+        .build()
+        .context("build the HTTPS client")?;
+    let response = client
+        .get(url.clone())
+        .header(ACCEPT, "application/json")
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.text().await?)
+}
+
+/// Server side of the OAUTHBEARER exchange: the client's one message
+/// carries its token. A rejected token gets an error challenge, which the
+/// client acknowledges with a lone `0x01` before the exchange fails.
+pub struct OAuthBearerServer {
+    validator: Option<Arc<OAuthBearerValidator>>,
+    /// Why the token was rejected, once the error challenge is sent.
+    rejected: Option<String>,
+}
+
+impl OAuthBearerServer {
+    pub fn new(validator: Option<Arc<OAuthBearerValidator>>) -> Self {
+        Self {
+            validator,
+            rejected: None,
+        }
+    }
+}
+
+impl SaslServer for OAuthBearerServer {
+    fn mechanism(&self) -> &'static str {
+        OAUTHBEARER_MECHANISM
+    }
+
+    fn evaluate_response(&mut self, response: &[u8]) -> Result<SaslStep, String> {
+        if let Some(reason) = self.rejected.take() {
+            return Err(reason);
+        }
+        let message = std::str::from_utf8(response)
+            .map_err(|_| "OAUTHBEARER message is not valid UTF-8".to_string())?;
+        // gs2-header kvsep *(key=value kvsep) kvsep, kvsep being 0x01.
+        let (gs2_header, rest) = message
+            .split_once('\x01')
+            .ok_or("invalid OAUTHBEARER message")?;
+        let authzid = match gs2_header
+            .strip_prefix("n,")
+            .and_then(|h| h.strip_suffix(','))
+        {
+            Some("") => None,
+            Some(authzid) => Some(
+                authzid
+                    .strip_prefix("a=")
+                    .ok_or("invalid OAUTHBEARER GS2 header")?,
+            ),
+            None => return Err("invalid OAUTHBEARER GS2 header".to_string()),
+        };
+        let rest = rest
+            .strip_suffix("\x01\x01")
+            .ok_or("invalid OAUTHBEARER message")?;
+        let token = rest
+            .split('\x01')
+            .find_map(|kv| kv.strip_prefix("auth="))
+            .and_then(|auth| {
+                auth.split_once(' ')
+                    .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+                    .map(|(_, token)| token.trim())
+            })
+            .ok_or("OAUTHBEARER message has no bearer token")?;
+
+        let result = match &self.validator {
+            Some(validator) => validator.validate(token),
+            None => Err("no JWKS endpoint is configured".to_string()),
+        };
+        let result = result.and_then(|principal| match authzid {
+            Some(authzid) if authzid != principal.name => {
+                Err("authorization id must match the token subject".to_string())
+            }
+            _ => Ok(principal),
+        });
+        match result {
+            Ok(principal) => Ok(SaslStep::Complete(principal, Vec::new())),
+            Err(reason) => {
+                debug!(reason = %reason, "rejected OAUTHBEARER token");
+                self.rejected = Some(reason);
+                Ok(SaslStep::Challenge(INVALID_TOKEN_CHALLENGE.to_vec()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    /// The HMAC key of RFC 7515 appendix A.1.
+    const HS256_KEY: &str =
+        "AyM1SysPpbyDfgZld3umj1qzKObwVMkoqQ-EstJQLr_T-1qS0gZH75aKtMN3Yj0iPS4hcgUuTwjAzZr1Z9CAow";
+    /// The RSA modulus of RFC 7515 appendix A.2.
+    const RS256_N: &str = "ofgWCuLjybRlzo0tZWJjNiuSfb4p4fAkd_wWJcyQoTbji9k0l8W26mPddxHmfHQp-Vaw-4qPCJrcS2mJPMEzP1Pt0Bm4d4QlL-yRT-SFd2lZS-pCgNMsD1W_YpRPEwOWvG6b32690r2jZ47soMZo9wGzjb_7OMg0LOL-bSf63kpaSHSXndS5z5rexMdbBYUsLA9e-KXBdQOS-UTo7WTBEMa2R2CapHg665xsmtdVMTBQY4uDZlxvb3qCo5ZwKh9kG4LT6_I5IhlJH7aGhyxXFvUK-DWNmoudF8NAco9_h9iaGNj8q2ethFkMLs91kzk2PAcDTW9gb54h4FRWyuXpoQ";
+    /// The payload both appendices sign, which expired in 2011.
+    const RFC_PAYLOAD: &str = "eyJpc3MiOiJqb2UiLA0KICJleHAiOjEzMDA4MTkzODAsDQogImh0dHA6Ly9leGFtcGxlLmNvbS9pc19yb290Ijp0cnVlfQ";
+    const HS256_TOKEN: &str = "eyJ0eXAiOiJKV1QiLA0KICJhbGciOiJIUzI1NiJ9.{payload}.dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const RS256_TOKEN: &str = "eyJhbGciOiJSUzI1NiJ9.{payload}.cC4hiUPoj9Eetdgtv3hF80EGrhuB__dzERat0XF9g2VtQgr9PJbu3XOiZj5RZmh7AAuHIm4Bh-0Qc_lF5YKt_O8W2Fp5jujGbds9uJdbF9CUAr7t1dnZcAcQjbKBYNX4BAynRFdiuB--f_nZLgrnbyTyWzO75vRK5h6xBArLIARNPvkSjtQBMHlb1L07Qe7K0GarZRmB_eSN9383LcOLn6_dO--xi12jzDwusC-eOkHWEsqtFZESc6BfI7noOPqvhJ1phCnvWh6IeYI2w9QOYEUipUTI8np6LbgGY9Fs98rqVt5AXLIhWkWywlVmtVrBp0igcN_IoypGlUPQGe77Rw";
+
+    fn validator(keys: serde_json::Value) -> OAuthBearerValidator {
+        OAuthBearerValidator {
+            settings: OAuthBearerSettings {
+                jwks_endpoint: JwksEndpoint::File(PathBuf::from("/dev/null")),
+                jwks_refresh: Duration::from_secs(3600),
+                sub_claim_name: "sub".to_string(),
+                expected_audience: Vec::new(),
+                expected_issuer: None,
+                clock_skew: Duration::from_secs(30),
+            },
+            keys: RwLock::new(parse_jwks(&json!({ "keys": keys }).to_string()).unwrap()),
+            task: Mutex::new(None),
+        }
+    }
+
+    fn rfc_keys() -> serde_json::Value {
+        json!([
+            { "kty": "oct", "k": HS256_KEY },
+            { "kty": "RSA", "n": RS256_N, "e": "AQAB" },
+        ])
+    }
+
+    fn rfc_token(template: &str) -> String {
+        template.replace("{payload}", RFC_PAYLOAD)
+    }
+
+    /// An HS256 token for `sub` expiring `expires_in` seconds from now.
+    fn hs256_token(secret: &[u8], sub: &str, expires_in: i64) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let claims = json!({ "sub": sub, "exp": now + expires_in });
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(secret),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn verifies_the_rfc_7515_examples() {
+        // Both are signed correctly, so what stops them is their expiry;
+        // with a signature byte changed, it's the signature.
+        let validator = validator(rfc_keys());
+        for template in [HS256_TOKEN, RS256_TOKEN] {
+            let token = rfc_token(template);
+            assert_eq!(validator.validate(&token).unwrap_err(), "token has expired");
+
+            let last = if token.ends_with('A') { "B" } else { "A" };
+            let tampered = format!("{}{}", &token[..token.len() - 1], last);
+            assert_eq!(
+                validator.validate(&tampered).unwrap_err(),
+                "token signature is invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_a_current_token_signed_by_a_jwks_key() {
+        let validator = validator(rfc_keys());
+        let secret = URL_SAFE_NO_PAD.decode(HS256_KEY).unwrap();
+        let principal = validator
+            .validate(&hs256_token(&secret, "alice", 60))
+            .unwrap();
+        assert_eq!(principal, KafkaPrincipal::user("alice"));
+    }
+
+    #[test]
+    fn refuses_a_token_signed_with_another_key() {
+        let validator = validator(rfc_keys());
+        let token = hs256_token(b"not the key in the JWK set", "alice", 60);
+        assert_eq!(
+            validator.validate(&token).unwrap_err(),
+            "token signature is invalid"
+        );
+    }
+
+    #[test]
+    fn refuses_an_hmac_token_keyed_with_the_rsa_public_key() {
+        // The algorithm confusion attack: the RSA public key is no secret,
+        // so an HS256 token signed with it mustn't pass as signed by it.
+        let validator = validator(json!([{ "kty": "RSA", "n": RS256_N, "e": "AQAB" }]));
+        let n = URL_SAFE_NO_PAD.decode(RS256_N).unwrap();
+        for secret in [&n[..], RS256_N.as_bytes()] {
+            let token = hs256_token(secret, "alice", 60);
+            assert_eq!(
+                validator.validate(&token).unwrap_err(),
+                "no HS256 key in the JWK set"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_an_expired_token() {
+        let validator = validator(rfc_keys());
+        let secret = URL_SAFE_NO_PAD.decode(HS256_KEY).unwrap();
+        // Within the clock skew it still passes; past it, it doesn't.
+        assert!(validator
+            .validate(&hs256_token(&secret, "alice", -10))
+            .is_ok());
+        assert_eq!(
+            validator
+                .validate(&hs256_token(&secret, "alice", -3600))
+                .unwrap_err(),
+            "token has expired"
+        );
+    }
+
+    #[test]
+    fn refuses_a_token_that_is_not_base64url() {
+        let validator = validator(rfc_keys());
+        let token = rfc_token(HS256_TOKEN);
+        let (header, rest) = token.split_once('.').unwrap();
+        let (payload, signature) = rest.split_once('.').unwrap();
+        for token in [
+            format!("{}!.{}.{}", header, payload, signature),
+            format!("{}.{}*.{}", header, payload, signature),
+            format!("{}.{}.{}+", header, payload, signature),
+            format!("{}.{}", header, payload),
+        ] {
+            assert!(validator.validate(&token).is_err(), "{}", token);
+        }
+    }
+
+    #[test]
+    fn takes_only_https_or_file_endpoints() {
+        assert!("http://idp.example.com/jwks"
+            .parse::<JwksEndpoint>()
+            .is_err());
+        assert!("ftp://idp.example.com/jwks"
+            .parse::<JwksEndpoint>()
+            .is_err());
+        let endpoint: JwksEndpoint = "https://idp.example.com:8443/jwks".parse().unwrap();
+        assert_eq!(endpoint.to_string(), "https://idp.example.com:8443/jwks");
+        assert_eq!(
+            "file:///etc/kafka/jwks.json"
+                .parse::<JwksEndpoint>()
+                .unwrap(),
+            JwksEndpoint::File(PathBuf::from("/etc/kafka/jwks.json"))
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::metrics::Metrics;
+use crate::quota::{QuotaWindow, Rate};
+
+pub const PARTITION_BYTES_IN_METRIC: &str = "kafka_server_partition_bytes_in_total";
+pub const PARTITION_BYTES_OUT_METRIC: &str = "kafka_server_partition_bytes_out_total";
+pub const PARTITION_BYTES_IN_RATE_METRIC: &str = "kafka_server_partition_bytes_in_per_second";
+pub const PARTITION_BYTES_OUT_RATE_METRIC: &str = "kafka_server_partition_bytes_out_per_second";
+pub const TOPIC_BYTES_IN_RATE_METRIC: &str = "kafka_server_topic_bytes_in_per_second";
+pub const TOPIC_BYTES_OUT_RATE_METRIC: &str = "kafka_server_topic_bytes_out_per_second";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ByteDirection {
+    /// Bytes appended to a partition's log.
+    In,
+    /// Bytes read from a partition's log for a fetch.
+    Out,
+}
+
+impl ByteDirection {
+    fn metrics(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Self::In => (
+                PARTITION_BYTES_IN_METRIC,
+                PARTITION_BYTES_IN_RATE_METRIC,
+                TOPIC_BYTES_IN_RATE_METRIC,
+            ),
+            Self::Out => (
+                PARTITION_BYTES_OUT_METRIC,
+                PARTITION_BYTES_OUT_RATE_METRIC,
+                TOPIC_BYTES_OUT_RATE_METRIC,
+            ),
+        }
+    }
+}
+
+/// A topic's rate, or one of its partitions'.
+type RateKey = (ByteDirection, String, Option<i32>);
+
+/// Bytes appended to and fetched from each partition, and each topic, as
+/// totals and as rates measured over the same windows as client quotas.
+///
+/// Replication counts too: a follower's appends are bytes in, and a
+/// follower's fetches from the leader bytes out.
+pub struct PartitionRates {
+    window: QuotaWindow,
+    rates: Mutex<HashMap<RateKey, Rate>>,
+    metrics: Arc<Metrics>,
+}
+
+impl PartitionRates {
+    pub fn new(window: QuotaWindow, metrics: Arc<Metrics>) -> Self {
+        Self {
+            window,
+            rates: Mutex::new(HashMap::new()),
+            metrics,
+        }
+    }
+
+    /// Records `bytes` going `direction` for partition `partition` of
+    /// `topic`.
+    pub fn record(&self, direction: ByteDirection, topic: &str, partition: i32, bytes: usize) {
+        let now = Instant::now();
+        let (total_metric, _, _) = direction.metrics();
+        let partition_label = partition.to_string();
+        self.metrics.incr_counter(
+            total_metric,
+            &[("topic", topic), ("partition", &partition_label)],
+            bytes as u64,
+        );
+        let mut rates = self.rates.lock().unwrap();
+        for key in [
+            (direction, topic.to_string(), Some(partition)),
+            (direction, topic.to_string(), None),
+        ] {
+            let rate = rates
+                .entry(key.clone())
+                .or_insert_with(|| Rate::new(self.window, now));
+            rate.record(bytes as f64, now);
+            self.export(&key, rate.measure(now).0);
+        }
+    }
+
+    /// The bytes per second going `direction` for `partition` of `topic`,
+    /// or for the whole topic when `None`.
+    pub fn rate(&self, direction: ByteDirection, topic: &str, partition: Option<i32>) -> f64 {
+        let mut rates = self.rates.lock().unwrap();
+        rates
+            .get_mut(&(direction, topic.to_string(), partition))
+            .map_or(0.0, |rate| rate.measure(Instant::now()).0)
+    }
+
+    /// Brings every rate gauge up to date, so that rates fall back to 0 once
+    /// traffic stops rather than keeping the last value recorded.
+    pub fn refresh(&self) {
+        let now = Instant::now();
+        let mut rates = self.rates.lock().unwrap();
+        for (key, rate) in rates.iter_mut() {
+            self.export(key, rate.measure(now).0);
+        }
+    }
+
+    fn export(&self, (direction, topic, partition): &RateKey, bytes_per_second: f64) {
+        let (_, partition_metric, topic_metric) = direction.metrics();
+        let value = bytes_per_second.round() as i64;
+        match partition {
+            Some(partition) => self.metrics.set_gauge(
+                partition_metric,
+                &[("topic", topic), ("partition", &partition.to_string())],
+                value,
+            ),
+            None => self
+                .metrics
+                .set_gauge(topic_metric, &[("topic", topic)], value),
+        }
+    }
+}
+
+/// Refreshes the rate gauges once a sample window.
+pub async fn monitor_partition_rates(rates: Arc<PartitionRates>) {
+    loop {
+        tokio::time::sleep(rates.window.window).await;
+        rates.refresh();
+    }
+}
@@ -1,14 +1,47 @@
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
 
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut, TryGetError};
 use integer_encoding::*;
-use num_enum::{IntoPrimitive, TryFromPrimitive};
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
 
-pub const CLUSTER_METADATA_LOG_FILE: &str =
-    "/tmp/kraft-combined-logs/__cluster_metadata-0/00000000000000000000.log";
+use crate::listener::ListenerType;
+use crate::wire_debug::WireDump;
 
-pub trait Response {
+/// The first segment of the cluster metadata log under `log_dir`.
+pub fn cluster_metadata_log_file(log_dir: &Path) -> PathBuf {
+    log_dir.join("__cluster_metadata-0/00000000000000000000.log")
+}
+
+pub trait Response: Send {
     fn as_bytes(&self) -> Bytes;
+
+    /// The response's top-level error code, for metrics.
+    fn error_code(&self) -> ErrorCode {
+        ErrorCode::None
+    }
+
+    /// Reports a quota throttle to the client, for responses that carry one.
+    fn set_throttle_time_ms(&mut self, _throttle_time_ms: i32) {}
+
+    /// Explains the response's errors to the client, for responses that
+    /// carry an error message.
+    fn set_error_message(&mut self, _error_message: &str) {}
+
+    /// The topics, or `topic-partition`s, the response covers, for logging.
+    fn topic_partitions(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Writes the response's fields for the wire-debug log. Responses that
+    /// don't are logged by size alone.
+    fn describe(&self, _dump: &mut WireDump) {}
+
+    /// Whether the response is written back at all. A client producing with
+    /// acks=0 reads none.
+    fn is_sent(&self) -> bool {
+        true
+    }
 }
 
 pub trait Serialize {
@@ -16,24 +49,304 @@ pub trait Serialize {
 }
 
 pub trait Deserialize<T> {
-    fn deserialize(src: &mut Bytes) -> T;
+    fn deserialize(src: &mut Bytes) -> Result<T, DecodeError>;
+}
+
+/// Bytes that don't hold what they were read as: cut short, or with a length,
+/// string or enum value that can't be.
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("{0}")]
+    Truncated(#[from] TryGetError),
+    #[error("malformed varint")]
+    Varint,
+    #[error("string is not UTF-8")]
+    Utf8(#[from] std::string::FromUtf8Error),
+    #[error("{0}")]
+    Invalid(String),
 }
 
-#[derive(Clone, Copy, IntoPrimitive, TryFromPrimitive)]
+/// Splits off the next `len` bytes, if there are that many.
+pub fn take(src: &mut Bytes, len: usize) -> Result<Bytes, DecodeError> {
+    if src.remaining() < len {
+        return Err(TryGetError {
+            requested: len,
+            available: src.remaining(),
+        }
+        .into());
+    }
+    Ok(src.split_to(len))
+}
+
+/// Reads an unsigned varint.
+pub fn read_uvarint(src: &mut Bytes) -> Result<u32, DecodeError> {
+    let (n, read) = u32::decode_var(src).ok_or(DecodeError::Varint)?;
+    src.advance(read);
+    Ok(n)
+}
+
+/// Reads an unsigned varint wide enough for a 64-bit value.
+pub fn read_uvarlong(src: &mut Bytes) -> Result<u64, DecodeError> {
+    let (n, read) = u64::decode_var(src).ok_or(DecodeError::Varint)?;
+    src.advance(read);
+    Ok(n)
+}
+
+/// Reads a zigzag-encoded signed varint wide enough for a 64-bit value.
+pub fn read_varlong(src: &mut Bytes) -> Result<i64, DecodeError> {
+    let (n, read) = i64::decode_var(src).ok_or(DecodeError::Varint)?;
+    src.advance(read);
+    Ok(n)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, IntoPrimitive, TryFromPrimitive)]
 #[repr(i16)]
 pub enum ApiKey {
+    Produce = 0,
     Fetch = 1,
+    ListOffsets = 2,
+    Metadata = 3,
+    OffsetCommit = 8,
+    OffsetFetch = 9,
+    FindCoordinator = 10,
+    JoinGroup = 11,
+    Heartbeat = 12,
+    LeaveGroup = 13,
+    SyncGroup = 14,
+    SaslHandshake = 17,
     ApiVersions = 18,
+    CreateTopics = 19,
+    DeleteTopics = 20,
+    InitProducerId = 22,
+    AddPartitionsToTxn = 24,
+    EndTxn = 26,
+    DescribeConfigs = 32,
+    AlterConfigs = 33,
+    DescribeLogDirs = 35,
+    SaslAuthenticate = 36,
+    CreatePartitions = 37,
+    CreateDelegationToken = 38,
+    RenewDelegationToken = 39,
+    ExpireDelegationToken = 40,
+    DescribeDelegationToken = 41,
+    AlterPartitionReassignments = 45,
+    ListPartitionReassignments = 46,
+    OffsetDelete = 47,
+    DescribeClientQuotas = 48,
+    AlterClientQuotas = 49,
+    Vote = 52,
+    BeginQuorumEpoch = 53,
+    EndQuorumEpoch = 54,
+    DescribeQuorum = 55,
+    AlterPartition = 56,
+    UpdateFeatures = 57,
+    FetchSnapshot = 59,
+    DescribeCluster = 60,
+    BrokerRegistration = 62,
+    BrokerHeartbeat = 63,
+    ConsumerGroupHeartbeat = 68,
+    AssignReplicasToDirs = 73,
     DescribeTopicPartitions = 75,
+    ShareGroupHeartbeat = 76,
+    ShareFetch = 78,
+    AddRaftVoter = 80,
+    RemoveRaftVoter = 81,
 }
 
-#[derive(Debug, Clone, Copy, IntoPrimitive)]
+impl ApiKey {
+    /// Whether the API is served on listeners of `listener_type`. Controller
+    /// listeners only take the controller APIs plus what every connection
+    /// needs to get started.
+    pub fn is_enabled_on(&self, listener_type: ListenerType) -> bool {
+        match self {
+            ApiKey::ApiVersions | ApiKey::SaslHandshake | ApiKey::SaslAuthenticate => true,
+            // KIP-919 lets admin clients describe the controller quorum.
+            ApiKey::DescribeCluster => true,
+            // Voters fetch the metadata log from the quorum leader.
+            ApiKey::Fetch => true,
+            ApiKey::Vote
+            | ApiKey::BeginQuorumEpoch
+            | ApiKey::EndQuorumEpoch
+            | ApiKey::DescribeQuorum
+            | ApiKey::FetchSnapshot
+            | ApiKey::AddRaftVoter
+            | ApiKey::RemoveRaftVoter
+            | ApiKey::AlterPartition
+            | ApiKey::BrokerRegistration
+            | ApiKey::BrokerHeartbeat
+            | ApiKey::AssignReplicasToDirs => listener_type == ListenerType::Controller,
+            ApiKey::Produce
+            | ApiKey::ListOffsets
+            | ApiKey::Metadata
+            | ApiKey::OffsetCommit
+            | ApiKey::OffsetFetch
+            | ApiKey::FindCoordinator
+            | ApiKey::InitProducerId
+            | ApiKey::AddPartitionsToTxn
+            | ApiKey::EndTxn
+            | ApiKey::JoinGroup
+            | ApiKey::Heartbeat
+            | ApiKey::LeaveGroup
+            | ApiKey::SyncGroup
+            | ApiKey::CreateTopics
+            | ApiKey::DeleteTopics
+            | ApiKey::CreatePartitions
+            | ApiKey::DescribeConfigs
+            | ApiKey::AlterConfigs
+            | ApiKey::DescribeLogDirs
+            | ApiKey::CreateDelegationToken
+            | ApiKey::RenewDelegationToken
+            | ApiKey::ExpireDelegationToken
+            | ApiKey::DescribeDelegationToken
+            | ApiKey::AlterPartitionReassignments
+            | ApiKey::ListPartitionReassignments
+            | ApiKey::OffsetDelete
+            | ApiKey::DescribeClientQuotas
+            | ApiKey::AlterClientQuotas
+            | ApiKey::UpdateFeatures
+            | ApiKey::ConsumerGroupHeartbeat
+            | ApiKey::DescribeTopicPartitions
+            | ApiKey::ShareGroupHeartbeat
+            | ApiKey::ShareFetch => listener_type == ListenerType::Broker,
+        }
+    }
+
+    /// Whether `api_version` of this API uses the flexible (tagged field)
+    /// encoding, which also selects request header v2 over v1.
+    pub fn is_flexible(&self, api_version: i16) -> bool {
+        match self {
+            ApiKey::Produce => api_version >= 9,
+            ApiKey::Fetch => api_version >= 12,
+            ApiKey::ListOffsets => api_version >= 6,
+            ApiKey::Metadata => api_version >= 9,
+            ApiKey::OffsetCommit => api_version >= 8,
+            ApiKey::OffsetFetch => api_version >= 6,
+            ApiKey::FindCoordinator => api_version >= 3,
+            ApiKey::JoinGroup => api_version >= 6,
+            ApiKey::Heartbeat => api_version >= 4,
+            ApiKey::LeaveGroup => api_version >= 4,
+            ApiKey::SyncGroup => api_version >= 4,
+            ApiKey::SaslHandshake => false,
+            ApiKey::ApiVersions => api_version >= 3,
+            ApiKey::CreateTopics => api_version >= 5,
+            ApiKey::DeleteTopics => api_version >= 4,
+            ApiKey::InitProducerId => api_version >= 2,
+            ApiKey::AddPartitionsToTxn => api_version >= 3,
+            ApiKey::EndTxn => api_version >= 3,
+            ApiKey::DescribeConfigs => api_version >= 4,
+            ApiKey::SaslAuthenticate => api_version >= 2,
+            ApiKey::CreatePartitions => api_version >= 2,
+            ApiKey::AlterConfigs => api_version >= 2,
+            ApiKey::DescribeLogDirs => api_version >= 2,
+            ApiKey::CreateDelegationToken => api_version >= 2,
+            ApiKey::RenewDelegationToken => api_version >= 2,
+            ApiKey::ExpireDelegationToken => api_version >= 2,
+            ApiKey::DescribeDelegationToken => api_version >= 2,
+            ApiKey::AlterPartitionReassignments => true,
+            ApiKey::ListPartitionReassignments => true,
+            ApiKey::OffsetDelete => false,
+            ApiKey::DescribeClientQuotas => api_version >= 1,
+            ApiKey::AlterClientQuotas => api_version >= 1,
+            ApiKey::Vote => true,
+            ApiKey::BeginQuorumEpoch => api_version >= 1,
+            ApiKey::EndQuorumEpoch => api_version >= 1,
+            ApiKey::DescribeQuorum => true,
+            ApiKey::AlterPartition => true,
+            ApiKey::UpdateFeatures => true,
+            ApiKey::FetchSnapshot => true,
+            ApiKey::DescribeCluster => true,
+            ApiKey::BrokerRegistration => true,
+            ApiKey::BrokerHeartbeat => true,
+            ApiKey::ConsumerGroupHeartbeat => true,
+            ApiKey::AssignReplicasToDirs => true,
+            ApiKey::DescribeTopicPartitions => true,
+            ApiKey::ShareGroupHeartbeat => true,
+            ApiKey::ShareFetch => true,
+            ApiKey::AddRaftVoter => true,
+            ApiKey::RemoveRaftVoter => true,
+        }
+    }
+}
+
+/// Codes this broker doesn't know read as `UnknownServerError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
 #[repr(i16)]
 pub enum ErrorCode {
+    #[num_enum(default)]
+    UnknownServerError = -1,
     None = 0,
+    CorruptMessage = 2,
     UnknownTopicOrPartition = 3,
+    LeaderNotAvailable = 5,
+    NotLeaderOrFollower = 6,
+    RequestTimedOut = 7,
+    OffsetMetadataTooLarge = 12,
+    CoordinatorNotAvailable = 15,
+    InvalidTopicException = 17,
+    InvalidRequiredAcks = 21,
+    IllegalGeneration = 22,
+    InconsistentGroupProtocol = 23,
+    InvalidGroupId = 24,
+    UnknownMemberId = 25,
+    InvalidSessionTimeout = 26,
+    RebalanceInProgress = 27,
+    TopicAuthorizationFailed = 29,
+    GroupAuthorizationFailed = 30,
+    ClusterAuthorizationFailed = 31,
+    UnsupportedSaslMechanism = 33,
+    IllegalSaslState = 34,
     UnsupportedVersion = 35,
+    TopicAlreadyExists = 36,
+    InvalidPartitions = 37,
+    InvalidReplicationFactor = 38,
+    InvalidReplicaAssignment = 39,
+    InvalidConfig = 40,
+    NotController = 41,
+    InvalidRequest = 42,
+    InvalidProducerEpoch = 47,
+    InvalidTxnState = 48,
+    InvalidProducerIdMapping = 49,
+    InvalidTransactionTimeout = 50,
+    ConcurrentTransactions = 51,
+    TransactionalIdAuthorizationFailed = 53,
+    OperationNotAttempted = 55,
+    KafkaStorageError = 56,
+    SaslAuthenticationFailed = 58,
+    DelegationTokenAuthDisabled = 61,
+    DelegationTokenNotFound = 62,
+    DelegationTokenOwnerMismatch = 63,
+    DelegationTokenRequestNotAllowed = 64,
+    DelegationTokenAuthorizationFailed = 65,
+    FetchSessionIdNotFound = 70,
+    InvalidFetchSessionEpoch = 71,
+    FencedLeaderEpoch = 74,
+    UnknownLeaderEpoch = 75,
+    UnsupportedCompressionType = 76,
+    StaleBrokerEpoch = 77,
+    InconsistentVoterSet = 94,
+    InvalidUpdateVersion = 95,
+    SnapshotNotFound = 98,
+    PositionOutOfRange = 99,
+    NonEmptyGroup = 68,
+    GroupIdNotFound = 69,
+    MemberIdRequired = 79,
+    NoReassignmentInProgress = 85,
+    GroupSubscribedToTopic = 86,
+    InvalidRecord = 87,
     UnknownTopicId = 100,
+    DuplicateBrokerRegistration = 101,
+    BrokerIdNotRegistered = 102,
+    InconsistentClusterId = 104,
+    IneligibleReplica = 107,
+    FencedMemberEpoch = 110,
+    UnsupportedAssignor = 112,
+    StaleMemberEpoch = 113,
+    UnsupportedEndpointType = 115,
+    InvalidRecordState = 121,
+    ShareSessionNotFound = 122,
+    InvalidShareSessionEpoch = 123,
+    DuplicateVoter = 126,
+    VoterNotFound = 127,
 }
 
 pub struct HeaderV0 {
@@ -46,6 +359,12 @@ impl HeaderV0 {
     }
 }
 
+impl Deserialize<Self> for HeaderV0 {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        Ok(Self::new(src.try_get_i32()?))
+    }
+}
+
 impl Serialize for HeaderV0 {
     fn serialize(&self) -> Bytes {
         let mut bytes = BytesMut::with_capacity(4);
@@ -65,6 +384,14 @@ impl HeaderV1 {
     }
 }
 
+impl Deserialize<Self> for HeaderV1 {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let correlation_id = src.try_get_i32()?;
+        TagBuffer::deserialize_fields(src)?;
+        Ok(Self::new(correlation_id))
+    }
+}
+
 impl Serialize for HeaderV1 {
     fn serialize(&self) -> Bytes {
         let mut bytes = BytesMut::with_capacity(5);
@@ -74,6 +401,9 @@ impl Serialize for HeaderV1 {
     }
 }
 
+/// A request header. Non-flexible request versions use header v1, which is the
+/// same layout without the trailing tagged fields.
+#[derive(Clone)]
 pub struct HeaderV2 {
     pub api_key: i16,
     pub api_version: i16,
@@ -82,24 +412,48 @@ pub struct HeaderV2 {
 }
 
 impl Deserialize<Self> for HeaderV2 {
-    fn deserialize(src: &mut Bytes) -> Self {
-        let api_key = src.get_i16();
-        let api_version = src.get_i16();
-        let correlation_id = src.get_i32();
-        let client_id = NullableString::deserialize(src);
-        TagBuffer::deserialize(src);
-        Self {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let api_key = src.try_get_i16()?;
+        let api_version = src.try_get_i16()?;
+        let correlation_id = src.try_get_i32()?;
+        let client_id = NullableString::deserialize(src)?;
+        let flexible = ApiKey::try_from(api_key)
+            .map(|key| key.is_flexible(api_version))
+            .unwrap_or(true);
+        if flexible {
+            TagBuffer::deserialize(src)?;
+        }
+        Ok(Self {
             api_key,
             api_version,
             correlation_id,
             client_id,
-        }
+        })
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Uuid(pub String);
 
+impl Uuid {
+    /// A random id, for a new topic or broker incarnation.
+    pub fn random() -> Self {
+        let mut bytes = [0u8; 16];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut bytes);
+        Self::from_bytes(bytes)
+    }
+
+    /// The id whose 16 bytes are `bytes`.
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        let mut s = hex::encode(bytes);
+        s.insert(8, '-');
+        s.insert(13, '-');
+        s.insert(18, '-');
+        s.insert(23, '-');
+        Self(s)
+    }
+}
+
 impl Display for Uuid {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -116,27 +470,38 @@ impl Serialize for Uuid {
 }
 
 impl Deserialize<Self> for Uuid {
-    fn deserialize(src: &mut Bytes) -> Self {
-        let mut s = hex::encode(src.split_to(16));
-        s.insert(8, '-');
-        s.insert(13, '-');
-        s.insert(18, '-');
-        s.insert(23, '-');
-        Self(s)
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let mut bytes = [0; 16];
+        src.try_copy_to_slice(&mut bytes)?;
+        Ok(Self::from_bytes(bytes))
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct NullableString(pub Option<String>);
 
+impl Serialize for NullableString {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        match &self.0 {
+            Some(s) => {
+                b.put_i16(s.len() as i16);
+                b.put(s.as_bytes());
+            }
+            None => b.put_i16(-1),
+        }
+        b.freeze()
+    }
+}
+
 impl Deserialize<Self> for NullableString {
-    fn deserialize(src: &mut Bytes) -> Self {
-        let len = src.get_i16();
-        let string_len = if len == -1 { 0 } else { len as usize };
-        if string_len == 0 || src.remaining() < string_len {
-            return Self(None);
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let len = src.try_get_i16()?;
+        if len <= 0 {
+            return Ok(Self(None));
         }
-        let bytes = src.split_to(string_len);
-        Self(Some(String::from_utf8(bytes.to_vec()).unwrap()))
+        let bytes = take(src, len as usize)?;
+        Ok(Self(Some(String::from_utf8(bytes.to_vec())?)))
     }
 }
 
@@ -165,14 +530,13 @@ impl Serialize for CompactNullableString {
 }
 
 impl Deserialize<Self> for CompactNullableString {
-    fn deserialize(src: &mut Bytes) -> Self {
-        let (len, read) = u32::decode_var(src).expect("Failed to decode length");
-        src.advance(read);
-        if len == 0 || src.remaining() < (len as usize - 1) {
-            return Self(None);
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let len = read_uvarint(src)?;
+        if len == 0 {
+            return Ok(Self(None));
         }
-        let bytes = src.split_to(len as usize - 1);
-        Self(Some(String::from_utf8(bytes.to_vec()).unwrap()))
+        let bytes = take(src, len as usize - 1)?;
+        Ok(Self(Some(String::from_utf8(bytes.to_vec())?)))
     }
 }
 
@@ -196,15 +560,123 @@ impl<T, U> Deserialize<Vec<U>> for CompactArray<T>
 where
     T: Deserialize<U>,
 {
-    fn deserialize(src: &mut Bytes) -> Vec<U> {
-        let (len, read) = u64::decode_var(src).expect("Failed to decode length");
-        src.advance(read);
-        let items_count = if len > 1 { len as usize - 1 } else { 0 };
-        let mut items = Vec::with_capacity(items_count);
-        for _ in 0..items_count {
-            items.push(T::deserialize(src));
+    fn deserialize(src: &mut Bytes) -> Result<Vec<U>, DecodeError> {
+        CompactArray::deserialize_with(src, T::deserialize)
+    }
+}
+
+impl<T> CompactArray<T> {
+    /// Reads an array whose items are decoded by `item`, for items whose
+    /// layout depends on the API version.
+    pub fn deserialize_with(
+        src: &mut Bytes,
+        item: impl FnMut(&mut Bytes) -> Result<T, DecodeError>,
+    ) -> Result<Vec<T>, DecodeError> {
+        Ok(Self::deserialize_nullable_with(src, item)?.unwrap_or_default())
+    }
+
+    /// Like `deserialize_with`, for arrays where null means something
+    /// different from empty.
+    pub fn deserialize_nullable_with(
+        src: &mut Bytes,
+        item: impl FnMut(&mut Bytes) -> Result<T, DecodeError>,
+    ) -> Result<Option<Vec<T>>, DecodeError> {
+        let len = read_uvarlong(src)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        read_items(src, len - 1, item).map(Some)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Array<T>(pub Vec<T>);
+
+impl<T: Serialize> Serialize for Array<T> {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_i32(self.0.len() as i32);
+        for item in &self.0 {
+            b.put(item.serialize());
+        }
+        b.freeze()
+    }
+}
+
+impl<T, U> Deserialize<Vec<U>> for Array<T>
+where
+    T: Deserialize<U>,
+{
+    fn deserialize(src: &mut Bytes) -> Result<Vec<U>, DecodeError> {
+        let len = src.try_get_i32()?;
+        read_items(src, len.max(0) as u64, T::deserialize)
+    }
+}
+
+/// Reads `count` items. The count comes off the wire, so the items are only
+/// allocated for as they are read, each taking at least one byte.
+fn read_items<T>(
+    src: &mut Bytes,
+    count: u64,
+    mut item: impl FnMut(&mut Bytes) -> Result<T, DecodeError>,
+) -> Result<Vec<T>, DecodeError> {
+    if count > src.remaining() as u64 {
+        return Err(DecodeError::Invalid(format!(
+            "{count} items in {} bytes",
+            src.remaining()
+        )));
+    }
+    let mut items = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        items.push(item(src)?);
+    }
+    Ok(items)
+}
+
+/// `BYTES`: an INT32 length followed by the raw bytes.
+#[derive(Debug, Clone, Default)]
+pub struct KafkaBytes(pub Bytes);
+
+impl Serialize for KafkaBytes {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::with_capacity(4 + self.0.len());
+        b.put_i32(self.0.len() as i32);
+        b.put(self.0.clone());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for KafkaBytes {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let len = src.try_get_i32()?;
+        if len <= 0 {
+            return Ok(Self(Bytes::new()));
         }
-        items
+        Ok(Self(take(src, len as usize)?))
+    }
+}
+
+/// `COMPACT_BYTES`: an unsigned varint of length + 1 followed by the raw bytes.
+#[derive(Debug, Clone, Default)]
+pub struct CompactBytes(pub Bytes);
+
+impl Serialize for CompactBytes {
+    fn serialize(&self) -> Bytes {
+        let mut b = BytesMut::zeroed(10);
+        let n = (self.0.len() + 1).encode_var(&mut b);
+        b.truncate(n);
+        b.put(self.0.clone());
+        b.freeze()
+    }
+}
+
+impl Deserialize<Self> for CompactBytes {
+    fn deserialize(src: &mut Bytes) -> Result<Self, DecodeError> {
+        let len = read_uvarlong(src)?;
+        if len <= 1 {
+            return Ok(Self(Bytes::new()));
+        }
+        Ok(Self(take(src, len as usize - 1)?))
     }
 }
 
@@ -214,14 +686,9 @@ impl<T, U> Deserialize<Vec<U>> for NullableBytes<T>
 where
     T: Deserialize<U>,
 {
-    fn deserialize(src: &mut Bytes) -> Vec<U> {
-        let len = src.get_i32();
-        let items_count = if len == -1 { 0 } else { len as usize };
-        let mut items = Vec::with_capacity(items_count);
-        for _ in 0..items_count {
-            items.push(T::deserialize(src));
-        }
-        items
+    fn deserialize(src: &mut Bytes) -> Result<Vec<U>, DecodeError> {
+        let len = src.try_get_i32()?;
+        read_items(src, len.max(0) as u64, T::deserialize)
     }
 }
 
@@ -231,10 +698,71 @@ impl TagBuffer {
     pub fn serialize() -> Bytes {
         Bytes::from_static(&[0])
     }
+
+    /// Writes `fields`, each a tag and its already encoded value, in the
+    /// order given, which must be by increasing tag.
+    pub fn serialize_fields(fields: &[(u32, Bytes)]) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_slice(&(fields.len() as u32).encode_var_vec());
+        for (tag, value) in fields {
+            b.put_slice(&tag.encode_var_vec());
+            b.put_slice(&(value.len() as u32).encode_var_vec());
+            b.put(value.clone());
+        }
+        b.freeze()
+    }
+
+    /// Reads every tagged field as its tag and raw value, for structures
+    /// whose tagged fields are read rather than skipped.
+    pub fn deserialize_fields(src: &mut Bytes) -> Result<Vec<(u32, Bytes)>, DecodeError> {
+        let count = read_uvarint(src)?;
+        read_items(src, count.into(), |src| {
+            let tag = read_uvarint(src)?;
+            let len = read_uvarint(src)?;
+            Ok((tag, take(src, len as usize)?))
+        })
+    }
 }
 
 impl Deserialize<u8> for TagBuffer {
-    fn deserialize(src: &mut Bytes) -> u8 {
-        src.get_u8()
+    fn deserialize(src: &mut Bytes) -> Result<u8, DecodeError> {
+        Ok(src.try_get_u8()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_on_a_string_longer_than_its_data() {
+        let mut src = Bytes::from_static(b"\x06abc");
+        assert!(CompactNullableString::deserialize(&mut src).is_err());
+        let mut src = Bytes::from_static(b"\0\x05abc");
+        assert!(NullableString::deserialize(&mut src).is_err());
+        let mut src = Bytes::from_static(b"\x04abc");
+        assert_eq!(
+            CompactNullableString::deserialize(&mut src).unwrap().0,
+            Some("abc".to_string())
+        );
+    }
+
+    #[test]
+    fn fails_on_an_array_longer_than_its_data() {
+        // Two billion items in four bytes: refused before anything is
+        // allocated for them.
+        let mut src = Bytes::from_static(b"\x7f\xff\xff\xff\0\0\0\x01");
+        assert!(Array::<TagBuffer>::deserialize(&mut src).is_err());
+        let mut src = Bytes::from_static(b"\x03\0");
+        assert!(CompactArray::<TagBuffer>::deserialize(&mut src).is_err());
+    }
+
+    #[test]
+    fn fails_on_a_malformed_varint() {
+        let mut src = Bytes::from_static(b"\xff\xff");
+        assert!(matches!(
+            CompactBytes::deserialize(&mut src),
+            Err(DecodeError::Varint)
+        ));
     }
 }
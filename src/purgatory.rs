@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::Notify, time::Instant};
+
+use crate::request_context::Cancellation;
+
+/// Watch registrations are swept for finished operations after this many.
+const PURGE_INTERVAL: usize = 1000;
+
+/// An operation that may not be able to finish yet, such as a Fetch waiting
+/// for `min_bytes` or a Produce waiting for replication.
+pub trait DelayedOperation: Send {
+    type Output;
+
+    /// Returns the result if the operation can finish now.
+    fn try_complete(&mut self) -> Option<Self::Output>;
+
+    /// Finishes the operation once its timeout has passed.
+    fn on_expiration(self) -> Self::Output;
+}
+
+/// Parks [`DelayedOperation`]s until an event on one of their watch keys lets
+/// them complete, or their timeout expires, in the manner of Kafka's
+/// `DelayedOperationPurgatory`.
+///
+/// Timeouts are driven by tokio's timer, which is itself a hierarchical timing
+/// wheel, so parking many operations costs no more than the sleeps themselves.
+pub struct Purgatory<K> {
+    name: &'static str,
+    watchers: Mutex<Watchers<K>>,
+    pending: AtomicUsize,
+}
+
+struct Watchers<K> {
+    by_key: HashMap<K, Vec<Arc<Notify>>>,
+    registrations: usize,
+}
+
+impl<K: Hash + Eq + Clone> Purgatory<K> {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            watchers: Mutex::new(Watchers {
+                by_key: HashMap::new(),
+                registrations: 0,
+            }),
+            pending: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Operations currently parked.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Completes `op` right away if it can; otherwise parks it on `keys` and
+    /// retries each time one of them is checked, until `timeout` passes.
+    ///
+    /// Once `cancellation` is set, as when the client that asked has gone,
+    /// the operation is dropped without finishing and `None` returned.
+    /// Dropping the returned future abandons the operation too. Either way
+    /// it stops watching its keys at once rather than at the next sweep.
+    pub async fn try_complete_else_watch<O: DelayedOperation>(
+        &self,
+        mut op: O,
+        keys: &[K],
+        timeout: Duration,
+        cancellation: &Cancellation,
+    ) -> Option<O::Output> {
+        if let Some(output) = op.try_complete() {
+            return Some(output);
+        }
+        let deadline = Instant::now() + timeout;
+        let notify = Arc::new(Notify::new());
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        let _parked = Parked {
+            purgatory: self,
+            keys,
+            notify: &notify,
+        };
+        loop {
+            self.watch(keys, &notify);
+            // Checked again after registering, so an event that landed between
+            // the last attempt and `watch` is not missed.
+            if let Some(output) = op.try_complete() {
+                return Some(output);
+            }
+            tokio::select! {
+                _ = notify.notified() => {}
+                _ = tokio::time::sleep_until(deadline) => return Some(op.on_expiration()),
+                _ = cancellation.cancelled() => return None,
+            }
+        }
+    }
+
+    /// Wakes every operation watching `key` so it can try to complete.
+    /// Returns how many were woken.
+    pub fn check_and_complete(&self, key: &K) -> usize {
+        let notifies = {
+            let mut watchers = self.watchers.lock().unwrap();
+            let notifies = watchers.by_key.remove(key).unwrap_or_default();
+            watchers.registrations -= notifies.len();
+            notifies
+        };
+        for notify in &notifies {
+            notify.notify_one();
+        }
+        notifies.len()
+    }
+
+    fn watch(&self, keys: &[K], notify: &Arc<Notify>) {
+        let mut watchers = self.watchers.lock().unwrap();
+        for key in keys {
+            let list = watchers.by_key.entry(key.clone()).or_default();
+            if !list.iter().any(|n| Arc::ptr_eq(n, notify)) {
+                list.push(notify.clone());
+                watchers.registrations += 1;
+            }
+        }
+        if watchers.registrations >= PURGE_INTERVAL {
+            watchers.purge();
+        }
+    }
+
+    fn unwatch(&self, keys: &[K], notify: &Arc<Notify>) {
+        let mut watchers = self.watchers.lock().unwrap();
+        for key in keys {
+            let Some(list) = watchers.by_key.get_mut(key) else {
+                continue;
+            };
+            let before = list.len();
+            list.retain(|n| !Arc::ptr_eq(n, notify));
+            let removed = before - list.len();
+            if list.is_empty() {
+                watchers.by_key.remove(key);
+            }
+            watchers.registrations -= removed;
+        }
+    }
+}
+
+impl<K> Watchers<K> {
+    /// Drops registrations of operations that already finished; the map holds
+    /// the only remaining reference to their `Notify`.
+    fn purge(&mut self) {
+        self.by_key.retain(|_, list| {
+            list.retain(|notify| Arc::strong_count(notify) > 1);
+            !list.is_empty()
+        });
+        self.registrations = self.by_key.values().map(Vec::len).sum();
+    }
+}
+
+/// An operation parked in a purgatory, counted as pending and watching its
+/// keys until it finishes or is abandoned.
+struct Parked<'a, K: Hash + Eq + Clone> {
+    purgatory: &'a Purgatory<K>,
+    keys: &'a [K],
+    notify: &'a Arc<Notify>,
+}
+
+impl<K: Hash + Eq + Clone> Drop for Parked<'_, K> {
+    fn drop(&mut self) {
+        self.purgatory.unwatch(self.keys, self.notify);
+        self.purgatory.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+}
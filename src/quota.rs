@@ -0,0 +1,281 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::api::cluster_metadata::RecordBatches;
+use crate::metrics::Metrics;
+
+pub const QUOTA_THROTTLE_TIME_METRIC: &str = "kafka_server_quota_throttle_time_ms_total";
+pub const QUOTA_VIOLATIONS_METRIC: &str = "kafka_server_quota_violations_total";
+pub const QUOTA_BYTES_METRIC: &str = "kafka_server_quota_bytes_total";
+
+/// The entity types client quotas are set for.
+pub const USER_QUOTA_ENTITY: &str = "user";
+pub const CLIENT_ID_QUOTA_ENTITY: &str = "client-id";
+
+/// The quotas an entity can have, in bytes per second.
+pub const PRODUCER_BYTE_RATE: &str = "producer_byte_rate";
+pub const CONSUMER_BYTE_RATE: &str = "consumer_byte_rate";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaType {
+    /// Bytes produced, measured on the request.
+    Produce,
+    /// Bytes fetched, measured on the response.
+    Fetch,
+}
+
+impl QuotaType {
+    /// The name of the quota, as AlterClientQuotas sets it.
+    pub fn key(&self) -> &'static str {
+        match self {
+            Self::Produce => PRODUCER_BYTE_RATE,
+            Self::Fetch => CONSUMER_BYTE_RATE,
+        }
+    }
+}
+
+impl Display for QuotaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Produce => write!(f, "Produce"),
+            Self::Fetch => write!(f, "Fetch"),
+        }
+    }
+}
+
+/// Byte-rate limits for one [`QuotaType`], by client id.
+#[derive(Debug, Clone, Default)]
+pub struct QuotaSettings {
+    /// Bytes per second for clients without an override; `None` is unlimited.
+    pub default: Option<f64>,
+    pub overrides: HashMap<String, f64>,
+}
+
+/// Whose traffic a quota set through AlterClientQuotas limits. A `None`
+/// name is the default for every user, or every client id, without a quota
+/// of its own.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum QuotaEntity {
+    User(Option<String>),
+    ClientId(Option<String>),
+}
+
+impl QuotaEntity {
+    /// The entity named by `(entity type, name)` components, if it is one
+    /// this broker enforces quotas for. Quotas on a user and client id
+    /// together are not supported.
+    pub fn from_components(components: &[(String, Option<String>)]) -> Option<Self> {
+        let [(entity_type, name)] = components else {
+            return None;
+        };
+        if name.as_deref() == Some("") {
+            return None;
+        }
+        match entity_type.as_str() {
+            USER_QUOTA_ENTITY => Some(Self::User(name.clone())),
+            CLIENT_ID_QUOTA_ENTITY => Some(Self::ClientId(name.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// How byte rates are measured: over `samples` windows of `window` each.
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaWindow {
+    pub samples: usize,
+    pub window: Duration,
+}
+
+impl Default for QuotaWindow {
+    fn default() -> Self {
+        Self {
+            samples: 11,
+            window: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Enforces per-user and per-client-id byte-rate quotas, in the manner of
+/// Kafka's `ClientQuotaManager`.
+///
+/// Each client's rate is measured over a sliding set of sample windows. A
+/// client over its quota is throttled for as long as it would take its rate to
+/// fall back to the quota, and the caller delays the response by that much.
+///
+/// Quotas set through AlterClientQuotas take precedence over the ones in the
+/// config file: a user's own, then the user default, then a client id's own,
+/// then the client id default.
+pub struct ClientQuotaManager {
+    quota_type: QuotaType,
+    window: QuotaWindow,
+    settings: Mutex<QuotaSettings>,
+    dynamic: Mutex<HashMap<QuotaEntity, f64>>,
+    rates: Mutex<HashMap<QuotaEntity, Rate>>,
+    metrics: Arc<Metrics>,
+}
+
+impl ClientQuotaManager {
+    pub fn new(
+        quota_type: QuotaType,
+        settings: QuotaSettings,
+        window: QuotaWindow,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            quota_type,
+            window,
+            settings: Mutex::new(settings),
+            dynamic: Mutex::new(HashMap::new()),
+            rates: Mutex::new(HashMap::new()),
+            metrics,
+        }
+    }
+
+    pub fn quota_type(&self) -> QuotaType {
+        self.quota_type
+    }
+
+    /// The quota for a client of `user` with `client_id`, and the entity
+    /// whose rate it limits.
+    pub fn quota(&self, user: &str, client_id: &str) -> Option<(QuotaEntity, f64)> {
+        let dynamic = self.dynamic.lock().unwrap();
+        let settings = self.settings.lock().unwrap();
+        let user_quota = dynamic
+            .get(&QuotaEntity::User(Some(user.to_string())))
+            .or_else(|| dynamic.get(&QuotaEntity::User(None)));
+        if let Some(&quota) = user_quota {
+            return Some((QuotaEntity::User(Some(user.to_string())), quota));
+        }
+        dynamic
+            .get(&QuotaEntity::ClientId(Some(client_id.to_string())))
+            .or_else(|| settings.overrides.get(client_id))
+            .or_else(|| dynamic.get(&QuotaEntity::ClientId(None)))
+            .or(settings.default.as_ref())
+            .map(|&quota| (QuotaEntity::ClientId(Some(client_id.to_string())), quota))
+    }
+
+    /// Sets the quota for `client_id`, or the default when `None`. A `None`
+    /// rate removes the limit.
+    pub fn set_quota(&self, client_id: Option<&str>, bytes_per_second: Option<f64>) {
+        let mut settings = self.settings.lock().unwrap();
+        match (client_id, bytes_per_second) {
+            (Some(client_id), Some(rate)) => {
+                settings.overrides.insert(client_id.to_string(), rate);
+            }
+            (Some(client_id), None) => {
+                settings.overrides.remove(client_id);
+            }
+            (None, rate) => settings.default = rate,
+        }
+    }
+
+    /// Replaces the default and every override, as on a config reload.
+    /// Measured rates are kept.
+    pub fn reconfigure(&self, settings: QuotaSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    /// Replaces the quotas set through AlterClientQuotas with the ones the
+    /// metadata log now records.
+    pub fn update(&self, metadata: &RecordBatches) {
+        let quotas = metadata
+            .client_quotas()
+            .into_iter()
+            .filter_map(|(components, quotas)| {
+                let entity = QuotaEntity::from_components(&components)?;
+                Some((entity, *quotas.get(self.quota_type.key())?))
+            })
+            .collect();
+        *self.dynamic.lock().unwrap() = quotas;
+    }
+
+    /// Records `bytes` for a client of `user` with `client_id` and returns
+    /// how long its response should be delayed.
+    pub fn record(&self, user: &str, client_id: &str, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let Some((entity, quota)) = self.quota(user, client_id) else {
+            return Duration::ZERO;
+        };
+        let quota_type = self.quota_type.to_string();
+        let labels = [("type", quota_type.as_str()), ("client_id", client_id)];
+        self.metrics
+            .incr_counter(QUOTA_BYTES_METRIC, &labels, bytes as u64);
+
+        let mut rates = self.rates.lock().unwrap();
+        let rate = rates
+            .entry(entity)
+            .or_insert_with(|| Rate::new(self.window, now));
+        rate.record(bytes as f64, now);
+        let (observed, span) = rate.measure(now);
+        drop(rates);
+
+        if observed <= quota {
+            return Duration::ZERO;
+        }
+        let max_throttle = self.window.window * self.window.samples as u32;
+        let throttle = span.mul_f64((observed - quota) / quota).min(max_throttle);
+        self.metrics
+            .incr_counter(QUOTA_VIOLATIONS_METRIC, &labels, 1);
+        self.metrics.incr_counter(
+            QUOTA_THROTTLE_TIME_METRIC,
+            &labels,
+            throttle.as_millis() as u64,
+        );
+        throttle
+    }
+}
+
+/// A windowed rate: the bytes in the last `samples` windows over the time
+/// they cover.
+pub(crate) struct Rate {
+    window: QuotaWindow,
+    /// Start of each sample window and the bytes recorded in it, oldest first.
+    samples: Vec<(Instant, f64)>,
+}
+
+impl Rate {
+    pub(crate) fn new(window: QuotaWindow, now: Instant) -> Self {
+        Self {
+            window,
+            samples: vec![(now, 0.0)],
+        }
+    }
+
+    pub(crate) fn record(&mut self, value: f64, now: Instant) {
+        self.expire(now);
+        let (start, _) = *self.samples.last().unwrap();
+        if now.duration_since(start) >= self.window.window {
+            self.samples.push((now, value));
+            if self.samples.len() > self.window.samples {
+                self.samples.remove(0);
+            }
+        } else {
+            self.samples.last_mut().unwrap().1 += value;
+        }
+    }
+
+    /// The current rate in units per second and the time span it covers.
+    pub(crate) fn measure(&mut self, now: Instant) -> (f64, Duration) {
+        self.expire(now);
+        let total: f64 = self.samples.iter().map(|(_, v)| v).sum();
+        let elapsed = now.duration_since(self.samples[0].0);
+        // Like Kafka, never divide by less than all but one full window so a
+        // burst right after startup is not mistaken for a huge rate.
+        let min_span = self.window.window * (self.window.samples as u32 - 1).max(1);
+        let span = elapsed.max(min_span);
+        (total / span.as_secs_f64(), span)
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let max_age = self.window.window * self.window.samples as u32;
+        self.samples
+            .retain(|(start, _)| now.duration_since(*start) < max_age);
+        if self.samples.is_empty() {
+            self.samples.push((now, 0.0));
+        }
+    }
+}
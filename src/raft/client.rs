@@ -0,0 +1,134 @@
+//! Requests from this voter to the others. Each peer gets a task with one
+//! connection, opened when there is something to send and dropped when an
+//! exchange fails; the quorum hears back through an event channel.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use super::Voter;
+use crate::api::{
+    begin_quorum_epoch::{BeginQuorumEpochRequest, QuorumEpochResponse},
+    end_quorum_epoch::EndQuorumEpochRequest,
+    fetch::{FetchRequestV16, FetchResponseV16},
+    fetch_snapshot::{FetchSnapshotRequest, FetchSnapshotResponse},
+    vote::{VoteRequest, VoteResponse},
+};
+use crate::client::Connection;
+use crate::protocol::ApiKey;
+
+pub(super) enum Request {
+    Vote(VoteRequest),
+    BeginQuorumEpoch(BeginQuorumEpochRequest),
+    EndQuorumEpoch(EndQuorumEpochRequest),
+    Fetch(FetchRequestV16),
+    FetchSnapshot(FetchSnapshotRequest),
+}
+
+pub(super) enum Reply {
+    Vote(VoteResponse),
+    BeginQuorumEpoch(QuorumEpochResponse),
+    EndQuorumEpoch,
+    Fetch(FetchResponseV16),
+    FetchSnapshot(FetchSnapshotResponse),
+}
+
+/// What became of a request: the voter it went to, the epoch it was sent
+/// in, and the response or why there is none.
+pub(super) struct Event {
+    pub(super) voter_id: i32,
+    pub(super) epoch: i32,
+    pub(super) reply: Result<Reply>,
+}
+
+/// The sending side of one peer's task.
+pub(super) struct PeerClient {
+    requests: mpsc::UnboundedSender<(i32, Request)>,
+}
+
+impl PeerClient {
+    pub(super) fn spawn(
+        node_id: i32,
+        voter: Voter,
+        request_timeout: Duration,
+        events: mpsc::UnboundedSender<Event>,
+    ) -> Self {
+        let (requests, rx) = mpsc::unbounded_channel();
+        let peer = Peer {
+            client_id: format!("raft-client-{}", node_id),
+            voter,
+            request_timeout,
+            connection: None,
+        };
+        tokio::spawn(peer.run(rx, events));
+        Self { requests }
+    }
+
+    /// Queues `request`, sent in `epoch`.
+    pub(super) fn send(&self, epoch: i32, request: Request) {
+        let _ = self.requests.send((epoch, request));
+    }
+}
+
+struct Peer {
+    client_id: String,
+    voter: Voter,
+    request_timeout: Duration,
+    connection: Option<Connection>,
+}
+
+impl Peer {
+    async fn run(
+        mut self,
+        mut requests: mpsc::UnboundedReceiver<(i32, Request)>,
+        events: mpsc::UnboundedSender<Event>,
+    ) {
+        while let Some((epoch, request)) = requests.recv().await {
+            let reply =
+                match tokio::time::timeout(self.request_timeout, self.exchange(&request)).await {
+                    Ok(reply) => reply,
+                    Err(_) => Err(anyhow!("timed out")),
+                };
+            if let Err(e) = &reply {
+                debug!(voter = self.voter.id, error = %e, "request to voter failed");
+                self.connection = None;
+            }
+            let event = Event {
+                voter_id: self.voter.id,
+                epoch,
+                reply,
+            };
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn exchange(&mut self, request: &Request) -> Result<Reply> {
+        if self.connection.is_none() {
+            let connection =
+                Connection::connect(&self.voter.host, self.voter.port, &self.client_id).await?;
+            self.connection = Some(connection);
+        }
+        let connection = self.connection.as_mut().unwrap();
+        Ok(match request {
+            Request::Vote(req) => Reply::Vote(connection.send(ApiKey::Vote, 0, req).await?),
+            Request::BeginQuorumEpoch(req) => {
+                Reply::BeginQuorumEpoch(connection.send(ApiKey::BeginQuorumEpoch, 0, req).await?)
+            }
+            Request::EndQuorumEpoch(req) => {
+                // Nothing in the answer changes what a resigning leader does.
+                connection
+                    .send::<QuorumEpochResponse>(ApiKey::EndQuorumEpoch, 0, req)
+                    .await?;
+                Reply::EndQuorumEpoch
+            }
+            Request::Fetch(req) => Reply::Fetch(connection.send(ApiKey::Fetch, 16, req).await?),
+            Request::FetchSnapshot(req) => {
+                Reply::FetchSnapshot(connection.send(ApiKey::FetchSnapshot, 0, req).await?)
+            }
+        })
+    }
+}
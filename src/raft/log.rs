@@ -0,0 +1,377 @@
+//! The `__cluster_metadata` partition as the quorum replicates it: one
+//! segment of batches, each stamped with the epoch of the leader that
+//! appended it. Those epochs are how a follower finds where its log stops
+//! agreeing with the leader's. Once enough of it is committed, that much
+//! moves into a snapshot and the segment starts after it.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{ensure, Context, Result};
+use bytes::Bytes;
+use tracing::{info, warn};
+
+use super::snapshot::{self, SnapshotId};
+use super::METADATA_TOPIC;
+use crate::record_batch::{encode_batch, BatchHeader, BatchRecord};
+
+const SEGMENT_FILE: &str = "00000000000000000000.log";
+
+/// Where a batch is in the segment.
+#[derive(Debug, Clone, Copy)]
+struct BatchEntry {
+    base_offset: i64,
+    last_offset: i64,
+    epoch: i32,
+    position: u64,
+    size: usize,
+}
+
+pub(super) struct MetadataLog {
+    dir: PathBuf,
+    path: PathBuf,
+    file: File,
+    batches: Vec<BatchEntry>,
+    /// The latest snapshot; the segment carries on from it, or from the
+    /// one before if a crash came between writing it and cutting the
+    /// segment.
+    snapshot: Option<SnapshotId>,
+}
+
+impl MetadataLog {
+    pub(super) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Opens the partition under `log_dir`, creating it if missing. A torn
+    /// batch at the end, left by a crash mid-append, is cut off.
+    pub(super) fn open(log_dir: &Path) -> Result<Self> {
+        let dir = log_dir.join(format!("{}-0", METADATA_TOPIC));
+        std::fs::create_dir_all(&dir).with_context(|| format!("create '{}'", dir.display()))?;
+        let path = dir.join(SEGMENT_FILE);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("open '{}'", path.display()))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)
+            .with_context(|| format!("read '{}'", path.display()))?;
+
+        let mut batches = Vec::new();
+        let mut position = 0;
+        while let Some(header) = BatchHeader::parse(&data[position..]) {
+            batches.push(BatchEntry {
+                base_offset: header.base_offset,
+                last_offset: header.last_offset(),
+                epoch: header.partition_leader_epoch,
+                position: position as u64,
+                size: header.size(),
+            });
+            position += header.size();
+        }
+        if position < data.len() {
+            warn!(
+                bytes = data.len() - position,
+                "cutting off torn batch at the end of the metadata log"
+            );
+            file.set_len(position as u64)?;
+        }
+        let snapshots = snapshot::list(&dir)?;
+        if let Some(first) = batches.first().filter(|b| b.base_offset > 0) {
+            ensure!(
+                snapshots.iter().any(|s| s.end_offset == first.base_offset),
+                "metadata log starts at offset {} but no snapshot ends there",
+                first.base_offset
+            );
+        }
+        let log = Self {
+            dir,
+            path,
+            file,
+            batches,
+            snapshot: snapshots.last().copied(),
+        };
+        info!(
+            log_start_offset = log.log_start_offset(),
+            end_offset = log.end_offset(),
+            last_epoch = log.last_epoch(),
+            "opened metadata log"
+        );
+        Ok(log)
+    }
+
+    /// The first offset still in the segment; anything before it is only
+    /// in the snapshot.
+    pub(super) fn log_start_offset(&self) -> i64 {
+        match self.batches.first() {
+            Some(first) => first.base_offset,
+            None => self.snapshot.map_or(0, |s| s.end_offset),
+        }
+    }
+
+    /// The offset the next record appended gets.
+    pub(super) fn end_offset(&self) -> i64 {
+        match self.batches.last() {
+            Some(last) => last.last_offset + 1,
+            None => self.snapshot.map_or(0, |s| s.end_offset),
+        }
+    }
+
+    /// The epoch of the last batch, 0 while the log is empty.
+    pub(super) fn last_epoch(&self) -> i32 {
+        match self.batches.last() {
+            Some(last) => last.epoch,
+            None => self.snapshot.map_or(0, |s| s.epoch),
+        }
+    }
+
+    pub(super) fn latest_snapshot(&self) -> Option<SnapshotId> {
+        self.snapshot
+    }
+
+    /// The largest epoch in the log no greater than `epoch`, and where the
+    /// log's record of it ends: the start of the next epoch, or the log end.
+    /// `(-1, -1)` if every epoch in the log is greater. Of the epochs in the
+    /// snapshot only the last is known, ending at the log start.
+    pub(super) fn end_offset_for_epoch(&self, epoch: i32) -> (i32, i64) {
+        match self.batches.iter().position(|b| b.epoch > epoch) {
+            Some(0) => {}
+            Some(next) => return (self.batches[next - 1].epoch, self.batches[next].base_offset),
+            None if self.batches.is_empty() => {}
+            None => return (self.last_epoch(), self.end_offset()),
+        }
+        match self.snapshot {
+            Some(s) if s.epoch <= epoch && s.end_offset == self.log_start_offset() => {
+                (s.epoch, s.end_offset)
+            }
+            _ => (-1, -1),
+        }
+    }
+
+    /// How many bytes of the segment hold batches below `high_watermark`,
+    /// i.e. how much a snapshot taken now would move out of it.
+    pub(super) fn committed_bytes(&self, high_watermark: i64) -> usize {
+        self.batches
+            .iter()
+            .take_while(|b| b.last_offset < high_watermark)
+            .map(|b| b.size)
+            .sum()
+    }
+
+    /// Moves every batch below `high_watermark` into a new snapshot, which
+    /// is the one the segment carried on from plus those batches, and cuts
+    /// them from the segment. The snapshot before stays for readers that
+    /// read the segment before it was cut; older ones are deleted.
+    pub(super) fn take_snapshot(&mut self, high_watermark: i64) -> Result<()> {
+        let count = self
+            .batches
+            .iter()
+            .take_while(|b| b.last_offset < high_watermark)
+            .count();
+        let Some(&last) = count.checked_sub(1).and_then(|i| self.batches.get(i)) else {
+            return Ok(());
+        };
+        let id = SnapshotId {
+            end_offset: last.last_offset + 1,
+            epoch: last.epoch,
+        };
+        let previous =
+            snapshot::read_preceding_snapshot(&self.path, Some(self.log_start_offset()))?
+                .unwrap_or_default();
+        let mut segment = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file
+            .read_to_end(&mut segment)
+            .with_context(|| format!("read '{}'", self.path.display()))?;
+        let split = (last.position as usize + last.size).min(segment.len());
+        snapshot::write(&self.dir, id, &[&previous, &segment[..split]])?;
+        self.snapshot = Some(id);
+
+        let tmp = self.path.with_extension("log.part");
+        File::create(&tmp)
+            .and_then(|mut f| f.write_all(&segment[split..]).and_then(|()| f.sync_all()))
+            .with_context(|| format!("write '{}'", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("rename to '{}'", self.path.display()))?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .with_context(|| format!("open '{}'", self.path.display()))?;
+        self.batches.drain(..count);
+        for batch in &mut self.batches {
+            batch.position -= split as u64;
+        }
+        self.delete_old_snapshots()?;
+        info!(
+            end_offset = id.end_offset,
+            epoch = id.epoch,
+            bytes = previous.len() + split,
+            "took metadata snapshot"
+        );
+        Ok(())
+    }
+
+    /// Replaces the whole log with the snapshot `data`, fetched from the
+    /// leader: the segment is emptied and the next append follows it.
+    pub(super) fn install_snapshot(&mut self, id: SnapshotId, data: &[u8]) -> Result<()> {
+        snapshot::write(&self.dir, id, &[data])?;
+        self.snapshot = Some(id);
+        self.file
+            .set_len(0)
+            .and_then(|()| self.file.sync_data())
+            .with_context(|| format!("truncate '{}'", self.path.display()))?;
+        self.batches.clear();
+        self.delete_old_snapshots()?;
+        info!(
+            end_offset = id.end_offset,
+            epoch = id.epoch,
+            bytes = data.len(),
+            "installed metadata snapshot"
+        );
+        Ok(())
+    }
+
+    /// Up to `max_bytes` of snapshot `id` from `position`, and its size.
+    pub(super) fn read_snapshot(
+        &self,
+        id: SnapshotId,
+        position: u64,
+        max_bytes: usize,
+    ) -> Result<Option<(u64, Bytes)>> {
+        let chunk = snapshot::read_chunk(&self.dir, id, position, max_bytes)?;
+        Ok(chunk.map(|(size, data)| (size, Bytes::from(data))))
+    }
+
+    fn delete_old_snapshots(&self) -> Result<()> {
+        let ids = snapshot::list(&self.dir)?;
+        for id in &ids[..ids.len().saturating_sub(2)] {
+            let path = id.path(&self.dir);
+            std::fs::remove_file(&path).with_context(|| format!("delete '{}'", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Whole batches from the one holding `offset`, stopping before the
+    /// batch that would take them past `max_bytes`. The first batch is
+    /// always included, however big.
+    pub(super) fn read(&mut self, offset: i64, max_bytes: usize) -> Result<Bytes> {
+        let Some(first) = self.batches.iter().position(|b| b.last_offset >= offset) else {
+            return Ok(Bytes::new());
+        };
+        let start = self.batches[first].position;
+        let mut len = 0;
+        for batch in &self.batches[first..] {
+            if len > 0 && len + batch.size > max_bytes {
+                break;
+            }
+            len += batch.size;
+        }
+        let mut data = vec![0; len];
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file
+            .read_exact(&mut data)
+            .with_context(|| format!("read '{}'", self.path.display()))?;
+        Ok(Bytes::from(data))
+    }
+
+    /// The whole log: the snapshot the segment carries on from, then the
+    /// segment.
+    pub(super) fn read_all(&mut self) -> Result<Bytes> {
+        let segment_start = self.batches.first().map(|b| b.base_offset);
+        let mut data =
+            snapshot::read_preceding_snapshot(&self.path, segment_start)?.unwrap_or_default();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file
+            .read_to_end(&mut data)
+            .with_context(|| format!("read '{}'", self.path.display()))?;
+        Ok(Bytes::from(data))
+    }
+
+    /// Appends `records` as one batch of the leader's `epoch` and returns
+    /// its base offset.
+    pub(super) fn append_as_leader(
+        &mut self,
+        epoch: i32,
+        attributes: i16,
+        records: &[BatchRecord],
+    ) -> Result<i64> {
+        let base_offset = self.end_offset();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        let batch = encode_batch(base_offset, epoch, attributes, records, timestamp);
+        self.append(&batch)?;
+        Ok(base_offset)
+    }
+
+    /// Appends batches fetched from the leader, which must carry on from
+    /// the end of the log.
+    pub(super) fn append_as_follower(&mut self, data: &[u8]) -> Result<()> {
+        let mut position = 0;
+        let mut end_offset = self.end_offset();
+        while let Some(header) = BatchHeader::parse(&data[position..]) {
+            ensure!(
+                header.base_offset == end_offset,
+                "fetched batch at offset {} doesn't follow the log end {}",
+                header.base_offset,
+                end_offset
+            );
+            end_offset = header.last_offset() + 1;
+            position += header.size();
+        }
+        // A batch cut short by the fetch size limit is fetched again next time.
+        self.append(&data[..position])
+    }
+
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let mut position = self.file.seek(SeekFrom::End(0))?;
+        self.file
+            .write_all(data)
+            .and_then(|()| self.file.sync_data())
+            .with_context(|| format!("append to '{}'", self.path.display()))?;
+        let mut rest = data;
+        while let Some(header) = BatchHeader::parse(rest) {
+            self.batches.push(BatchEntry {
+                base_offset: header.base_offset,
+                last_offset: header.last_offset(),
+                epoch: header.partition_leader_epoch,
+                position,
+                size: header.size(),
+            });
+            position += header.size() as u64;
+            rest = &rest[header.size()..];
+        }
+        Ok(())
+    }
+
+    /// Drops every batch holding records at or after `offset`.
+    pub(super) fn truncate_to(&mut self, offset: i64) -> Result<()> {
+        let keep = self
+            .batches
+            .iter()
+            .position(|b| b.last_offset >= offset)
+            .unwrap_or(self.batches.len());
+        let Some(first_dropped) = self.batches.get(keep) else {
+            return Ok(());
+        };
+        let len = first_dropped.position;
+        self.file
+            .set_len(len)
+            .and_then(|()| self.file.sync_data())
+            .with_context(|| format!("truncate '{}'", self.path.display()))?;
+        self.batches.truncate(keep);
+        info!(end_offset = self.end_offset(), "truncated metadata log");
+        Ok(())
+    }
+}
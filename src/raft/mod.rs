@@ -0,0 +1,1806 @@
+//! The KRaft metadata quorum: the voters listed in
+//! `controller.quorum.voters` elect a leader among themselves and replicate
+//! the `__cluster_metadata` partition from it, as KIP-595 describes.
+//!
+//! A voter is unattached (with or without a vote cast in its epoch), a
+//! candidate, the leader, or a follower of a known leader. Candidates ask for
+//! votes with Vote; the winner writes a LeaderChange control record and
+//! announces itself with BeginQuorumEpoch; followers pull from it with Fetch,
+//! which is also how it learns they are alive. A leader resigns with
+//! EndQuorumEpoch when it shuts down.
+//!
+//! Every voter moves the committed start of its log into a snapshot once it
+//! has grown big enough. A follower whose fetch offset is before the
+//! leader's log start is pointed at the leader's latest snapshot, reads it
+//! with FetchSnapshot a chunk at a time, and fetches from its end after.
+//!
+//! The voter set starts out as `controller.quorum.voters` and changes one
+//! voter at a time with AddRaftVoter and RemoveRaftVoter, as KIP-853
+//! describes: the leader writes a VotersRecord with the new set, which
+//! every voter takes up as soon as it is in its log.
+
+mod client;
+mod log;
+mod quorum_state;
+mod snapshot;
+mod voters;
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::Display,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use rand::Rng;
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{error, info, warn};
+
+use crate::api::{
+    begin_quorum_epoch::BeginQuorumEpochRequest,
+    end_quorum_epoch::EndQuorumEpochRequest,
+    fetch::{FetchRequestV16, FetchResponseV16},
+    fetch_snapshot::{FetchSnapshotRequest, FetchSnapshotResponse},
+    vote::VoteRequest,
+};
+use crate::protocol::{ErrorCode, TagBuffer};
+use crate::record_batch::{BatchRecord, CONTROL_FLAG};
+use client::{Event, PeerClient, Reply, Request};
+use log::MetadataLog;
+use quorum_state::{ElectionState, QuorumStateFile};
+pub use snapshot::{read_preceding_snapshot, SnapshotId};
+
+pub const METADATA_TOPIC: &str = "__cluster_metadata";
+/// The fixed id of the metadata topic, which has no TopicRecord of its own.
+pub const METADATA_TOPIC_ID: &str = "00000000-0000-0000-0000-000000000001";
+
+/// Commands queued for the quorum before callers block on sending.
+const COMMAND_QUEUE_SIZE: usize = 1024;
+/// How often timeouts are checked and requests that are due are sent.
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+/// How long the leader may hold a follower's fetch while it has nothing new.
+const FETCH_MAX_WAIT: Duration = Duration::from_millis(500);
+const FETCH_MAX_BYTES: usize = 8 * 1024 * 1024;
+/// How much of a snapshot a follower asks for at a time.
+const FETCH_SNAPSHOT_MAX_BYTES: usize = 1024 * 1024;
+/// How long to wait before asking a voter again after a failed request.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+/// The control record type of a LeaderChange record.
+const LEADER_CHANGE_TYPE: i16 = 2;
+
+/// One entry of `controller.quorum.voters`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Voter {
+    pub id: i32,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Voter {
+    /// Parses `id@host:port` entries separated by commas.
+    pub fn parse_list(value: &str) -> Result<Vec<Self>> {
+        let mut voters: Vec<Self> = Vec::new();
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let invalid = || anyhow!("invalid voter '{}', expected id@host:port", entry);
+            let (id, address) = entry.split_once('@').ok_or_else(invalid)?;
+            let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            if host.is_empty() {
+                return Err(invalid());
+            }
+            let voter = Self {
+                id: id.parse().map_err(|_| invalid())?,
+                host: host.to_string(),
+                port: port.parse().map_err(|_| invalid())?,
+            };
+            if voters.iter().any(|v| v.id == voter.id) {
+                return Err(anyhow!("voter {} is listed more than once", voter.id));
+            }
+            voters.push(voter);
+        }
+        Ok(voters)
+    }
+}
+
+impl Display for Voter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}:{}", self.id, self.host, self.port)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QuorumSettings {
+    /// From `controller.quorum.voters`.
+    pub voters: Vec<Voter>,
+    /// How long a voter goes without a leader before standing for election,
+    /// from `controller.quorum.election.timeout.ms`. Each wait is picked at
+    /// random between this and twice this, so voters rarely stand at once.
+    pub election_timeout: Duration,
+    /// The most a candidate that lost waits before standing again, from
+    /// `controller.quorum.election.backoff.max.ms`.
+    pub election_backoff_max: Duration,
+    /// How long a follower goes without a successful fetch before standing
+    /// for election, from `controller.quorum.fetch.timeout.ms`. A leader that
+    /// hears from too few followers for one and a half times this resigns.
+    pub fetch_timeout: Duration,
+    /// How long a request to another voter may take, from
+    /// `controller.quorum.request.timeout.ms`.
+    pub request_timeout: Duration,
+    /// How many committed bytes the segment gathers before they move into
+    /// a snapshot, from `metadata.log.max.record.bytes.between.snapshots`.
+    pub snapshot_interval_bytes: usize,
+}
+
+impl Default for QuorumSettings {
+    fn default() -> Self {
+        Self {
+            voters: Vec::new(),
+            election_timeout: Duration::from_millis(1_000),
+            election_backoff_max: Duration::from_millis(1_000),
+            fetch_timeout: Duration::from_millis(2_000),
+            request_timeout: Duration::from_millis(2_000),
+            snapshot_interval_bytes: 20 * 1024 * 1024,
+        }
+    }
+}
+
+pub struct Vote {
+    pub candidate_epoch: i32,
+    pub candidate_id: i32,
+    /// The epoch and end of the candidate's log, which must be at least as
+    /// far along as the voter's.
+    pub last_offset_epoch: i32,
+    pub last_offset: i64,
+}
+
+pub struct VoteResult {
+    pub error_code: ErrorCode,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub vote_granted: bool,
+}
+
+/// The answer to BeginQuorumEpoch and EndQuorumEpoch: the voter's view of
+/// the leader.
+pub struct QuorumEpochResult {
+    pub error_code: ErrorCode,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+}
+
+pub struct MetadataFetch {
+    /// The fetching voter, or -1 for an observer.
+    pub replica_id: i32,
+    pub current_leader_epoch: i32,
+    pub fetch_offset: i64,
+    /// The epoch of the batch just before `fetch_offset` in the fetcher's
+    /// log, which tells the leader whether the logs still agree.
+    pub last_fetched_epoch: i32,
+    pub max_wait: Duration,
+    pub max_bytes: usize,
+}
+
+pub struct MetadataFetchResult {
+    pub error_code: ErrorCode,
+    pub high_watermark: i64,
+    pub records: Bytes,
+    /// Set when the fetcher's log diverges from the leader's: the last
+    /// epoch they share and where the leader's log of it ends.
+    pub diverging_epoch: Option<(i32, i64)>,
+    /// The leader id and epoch as this voter knows them.
+    pub current_leader: (i32, i32),
+    pub log_start_offset: i64,
+    /// Set when the fetch offset is before the log start: the snapshot to
+    /// fetch instead.
+    pub snapshot_id: Option<SnapshotId>,
+}
+
+pub struct SnapshotFetch {
+    pub replica_id: i32,
+    pub current_leader_epoch: i32,
+    pub snapshot_id: SnapshotId,
+    pub position: u64,
+    pub max_bytes: usize,
+}
+
+pub struct SnapshotFetchResult {
+    pub error_code: ErrorCode,
+    /// The size of the whole snapshot, -1 on error.
+    pub size: i64,
+    pub records: Bytes,
+    /// The leader id and epoch as this voter knows them.
+    pub current_leader: (i32, i32),
+}
+
+/// The quorum as its leader sees it, for DescribeQuorum.
+pub struct QuorumDescription {
+    pub error_code: ErrorCode,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub high_watermark: i64,
+    pub voters: Vec<ReplicaState>,
+    /// Replicas that fetch without being voters, such as removed voters.
+    pub observers: Vec<ReplicaState>,
+}
+
+/// How far a replica's log has got, as of its last fetch. Unknown values
+/// are -1.
+pub struct ReplicaState {
+    pub replica_id: i32,
+    pub log_end_offset: i64,
+    /// Milliseconds since the epoch.
+    pub last_fetch_timestamp: i64,
+    /// When the replica last fetched from the leader's log end.
+    pub last_caught_up_timestamp: i64,
+}
+
+enum Command {
+    Vote(Vote, oneshot::Sender<VoteResult>),
+    BeginQuorumEpoch {
+        leader_id: i32,
+        leader_epoch: i32,
+        reply: oneshot::Sender<QuorumEpochResult>,
+    },
+    EndQuorumEpoch {
+        leader_id: i32,
+        leader_epoch: i32,
+        preferred_successors: Vec<i32>,
+        reply: oneshot::Sender<QuorumEpochResult>,
+    },
+    Fetch(MetadataFetch, oneshot::Sender<MetadataFetchResult>),
+    FetchSnapshot(SnapshotFetch, oneshot::Sender<SnapshotFetchResult>),
+    Append(Vec<BatchRecord>, oneshot::Sender<AppendResult>),
+    AddVoter(Voter, oneshot::Sender<AppendResult>),
+    RemoveVoter(i32, oneshot::Sender<AppendResult>),
+    Describe(oneshot::Sender<QuorumDescription>),
+    Resign(oneshot::Sender<()>),
+}
+
+enum VoterChange {
+    Add(Voter),
+    Remove(i32),
+}
+
+/// The offset of the last record appended, once committed, or why it
+/// could not be.
+pub type AppendResult = Result<i64, ErrorCode>;
+
+/// This node's place in the metadata quorum.
+///
+/// The quorum runs in one task that owns the metadata log and the election
+/// state and takes requests from the other voters over a channel, like the
+/// group coordinator. This handle is cheap to clone; its calls other than
+/// `resign` block until the quorum answers, which for a fetch can be the
+/// fetch's whole wait, so they belong on the blocking pool.
+#[derive(Clone)]
+pub struct MetadataQuorum {
+    commands: mpsc::Sender<Command>,
+    cluster_id: Arc<str>,
+    log_file: Arc<Path>,
+    stop: Arc<watch::Sender<bool>>,
+    task: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl MetadataQuorum {
+    /// Opens the metadata log and election state kept under `log_dir` and
+    /// starts the quorum task on the current runtime.
+    pub fn start(
+        settings: QuorumSettings,
+        node_id: i32,
+        cluster_id: String,
+        log_dir: &Path,
+    ) -> Result<Self> {
+        let mut log = MetadataLog::open(log_dir)?;
+        let log_file = log.path().into();
+        let state_file = QuorumStateFile::new(log_dir);
+        let election = state_file.read().context("read the quorum state")?;
+        let voters = voters::last_voters(log.read_all()?)
+            .context("read the voter set from the metadata log")?
+            .unwrap_or_else(|| (-1, settings.voters.clone()));
+        let (commands, rx) = mpsc::channel(COMMAND_QUEUE_SIZE);
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let quorum = Quorum::new(
+            settings,
+            node_id,
+            cluster_id.clone(),
+            log,
+            state_file,
+            election,
+            voters,
+            events_tx,
+        );
+        let (stop, stopped) = watch::channel(false);
+        let task = tokio::spawn(quorum.run(rx, events, stopped));
+        Ok(Self {
+            commands,
+            cluster_id: cluster_id.into(),
+            log_file,
+            stop: Arc::new(stop),
+            task: Arc::new(Mutex::new(Some(task))),
+        })
+    }
+
+    /// The segment the quorum appends to.
+    pub fn log_file(&self) -> &Path {
+        &self.log_file
+    }
+
+    /// Whether a request naming `cluster_id` is meant for this cluster.
+    /// Requests that name none are taken to be.
+    pub fn is_cluster(&self, cluster_id: Option<&str>) -> bool {
+        cluster_id.is_none_or(|id| id == &*self.cluster_id)
+    }
+
+    pub fn vote(&self, vote: Vote) -> VoteResult {
+        self.call(|reply| Command::Vote(vote, reply))
+            .unwrap_or(VoteResult {
+                error_code: ErrorCode::UnknownServerError,
+                leader_id: -1,
+                leader_epoch: -1,
+                vote_granted: false,
+            })
+    }
+
+    pub fn begin_quorum_epoch(&self, leader_id: i32, leader_epoch: i32) -> QuorumEpochResult {
+        self.call(|reply| Command::BeginQuorumEpoch {
+            leader_id,
+            leader_epoch,
+            reply,
+        })
+        .unwrap_or_else(QuorumEpochResult::unavailable)
+    }
+
+    pub fn end_quorum_epoch(
+        &self,
+        leader_id: i32,
+        leader_epoch: i32,
+        preferred_successors: Vec<i32>,
+    ) -> QuorumEpochResult {
+        self.call(|reply| Command::EndQuorumEpoch {
+            leader_id,
+            leader_epoch,
+            preferred_successors,
+            reply,
+        })
+        .unwrap_or_else(QuorumEpochResult::unavailable)
+    }
+
+    /// Answers once there is something past the fetch offset, the high
+    /// watermark moves, or the fetch's wait is up.
+    pub fn fetch(&self, fetch: MetadataFetch) -> MetadataFetchResult {
+        self.call(|reply| Command::Fetch(fetch, reply))
+            .unwrap_or_else(|| MetadataFetchResult::error(ErrorCode::UnknownServerError, (-1, -1)))
+    }
+
+    /// A chunk of one of the leader's snapshots.
+    pub fn fetch_snapshot(&self, fetch: SnapshotFetch) -> SnapshotFetchResult {
+        self.call(|reply| Command::FetchSnapshot(fetch, reply))
+            .unwrap_or_else(|| SnapshotFetchResult::error(ErrorCode::UnknownServerError, (-1, -1)))
+    }
+
+    /// Appends `records` to the metadata log as one batch if this node
+    /// leads, answering once a majority of voters have it or the request
+    /// timeout is up. Any other voter answers NOT_CONTROLLER, so an empty
+    /// append just checks that this node leads.
+    pub fn append(&self, records: Vec<BatchRecord>) -> AppendResult {
+        self.call(|reply| Command::Append(records, reply))
+            .unwrap_or(Err(ErrorCode::UnknownServerError))
+    }
+
+    /// Adds `voter` to the voter set if this node leads, answering once the
+    /// new set is committed.
+    pub fn add_voter(&self, voter: Voter) -> AppendResult {
+        self.call(|reply| Command::AddVoter(voter, reply))
+            .unwrap_or(Err(ErrorCode::UnknownServerError))
+    }
+
+    /// Removes voter `voter_id` from the voter set if this node leads,
+    /// answering once the new set is committed. A leader that removes
+    /// itself resigns once it is.
+    pub fn remove_voter(&self, voter_id: i32) -> AppendResult {
+        self.call(|reply| Command::RemoveVoter(voter_id, reply))
+            .unwrap_or(Err(ErrorCode::UnknownServerError))
+    }
+
+    /// The voters and observers and how far each has got, which only the
+    /// leader knows.
+    pub fn describe(&self) -> QuorumDescription {
+        self.call(Command::Describe)
+            .unwrap_or_else(|| QuorumDescription {
+                error_code: ErrorCode::UnknownServerError,
+                leader_id: -1,
+                leader_epoch: -1,
+                high_watermark: -1,
+                voters: Vec::new(),
+                observers: Vec::new(),
+            })
+    }
+
+    /// If this node leads, steps down and tells the other voters, so one of
+    /// them takes over without waiting out its election timeout. Returns
+    /// once they have answered or the request timeout is up.
+    pub async fn resign(&self) {
+        let (reply, rx) = oneshot::channel();
+        if self.commands.send(Command::Resign(reply)).await.is_ok() {
+            let _ = rx.await;
+        }
+    }
+
+    /// Stops the quorum task, so the metadata log is neither appended to nor
+    /// voted on after this returns. A leader should `resign` first.
+    pub async fn shutdown(&self) {
+        self.stop.send_replace(true);
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+    }
+
+    fn call<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> Option<T> {
+        let (reply, rx) = oneshot::channel();
+        self.commands.blocking_send(command(reply)).ok()?;
+        rx.blocking_recv().ok()
+    }
+}
+
+impl QuorumEpochResult {
+    fn unavailable() -> Self {
+        Self {
+            error_code: ErrorCode::UnknownServerError,
+            leader_id: -1,
+            leader_epoch: -1,
+        }
+    }
+}
+
+impl MetadataFetchResult {
+    fn error(error_code: ErrorCode, current_leader: (i32, i32)) -> Self {
+        Self {
+            error_code,
+            high_watermark: -1,
+            records: Bytes::new(),
+            diverging_epoch: None,
+            current_leader,
+            log_start_offset: -1,
+            snapshot_id: None,
+        }
+    }
+}
+
+impl SnapshotFetchResult {
+    fn error(error_code: ErrorCode, current_leader: (i32, i32)) -> Self {
+        Self {
+            error_code,
+            size: -1,
+            records: Bytes::new(),
+            current_leader,
+        }
+    }
+}
+
+enum Role {
+    /// No leader known in this epoch. Whether a vote has been cast in it is
+    /// in the election state.
+    Unattached {
+        election_deadline: Instant,
+    },
+    Candidate {
+        granted: BTreeSet<i32>,
+        rejected: BTreeSet<i32>,
+        election_deadline: Instant,
+    },
+    Leader(LeaderState),
+    Follower {
+        leader_id: i32,
+        /// When the follower stands for election if no fetch succeeds.
+        fetch_deadline: Instant,
+    },
+}
+
+impl Role {
+    fn name(&self) -> &'static str {
+        match self {
+            Role::Unattached { .. } => "unattached",
+            Role::Candidate { .. } => "candidate",
+            Role::Leader(_) => "leader",
+            Role::Follower { .. } => "follower",
+        }
+    }
+}
+
+struct LeaderState {
+    /// Where this leader's epoch starts in the log. The high watermark may
+    /// not move until a majority has the epoch's first record, so nothing
+    /// from an earlier epoch is committed on this leader's word alone.
+    epoch_start_offset: i64,
+    /// Each other voter's log end as of its last fetch, and when that was.
+    followers: HashMap<i32, (i64, Option<Instant>)>,
+    /// The same for replicas that fetch without being voters.
+    observers: HashMap<i32, (i64, Instant)>,
+    /// Voters that have not acknowledged BeginQuorumEpoch yet.
+    unacknowledged: BTreeSet<i32>,
+    /// When the leader last checked it still has a majority.
+    quorum_checked: Instant,
+}
+
+/// A fetch held until there is something to answer it with.
+struct ParkedFetch {
+    fetch: MetadataFetch,
+    reply: oneshot::Sender<MetadataFetchResult>,
+    deadline: Instant,
+}
+
+/// An append waiting for the high watermark to pass it.
+struct PendingAppend {
+    last_offset: i64,
+    reply: oneshot::Sender<AppendResult>,
+    deadline: Instant,
+}
+
+/// A follower's fetch of the leader's snapshot, as far as it has got.
+struct PendingSnapshot {
+    id: SnapshotId,
+    data: Vec<u8>,
+}
+
+/// A resignation waiting for the other voters to hear of it, and who asked
+/// for it, unless the leader resigned of its own accord.
+struct Resigning {
+    reply: Option<oneshot::Sender<()>>,
+    pending: BTreeSet<i32>,
+    deadline: Instant,
+}
+
+/// The state owned by the quorum task.
+struct Quorum {
+    settings: QuorumSettings,
+    node_id: i32,
+    cluster_id: String,
+    /// The voter set in force, and the offset of the VotersRecord that set
+    /// it, -1 while it is still the configured one.
+    voters: Vec<Voter>,
+    voters_offset: i64,
+    voter_ids: Vec<i32>,
+    log: MetadataLog,
+    state_file: QuorumStateFile,
+    election: ElectionState,
+    role: Role,
+    high_watermark: i64,
+    peers: HashMap<i32, PeerClient>,
+    /// Where peers report back, for peers started as voters are added.
+    events: mpsc::UnboundedSender<Event>,
+    /// Voters with a request outstanding; each gets one at a time.
+    in_flight: HashSet<i32>,
+    /// Voters not to be sent anything until the time given, after a failure.
+    backoff: HashMap<i32, Instant>,
+    parked: Vec<ParkedFetch>,
+    appending: Vec<PendingAppend>,
+    resigning: Option<Resigning>,
+    snapshot_fetch: Option<PendingSnapshot>,
+}
+
+impl Quorum {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        settings: QuorumSettings,
+        node_id: i32,
+        cluster_id: String,
+        log: MetadataLog,
+        state_file: QuorumStateFile,
+        mut election: ElectionState,
+        (voters_offset, voters): (i64, Vec<Voter>),
+        events: mpsc::UnboundedSender<Event>,
+    ) -> Self {
+        // A leader that restarts has lost its followers' progress, so it
+        // can't carry on leading; it waits for the next election instead.
+        if election.leader_id == Some(node_id) {
+            election.leader_id = None;
+        }
+        let mut quorum = Self {
+            settings,
+            node_id,
+            cluster_id,
+            voters: Vec::new(),
+            voters_offset: -1,
+            voter_ids: Vec::new(),
+            log,
+            state_file,
+            election,
+            role: Role::Unattached {
+                election_deadline: Instant::now(),
+            },
+            high_watermark: 0,
+            peers: HashMap::new(),
+            events,
+            in_flight: HashSet::new(),
+            backoff: HashMap::new(),
+            parked: Vec::new(),
+            appending: Vec::new(),
+            resigning: None,
+            snapshot_fetch: None,
+        };
+        quorum.set_voters(voters_offset, voters);
+        quorum.role = match election.leader_id {
+            Some(leader_id) => Role::Follower {
+                leader_id,
+                fetch_deadline: Instant::now() + quorum.settings.fetch_timeout,
+            },
+            None => Role::Unattached {
+                election_deadline: quorum.election_deadline(),
+            },
+        };
+        info!(
+            epoch = election.epoch,
+            leader = ?election.leader_id,
+            voted = ?election.voted_id,
+            voters = ?quorum.voter_ids,
+            role = quorum.role.name(),
+            "metadata quorum starting"
+        );
+        quorum
+    }
+
+    async fn run(
+        mut self,
+        mut commands: mpsc::Receiver<Command>,
+        mut events: mpsc::UnboundedReceiver<Event>,
+        mut stop: watch::Receiver<bool>,
+    ) {
+        let mut tick = tokio::time::interval(TICK_INTERVAL);
+        while !*stop.borrow() {
+            tokio::select! {
+                _ = stop.wait_for(|&stop| stop) => return,
+                command = commands.recv() => match command {
+                    Some(command) => self.handle(command),
+                    None => return,
+                },
+                Some(event) = events.recv() => self.handle_event(event),
+                _ = tick.tick() => {}
+            }
+            self.poll(Instant::now());
+        }
+    }
+
+    fn majority(&self) -> usize {
+        self.voter_ids.len() / 2 + 1
+    }
+
+    /// Whether this node is one of the voters; one that isn't follows the
+    /// leader without ever standing for election.
+    fn is_voter(&self) -> bool {
+        self.voter_ids.contains(&self.node_id)
+    }
+
+    /// Makes `voters`, as set by the VotersRecord at `offset`, the voter
+    /// set. Peers are started for voters that are new or have moved and
+    /// dropped for those that are gone, and a leader starts replicating to
+    /// the new ones.
+    fn set_voters(&mut self, offset: i64, voters: Vec<Voter>) {
+        let old = std::mem::take(&mut self.voters);
+        self.peers
+            .retain(|id, _| voters.iter().any(|v| v.id == *id && old.contains(v)));
+        for voter in voters.iter().filter(|v| v.id != self.node_id) {
+            if !self.peers.contains_key(&voter.id) {
+                let client = PeerClient::spawn(
+                    self.node_id,
+                    voter.clone(),
+                    self.settings.request_timeout,
+                    self.events.clone(),
+                );
+                self.peers.insert(voter.id, client);
+            }
+        }
+        self.in_flight.retain(|id| self.peers.contains_key(id));
+        self.voter_ids = voters.iter().map(|v| v.id).collect();
+        if let Role::Leader(leader) = &mut self.role {
+            leader.followers.retain(|id, _| self.voter_ids.contains(id));
+            leader
+                .unacknowledged
+                .retain(|id| self.voter_ids.contains(id));
+            for &id in &self.voter_ids {
+                if id != self.node_id && !leader.followers.contains_key(&id) {
+                    leader.observers.remove(&id);
+                    leader.followers.insert(id, (0, None));
+                    leader.unacknowledged.insert(id);
+                }
+            }
+        }
+        if !old.is_empty() && old != voters {
+            info!(offset, voters = ?self.voter_ids, "voter set changed");
+        }
+        self.voters = voters;
+        self.voters_offset = offset;
+        self.persist();
+    }
+
+    /// Takes up the last VotersRecord in `records`, just appended to the
+    /// log.
+    fn apply_voters(&mut self, records: Bytes) {
+        match voters::last_voters(records) {
+            Ok(Some((offset, voters))) => self.set_voters(offset, voters),
+            Ok(None) => {}
+            Err(e) => error!(error = %e, "failed to read the voter set"),
+        }
+    }
+
+    /// Reads the voter set back from the whole log, after the VotersRecord
+    /// that set the current one may have been truncated or replaced by a
+    /// snapshot.
+    fn reload_voters(&mut self) {
+        let voters = self
+            .log
+            .read_all()
+            .and_then(voters::last_voters)
+            .map(|voters| voters.unwrap_or_else(|| (-1, self.settings.voters.clone())));
+        match voters {
+            Ok((offset, voters)) => self.set_voters(offset, voters),
+            Err(e) => error!(error = %e, "failed to read the voter set"),
+        }
+    }
+
+    fn leader(&self) -> (i32, i32) {
+        (self.election.leader_id.unwrap_or(-1), self.election.epoch)
+    }
+
+    /// A random wait in `[election_timeout, 2 * election_timeout)`.
+    fn election_deadline(&self) -> Instant {
+        let timeout = self.settings.election_timeout;
+        Instant::now() + rand::thread_rng().gen_range(timeout..timeout * 2)
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Vote(vote, reply) => {
+                let _ = reply.send(self.handle_vote(vote));
+            }
+            Command::BeginQuorumEpoch {
+                leader_id,
+                leader_epoch,
+                reply,
+            } => {
+                let _ = reply.send(self.handle_begin_quorum_epoch(leader_id, leader_epoch));
+            }
+            Command::EndQuorumEpoch {
+                leader_id,
+                leader_epoch,
+                preferred_successors,
+                reply,
+            } => {
+                let result =
+                    self.handle_end_quorum_epoch(leader_id, leader_epoch, &preferred_successors);
+                let _ = reply.send(result);
+            }
+            Command::Fetch(fetch, reply) => self.handle_fetch(fetch, reply),
+            Command::FetchSnapshot(fetch, reply) => {
+                let _ = reply.send(self.handle_fetch_snapshot(fetch));
+            }
+            Command::Append(records, reply) => self.handle_append(records, reply),
+            Command::AddVoter(voter, reply) => {
+                let change = VoterChange::Add(voter);
+                self.handle_voter_change(change, reply)
+            }
+            Command::RemoveVoter(voter_id, reply) => {
+                self.handle_voter_change(VoterChange::Remove(voter_id), reply)
+            }
+            Command::Describe(reply) => {
+                let _ = reply.send(self.describe());
+            }
+            Command::Resign(reply) => self.resign(Some(reply)),
+        }
+    }
+
+    fn handle_vote(&mut self, vote: Vote) -> VoteResult {
+        let answer = |quorum: &Self, error_code, vote_granted| {
+            let (leader_id, leader_epoch) = quorum.leader();
+            VoteResult {
+                error_code,
+                leader_id,
+                leader_epoch,
+                vote_granted,
+            }
+        };
+        if !self.voter_ids.contains(&vote.candidate_id) {
+            return answer(self, ErrorCode::InconsistentVoterSet, false);
+        }
+        if vote.candidate_epoch < self.election.epoch {
+            return answer(self, ErrorCode::FencedLeaderEpoch, false);
+        }
+        if vote.candidate_epoch > self.election.epoch {
+            self.become_unattached(vote.candidate_epoch);
+        }
+        let granted = match &self.role {
+            Role::Unattached { .. } => match self.election.voted_id {
+                Some(voted_id) => voted_id == vote.candidate_id,
+                None => {
+                    (vote.last_offset_epoch, vote.last_offset)
+                        >= (self.log.last_epoch(), self.log.end_offset())
+                }
+            },
+            // A candidate has voted for itself; a leader or follower knows
+            // who won.
+            Role::Candidate { .. } | Role::Leader(_) | Role::Follower { .. } => false,
+        };
+        if granted && self.election.voted_id.is_none() {
+            self.election.voted_id = Some(vote.candidate_id);
+            self.role = Role::Unattached {
+                election_deadline: self.election_deadline(),
+            };
+            self.persist();
+            info!(
+                epoch = self.election.epoch,
+                candidate = vote.candidate_id,
+                "voted"
+            );
+        }
+        answer(self, ErrorCode::None, granted)
+    }
+
+    fn handle_begin_quorum_epoch(
+        &mut self,
+        leader_id: i32,
+        leader_epoch: i32,
+    ) -> QuorumEpochResult {
+        let error_code = if !self.voter_ids.contains(&leader_id) {
+            ErrorCode::InconsistentVoterSet
+        } else if leader_epoch < self.election.epoch {
+            ErrorCode::FencedLeaderEpoch
+        } else {
+            self.observe_leader(leader_epoch, Some(leader_id));
+            ErrorCode::None
+        };
+        let (leader_id, leader_epoch) = self.leader();
+        QuorumEpochResult {
+            error_code,
+            leader_id,
+            leader_epoch,
+        }
+    }
+
+    /// A follower of the resigning leader stands for election: at once if
+    /// it is the leader's first choice, later the further down the list it
+    /// is, and after a full election timeout if it isn't on it.
+    fn handle_end_quorum_epoch(
+        &mut self,
+        leader_id: i32,
+        leader_epoch: i32,
+        preferred_successors: &[i32],
+    ) -> QuorumEpochResult {
+        let error_code = if leader_epoch < self.election.epoch {
+            ErrorCode::FencedLeaderEpoch
+        } else {
+            self.observe_leader(leader_epoch, None);
+            if self.election.epoch == leader_epoch
+                && matches!(self.role, Role::Follower { leader_id: l, .. } if l == leader_id)
+            {
+                let election_deadline = match preferred_successors
+                    .iter()
+                    .position(|&id| id == self.node_id)
+                {
+                    Some(position) => Instant::now() + RETRY_BACKOFF * position as u32,
+                    None => self.election_deadline(),
+                };
+                self.election.leader_id = None;
+                self.role = Role::Unattached { election_deadline };
+                self.persist();
+                info!(epoch = leader_epoch, leader = leader_id, "leader resigned");
+            }
+            ErrorCode::None
+        };
+        let (leader_id, leader_epoch) = self.leader();
+        QuorumEpochResult {
+            error_code,
+            leader_id,
+            leader_epoch,
+        }
+    }
+
+    /// Whether a fetch made in `epoch` may be served here: only the leader
+    /// of the current epoch serves them.
+    fn check_fetch_epoch(&self, epoch: i32) -> ErrorCode {
+        if epoch < self.election.epoch {
+            ErrorCode::FencedLeaderEpoch
+        } else if epoch > self.election.epoch {
+            ErrorCode::UnknownLeaderEpoch
+        } else if !matches!(self.role, Role::Leader(_)) {
+            ErrorCode::NotLeaderOrFollower
+        } else {
+            ErrorCode::None
+        }
+    }
+
+    fn handle_fetch(&mut self, fetch: MetadataFetch, reply: oneshot::Sender<MetadataFetchResult>) {
+        let current_leader = self.leader();
+        let error_code = self.check_fetch_epoch(fetch.current_leader_epoch);
+        if error_code != ErrorCode::None {
+            let _ = reply.send(MetadataFetchResult::error(error_code, current_leader));
+            return;
+        }
+
+        let log_start_offset = self.log.log_start_offset();
+        let mut diverging_epoch = None;
+        if fetch.fetch_offset > 0 {
+            let (epoch, end_offset) = self.log.end_offset_for_epoch(fetch.last_fetched_epoch);
+            if epoch != fetch.last_fetched_epoch || fetch.fetch_offset > end_offset {
+                diverging_epoch = Some((epoch, end_offset));
+            }
+        }
+        // A fetcher that is behind the log start, or whose log only agrees
+        // with this one somewhere before it, has to start from a snapshot.
+        let behind = fetch.fetch_offset < log_start_offset
+            || diverging_epoch.is_some_and(|(_, end_offset)| end_offset < log_start_offset);
+        let snapshot_id = self.log.latest_snapshot().filter(|_| behind);
+        if diverging_epoch.is_some() || snapshot_id.is_some() {
+            let _ = reply.send(MetadataFetchResult {
+                error_code: ErrorCode::None,
+                high_watermark: self.high_watermark,
+                records: Bytes::new(),
+                diverging_epoch: diverging_epoch.filter(|_| snapshot_id.is_none()),
+                current_leader,
+                log_start_offset,
+                snapshot_id,
+            });
+            return;
+        }
+        if let Role::Leader(leader) = &mut self.role {
+            if let Some(follower) = leader.followers.get_mut(&fetch.replica_id) {
+                *follower = (fetch.fetch_offset, Some(Instant::now()));
+            } else if fetch.replica_id >= 0 && fetch.replica_id != self.node_id {
+                leader
+                    .observers
+                    .insert(fetch.replica_id, (fetch.fetch_offset, Instant::now()));
+            }
+        }
+        self.update_high_watermark();
+
+        let deadline = Instant::now() + fetch.max_wait.min(FETCH_MAX_WAIT);
+        let parked = ParkedFetch {
+            fetch,
+            reply,
+            deadline,
+        };
+        if let Some(parked) = self.try_complete(parked, false) {
+            self.parked.push(parked);
+        }
+    }
+
+    /// Answers `parked` if there are records for it, or regardless when
+    /// `force` is set; hands it back otherwise.
+    fn try_complete(&mut self, parked: ParkedFetch, force: bool) -> Option<ParkedFetch> {
+        let has_data = parked.fetch.fetch_offset < self.log.end_offset();
+        if !has_data && !force && Instant::now() < parked.deadline {
+            return Some(parked);
+        }
+        let result = match self
+            .log
+            .read(parked.fetch.fetch_offset, parked.fetch.max_bytes)
+        {
+            Ok(records) => MetadataFetchResult {
+                error_code: ErrorCode::None,
+                high_watermark: self.high_watermark,
+                records,
+                diverging_epoch: None,
+                current_leader: self.leader(),
+                log_start_offset: self.log.log_start_offset(),
+                snapshot_id: None,
+            },
+            Err(e) => {
+                error!(error = %e, "failed to read the metadata log");
+                MetadataFetchResult::error(ErrorCode::UnknownServerError, self.leader())
+            }
+        };
+        let _ = parked.reply.send(result);
+        None
+    }
+
+    /// Snapshots are served by the leader, like fetches. A snapshot fetch
+    /// counts as hearing from the follower, but says nothing of how far its
+    /// log has got.
+    fn handle_fetch_snapshot(&mut self, fetch: SnapshotFetch) -> SnapshotFetchResult {
+        let current_leader = self.leader();
+        let error_code = self.check_fetch_epoch(fetch.current_leader_epoch);
+        if error_code != ErrorCode::None {
+            return SnapshotFetchResult::error(error_code, current_leader);
+        }
+        if let Role::Leader(leader) = &mut self.role {
+            if let Some((_, last_fetch)) = leader.followers.get_mut(&fetch.replica_id) {
+                *last_fetch = Some(Instant::now());
+            }
+        }
+        match self
+            .log
+            .read_snapshot(fetch.snapshot_id, fetch.position, fetch.max_bytes)
+        {
+            Ok(None) => SnapshotFetchResult::error(ErrorCode::SnapshotNotFound, current_leader),
+            Ok(Some((size, _))) if fetch.position > size => {
+                SnapshotFetchResult::error(ErrorCode::PositionOutOfRange, current_leader)
+            }
+            Ok(Some((size, records))) => SnapshotFetchResult {
+                error_code: ErrorCode::None,
+                size: size as i64,
+                records,
+                current_leader,
+            },
+            Err(e) => {
+                error!(error = %e, "failed to read a metadata snapshot");
+                SnapshotFetchResult::error(ErrorCode::UnknownServerError, current_leader)
+            }
+        }
+    }
+
+    /// Answers held fetches that have become answerable, all of them when
+    /// `force` is set.
+    fn complete_parked(&mut self, force: bool) {
+        for parked in std::mem::take(&mut self.parked) {
+            if let Some(parked) = self.try_complete(parked, force) {
+                self.parked.push(parked);
+            }
+        }
+    }
+
+    /// Moves the high watermark to the offset a majority of voters have
+    /// reached, once that is inside this leader's epoch.
+    fn update_high_watermark(&mut self) {
+        let Role::Leader(leader) = &self.role else {
+            return;
+        };
+        let mut ends: Vec<i64> = leader.followers.values().map(|(end, _)| *end).collect();
+        // A leader that removed itself replicates the change without
+        // counting towards it.
+        if self.is_voter() {
+            ends.push(self.log.end_offset());
+        }
+        ends.sort_unstable_by(|a, b| b.cmp(a));
+        let majority_end = ends[self.majority() - 1];
+        if majority_end > leader.epoch_start_offset && majority_end > self.high_watermark {
+            self.high_watermark = majority_end;
+            // Followers learn of it from the next fetch answered.
+            self.complete_parked(true);
+            let high_watermark = self.high_watermark;
+            for pending in std::mem::take(&mut self.appending) {
+                if pending.last_offset < high_watermark {
+                    let _ = pending.reply.send(Ok(pending.last_offset));
+                } else {
+                    self.appending.push(pending);
+                }
+            }
+        }
+    }
+
+    fn handle_append(&mut self, records: Vec<BatchRecord>, reply: oneshot::Sender<AppendResult>) {
+        if !matches!(self.role, Role::Leader(_)) {
+            let _ = reply.send(Err(ErrorCode::NotController));
+            return;
+        }
+        if records.is_empty() {
+            let _ = reply.send(Ok(self.log.end_offset() - 1));
+            return;
+        }
+        if let Err(e) = self.log.append_as_leader(self.election.epoch, 0, &records) {
+            error!(error = %e, "failed to append to the metadata log");
+            let _ = reply.send(Err(ErrorCode::UnknownServerError));
+            return;
+        }
+        self.appending.push(PendingAppend {
+            last_offset: self.log.end_offset() - 1,
+            reply,
+            deadline: Instant::now() + self.settings.request_timeout,
+        });
+        // A lone voter is its own majority.
+        self.update_high_watermark();
+    }
+
+    /// Adds or removes one voter, if this node leads and the last change
+    /// has been committed in its epoch. The new set applies as soon as its
+    /// VotersRecord is appended, so it is the new majority that commits it.
+    fn handle_voter_change(&mut self, change: VoterChange, reply: oneshot::Sender<AppendResult>) {
+        let Role::Leader(leader) = &self.role else {
+            let _ = reply.send(Err(ErrorCode::NotLeaderOrFollower));
+            return;
+        };
+        if self.high_watermark <= leader.epoch_start_offset
+            || self.voters_offset >= self.high_watermark
+        {
+            let _ = reply.send(Err(ErrorCode::RequestTimedOut));
+            return;
+        }
+        let mut voters = self.voters.clone();
+        match change {
+            VoterChange::Add(voter) => {
+                if voters.iter().any(|v| v.id == voter.id) {
+                    let _ = reply.send(Err(ErrorCode::DuplicateVoter));
+                    return;
+                }
+                voters.push(voter);
+            }
+            VoterChange::Remove(voter_id) => {
+                let Some(position) = voters.iter().position(|v| v.id == voter_id) else {
+                    let _ = reply.send(Err(ErrorCode::VoterNotFound));
+                    return;
+                };
+                if voters.len() == 1 {
+                    let _ = reply.send(Err(ErrorCode::InvalidRequest));
+                    return;
+                }
+                voters.remove(position);
+            }
+        }
+        let record = voters::voters_record(&voters);
+        let offset = match self
+            .log
+            .append_as_leader(self.election.epoch, CONTROL_FLAG, &[record])
+        {
+            Ok(offset) => offset,
+            Err(e) => {
+                error!(error = %e, "failed to append to the metadata log");
+                let _ = reply.send(Err(ErrorCode::UnknownServerError));
+                return;
+            }
+        };
+        self.set_voters(offset, voters);
+        self.appending.push(PendingAppend {
+            last_offset: offset,
+            reply,
+            deadline: Instant::now() + self.settings.request_timeout,
+        });
+        self.update_high_watermark();
+    }
+
+    fn describe(&self) -> QuorumDescription {
+        let (leader_id, leader_epoch) = self.leader();
+        let Role::Leader(leader) = &self.role else {
+            return QuorumDescription {
+                error_code: ErrorCode::NotLeaderOrFollower,
+                leader_id,
+                leader_epoch,
+                high_watermark: -1,
+                voters: Vec::new(),
+                observers: Vec::new(),
+            };
+        };
+        let now = Instant::now();
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        let end_offset = self.log.end_offset();
+        let state = |replica_id, end: i64, at: Instant| {
+            let timestamp = now_ms - now.saturating_duration_since(at).as_millis() as i64;
+            ReplicaState {
+                replica_id,
+                log_end_offset: end,
+                last_fetch_timestamp: timestamp,
+                last_caught_up_timestamp: if end >= end_offset { timestamp } else { -1 },
+            }
+        };
+        let voters = self
+            .voter_ids
+            .iter()
+            .map(|&id| match leader.followers.get(&id) {
+                _ if id == self.node_id => state(id, end_offset, now),
+                Some(&(end, Some(at))) => state(id, end, at),
+                _ => ReplicaState {
+                    replica_id: id,
+                    log_end_offset: -1,
+                    last_fetch_timestamp: -1,
+                    last_caught_up_timestamp: -1,
+                },
+            })
+            .collect();
+        let mut observers: Vec<ReplicaState> = leader
+            .observers
+            .iter()
+            .map(|(&id, &(end, at))| state(id, end, at))
+            .collect();
+        observers.sort_unstable_by_key(|r| r.replica_id);
+        QuorumDescription {
+            error_code: ErrorCode::None,
+            leader_id,
+            leader_epoch,
+            high_watermark: self.high_watermark,
+            voters,
+            observers,
+        }
+    }
+
+    fn resign(&mut self, reply: Option<oneshot::Sender<()>>) {
+        let Role::Leader(leader) = &self.role else {
+            if let Some(reply) = reply {
+                let _ = reply.send(());
+            }
+            return;
+        };
+        let mut successors: Vec<(i64, i32)> = leader
+            .followers
+            .iter()
+            .map(|(&id, &(end, _))| (end, id))
+            .collect();
+        successors.sort_unstable_by(|a, b| b.cmp(a));
+        let preferred_successors: Vec<i32> = successors.iter().map(|&(_, id)| id).collect();
+        let epoch = self.election.epoch;
+        info!(epoch, "resigning as metadata quorum leader");
+        self.become_unattached(epoch);
+        for &id in &preferred_successors {
+            self.send(
+                id,
+                Request::EndQuorumEpoch(EndQuorumEpochRequest::metadata(
+                    &self.cluster_id,
+                    self.node_id,
+                    epoch,
+                    preferred_successors.clone(),
+                )),
+            );
+        }
+        self.resigning = Some(Resigning {
+            reply,
+            pending: preferred_successors.into_iter().collect(),
+            deadline: Instant::now() + self.settings.request_timeout,
+        });
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        self.in_flight.remove(&event.voter_id);
+        if let Some(resigning) = &mut self.resigning {
+            if matches!(event.reply, Ok(Reply::EndQuorumEpoch) | Err(_)) {
+                resigning.pending.remove(&event.voter_id);
+            }
+        }
+        let reply = match event.reply {
+            Ok(reply) => reply,
+            Err(_) => {
+                self.backoff
+                    .insert(event.voter_id, Instant::now() + RETRY_BACKOFF);
+                return;
+            }
+        };
+        // Answers to requests from an earlier epoch may still say something
+        // about a later one, so they are read before being dropped.
+        match reply {
+            Reply::Vote(res) => {
+                let Some(p) = res
+                    .metadata_partition()
+                    .filter(|_| res.error_code == ErrorCode::None)
+                else {
+                    self.backoff_after_error(event.voter_id, res.error_code);
+                    return;
+                };
+                let (error_code, granted) = (p.error_code, p.vote_granted);
+                let (leader_id, leader_epoch) = (p.leader_id, p.leader_epoch);
+                self.observe_leader(leader_epoch, (leader_id >= 0).then_some(leader_id));
+                if event.epoch != self.election.epoch {
+                    return;
+                }
+                if error_code != ErrorCode::None {
+                    self.backoff_after_error(event.voter_id, error_code);
+                    return;
+                }
+                let majority = self.majority();
+                if let Role::Candidate {
+                    granted: votes,
+                    rejected,
+                    ..
+                } = &mut self.role
+                {
+                    if granted {
+                        votes.insert(event.voter_id);
+                    } else {
+                        rejected.insert(event.voter_id);
+                    }
+                    if votes.len() >= majority {
+                        self.become_leader();
+                    }
+                }
+            }
+            Reply::BeginQuorumEpoch(res) => {
+                let Some(p) = res
+                    .metadata_partition()
+                    .filter(|_| res.error_code == ErrorCode::None)
+                else {
+                    self.backoff_after_error(event.voter_id, res.error_code);
+                    return;
+                };
+                let (error_code, leader_id, leader_epoch) =
+                    (p.error_code, p.leader_id, p.leader_epoch);
+                self.observe_leader(leader_epoch, (leader_id >= 0).then_some(leader_id));
+                if event.epoch != self.election.epoch {
+                    return;
+                }
+                if error_code != ErrorCode::None {
+                    self.backoff_after_error(event.voter_id, error_code);
+                    return;
+                }
+                if let Role::Leader(leader) = &mut self.role {
+                    leader.unacknowledged.remove(&event.voter_id);
+                }
+            }
+            Reply::EndQuorumEpoch => {}
+            Reply::Fetch(res) => self.handle_fetch_response(event.voter_id, event.epoch, res),
+            Reply::FetchSnapshot(res) => {
+                self.handle_fetch_snapshot_response(event.voter_id, event.epoch, res)
+            }
+        }
+    }
+
+    fn handle_fetch_response(&mut self, voter_id: i32, epoch: i32, res: FetchResponseV16) {
+        let Some(p) = res
+            .metadata_partition()
+            .filter(|_| res.error_code == ErrorCode::None)
+        else {
+            self.backoff_after_error(voter_id, res.error_code);
+            return;
+        };
+        if let Some((leader_id, leader_epoch)) = p.current_leader {
+            self.observe_leader(leader_epoch, (leader_id >= 0).then_some(leader_id));
+        }
+        let following =
+            matches!(self.role, Role::Follower { leader_id, .. } if leader_id == voter_id);
+        if epoch != self.election.epoch || !following {
+            return;
+        }
+        if p.error_code != ErrorCode::None {
+            self.backoff_after_error(voter_id, p.error_code);
+            return;
+        }
+        if let Some(id) = p.snapshot_id {
+            info!(
+                end_offset = id.end_offset,
+                epoch = id.epoch,
+                log_start_offset = p.log_start_offset,
+                "metadata log is behind the leader's log start, fetching its snapshot"
+            );
+            self.snapshot_fetch = Some(PendingSnapshot {
+                id,
+                data: Vec::new(),
+            });
+            self.fetch_succeeded();
+            return;
+        }
+        if let Some((diverging_epoch, end_offset)) = p.diverging_epoch {
+            let (local_epoch, local_end) = self.log.end_offset_for_epoch(diverging_epoch);
+            let truncate_to = if local_epoch == diverging_epoch {
+                end_offset.min(local_end)
+            } else {
+                local_end.max(0).min(end_offset)
+            };
+            warn!(
+                epoch = diverging_epoch,
+                offset = truncate_to,
+                "metadata log diverges from the leader's, truncating"
+            );
+            if let Err(e) = self.log.truncate_to(truncate_to) {
+                error!(error = %e, "failed to truncate the metadata log");
+                self.backoff
+                    .insert(voter_id, Instant::now() + RETRY_BACKOFF);
+            } else if truncate_to <= self.voters_offset {
+                self.reload_voters();
+            }
+            return;
+        }
+        if let Err(e) = self.log.append_as_follower(&p.records) {
+            error!(error = %e, "failed to append fetched metadata");
+            self.backoff
+                .insert(voter_id, Instant::now() + RETRY_BACKOFF);
+            return;
+        }
+        self.apply_voters(p.records.clone());
+        self.high_watermark = p.high_watermark.min(self.log.end_offset());
+        self.fetch_succeeded();
+    }
+
+    /// Adds a chunk to the snapshot being fetched, and once it is whole
+    /// replaces the log with it. A snapshot the leader no longer has, or a
+    /// position it doesn't agree with, sends the follower back to Fetch to
+    /// learn which snapshot to start over with.
+    fn handle_fetch_snapshot_response(
+        &mut self,
+        voter_id: i32,
+        epoch: i32,
+        res: FetchSnapshotResponse,
+    ) {
+        let Some(p) = res
+            .metadata_partition()
+            .filter(|_| res.error_code == ErrorCode::None)
+        else {
+            self.backoff_after_error(voter_id, res.error_code);
+            return;
+        };
+        if let Some((leader_id, leader_epoch)) = p.current_leader {
+            self.observe_leader(leader_epoch, (leader_id >= 0).then_some(leader_id));
+        }
+        let following =
+            matches!(self.role, Role::Follower { leader_id, .. } if leader_id == voter_id);
+        if epoch != self.election.epoch || !following {
+            return;
+        }
+        if p.error_code != ErrorCode::None {
+            if matches!(
+                p.error_code,
+                ErrorCode::SnapshotNotFound | ErrorCode::PositionOutOfRange
+            ) {
+                self.snapshot_fetch = None;
+            }
+            self.backoff_after_error(voter_id, p.error_code);
+            return;
+        }
+        let Some(pending) = &mut self.snapshot_fetch else {
+            return;
+        };
+        if p.snapshot_id != pending.id || p.position != pending.data.len() as i64 {
+            return;
+        }
+        pending.data.extend_from_slice(&p.unaligned_records);
+        let complete = pending.data.len() as i64 >= p.size;
+        if !complete && p.unaligned_records.is_empty() {
+            self.snapshot_fetch = None;
+            self.backoff_after_error(voter_id, ErrorCode::PositionOutOfRange);
+            return;
+        }
+        self.fetch_succeeded();
+        if !complete {
+            return;
+        }
+        let pending = self.snapshot_fetch.take().unwrap();
+        if let Err(e) = self.log.install_snapshot(pending.id, &pending.data) {
+            error!(error = %e, "failed to install the fetched metadata snapshot");
+            self.backoff
+                .insert(voter_id, Instant::now() + RETRY_BACKOFF);
+            return;
+        }
+        // Only committed records go into a snapshot.
+        self.high_watermark = pending.id.end_offset;
+        self.reload_voters();
+    }
+
+    /// A follower that hears from its leader puts off standing for election.
+    fn fetch_succeeded(&mut self) {
+        if let Role::Follower { fetch_deadline, .. } = &mut self.role {
+            *fetch_deadline = Instant::now() + self.settings.fetch_timeout;
+        }
+    }
+
+    fn backoff_after_error(&mut self, voter_id: i32, error_code: ErrorCode) {
+        warn!(voter = voter_id, error = ?error_code, "metadata quorum request failed");
+        self.backoff
+            .insert(voter_id, Instant::now() + RETRY_BACKOFF);
+    }
+
+    /// Acts on what a request or response says about the leader of
+    /// `epoch`: a later epoch is joined, and a leader of the current one
+    /// followed if none was known.
+    fn observe_leader(&mut self, epoch: i32, leader_id: Option<i32>) {
+        let leader_id = leader_id.filter(|&id| id != self.node_id);
+        if epoch > self.election.epoch {
+            match leader_id {
+                Some(leader_id) => self.become_follower(epoch, leader_id),
+                None => self.become_unattached(epoch),
+            }
+        } else if epoch == self.election.epoch {
+            if let Some(leader_id) = leader_id {
+                if matches!(self.role, Role::Unattached { .. } | Role::Candidate { .. }) {
+                    self.become_follower(epoch, leader_id);
+                }
+            }
+        }
+    }
+
+    fn become_unattached(&mut self, epoch: i32) {
+        if epoch > self.election.epoch {
+            self.election = ElectionState {
+                epoch,
+                leader_id: None,
+                voted_id: None,
+            };
+        } else {
+            self.election.leader_id = None;
+        }
+        self.role = Role::Unattached {
+            election_deadline: self.election_deadline(),
+        };
+        self.persist();
+        self.role_changed();
+    }
+
+    fn become_candidate(&mut self) {
+        self.election = ElectionState {
+            epoch: self.election.epoch + 1,
+            leader_id: None,
+            voted_id: Some(self.node_id),
+        };
+        self.role = Role::Candidate {
+            granted: BTreeSet::from([self.node_id]),
+            rejected: BTreeSet::new(),
+            election_deadline: self.election_deadline(),
+        };
+        self.persist();
+        self.role_changed();
+        if self.majority() == 1 {
+            self.become_leader();
+        }
+    }
+
+    fn become_leader(&mut self) {
+        let granting_voters = match &self.role {
+            Role::Candidate { granted, .. } => granted.iter().copied().collect(),
+            _ => Vec::new(),
+        };
+        self.election.leader_id = Some(self.node_id);
+        self.persist();
+        let epoch_start_offset = self.log.end_offset();
+        let record = self.leader_change_record(&granting_voters);
+        if let Err(e) = self
+            .log
+            .append_as_leader(self.election.epoch, CONTROL_FLAG, &[record])
+        {
+            error!(error = %e, "failed to write the leader change, standing down");
+            self.become_unattached(self.election.epoch);
+            return;
+        }
+        let now = Instant::now();
+        let followers: HashMap<i32, (i64, Option<Instant>)> = self
+            .voter_ids
+            .iter()
+            .filter(|&&id| id != self.node_id)
+            .map(|&id| (id, (0, None)))
+            .collect();
+        self.role = Role::Leader(LeaderState {
+            epoch_start_offset,
+            unacknowledged: followers.keys().copied().collect(),
+            followers,
+            observers: HashMap::new(),
+            quorum_checked: now,
+        });
+        self.role_changed();
+        self.update_high_watermark();
+    }
+
+    fn become_follower(&mut self, epoch: i32, leader_id: i32) {
+        if epoch > self.election.epoch {
+            self.election.voted_id = None;
+        }
+        self.election.epoch = epoch;
+        self.election.leader_id = Some(leader_id);
+        self.role = Role::Follower {
+            leader_id,
+            fetch_deadline: Instant::now() + self.settings.fetch_timeout,
+        };
+        self.persist();
+        self.role_changed();
+    }
+
+    /// The LeaderChange control record: version 0 key and value, the value
+    /// being the new leader, the voters and those that voted for it.
+    fn leader_change_record(&self, granting_voters: &[i32]) -> BatchRecord {
+        let mut key = BytesMut::new();
+        key.put_i16(0);
+        key.put_i16(LEADER_CHANGE_TYPE);
+        let voters = |ids: &[i32]| {
+            let mut b = BytesMut::new();
+            b.put_slice(&integer_encoding::VarInt::encode_var_vec(
+                ids.len() as u32 + 1,
+            ));
+            for &id in ids {
+                b.put_i32(id);
+                b.put(TagBuffer::serialize());
+            }
+            b.freeze()
+        };
+        let mut value = BytesMut::new();
+        value.put_i16(0);
+        value.put_i32(self.node_id);
+        value.put(voters(&self.voter_ids));
+        value.put(voters(granting_voters));
+        value.put(TagBuffer::serialize());
+        BatchRecord {
+            key: Some(key.freeze()),
+            value: Some(value.freeze()),
+        }
+    }
+
+    /// Every role change fails the fetches held for the old one, which only
+    /// a leader serves.
+    fn role_changed(&mut self) {
+        info!(
+            epoch = self.election.epoch,
+            leader = ?self.election.leader_id,
+            voted = ?self.election.voted_id,
+            role = self.role.name(),
+            "metadata quorum state changed"
+        );
+        self.snapshot_fetch = None;
+        let current_leader = self.leader();
+        for parked in self.parked.drain(..) {
+            let _ = parked.reply.send(MetadataFetchResult::error(
+                ErrorCode::NotLeaderOrFollower,
+                current_leader,
+            ));
+        }
+        // Records not yet committed may still be truncated by the next
+        // leader, so whoever asked has to try again there.
+        for pending in self.appending.drain(..) {
+            let _ = pending.reply.send(Err(ErrorCode::NotController));
+        }
+    }
+
+    fn persist(&self) {
+        if let Err(e) = self.state_file.write(&self.election, &self.voter_ids) {
+            error!(error = %e, "failed to write the quorum state");
+        }
+    }
+
+    fn send(&mut self, voter_id: i32, request: Request) {
+        if let Some(peer) = self.peers.get(&voter_id) {
+            self.in_flight.insert(voter_id);
+            peer.send(self.election.epoch, request);
+        }
+    }
+
+    /// Whether a request may go to `voter_id` now.
+    fn can_send(&self, voter_id: i32, now: Instant) -> bool {
+        !self.in_flight.contains(&voter_id)
+            && self
+                .backoff
+                .get(&voter_id)
+                .is_none_or(|&until| now >= until)
+    }
+
+    /// Fires whatever timeouts have passed and sends the requests the
+    /// current role calls for.
+    fn poll(&mut self, now: Instant) {
+        if let Some(resigning) = self.resigning.take() {
+            if resigning.pending.is_empty() || now >= resigning.deadline {
+                if let Some(reply) = resigning.reply {
+                    let _ = reply.send(());
+                }
+            } else {
+                self.resigning = Some(resigning);
+            }
+        }
+        self.complete_parked(false);
+        for pending in std::mem::take(&mut self.appending) {
+            if now >= pending.deadline {
+                let _ = pending.reply.send(Err(ErrorCode::RequestTimedOut));
+            } else {
+                self.appending.push(pending);
+            }
+        }
+        if self.log.committed_bytes(self.high_watermark) >= self.settings.snapshot_interval_bytes {
+            if let Err(e) = self.log.take_snapshot(self.high_watermark) {
+                error!(error = %e, "failed to snapshot the metadata log");
+            }
+        }
+
+        match &self.role {
+            Role::Unattached { election_deadline }
+                if now >= *election_deadline && self.is_voter() =>
+            {
+                self.become_candidate()
+            }
+            Role::Candidate {
+                election_deadline, ..
+            } if now >= *election_deadline => {
+                // Lost, or nobody won: stand again after a random backoff.
+                let backoff = rand::thread_rng()
+                    .gen_range(Duration::ZERO..=self.settings.election_backoff_max);
+                self.role = Role::Unattached {
+                    election_deadline: now + backoff,
+                };
+                info!(epoch = self.election.epoch, "election timed out");
+            }
+            Role::Follower { fetch_deadline, .. } if now >= *fetch_deadline && self.is_voter() => {
+                warn!(
+                    epoch = self.election.epoch,
+                    leader = ?self.election.leader_id,
+                    "no fetch from the metadata quorum leader succeeded in time"
+                );
+                self.become_candidate();
+            }
+            Role::Leader(_) if !self.is_voter() && self.high_watermark > self.voters_offset => {
+                info!(
+                    epoch = self.election.epoch,
+                    "removed from the metadata quorum, resigning"
+                );
+                self.resign(None);
+            }
+            Role::Leader(leader)
+                if now >= leader.quorum_checked + self.settings.fetch_timeout * 3 / 2 =>
+            {
+                let window = self.settings.fetch_timeout * 3 / 2;
+                let in_touch = usize::from(self.is_voter())
+                    + leader
+                        .followers
+                        .values()
+                        .filter(|(_, last_fetch)| last_fetch.is_some_and(|at| now - at < window))
+                        .count();
+                if in_touch < self.majority() {
+                    warn!(
+                        epoch = self.election.epoch,
+                        "lost contact with a majority of the metadata quorum, resigning"
+                    );
+                    self.become_unattached(self.election.epoch);
+                } else if let Role::Leader(leader) = &mut self.role {
+                    leader.quorum_checked = now;
+                }
+            }
+            _ => {}
+        }
+
+        let epoch = self.election.epoch;
+        match &self.role {
+            Role::Candidate {
+                granted, rejected, ..
+            } => {
+                let vote = Vote {
+                    candidate_epoch: epoch,
+                    candidate_id: self.node_id,
+                    last_offset_epoch: self.log.last_epoch(),
+                    last_offset: self.log.end_offset(),
+                };
+                let to_ask: Vec<i32> = self
+                    .voter_ids
+                    .iter()
+                    .copied()
+                    .filter(|id| !granted.contains(id) && !rejected.contains(id))
+                    .filter(|&id| self.can_send(id, now))
+                    .collect();
+                for id in to_ask {
+                    let request = VoteRequest::metadata(&self.cluster_id, &vote);
+                    self.send(id, Request::Vote(request));
+                }
+            }
+            Role::Leader(leader) => {
+                let to_tell: Vec<i32> = leader
+                    .unacknowledged
+                    .iter()
+                    .copied()
+                    .filter(|&id| self.can_send(id, now))
+                    .collect();
+                for id in to_tell {
+                    let request =
+                        BeginQuorumEpochRequest::metadata(&self.cluster_id, self.node_id, epoch);
+                    self.send(id, Request::BeginQuorumEpoch(request));
+                }
+            }
+            Role::Follower { leader_id, .. } if self.can_send(*leader_id, now) => {
+                let leader_id = *leader_id;
+                let request = match &self.snapshot_fetch {
+                    Some(pending) => {
+                        let fetch = SnapshotFetch {
+                            replica_id: self.node_id,
+                            current_leader_epoch: epoch,
+                            snapshot_id: pending.id,
+                            position: pending.data.len() as u64,
+                            max_bytes: FETCH_SNAPSHOT_MAX_BYTES,
+                        };
+                        Request::FetchSnapshot(FetchSnapshotRequest::metadata(
+                            &self.cluster_id,
+                            self.node_id,
+                            &fetch,
+                        ))
+                    }
+                    None => {
+                        let fetch = MetadataFetch {
+                            replica_id: self.node_id,
+                            current_leader_epoch: epoch,
+                            fetch_offset: self.log.end_offset(),
+                            last_fetched_epoch: self.log.last_epoch(),
+                            max_wait: FETCH_MAX_WAIT,
+                            max_bytes: FETCH_MAX_BYTES,
+                        };
+                        Request::Fetch(FetchRequestV16::metadata(
+                            &self.cluster_id,
+                            self.node_id,
+                            &fetch,
+                        ))
+                    }
+                };
+                self.send(leader_id, request);
+            }
+            _ => {}
+        }
+    }
+}
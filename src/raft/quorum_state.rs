@@ -0,0 +1,90 @@
+//! The `quorum-state` file, where a voter keeps the epoch it is in, who it
+//! voted for and who leads, so a restart can't make it vote twice in one
+//! epoch. It is JSON in Kafka's version 0 layout.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use super::METADATA_TOPIC;
+
+const QUORUM_STATE_FILE: &str = "quorum-state";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct ElectionState {
+    pub(super) epoch: i32,
+    pub(super) leader_id: Option<i32>,
+    pub(super) voted_id: Option<i32>,
+}
+
+pub(super) struct QuorumStateFile {
+    path: PathBuf,
+}
+
+impl QuorumStateFile {
+    pub(super) fn new(log_dir: &Path) -> Self {
+        Self {
+            path: log_dir
+                .join(format!("{}-0", METADATA_TOPIC))
+                .join(QUORUM_STATE_FILE),
+        }
+    }
+
+    /// The state last written, or epoch 0 with no vote if there is none.
+    pub(super) fn read(&self) -> Result<ElectionState> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(ElectionState::default())
+            }
+            Err(e) => return Err(e).with_context(|| format!("read '{}'", self.path.display())),
+        };
+        let field = |name| {
+            json_int(&contents, name)
+                .with_context(|| format!("'{}' has no valid '{}'", self.path.display(), name))
+        };
+        let node = |id: i64| (id >= 0).then_some(id as i32);
+        Ok(ElectionState {
+            epoch: field("leaderEpoch")? as i32,
+            leader_id: node(field("leaderId")?),
+            voted_id: node(field("votedId")?),
+        })
+    }
+
+    /// Replaces the file, synced to disk before it takes the old one's
+    /// place.
+    pub(super) fn write(&self, state: &ElectionState, voters: &[i32]) -> Result<()> {
+        let voters: Vec<_> = voters
+            .iter()
+            .map(|id| format!("{{\"voterId\":{}}}", id))
+            .collect();
+        let contents = format!(
+            "{{\"clusterId\":\"\",\"leaderId\":{},\"leaderEpoch\":{},\"votedId\":{},\"appliedOffset\":0,\"currentVoters\":[{}],\"data_version\":0}}",
+            state.leader_id.unwrap_or(-1),
+            state.epoch,
+            state.voted_id.unwrap_or(-1),
+            voters.join(",")
+        );
+        let tmp = self.path.with_extension("tmp");
+        File::create(&tmp)
+            .and_then(|mut f| f.write_all(contents.as_bytes()).and_then(|()| f.sync_all()))
+            .with_context(|| format!("write '{}'", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("replace '{}'", self.path.display()))
+    }
+}
+
+/// The integer value of the top-level `"name":` in a flat JSON object.
+fn json_int(json: &str, name: &str) -> Option<i64> {
+    let key = format!("\"{}\"", name);
+    let rest = json[json.find(&key)? + key.len()..].trim_start();
+    let rest = rest.strip_prefix(':')?.trim_start();
+    let end = rest
+        .find(|c: char| c != '-' && !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
@@ -0,0 +1,120 @@
+//! Snapshots of the metadata log: everything committed before some offset,
+//! kept beside the segment as `<end offset>-<epoch>.checkpoint` so the
+//! segment can start where the snapshot ends. A snapshot holds the log's
+//! batches as they were, so reading it and then the segment reads the
+//! whole log.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+const SNAPSHOT_SUFFIX: &str = ".checkpoint";
+
+/// A snapshot is named for the offset after its last record and the epoch
+/// of that record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SnapshotId {
+    pub end_offset: i64,
+    pub epoch: i32,
+}
+
+impl SnapshotId {
+    pub(super) fn path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!(
+            "{:020}-{:010}{}",
+            self.end_offset, self.epoch, SNAPSHOT_SUFFIX
+        ))
+    }
+
+    fn parse(file_name: &str) -> Option<Self> {
+        let (end_offset, epoch) = file_name.strip_suffix(SNAPSHOT_SUFFIX)?.split_once('-')?;
+        Some(Self {
+            end_offset: end_offset.parse().ok()?,
+            epoch: epoch.parse().ok()?,
+        })
+    }
+}
+
+/// The snapshots in `dir`, oldest first.
+pub(super) fn list(dir: &Path) -> Result<Vec<SnapshotId>> {
+    let mut ids = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ids),
+        Err(e) => return Err(e).with_context(|| format!("list '{}'", dir.display())),
+    };
+    for entry in entries {
+        if let Some(id) = entry?.file_name().to_str().and_then(SnapshotId::parse) {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// Writes a snapshot whole or not at all: into a temporary file first,
+/// renamed into place once synced.
+pub(super) fn write(dir: &Path, id: SnapshotId, parts: &[&[u8]]) -> Result<()> {
+    let path = id.path(dir);
+    let tmp = path.with_extension("checkpoint.part");
+    let mut file = File::create(&tmp).with_context(|| format!("create '{}'", tmp.display()))?;
+    for part in parts {
+        file.write_all(part)?;
+    }
+    file.sync_all()?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("rename to '{}'", path.display()))
+}
+
+/// Up to `max_bytes` of snapshot `id` from `position`, and the snapshot's
+/// size; `None` if there is no such snapshot.
+pub(super) fn read_chunk(
+    dir: &Path,
+    id: SnapshotId,
+    position: u64,
+    max_bytes: usize,
+) -> Result<Option<(u64, Vec<u8>)>> {
+    let path = id.path(dir);
+    let mut file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("open '{}'", path.display())),
+    };
+    let size = file.metadata()?.len();
+    let len = size.saturating_sub(position).min(max_bytes as u64) as usize;
+    let mut data = vec![0; len];
+    file.seek(SeekFrom::Start(position))?;
+    file.read_exact(&mut data)
+        .with_context(|| format!("read '{}'", path.display()))?;
+    Ok(Some((size, data)))
+}
+
+/// The snapshot that the metadata segment at `log_file` carries on from:
+/// the one ending where the segment starts, or the latest if the segment
+/// is empty. `None` when the segment starts at offset 0.
+pub fn read_preceding_snapshot(
+    log_file: &Path,
+    segment_start: Option<i64>,
+) -> Result<Option<Vec<u8>>> {
+    if segment_start == Some(0) {
+        return Ok(None);
+    }
+    let Some(dir) = log_file.parent() else {
+        return Ok(None);
+    };
+    let ids = list(dir)?;
+    let id = match segment_start {
+        Some(start) => ids.into_iter().find(|id| id.end_offset == start),
+        None => ids.into_iter().last(),
+    };
+    let Some(id) = id else {
+        return Ok(None);
+    };
+    let path = id.path(dir);
+    std::fs::read(&path)
+        .map(Some)
+        .with_context(|| format!("read '{}'", path.display()))
+}
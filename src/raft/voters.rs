@@ -0,0 +1,90 @@
+//! The VotersRecord control record of KIP-853, which sets the voter set from
+//! its offset on. The leader writes one for every voter added or removed,
+//! and every voter takes up the last one in its log, so a voter set that
+//! differs from `controller.quorum.voters` survives restarts.
+
+use anyhow::{ensure, Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::Voter;
+use crate::protocol::{
+    CompactArray, CompactNullableString, Deserialize, Serialize, TagBuffer, Uuid,
+};
+use crate::record_batch::{decode_control_batches, BatchRecord};
+
+/// The control record type of a VotersRecord.
+const VOTERS_TYPE: i16 = 6;
+/// Voters are written with one endpoint, under this listener name.
+const ENDPOINT_NAME: &str = "CONTROLLER";
+/// The `kraft.version` levels a voter this broker writes supports.
+const KRAFT_VERSIONS: (i16, i16) = (0, 1);
+
+/// A version 0 VotersRecord for `voters`.
+pub(super) fn voters_record(voters: &[Voter]) -> BatchRecord {
+    let mut key = BytesMut::new();
+    key.put_i16(0);
+    key.put_i16(VOTERS_TYPE);
+    let mut value = BytesMut::new();
+    value.put_i16(0);
+    value.put_slice(&integer_encoding::VarInt::encode_var_vec(
+        voters.len() as u32 + 1,
+    ));
+    for voter in voters {
+        value.put_i32(voter.id);
+        value.put_bytes(0, 16); // directory id
+        value.put_slice(&integer_encoding::VarInt::encode_var_vec(2u32)); // one endpoint
+        value.put(CompactNullableString(Some(ENDPOINT_NAME.to_string())).serialize());
+        value.put(CompactNullableString(Some(voter.host.clone())).serialize());
+        value.put_u16(voter.port);
+        value.put(TagBuffer::serialize());
+        value.put_i16(KRAFT_VERSIONS.0);
+        value.put_i16(KRAFT_VERSIONS.1);
+        value.put(TagBuffer::serialize());
+        value.put(TagBuffer::serialize());
+    }
+    value.put(TagBuffer::serialize());
+    BatchRecord {
+        key: Some(key.freeze()),
+        value: Some(value.freeze()),
+    }
+}
+
+/// The voter set of the last VotersRecord in `data`, whole batches of the
+/// metadata log, and its offset; `None` if there is none.
+pub(super) fn last_voters(data: Bytes) -> Result<Option<(i64, Vec<Voter>)>> {
+    let mut last = None;
+    for record in decode_control_batches(data)? {
+        let (Some(mut key), Some(mut value)) = (record.key, record.value) else {
+            continue;
+        };
+        if key.remaining() < 4 {
+            continue;
+        }
+        key.advance(2);
+        if key.get_i16() != VOTERS_TYPE {
+            continue;
+        }
+        ensure!(value.remaining() >= 2, "truncated VotersRecord");
+        value.advance(2);
+        let voters = CompactArray::deserialize_with(&mut value, |src| {
+            let id = src.try_get_i32()?;
+            Uuid::deserialize(src)?; // directory id
+            let endpoints = CompactArray::deserialize_with(src, |src| {
+                CompactNullableString::deserialize(src)?;
+                let host = CompactNullableString::deserialize(src)?
+                    .0
+                    .unwrap_or_default();
+                let port = src.try_get_u16()?;
+                TagBuffer::deserialize_fields(src)?;
+                Ok((host, port))
+            })?;
+            src.try_get_u32()?; // supported kraft.version range
+            TagBuffer::deserialize_fields(src)?;
+            let (host, port) = endpoints.into_iter().next().unwrap_or_default();
+            Ok(Voter { id, host, port })
+        })
+        .context("malformed VotersRecord")?;
+        last = Some((record.offset, voters));
+    }
+    Ok(last)
+}
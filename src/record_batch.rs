@@ -0,0 +1,430 @@
+use anyhow::{bail, ensure, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use integer_encoding::VarInt;
+
+/// The fixed part of a v2 batch, up to and including its record count.
+pub const BATCH_HEADER_SIZE: usize = 61;
+/// Base offset and batch length, which precede the length-counted part.
+pub const BATCH_LENGTH_OFFSET: usize = 12;
+const MAGIC_OFFSET: usize = 16;
+const CRC_OFFSET: usize = 17;
+/// Where the CRC-covered part of a batch starts, just after the CRC.
+const CRC_START: usize = 21;
+pub const MAGIC: i8 = 2;
+pub const COMPRESSION_MASK: i16 = 0x07;
+pub const TRANSACTIONAL_FLAG: i16 = 0x10;
+pub const CONTROL_FLAG: i16 = 0x20;
+
+/// One record to be written into a batch.
+pub struct BatchRecord {
+    pub key: Option<Bytes>,
+    pub value: Option<Bytes>,
+}
+
+/// The header fields of a v2 batch that say where it is and what it holds.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchHeader {
+    pub base_offset: i64,
+    pub batch_length: i32,
+    pub partition_leader_epoch: i32,
+    pub attributes: i16,
+    pub last_offset_delta: i32,
+    /// The latest timestamp of any record in the batch.
+    pub max_timestamp: i64,
+    pub producer_id: i64,
+    pub record_count: i32,
+}
+
+impl BatchHeader {
+    /// Reads the header at the start of `data`, or `None` if `data` doesn't
+    /// hold a whole batch.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        Self::parse_fixed(data).filter(|header| header.size() <= data.len())
+    }
+
+    /// Reads just the fixed header at the start of `data`, for a batch whose
+    /// records haven't been read.
+    pub fn parse_fixed(data: &[u8]) -> Option<Self> {
+        let header = data.get(..BATCH_HEADER_SIZE)?;
+        let i16_at = |at: usize| i16::from_be_bytes(header[at..at + 2].try_into().unwrap());
+        let i32_at = |at: usize| i32::from_be_bytes(header[at..at + 4].try_into().unwrap());
+        let i64_at = |at: usize| i64::from_be_bytes(header[at..at + 8].try_into().unwrap());
+        let parsed = Self {
+            base_offset: i64_at(0),
+            batch_length: i32_at(8),
+            partition_leader_epoch: i32_at(BATCH_LENGTH_OFFSET),
+            attributes: i16_at(CRC_START),
+            last_offset_delta: i32_at(23),
+            max_timestamp: i64_at(35),
+            producer_id: i64_at(43),
+            record_count: i32_at(57),
+        };
+        (parsed.size() >= BATCH_HEADER_SIZE).then_some(parsed)
+    }
+
+    /// The whole batch, header included.
+    pub fn size(&self) -> usize {
+        BATCH_LENGTH_OFFSET + self.batch_length.max(0) as usize
+    }
+
+    pub fn last_offset(&self) -> i64 {
+        self.base_offset + self.last_offset_delta as i64
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.attributes & CONTROL_FLAG != 0
+    }
+}
+
+/// The leader epochs a log's batches carry and the offset each starts at,
+/// as in Kafka's leader epoch cache. Batches written without an epoch, -1,
+/// start none.
+#[derive(Debug, Clone, Default)]
+pub struct LeaderEpochs {
+    starts: Vec<(i32, i64)>,
+    end_offset: i64,
+}
+
+impl LeaderEpochs {
+    /// Takes in the next batch of the log.
+    pub fn push(&mut self, header: &BatchHeader) {
+        let epoch = header.partition_leader_epoch;
+        if epoch >= 0 && self.starts.last().is_none_or(|(last, _)| epoch > *last) {
+            self.starts.push((epoch, header.base_offset));
+        }
+        self.end_offset = header.last_offset() + 1;
+    }
+
+    /// The epoch of the last batch with one, -1 if none has.
+    pub fn last_epoch(&self) -> i32 {
+        self.starts.last().map_or(-1, |(epoch, _)| *epoch)
+    }
+
+    /// The largest epoch no greater than `epoch`, and where the log's record
+    /// of it ends: the start of the next epoch, or the log end. `(-1, -1)` if
+    /// every epoch in the log is greater.
+    pub fn end_offset_for(&self, epoch: i32) -> (i32, i64) {
+        match self.starts.iter().position(|(start, _)| *start > epoch) {
+            Some(0) => (-1, -1),
+            Some(next) => (self.starts[next - 1].0, self.starts[next].1),
+            None if self.starts.is_empty() => (-1, -1),
+            None => (self.last_epoch(), self.end_offset),
+        }
+    }
+}
+
+/// A record read back out of a batch, its offset and timestamp resolved
+/// against the batch's.
+#[derive(Debug, Clone)]
+pub struct DecodedRecord {
+    pub offset: i64,
+    pub timestamp: i64,
+    pub key: Option<Bytes>,
+    pub value: Option<Bytes>,
+    pub headers: Vec<(String, Option<Bytes>)>,
+}
+
+/// The records in every whole batch in `data`, leaving out control batches.
+/// A batch cut short at the end, as a fetch's size limit may leave it, is
+/// left out too.
+pub fn decode_batches(data: Bytes) -> Result<Vec<DecodedRecord>> {
+    decode(data, false)
+}
+
+/// Like `decode_batches`, but the records of control batches only.
+pub fn decode_control_batches(data: Bytes) -> Result<Vec<DecodedRecord>> {
+    decode(data, true)
+}
+
+fn decode(mut data: Bytes, control: bool) -> Result<Vec<DecodedRecord>> {
+    let mut records = Vec::new();
+    while let Some(header) = BatchHeader::parse(&data) {
+        let mut batch = data.split_to(header.size());
+        if batch[16] as i8 != MAGIC {
+            bail!("unsupported batch magic {}", batch[16] as i8);
+        }
+        if header.attributes & COMPRESSION_MASK != 0 {
+            bail!("compressed batch at offset {}", header.base_offset);
+        }
+        if header.is_control() != control {
+            continue;
+        }
+        // Everything up to the first timestamp, then the max timestamp,
+        // producer id, epoch and base sequence.
+        batch.advance(27);
+        let base_timestamp = batch.get_i64();
+        batch.advance(8 + 8 + 2 + 4);
+        let count = batch.get_i32();
+        for _ in 0..count {
+            let length = read_varint(&mut batch)?;
+            ensure!(
+                length >= 0 && batch.remaining() >= length as usize,
+                "truncated record in batch at offset {}",
+                header.base_offset
+            );
+            let mut record = batch.split_to(length as usize);
+            ensure!(record.has_remaining(), "empty record");
+            record.advance(1); // attributes
+            let timestamp_delta = read_varint(&mut record)?;
+            let offset_delta = read_varint(&mut record)?;
+            let key = read_varint_bytes(&mut record)?;
+            let value = read_varint_bytes(&mut record)?;
+            let mut headers = Vec::new();
+            for _ in 0..read_varint(&mut record)? {
+                let name = read_varint_bytes(&mut record)?.unwrap_or_default();
+                let value = read_varint_bytes(&mut record)?;
+                headers.push((String::from_utf8_lossy(&name).into_owned(), value));
+            }
+            records.push(DecodedRecord {
+                offset: header.base_offset + offset_delta,
+                timestamp: base_timestamp + timestamp_delta,
+                key,
+                value,
+                headers,
+            });
+        }
+    }
+    Ok(records)
+}
+
+/// Whether `batch`, one whole batch, is a v2 batch whose CRC matches what
+/// it holds.
+pub fn is_intact(batch: &[u8]) -> bool {
+    if batch.len() < BATCH_HEADER_SIZE || batch[MAGIC_OFFSET] as i8 != MAGIC {
+        return false;
+    }
+    let crc = u32::from_be_bytes(batch[CRC_OFFSET..CRC_START].try_into().unwrap());
+    crc32c::crc32c(&batch[CRC_START..]) == crc
+}
+
+/// Gives the batch at the start of `batch` its place in a log: its base
+/// offset and the epoch of the leader that appended it. Neither is covered
+/// by the CRC, so the batch stays intact.
+pub fn place_batch(batch: &mut [u8], base_offset: i64, partition_leader_epoch: i32) {
+    batch[..8].copy_from_slice(&base_offset.to_be_bytes());
+    batch[BATCH_LENGTH_OFFSET..BATCH_LENGTH_OFFSET + 4]
+        .copy_from_slice(&partition_leader_epoch.to_be_bytes());
+}
+
+/// An uncompressed v2 record batch, without producer ids.
+pub fn encode_batch(
+    base_offset: i64,
+    partition_leader_epoch: i32,
+    attributes: i16,
+    records: &[BatchRecord],
+    timestamp: i64,
+) -> Bytes {
+    encode(
+        base_offset,
+        partition_leader_epoch,
+        attributes,
+        (-1, -1),
+        records,
+        timestamp,
+    )
+}
+
+/// A transaction marker: a control batch of one record that commits or
+/// aborts `producer_id`'s transaction. Its key is a version and the control
+/// type, 1 for commit and 0 for abort; its value a version and the
+/// coordinator epoch.
+pub fn encode_marker(producer_id: i64, producer_epoch: i16, commit: bool, timestamp: i64) -> Bytes {
+    let mut key = BytesMut::new();
+    key.put_i16(0);
+    key.put_i16(commit.into());
+    let mut value = BytesMut::new();
+    value.put_i16(0);
+    value.put_i32(0);
+    let record = BatchRecord {
+        key: Some(key.freeze()),
+        value: Some(value.freeze()),
+    };
+    encode(
+        0,
+        0,
+        TRANSACTIONAL_FLAG | CONTROL_FLAG,
+        (producer_id, producer_epoch),
+        &[record],
+        timestamp,
+    )
+}
+
+fn encode(
+    base_offset: i64,
+    partition_leader_epoch: i32,
+    attributes: i16,
+    (producer_id, producer_epoch): (i64, i16),
+    records: &[BatchRecord],
+    timestamp: i64,
+) -> Bytes {
+    let mut body = BytesMut::new();
+    for (delta, record) in records.iter().enumerate() {
+        let mut r = BytesMut::new();
+        r.put_i8(0); // attributes
+        put_varint(&mut r, 0); // timestamp delta
+        put_varint(&mut r, delta as i64);
+        put_varint_bytes(&mut r, record.key.as_ref());
+        put_varint_bytes(&mut r, record.value.as_ref());
+        put_varint(&mut r, 0); // headers
+        put_varint(&mut body, r.len() as i64);
+        body.put(r);
+    }
+
+    let mut b = BytesMut::with_capacity(BATCH_HEADER_SIZE + body.len());
+    b.put_i64(base_offset);
+    b.put_i32((BATCH_HEADER_SIZE - BATCH_LENGTH_OFFSET + body.len()) as i32);
+    b.put_i32(partition_leader_epoch);
+    b.put_i8(MAGIC);
+    b.put_u32(0); // crc, filled in below
+    b.put_i16(attributes);
+    b.put_i32(records.len() as i32 - 1);
+    b.put_i64(timestamp);
+    b.put_i64(timestamp);
+    b.put_i64(producer_id);
+    b.put_i16(producer_epoch);
+    b.put_i32(-1); // base sequence
+    b.put_i32(records.len() as i32);
+    b.put(body);
+    let crc = crc32c::crc32c(&b[CRC_START..]);
+    b[CRC_OFFSET..CRC_START].copy_from_slice(&crc.to_be_bytes());
+    b.freeze()
+}
+
+fn put_varint(b: &mut BytesMut, n: i64) {
+    b.put_slice(&n.encode_var_vec());
+}
+
+/// A varint length, -1 for null, then the bytes.
+fn put_varint_bytes(b: &mut BytesMut, bytes: Option<&Bytes>) {
+    match bytes {
+        Some(bytes) => {
+            put_varint(b, bytes.len() as i64);
+            b.put_slice(bytes);
+        }
+        None => put_varint(b, -1),
+    }
+}
+
+fn read_varint(src: &mut Bytes) -> Result<i64> {
+    let Some((n, read)) = i64::decode_var(src) else {
+        bail!("truncated varint");
+    };
+    src.advance(read);
+    Ok(n)
+}
+
+/// A varint length, -1 for null, then the bytes.
+fn read_varint_bytes(src: &mut Bytes) -> Result<Option<Bytes>> {
+    let len = read_varint(src)?;
+    if len < 0 {
+        return Ok(None);
+    }
+    ensure!(src.remaining() >= len as usize, "truncated record");
+    Ok(Some(src.split_to(len as usize)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(key: &str, value: Option<&str>) -> BatchRecord {
+        BatchRecord {
+            key: Some(Bytes::copy_from_slice(key.as_bytes())),
+            value: value.map(|v| Bytes::copy_from_slice(v.as_bytes())),
+        }
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let batch = encode_batch(5, 3, 0, &[record("a", Some("1")), record("b", None)], 1_000);
+        let header = BatchHeader::parse(&batch).unwrap();
+        assert_eq!(header.size(), batch.len());
+        assert_eq!(header.base_offset, 5);
+        assert_eq!(header.partition_leader_epoch, 3);
+        assert_eq!(header.last_offset_delta, 1);
+        assert_eq!(header.record_count, 2);
+        assert_eq!(header.last_offset(), 6);
+        assert!(is_intact(&batch));
+
+        let records = decode_batches(batch).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].offset, records[0].timestamp), (5, 1_000));
+        assert_eq!(records[0].value.as_deref(), Some(&b"1"[..]));
+        assert_eq!(records[1].offset, 6);
+        assert_eq!(records[1].key.as_deref(), Some(&b"b"[..]));
+        assert_eq!(records[1].value, None);
+    }
+
+    #[test]
+    fn placing_a_batch_keeps_its_crc() {
+        let mut batch = BytesMut::from(&encode_batch(0, -1, 0, &[record("k", Some("v"))], 0)[..]);
+        place_batch(&mut batch, 42, 7);
+        assert!(is_intact(&batch));
+        let header = BatchHeader::parse(&batch).unwrap();
+        assert_eq!((header.base_offset, header.partition_leader_epoch), (42, 7));
+        assert_eq!(decode_batches(batch.freeze()).unwrap()[0].offset, 42);
+    }
+
+    #[test]
+    fn flipped_bits_fail_the_crc() {
+        let mut batch = BytesMut::from(&encode_batch(0, 0, 0, &[record("k", Some("v"))], 0)[..]);
+        let last = batch.len() - 1;
+        batch[last] ^= 1;
+        assert!(!is_intact(&batch));
+    }
+
+    #[test]
+    fn leaves_out_a_batch_cut_short() {
+        let first = encode_batch(0, 0, 0, &[record("a", Some("1"))], 0);
+        let second = encode_batch(1, 0, 0, &[record("b", Some("2"))], 0);
+        let mut data = BytesMut::from(&first[..]);
+        data.extend_from_slice(&second[..second.len() - 1]);
+        assert!(BatchHeader::parse(&data[first.len()..]).is_none());
+        assert_eq!(decode_batches(data.freeze()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn separates_control_batches() {
+        let control = encode_batch(0, 0, CONTROL_FLAG, &[record("m", None)], 0);
+        assert!(BatchHeader::parse(&control).unwrap().is_control());
+        assert!(decode_batches(control.clone()).unwrap().is_empty());
+        assert_eq!(decode_control_batches(control).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn markers_are_transactional_control_batches() {
+        let marker = encode_marker(7, 2, true, 0);
+        let header = BatchHeader::parse(&marker).unwrap();
+        assert!(header.is_control() && header.attributes & TRANSACTIONAL_FLAG != 0);
+        assert_eq!(header.producer_id, 7);
+        assert!(is_intact(&marker));
+        let records = decode_control_batches(marker).unwrap();
+        assert_eq!(records[0].key.as_deref(), Some(&[0, 0, 0, 1][..]));
+    }
+
+    #[test]
+    fn rejects_compressed_batches() {
+        let batch = encode_batch(0, 0, 1, &[record("k", Some("v"))], 0);
+        assert!(decode_batches(batch).is_err());
+    }
+
+    #[test]
+    fn tracks_leader_epoch_starts() {
+        let mut epochs = LeaderEpochs::default();
+        for (base_offset, epoch) in [(0, 1), (2, 1), (4, 3), (6, -1)] {
+            let batch = encode_batch(
+                base_offset,
+                epoch,
+                0,
+                &[record("a", None), record("b", None)],
+                0,
+            );
+            epochs.push(&BatchHeader::parse(&batch).unwrap());
+        }
+        assert_eq!(epochs.last_epoch(), 3);
+        assert_eq!(epochs.end_offset_for(0), (-1, -1));
+        assert_eq!(epochs.end_offset_for(1), (1, 4));
+        assert_eq!(epochs.end_offset_for(2), (1, 4));
+        assert_eq!(epochs.end_offset_for(5), (3, 8));
+    }
+}
@@ -0,0 +1,616 @@
+//! Copies the partitions this broker follows from their leaders, in the
+//! manner of Kafka's `ReplicaFetcherThread`: one task per leader, fetching
+//! as a replica and appending whatever comes back to the local log.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use bytes::Bytes;
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+use crate::api::cluster_metadata::RecordBatches;
+use crate::api::fetch::{
+    FetchPurgatory, FetchRequestV16, FetchResponseV16, Partition, TopicRequest,
+};
+use crate::client::Connection;
+use crate::log_manager::{create_partition_dir, log_end_offset};
+use crate::metrics::Metrics;
+use crate::partition_rates::{ByteDirection, PartitionRates};
+use crate::protocol::{ApiKey, ErrorCode};
+use crate::record_batch::{BatchHeader, LeaderEpochs};
+
+pub const REPLICA_LAG_METRIC: &str = "kafka_replica_fetcher_lag";
+pub const REPLICA_FETCHED_BYTES_METRIC: &str = "kafka_replica_fetcher_bytes_total";
+pub const REPLICA_FETCH_ERRORS_METRIC: &str = "kafka_replica_fetcher_errors_total";
+
+const SEGMENT_FILE: &str = "00000000000000000000.log";
+/// The `SecurityProtocol` id of a plaintext endpoint in a broker
+/// registration; it is the only kind the fetchers can connect to.
+const PLAINTEXT: i16 = 0;
+
+/// How followers fetch, from the `replica.*` properties.
+#[derive(Debug, Clone)]
+pub struct ReplicaFetcherSettings {
+    /// The listener leaders are reached on, `inter.broker.listener.name`.
+    pub listener_name: String,
+    /// How long a leader may hold a fetch waiting for data,
+    /// `replica.fetch.wait.max.ms`. The fetchers also wait this long before
+    /// asking again when a fetch brings nothing new.
+    pub max_wait: Duration,
+    pub min_bytes: u32,
+    /// Per partition, `replica.fetch.max.bytes`.
+    pub max_bytes: i32,
+    pub response_max_bytes: u32,
+    /// The pause after a failed fetch, `replica.fetch.backoff.ms`.
+    pub backoff: Duration,
+    pub socket_timeout: Duration,
+}
+
+impl Default for ReplicaFetcherSettings {
+    fn default() -> Self {
+        Self {
+            listener_name: "PLAINTEXT".to_string(),
+            max_wait: Duration::from_millis(500),
+            min_bytes: 1,
+            max_bytes: 1024 * 1024,
+            response_max_bytes: 10 * 1024 * 1024,
+            backoff: Duration::from_millis(1_000),
+            socket_timeout: Duration::from_millis(30_000),
+        }
+    }
+}
+
+/// Where a followed partition stands, as its fetcher last saw it.
+#[derive(Debug, Clone)]
+pub struct FollowerState {
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub log_end_offset: i64,
+    /// -1 until the leader first answers.
+    pub leader_high_watermark: i64,
+    pub last_fetch: Option<Instant>,
+    pub error: Option<String>,
+}
+
+impl FollowerState {
+    pub fn lag(&self) -> i64 {
+        (self.leader_high_watermark - self.log_end_offset).max(0)
+    }
+}
+
+type FollowerStates = Arc<Mutex<BTreeMap<(String, i32), FollowerState>>>;
+
+/// The fetcher tasks, and the state of every partition they follow.
+pub struct ReplicaFetchers {
+    settings: ReplicaFetcherSettings,
+    node_id: i32,
+    log_dir: PathBuf,
+    metrics: Arc<Metrics>,
+    rates: Arc<PartitionRates>,
+    /// Consumer fetches waiting on the partitions followed here.
+    fetches: Arc<FetchPurgatory>,
+    states: FollowerStates,
+    running: Mutex<Running>,
+    stop: watch::Sender<bool>,
+}
+
+/// The followed partitions with the leader and epoch they were assigned
+/// for, and the tasks fetching them.
+#[derive(Default)]
+struct Running {
+    assignment: BTreeMap<(String, i32), (i32, i32)>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ReplicaFetchers {
+    /// Starts fetching every partition the metadata log lists this broker
+    /// as a replica of but not the leader. Partitions whose leader can't be
+    /// reached over `settings.listener_name` are skipped with a warning.
+    pub fn start(
+        settings: ReplicaFetcherSettings,
+        node_id: i32,
+        log_dir: &Path,
+        metadata_log_file: &Path,
+        metrics: Arc<Metrics>,
+        rates: Arc<PartitionRates>,
+        fetches: Arc<FetchPurgatory>,
+    ) -> Result<Self> {
+        let fetchers = Self {
+            settings,
+            node_id,
+            log_dir: log_dir.to_path_buf(),
+            metrics,
+            rates,
+            fetches,
+            states: FollowerStates::default(),
+            running: Mutex::new(Running::default()),
+            stop: watch::channel(false).0,
+        };
+        if !metadata_log_file.exists() {
+            return Ok(fetchers);
+        }
+        let metadata = RecordBatches::from_file(metadata_log_file)?;
+        fetchers.assign(&metadata)?;
+        Ok(fetchers)
+    }
+
+    /// Restarts the fetchers when `metadata` moves a followed partition to
+    /// another leader or epoch, or adds or removes one. The old fetchers are
+    /// stopped before the new ones start, so no log has two writers.
+    pub async fn update(&self, metadata: &RecordBatches) -> Result<()> {
+        let assignment = self.assignment(metadata);
+        let tasks = {
+            let mut running = self.running.lock().unwrap();
+            if running.assignment == assignment {
+                return Ok(());
+            }
+            std::mem::take(&mut running.tasks)
+        };
+        info!(partitions = assignment.len(), "followed partitions changed");
+        for task in tasks {
+            task.abort();
+            let _ = task.await;
+        }
+        self.assign(metadata)
+    }
+
+    /// The partitions this broker follows, with their leader and epoch.
+    fn assignment(&self, metadata: &RecordBatches) -> BTreeMap<(String, i32), (i32, i32)> {
+        let mut assignment = BTreeMap::new();
+        for topic in metadata.topics() {
+            let topic_name = topic.topic_name.0.clone().unwrap_or_default();
+            for p in metadata.partitions(&topic.topic_id) {
+                let leader_id = p.leader_id as i32;
+                if leader_id < 0
+                    || leader_id == self.node_id
+                    || !p.replicas.iter().any(|r| *r as i32 == self.node_id)
+                {
+                    continue;
+                }
+                let key = (topic_name.clone(), p.partition_id as i32);
+                assignment.insert(key, (leader_id, p.leader_epoch as i32));
+            }
+        }
+        assignment
+    }
+
+    /// Starts one fetcher per leader for the partitions `metadata` has this
+    /// broker follow, replacing every followed partition's state.
+    fn assign(&self, metadata: &RecordBatches) -> Result<()> {
+        let mut states = self.states.lock().unwrap();
+        states.clear();
+        let mut tasks = Vec::new();
+        let mut by_leader: BTreeMap<i32, Vec<FollowedPartition>> = BTreeMap::new();
+        for topic in metadata.topics() {
+            let topic_name = topic.topic_name.0.clone().unwrap_or_default();
+            for p in metadata.partitions(&topic.topic_id) {
+                let leader_id = p.leader_id as i32;
+                if leader_id < 0
+                    || leader_id == self.node_id
+                    || !p.replicas.iter().any(|r| *r as i32 == self.node_id)
+                {
+                    continue;
+                }
+                let dir = self
+                    .log_dir
+                    .join(format!("{}-{}", topic_name, p.partition_id));
+                create_partition_dir(&dir, &topic.topic_id.0)?;
+                let log_end_offset = log_end_offset(&dir)?.unwrap_or(0);
+                let (epochs, _) = scan_log(&dir)?;
+                states.insert(
+                    (topic_name.clone(), p.partition_id as i32),
+                    FollowerState {
+                        leader_id,
+                        leader_epoch: p.leader_epoch as i32,
+                        log_end_offset,
+                        leader_high_watermark: -1,
+                        last_fetch: None,
+                        error: None,
+                    },
+                );
+                by_leader
+                    .entry(leader_id)
+                    .or_default()
+                    .push(FollowedPartition {
+                        topic_name: topic_name.clone(),
+                        topic_id: topic.topic_id.0.clone(),
+                        partition_index: p.partition_id as i32,
+                        leader_epoch: p.leader_epoch as i32,
+                        dir,
+                        log_end_offset,
+                        last_fetched_epoch: epochs.last_epoch(),
+                    });
+            }
+        }
+
+        for (leader_id, partitions) in by_leader {
+            let endpoint = metadata
+                .brokers()
+                .find(|b| b.broker_id == leader_id)
+                .and_then(|b| b.endpoint(&self.settings.listener_name));
+            let (host, port) = match endpoint {
+                Some(e) if e.security_protocol == PLAINTEXT => (e.host.clone(), e.port),
+                unreachable => {
+                    let error = match unreachable {
+                        Some(_) => "leader's inter-broker listener isn't plaintext",
+                        None => "leader has no registered inter-broker endpoint",
+                    };
+                    warn!(
+                        leader = leader_id,
+                        listener = %self.settings.listener_name,
+                        "{}, not fetching from it",
+                        error
+                    );
+                    for p in &partitions {
+                        let key = (p.topic_name.clone(), p.partition_index);
+                        if let Some(state) = states.get_mut(&key) {
+                            state.error = Some(error.to_string());
+                        }
+                    }
+                    continue;
+                }
+            };
+            info!(
+                leader = leader_id,
+                address = %format!("{}:{}", host, port),
+                partitions = partitions.len(),
+                "starting replica fetcher"
+            );
+            let fetcher = LeaderFetcher {
+                client_id: format!("broker-{}-fetcher-{}", self.node_id, leader_id),
+                node_id: self.node_id,
+                leader_id,
+                host,
+                port,
+                settings: self.settings.clone(),
+                partitions,
+                states: self.states.clone(),
+                metrics: self.metrics.clone(),
+                rates: self.rates.clone(),
+                fetches: self.fetches.clone(),
+            };
+            tasks.push(tokio::spawn(fetcher.run(self.stop.subscribe())));
+        }
+        drop(states);
+        let mut running = self.running.lock().unwrap();
+        running.assignment = self.assignment(metadata);
+        running.tasks = tasks;
+        Ok(())
+    }
+
+    /// Each followed partition's state, by topic name and partition.
+    pub fn states(&self) -> BTreeMap<(String, i32), FollowerState> {
+        self.states.lock().unwrap().clone()
+    }
+
+    /// The state of one followed partition, if it is followed.
+    pub fn state(&self, topic_name: &str, partition: i32) -> Option<FollowerState> {
+        let key = (topic_name.to_string(), partition);
+        self.states.lock().unwrap().get(&key).cloned()
+    }
+
+    /// One line per followed partition.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for ((topic, partition), state) in self.states() {
+            let _ = writeln!(
+                out,
+                "topic={} partition={} leader={} leader_epoch={} log_end_offset={} \
+                 leader_high_watermark={} lag={} last_fetch_ms={} error={}",
+                topic,
+                partition,
+                state.leader_id,
+                state.leader_epoch,
+                state.log_end_offset,
+                state.leader_high_watermark,
+                state.lag(),
+                state
+                    .last_fetch
+                    .map_or(-1, |at| at.elapsed().as_millis() as i64),
+                state.error.as_deref().unwrap_or("none"),
+            );
+        }
+        out
+    }
+
+    /// Stops every fetcher and waits for them, so nothing is appended
+    /// while the logs are flushed. A fetcher is stopped between appends,
+    /// never partway through one.
+    pub async fn shutdown(&self) {
+        self.stop.send_replace(true);
+        let tasks = std::mem::take(&mut self.running.lock().unwrap().tasks);
+        for task in tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+struct FollowedPartition {
+    topic_name: String,
+    topic_id: String,
+    partition_index: i32,
+    leader_epoch: i32,
+    dir: PathBuf,
+    log_end_offset: i64,
+    /// The epoch of the last batch in the log, -1 if none has one.
+    last_fetched_epoch: i32,
+}
+
+impl FollowedPartition {
+    /// Appends the batches in `records` past the local log end and returns
+    /// how many bytes that was. Batches the log already has are skipped; the
+    /// rest must carry on from its end.
+    fn append(&mut self, records: &Bytes) -> Result<usize> {
+        let mut next_offset = self.log_end_offset;
+        let mut last_fetched_epoch = self.last_fetched_epoch;
+        let mut start = None;
+        let mut pos = 0;
+        while let Some(header) = BatchHeader::parse(&records[pos..]) {
+            if header.last_offset() >= next_offset {
+                ensure!(
+                    header.base_offset == next_offset,
+                    "leader sent a batch at offset {} for log end offset {}",
+                    header.base_offset,
+                    next_offset
+                );
+                start.get_or_insert(pos);
+                next_offset = header.last_offset() + 1;
+                if header.partition_leader_epoch >= 0 {
+                    last_fetched_epoch = header.partition_leader_epoch;
+                }
+            }
+            pos += header.size();
+        }
+        let Some(start) = start else {
+            return Ok(0);
+        };
+
+        let path = self.dir.join(SEGMENT_FILE);
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut f| f.write_all(&records[start..pos]))
+            .with_context(|| format!("append to '{}'", path.display()))?;
+        self.log_end_offset = next_offset;
+        self.last_fetched_epoch = last_fetched_epoch;
+        Ok(pos - start)
+    }
+
+    /// Cuts the log back to where it last matches the leader's, which the
+    /// leader says holds `epoch` up to `end_offset`, and returns the new log
+    /// end offset.
+    fn truncate(&mut self, epoch: i32, end_offset: i64) -> Result<i64> {
+        let (epochs, batch_ends) = scan_log(&self.dir)?;
+        let (local_epoch, local_end) = epochs.end_offset_for(epoch);
+        let truncate_to = if local_epoch == epoch {
+            end_offset.min(local_end)
+        } else {
+            local_end.max(0).min(end_offset)
+        };
+        let (log_end_offset, len) = batch_ends
+            .iter()
+            .take_while(|(end, _)| *end <= truncate_to)
+            .last()
+            .copied()
+            .unwrap_or((0, 0));
+        let path = self.dir.join(SEGMENT_FILE);
+        if path.exists() {
+            OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .and_then(|f| f.set_len(len))
+                .with_context(|| format!("truncate '{}'", path.display()))?;
+        }
+        let (epochs, _) = scan_log(&self.dir)?;
+        self.log_end_offset = log_end_offset;
+        self.last_fetched_epoch = epochs.last_epoch();
+        Ok(log_end_offset)
+    }
+}
+
+/// The leader epochs of the followed log in `dir`, and the offset after
+/// each of its batches with where the batch ends in the segment.
+fn scan_log(dir: &Path) -> Result<(LeaderEpochs, Vec<(i64, u64)>)> {
+    let path = dir.join(SEGMENT_FILE);
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).with_context(|| format!("read '{}'", path.display())),
+    };
+    let mut epochs = LeaderEpochs::default();
+    let mut batch_ends = Vec::new();
+    let mut pos = 0;
+    while let Some(header) = BatchHeader::parse(&data[pos..]) {
+        epochs.push(&header);
+        pos += header.size();
+        batch_ends.push((header.last_offset() + 1, pos as u64));
+    }
+    Ok((epochs, batch_ends))
+}
+
+/// Fetches one leader's partitions.
+struct LeaderFetcher {
+    client_id: String,
+    node_id: i32,
+    leader_id: i32,
+    host: String,
+    port: u16,
+    settings: ReplicaFetcherSettings,
+    partitions: Vec<FollowedPartition>,
+    states: FollowerStates,
+    metrics: Arc<Metrics>,
+    rates: Arc<PartitionRates>,
+    fetches: Arc<FetchPurgatory>,
+}
+
+impl LeaderFetcher {
+    async fn run(mut self, mut stop: watch::Receiver<bool>) {
+        let mut connection = None;
+        loop {
+            let fetch =
+                tokio::time::timeout(self.settings.socket_timeout, self.fetch(&mut connection));
+            let fetched = tokio::select! {
+                biased;
+                _ = stop.wait_for(|&stop| stop) => return,
+                fetched = fetch => fetched.unwrap_or_else(|_| Err(anyhow!("timed out"))),
+            };
+            let pause = match fetched {
+                Ok(0) => self.settings.max_wait,
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!(leader = self.leader_id, error = %e, "replica fetch failed");
+                    connection = None;
+                    self.metrics.incr_counter(
+                        REPLICA_FETCH_ERRORS_METRIC,
+                        &[("leader", &self.leader_id.to_string())],
+                        1,
+                    );
+                    self.report_error(&e.to_string());
+                    self.settings.backoff
+                }
+            };
+            tokio::select! {
+                _ = stop.wait_for(|&stop| stop) => return,
+                _ = tokio::time::sleep(pause) => {}
+            }
+        }
+    }
+
+    /// Fetches once and returns how many bytes were appended.
+    async fn fetch(&mut self, connection: &mut Option<Connection>) -> Result<usize> {
+        if connection.is_none() {
+            *connection = Some(Connection::connect(&self.host, self.port, &self.client_id).await?);
+        }
+        let mut topics: BTreeMap<&str, Vec<Partition>> = BTreeMap::new();
+        for p in &self.partitions {
+            let mut fetch = Partition::new(
+                p.partition_index,
+                p.leader_epoch,
+                p.log_end_offset,
+                self.settings.max_bytes,
+            );
+            fetch.last_fetched_epoch = p.last_fetched_epoch;
+            topics.entry(&p.topic_id).or_default().push(fetch);
+        }
+        let topics = topics
+            .into_iter()
+            .map(|(topic_id, partitions)| TopicRequest::new(topic_id.to_string(), partitions))
+            .collect();
+        let request = FetchRequestV16::replica(
+            self.node_id,
+            self.settings.max_wait,
+            self.settings.min_bytes,
+            self.settings.response_max_bytes,
+            topics,
+        );
+        let response: FetchResponseV16 = connection
+            .as_mut()
+            .unwrap()
+            .send(ApiKey::Fetch, 16, &request)
+            .await?;
+        if response.error_code != ErrorCode::None {
+            return Err(anyhow!("leader answered {:?}", response.error_code));
+        }
+
+        let mut appended = 0;
+        for (topic_id, res) in response.partitions() {
+            let Some(partition) = self
+                .partitions
+                .iter_mut()
+                .find(|p| p.topic_id == topic_id && p.partition_index == res.partition_index)
+            else {
+                continue;
+            };
+            let key = (partition.topic_name.clone(), partition.partition_index);
+            let mut states = self.states.lock().unwrap();
+            let state = states.get_mut(&key).unwrap();
+            state.last_fetch = Some(Instant::now());
+            if res.error_code != ErrorCode::None {
+                if state.error.is_none() {
+                    warn!(
+                        topic = %key.0,
+                        partition = key.1,
+                        leader = self.leader_id,
+                        error = ?res.error_code,
+                        "leader refused replica fetch"
+                    );
+                }
+                state.error = Some(format!("{:?}", res.error_code));
+                continue;
+            }
+            if let Some((epoch, end_offset)) = res.diverging_epoch {
+                let log_end_offset = match partition.truncate(epoch, end_offset) {
+                    Ok(log_end_offset) => log_end_offset,
+                    Err(e) => {
+                        state.error = Some(e.to_string());
+                        return Err(e);
+                    }
+                };
+                warn!(
+                    topic = %key.0,
+                    partition = key.1,
+                    epoch,
+                    offset = log_end_offset,
+                    "log diverges from the leader's, truncated"
+                );
+                state.log_end_offset = log_end_offset;
+                state.error = None;
+                continue;
+            }
+            let bytes = match partition.append(&res.records) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    state.error = Some(e.to_string());
+                    return Err(e);
+                }
+            };
+            let high_watermark_moved = state.leader_high_watermark != res.high_watermark;
+            state.log_end_offset = partition.log_end_offset;
+            state.leader_high_watermark = res.high_watermark;
+            state.error = None;
+            // A consumer fetching from this follower reads up to the high
+            // watermark.
+            if bytes > 0 || high_watermark_moved {
+                self.fetches.check_and_complete(&key);
+            }
+            let partition_label = key.1.to_string();
+            let labels = [("topic", key.0.as_str()), ("partition", &partition_label)];
+            self.metrics
+                .set_gauge(REPLICA_LAG_METRIC, &labels, state.lag());
+            if bytes > 0 {
+                self.metrics
+                    .incr_counter(REPLICA_FETCHED_BYTES_METRIC, &labels, bytes as u64);
+                self.rates.record(ByteDirection::In, &key.0, key.1, bytes);
+                debug!(
+                    topic = %key.0,
+                    partition = key.1,
+                    bytes,
+                    log_end_offset = partition.log_end_offset,
+                    "appended fetched batches"
+                );
+            }
+            appended += bytes;
+        }
+        Ok(appended)
+    }
+
+    /// Marks every partition of this leader with `error`.
+    fn report_error(&self, error: &str) {
+        let mut states = self.states.lock().unwrap();
+        for p in &self.partitions {
+            if let Some(state) = states.get_mut(&(p.topic_name.clone(), p.partition_index)) {
+                state.error = Some(error.to_string());
+            }
+        }
+    }
+}
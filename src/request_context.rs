@@ -0,0 +1,177 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc,
+    },
+};
+
+use tokio::sync::Notify;
+
+use crate::{
+    listener::ListenerType, protocol::HeaderV2, sasl::Authenticator, security::KafkaPrincipal,
+};
+
+/// Per-connection state shared by every request on that connection.
+pub struct Session {
+    pub listener_name: String,
+    pub listener_type: ListenerType,
+    pub principal: KafkaPrincipal,
+    pub client_address: SocketAddr,
+    /// The broker-side address the client connected to.
+    pub local_address: SocketAddr,
+    /// Present on SASL listeners; gates requests until authentication completes.
+    pub authenticator: Option<Authenticator>,
+    /// Cancelled once the connection closes.
+    pub cancellation: Cancellation,
+}
+
+impl Session {
+    pub fn new(
+        listener_name: String,
+        listener_type: ListenerType,
+        principal: KafkaPrincipal,
+        client_address: SocketAddr,
+        local_address: SocketAddr,
+    ) -> Self {
+        Self {
+            listener_name,
+            listener_type,
+            principal,
+            client_address,
+            local_address,
+            authenticator: None,
+            cancellation: Cancellation::default(),
+        }
+    }
+
+    pub fn with_authenticator(mut self, authenticator: Authenticator) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    pub fn authentication_failed(&self) -> bool {
+        self.authenticator.as_ref().is_some_and(|a| a.is_failed())
+    }
+
+    /// The SASL state, or `none` on listeners without SASL.
+    pub fn auth_state(&self) -> &'static str {
+        self.authenticator
+            .as_ref()
+            .map_or("none", Authenticator::state_name)
+    }
+
+    pub fn request_context(&self, header: HeaderV2) -> RequestContext {
+        RequestContext {
+            header,
+            listener_name: self.listener_name.clone(),
+            listener_type: self.listener_type,
+            principal: self.principal.clone(),
+            token_authenticated: self
+                .authenticator
+                .as_ref()
+                .is_some_and(Authenticator::is_token_authenticated),
+            client_address: self.client_address,
+            local_address: self.local_address,
+            cancellation: self.cancellation.clone(),
+            commit: RequestCommit::default(),
+        }
+    }
+}
+
+/// Everything known about a request besides its body: the decoded header and
+/// the connection it arrived on.
+pub struct RequestContext {
+    pub header: HeaderV2,
+    pub listener_name: String,
+    pub listener_type: ListenerType,
+    pub principal: KafkaPrincipal,
+    /// Whether the connection authenticated with a delegation token.
+    pub token_authenticated: bool,
+    pub client_address: SocketAddr,
+    pub local_address: SocketAddr,
+    /// Cancelled once nobody is left to read the response, so that long
+    /// reads and parked operations can give up early.
+    pub cancellation: Cancellation,
+    /// Settles whether the request times out or its handler makes its
+    /// changes, whichever comes first.
+    pub commit: RequestCommit,
+}
+
+/// Set once, when the connection a request came in on closes. Work on the
+/// blocking pool checks it between steps; async work can wait on it.
+#[derive(Clone, Default)]
+pub struct Cancellation(Arc<CancellationState>);
+
+#[derive(Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Release);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Resolves once cancelled.
+    pub async fn cancelled(&self) {
+        let notified = self.0.notify.notified();
+        tokio::pin!(notified);
+        // Registered before checking, so a cancel in between isn't missed.
+        notified.as_mut().enable();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// A request is either answered with REQUEST_TIMED_OUT or allowed to change
+/// anything, never both: a client told its request timed out retries it, and
+/// the first attempt landing too would duplicate it. A handler on the
+/// blocking pool can't be interrupted, so it asks before its first change.
+#[derive(Default)]
+pub struct RequestCommit(AtomicU8);
+
+const PENDING: u8 = 0;
+const COMMITTED: u8 = 1;
+const TIMED_OUT: u8 = 2;
+
+impl RequestCommit {
+    /// Called by a handler before its first change. `false` once the request
+    /// has timed out, and then it must change nothing.
+    pub fn commit(&self) -> bool {
+        self.settle(COMMITTED)
+    }
+
+    /// Called once the request runs out of time. `false` if its handler has
+    /// already started making changes, whose outcome is then waited for.
+    pub fn time_out(&self) -> bool {
+        self.settle(TIMED_OUT)
+    }
+
+    fn settle(&self, outcome: u8) -> bool {
+        match self
+            .0
+            .compare_exchange(PENDING, outcome, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => true,
+            Err(settled) => settled == outcome,
+        }
+    }
+}
+
+/// Cancels when dropped, however the connection's task ends.
+pub struct CancelOnDrop(pub Cancellation);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
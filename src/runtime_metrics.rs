@@ -0,0 +1,116 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::{runtime::Handle, task::JoinHandle};
+use tracing::{info, warn};
+
+use crate::metrics::Metrics;
+
+pub const RUNTIME_WORKERS_METRIC: &str = "kafka_server_runtime_workers";
+pub const RUNTIME_ALIVE_TASKS_METRIC: &str = "kafka_server_runtime_alive_tasks";
+pub const RUNTIME_GLOBAL_QUEUE_DEPTH_METRIC: &str = "kafka_server_runtime_global_queue_depth";
+pub const RUNTIME_SCHEDULING_DELAY_METRIC: &str = "kafka_server_runtime_scheduling_delay_us";
+pub const BLOCKING_QUEUED_METRIC: &str = "kafka_server_runtime_blocking_queued";
+pub const BLOCKING_RUNNING_METRIC: &str = "kafka_server_runtime_blocking_running";
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A wakeup this late means the workers are too busy to poll timers and
+/// sockets on time, usually because something blocks on a worker thread.
+const SLOW_SCHEDULING_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// Counts handler work sent to tokio's blocking pool, which tokio only
+/// reports with unstable features. Work queued but not running means the pool
+/// is saturated, typically by slow file I/O.
+#[derive(Default)]
+pub struct BlockingTasks {
+    queued: AtomicUsize,
+    running: AtomicUsize,
+}
+
+impl BlockingTasks {
+    /// Runs `f` on the blocking pool, as [`tokio::task::spawn_blocking`] does,
+    /// keeping count of it while it waits and while it runs.
+    pub fn spawn<F, R>(self: &Arc<Self>, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        let tasks = self.clone();
+        tokio::task::spawn_blocking(move || {
+            tasks.queued.fetch_sub(1, Ordering::Relaxed);
+            tasks.running.fetch_add(1, Ordering::Relaxed);
+            let _running = RunningGuard(&tasks.running);
+            f()
+        })
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Relaxed)
+    }
+}
+
+struct RunningGuard<'a>(&'a AtomicUsize);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Samples the runtime into `metrics` once a second.
+///
+/// Besides tokio's stable counters, it measures scheduling delay: how much
+/// later than asked a timer wakes up. A reactor starved by blocking work or
+/// overload shows up here before it shows up as request timeouts.
+pub async fn monitor_runtime(metrics: Arc<Metrics>, blocking: Arc<BlockingTasks>) {
+    let runtime = Handle::current().metrics();
+    let mut slow = false;
+    loop {
+        let start = Instant::now();
+        tokio::time::sleep(PROBE_INTERVAL).await;
+        let delay = start.elapsed().saturating_sub(PROBE_INTERVAL);
+
+        metrics.set_gauge(RUNTIME_WORKERS_METRIC, &[], runtime.num_workers() as i64);
+        metrics.set_gauge(
+            RUNTIME_ALIVE_TASKS_METRIC,
+            &[],
+            runtime.num_alive_tasks() as i64,
+        );
+        metrics.set_gauge(
+            RUNTIME_GLOBAL_QUEUE_DEPTH_METRIC,
+            &[],
+            runtime.global_queue_depth() as i64,
+        );
+        metrics.set_gauge(
+            RUNTIME_SCHEDULING_DELAY_METRIC,
+            &[],
+            delay.as_micros() as i64,
+        );
+        metrics.set_gauge(BLOCKING_QUEUED_METRIC, &[], blocking.queued() as i64);
+        metrics.set_gauge(BLOCKING_RUNNING_METRIC, &[], blocking.running() as i64);
+
+        // Logged on the way in and out of trouble, not on every sample.
+        match (slow, delay >= SLOW_SCHEDULING_THRESHOLD) {
+            (false, true) => warn!(
+                delay = ?delay,
+                global_queue_depth = runtime.global_queue_depth(),
+                blocking_queued = blocking.queued(),
+                "runtime is falling behind; worker threads may be blocked"
+            ),
+            (true, false) => info!(delay = ?delay, "runtime caught up"),
+            _ => {}
+        }
+        slow = delay >= SLOW_SCHEDULING_THRESHOLD;
+    }
+}
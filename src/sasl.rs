@@ -0,0 +1,281 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    config::Config,
+    delegation_token::DelegationTokenManager,
+    oauth::{OAuthBearerServer, OAuthBearerValidator, OAUTHBEARER_MECHANISM},
+    protocol::{ApiKey, ErrorCode},
+    scram::{ScramCredentials, ScramMechanism, ScramServer},
+    security::KafkaPrincipal,
+};
+
+pub const PLAIN_MECHANISM: &str = "PLAIN";
+
+/// The outcome of feeding one client message to a [`SaslServer`].
+pub enum SaslStep {
+    /// More round trips are needed; send these bytes back to the client.
+    Challenge(Vec<u8>),
+    /// The client authenticated. The bytes are the final server message.
+    Complete(KafkaPrincipal, Vec<u8>),
+}
+
+/// Server side of one SASL mechanism exchange.
+pub trait SaslServer: Send {
+    fn mechanism(&self) -> &'static str;
+
+    fn evaluate_response(&mut self, response: &[u8]) -> Result<SaslStep, String>;
+
+    /// Whether the client authenticated with a delegation token.
+    fn token_authenticated(&self) -> bool {
+        false
+    }
+}
+
+/// Where a connection is in the SASL exchange, mirroring Kafka's
+/// `SaslServerAuthenticator` states.
+pub enum AuthState {
+    /// Waiting for SaslHandshake (ApiVersions may come first).
+    Handshake,
+    /// A mechanism has been selected and SaslAuthenticate rounds are running.
+    Authenticate(Box<dyn SaslServer>),
+    Complete,
+    Failed,
+}
+
+/// Per-connection SASL state machine. Until authentication completes, only
+/// ApiVersions, SaslHandshake and SaslAuthenticate are accepted.
+pub struct Authenticator {
+    credentials: Arc<SaslCredentials>,
+    state: AuthState,
+    token_authenticated: bool,
+}
+
+/// Credentials and mechanisms shared by every SASL connection.
+pub struct SaslCredentials {
+    pub enabled_mechanisms: Vec<String>,
+    pub plain_users: HashMap<String, String>,
+    pub scram: Arc<ScramCredentials>,
+    /// Tokens SCRAM clients may authenticate with instead of a password.
+    pub delegation_tokens: Arc<DelegationTokenManager>,
+    /// Validates OAUTHBEARER tokens; `None` rejects them all.
+    pub oauthbearer: Option<Arc<OAuthBearerValidator>>,
+}
+
+impl SaslCredentials {
+    pub fn new(
+        config: &Config,
+        enabled_mechanisms: Vec<String>,
+        scram: Arc<ScramCredentials>,
+        delegation_tokens: Arc<DelegationTokenManager>,
+        oauthbearer: Option<Arc<OAuthBearerValidator>>,
+    ) -> Self {
+        Self {
+            enabled_mechanisms,
+            plain_users: config.sasl_users.clone(),
+            scram,
+            delegation_tokens,
+            oauthbearer,
+        }
+    }
+}
+
+impl Authenticator {
+    pub fn new(credentials: Arc<SaslCredentials>) -> Self {
+        Self {
+            credentials,
+            state: AuthState::Handshake,
+            token_authenticated: false,
+        }
+    }
+
+    pub fn enabled_mechanisms(&self) -> &[String] {
+        &self.credentials.enabled_mechanisms
+    }
+
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, AuthState::Complete)
+    }
+
+    /// Whether the connection authenticated with a delegation token, which
+    /// can't be used to get more of them.
+    pub fn is_token_authenticated(&self) -> bool {
+        self.token_authenticated
+    }
+
+    /// Once failed, the connection is closed after the current response.
+    pub fn is_failed(&self) -> bool {
+        matches!(self.state, AuthState::Failed)
+    }
+
+    /// A short name for the current state, for introspection.
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            AuthState::Handshake => "handshake",
+            AuthState::Authenticate(_) => "authenticating",
+            AuthState::Complete => "authenticated",
+            AuthState::Failed => "failed",
+        }
+    }
+
+    /// Whether a request for `api_key` may be processed in the current state.
+    pub fn allows(&self, api_key: ApiKey) -> bool {
+        match &self.state {
+            AuthState::Complete => !matches!(api_key, ApiKey::SaslHandshake),
+            AuthState::Handshake => {
+                matches!(api_key, ApiKey::ApiVersions | ApiKey::SaslHandshake)
+            }
+            AuthState::Authenticate(_) => matches!(api_key, ApiKey::SaslAuthenticate),
+            AuthState::Failed => false,
+        }
+    }
+
+    /// Handles SaslHandshake, selecting `mechanism` if it is enabled.
+    pub fn handshake(&mut self, mechanism: &str) -> ErrorCode {
+        if !matches!(self.state, AuthState::Handshake) {
+            self.state = AuthState::Failed;
+            return ErrorCode::IllegalSaslState;
+        }
+        if !self
+            .credentials
+            .enabled_mechanisms
+            .iter()
+            .any(|m| m == mechanism)
+        {
+            self.state = AuthState::Failed;
+            return ErrorCode::UnsupportedSaslMechanism;
+        }
+        match self.create_server(mechanism) {
+            Some(server) => {
+                self.state = AuthState::Authenticate(server);
+                ErrorCode::None
+            }
+            None => {
+                self.state = AuthState::Failed;
+                ErrorCode::UnsupportedSaslMechanism
+            }
+        }
+    }
+
+    /// Handles one SaslAuthenticate round.
+    pub fn authenticate(&mut self, auth_bytes: &[u8]) -> AuthenticateOutcome {
+        let AuthState::Authenticate(server) = &mut self.state else {
+            self.state = AuthState::Failed;
+            return AuthenticateOutcome::error(
+                ErrorCode::IllegalSaslState,
+                "SaslAuthenticate received before a successful SaslHandshake",
+            );
+        };
+        match server.evaluate_response(auth_bytes) {
+            Ok(SaslStep::Challenge(challenge)) => AuthenticateOutcome {
+                error_code: ErrorCode::None,
+                error_message: None,
+                auth_bytes: challenge,
+                principal: None,
+            },
+            Ok(SaslStep::Complete(principal, final_message)) => {
+                self.token_authenticated = server.token_authenticated();
+                self.state = AuthState::Complete;
+                AuthenticateOutcome {
+                    error_code: ErrorCode::None,
+                    error_message: None,
+                    auth_bytes: final_message,
+                    principal: Some(principal),
+                }
+            }
+            Err(message) => {
+                self.state = AuthState::Failed;
+                AuthenticateOutcome::error(ErrorCode::SaslAuthenticationFailed, &message)
+            }
+        }
+    }
+
+    /// Marks the exchange as failed, e.g. after a request arrived out of order.
+    pub fn fail(&mut self) {
+        self.state = AuthState::Failed;
+    }
+
+    fn create_server(&self, mechanism: &str) -> Option<Box<dyn SaslServer>> {
+        match mechanism {
+            PLAIN_MECHANISM => Some(Box::new(PlainServer {
+                credentials: self.credentials.clone(),
+            })),
+            OAUTHBEARER_MECHANISM => Some(Box::new(OAuthBearerServer::new(
+                self.credentials.oauthbearer.clone(),
+            ))),
+            _ => {
+                let scram = ScramMechanism::from_name(mechanism)?;
+                Some(Box::new(ScramServer::new(
+                    scram,
+                    self.credentials.scram.clone(),
+                    self.credentials.delegation_tokens.clone(),
+                )))
+            }
+        }
+    }
+}
+
+pub struct AuthenticateOutcome {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub auth_bytes: Vec<u8>,
+    pub principal: Option<KafkaPrincipal>,
+}
+
+impl AuthenticateOutcome {
+    fn error(error_code: ErrorCode, message: &str) -> Self {
+        Self {
+            error_code,
+            error_message: Some(message.to_string()),
+            auth_bytes: Vec::new(),
+            principal: None,
+        }
+    }
+}
+
+/// SASL/PLAIN (RFC 4616): a single `authzid \0 authcid \0 passwd` message.
+struct PlainServer {
+    credentials: Arc<SaslCredentials>,
+}
+
+impl SaslServer for PlainServer {
+    fn mechanism(&self) -> &'static str {
+        PLAIN_MECHANISM
+    }
+
+    fn evaluate_response(&mut self, response: &[u8]) -> Result<SaslStep, String> {
+        let message = std::str::from_utf8(response)
+            .map_err(|_| "PLAIN message is not valid UTF-8".to_string())?;
+        let parts: Vec<&str> = message.split('\0').collect();
+        let [authzid, username, password] = parts[..] else {
+            return Err("invalid PLAIN message".to_string());
+        };
+        if username.is_empty() {
+            return Err("PLAIN username must not be empty".to_string());
+        }
+        if !authzid.is_empty() && authzid != username {
+            return Err("PLAIN authorization id must match the username".to_string());
+        }
+        match self.credentials.plain_users.get(username) {
+            Some(expected) if expected == password => Ok(SaslStep::Complete(
+                KafkaPrincipal::user(username),
+                Vec::new(),
+            )),
+            _ => Err("Invalid username or password".to_string()),
+        }
+    }
+}
+
+/// Extracts the `user_<name>="<password>"` options of a PLAIN JAAS config.
+pub fn parse_jaas_users(jaas_config: &str) -> HashMap<String, String> {
+    let mut users = HashMap::new();
+    for token in jaas_config.split_whitespace() {
+        let token = token.trim_end_matches(';');
+        let Some((key, value)) = token.split_once('=') else {
+            continue;
+        };
+        if let Some(user) = key.strip_prefix("user_") {
+            users.insert(user.to_string(), value.trim_matches('"').to_string());
+        }
+    }
+    users
+}
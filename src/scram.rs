@@ -0,0 +1,614 @@
+use std::{collections::HashMap, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng, RngCore};
+use sha2::{Digest, Sha256, Sha512};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    cluster_metadata::RecordBatches,
+    delegation_token::DelegationTokenManager,
+    sasl::{SaslServer, SaslStep},
+    security::KafkaPrincipal,
+};
+
+/// Iteration count used when deriving credentials from configured passwords.
+pub const DEFAULT_SCRAM_ITERATIONS: u32 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScramMechanism {
+    Sha256,
+    Sha512,
+}
+
+impl ScramMechanism {
+    pub const ALL: [ScramMechanism; 2] = [ScramMechanism::Sha256, ScramMechanism::Sha512];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "SCRAM-SHA-256" => Some(Self::Sha256),
+            "SCRAM-SHA-512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "SCRAM-SHA-256",
+            Self::Sha512 => "SCRAM-SHA-512",
+        }
+    }
+
+    /// The mechanism's number in UserScramCredentialRecords.
+    pub fn type_id(&self) -> i8 {
+        match self {
+            Self::Sha256 => 1,
+            Self::Sha512 => 2,
+        }
+    }
+
+    pub fn from_type_id(type_id: i8) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.type_id() == type_id)
+    }
+
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => Sha256::digest(data).to_vec(),
+            Self::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    fn hmac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Self::Sha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC takes any key");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    fn salted_password(&self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        match self {
+            Self::Sha256 => {
+                let mut out = vec![0; 32];
+                pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut out);
+                out
+            }
+            Self::Sha512 => {
+                let mut out = vec![0; 64];
+                pbkdf2::pbkdf2_hmac::<Sha512>(password, salt, iterations, &mut out);
+                out
+            }
+        }
+    }
+}
+
+/// A stored SCRAM credential: never the password itself, only what the server
+/// needs to verify a client proof and prove its own identity.
+#[derive(Debug, Clone)]
+pub struct ScramCredential {
+    pub salt: Vec<u8>,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+    pub iterations: u32,
+}
+
+impl ScramCredential {
+    pub fn from_password(
+        mechanism: ScramMechanism,
+        password: &str,
+        salt: Vec<u8>,
+        iterations: u32,
+    ) -> Self {
+        let salted = mechanism.salted_password(password.as_bytes(), &salt, iterations);
+        let client_key = mechanism.hmac(&salted, b"Client Key");
+        Self {
+            stored_key: mechanism.hash(&client_key),
+            server_key: mechanism.hmac(&salted, b"Server Key"),
+            salt,
+            iterations,
+        }
+    }
+
+    /// Derives a credential with a fresh random salt.
+    pub fn generate(mechanism: ScramMechanism, password: &str, iterations: u32) -> Self {
+        let mut salt = vec![0; 32];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Self::from_password(mechanism, password, salt, iterations)
+    }
+}
+
+/// SCRAM credentials keyed by mechanism and user name.
+#[derive(Debug)]
+pub struct ScramCredentials {
+    credentials: HashMap<(ScramMechanism, String), ScramCredential>,
+    /// Derives the salts offered to users with no credential, so that each
+    /// is offered the same salt every time, as a real user would be.
+    unknown_user_key: [u8; 32],
+}
+
+impl Default for ScramCredentials {
+    fn default() -> Self {
+        let mut unknown_user_key = [0; 32];
+        rand::thread_rng().fill_bytes(&mut unknown_user_key);
+        Self {
+            credentials: HashMap::new(),
+            unknown_user_key,
+        }
+    }
+}
+
+impl ScramCredentials {
+    /// Derives credentials for every mechanism from plaintext passwords.
+    pub fn from_passwords(users: &HashMap<String, String>) -> Self {
+        let mut credentials = Self::default();
+        for (user, password) in users {
+            for mechanism in ScramMechanism::ALL {
+                let credential =
+                    ScramCredential::generate(mechanism, password, DEFAULT_SCRAM_ITERATIONS);
+                credentials.insert(mechanism, user, credential);
+            }
+        }
+        credentials
+    }
+
+    /// Adds the credentials in the metadata log's UserScramCredentialRecords,
+    /// such as those `format --add-scram` writes. Users that already have a
+    /// credential for a mechanism, from a configured password, keep it.
+    pub fn add_from_metadata(&mut self, metadata: &RecordBatches) {
+        let mut from_metadata = HashMap::new();
+        for record in metadata.scram_credentials() {
+            let Some(mechanism) = ScramMechanism::from_type_id(record.mechanism) else {
+                continue;
+            };
+            let credential = ScramCredential {
+                salt: record.salt.to_vec(),
+                stored_key: record.stored_key.to_vec(),
+                server_key: record.server_key.to_vec(),
+                iterations: record.iterations as u32,
+            };
+            from_metadata.insert((mechanism, record.name.clone()), credential);
+        }
+        for (key, credential) in from_metadata {
+            self.credentials.entry(key).or_insert(credential);
+        }
+    }
+
+    pub fn insert(&mut self, mechanism: ScramMechanism, user: &str, credential: ScramCredential) {
+        self.credentials
+            .insert((mechanism, user.to_string()), credential);
+    }
+
+    pub fn get(&self, mechanism: ScramMechanism, user: &str) -> Option<&ScramCredential> {
+        self.credentials.get(&(mechanism, user.to_string()))
+    }
+
+    /// A credential no password matches, offered to a user who has none so
+    /// that the server-first message doesn't tell them apart from real users.
+    fn unknown_user(&self, mechanism: ScramMechanism, user: &str) -> ScramCredential {
+        let salt = mechanism.hmac(
+            &self.unknown_user_key,
+            format!("{}:{}", mechanism.name(), user).as_bytes(),
+        );
+        let mut stored_key = vec![0; salt.len()];
+        rand::thread_rng().fill_bytes(&mut stored_key);
+        ScramCredential {
+            salt: salt[..32].to_vec(),
+            server_key: stored_key.clone(),
+            stored_key,
+            iterations: DEFAULT_SCRAM_ITERATIONS,
+        }
+    }
+}
+
+enum ScramState {
+    ReceiveClientFirst,
+    ReceiveClientFinal {
+        /// `None` for a user or token that doesn't exist, which is only
+        /// refused at client-final so as not to be told apart before.
+        principal: Option<KafkaPrincipal>,
+        credential: ScramCredential,
+        client_first: String,
+        server_first: String,
+        nonce: String,
+    },
+    Done,
+}
+
+/// Server side of RFC 5802 SCRAM without channel binding. A client-first
+/// message with the `tokenauth=true` extension authenticates with a
+/// delegation token, named by the user name, as the token's owner.
+pub struct ScramServer {
+    mechanism: ScramMechanism,
+    credentials: Arc<ScramCredentials>,
+    delegation_tokens: Arc<DelegationTokenManager>,
+    state: ScramState,
+    token_authenticated: bool,
+}
+
+impl ScramServer {
+    pub fn new(
+        mechanism: ScramMechanism,
+        credentials: Arc<ScramCredentials>,
+        delegation_tokens: Arc<DelegationTokenManager>,
+    ) -> Self {
+        Self {
+            mechanism,
+            credentials,
+            delegation_tokens,
+            state: ScramState::ReceiveClientFirst,
+            token_authenticated: false,
+        }
+    }
+
+    fn client_first(&mut self, message: &str) -> Result<SaslStep, String> {
+        let server_nonce: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        self.client_first_with_nonce(message, &server_nonce)
+    }
+
+    fn client_first_with_nonce(
+        &mut self,
+        message: &str,
+        server_nonce: &str,
+    ) -> Result<SaslStep, String> {
+        let (gs2_header, client_first_bare) = split_gs2_header(message)?;
+        let mut parts = gs2_header.split(',');
+        let cbind_flag = parts.next().unwrap_or_default();
+        let authzid = parts.next().unwrap_or_default();
+        if cbind_flag != "n" && cbind_flag != "y" {
+            return Err("SCRAM channel binding is not supported".to_string());
+        }
+
+        let attrs = parse_attributes(client_first_bare)?;
+        let username = unescape_username(attrs.get("n").ok_or("missing SCRAM username")?)?;
+        let client_nonce = attrs.get("r").ok_or("missing SCRAM client nonce")?;
+        if let Some(authzid) = authzid.strip_prefix("a=") {
+            if unescape_username(authzid)? != username {
+                return Err("SCRAM authorization id must match the username".to_string());
+            }
+        }
+
+        let token_auth = attrs.get("tokenauth") == Some(&"true");
+        let found = if token_auth {
+            self.delegation_tokens
+                .scram_credential(self.mechanism, &username)
+        } else {
+            self.credentials
+                .get(self.mechanism, &username)
+                .cloned()
+                .map(|credential| (credential, KafkaPrincipal::user(username.clone())))
+        };
+        let (credential, principal) = match found {
+            Some((credential, principal)) => (credential, Some(principal)),
+            None => (
+                self.credentials.unknown_user(self.mechanism, &username),
+                None,
+            ),
+        };
+        self.token_authenticated = token_auth;
+        let nonce = format!("{}{}", client_nonce, server_nonce);
+        let server_first = format!(
+            "r={},s={},i={}",
+            nonce,
+            BASE64.encode(&credential.salt),
+            credential.iterations
+        );
+
+        let challenge = server_first.clone().into_bytes();
+        self.state = ScramState::ReceiveClientFinal {
+            principal,
+            credential,
+            client_first: message.to_string(),
+            server_first,
+            nonce,
+        };
+        Ok(SaslStep::Challenge(challenge))
+    }
+
+    fn client_final(&mut self, message: &str) -> Result<SaslStep, String> {
+        let ScramState::ReceiveClientFinal {
+            principal,
+            credential,
+            client_first,
+            server_first,
+            nonce,
+        } = std::mem::replace(&mut self.state, ScramState::Done)
+        else {
+            return Err("unexpected SCRAM message".to_string());
+        };
+
+        let (without_proof, proof) = message
+            .rsplit_once(",p=")
+            .ok_or("missing SCRAM client proof")?;
+        let attrs = parse_attributes(without_proof)?;
+        if attrs.get("r") != Some(&nonce.as_str()) {
+            return Err("Invalid SCRAM nonce".to_string());
+        }
+        // Without channel binding, `c=` carries the gs2-header back.
+        let (gs2_header, client_first_bare) = split_gs2_header(&client_first)?;
+        if attrs.get("c") != Some(&BASE64.encode(gs2_header).as_str()) {
+            return Err("Invalid SCRAM channel binding".to_string());
+        }
+        let proof = BASE64
+            .decode(proof)
+            .map_err(|_| "invalid SCRAM client proof encoding")?;
+
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+        let client_signature = self
+            .mechanism
+            .hmac(&credential.stored_key, auth_message.as_bytes());
+        if proof.len() != client_signature.len() {
+            return Err("Authentication failed: Invalid user credentials".to_string());
+        }
+        let client_key: Vec<u8> = proof
+            .iter()
+            .zip(&client_signature)
+            .map(|(p, s)| p ^ s)
+            .collect();
+        let matches: bool = self
+            .mechanism
+            .hash(&client_key)
+            .ct_eq(&credential.stored_key)
+            .into();
+        let principal = match principal {
+            Some(principal) if matches => principal,
+            _ if self.token_authenticated => {
+                return Err("Authentication failed: Invalid delegation token".to_string())
+            }
+            _ => return Err("Authentication failed: Invalid user credentials".to_string()),
+        };
+
+        let server_signature = self
+            .mechanism
+            .hmac(&credential.server_key, auth_message.as_bytes());
+        let server_final = format!("v={}", BASE64.encode(server_signature));
+        Ok(SaslStep::Complete(principal, server_final.into_bytes()))
+    }
+}
+
+impl SaslServer for ScramServer {
+    fn mechanism(&self) -> &'static str {
+        self.mechanism.name()
+    }
+
+    fn evaluate_response(&mut self, response: &[u8]) -> Result<SaslStep, String> {
+        let message =
+            std::str::from_utf8(response).map_err(|_| "SCRAM message is not valid UTF-8")?;
+        match self.state {
+            ScramState::ReceiveClientFirst => self.client_first(message),
+            ScramState::ReceiveClientFinal { .. } => self.client_final(message),
+            ScramState::Done => Err("SCRAM exchange already completed".to_string()),
+        }
+    }
+
+    fn token_authenticated(&self) -> bool {
+        self.token_authenticated
+    }
+}
+
+/// Splits a client-first message into its gs2-header, `n,,` or `n,a=authzid,`
+/// with the trailing comma, and the client-first-bare after it.
+fn split_gs2_header(message: &str) -> Result<(&str, &str), String> {
+    let authzid_end = message
+        .match_indices(',')
+        .nth(1)
+        .map(|(i, _)| i + 1)
+        .ok_or("invalid SCRAM client-first message")?;
+    Ok(message.split_at(authzid_end))
+}
+
+/// Splits `k=v,k=v` SCRAM attributes. Values may themselves contain `=`.
+fn parse_attributes(message: &str) -> Result<HashMap<&str, &str>, String> {
+    message
+        .split(',')
+        .map(|attr| {
+            attr.split_once('=')
+                .ok_or_else(|| format!("invalid SCRAM attribute '{}'", attr))
+        })
+        .collect()
+}
+
+/// Reverses the `=2C`/`=3D` escaping of `,` and `=` in SCRAM user names.
+fn unescape_username(name: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(idx) = rest.find('=') {
+        unescaped.push_str(&rest[..idx]);
+        match rest.get(idx..idx + 3) {
+            Some("=2C") => unescaped.push(','),
+            Some("=3D") => unescaped.push('='),
+            _ => return Err("invalid SCRAM username encoding".to_string()),
+        }
+        rest = &rest[idx + 3..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(mechanism: ScramMechanism, user: &str, credential: ScramCredential) -> ScramServer {
+        let mut credentials = ScramCredentials::default();
+        credentials.insert(mechanism, user, credential);
+        ScramServer::new(
+            mechanism,
+            Arc::new(credentials),
+            Arc::new(DelegationTokenManager::new(None)),
+        )
+    }
+
+    fn challenge(step: SaslStep) -> String {
+        match step {
+            SaslStep::Challenge(challenge) => String::from_utf8(challenge).unwrap(),
+            SaslStep::Complete(..) => panic!("completed early"),
+        }
+    }
+
+    /// The client-final message a client with `password` sends in answer to
+    /// `server_first`, with `c=` set to `channel_binding`.
+    fn client_final(
+        mechanism: ScramMechanism,
+        password: &str,
+        client_first_bare: &str,
+        server_first: &str,
+        channel_binding: &str,
+    ) -> String {
+        let attrs = parse_attributes(server_first).unwrap();
+        let salt = BASE64.decode(attrs["s"]).unwrap();
+        let iterations = attrs["i"].parse().unwrap();
+        let salted = mechanism.salted_password(password.as_bytes(), &salt, iterations);
+        let client_key = mechanism.hmac(&salted, b"Client Key");
+        let without_proof = format!("c={},r={}", channel_binding, attrs["r"]);
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+        let signature = mechanism.hmac(&mechanism.hash(&client_key), auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(&signature)
+            .map(|(k, s)| k ^ s)
+            .collect();
+        format!("{},p={}", without_proof, BASE64.encode(proof))
+    }
+
+    #[test]
+    fn answers_the_rfc_7677_example() {
+        // RFC 7677 section 3; RFC 5802's own example is SCRAM-SHA-1, which
+        // Kafka doesn't offer.
+        let salt = BASE64.decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap();
+        let credential =
+            ScramCredential::from_password(ScramMechanism::Sha256, "pencil", salt, 4096);
+        let mut server = server(ScramMechanism::Sha256, "user", credential);
+
+        let server_first = server
+            .client_first_with_nonce(
+                "n,,n=user,r=rOprNGfwEbeRWgbNEkqO",
+                "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0",
+            )
+            .map(challenge)
+            .unwrap();
+        assert_eq!(
+            server_first,
+            "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+        );
+        let step = server
+            .evaluate_response(
+                b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                  p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=",
+            )
+            .unwrap();
+        let SaslStep::Complete(principal, server_final) = step else {
+            panic!("not completed");
+        };
+        assert_eq!(principal, KafkaPrincipal::user("user"));
+        assert_eq!(
+            server_final,
+            b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="
+        );
+    }
+
+    #[test]
+    fn authenticates_with_either_mechanism() {
+        for mechanism in ScramMechanism::ALL {
+            let credential = ScramCredential::generate(mechanism, "secret", 4096);
+            let mut server = server(mechanism, "alice", credential);
+            let server_first = challenge(server.evaluate_response(b"n,,n=alice,r=abc").unwrap());
+            let client_final =
+                client_final(mechanism, "secret", "n=alice,r=abc", &server_first, "biws");
+            let step = server.evaluate_response(client_final.as_bytes()).unwrap();
+            assert!(matches!(step, SaslStep::Complete(..)), "{:?}", mechanism);
+        }
+    }
+
+    #[test]
+    fn refuses_a_wrong_password() {
+        let credential = ScramCredential::generate(ScramMechanism::Sha256, "secret", 4096);
+        let mut server = server(ScramMechanism::Sha256, "alice", credential);
+        let server_first = challenge(server.evaluate_response(b"n,,n=alice,r=abc").unwrap());
+        let client_final = client_final(
+            ScramMechanism::Sha256,
+            "guess",
+            "n=alice,r=abc",
+            &server_first,
+            "biws",
+        );
+        assert!(server.evaluate_response(client_final.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn refuses_channel_binding_that_does_not_match_the_gs2_header() {
+        let credential = ScramCredential::generate(ScramMechanism::Sha256, "secret", 4096);
+        let mut server = server(ScramMechanism::Sha256, "alice", credential);
+        let server_first = challenge(
+            server
+                .evaluate_response(b"n,a=alice,n=alice,r=abc")
+                .unwrap(),
+        );
+        // "biws" is "n,,", not the "n,a=alice," the client started with.
+        let client_final = client_final(
+            ScramMechanism::Sha256,
+            "secret",
+            "n=alice,r=abc",
+            &server_first,
+            "biws",
+        );
+        let err = server
+            .evaluate_response(client_final.as_bytes())
+            .err()
+            .unwrap();
+        assert!(err.contains("channel binding"), "{}", err);
+    }
+
+    #[test]
+    fn answers_an_unknown_user_like_a_known_one_then_fails_at_client_final() {
+        let credential = ScramCredential::generate(ScramMechanism::Sha256, "secret", 4096);
+        let credentials = Arc::new({
+            let mut credentials = ScramCredentials::default();
+            credentials.insert(ScramMechanism::Sha256, "alice", credential);
+            credentials
+        });
+        let first = |user: &str| {
+            let mut server = ScramServer::new(
+                ScramMechanism::Sha256,
+                credentials.clone(),
+                Arc::new(DelegationTokenManager::new(None)),
+            );
+            let message = format!("n,,n={},r=abc", user);
+            let server_first = challenge(server.evaluate_response(message.as_bytes()).unwrap());
+            (server, server_first)
+        };
+
+        let (mut server, server_first) = first("mallory");
+        let attrs = parse_attributes(&server_first).unwrap();
+        assert_eq!(BASE64.decode(attrs["s"]).unwrap().len(), 32);
+        assert_eq!(attrs["i"], DEFAULT_SCRAM_ITERATIONS.to_string());
+        // Asked again, the same salt, as for a user who exists.
+        let (_, again) = first("mallory");
+        assert_eq!(parse_attributes(&again).unwrap()["s"], attrs["s"]);
+        assert_ne!(parse_attributes(&first("eve").1).unwrap()["s"], attrs["s"]);
+
+        let client_final = client_final(
+            ScramMechanism::Sha256,
+            "secret",
+            "n=mallory,r=abc",
+            &server_first,
+            "biws",
+        );
+        let err = server
+            .evaluate_response(client_final.as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err, "Authentication failed: Invalid user credentials");
+    }
+}
@@ -0,0 +1,68 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Result};
+
+pub const USER_PRINCIPAL_TYPE: &str = "User";
+
+/// The identity a connection authenticated as, e.g. `User:alice`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KafkaPrincipal {
+    pub principal_type: String,
+    pub name: String,
+}
+
+impl KafkaPrincipal {
+    pub fn user(name: impl Into<String>) -> Self {
+        Self {
+            principal_type: USER_PRINCIPAL_TYPE.to_string(),
+            name: name.into(),
+        }
+    }
+
+    /// The principal of connections that have not authenticated.
+    pub fn anonymous() -> Self {
+        Self::user("ANONYMOUS")
+    }
+}
+
+impl Display for KafkaPrincipal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.principal_type, self.name)
+    }
+}
+
+impl FromStr for KafkaPrincipal {
+    type Err = anyhow::Error;
+
+    /// Parses `<type>:<name>`, e.g. `User:alice`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some((principal_type, name)) if !principal_type.is_empty() && !name.is_empty() => {
+                Ok(Self {
+                    principal_type: principal_type.to_string(),
+                    name: name.to_string(),
+                })
+            }
+            _ => Err(anyhow!("invalid principal '{}', expected <type>:<name>", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_prints_type_and_name() {
+        let principal: KafkaPrincipal = "User:CN=alice,O=example".parse().unwrap();
+        assert_eq!(principal, KafkaPrincipal::user("CN=alice,O=example"));
+        assert_eq!(principal.to_string(), "User:CN=alice,O=example");
+    }
+
+    #[test]
+    fn rejects_principals_missing_a_type_or_name() {
+        for s in ["alice", ":alice", "User:", ""] {
+            assert!(s.parse::<KafkaPrincipal>().is_err(), "{s:?}");
+        }
+    }
+}
@@ -0,0 +1,1164 @@
+//! The broker itself: everything it starts, the listeners it accepts
+//! connections on, and how each connection's requests are read, handled and
+//! answered. The binary only parses its arguments and sets up logging.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::ErrorKind,
+    net::SocketAddr,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use bytes::{Buf, Bytes};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, watch},
+    task::{AbortHandle, JoinSet},
+    time::MissedTickBehavior,
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, debug_span, error, info, info_span, trace, warn, Instrument, Span};
+
+use crate::*;
+
+/// How long in-flight requests get to complete once shutdown begins.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the config file is checked for changes.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the metadata log is checked for new records. Handlers see a
+/// change once it is published, so this is how stale they may be.
+const METADATA_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// State shared by every connection.
+struct Server {
+    /// Swapped when the config file is reloaded.
+    config: Arc<SharedConfig>,
+    metrics: Arc<Metrics>,
+    /// Bounds request bytes held across all connections.
+    request_pool: Arc<MemoryPool>,
+    /// Bounds request and response bytes in flight across all connections.
+    in_flight_memory: Arc<MemoryBudget>,
+    /// Where handlers run, off the async workers.
+    blocking: Arc<BlockingTasks>,
+    produce_quotas: Arc<ClientQuotaManager>,
+    fetch_quotas: Arc<ClientQuotaManager>,
+    connections: Arc<ConnectionRegistry>,
+    /// The handlers and the middleware around them.
+    apis: ApiRegistry,
+}
+
+impl Server {
+    fn config(&self) -> Arc<Config> {
+        self.config.get()
+    }
+}
+
+/// A configured listener and the security it applies to its connections.
+struct Listener {
+    endpoint: Endpoint,
+    listener_type: ListenerType,
+    tls_acceptor: Option<TlsAcceptor>,
+    sasl_credentials: Option<Arc<SaslCredentials>>,
+}
+
+impl Listener {
+    fn new(
+        config: &Config,
+        endpoint: &Endpoint,
+        scram: Arc<ScramCredentials>,
+        delegation_tokens: Arc<DelegationTokenManager>,
+        oauthbearer: Option<Arc<OAuthBearerValidator>>,
+    ) -> Result<Self> {
+        let tls_acceptor = if endpoint.security_protocol.uses_tls() {
+            let settings = config.ssl_settings(&endpoint.listener_name)?;
+            Some(build_tls_acceptor(&settings)?)
+        } else {
+            None
+        };
+        let sasl_credentials = if endpoint.security_protocol.uses_sasl() {
+            let mechanisms = config.sasl_enabled_mechanisms(endpoint);
+            Some(Arc::new(SaslCredentials::new(
+                config,
+                mechanisms,
+                scram,
+                delegation_tokens,
+                oauthbearer,
+            )))
+        } else {
+            None
+        };
+        Ok(Self {
+            endpoint: endpoint.clone(),
+            listener_type: config.listener_type(&endpoint.listener_name),
+            tls_acceptor,
+            sasl_credentials,
+        })
+    }
+}
+
+struct Accepted {
+    stream: ClientStream,
+    peer: SocketAddr,
+    slot: ConnectionSlot,
+    listener: Arc<Listener>,
+}
+/// Runs a broker with `config` until `shutdown` resolves, then stops
+/// accepting, drains open connections and stops everything it started.
+/// When `config_path` is given, the file is reloaded when it changes.
+pub async fn run_broker(
+    mut config: Config,
+    config_path: Option<PathBuf>,
+    log_level: Arc<LogLevel>,
+    shutdown: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    let sockets = bind_listeners(&mut config).await?;
+    serve_listeners(config, sockets, config_path, log_level, shutdown).await
+}
+
+/// Binds every listener in `config.listeners`, in order. A listener on port
+/// 0 gets its port from the OS, which is written back into `config`: into
+/// the listener, its advertised listeners on port 0 and, for a controller
+/// listener, this node's own entry in `controller.quorum.voters`.
+pub async fn bind_listeners(config: &mut Config) -> Result<Vec<ListenerSocket>> {
+    let mut sockets = Vec::new();
+    for i in 0..config.listeners.len() {
+        let endpoint = &config.listeners[i];
+        // KIP-797: an IPv4 listener on the same port owns the IPv4 traffic.
+        let v6_only = endpoint.port != 0
+            && config.listeners.iter().any(|other| {
+                other.port == endpoint.port && other.ip().is_some_and(|ip| ip.is_ipv4())
+            });
+        let socket = config
+            .socket_options
+            .bind(endpoint, v6_only)
+            .await
+            .with_context(|| format!("bind listener {}", endpoint))?;
+        if endpoint.port == 0 && endpoint.unix_path.is_none() {
+            let port = socket.local_port()?;
+            let name = endpoint.listener_name.clone();
+            config.listeners[i].port = port;
+            for advertised in &mut config.advertised_listeners {
+                if advertised.listener_name == name && advertised.port == 0 {
+                    advertised.port = port;
+                }
+            }
+            if config.controller_listener_names.contains(&name) {
+                let node_id = config.node_id;
+                let voters = config
+                    .quorum
+                    .iter_mut()
+                    .flat_map(|quorum| quorum.voters.iter_mut())
+                    .chain(config.isr.controllers.iter_mut());
+                for voter in voters.filter(|v| v.id == node_id && v.port == 0) {
+                    voter.port = port;
+                }
+            }
+        }
+        sockets.push(socket);
+    }
+    Ok(sockets)
+}
+
+/// Runs a broker on listeners already bound by [`bind_listeners`].
+pub async fn serve_listeners(
+    config: Config,
+    sockets: Vec<ListenerSocket>,
+    config_path: Option<PathBuf>,
+    log_level: Arc<LogLevel>,
+    shutdown: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    let cluster_id = ensure_meta_properties(&config.log_dirs, config.node_id)?;
+    info!(cluster_id = %cluster_id, node_id = config.node_id, "starting broker");
+    let log_manager = LogManager::new(config.log_dirs.clone());
+    log_manager.startup()?;
+
+    let config = Arc::new(config);
+    let metrics = Arc::new(Metrics::default());
+    let connection_quotas = ConnectionQuotas::new(&config, metrics.clone());
+    let shared_config = Arc::new(SharedConfig::new(config.clone()));
+    let fetch_quotas = Arc::new(ClientQuotaManager::new(
+        QuotaType::Fetch,
+        config.consumer_quotas.clone(),
+        config.quota_window,
+        metrics.clone(),
+    ));
+    let produce_quotas = Arc::new(ClientQuotaManager::new(
+        QuotaType::Produce,
+        config.producer_quotas.clone(),
+        config.quota_window,
+        metrics.clone(),
+    ));
+    let partition_rates = Arc::new(PartitionRates::new(config.quota_window, metrics.clone()));
+    let features = Arc::new(FeatureCache::default());
+    let mut scram = ScramCredentials::from_passwords(&config.sasl_users);
+    // Later changes are picked up by `watch_metadata`, except to SCRAM
+    // credentials, which are only read at startup.
+    let image = Arc::new(MetadataImage::load(&config.metadata_log_file()));
+    let metadata = image.current();
+    delete_removed_partitions(&config.log_dirs, metadata.removed_topic_ids());
+    produce_quotas.update(&metadata);
+    fetch_quotas.update(&metadata);
+    features.update(&metadata);
+    scram.add_from_metadata(&metadata);
+    let scram = Arc::new(scram);
+    let quorum = match config.quorum.clone() {
+        Some(settings) => Some(MetadataQuorum::start(
+            settings,
+            config.node_id,
+            cluster_id.clone(),
+            &config.log_dirs[0],
+        )?),
+        None => None,
+    };
+    let controller = quorum
+        .clone()
+        .map(|quorum| Controller::start(quorum, config.lifecycle.session_timeout));
+    let fetches = Arc::new(fetch::FetchPurgatory::new("Fetch"));
+    let replica_fetchers = Arc::new(ReplicaFetchers::start(
+        config.replica_fetcher.clone(),
+        config.node_id,
+        &config.log_dirs[0],
+        &config.metadata_log_file(),
+        metrics.clone(),
+        partition_rates.clone(),
+        fetches.clone(),
+    )?);
+    let isr_manager = Arc::new(IsrManager::start(
+        config.isr.clone(),
+        config.node_id,
+        &config.metadata_log_file(),
+        metrics.clone(),
+    )?);
+    let lifecycle = BrokerLifecycle::start(&config, cluster_id.clone());
+    let appends = Arc::new(produce::PartitionLocks::default());
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let metadata_watcher = tokio::spawn(watch_metadata(
+        replica_fetchers.clone(),
+        isr_manager.clone(),
+        produce_quotas.clone(),
+        fetch_quotas.clone(),
+        features.clone(),
+        appends.clone(),
+        image.clone(),
+        config.log_dirs.clone(),
+        config.metadata_log_file(),
+        shutdown_rx.clone(),
+    ));
+    let delegation_tokens = Arc::new(DelegationTokenManager::new(
+        config.delegation_tokens.clone(),
+    ));
+    let coordinator = GroupCoordinator::start(
+        config.group_settings.clone(),
+        &config.log_dirs[0],
+        metrics.clone(),
+    )?;
+    let transactions = Arc::new(TransactionCoordinator::open(
+        &config.log_dirs[0],
+        Arc::new(produce::TransactionMarkers::new(
+            config.node_id,
+            fetches.clone(),
+            image.clone(),
+            config.log_dirs[0].clone(),
+            appends.clone(),
+        )),
+    )?);
+    let mut apis = ApiRegistry::broker(
+        shared_config.clone(),
+        cluster_id,
+        build_authorizer(&config)?,
+        coordinator.clone(),
+        transactions,
+        quorum.clone(),
+        controller.clone(),
+        isr_manager.clone(),
+        replica_fetchers.clone(),
+        partition_rates.clone(),
+        fetches,
+        appends,
+        image,
+        delegation_tokens.clone(),
+        features,
+    );
+    // Outermost first: the wire-debug log sees responses as they are sent,
+    // and throttling comes after a request is measured, so quota delays
+    // don't count towards its latency.
+    apis.layer(WireDebugLayer::new(shared_config.clone()));
+    apis.layer(ThrottleLayer::new(ApiKey::Produce, produce_quotas.clone()));
+    apis.layer(ThrottleLayer::new(ApiKey::Fetch, fetch_quotas.clone()));
+    if let Some(settings) = config.audit_log.clone() {
+        apis.layer(AuditLayer::new(AuditLog::open(settings)?));
+    }
+    apis.layer(MetricsLayer::new(metrics.clone(), shared_config.clone()));
+    apis.layer(TimeoutLayer::new(shared_config.clone()));
+    let server = Arc::new(Server {
+        config: shared_config.clone(),
+        metrics: metrics.clone(),
+        request_pool: MemoryPool::new(config.queued_max_request_bytes),
+        in_flight_memory: MemoryBudget::new(config.in_flight_max_bytes, metrics.clone()),
+        blocking: Arc::new(BlockingTasks::default()),
+        produce_quotas,
+        fetch_quotas,
+        connections: Arc::new(ConnectionRegistry::default()),
+        apis,
+    });
+    // Tasks that change nothing, and so are stopped wherever they are.
+    let mut background = JoinSet::new();
+    background.spawn(monitor_runtime(metrics.clone(), server.blocking.clone()));
+    background.spawn(monitor_partition_rates(partition_rates));
+    if let Some(interval) = config.metrics_summary_interval {
+        background.spawn(log_request_summary(metrics.clone(), interval));
+    }
+    let health = Arc::new(Health::new(
+        config.log_dirs.clone(),
+        config.metadata_log_file(),
+    ));
+    if let Some(addr) = config.admin_listener {
+        let admin = Arc::new(AdminServer {
+            metrics: metrics.clone(),
+            connections: server.connections.clone(),
+            health: health.clone(),
+            log_level: log_level.clone(),
+            replica_fetchers: replica_fetchers.clone(),
+            isr_manager: isr_manager.clone(),
+        });
+        let tcp = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("bind admin listener {}", addr))?;
+        info!(address = %addr, "admin endpoint listening");
+        background.spawn(admin.serve(tcp));
+    }
+    if let Some(path) = config_path {
+        background.spawn(watch_config(
+            path,
+            server.clone(),
+            connection_quotas.clone(),
+            log_level,
+        ));
+    }
+
+    let oauthbearer = config.oauthbearer.clone().map(OAuthBearerValidator::start);
+    let (accepted_tx, mut accepted_rx) = mpsc::channel(64);
+    let mut acceptors = JoinSet::new();
+    for (endpoint, socket) in config.listeners.iter().zip(sockets) {
+        let listener = Arc::new(Listener::new(
+            &config,
+            endpoint,
+            scram.clone(),
+            delegation_tokens.clone(),
+            oauthbearer.clone(),
+        )?);
+        info!(listener = %endpoint, protocol = %endpoint.security_protocol, "listening");
+        acceptors.spawn(accept_loop(
+            socket,
+            listener,
+            config.socket_options.clone(),
+            connection_quotas.clone(),
+            accepted_tx.clone(),
+        ));
+    }
+    drop(accepted_tx);
+    health.set_serving(true);
+
+    let mut connections = JoinSet::new();
+
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            Some(accepted) = accepted_rx.recv() => {
+                let Accepted { stream, peer, slot, listener } = accepted;
+                let Some(permit) = connection_quotas.try_acquire(peer.ip(), slot) else {
+                    warn!(peer = %peer, "rejecting connection: too many connections from this address");
+                    continue;
+                };
+                let server = server.clone();
+                let connection_quotas = connection_quotas.clone();
+                let mut shutdown = shutdown_rx.clone();
+                let span = info_span!(
+                    "connection",
+                    id = tracing::field::Empty,
+                    peer = %peer,
+                    listener = %listener.endpoint.listener_name,
+                );
+                connections.spawn(
+                    async move {
+                        debug!("accepted connection");
+                        tokio::select! {
+                            _ = connection_quotas.pace_creation_from(peer.ip()) => {}
+                            _ = shutdown.changed() => return,
+                        }
+                        match serve_conn(stream, peer, listener, server, shutdown).await {
+                            Ok(()) => {}
+                            Err(e) if is_disconnect(&e) => {
+                                debug!(error = %e, "client disconnected");
+                            }
+                            Err(e) => warn!(error = %e, "connection closed with error"),
+                        }
+                        drop(permit);
+                    }
+                    .instrument(span),
+                );
+            }
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            res = &mut shutdown => {
+                res?;
+                break;
+            }
+        }
+    }
+
+    // Stop accepting, then let open connections finish the request they are on.
+    health.set_serving(false);
+    acceptors.shutdown().await;
+    shutdown_tx.send_replace(true);
+    info!(
+        connections = connections.len(),
+        "shutting down, draining connections"
+    );
+
+    let drained = tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        warn!(
+            connections = connections.len(),
+            "drain timeout elapsed, aborting connections"
+        );
+        connections.shutdown().await;
+    }
+    let _ = metadata_watcher.await;
+    background.shutdown().await;
+    lifecycle.shutdown();
+    if let Some(oauthbearer) = &oauthbearer {
+        oauthbearer.shutdown();
+    }
+    if let Some(controller) = &controller {
+        controller.shutdown().await;
+    }
+    if let Some(quorum) = &quorum {
+        quorum.resign().await;
+        quorum.shutdown().await;
+    }
+    // Nothing may append to the logs once they are flushed and marked clean.
+    replica_fetchers.shutdown().await;
+    isr_manager.shutdown().await;
+    coordinator.shutdown().await;
+    log_manager.shutdown(drained.is_ok());
+
+    info!("shutdown complete");
+    Ok(())
+}
+
+/// Accepts connections on one listener, waiting for a free `max.connections`
+/// slot and for `max.connection.creation.rate` before each accept.
+async fn accept_loop(
+    socket: ListenerSocket,
+    listener: Arc<Listener>,
+    socket_options: SocketOptions,
+    connection_quotas: Arc<ConnectionQuotas>,
+    accepted_tx: mpsc::Sender<Accepted>,
+) {
+    loop {
+        let listener_name = &listener.endpoint.listener_name;
+        let slot = connection_quotas.reserve(listener_name).await;
+        connection_quotas.pace_creation(listener_name).await;
+        let (stream, peer) = match socket.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!(listener = %listener.endpoint, error = %e, "accept failed");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        if let Err(e) = socket_options.apply(&stream) {
+            warn!(peer = %peer, error = %e, "failed to set socket options");
+        }
+        let accepted = Accepted {
+            stream,
+            peer,
+            slot,
+            listener: listener.clone(),
+        };
+        if accepted_tx.send(accepted).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Reloads the config file on SIGHUP or when it changes on disk.
+async fn watch_config(
+    path: PathBuf,
+    server: Arc<Server>,
+    connection_quotas: Arc<ConnectionQuotas>,
+    log_level: Arc<LogLevel>,
+) {
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(e) => {
+            error!(error = %e, "failed to install SIGHUP handler, config reload disabled");
+            return;
+        }
+    };
+    let mut ticker = tokio::time::interval(CONFIG_POLL_INTERVAL);
+    let mut modified = modified_time(&path);
+    loop {
+        tokio::select! {
+            _ = sighup.recv() => {}
+            _ = ticker.tick() => {
+                if modified_time(&path) == modified {
+                    continue;
+                }
+            }
+        }
+        modified = modified_time(&path);
+        if let Err(e) = reload_config(&path, &server, &connection_quotas, &log_level) {
+            error!(
+                path = %path.display(),
+                error = %e,
+                "config reload failed, keeping current settings"
+            );
+        }
+    }
+}
+
+/// Publishes the metadata log to `image` whenever it changes, e.g. when the
+/// controller moves a partition's leadership, and brings the replica
+/// fetchers, the ISR of led partitions, the client quotas and the finalized
+/// features up to date with it. Also deletes this broker's replicas of
+/// removed topics. Stops at shutdown.
+#[allow(clippy::too_many_arguments)]
+async fn watch_metadata(
+    replica_fetchers: Arc<ReplicaFetchers>,
+    isr_manager: Arc<IsrManager>,
+    produce_quotas: Arc<ClientQuotaManager>,
+    fetch_quotas: Arc<ClientQuotaManager>,
+    features: Arc<FeatureCache>,
+    appends: Arc<produce::PartitionLocks>,
+    image: Arc<MetadataImage>,
+    log_dirs: Vec<PathBuf>,
+    path: PathBuf,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let version = || {
+        std::fs::metadata(&path)
+            .and_then(|m| Ok((m.len(), m.modified()?)))
+            .ok()
+    };
+    let mut ticker = tokio::time::interval(METADATA_POLL_INTERVAL);
+    let mut seen = version();
+    loop {
+        tokio::select! {
+            _ = shutdown_requested(&mut shutdown) => return,
+            _ = ticker.tick() => {}
+        }
+        let current = version();
+        if current == seen {
+            continue;
+        }
+        // A log that can't be read is reported once, and read again when it
+        // next changes.
+        seen = current;
+        let metadata = match cluster_metadata::RecordBatches::from_file(&path) {
+            Ok(metadata) => image.publish(metadata),
+            Err(e) => {
+                error!(error = %e, "failed to read the metadata log");
+                continue;
+            }
+        };
+        appends.remove(&delete_removed_partitions(
+            &log_dirs,
+            metadata.removed_topic_ids(),
+        ));
+        isr_manager.update(&metadata);
+        produce_quotas.update(&metadata);
+        fetch_quotas.update(&metadata);
+        features.update(&metadata);
+        if let Err(e) = replica_fetchers.update(&metadata).await {
+            error!(error = %e, "failed to update replica fetchers");
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Applies the reloadable settings from the config file. Nothing changes
+/// unless the whole file is valid.
+fn reload_config(
+    path: &Path,
+    server: &Server,
+    connection_quotas: &ConnectionQuotas,
+    log_level: &LogLevel,
+) -> Result<()> {
+    let current = server.config();
+    let (config, needs_restart) = current.reload(&read_properties(path)?)?;
+    for key in &needs_restart {
+        warn!(key, "changed setting only takes effect after a restart");
+    }
+    // Built before anything is applied, so the one step that can fail can't
+    // leave the broker half reconfigured. Left alone unless `log.level`
+    // changed, so a filter set from the admin endpoint survives other edits.
+    if config.log_level != current.log_level {
+        log_level.replace(build_log_filter(&config)?)?;
+    }
+    server
+        .produce_quotas
+        .reconfigure(config.producer_quotas.clone());
+    server
+        .fetch_quotas
+        .reconfigure(config.consumer_quotas.clone());
+    connection_quotas.reconfigure(&config);
+    server.config.replace(Arc::new(config));
+    info!(path = %path.display(), "config reloaded");
+    Ok(())
+}
+
+/// Whether `e` is the client going away rather than something going wrong.
+fn is_disconnect(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<Error>() {
+        Some(Error::Io(e)) => crate::error::is_disconnect(e),
+        Some(Error::Cancelled) => true,
+        _ => e
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(crate::error::is_disconnect),
+    }
+}
+
+/// Runs the TLS handshake when the listener is TLS-enabled, then serves requests.
+async fn serve_conn(
+    stream: ClientStream,
+    peer: SocketAddr,
+    listener: Arc<Listener>,
+    server: Arc<Server>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut session = Session::new(
+        listener.endpoint.listener_name.clone(),
+        listener.listener_type,
+        KafkaPrincipal::anonymous(),
+        peer,
+        stream.local_addr()?,
+    );
+    if let Some(credentials) = &listener.sasl_credentials {
+        session = session.with_authenticator(Authenticator::new(credentials.clone()));
+    }
+
+    // Taken before the TLS handshake, which hides the TCP socket.
+    let probe = match server.config().socket_options.keepalive {
+        Some(_) => stream.peer_probe()?,
+        None => None,
+    };
+    let connection = server
+        .connections
+        .register(&listener.endpoint.listener_name, peer);
+    // Matches the id on the admin endpoint's connection list.
+    Span::current().record("id", connection.id);
+
+    let Some(acceptor) = &listener.tls_acceptor else {
+        return handle_conn(stream, session, &connection, probe, server, shutdown).await;
+    };
+
+    let stream = acceptor.accept(stream).await?;
+    if let Some([cert, ..]) = stream.get_ref().1.peer_certificates() {
+        session.principal = principal_from_certificate(cert)?;
+    }
+    debug!(principal = %session.principal, "TLS handshake complete");
+    handle_conn(stream, session, &connection, probe, server, shutdown).await
+}
+
+/// A request whose response has not been written yet. Responses are written
+/// in the order the requests arrived, whichever finishes first. `None` means
+/// the request gets no response at all, as for acks=0 produce requests.
+struct InFlight {
+    /// The request's span, which the response write is also recorded under.
+    span: Span,
+    response: Pin<Box<dyn Future<Output = Result<Option<Bytes>>> + Send>>,
+}
+
+async fn handle_conn<S>(
+    stream: S,
+    mut session: Session,
+    connection: &ConnectionState,
+    probe: Option<PeerProbe>,
+    server: Arc<Server>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    // However the connection ends, requests still being handled for it are
+    // told nobody will read their responses.
+    let _cancel_on_close = CancelOnDrop(session.cancellation.clone());
+    connection.update_session(&session);
+    let config = server.config();
+    // Checked as often as keepalive probes go out, so a dead peer is reaped
+    // within one interval of the OS giving up on it.
+    let mut liveness = tokio::time::interval(
+        config
+            .socket_options
+            .keepalive
+            .map_or(Duration::MAX, |k| k.interval),
+    );
+    liveness.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let connection_pool = MemoryPool::new(config.queued_max_request_bytes_per_connection);
+    let mut frames = FrameDecoder::new(config.socket_request_max_bytes);
+    let mut in_flight: VecDeque<InFlight> = VecDeque::new();
+    // Room in both memory pools for the request at the front of `frames`.
+    let mut reserved: Option<RequestPermit> = None;
+    let mut closing = false;
+    // Only reads and writes count as activity; keepalive probes and the
+    // loop waking up for them don't keep an idle connection open.
+    let mut last_active = Instant::now();
+    loop {
+        while !closing
+            && in_flight.len() < config.max_in_flight
+            && !server.in_flight_memory.is_exceeded()
+        {
+            if reserved.is_none() {
+                let Some(len) = frames.frame_len()? else {
+                    break;
+                };
+                reserved = try_reserve(&server.request_pool, &connection_pool, len);
+            }
+            if reserved.is_none() {
+                break;
+            }
+            let Some(message) = frames.next_frame()? else {
+                break;
+            };
+            let permit = reserved.take();
+            // Stands in for the handler's result too until it is serialized.
+            let charge = server.in_flight_memory.charge(message.len());
+            let InFlight { span, response } = dispatch(&server, &mut session, connection, message)?;
+            in_flight.push_back(InFlight {
+                span,
+                response: Box::pin(async move {
+                    let _permit = permit;
+                    let _charge = charge;
+                    response.await
+                }),
+            });
+            connection.set_in_flight(in_flight.len());
+            connection.update_session(&session);
+            if session.authentication_failed() {
+                info!("closing connection: authentication failed");
+                closing = true;
+            }
+        }
+        if closing && in_flight.is_empty() {
+            return Ok(());
+        }
+
+        // Only idle connections are interrupted; a request that has started
+        // being read is always answered before the connection closes.
+        let idle = in_flight.is_empty() && frames.is_empty();
+        let idle_timeout = tokio::time::sleep(
+            config
+                .connections_max_idle
+                .saturating_sub(last_active.elapsed()),
+        );
+        // Stop reading while the next request's bytes can't be reserved, so the
+        // socket fills up and the client is slowed down by TCP itself.
+        let next_len = frames.frame_len()?;
+        let waiting_for_memory = reserved.is_none() && next_len.is_some();
+        let over_budget = server.in_flight_memory.is_exceeded();
+        tokio::select! {
+            biased;
+            res = next_response(&mut in_flight), if !in_flight.is_empty() => {
+                let done = in_flight.pop_front().expect("a request is in flight");
+                connection.set_in_flight(in_flight.len());
+                if let Some(resp) = res? {
+                    let bytes = 4 + resp.len();
+                    let _charge = server.in_flight_memory.charge(resp.len());
+                    let write = async {
+                        trace!(bytes = %hex::encode(&resp), "response");
+                        write_response(&mut writer, resp).await?;
+                        debug!(bytes, "response written");
+                        anyhow::Ok(())
+                    };
+                    write.instrument(done.span).await?;
+                    connection.add_bytes_sent(bytes);
+                    last_active = Instant::now();
+                }
+            }
+            permit = reserve(&server.request_pool, &connection_pool, next_len.unwrap_or_default()),
+                if waiting_for_memory && in_flight.len() < config.max_in_flight =>
+            {
+                reserved = Some(permit);
+            }
+            _ = server.in_flight_memory.wait_for_room(), if over_budget && !closing => {}
+            res = frames.read_from(&mut reader),
+                if !closing
+                    && !waiting_for_memory
+                    && !over_budget
+                    && in_flight.len() < config.max_in_flight =>
+            {
+                let n = match res {
+                    Ok(n) => n,
+                    Err(e) if e.kind() == ErrorKind::TimedOut => {
+                        reap(&server, connection, &e);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                connection.add_bytes_received(n);
+                last_active = Instant::now();
+                if n == 0 {
+                    // Nobody is left to read the responses, so requests still
+                    // in flight are dropped rather than finished.
+                    debug!(
+                        in_flight = in_flight.len(),
+                        partial_request = !frames.is_empty(),
+                        "client closed connection"
+                    );
+                    return Ok(());
+                }
+            }
+            _ = idle_timeout, if idle && !closing => {
+                debug!(
+                    idle = ?config.connections_max_idle,
+                    "closing idle connection"
+                );
+                return Ok(());
+            }
+            _ = shutdown_requested(&mut shutdown), if frames.is_empty() && !closing => {
+                closing = true;
+            }
+            _ = liveness.tick(), if probe.is_some() => {
+                if let Some(e) = probe.as_ref().and_then(|p| p.take_error().ok().flatten()) {
+                    reap(&server, connection, &e);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Records a connection whose peer vanished without closing it. Requests
+/// still in flight are dropped, as nobody is left to read the responses.
+fn reap(server: &Server, connection: &ConnectionState, e: &std::io::Error) {
+    info!(error = %e, "closing connection: peer is unreachable");
+    server.metrics.incr_counter(
+        CONNECTIONS_REAPED_METRIC,
+        &[("listener", &connection.listener_name)],
+        1,
+    );
+}
+
+/// Memory held for a request from before its body is read until its handler
+/// finishes.
+type RequestPermit = (MemoryPermit, MemoryPermit);
+
+async fn reserve(global: &MemoryPool, connection: &MemoryPool, len: usize) -> RequestPermit {
+    let connection = connection.acquire(len).await;
+    (global.acquire(len).await, connection)
+}
+
+fn try_reserve(global: &MemoryPool, connection: &MemoryPool, len: usize) -> Option<RequestPermit> {
+    let connection = connection.try_acquire(len)?;
+    Some((global.try_acquire(len)?, connection))
+}
+
+/// Waits for the oldest in-flight request. The caller pops it once it is done.
+async fn next_response(in_flight: &mut VecDeque<InFlight>) -> Result<Option<Bytes>> {
+    match in_flight.front_mut() {
+        Some(request) => request.response.as_mut().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Writes one size-prefixed response. The prefix and body go out as a single
+/// vectored write where the transport supports it, and short writes are
+/// retried until the whole frame is sent.
+async fn write_response<W>(writer: &mut W, body: Bytes) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let prefix = (body.len() as i32).to_be_bytes();
+    let mut frame = Buf::chain(prefix.as_slice(), body);
+    writer.write_all_buf(&mut frame).await?;
+    // TLS buffers records until flushed.
+    writer.flush().await?;
+    Ok(())
+}
+
+async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    // Not holding on to the borrowed value keeps the connection future `Send`.
+    let _ = shutdown.wait_for(|&stop| stop).await;
+}
+
+/// Starts processing a request. Requests that drive SASL authentication change
+/// the session and run inline; everything else runs on the blocking pool so
+/// later requests on the connection are not held up behind it.
+fn dispatch(
+    server: &Arc<Server>,
+    session: &mut Session,
+    connection: &ConnectionState,
+    mut message: Bytes,
+) -> Result<InFlight, Error> {
+    // Without a correlation id there is nothing to answer, so a header that
+    // can't be decoded ends the connection.
+    let header = HeaderV2::deserialize(&mut message).map_err(|_| Error::MalformedHeader)?;
+    let api_key = match ApiKey::try_from(header.api_key) {
+        Ok(key) if server.apis.handler(key).is_some() => key,
+        _ => {
+            return Err(Error::UnsupportedApi(format!(
+                "Invalid request api key, {:?}",
+                header.api_key
+            )));
+        }
+    };
+    if !api_key.is_enabled_on(session.listener_type) {
+        // As in Kafka, an API the listener does not serve ends the connection.
+        return Err(Error::UnsupportedApi(format!(
+            "{:?} is not served on controller listener {}",
+            api_key, session.listener_name
+        )));
+    }
+    // ApiVersions answers any version, so that a client can find out which
+    // it may send.
+    if api_key != ApiKey::ApiVersions && !server.apis.supports(api_key, header.api_version) {
+        return Err(Error::UnsupportedVersion(api_key, header.api_version));
+    }
+    let span = info_span!(
+        "request",
+        api_key = ?api_key,
+        api_version = header.api_version,
+        correlation_id = header.correlation_id,
+        client_id = header.client_id.0.as_deref().unwrap_or_default(),
+    );
+    let _enter = span.enter();
+    trace!(bytes = %hex::encode(&message), "request");
+    connection.record_request(header.client_id.0.as_deref().unwrap_or_default());
+    let received = Instant::now();
+    let body = message.clone();
+
+    let authenticating = session.authenticator.as_ref().is_some_and(|a| {
+        !a.is_complete() || matches!(api_key, ApiKey::SaslHandshake | ApiKey::SaslAuthenticate)
+    });
+    let (ctx, handled): (_, BoxFuture<'static, _>) = if authenticating {
+        let res = process_message(server, session, header.clone(), api_key, &mut message);
+        // Taken after handling, as authentication may have just completed.
+        let ctx = Arc::new(session.request_context(header));
+        (ctx, Box::pin(std::future::ready(res)))
+    } else {
+        let ctx = Arc::new(session.request_context(header));
+        let (server, handler_ctx, span) = (server.clone(), ctx.clone(), span.clone());
+        let handled = async move {
+            let blocking = server.blocking.clone();
+            let handle = blocking.spawn(move || {
+                let _enter = span.enter();
+                // Queued behind slow work while the client went away.
+                if handler_ctx.cancellation.is_cancelled() {
+                    debug!("client gone, request not handled");
+                    return Err(Error::Cancelled);
+                }
+                let _handle = debug_span!("handle").entered();
+                catch_panic(&server, api_key, &handler_ctx, &mut message, |message| {
+                    handle_request(&server, &handler_ctx, api_key, message)
+                })
+            });
+            // The handler's panics are caught, so the task only fails when the
+            // runtime shuts down under it.
+            handle.await.unwrap_or(Err(Error::Cancelled))
+        };
+        (ctx, Box::pin(handled))
+    };
+    let request = Request {
+        api_key,
+        ctx,
+        body,
+        received,
+    };
+    // Spawned so the request is handled while earlier responses on the
+    // connection are still being waited for.
+    let server = server.clone();
+    let task = tokio::spawn(
+        async move { server.apis.serve(&request, handled).await }.instrument(span.clone()),
+    );
+    let abort = AbortOnDrop(task.abort_handle());
+    let response = async move {
+        let _abort = abort;
+        let reply = task.await??;
+        Ok(reply.response.is_sent().then_some(reply.bytes))
+    };
+    Ok(InFlight {
+        span: span.clone(),
+        response: Box::pin(response),
+    })
+}
+
+/// Stops a request's task when the connection gives up on its response.
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Periodically logs per-API request counts and latencies.
+async fn log_request_summary(metrics: Arc<Metrics>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        for summary in metrics.request_summary() {
+            info!(
+                api_key = %summary.api_key,
+                requests = summary.requests,
+                errors = summary.errors,
+                mean_ms = summary.latency.mean() * 1000.0,
+                p99_ms = summary.latency.quantile(0.99) * 1000.0,
+                "request summary"
+            );
+        }
+    }
+}
+
+/// Handles a request on a connection that is still authenticating, or one
+/// that drives SASL authentication.
+fn process_message(
+    server: &Server,
+    session: &mut Session,
+    header: HeaderV2,
+    api_key: ApiKey,
+    message: &mut Bytes,
+) -> Result<Box<dyn Response>, Error> {
+    let ctx = session.request_context(header);
+    catch_panic(server, api_key, &ctx, message, |message| {
+        let Some(authenticator) = &mut session.authenticator else {
+            return handle_request(server, &ctx, api_key, message);
+        };
+        if !authenticator.allows(api_key) {
+            authenticator.fail();
+            return error_response(
+                server,
+                api_key,
+                &ctx,
+                message,
+                ErrorCode::IllegalSaslState,
+                &format!("{:?} is not allowed during SASL authentication", api_key),
+            );
+        }
+        let response: Box<dyn Response> = match api_key {
+            ApiKey::SaslHandshake => {
+                let res = sasl_handshake::handle_request(&ctx, message, authenticator)?;
+                Box::new(res)
+            }
+            ApiKey::SaslAuthenticate => {
+                let (res, principal) =
+                    sasl_authenticate::handle_request(&ctx, message, authenticator)?;
+                if let Some(principal) = principal {
+                    info!(principal = %principal, "SASL authentication complete");
+                    session.principal = principal;
+                }
+                Box::new(res)
+            }
+            _ => return handle_request(server, &ctx, api_key, message),
+        };
+        Ok(response)
+    })
+}
+
+/// Runs `handler`, turning an error or a panic into a response with the
+/// error's code that explains it, so one bad request doesn't take down its
+/// connection and the requests pipelined behind it. Only errors that leave
+/// nothing to answer, or nobody to answer, are passed on to close it.
+fn catch_panic(
+    server: &Server,
+    api_key: ApiKey,
+    ctx: &RequestContext,
+    message: &mut Bytes,
+    handler: impl FnOnce(&mut Bytes) -> Result<Box<dyn Response>, Error>,
+) -> Result<Box<dyn Response>, Error> {
+    let mut original = message.clone();
+    let e = match panic::catch_unwind(AssertUnwindSafe(|| handler(message))) {
+        Ok(Ok(response)) => return Ok(response),
+        Ok(Err(e)) if !e.keeps_connection() => return Err(e),
+        Ok(Err(e)) => {
+            // The whole context chain, as the client has nothing else to go on.
+            warn!(error = %e, error_code = ?e.error_code(), "request handler failed");
+            e
+        }
+        Err(panic) => {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown");
+            error!(panic = reason, "request handler panicked");
+            Error::Panic(reason.to_string())
+        }
+    };
+    // Some error responses echo the request's topics, so a request too
+    // malformed to handle can be too malformed to answer.
+    error_response(
+        server,
+        api_key,
+        ctx,
+        &mut original,
+        e.error_code(),
+        &e.to_string(),
+    )
+}
+
+fn handle_request(
+    server: &Server,
+    ctx: &RequestContext,
+    api_key: ApiKey,
+    message: &mut Bytes,
+) -> Result<Box<dyn Response>, Error> {
+    handler(server, api_key).handle(ctx, message)
+}
+
+fn build_authorizer(config: &Config) -> Result<Arc<dyn Authorizer>> {
+    let Some(settings) = &config.authorizer else {
+        return Ok(Arc::new(AllowAllAuthorizer));
+    };
+    let authorizer = AclAuthorizer::new(settings)?;
+    info!(
+        acls = authorizer.acls().len(),
+        super_users = settings.super_users.len(),
+        "ACL authorization enabled"
+    );
+    Ok(Arc::new(authorizer))
+}
+
+/// Builds the response for a request that is rejected without being handled,
+/// explained by `error_message` where the response has room for one.
+fn error_response(
+    server: &Server,
+    api_key: ApiKey,
+    ctx: &RequestContext,
+    message: &mut Bytes,
+    error_code: ErrorCode,
+    error_message: &str,
+) -> Result<Box<dyn Response>, Error> {
+    let mut response = handler(server, api_key)
+        .error_response(ctx, message, error_code)
+        .map_err(|e| Error::Decode(api_key, e))?;
+    response.set_error_message(error_message);
+    Ok(response)
+}
+
+fn handler(server: &Server, api_key: ApiKey) -> &dyn ApiHandler {
+    // `dispatch` turns away requests for APIs without a handler.
+    server
+        .apis
+        .handler(api_key)
+        .expect("only registered APIs are dispatched")
+}
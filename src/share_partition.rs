@@ -0,0 +1,367 @@
+//! Share partitions and share sessions, as in KIP-932: the members of a
+//! share group consume a partition together, each record handed to one of
+//! them at a time. A record a member fetches is acquired under a lock until
+//! the member acknowledges it: accepted and rejected records are done with,
+//! released ones go round again, and a record whose lock runs out is
+//! released for the member. A record delivered
+//! `group.share.delivery.attempt.limit` times is archived instead of going
+//! round again, so one that keeps failing can't hold the partition up.
+//!
+//! The state is kept in memory only, so a restart hands out again whatever
+//! was in flight. A group starts consuming a partition at its log end.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::coordinator::GroupSettings;
+use crate::protocol::ErrorCode;
+
+/// The epoch of a ShareFetch that opens a share session.
+pub const SHARE_INITIAL_EPOCH: i32 = 0;
+/// The epoch of a ShareFetch that closes its share session, releasing what
+/// the member still holds.
+pub const SHARE_FINAL_EPOCH: i32 = -1;
+
+/// How a member acknowledges records. A gap is an offset the member was
+/// given but found no record at, and is done with like a rejected record.
+pub const ACKNOWLEDGE_GAP: i8 = 0;
+pub const ACKNOWLEDGE_ACCEPT: i8 = 1;
+pub const ACKNOWLEDGE_RELEASE: i8 = 2;
+pub const ACKNOWLEDGE_REJECT: i8 = 3;
+
+/// A partition as a share group consumes it: the group, topic id and
+/// partition index.
+pub type SharePartitionKey = (String, String, i32);
+
+/// Records acquired by a member, from `first_offset` to `last_offset`
+/// inclusive, each delivered `delivery_count` times counting this one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcquiredRecords {
+    pub first_offset: i64,
+    pub last_offset: i64,
+    pub delivery_count: i16,
+}
+
+/// A range of records a member acknowledges, with one type for the whole
+/// range or one per offset.
+pub struct Acknowledgement {
+    pub first_offset: i64,
+    pub last_offset: i64,
+    pub types: Vec<i8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RecordState {
+    Available,
+    Acquired {
+        member_id: Arc<str>,
+        deadline: Instant,
+    },
+    /// Accepted, rejected, or delivered as often as it may be.
+    Done,
+}
+
+struct InFlightRecord {
+    state: RecordState,
+    delivery_count: i16,
+}
+
+/// One group's view of one partition.
+struct SharePartition {
+    /// Every record before this one is done with.
+    start_offset: i64,
+    /// The first offset never handed out. Every offset from `start_offset`
+    /// up to here is in `in_flight`.
+    end_offset: i64,
+    in_flight: BTreeMap<i64, InFlightRecord>,
+}
+
+impl SharePartition {
+    fn new(log_end: i64) -> Self {
+        Self {
+            start_offset: log_end,
+            end_offset: log_end,
+            in_flight: BTreeMap::new(),
+        }
+    }
+
+    /// Makes records whose locks ran out available again, or done with if
+    /// they have been delivered `delivery_limit` times.
+    fn release_expired(&mut self, now: Instant, delivery_limit: i16) {
+        for record in self.in_flight.values_mut() {
+            if matches!(record.state, RecordState::Acquired { deadline, .. } if deadline <= now) {
+                record.release(delivery_limit);
+            }
+        }
+    }
+
+    /// Hands `member_id` the records nobody holds, redeliveries first, then
+    /// new ones up to `log_end` as far as `max_in_flight` allows.
+    fn acquire(
+        &mut self,
+        member_id: &Arc<str>,
+        log_end: i64,
+        deadline: Instant,
+        max_in_flight: usize,
+    ) -> Vec<AcquiredRecords> {
+        let mut acquired: Vec<AcquiredRecords> = Vec::new();
+        let mut push = |offset: i64, delivery_count: i16| match acquired.last_mut() {
+            Some(last)
+                if last.last_offset + 1 == offset && last.delivery_count == delivery_count =>
+            {
+                last.last_offset = offset;
+            }
+            _ => acquired.push(AcquiredRecords {
+                first_offset: offset,
+                last_offset: offset,
+                delivery_count,
+            }),
+        };
+        for (&offset, record) in self.in_flight.iter_mut() {
+            if record.state == RecordState::Available {
+                record.state = RecordState::Acquired {
+                    member_id: member_id.clone(),
+                    deadline,
+                };
+                record.delivery_count += 1;
+                push(offset, record.delivery_count);
+            }
+        }
+        let limit = self.start_offset + max_in_flight as i64;
+        while self.end_offset < log_end.min(limit) {
+            self.in_flight.insert(
+                self.end_offset,
+                InFlightRecord {
+                    state: RecordState::Acquired {
+                        member_id: member_id.clone(),
+                        deadline,
+                    },
+                    delivery_count: 1,
+                },
+            );
+            push(self.end_offset, 1);
+            self.end_offset += 1;
+        }
+        acquired
+    }
+
+    /// Applies `ack` to records `member_id` holds. Nothing changes unless
+    /// every offset in the range is held by the member.
+    fn acknowledge(
+        &mut self,
+        member_id: &str,
+        ack: &Acknowledgement,
+        delivery_limit: i16,
+    ) -> Result<(), ErrorCode> {
+        let count = ack.last_offset - ack.first_offset + 1;
+        if count <= 0 || (ack.types.len() != 1 && ack.types.len() as i64 != count) {
+            return Err(ErrorCode::InvalidRequest);
+        }
+        if ack
+            .types
+            .iter()
+            .any(|t| !(ACKNOWLEDGE_GAP..=ACKNOWLEDGE_REJECT).contains(t))
+        {
+            return Err(ErrorCode::InvalidRequest);
+        }
+        let held = (ack.first_offset..=ack.last_offset).all(|offset| {
+            matches!(
+                self.in_flight.get(&offset).map(|r| &r.state),
+                Some(RecordState::Acquired { member_id: holder, .. }) if &**holder == member_id
+            )
+        });
+        if !held {
+            return Err(ErrorCode::InvalidRecordState);
+        }
+        for (i, offset) in (ack.first_offset..=ack.last_offset).enumerate() {
+            let ack_type = ack.types[if ack.types.len() == 1 { 0 } else { i }];
+            let record = self.in_flight.get_mut(&offset).unwrap();
+            if ack_type == ACKNOWLEDGE_RELEASE {
+                record.release(delivery_limit);
+            } else {
+                record.state = RecordState::Done;
+            }
+        }
+        Ok(())
+    }
+
+    /// Releases everything `member_id` holds.
+    fn release_member(&mut self, member_id: &str, delivery_limit: i16) {
+        for record in self.in_flight.values_mut() {
+            if matches!(&record.state, RecordState::Acquired { member_id: holder, .. } if &**holder == member_id)
+            {
+                record.release(delivery_limit);
+            }
+        }
+    }
+
+    /// Moves the start past the records done with.
+    fn advance(&mut self) {
+        while let Some(entry) = self.in_flight.first_entry() {
+            if entry.get().state != RecordState::Done {
+                break;
+            }
+            self.start_offset = *entry.key() + 1;
+            entry.remove();
+        }
+        if self.in_flight.is_empty() {
+            self.start_offset = self.end_offset;
+        }
+    }
+}
+
+impl InFlightRecord {
+    fn release(&mut self, delivery_limit: i16) {
+        self.state = if self.delivery_count >= delivery_limit {
+            RecordState::Done
+        } else {
+            RecordState::Available
+        };
+    }
+}
+
+/// A member's share session: the partitions it fetches from, so a fetch
+/// need only name what changed.
+struct ShareSession {
+    /// The epoch the next fetch in the session must carry.
+    next_epoch: i32,
+    partitions: BTreeSet<(String, i32)>,
+}
+
+/// The share partitions and share sessions of every share group fetching
+/// from this broker.
+pub struct SharePartitionManager {
+    record_lock_duration: Duration,
+    delivery_attempt_limit: i16,
+    max_record_locks: usize,
+    partitions: Mutex<HashMap<SharePartitionKey, SharePartition>>,
+    /// By group and member id.
+    sessions: Mutex<HashMap<(String, String), ShareSession>>,
+}
+
+impl SharePartitionManager {
+    pub fn new(settings: &GroupSettings) -> Self {
+        Self {
+            record_lock_duration: settings.share_record_lock_duration,
+            delivery_attempt_limit: settings.share_delivery_attempt_limit,
+            max_record_locks: settings.share_partition_max_record_locks,
+            partitions: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Opens, continues or closes the member's share session at `epoch`,
+    /// returning the partitions the fetch is for: those `requested` when
+    /// the session opens, or every partition of the session after
+    /// `requested` are added and `forgotten` removed. A closing fetch only
+    /// acknowledges, so it is for none.
+    pub fn begin_session(
+        &self,
+        group_id: &str,
+        member_id: &str,
+        epoch: i32,
+        requested: &[(String, i32)],
+        forgotten: &[(String, i32)],
+    ) -> Result<Vec<(String, i32)>, ErrorCode> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let key = (group_id.to_string(), member_id.to_string());
+        match epoch {
+            SHARE_INITIAL_EPOCH => {
+                sessions.insert(
+                    key,
+                    ShareSession {
+                        next_epoch: 1,
+                        partitions: requested.iter().cloned().collect(),
+                    },
+                );
+                Ok(requested.to_vec())
+            }
+            SHARE_FINAL_EPOCH => {
+                if sessions.remove(&key).is_none() {
+                    return Err(ErrorCode::ShareSessionNotFound);
+                }
+                Ok(Vec::new())
+            }
+            _ => {
+                let session = sessions
+                    .get_mut(&key)
+                    .ok_or(ErrorCode::ShareSessionNotFound)?;
+                if epoch != session.next_epoch {
+                    return Err(ErrorCode::InvalidShareSessionEpoch);
+                }
+                session.next_epoch = session.next_epoch.checked_add(1).unwrap_or(1);
+                session.partitions.extend(requested.iter().cloned());
+                for partition in forgotten {
+                    session.partitions.remove(partition);
+                }
+                Ok(session.partitions.iter().cloned().collect())
+            }
+        }
+    }
+
+    /// Applies a member's acknowledgements to a partition, in order,
+    /// stopping at the first that fails.
+    pub fn acknowledge(
+        &self,
+        key: &SharePartitionKey,
+        member_id: &str,
+        acks: &[Acknowledgement],
+    ) -> Result<(), ErrorCode> {
+        let mut partitions = self.partitions.lock().unwrap();
+        let Some(partition) = partitions.get_mut(key) else {
+            return Err(ErrorCode::InvalidRecordState);
+        };
+        partition.release_expired(Instant::now(), self.delivery_attempt_limit);
+        let result = acks
+            .iter()
+            .try_for_each(|ack| partition.acknowledge(member_id, ack, self.delivery_attempt_limit));
+        partition.advance();
+        result
+    }
+
+    /// Acquires for `member_id` whatever of the partition nobody holds, up
+    /// to `log_end`.
+    pub fn acquire(
+        &self,
+        key: &SharePartitionKey,
+        member_id: &str,
+        log_end: i64,
+    ) -> Vec<AcquiredRecords> {
+        let now = Instant::now();
+        let mut partitions = self.partitions.lock().unwrap();
+        let partition = partitions.entry(key.clone()).or_insert_with(|| {
+            debug!(
+                group = %key.0,
+                topic_id = %key.1,
+                partition = key.2,
+                start_offset = log_end,
+                "initialized share partition"
+            );
+            SharePartition::new(log_end)
+        });
+        partition.release_expired(now, self.delivery_attempt_limit);
+        let acquired = partition.acquire(
+            &Arc::from(member_id),
+            log_end,
+            now + self.record_lock_duration,
+            self.max_record_locks,
+        );
+        partition.advance();
+        acquired
+    }
+
+    /// Releases everything the member holds in the group's partitions, as
+    /// when it closes its share session.
+    pub fn release_member(&self, group_id: &str, member_id: &str) {
+        let mut partitions = self.partitions.lock().unwrap();
+        for (key, partition) in partitions.iter_mut() {
+            if key.0 == group_id {
+                partition.release_member(member_id, self.delivery_attempt_limit);
+                partition.advance();
+            }
+        }
+    }
+}
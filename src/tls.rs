@@ -0,0 +1,188 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context, Result};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use x509_parser::{
+    objects::{oid2abbrev, oid_registry},
+    prelude::{FromDer, X509Certificate},
+};
+
+use crate::security::KafkaPrincipal;
+
+/// `ssl.client.auth`: whether TLS clients must present a certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SslClientAuth {
+    #[default]
+    None,
+    Requested,
+    Required,
+}
+
+impl std::str::FromStr for SslClientAuth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "requested" => Ok(Self::Requested),
+            "required" => Ok(Self::Required),
+            other => Err(anyhow!("unknown ssl.client.auth '{}'", other)),
+        }
+    }
+}
+
+/// The `ssl.*` settings in effect for one listener.
+#[derive(Debug, Clone, Default)]
+pub struct SslSettings {
+    /// A PEM file holding the certificate chain and private key.
+    pub keystore_location: Option<PathBuf>,
+    /// A PEM bundle of the CAs trusted to sign client certificates.
+    pub truststore_location: Option<PathBuf>,
+    pub client_auth: SslClientAuth,
+}
+
+/// Builds the acceptor for a TLS listener, verifying client certificates
+/// against the truststore when client auth is enabled.
+pub fn build_tls_acceptor(settings: &SslSettings) -> Result<TlsAcceptor> {
+    let keystore = settings
+        .keystore_location
+        .as_ref()
+        .ok_or_else(|| anyhow!("ssl.keystore.location is required for TLS"))?;
+    let cert_chain = load_certs(keystore)?;
+    let key = PrivateKeyDer::from_pem_file(keystore)
+        .with_context(|| format!("read private key from '{}'", keystore.display()))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match settings.client_auth {
+        SslClientAuth::None => builder.with_no_client_auth(),
+        auth => {
+            let truststore = settings.truststore_location.as_ref().ok_or_else(|| {
+                anyhow!("ssl.truststore.location is required when ssl.client.auth is set")
+            })?;
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(truststore)? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+            let verifier = if auth == SslClientAuth::Requested {
+                verifier.allow_unauthenticated().build()?
+            } else {
+                verifier.build()?
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+    let server_config = builder.with_single_cert(cert_chain, key)?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("read certificates from '{}'", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in '{}'", path.display()));
+    }
+    Ok(certs)
+}
+
+/// Maps a verified client certificate to a principal named after its subject
+/// DN in RFC 2253 form (`CN=alice,OU=eng,O=example`), as Kafka's default
+/// `ssl.principal.mapping.rules` does.
+pub fn principal_from_certificate(cert: &CertificateDer) -> Result<KafkaPrincipal> {
+    let (_, cert) = X509Certificate::from_der(cert.as_ref())
+        .map_err(|e| anyhow!("parse client certificate: {}", e))?;
+    let registry = oid_registry();
+    let subject: Vec<_> = cert.subject().iter().collect();
+    let mut rdns = Vec::new();
+    for rdn in subject.into_iter().rev() {
+        let mut attrs = Vec::new();
+        for attr in rdn.iter() {
+            let name = oid2abbrev(attr.attr_type(), registry)
+                .map(str::to_string)
+                .unwrap_or_else(|_| attr.attr_type().to_id_string());
+            let value = attr
+                .as_str()
+                .map_err(|e| anyhow!("client certificate subject {}: {}", name, e))?;
+            attrs.push(format!("{}={}", name, escape_dn_value(value)));
+        }
+        rdns.push(attrs.join("+"));
+    }
+    Ok(KafkaPrincipal::user(rdns.join(",")))
+}
+
+fn escape_dn_value(value: &str) -> String {
+    let last = value.chars().count().saturating_sub(1);
+    let mut escaped = String::with_capacity(value.len());
+    for (i, c) in value.chars().enumerate() {
+        let leading = i == 0 && (c == ' ' || c == '#');
+        let trailing = i == last && c == ' ';
+        if leading || trailing || matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed, subject `O=example, OU=eng, CN=alice, jr`.
+    const CLIENT_CERT: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBvzCCAWWgAwIBAgIUZHlKUXF4Pp/WP4ZgdEZfPKVNFG0wCgYIKoZIzj0EAwIw
+NDEQMA4GA1UECgwHZXhhbXBsZTEMMAoGA1UECwwDZW5nMRIwEAYDVQQDDAlhbGlj
+ZSwganIwIBcNMjYxMDE3MTAwMjM1WhgPMjEyNjA5MjMxMDAyMzVaMDQxEDAOBgNV
+BAoMB2V4YW1wbGUxDDAKBgNVBAsMA2VuZzESMBAGA1UEAwwJYWxpY2UsIGpyMFkw
+EwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEXMgcNHPodApfkVmxEYBAkkKQQujCihni
+QnbS9WRrQItEDXWu+ZGJ0CYb7yo2Pd3iLQwhB2yDgYSGw1C6Um6RZKNTMFEwHQYD
+VR0OBBYEFAZnnZO/dEl3IDjW+zNr1yt8aOTSMB8GA1UdIwQYMBaAFAZnnZO/dEl3
+IDjW+zNr1yt8aOTSMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIh
+AICcURHMF8XvyqojmRnnrVSiRX31rA2R9stR0/geXdHKAiB6RTIBVJypHPd4sHSs
+WMhGSZ9XRvgZL50L+bjwbrQUfw==
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn names_principal_after_subject_in_rfc2253_order() {
+        let cert = CertificateDer::from_pem_slice(CLIENT_CERT.as_bytes()).unwrap();
+        let principal = principal_from_certificate(&cert).unwrap();
+        assert_eq!(
+            principal,
+            KafkaPrincipal::user("CN=alice\\, jr,OU=eng,O=example")
+        );
+    }
+
+    #[test]
+    fn escapes_special_characters_in_dn_values() {
+        assert_eq!(escape_dn_value("a+b=c"), "a\\+b\\=c");
+        assert_eq!(escape_dn_value("#lead "), "\\#lead\\ ");
+        assert_eq!(escape_dn_value(" "), "\\ ");
+        assert_eq!(escape_dn_value(""), "");
+    }
+
+    #[test]
+    fn parses_client_auth_case_insensitively() {
+        assert_eq!(
+            "REQUIRED".parse::<SslClientAuth>().unwrap(),
+            SslClientAuth::Required
+        );
+        assert_eq!(
+            "requested".parse::<SslClientAuth>().unwrap(),
+            SslClientAuth::Requested
+        );
+        assert!("sometimes".parse::<SslClientAuth>().is_err());
+    }
+}
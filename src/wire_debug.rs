@@ -0,0 +1,89 @@
+//! Requests and responses written out field by field, for the wire-debug log
+//! that `log.wire.debug` turns on.
+
+use std::fmt::{self, Write};
+
+use crate::protocol::CompactNullableString;
+use crate::record_batch::BatchHeader;
+
+/// A decoded request or response as indented `name: value` lines, with the
+/// fields of nested structs and array elements indented under their name.
+#[derive(Default)]
+pub struct WireDump {
+    text: String,
+    depth: usize,
+}
+
+impl WireDump {
+    pub fn field(&mut self, name: &str, value: impl fmt::Display) -> &mut Self {
+        let indent = self.depth * 2;
+        let _ = writeln!(self.text, "{:indent$}{}: {}", "", name, value);
+        self
+    }
+
+    /// A field that may be null.
+    pub fn nullable(&mut self, name: &str, value: Option<impl fmt::Display>) -> &mut Self {
+        match value {
+            Some(value) => self.field(name, value),
+            None => self.field(name, "null"),
+        }
+    }
+
+    pub fn string(&mut self, name: &str, value: &CompactNullableString) -> &mut Self {
+        self.nullable(name, value.0.as_deref())
+    }
+
+    /// Writes `name`, then whatever `f` writes indented beneath it.
+    pub fn nested(&mut self, name: &str, f: impl FnOnce(&mut Self)) -> &mut Self {
+        let indent = self.depth * 2;
+        let _ = writeln!(self.text, "{:indent$}{}:", "", name);
+        self.depth += 1;
+        f(self);
+        self.depth -= 1;
+        self
+    }
+
+    /// Each of `items` nested under `name[index]`.
+    pub fn list<T>(
+        &mut self,
+        name: &str,
+        items: impl IntoIterator<Item = T>,
+        mut f: impl FnMut(&mut Self, T),
+    ) -> &mut Self {
+        for (i, item) in items.into_iter().enumerate() {
+            self.nested(&format!("{}[{}]", name, i), |dump| f(dump, item));
+        }
+        self
+    }
+
+    /// A record set, summarised as its size and the offsets of its batches
+    /// rather than dumped.
+    pub fn records(&mut self, name: &str, records: &[u8]) -> &mut Self {
+        let (mut at, mut batches, mut offsets) = (0, 0, None);
+        while let Some(header) = BatchHeader::parse(&records[at..]) {
+            let first = offsets.map_or(header.base_offset, |(first, _)| first);
+            offsets = Some((first, header.last_offset()));
+            at += header.size();
+            batches += 1;
+        }
+        match offsets {
+            Some((first, last)) => self.field(
+                name,
+                format_args!(
+                    "{} bytes, {} batches, offsets {}..={}",
+                    records.len(),
+                    batches,
+                    first,
+                    last
+                ),
+            ),
+            None => self.field(name, format_args!("{} bytes", records.len())),
+        }
+    }
+}
+
+impl fmt::Display for WireDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.text.trim_end())
+    }
+}
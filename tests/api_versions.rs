@@ -0,0 +1,374 @@
+//! Every version ApiVersions advertises is one the broker takes: a request
+//! at each API's lowest and highest version is answered on a connection
+//! that stays open, one past the highest is turned away, and one whose body
+//! is cut short closes its connection.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use kafka_starter_rust::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A request body built field by field, in the encodings the request's
+/// version uses.
+struct Body {
+    b: BytesMut,
+    flexible: bool,
+}
+
+impl Body {
+    fn new(api_key: ApiKey, api_version: i16) -> Self {
+        Self {
+            b: BytesMut::new(),
+            flexible: api_key.is_flexible(api_version),
+        }
+    }
+
+    fn i8(mut self, v: i8) -> Self {
+        self.b.put_i8(v);
+        self
+    }
+
+    fn i16(mut self, v: i16) -> Self {
+        self.b.put_i16(v);
+        self
+    }
+
+    fn i32(mut self, v: i32) -> Self {
+        self.b.put_i32(v);
+        self
+    }
+
+    fn i64(mut self, v: i64) -> Self {
+        self.b.put_i64(v);
+        self
+    }
+
+    fn string(mut self, v: &str) -> Self {
+        if self.flexible {
+            self.b.put_u8(v.len() as u8 + 1);
+        } else {
+            self.b.put_i16(v.len() as i16);
+        }
+        self.b.put_slice(v.as_bytes());
+        self
+    }
+
+    fn null_string(mut self) -> Self {
+        if self.flexible {
+            self.b.put_u8(0);
+        } else {
+            self.b.put_i16(-1);
+        }
+        self
+    }
+
+    fn empty_array(mut self) -> Self {
+        if self.flexible {
+            self.b.put_u8(1);
+        } else {
+            self.b.put_i32(0);
+        }
+        self
+    }
+
+    fn empty_bytes(self) -> Self {
+        self.empty_array()
+    }
+
+    /// Ends the body, or one of its structs, with its tagged fields.
+    fn tags(mut self) -> Bytes {
+        if self.flexible {
+            self.b.put_u8(0);
+        }
+        self.b.freeze()
+    }
+}
+
+/// The smallest valid request body for `api_version` of `api_key`: empty
+/// lists and names, so that nothing is created or changed.
+fn minimal_body(api_key: ApiKey, v: i16) -> Bytes {
+    let body = Body::new(api_key, v);
+    match api_key {
+        ApiKey::Produce => body.null_string().i16(1).i32(1000).empty_array().tags(),
+        ApiKey::ListOffsets => body.i32(-1).i8(0).empty_array().tags(),
+        ApiKey::Fetch => body
+            .i32(0)
+            .i32(0)
+            .i32(1024)
+            .i8(0)
+            .i32(0)
+            .i32(-1)
+            .empty_array()
+            .empty_array()
+            .string("")
+            .tags(),
+        ApiKey::Metadata => {
+            let body = body.empty_array().i8(0);
+            let body = if v <= 10 { body.i8(0) } else { body };
+            body.i8(0).tags()
+        }
+        ApiKey::OffsetCommit => body
+            .string("")
+            .i32(-1)
+            .string("")
+            .null_string()
+            .empty_array()
+            .tags(),
+        ApiKey::OffsetFetch if v >= 8 => body.empty_array().i8(0).tags(),
+        ApiKey::OffsetFetch => {
+            let body = body.string("").empty_array();
+            let body = if v >= 7 { body.i8(0) } else { body };
+            body.tags()
+        }
+        ApiKey::OffsetDelete => body.string("").empty_array().tags(),
+        ApiKey::FindCoordinator if v >= 4 => body.i8(0).empty_array().tags(),
+        ApiKey::FindCoordinator => body.string("").i8(0).tags(),
+        ApiKey::InitProducerId => {
+            let body = body.null_string().i32(1000);
+            let body = if v >= 3 { body.i64(-1).i16(-1) } else { body };
+            body.tags()
+        }
+        ApiKey::AddPartitionsToTxn => body.string("").i64(-1).i16(-1).empty_array().tags(),
+        ApiKey::EndTxn => body.string("").i64(-1).i16(-1).i8(0).tags(),
+        ApiKey::JoinGroup => {
+            let body = body
+                .string("")
+                .i32(10_000)
+                .i32(10_000)
+                .string("")
+                .null_string()
+                .string("consumer")
+                .empty_array();
+            let body = if v >= 8 { body.null_string() } else { body };
+            body.tags()
+        }
+        ApiKey::Heartbeat => body.string("").i32(0).string("").null_string().tags(),
+        ApiKey::LeaveGroup => body.string("").empty_array().tags(),
+        ApiKey::SyncGroup => {
+            let body = body.string("").i32(0).string("").null_string();
+            let body = if v >= 5 {
+                body.null_string().null_string()
+            } else {
+                body
+            };
+            body.empty_array().tags()
+        }
+        ApiKey::SaslHandshake => body.string("PLAIN").tags(),
+        ApiKey::SaslAuthenticate => body.empty_bytes().tags(),
+        ApiKey::ApiVersions if v >= 3 => body.string("test").string("1").tags(),
+        ApiKey::ApiVersions => body.tags(),
+        ApiKey::CreateTopics => body.empty_array().i32(1000).i8(0).tags(),
+        ApiKey::DeleteTopics => body.empty_array().i32(1000).tags(),
+        ApiKey::DescribeConfigs => body.empty_array().i8(0).i8(0).tags(),
+        ApiKey::AlterConfigs => body.empty_array().i8(0).tags(),
+        ApiKey::CreatePartitions => body.empty_array().i32(1000).i8(0).tags(),
+        ApiKey::DescribeLogDirs => body.empty_array().tags(),
+        ApiKey::CreateDelegationToken => {
+            let body = if v >= 3 {
+                body.null_string().null_string()
+            } else {
+                body
+            };
+            body.empty_array().i64(-1).tags()
+        }
+        ApiKey::RenewDelegationToken | ApiKey::ExpireDelegationToken => {
+            body.empty_bytes().i64(-1).tags()
+        }
+        ApiKey::DescribeDelegationToken => body.empty_array().tags(),
+        ApiKey::AlterPartitionReassignments => body.i32(1000).empty_array().tags(),
+        ApiKey::ListPartitionReassignments => body.i32(1000).empty_array().tags(),
+        ApiKey::DescribeClientQuotas => body.empty_array().i8(0).tags(),
+        ApiKey::AlterClientQuotas => body.empty_array().i8(0).tags(),
+        ApiKey::UpdateFeatures => {
+            let body = body.i32(1000).empty_array();
+            let body = if v >= 1 { body.i8(0) } else { body };
+            body.tags()
+        }
+        ApiKey::DescribeCluster => {
+            let body = body.i8(0);
+            let body = if v >= 1 { body.i8(1) } else { body };
+            body.tags()
+        }
+        ApiKey::DescribeTopicPartitions => body.empty_array().i32(100).i8(-1).tags(),
+        ApiKey::ConsumerGroupHeartbeat => body
+            .string("")
+            .string("")
+            .i32(0)
+            .null_string()
+            .null_string()
+            .i32(-1)
+            .null_string()
+            .null_string()
+            .null_string()
+            .tags(),
+        ApiKey::ShareGroupHeartbeat => body
+            .string("")
+            .string("")
+            .i32(0)
+            .null_string()
+            .null_string()
+            .tags(),
+        ApiKey::ShareFetch => body
+            .string("")
+            .string("")
+            .i32(0)
+            .i32(0)
+            .i32(0)
+            .i32(1024)
+            .empty_array()
+            .empty_array()
+            .tags(),
+        other => panic!("no request body for {:?} v{}", other, v),
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    correlation_id: i32,
+}
+
+impl Client {
+    async fn connect(broker: &Broker) -> Self {
+        Self {
+            stream: TcpStream::connect(broker.address()).await.unwrap(),
+            correlation_id: 0,
+        }
+    }
+
+    /// Sends a request and returns its response body, after the
+    /// correlation id, or `None` if the broker closed the connection.
+    async fn send(&mut self, api_key: ApiKey, api_version: i16, body: &[u8]) -> Option<Bytes> {
+        self.correlation_id += 1;
+        let mut request = BytesMut::new();
+        request.put_i16(api_key.into());
+        request.put_i16(api_version);
+        request.put_i32(self.correlation_id);
+        request.put_i16(4);
+        request.put_slice(b"test");
+        if api_key.is_flexible(api_version) {
+            request.put_u8(0);
+        }
+        request.put_slice(body);
+        let mut frame = BytesMut::new();
+        frame.put_i32(request.len() as i32);
+        frame.put(request);
+        self.stream.write_all(&frame).await.ok()?;
+
+        let len = self.stream.read_i32().await.ok()?;
+        let mut response = vec![0; len as usize];
+        self.stream.read_exact(&mut response).await.ok()?;
+        let mut response = Bytes::from(response);
+        assert_eq!(
+            response.get_i32(),
+            self.correlation_id,
+            "{:?} v{} answered out of turn",
+            api_key,
+            api_version
+        );
+        Some(response)
+    }
+}
+
+/// The APIs the broker listener advertises, with their version ranges.
+async fn advertised(client: &mut Client) -> Vec<(ApiKey, i16, i16)> {
+    let body = minimal_body(ApiKey::ApiVersions, 3);
+    let mut response = client.send(ApiKey::ApiVersions, 3, &body).await.unwrap();
+    assert_eq!(response.get_i16(), 0);
+    let count = response.get_u8() - 1;
+    (0..count)
+        .map(|_| {
+            let key = ApiKey::try_from(response.get_i16()).unwrap();
+            let min = response.get_i16();
+            let max = response.get_i16();
+            response.advance(1);
+            (key, min, max)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn answers_every_advertised_version() {
+    let broker = Broker::start_ephemeral(&[]).await.unwrap();
+    let mut client = Client::connect(&broker).await;
+    let apis = advertised(&mut client).await;
+    assert!(!apis.is_empty());
+    for &(api_key, min, max) in &apis {
+        for version in [min, max] {
+            let body = minimal_body(api_key, version);
+            assert!(
+                client.send(api_key, version, &body).await.is_some(),
+                "{:?} v{} was advertised but not answered",
+                api_key,
+                version
+            );
+        }
+    }
+    broker.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn turns_away_versions_past_the_advertised_range() {
+    let broker = Broker::start_ephemeral(&[]).await.unwrap();
+    let mut client = Client::connect(&broker).await;
+    let apis = advertised(&mut client).await;
+
+    // ApiVersions answers as v0, so the client can still read what it may
+    // send.
+    let (_, _, max) = *apis
+        .iter()
+        .find(|(key, _, _)| *key == ApiKey::ApiVersions)
+        .unwrap();
+    let mut response = client
+        .send(ApiKey::ApiVersions, max + 1, &[])
+        .await
+        .unwrap();
+    assert_eq!(
+        ErrorCode::from(response.get_i16()),
+        ErrorCode::UnsupportedVersion
+    );
+    assert_eq!(response.get_i32() as usize, apis.len());
+
+    let (_, _, max) = *apis
+        .iter()
+        .find(|(key, _, _)| *key == ApiKey::Metadata)
+        .unwrap();
+    let body = minimal_body(ApiKey::Metadata, max);
+    assert!(client
+        .send(ApiKey::Metadata, max + 1, &body)
+        .await
+        .is_none());
+    broker.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn closes_the_connection_on_a_truncated_body() {
+    let broker = Broker::start_ephemeral(&[]).await.unwrap();
+    let apis = advertised(&mut Client::connect(&broker).await).await;
+    for &(api_key, _, max) in &apis {
+        // ApiVersions answers whatever its body holds, and without SASL on
+        // the listener the SASL APIs are refused unread.
+        if matches!(
+            api_key,
+            ApiKey::ApiVersions | ApiKey::SaslHandshake | ApiKey::SaslAuthenticate
+        ) {
+            continue;
+        }
+        let body = minimal_body(api_key, max);
+        if body.is_empty() {
+            continue;
+        }
+        let mut client = Client::connect(&broker).await;
+        assert!(
+            client
+                .send(api_key, max, &body[..body.len() - 1])
+                .await
+                .is_none(),
+            "{:?} v{} answered a truncated body",
+            api_key,
+            max
+        );
+    }
+    // Only those connections were closed.
+    assert_eq!(advertised(&mut Client::connect(&broker).await).await, apis);
+    broker.shutdown().await.unwrap();
+}
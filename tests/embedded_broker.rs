@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use bytes::Bytes;
+use kafka_starter_rust::*;
+
+fn record(key: &str, value: &str) -> BatchRecord {
+    BatchRecord {
+        key: Some(Bytes::copy_from_slice(key.as_bytes())),
+        value: Some(Bytes::copy_from_slice(value.as_bytes())),
+    }
+}
+
+#[tokio::test]
+async fn serves_fixture_topics_and_records() {
+    let broker = Broker::start_ephemeral(&[FixtureTopic {
+        name: "orders".to_string(),
+        partitions: 2,
+        records: BTreeMap::from([(1, vec![record("k0", "a"), record("k1", "b")])]),
+        ..Default::default()
+    }])
+    .await
+    .unwrap();
+
+    let mut admin = Admin::new(AdminConfig {
+        bootstrap_servers: vec![broker.bootstrap_servers()],
+        ..Default::default()
+    })
+    .unwrap();
+    let topics = admin.describe_topics(Some(&["orders"])).await.unwrap();
+    assert_eq!(topics.len(), 1);
+    assert_eq!(topics[0].error_code, ErrorCode::None);
+    assert_eq!(topics[0].partitions.0.len(), 2);
+
+    let mut consumer = Consumer::connect(ConsumerConfig {
+        bootstrap_servers: vec![broker.bootstrap_servers()],
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    consumer
+        .assign(vec![TopicPartition {
+            topic: "orders".to_string(),
+            partition: 1,
+        }])
+        .await
+        .unwrap();
+    let records = consumer.poll(Duration::from_secs(5)).await.unwrap();
+    let values: Vec<_> = records.iter().map(|r| r.value.clone().unwrap()).collect();
+    assert_eq!(values, [Bytes::from("a"), Bytes::from("b")]);
+    assert_eq!(records[1].offset, 1);
+    consumer.close().await.unwrap();
+
+    let log_dir = broker.log_dir().to_path_buf();
+    broker.shutdown().await.unwrap();
+    assert!(!log_dir.exists());
+}
+
+#[tokio::test]
+async fn reads_back_committed_offsets() {
+    let broker = Broker::start_ephemeral(&[FixtureTopic {
+        name: "orders".to_string(),
+        partitions: 2,
+        records: BTreeMap::from([(0, vec![record("k0", "a"), record("k1", "b")])]),
+        ..Default::default()
+    }])
+    .await
+    .unwrap();
+    let committed = TopicPartition {
+        topic: "orders".to_string(),
+        partition: 0,
+    };
+    let uncommitted = TopicPartition {
+        topic: "orders".to_string(),
+        partition: 1,
+    };
+
+    let mut consumer = Consumer::connect(ConsumerConfig {
+        bootstrap_servers: vec![broker.bootstrap_servers()],
+        group_id: Some("audit".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    consumer.assign(vec![committed.clone()]).await.unwrap();
+    consumer.seek(&committed, 2).unwrap();
+    consumer.commit().await.unwrap();
+    consumer.close().await.unwrap();
+
+    let mut admin = Admin::new(AdminConfig {
+        bootstrap_servers: vec![broker.bootstrap_servers()],
+        ..Default::default()
+    })
+    .unwrap();
+    let all = admin.list_group_offsets("audit", None).await.unwrap();
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0].name, "orders");
+    assert_eq!(all[0].partitions.0.len(), 1);
+    assert_eq!(all[0].partitions.0[0].partition_index, 0);
+    assert_eq!(all[0].partitions.0[0].committed_offset, 2);
+
+    let asked = admin
+        .list_group_offsets("audit", Some(&[committed, uncommitted]))
+        .await
+        .unwrap();
+    let offsets: Vec<_> = asked[0]
+        .partitions
+        .0
+        .iter()
+        .map(|p| (p.partition_index, p.committed_offset))
+        .collect();
+    assert_eq!(offsets, [(0, 2), (1, -1)]);
+
+    broker.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn forgets_committed_offsets_of_deleted_topics() {
+    let broker = Broker::start_ephemeral(&[FixtureTopic {
+        name: "orders".to_string(),
+        partitions: 1,
+        records: BTreeMap::from([(0, vec![record("k0", "a"), record("k1", "b")])]),
+        ..Default::default()
+    }])
+    .await
+    .unwrap();
+    let partition = TopicPartition {
+        topic: "orders".to_string(),
+        partition: 0,
+    };
+
+    let mut consumer = Consumer::connect(ConsumerConfig {
+        bootstrap_servers: vec![broker.bootstrap_servers()],
+        group_id: Some("audit".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    consumer.assign(vec![partition.clone()]).await.unwrap();
+    consumer.seek(&partition, 2).unwrap();
+    consumer.commit().await.unwrap();
+    consumer.close().await.unwrap();
+
+    let mut admin = Admin::new(AdminConfig {
+        bootstrap_servers: vec![broker.bootstrap_servers()],
+        ..Default::default()
+    })
+    .unwrap();
+    let deleted = admin.delete_topics(&["orders"]).await.unwrap();
+    assert_eq!(deleted[0].error_code, ErrorCode::None);
+    assert!(admin
+        .list_group_offsets("audit", None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Offset 2 would be past the end of the new, empty topic.
+    admin.create_topic("orders", 1, 1).await.unwrap();
+    let offsets = admin
+        .list_group_offsets("audit", Some(&[partition]))
+        .await
+        .unwrap();
+    assert_eq!(offsets[0].partitions.0[0].committed_offset, -1);
+
+    broker.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn brokers_get_their_own_ports_and_directories() {
+    let a = Broker::start_ephemeral(&[]).await.unwrap();
+    let b = Broker::start_ephemeral(&[]).await.unwrap();
+    assert_ne!(a.address(), b.address());
+    assert_ne!(a.log_dir(), b.log_dir());
+
+    let mut admin = Admin::new(AdminConfig {
+        bootstrap_servers: vec![b.bootstrap_servers()],
+        ..Default::default()
+    })
+    .unwrap();
+    let cluster = admin.describe_cluster().await.unwrap();
+    assert_eq!(cluster.brokers.0.len(), 1);
+    assert_eq!(cluster.brokers.0[0].port as u16, b.address().port());
+
+    a.shutdown().await.unwrap();
+    b.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn rejects_records_for_missing_partitions() {
+    let res = Broker::start_ephemeral(&[FixtureTopic {
+        name: "orders".to_string(),
+        partitions: 1,
+        records: BTreeMap::from([(3, vec![record("k", "v")])]),
+        ..Default::default()
+    }])
+    .await;
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn end_offsets_follow_produced_records() {
+    let broker = Broker::start_ephemeral(&[FixtureTopic {
+        name: "orders".to_string(),
+        partitions: 1,
+        records: BTreeMap::from([(0, vec![record("k0", "a"), record("k1", "b")])]),
+        ..Default::default()
+    }])
+    .await
+    .unwrap();
+    let partition = TopicPartition {
+        topic: "orders".to_string(),
+        partition: 0,
+    };
+    let mut consumer = Consumer::connect(ConsumerConfig {
+        bootstrap_servers: vec![broker.bootstrap_servers()],
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    consumer.assign(vec![partition.clone()]).await.unwrap();
+    consumer
+        .seek_to_end(std::slice::from_ref(&partition))
+        .await
+        .unwrap();
+    assert_eq!(consumer.position(&partition), Some(2));
+
+    let mut producer = Producer::connect(ProducerConfig {
+        bootstrap_servers: vec![broker.bootstrap_servers()],
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    producer
+        .send(ProducerRecord {
+            topic: "orders".to_string(),
+            partition: Some(0),
+            key: None,
+            value: Some(Bytes::from("c")),
+        })
+        .await
+        .unwrap();
+    let sent = producer.flush().await.unwrap();
+    assert_eq!(sent[0].offset, Some(2));
+
+    consumer
+        .seek_to_end(std::slice::from_ref(&partition))
+        .await
+        .unwrap();
+    assert_eq!(consumer.position(&partition), Some(3));
+    consumer.close().await.unwrap();
+    broker.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_stops_every_task() {
+    let runtime = tokio::runtime::Handle::current().metrics();
+    let before = runtime.num_alive_tasks();
+    let broker = Broker::start_ephemeral(&[FixtureTopic {
+        name: "orders".to_string(),
+        partitions: 1,
+        ..Default::default()
+    }])
+    .await
+    .unwrap();
+    assert!(runtime.num_alive_tasks() > before);
+    broker.shutdown().await.unwrap();
+
+    // Tasks that end when a stopped one drops their channel may take a poll
+    // or two to notice.
+    for _ in 0..50 {
+        if runtime.num_alive_tasks() <= before {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!(
+        "{} tasks outlived the broker",
+        runtime.num_alive_tasks() - before
+    );
+}
+
+#[tokio::test]
+async fn fetches_wait_for_produced_records() {
+    let broker = Broker::start_ephemeral(&[FixtureTopic {
+        name: "orders".to_string(),
+        partitions: 1,
+        ..Default::default()
+    }])
+    .await
+    .unwrap();
+    let mut consumer = Consumer::connect(ConsumerConfig {
+        bootstrap_servers: vec![broker.bootstrap_servers()],
+        fetch_max_wait: Duration::from_millis(300),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    consumer
+        .assign(vec![TopicPartition {
+            topic: "orders".to_string(),
+            partition: 0,
+        }])
+        .await
+        .unwrap();
+
+    // Nothing is appended, so the broker holds the fetch until max_wait.
+    let started = std::time::Instant::now();
+    let records = consumer.poll(Duration::from_millis(300)).await.unwrap();
+    assert!(records.is_empty());
+    assert!(started.elapsed() >= Duration::from_millis(250));
+    consumer.close().await.unwrap();
+
+    let mut consumer = Consumer::connect(ConsumerConfig {
+        bootstrap_servers: vec![broker.bootstrap_servers()],
+        fetch_max_wait: Duration::from_secs(10),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    consumer
+        .assign(vec![TopicPartition {
+            topic: "orders".to_string(),
+            partition: 0,
+        }])
+        .await
+        .unwrap();
+    let bootstrap = broker.bootstrap_servers();
+    let produce = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut producer = Producer::connect(ProducerConfig {
+            bootstrap_servers: vec![bootstrap],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        producer
+            .send(ProducerRecord {
+                topic: "orders".to_string(),
+                partition: Some(0),
+                key: None,
+                value: Some(Bytes::from("a")),
+            })
+            .await
+            .unwrap();
+        producer.flush().await.unwrap();
+    });
+
+    // The append completes the held fetch long before its max_wait.
+    let started = std::time::Instant::now();
+    let records = consumer.poll(Duration::from_secs(20)).await.unwrap();
+    assert_eq!(records.len(), 1);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    produce.await.unwrap();
+    consumer.close().await.unwrap();
+    broker.shutdown().await.unwrap();
+}
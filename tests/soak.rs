@@ -0,0 +1,368 @@
+//! Randomized soak test: workers interleave produce, fetch, metadata and
+//! consumer group operations against one embedded broker, checking that
+//! offsets never go backwards, that every acknowledged record can be read
+//! back as written, and that nothing panics.
+//!
+//! It runs for a few seconds with `cargo test`; a longer run takes:
+//!
+//! ```text
+//! SOAK_SECS=60 SOAK_WORKERS=8 cargo test --test soak -- --nocapture
+//! ```
+//!
+//! Every run makes the same operation choices unless `SOAK_SEED` picks
+//! others; a failure names the seed it ran with, so it can be replayed.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+use bytes::Bytes;
+use kafka_starter_rust::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::time::Instant;
+
+const TOPIC: &str = "soak";
+const PARTITIONS: i32 = 3;
+/// The seed of runs that don't set `SOAK_SEED`.
+const DEFAULT_SEED: u64 = 0x5eed;
+
+/// Every acknowledged record, by partition and offset.
+type Acked = Arc<Mutex<BTreeMap<(i32, i64), Bytes>>>;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn soak() {
+    let duration = Duration::from_secs(env_or("SOAK_SECS", 3));
+    let workers: u64 = env_or("SOAK_WORKERS", 4);
+    let seed: u64 = env_or("SOAK_SEED", DEFAULT_SEED);
+    println!(
+        "soak: {:?} with {} workers, SOAK_SEED={}",
+        duration, workers, seed
+    );
+
+    let panics = Arc::new(AtomicUsize::new(0));
+    let default_hook = std::panic::take_hook();
+    let counted = panics.clone();
+    std::panic::set_hook(Box::new(move |info| {
+        counted.fetch_add(1, Ordering::Relaxed);
+        default_hook(info);
+    }));
+
+    let broker = Broker::start_ephemeral(&[FixtureTopic {
+        name: TOPIC.to_string(),
+        partitions: PARTITIONS,
+        ..Default::default()
+    }])
+    .await
+    .unwrap();
+    let bootstrap = broker.bootstrap_servers();
+    let acked = Acked::default();
+    let deadline = Instant::now() + duration;
+
+    let tasks: Vec<_> = (0..workers)
+        .map(|worker| {
+            let bootstrap = bootstrap.clone();
+            let acked = acked.clone();
+            tokio::spawn(async move {
+                let mut rng = StdRng::seed_from_u64(seed.wrapping_add(worker));
+                let mut ops = 0;
+                let mut produced = BTreeMap::new();
+                while Instant::now() < deadline {
+                    let op = rng.gen_range(0..4);
+                    let res = match op {
+                        0 => {
+                            produce(&bootstrap, worker, ops, &mut rng, &acked, &mut produced).await
+                        }
+                        1 => fetch(&bootstrap, &mut rng, &acked).await,
+                        2 => metadata(&bootstrap).await,
+                        _ => group(&bootstrap, worker, &mut rng).await,
+                    };
+                    res.with_context(|| format!("worker {} operation {} ({})", worker, ops, op))?;
+                    ops += 1;
+                }
+                Ok::<_, anyhow::Error>(ops)
+            })
+        })
+        .collect();
+    let mut total = 0;
+    for task in tasks {
+        total += task.await.unwrap().unwrap_or_else(|e| failed(seed, e));
+    }
+
+    verify_all(&bootstrap, &acked)
+        .await
+        .unwrap_or_else(|e| failed(seed, e));
+    println!(
+        "soak: {} operations, {} records acknowledged",
+        total,
+        acked.lock().unwrap().len()
+    );
+    broker.shutdown().await.unwrap();
+    let _ = std::panic::take_hook();
+    assert_eq!(
+        panics.load(Ordering::Relaxed),
+        0,
+        "something panicked with SOAK_SEED={}",
+        seed
+    );
+}
+
+fn failed(seed: u64, e: anyhow::Error) -> ! {
+    panic!("soak failed with SOAK_SEED={}: {:#}", seed, e)
+}
+
+/// Writes a few records to one partition, checking that they land after
+/// whatever this worker wrote there before and at offsets nobody else got.
+async fn produce(
+    bootstrap: &str,
+    worker: u64,
+    op: u64,
+    rng: &mut StdRng,
+    acked: &Acked,
+    produced: &mut BTreeMap<i32, i64>,
+) -> Result<()> {
+    let partition = rng.gen_range(0..PARTITIONS);
+    let count = rng.gen_range(1..=20);
+    let mut producer = Producer::connect(ProducerConfig {
+        bootstrap_servers: vec![bootstrap.to_string()],
+        ..Default::default()
+    })
+    .await?;
+    let values: Vec<Bytes> = (0..count)
+        .map(|i| Bytes::from(format!("w{}-op{}-r{}", worker, op, i)))
+        .collect();
+    for value in &values {
+        producer
+            .send(ProducerRecord {
+                topic: TOPIC.to_string(),
+                partition: Some(partition),
+                key: None,
+                value: Some(value.clone()),
+            })
+            .await?;
+    }
+    let mut offsets: Vec<i64> = producer
+        .flush()
+        .await?
+        .into_iter()
+        .map(|meta| meta.offset.context("no offset with acks=all"))
+        .collect::<Result<_>>()?;
+    ensure!(
+        offsets.len() == values.len(),
+        "{} offsets for {} records",
+        offsets.len(),
+        values.len()
+    );
+    offsets.sort_unstable();
+
+    if let Some(&previous) = produced.get(&partition) {
+        ensure!(
+            offsets[0] > previous,
+            "partition {} went back from {} to {}",
+            partition,
+            previous,
+            offsets[0]
+        );
+    }
+    produced.insert(partition, offsets[offsets.len() - 1]);
+
+    let mut acked = acked.lock().unwrap();
+    for (&offset, value) in offsets.iter().zip(&values) {
+        if let Some(previous) = acked.get(&(partition, offset)) {
+            bail!(
+                "offset {} of partition {} acknowledged twice ({:?} then {:?})",
+                offset,
+                partition,
+                previous,
+                value
+            );
+        }
+        acked.insert((partition, offset), value.clone());
+    }
+    Ok(())
+}
+
+/// Reads a partition from a random acknowledged offset, checking every
+/// record against what was acknowledged and that offsets only go up.
+async fn fetch(bootstrap: &str, rng: &mut StdRng, acked: &Acked) -> Result<()> {
+    let partition = rng.gen_range(0..PARTITIONS);
+    let start = {
+        let acked = acked.lock().unwrap();
+        let offsets: Vec<i64> = acked
+            .range((partition, 0)..(partition, i64::MAX))
+            .map(|(&(_, offset), _)| offset)
+            .collect();
+        if offsets.is_empty() {
+            0
+        } else {
+            offsets[rng.gen_range(0..offsets.len())]
+        }
+    };
+    let mut consumer = connect_consumer(bootstrap, None).await?;
+    let tp = TopicPartition {
+        topic: TOPIC.to_string(),
+        partition,
+    };
+    consumer.assign(vec![tp.clone()]).await?;
+    consumer.seek(&tp, start)?;
+    let records = consumer.poll(Duration::from_millis(200)).await?;
+    check_records(&records, start, acked)?;
+    consumer.close().await
+}
+
+async fn metadata(bootstrap: &str) -> Result<()> {
+    let mut admin = Admin::new(AdminConfig {
+        bootstrap_servers: vec![bootstrap.to_string()],
+        ..Default::default()
+    })?;
+    let topics = admin.describe_topics(Some(&[TOPIC])).await?;
+    ensure!(topics.len() == 1, "{} topics described", topics.len());
+    ensure!(
+        topics[0].error_code == ErrorCode::None,
+        "{:?}",
+        topics[0].error_code
+    );
+    ensure!(
+        topics[0].partitions.0.len() == PARTITIONS as usize,
+        "{} partitions",
+        topics[0].partitions.0.len()
+    );
+    Ok(())
+}
+
+/// Joins one of a few groups, reads a little and commits, checking that
+/// the group never hands out a position behind what it had committed.
+async fn group(bootstrap: &str, worker: u64, rng: &mut StdRng) -> Result<()> {
+    let group = format!("soak-{}", rng.gen_range(0..3));
+    let mut consumer = connect_consumer(bootstrap, Some(group.clone())).await?;
+    consumer.subscribe(&[TOPIC])?;
+    let records = consumer.poll(Duration::from_millis(500)).await?;
+    let mut last = BTreeMap::new();
+    for record in &records {
+        if let Some(&previous) = last.get(&record.partition) {
+            ensure!(
+                record.offset > previous,
+                "group {} worker {}: offset {} after {} in partition {}",
+                group,
+                worker,
+                record.offset,
+                previous,
+                record.partition
+            );
+        }
+        last.insert(record.partition, record.offset);
+    }
+    consumer.commit().await?;
+    consumer.close().await
+}
+
+/// Every acknowledged record has to be readable, at its offset, as written.
+async fn verify_all(bootstrap: &str, acked: &Acked) -> Result<()> {
+    for partition in 0..PARTITIONS {
+        let expected: BTreeMap<i64, Bytes> = acked
+            .lock()
+            .unwrap()
+            .range((partition, 0)..(partition, i64::MAX))
+            .map(|(&(_, offset), value)| (offset, value.clone()))
+            .collect();
+        let Some(&end) = expected.keys().next_back() else {
+            continue;
+        };
+        let mut consumer = connect_consumer(bootstrap, None).await?;
+        let tp = TopicPartition {
+            topic: TOPIC.to_string(),
+            partition,
+        };
+        consumer.assign(vec![tp.clone()]).await?;
+        consumer.seek(&tp, 0)?;
+        let mut read = BTreeMap::new();
+        let give_up = Instant::now() + Duration::from_secs(30);
+        while consumer
+            .position(&tp)
+            .is_some_and(|position| position <= end)
+        {
+            ensure!(
+                Instant::now() < give_up,
+                "partition {} stuck before {}",
+                partition,
+                end
+            );
+            let records = consumer.poll(Duration::from_millis(500)).await?;
+            check_records(&records, 0, acked)?;
+            for record in records {
+                read.insert(record.offset, record.value.unwrap_or_default());
+            }
+        }
+        consumer.close().await?;
+        for (offset, value) in expected {
+            match read.get(&offset) {
+                Some(read) if *read == value => {}
+                Some(read) => bail!(
+                    "partition {} offset {}: acknowledged {:?}, read {:?}",
+                    partition,
+                    offset,
+                    value,
+                    read
+                ),
+                None => bail!(
+                    "partition {} lost acknowledged offset {}",
+                    partition,
+                    offset
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_records(records: &[ConsumerRecord], start: i64, acked: &Acked) -> Result<()> {
+    let acked = acked.lock().unwrap();
+    let mut last = BTreeMap::new();
+    for record in records {
+        ensure!(
+            record.offset >= start,
+            "offset {} before {}",
+            record.offset,
+            start
+        );
+        if let Some(&previous) = last.get(&record.partition) {
+            ensure!(
+                record.offset > previous,
+                "offset {} after {}",
+                record.offset,
+                previous
+            );
+        }
+        last.insert(record.partition, record.offset);
+        if let Some(expected) = acked.get(&(record.partition, record.offset)) {
+            ensure!(
+                record.value.as_ref() == Some(expected),
+                "partition {} offset {}: acknowledged {:?}, read {:?}",
+                record.partition,
+                record.offset,
+                expected,
+                record.value
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn connect_consumer(bootstrap: &str, group_id: Option<String>) -> Result<Consumer> {
+    Consumer::connect(ConsumerConfig {
+        bootstrap_servers: vec![bootstrap.to_string()],
+        group_id,
+        fetch_max_wait: Duration::from_millis(100),
+        ..Default::default()
+    })
+    .await
+}